@@ -0,0 +1,19 @@
+#define EXPOSURE_HISTOGRAM_BIN_COUNT 256
+
+// Kept across frames: the histogram is filled by auto_exposure_histogram.comp, then reduced and
+// cleared by auto_exposure_average.comp, which adapts the exposure the tonemap pass applies.
+layout (buffer_reference, scalar) buffer ExposureBuffer {
+    float exposure;
+    uint histogram[EXPOSURE_HISTOGRAM_BIN_COUNT];
+};
+
+// Bin 0 counts black pixels, the rest split the log2 luminance range evenly like the image
+// analysis histogram.
+uint luminanceBin(vec3 color, float minLogLuminance, float maxLogLuminance) {
+    float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    if (!(luminance > 0.0) || isinf(luminance)) {
+        return 0;
+    }
+    float position = (log2(luminance) - minLogLuminance) / (maxLogLuminance - minLogLuminance);
+    return uint(clamp(position, 0.0, 1.0) * float(EXPOSURE_HISTOGRAM_BIN_COUNT - 2)) + 1;
+}
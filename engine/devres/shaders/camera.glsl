@@ -0,0 +1,28 @@
+struct Camera {
+    mat4 view;
+    mat4 projection;
+    vec3 position;
+    // World position of the camera; view and instance transforms are relative to it.
+    vec3 origin;
+    // 1 unless a physical camera is used, scene luminance is then expected in cd/m².
+    float exposure;
+    uint padding;
+    // Towards the sun, illuminances are in lux.
+    vec3 sunDirection;
+    float sunIlluminance;
+    vec3 sunColor;
+    float ambientIlluminance;
+    float bakedIlluminance;
+    // cd/m² of a skybox texel of 1, for image based lighting.
+    float skyLuminance;
+    // World space size of a sun shadow map texel.
+    float sunShadowTexelSize;
+    // From 0 for dry to 1 for soaked surfaces, see Material.wetness_response.
+    float wetness;
+    // From positions relative to the camera to the sun shadow map's clip space.
+    mat4 sunShadowMatrix;
+};
+
+layout (buffer_reference, scalar) buffer CameraBuffer {
+    Camera cameras[];
+};
\ No newline at end of file
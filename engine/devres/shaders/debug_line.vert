@@ -0,0 +1,32 @@
+#version 460
+#extension GL_EXT_buffer_reference: require
+#extension GL_EXT_scalar_block_layout: require
+#include "camera.glsl"
+
+struct DebugVertex {
+    vec3 position;
+    vec4 color;
+};
+
+layout (buffer_reference, scalar) buffer DebugVertexBuffer {
+    DebugVertex vertices[];
+};
+
+layout (scalar, push_constant) uniform Registers
+{
+    DebugVertexBuffer vertexBuffer;
+    CameraBuffer cameraBuffer;
+    // Only used by the point list pipeline.
+    float pointSize;
+} pushConstants;
+
+layout (location = 0) out vec4 fragColor;
+
+void main() {
+    DebugVertex vertex = pushConstants.vertexBuffer.vertices[gl_VertexIndex];
+    Camera camera = pushConstants.cameraBuffer.cameras[0];
+
+    gl_Position = camera.projection * camera.view * vec4(vertex.position - camera.origin, 1.0);
+    gl_PointSize = pushConstants.pointSize;
+    fragColor = vertex.color;
+}
\ No newline at end of file
@@ -0,0 +1,14 @@
+#version 460
+
+layout (set = 0, binding = 0) uniform sampler2D eguiTexture;
+
+layout (location = 0) in vec2 fragTexCoord;
+layout (location = 1) in vec4 fragColor;
+
+layout (location = 0) out vec4 outColor;
+
+void main() {
+    vec4 color = fragColor * texture(eguiTexture, fragTexCoord);
+    // egui colors are premultiplied, the UI target blends straight alpha like the painter.
+    outColor = vec4(color.rgb / max(color.a, 1e-5), color.a);
+}
@@ -0,0 +1,32 @@
+#version 460
+#extension GL_EXT_buffer_reference: require
+#extension GL_EXT_scalar_block_layout: require
+
+struct EguiVertex {
+    vec2 position;
+    vec2 texCoord;
+    // Premultiplied sRGBA, one byte per channel.
+    uint color;
+};
+
+layout (buffer_reference, scalar) buffer EguiVertexBuffer {
+    EguiVertex vertices[];
+};
+
+layout (scalar, push_constant) uniform Registers
+{
+    EguiVertexBuffer vertexBuffer;
+    // In egui points, which the vertex positions are in.
+    vec2 screenSize;
+} pushConstants;
+
+layout (location = 0) out vec2 fragTexCoord;
+layout (location = 1) out vec4 fragColor;
+
+void main() {
+    EguiVertex vertex = pushConstants.vertexBuffer.vertices[gl_VertexIndex];
+
+    gl_Position = vec4(vertex.position / pushConstants.screenSize * 2.0 - 1.0, 0.0, 1.0);
+    fragTexCoord = vertex.texCoord;
+    fragColor = unpackUnorm4x8(vertex.color);
+}
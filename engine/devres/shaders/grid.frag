@@ -0,0 +1,55 @@
+#version 460
+#extension GL_EXT_buffer_reference: require
+#extension GL_EXT_scalar_block_layout: require
+#include "camera.glsl"
+
+layout (scalar, push_constant) uniform Registers
+{
+    CameraBuffer cameraBuffer;
+} pushConstants;
+
+layout (location = 0) in vec3 nearPoint;
+layout (location = 1) in vec3 farPoint;
+
+layout (location = 0) out vec4 outColor;
+
+const float fadeDistance = 50.0;
+
+vec4 grid(vec3 position, float scale) {
+    vec2 coord = position.xz / scale;
+    vec2 derivative = fwidth(coord);
+    vec2 lines = abs(fract(coord - 0.5) - 0.5) / derivative;
+    float line = min(lines.x, lines.y);
+    vec2 axisWidth = fwidth(position.xz);
+
+    vec4 color = vec4(0.3, 0.3, 0.3, 1.0 - min(line, 1.0));
+    // X axis runs along z = 0 and Z axis along x = 0.
+    if (abs(position.z) < axisWidth.y) {
+        color = vec4(1.0, 0.2, 0.2, 1.0);
+    }
+    if (abs(position.x) < axisWidth.x) {
+        color = vec4(0.2, 0.4, 1.0, 1.0);
+    }
+    return color;
+}
+
+void main() {
+    Camera camera = pushConstants.cameraBuffer.cameras[0];
+
+    // Intersect the view ray with the y = 0 ground plane.
+    float t = -nearPoint.y / (farPoint.y - nearPoint.y);
+    if (t <= 0.0) {
+        discard;
+    }
+    vec3 position = nearPoint + t * (farPoint - nearPoint);
+
+    vec4 clipPosition = camera.projection * camera.view * vec4(position - camera.origin, 1.0);
+    gl_FragDepth = clipPosition.z / clipPosition.w;
+
+    float fade = max(0.0, 1.0 - distance(position, camera.origin) / fadeDistance);
+    outColor = grid(position, 1.0);
+    outColor.a *= fade;
+    if (outColor.a <= 0.0) {
+        discard;
+    }
+}
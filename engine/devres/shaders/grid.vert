@@ -0,0 +1,32 @@
+#version 460
+#extension GL_EXT_buffer_reference: require
+#extension GL_EXT_scalar_block_layout: require
+#include "camera.glsl"
+
+layout (scalar, push_constant) uniform Registers
+{
+    CameraBuffer cameraBuffer;
+} pushConstants;
+
+layout (location = 0) out vec3 nearPoint;
+layout (location = 1) out vec3 farPoint;
+
+const vec2 positions[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(1.0, 1.0), vec2(-1.0, 1.0), vec2(-1.0, -1.0)
+);
+
+vec3 unproject(vec3 position, mat4 inverseViewProjection) {
+    vec4 unprojected = inverseViewProjection * vec4(position, 1.0);
+    return unprojected.xyz / unprojected.w;
+}
+
+void main() {
+    Camera camera = pushConstants.cameraBuffer.cameras[0];
+    mat4 inverseViewProjection = inverse(camera.projection * camera.view);
+
+    vec2 position = positions[gl_VertexIndex];
+    nearPoint = unproject(vec3(position, -1.0), inverseViewProjection) + camera.origin;
+    farPoint = unproject(vec3(position, 1.0), inverseViewProjection) + camera.origin;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
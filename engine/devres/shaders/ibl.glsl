@@ -0,0 +1,41 @@
+const float PI = 3.14159265359;
+
+// Direction through the center of a texel of a cube map face, in +X, -X, +Y, -Y, +Z, -Z order.
+vec3 cubeTexelDirection(ivec3 coord, ivec2 size) {
+    vec2 uv = (vec2(coord.xy) + 0.5) / vec2(size) * 2.0 - 1.0;
+    switch (coord.z) {
+        case 0: return normalize(vec3(1.0, -uv.y, -uv.x));
+        case 1: return normalize(vec3(-1.0, -uv.y, uv.x));
+        case 2: return normalize(vec3(uv.x, 1.0, uv.y));
+        case 3: return normalize(vec3(uv.x, -1.0, -uv.y));
+        case 4: return normalize(vec3(uv.x, -uv.y, 1.0));
+        default: return normalize(vec3(-uv.x, -uv.y, -1.0));
+    }
+}
+
+vec3 tangentToWorld(vec3 direction, vec3 normal) {
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+    return tangent * direction.x + bitangent * direction.y + normal * direction.z;
+}
+
+vec2 hammersley(uint index, uint count) {
+    return vec2(float(index) / float(count), float(bitfieldReverse(index)) * 2.3283064365386963e-10);
+}
+
+// Half vector around normal distributed like GGX, roughness is perceptual, squared into alpha.
+vec3 importanceSampleGgx(vec2 xi, vec3 normal, float roughness) {
+    float alpha = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+    return tangentToWorld(vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta), normal);
+}
+
+float distributionGgx(float normalDotHalf, float roughness) {
+    float alpha = roughness * roughness;
+    float alpha2 = alpha * alpha;
+    float denominator = normalDotHalf * normalDotHalf * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * denominator * denominator);
+}
@@ -0,0 +1,80 @@
+#version 460
+#extension GL_EXT_buffer_reference: require
+#extension GL_EXT_scalar_block_layout: require
+#extension GL_EXT_samplerless_texture_functions: require
+#include "camera.glsl"
+#include "lens_flare.glsl"
+
+layout (set = 0, binding = 0) uniform texture2D depthBuffer;
+
+layout (scalar, push_constant) uniform Registers
+{
+    CameraBuffer cameraBuffer;
+    SunOcclusionBuffer occlusionBuffer;
+    float shaftIntensity;
+    float shaftLength;
+    float flareIntensity;
+    float aspectRatio;
+} pushConstants;
+
+layout (location = 0) in vec2 fragCoord;
+layout (location = 1) flat in uint fragElement;
+layout (location = 2) flat in vec3 fragColor;
+
+layout (location = 0) out vec4 outColor;
+
+const float PI = 3.14159265359;
+const int SHAFT_SAMPLES = 32;
+// Weight lost per sample towards the sun, nearer sky contributes more.
+const float SHAFT_DECAY = 0.96;
+
+// Jitters the start of the march per pixel, trading banding for noise.
+float interleavedGradientNoise(vec2 position) {
+    return fract(52.9829189 * fract(dot(position, vec2(0.06711056, 0.00583715))));
+}
+
+// Marches from the pixel towards the sun through the depth buffer, gathering the sky around
+// the sun that isn't hidden by the scene, so geometry in front of the sun casts dark rays.
+float lightShafts(vec2 sunPosition) {
+    ivec2 size = textureSize(depthBuffer, 0);
+    vec2 uv = gl_FragCoord.xy / vec2(size);
+    vec2 sunUv = sunPosition * 0.5 + 0.5;
+    vec2 stepUv = (sunUv - uv) * pushConstants.shaftLength / float(SHAFT_SAMPLES);
+    uv += stepUv * interleavedGradientNoise(gl_FragCoord.xy);
+
+    float sum = 0.0;
+    float weight = 1.0;
+    for (int i = 0; i < SHAFT_SAMPLES; i++) {
+        uv += stepUv;
+        ivec2 coord = ivec2(uv * vec2(size));
+        if (all(greaterThanEqual(coord, ivec2(0))) && all(lessThan(coord, size))
+            && texelFetch(depthBuffer, coord, 0).r >= 1.0) {
+            // Only the bright sky close to the sun streams out.
+            vec2 toSun = (sunUv - uv) * vec2(pushConstants.aspectRatio, 1.0);
+            sum += weight * exp(-8.0 * length(toSun));
+        }
+        weight *= SHAFT_DECAY;
+    }
+    return sum / float(SHAFT_SAMPLES);
+}
+
+// Added onto the scene, in the luminance of a white surface facing the sun.
+void main() {
+    Camera camera = pushConstants.cameraBuffer.cameras[0];
+    vec3 sunLuminance = camera.sunColor * camera.sunIlluminance / PI * camera.exposure;
+
+    float amount;
+    if (fragElement == 0) {
+        amount = lightShafts(pushConstants.occlusionBuffer.position);
+    } else {
+        float radius = length(fragCoord);
+        if (radius >= 1.0) {
+            discard;
+        }
+        // The glow falls off steeply, ghosts are discs that brighten towards their rim.
+        amount = fragElement == 1
+            ? pow(1.0 - radius, 4.0)
+            : (0.5 + 0.5 * radius) * (1.0 - smoothstep(0.8, 1.0, radius));
+    }
+    outColor = vec4(fragColor * sunLuminance * amount, 1.0);
+}
@@ -0,0 +1,9 @@
+// Written by lens_flare_occlusion.comp, read by the flares drawn after it in the same frame.
+layout (buffer_reference, scalar) buffer SunOcclusionBuffer {
+    // Of the sun in normalized device coordinates.
+    vec2 position;
+    // Fraction of the sun's disk in front of the sky, 0 behind the camera.
+    float visibility;
+    // 1 while the sun is on screen, fading to 0 some way past the edges.
+    float onScreen;
+};
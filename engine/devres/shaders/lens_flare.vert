@@ -0,0 +1,78 @@
+#version 460
+#extension GL_EXT_buffer_reference: require
+#extension GL_EXT_scalar_block_layout: require
+#include "camera.glsl"
+#include "lens_flare.glsl"
+
+layout (scalar, push_constant) uniform Registers
+{
+    CameraBuffer cameraBuffer;
+    SunOcclusionBuffer occlusionBuffer;
+    float shaftIntensity;
+    float shaftLength;
+    float flareIntensity;
+    // Width over height of the render target.
+    float aspectRatio;
+} pushConstants;
+
+// Across the quad from -1 to 1.
+layout (location = 0) out vec2 fragCoord;
+// The light shafts for the first instance, a flare element for the others.
+layout (location = 1) flat out uint fragElement;
+layout (location = 2) flat out vec3 fragColor;
+
+const vec2 corners[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+// Along the line from the sun through the center of the screen, 1 at the sun and -1 mirrored
+// across the center, and the radius in screen heights. The first is the glow around the sun.
+const vec2 elements[7] = vec2[](
+    vec2(1.0, 0.3),
+    vec2(0.6, 0.03),
+    vec2(0.3, 0.05),
+    vec2(-0.2, 0.08),
+    vec2(-0.45, 0.04),
+    vec2(-0.7, 0.12),
+    vec2(-1.1, 0.2)
+);
+
+const vec3 colors[7] = vec3[](
+    vec3(1.0, 0.9, 0.7),
+    vec3(0.2, 0.5, 1.0),
+    vec3(0.4, 1.0, 0.5),
+    vec3(1.0, 0.6, 0.3),
+    vec3(0.7, 0.3, 1.0),
+    vec3(0.3, 0.8, 1.0),
+    vec3(1.0, 0.8, 0.5)
+);
+
+void main() {
+    vec2 corner = corners[gl_VertexIndex];
+    fragCoord = corner;
+    fragElement = uint(gl_InstanceIndex);
+
+    float visibility = gl_InstanceIndex == 0
+        ? pushConstants.occlusionBuffer.onScreen
+        : pushConstants.occlusionBuffer.visibility;
+    if (visibility <= 0.0) {
+        // Degenerate, nothing to draw for a sun out of sight.
+        gl_Position = vec4(0.0);
+        return;
+    }
+
+    if (gl_InstanceIndex == 0) {
+        fragColor = vec3(pushConstants.shaftIntensity * visibility);
+        gl_Position = vec4(corner, 0.0, 1.0);
+        return;
+    }
+
+    int index = gl_InstanceIndex - 1;
+    fragColor = colors[index] * pushConstants.flareIntensity * visibility;
+    vec2 element = elements[index];
+    vec2 center = pushConstants.occlusionBuffer.position * element.x;
+    // Normalized device coordinates span 2 screen heights vertically.
+    vec2 radius = vec2(2.0 * element.y / pushConstants.aspectRatio, 2.0 * element.y);
+    gl_Position = vec4(center + corner * radius, 0.0, 1.0);
+}
@@ -0,0 +1,46 @@
+// Screen tiles across and down, then depth slices spaced exponentially between the camera's
+// near and far planes, see light_clusters.comp.
+const uvec3 CLUSTER_COUNTS = uvec3(16, 9, 24);
+// Lights touching a cluster past this many are left out of it.
+const uint MAX_CLUSTER_LIGHTS = 63;
+
+// Point and spot lights, point lights have a cone covering every direction.
+struct PointLight {
+    // Relative to the camera.
+    vec3 position;
+    float range;
+    vec3 color;
+    uint shadowIndex;
+    vec3 direction;
+    float cosOuterAngle;
+    float cosInnerAngle;
+    uint spotShadowIndex;
+    uint padding[2];
+    mat4 spotShadowMatrix;
+};
+
+struct LightCluster {
+    uint count;
+    // Into the light buffer.
+    uint lights[MAX_CLUSTER_LIGHTS];
+};
+
+layout (buffer_reference, scalar) buffer LightClusterBuffer {
+    float nearPlane;
+    // log(far / near), the depth range the slices are spread over.
+    float logDepthRange;
+    uint padding[2];
+    LightCluster clusters[];
+};
+
+layout (buffer_reference, scalar) buffer PointLightBuffer {
+    uint count;
+    uint padding;
+    // Lights binned for the camera the frame is rendered with.
+    LightClusterBuffer clusterBuffer;
+    PointLight lights[];
+};
+
+uint lightClusterIndex(uvec3 cluster) {
+    return (cluster.z * CLUSTER_COUNTS.y + cluster.y) * CLUSTER_COUNTS.x + cluster.x;
+}
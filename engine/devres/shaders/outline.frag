@@ -0,0 +1,8 @@
+#version 460
+#include "push_constants.glsl"
+
+layout (location = 0) out vec4 outColor;
+
+void main() {
+    outColor = pushConstants.outlineColor;
+}
@@ -0,0 +1,19 @@
+#version 460
+#include "push_constants.glsl"
+
+void main() {
+    Vertex vertex = loadVertex(gl_VertexIndex);
+    Camera camera = pushConstants.cameraBuffer.cameras[0];
+    mat4 model = instanceModel(gl_InstanceIndex, gl_VertexIndex);
+
+    mat4 viewProjection = camera.projection * camera.view;
+    vec4 position = viewProjection * model * vec4(vertex.position, 1.0);
+
+    // Extrude along the screen space normal so the outline width is constant in pixels.
+    mat3 normalMatrix = transpose(inverse(mat3(model)));
+    vec2 clipNormal = (viewProjection * vec4(normalMatrix * vertex.normal, 0.0)).xy;
+    if (dot(clipNormal, clipNormal) > 0.0) {
+        position.xy += normalize(clipNormal) * pushConstants.outlineSize * position.w;
+    }
+    gl_Position = position;
+}
@@ -0,0 +1,28 @@
+#version 460
+#extension GL_EXT_buffer_reference: require
+#extension GL_EXT_scalar_block_layout: require
+
+struct PainterVertex {
+    vec2 position;
+    vec4 color;
+};
+
+layout (buffer_reference, scalar) buffer PainterVertexBuffer {
+    PainterVertex vertices[];
+};
+
+layout (scalar, push_constant) uniform Registers
+{
+    PainterVertexBuffer vertexBuffer;
+    vec2 screenSize;
+} pushConstants;
+
+layout (location = 0) out vec4 fragColor;
+
+void main() {
+    PainterVertex vertex = pushConstants.vertexBuffer.vertices[gl_VertexIndex];
+
+    // Pixels from the top left corner map to clip space, which points down in Vulkan.
+    gl_Position = vec4(vertex.position / pushConstants.screenSize * 2.0 - 1.0, 0.0, 1.0);
+    fragColor = vertex.color;
+}
\ No newline at end of file
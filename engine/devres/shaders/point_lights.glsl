@@ -0,0 +1,125 @@
+const uint INVALID_SHADOW_INDEX = 0xFFFFFFFFu;
+const float SHADOW_NEAR_PLANE = 0.05;
+const float SHADOW_BIAS = 0.0005;
+const uint MAX_SHADOWED_POINT_LIGHTS = 4;
+
+layout (set = 0, binding = 1) uniform samplerCubeShadow pointShadowMaps[MAX_SHADOWED_POINT_LIGHTS];
+// One layer per shadowed spot light.
+layout (set = 0, binding = 6) uniform sampler2DArrayShadow spotShadowMaps;
+
+const vec3 PCF_OFFSETS[20] = vec3[](
+    vec3(1, 1, 1), vec3(1, -1, 1), vec3(-1, -1, 1), vec3(-1, 1, 1),
+    vec3(1, 1, -1), vec3(1, -1, -1), vec3(-1, -1, -1), vec3(-1, 1, -1),
+    vec3(1, 1, 0), vec3(1, -1, 0), vec3(-1, -1, 0), vec3(-1, 1, 0),
+    vec3(1, 0, 1), vec3(-1, 0, 1), vec3(1, 0, -1), vec3(-1, 0, -1),
+    vec3(0, 1, 1), vec3(0, -1, 1), vec3(0, -1, -1), vec3(0, 1, -1)
+);
+
+// Depth the shadow pass wrote for a point at this offset from the light, the cube face is
+// picked by the largest axis.
+float pointShadowDepth(vec3 lightToFragment, float range) {
+    vec3 absolute = abs(lightToFragment);
+    float distance = max(absolute.x, max(absolute.y, absolute.z));
+    return range * (distance - SHADOW_NEAR_PLANE) / (distance * (range - SHADOW_NEAR_PLANE));
+}
+
+// Fraction of the light reaching the fragment, filtered over a few taps around the direction.
+float pointShadow(PointLight light, vec3 lightToFragment) {
+    float depth = pointShadowDepth(lightToFragment, light.range) - SHADOW_BIAS;
+    float radius = 0.02 * length(lightToFragment);
+    float visibility = 0.0;
+    for (int i = 0; i < 20; i++) {
+        vec3 direction = lightToFragment + PCF_OFFSETS[i] * radius;
+        visibility += texture(pointShadowMaps[nonuniformEXT(light.shadowIndex)], vec4(direction, depth));
+    }
+    return visibility / 20.0;
+}
+
+// Fraction of the light reaching the fragment through the spot light's single face, 3x3
+// filtered. Lit outside the cone's shadow map.
+float spotShadow(PointLight light, vec3 position) {
+    vec4 clip = light.spotShadowMatrix * vec4(position, 1.0);
+    if (clip.w <= 0.0) {
+        return 1.0;
+    }
+    vec3 coordinates = vec3(clip.xy / clip.w * 0.5 + 0.5, clip.z / clip.w - SHADOW_BIAS);
+    if (any(lessThan(coordinates, vec3(0.0))) || any(greaterThan(coordinates, vec3(1.0)))) {
+        return 1.0;
+    }
+    vec2 texelSize = 1.0 / vec2(textureSize(spotShadowMaps, 0).xy);
+    float visibility = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            visibility += texture(
+                spotShadowMaps,
+                vec4(coordinates.xy + vec2(x, y) * texelSize, float(light.spotShadowIndex), coordinates.z)
+            );
+        }
+    }
+    return visibility / 9.0;
+}
+
+// Cluster of the light clusters binned for the camera that a position relative to it falls in.
+uint positionLightCluster(Camera camera, LightClusterBuffer clusterBuffer, vec3 position) {
+    vec4 viewPosition = camera.view * vec4(position, 1.0);
+    vec4 clip = camera.projection * viewPosition;
+    vec2 tile = (clip.xy / clip.w * 0.5 + 0.5) * vec2(CLUSTER_COUNTS.xy);
+    float slice = log(max(-viewPosition.z, clusterBuffer.nearPlane) / clusterBuffer.nearPlane)
+        / clusterBuffer.logDepthRange * float(CLUSTER_COUNTS.z);
+    uvec3 cluster = uvec3(clamp(vec3(tile, slice), vec3(0.0), vec3(CLUSTER_COUNTS - 1u)));
+    return lightClusterIndex(cluster);
+}
+
+// Illuminance in lux from the point and spot lights of the position's cluster, or all of them
+// without clusters, with a windowed inverse square falloff of their intensity in candela.
+vec3 pointLighting(vec3 position, vec3 normal) {
+    PointLightBuffer lightBuffer = pushConstants.pointLightBuffer;
+    LightClusterBuffer clusterBuffer = lightBuffer.clusterBuffer;
+    bool clustered = (pushConstants.flags & UNCLUSTERED_LIGHTS_FLAG) == 0u;
+    uint clusterIndex = 0u;
+    uint count = lightBuffer.count;
+    if (clustered) {
+        clusterIndex = positionLightCluster(
+            pushConstants.cameraBuffer.cameras[0],
+            clusterBuffer,
+            position
+        );
+        count = clusterBuffer.clusters[clusterIndex].count;
+    }
+    vec3 irradiance = vec3(0.0);
+    for (uint i = 0u; i < count; i++) {
+        uint lightIndex = clustered ? clusterBuffer.clusters[clusterIndex].lights[i] : i;
+        PointLight light = lightBuffer.lights[lightIndex];
+        vec3 toLight = light.position - position;
+        float distance = length(toLight);
+        if (distance >= light.range) {
+            continue;
+        }
+        float window = clamp(1.0 - pow(distance / light.range, 4.0), 0.0, 1.0);
+        float attenuation = window * window / max(distance * distance, 0.0001);
+        float diffuse = max(dot(normal, toLight / distance), 0.0);
+        if (diffuse <= 0.0) {
+            continue;
+        }
+        // Spot lights fade out between their inner and outer angles.
+        float cone = 1.0;
+        if (light.cosOuterAngle >= -1.0) {
+            cone = smoothstep(
+                light.cosOuterAngle,
+                light.cosInnerAngle,
+                dot(light.direction, -toLight / distance)
+            );
+            if (cone <= 0.0) {
+                continue;
+            }
+        }
+        float shadow = 1.0;
+        if (light.shadowIndex != INVALID_SHADOW_INDEX) {
+            shadow = pointShadow(light, -toLight);
+        } else if (light.spotShadowIndex != INVALID_SHADOW_INDEX) {
+            shadow = spotShadow(light, position);
+        }
+        irradiance += light.color * (attenuation * diffuse * cone * shadow);
+    }
+    return irradiance;
+}
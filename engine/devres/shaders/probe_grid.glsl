@@ -0,0 +1,31 @@
+const float SH_L0 = 0.282095;
+const float SH_L1 = 0.488603;
+const float PI = 3.14159265359;
+
+vec3 evaluateIrradianceProbe(IrradianceProbe probe, vec3 normal) {
+    vec3 irradiance = probe.coefficients[0] * SH_L0
+        + (probe.coefficients[1] * normal.x
+        + probe.coefficients[2] * normal.y
+        + probe.coefficients[3] * normal.z) * SH_L1;
+    return max(irradiance, vec3(0.0));
+}
+
+// Trilinearly blends the 8 probes surrounding position, returning irradiance / PI.
+vec3 sampleProbeGrid(ProbeGridBuffer grid, vec3 position, vec3 normal) {
+    uvec3 counts = grid.counts;
+    vec3 gridPosition = clamp((position - grid.origin) / grid.spacing, vec3(0.0), vec3(counts - 1u));
+    uvec3 baseCell = min(uvec3(floor(gridPosition)), counts - 1u);
+    vec3 fraction = gridPosition - vec3(baseCell);
+
+    vec3 irradiance = vec3(0.0);
+    for (uint corner = 0u; corner < 8u; corner++) {
+        uvec3 offset = uvec3(corner & 1u, (corner >> 1u) & 1u, (corner >> 2u) & 1u);
+        uvec3 cell = min(baseCell + offset, counts - 1u);
+        vec3 weights = mix(1.0 - fraction, fraction, vec3(offset));
+        float weight = weights.x * weights.y * weights.z;
+        uint index = cell.x + counts.x * (cell.y + counts.y * cell.z);
+        irradiance += weight * evaluateIrradianceProbe(grid.probes[index], normal);
+    }
+
+    return irradiance / PI;
+}
@@ -1,37 +1,172 @@
 #extension GL_EXT_buffer_reference: require
 #extension GL_EXT_scalar_block_layout: require
+#include "camera.glsl"
+#include "lights.glsl"
 
 struct Vertex {
     vec3 position;
     vec3 normal;
     vec2 texCoord;
+    vec2 lightmapTexCoord;
+    vec3 bentNormal;
+    float ambientOcclusion;
+    // Bitangent handedness in w.
+    vec4 tangent;
 };
 
-struct Camera {
-    mat4 view;
-    mat4 projection;
-    vec3 position;
+struct QuantizedVertex {
+    uvec2 position;
+    uint normal;
+    vec2 texCoord;
+    vec2 lightmapTexCoord;
+    // Octahedral bent normal in the first two snorm bytes, ambient occlusion in the third.
+    uint occlusion;
+    // Octahedral tangent in the first two snorm bytes, handedness in the third.
+    uint tangent;
+};
+
+struct SkinVertex {
+    uvec4 joints;
+    vec4 weights;
 };
 
-struct Instance {
+// Per object record of the scene buffer.
+struct Object {
     mat4 model;
+    // Last frame's model matrix, relative to the current camera position like model. Equal to
+    // model unless the renderer keeps previous transforms.
+    mat4 previousModel;
+    vec3 boundsMin;
+    uint materialIndex;
+    vec3 boundsMax;
+    uint flags;
+};
+
+struct Material {
+    vec4 baseColorFactor;
+    uint baseColorTextureIndex;
+    float specularStrength;
+    uint normalTextureIndex;
+    float wetnessResponse;
+    uint lightmapTextureIndex;
+};
+
+layout (buffer_reference, scalar) buffer MaterialBuffer {
+    Material materials[];
+};
+
+struct IrradianceProbe {
+    vec3 coefficients[4];
+};
+
+layout (buffer_reference, scalar) buffer ProbeGridBuffer {
+    vec3 origin;
+    vec3 spacing;
+    uvec3 counts;
+    IrradianceProbe probes[];
 };
 
 layout (buffer_reference, scalar) buffer VertexBuffer {
     Vertex vertices[];
 };
 
-layout (buffer_reference, scalar) buffer CameraBuffer {
-    Camera cameras[];
+layout (buffer_reference, scalar) buffer QuantizedVertexBuffer {
+    QuantizedVertex vertices[];
 };
 
-layout (buffer_reference, scalar) buffer InstanceBuffer {
-    Instance instances[];
+layout (buffer_reference, scalar) buffer SkinBuffer {
+    SkinVertex vertices[];
+};
+
+layout (buffer_reference, scalar) buffer JointBuffer {
+    mat4 matrices[];
+};
+
+layout (buffer_reference, scalar) buffer JointOffsetBuffer {
+    uint offsets[];
+};
+
+layout (buffer_reference, scalar) buffer SceneBuffer {
+    Object objects[];
 };
 
 layout (scalar, push_constant) uniform Registers
 {
     VertexBuffer vertexBuffer;
-    InstanceBuffer instanceBuffer;
+    SceneBuffer sceneBuffer;
     CameraBuffer cameraBuffer;
-} pushConstants;
\ No newline at end of file
+    uint flags;
+    uint padding;
+    ProbeGridBuffer probeGrid;
+    SkinBuffer skinBuffer;
+    JointBuffer jointBuffer;
+    JointOffsetBuffer jointOffsetBuffer;
+    vec3 positionOffset;
+    vec3 positionScale;
+    vec4 outlineColor;
+    vec2 outlineSize;
+    PointLightBuffer pointLightBuffer;
+    MaterialBuffer materialBuffer;
+} pushConstants;
+
+const uint PROBE_GRID_FLAG = 1u;
+const uint SKINNING_FLAG = 2u;
+const uint QUANTIZED_FLAG = 4u;
+const uint IMAGE_BASED_LIGHTING_FLAG = 8u;
+const uint SUN_SHADOW_FLAG = 16u;
+// Point lights are looked up in the light buffer rather than the main camera's clusters, for
+// cameras seeing other parts of the scene.
+const uint UNCLUSTERED_LIGHTS_FLAG = 32u;
+
+const uint OBJECT_VISIBLE_FLAG = 1u;
+const uint OBJECT_SELECTED_FLAG = 2u;
+
+const uint INVALID_JOINT_OFFSET = 0xFFFFFFFFu;
+
+const uint INVALID_TEXTURE_INDEX = 0xFFFFFFFFu;
+vec3 octahedralDecode(vec2 encoded) {
+    vec3 normal = vec3(encoded, 1.0 - abs(encoded.x) - abs(encoded.y));
+    float t = max(-normal.z, 0.0);
+    normal.x += normal.x >= 0.0 ? -t : t;
+    normal.y += normal.y >= 0.0 ? -t : t;
+    return normalize(normal);
+}
+
+Vertex loadVertex(uint index) {
+    if ((pushConstants.flags & QUANTIZED_FLAG) == 0u) {
+        return pushConstants.vertexBuffer.vertices[index];
+    }
+    QuantizedVertex quantized = QuantizedVertexBuffer(pushConstants.vertexBuffer).vertices[index];
+    vec3 position = vec3(
+        unpackSnorm2x16(quantized.position.x),
+        unpackSnorm2x16(quantized.position.y).x
+    );
+    vec4 tangent = unpackSnorm4x8(quantized.tangent);
+    return Vertex(
+        pushConstants.positionOffset + position * pushConstants.positionScale,
+        octahedralDecode(unpackSnorm2x16(quantized.normal)),
+        quantized.texCoord,
+        quantized.lightmapTexCoord,
+        octahedralDecode(unpackSnorm4x8(quantized.occlusion).xy),
+        unpackUnorm4x8(quantized.occlusion).z,
+        vec4(octahedralDecode(tangent.xy), tangent.z < 0.0 ? -1.0 : 1.0)
+    );
+}
+
+// Instance transform including skinning, if the geometry is skinned.
+mat4 instanceModel(uint instanceIndex, uint vertexIndex) {
+    mat4 model = pushConstants.sceneBuffer.objects[instanceIndex].model;
+    if ((pushConstants.flags & SKINNING_FLAG) != 0u) {
+        uint jointOffset = pushConstants.jointOffsetBuffer.offsets[instanceIndex];
+        if (jointOffset != INVALID_JOINT_OFFSET) {
+            SkinVertex skinVertex = pushConstants.skinBuffer.vertices[vertexIndex];
+            mat4 skinMatrix = mat4(0.0);
+            for (int i = 0; i < 4; i++) {
+                skinMatrix += skinVertex.weights[i]
+                    * pushConstants.jointBuffer.matrices[jointOffset + skinVertex.joints[i]];
+            }
+            model = model * skinMatrix;
+        }
+    }
+    return model;
+}
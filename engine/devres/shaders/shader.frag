@@ -1,29 +1,146 @@
 #version 460
+#extension GL_EXT_nonuniform_qualifier: require
 #include "push_constants.glsl"
+#include "probe_grid.glsl"
+#include "point_lights.glsl"
 
 layout (location = 0) in vec3 fragPosition;
 layout (location = 1) in vec3 fragNormal;
 layout (location = 2) in vec2 fragTexCoord;
+layout (location = 3) in vec2 fragLightmapTexCoord;
+layout (location = 4) flat in uint fragMaterialIndex;
+layout (location = 5) in vec3 fragBentNormal;
+layout (location = 6) in float fragAmbientOcclusion;
+layout (location = 7) in vec4 fragTangent;
 
 layout (location = 0) out vec4 outColor;
 
 layout (set = 0, binding = 0) uniform sampler2D textures[];
+layout (set = 0, binding = 2) uniform samplerCube irradianceMap;
+layout (set = 0, binding = 3) uniform samplerCube specularMap;
+layout (set = 0, binding = 4) uniform sampler2D brdfLut;
+layout (set = 0, binding = 5) uniform sampler2DShadow sunShadowMap;
 
-const vec3 sunDirection = normalize(vec3(0.5, -1.0, 0.5));
-const float specularStrength = 0.5;
-const float ambient = 0.1;
+// Close to the sun highlight's exponent of 32.
+const float SPECULAR_ROUGHNESS = 0.5;
+// Reflectance at normal incidence of common dielectrics.
+const float DIELECTRIC_F0 = 0.04;
+
+// Sun shadow map texels the position is pushed along the normal by before the lookup, which
+// keeps surfaces from shadowing themselves without a depth bias detaching the shadows.
+const float SUN_SHADOW_NORMAL_OFFSET = 1.5;
+
+// Fraction of the sunlight reaching the position, 3x3 filtered. Lit outside the shadow map.
+float sunShadow(Camera camera, vec3 position, vec3 normal) {
+    if ((pushConstants.flags & SUN_SHADOW_FLAG) == 0u) {
+        return 1.0;
+    }
+    vec3 offsetPosition = position + normal * camera.sunShadowTexelSize * SUN_SHADOW_NORMAL_OFFSET;
+    vec4 clip = camera.sunShadowMatrix * vec4(offsetPosition, 1.0);
+    vec3 coordinates = vec3(clip.xy / clip.w * 0.5 + 0.5, clip.z / clip.w);
+    if (any(lessThan(coordinates, vec3(0.0))) || any(greaterThan(coordinates, vec3(1.0)))) {
+        return 1.0;
+    }
+    vec2 texelSize = 1.0 / vec2(textureSize(sunShadowMap, 0));
+    float visibility = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            visibility += texture(
+                sunShadowMap,
+                vec3(coordinates.xy + vec2(x, y) * texelSize, coordinates.z)
+            );
+        }
+    }
+    return visibility / 9.0;
+}
+
+// Perturbs the interpolated normal by the material's tangent space normal map, if it has one
+// and the mesh has tangents.
+vec3 surfaceNormal(Material material) {
+    vec3 normal = normalize(fragNormal);
+    if (material.normalTextureIndex == INVALID_TEXTURE_INDEX) {
+        return normal;
+    }
+    // Gram-Schmidt, interpolation leaves the tangent slightly off the normal.
+    vec3 tangent = fragTangent.xyz - normal * dot(normal, fragTangent.xyz);
+    if (dot(tangent, tangent) < 1e-8) {
+        return normal;
+    }
+    tangent = normalize(tangent);
+    vec3 bitangent = fragTangent.w * cross(normal, tangent);
+    vec3 mapped = texture(textures[nonuniformEXT(material.normalTextureIndex)], fragTexCoord).xyz
+        * 2.0 - 1.0;
+    return normalize(mat3(tangent, bitangent, normal) * mapped);
+}
 
 void main() {
     Camera camera = pushConstants.cameraBuffer.cameras[0];
     vec3 cameraPosition = camera.position;
 
-    vec4 texColor = texture(textures[0], fragTexCoord);
+    Material material = pushConstants.materialBuffer.materials[fragMaterialIndex];
+    vec4 texColor = material.baseColorFactor;
+    if (material.baseColorTextureIndex != INVALID_TEXTURE_INDEX) {
+        texColor *= texture(textures[nonuniformEXT(material.baseColorTextureIndex)], fragTexCoord);
+    }
+    vec3 normal = surfaceNormal(material);
 
-    float diffuse = max(dot(fragNormal, sunDirection), 0.0);
+    // Water fills the pores of wet surfaces, darkening them and making them glossy, more so
+    // facing the sky where it collects. Up is -y.
+    float wetness = camera.wetness * material.wetnessResponse
+        * mix(0.5, 1.0, clamp(-normal.y, 0.0, 1.0));
+    texColor.rgb *= mix(1.0, 0.5, wetness);
+    float specularStrength = mix(material.specularStrength, 1.0, wetness);
+
+    vec3 sunDirection = camera.sunDirection;
+    vec3 sun = camera.sunColor * camera.sunIlluminance
+        * sunShadow(camera, fragPosition, normalize(fragNormal));
+    vec3 diffuse = sun * max(dot(normal, sunDirection), 0.0);
+
+    vec3 indirect = vec3(camera.ambientIlluminance);
+    if (material.lightmapTextureIndex != INVALID_TEXTURE_INDEX) {
+        indirect = camera.bakedIlluminance * texture(
+            textures[nonuniformEXT(material.lightmapTextureIndex)],
+            fragLightmapTexCoord
+        ).rgb;
+    } else if ((pushConstants.flags & PROBE_GRID_FLAG) != 0u) {
+        // Light reaches the surface mostly from its least occluded direction.
+        indirect = camera.bakedIlluminance * sampleProbeGrid(
+            pushConstants.probeGrid,
+            fragPosition + camera.origin,
+            normalize(fragBentNormal)
+        );
+    } else if ((pushConstants.flags & IMAGE_BASED_LIGHTING_FLAG) != 0u) {
+        // The irradiance map holds irradiance / π for a sky texel of 1, like the probe grid.
+        indirect = PI * camera.skyLuminance
+            * texture(irradianceMap, normalize(fragBentNormal)).rgb;
+    }
+    // Lightmaps already include the occlusion.
+    if (material.lightmapTextureIndex == INVALID_TEXTURE_INDEX) {
+        indirect *= fragAmbientOcclusion;
+    }
 
     vec3 viewDirection = normalize(cameraPosition - fragPosition);
-    vec3 reflectDirection = reflect(-sunDirection, fragNormal);
+    vec3 reflectDirection = reflect(-sunDirection, normal);
     float specular = pow(max(dot(viewDirection, reflectDirection), 0.0), 32);
 
-    outColor = vec4(texColor.rgb * (diffuse + ambient) + specularStrength * specular, texColor.a);
+    // Split sum approximation, the prefiltered environment is already in cd/m².
+    vec3 environmentSpecular = vec3(0.0);
+    if ((pushConstants.flags & IMAGE_BASED_LIGHTING_FLAG) != 0u) {
+        vec3 environmentDirection = reflect(-viewDirection, normal);
+        float lod = SPECULAR_ROUGHNESS * float(textureQueryLevels(specularMap) - 1);
+        vec3 prefiltered = textureLod(specularMap, environmentDirection, lod).rgb;
+        vec2 brdf = texture(
+            brdfLut,
+            vec2(max(dot(normal, viewDirection), 0.0), SPECULAR_ROUGHNESS)
+        ).rg;
+        environmentSpecular = camera.skyLuminance * prefiltered
+            * (DIELECTRIC_F0 * brdf.x + brdf.y) * fragAmbientOcclusion;
+    }
+
+    // Illuminance in lux, a Lambertian surface reflects it as luminance in cd/m² divided by π.
+    vec3 illuminance = diffuse + indirect + pointLighting(fragPosition, normal);
+    vec3 luminance = (texColor.rgb * illuminance + specularStrength * specular * sun) / PI
+        + specularStrength * environmentSpecular;
+    outColor = vec4(luminance, texColor.a);
+    outColor.rgb *= camera.exposure;
 }
\ No newline at end of file
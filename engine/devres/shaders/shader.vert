@@ -4,18 +4,30 @@
 layout (location = 0) out vec3 fragPosition;
 layout (location = 1) out vec3 fragNormal;
 layout (location = 2) out vec2 fragTexCoord;
+layout (location = 3) out vec2 fragLightmapTexCoord;
+layout (location = 4) flat out uint fragMaterialIndex;
+layout (location = 5) out vec3 fragBentNormal;
+layout (location = 6) out float fragAmbientOcclusion;
+layout (location = 7) out vec4 fragTangent;
 
 void main() {
-    Vertex vertex = pushConstants.vertexBuffer.vertices[gl_VertexIndex];
-    Instance instance = pushConstants.instanceBuffer.instances[gl_InstanceIndex];
+    Vertex vertex = loadVertex(gl_VertexIndex);
     Camera camera = pushConstants.cameraBuffer.cameras[0];
+    mat4 model = instanceModel(gl_InstanceIndex, gl_VertexIndex);
 
-    mat4 mvp = camera.projection * camera.view * instance.model;
+    mat4 mvp = camera.projection * camera.view * model;
     gl_Position = mvp * vec4(vertex.position, 1.0);
-    fragPosition = vec3(instance.model * vec4(vertex.position, 1.0));
+    fragPosition = vec3(model * vec4(vertex.position, 1.0));
 
-    mat3 normalMatrix = transpose(inverse(mat3(instance.model)));
+    mat3 normalMatrix = transpose(inverse(mat3(model)));
     fragNormal = normalize(normalMatrix * vertex.normal);
+    fragBentNormal = normalize(normalMatrix * vertex.bentNormal);
+    fragAmbientOcclusion = vertex.ambientOcclusion;
+    // Tangents lie in the surface, they transform like positions rather than like normals.
+    // Left unnormalized since meshes without generated tangents have zero ones.
+    fragTangent = vec4(mat3(model) * vertex.tangent.xyz, vertex.tangent.w);
 
     fragTexCoord = vertex.texCoord;
+    fragLightmapTexCoord = vertex.lightmapTexCoord;
+    fragMaterialIndex = pushConstants.sceneBuffer.objects[gl_InstanceIndex].materialIndex;
 }
\ No newline at end of file
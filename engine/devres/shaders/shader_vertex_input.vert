@@ -0,0 +1,50 @@
+#version 460
+#include "push_constants.glsl"
+
+// Same as shader.vert with the vertex read from classic vertex input bindings.
+layout (location = 0) in vec3 inPosition;
+layout (location = 1) in vec3 inNormal;
+layout (location = 2) in vec2 inTexCoord;
+layout (location = 3) in vec2 inLightmapTexCoord;
+layout (location = 4) in vec3 inBentNormal;
+layout (location = 5) in float inAmbientOcclusion;
+layout (location = 6) in vec4 inTangent;
+
+layout (location = 0) out vec3 fragPosition;
+layout (location = 1) out vec3 fragNormal;
+layout (location = 2) out vec2 fragTexCoord;
+layout (location = 3) out vec2 fragLightmapTexCoord;
+layout (location = 4) flat out uint fragMaterialIndex;
+layout (location = 5) out vec3 fragBentNormal;
+layout (location = 6) out float fragAmbientOcclusion;
+layout (location = 7) out vec4 fragTangent;
+
+void main() {
+    Vertex vertex = Vertex(
+        inPosition,
+        inNormal,
+        inTexCoord,
+        inLightmapTexCoord,
+        inBentNormal,
+        inAmbientOcclusion,
+        inTangent
+    );
+    Camera camera = pushConstants.cameraBuffer.cameras[0];
+    mat4 model = instanceModel(gl_InstanceIndex, gl_VertexIndex);
+
+    mat4 mvp = camera.projection * camera.view * model;
+    gl_Position = mvp * vec4(vertex.position, 1.0);
+    fragPosition = vec3(model * vec4(vertex.position, 1.0));
+
+    mat3 normalMatrix = transpose(inverse(mat3(model)));
+    fragNormal = normalize(normalMatrix * vertex.normal);
+    fragBentNormal = normalize(normalMatrix * vertex.bentNormal);
+    fragAmbientOcclusion = vertex.ambientOcclusion;
+    // Tangents lie in the surface, they transform like positions rather than like normals.
+    // Left unnormalized since meshes without generated tangents have zero ones.
+    fragTangent = vec4(mat3(model) * vertex.tangent.xyz, vertex.tangent.w);
+
+    fragTexCoord = vertex.texCoord;
+    fragLightmapTexCoord = vertex.lightmapTexCoord;
+    fragMaterialIndex = pushConstants.sceneBuffer.objects[gl_InstanceIndex].materialIndex;
+}
\ No newline at end of file
@@ -0,0 +1,10 @@
+#version 460
+#include "push_constants.glsl"
+
+void main() {
+    Vertex vertex = loadVertex(gl_VertexIndex);
+    Camera camera = pushConstants.cameraBuffer.cameras[0];
+    mat4 model = instanceModel(gl_InstanceIndex, gl_VertexIndex);
+
+    gl_Position = camera.projection * camera.view * model * vec4(vertex.position, 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,18 @@
+#version 460
+#extension GL_ARB_shader_viewport_layer_array : require
+#include "push_constants.glsl"
+
+// Draws every instance once per cube face, instances are drawn six times as many with the face
+// in the lowest digit. Each face goes to its own layer and viewport, with the cameras of the
+// six faces following each other in the camera buffer.
+void main() {
+    uint face = gl_InstanceIndex % 6;
+    uint instanceIndex = gl_InstanceIndex / 6;
+    Vertex vertex = loadVertex(gl_VertexIndex);
+    Camera camera = pushConstants.cameraBuffer.cameras[face];
+    mat4 model = instanceModel(instanceIndex, gl_VertexIndex);
+
+    gl_Position = camera.projection * camera.view * model * vec4(vertex.position, 1.0);
+    gl_Layer = int(face);
+    gl_ViewportIndex = int(face);
+}
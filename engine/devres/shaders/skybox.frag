@@ -0,0 +1,22 @@
+#version 460
+#extension GL_EXT_buffer_reference: require
+#extension GL_EXT_scalar_block_layout: require
+#include "camera.glsl"
+
+layout (scalar, push_constant) uniform Registers
+{
+    CameraBuffer cameraBuffer;
+    float luminance;
+} pushConstants;
+
+layout (location = 0) in vec3 direction;
+
+layout (location = 0) out vec4 outColor;
+
+layout (set = 0, binding = 0) uniform samplerCube skybox;
+
+void main() {
+    Camera camera = pushConstants.cameraBuffer.cameras[0];
+    vec3 color = texture(skybox, normalize(direction)).rgb;
+    outColor = vec4(color * pushConstants.luminance * camera.exposure, 1.0);
+}
@@ -0,0 +1,27 @@
+#version 460
+#extension GL_EXT_buffer_reference: require
+#extension GL_EXT_scalar_block_layout: require
+#include "camera.glsl"
+
+layout (scalar, push_constant) uniform Registers
+{
+    CameraBuffer cameraBuffer;
+    float luminance;
+} pushConstants;
+
+layout (location = 0) out vec3 direction;
+
+// Full screen triangle.
+const vec2 positions[3] = vec2[](vec2(-1.0, -1.0), vec2(3.0, -1.0), vec2(-1.0, 3.0));
+
+void main() {
+    Camera camera = pushConstants.cameraBuffer.cameras[0];
+    // The view only rotates, the camera sits at the origin.
+    mat4 inverseViewProjection = inverse(camera.projection * camera.view);
+
+    vec2 position = positions[gl_VertexIndex];
+    vec4 farPoint = inverseViewProjection * vec4(position, 1.0, 1.0);
+    direction = farPoint.xyz / farPoint.w;
+    // On the far plane, where the depth buffer was cleared to.
+    gl_Position = vec4(position, 1.0, 1.0);
+}
@@ -0,0 +1,57 @@
+#version 460
+#extension GL_EXT_buffer_reference: require
+#extension GL_EXT_scalar_block_layout: require
+#include "auto_exposure.glsl"
+
+layout (set = 0, binding = 0) uniform sampler2D renderTarget;
+
+layout (scalar, push_constant) uniform Registers
+{
+    ExposureBuffer exposureBuffer;
+    // Manual exposure compensation as a multiplier.
+    float exposure;
+    uint tonemapOperator;
+    uint autoExposure;
+} pushConstants;
+
+layout (location = 0) in vec2 fragUv;
+
+layout (location = 0) out vec4 outColor;
+
+// Must match TonemapOperator.
+const uint OPERATOR_CLAMP = 0;
+const uint OPERATOR_REINHARD = 1;
+const uint OPERATOR_ACES = 2;
+
+// Narkowicz's fit of the ACES filmic curve, scaled so an input of 1 isn't pushed to white.
+vec3 aces(vec3 color) {
+    color *= 0.6;
+    return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+}
+
+// Of the luminance, keeping the hue of bright colors instead of washing them out per channel.
+vec3 reinhard(vec3 color) {
+    float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    return color / (1.0 + luminance);
+}
+
+// Maps the exposed HDR scene into the swapchain's range, the sRGB encoding is left to the
+// swapchain format.
+void main() {
+    vec3 color = texture(renderTarget, fragUv).rgb * pushConstants.exposure;
+    if (pushConstants.autoExposure != 0) {
+        color *= pushConstants.exposureBuffer.exposure;
+    }
+
+    switch (pushConstants.tonemapOperator) {
+        case OPERATOR_REINHARD:
+            color = reinhard(color);
+            break;
+        case OPERATOR_ACES:
+            color = aces(color);
+            break;
+        default:
+            break;
+    }
+    outColor = vec4(clamp(color, 0.0, 1.0), 1.0);
+}
@@ -0,0 +1,13 @@
+#version 460
+#extension GL_EXT_samplerless_texture_functions: require
+
+layout (set = 0, binding = 0) uniform texture2D uiImage;
+
+layout (location = 0) out vec4 outColor;
+
+void main() {
+    // The UI target holds alpha blended, thus premultiplied, colors; unpremultiply them for the
+    // alpha blended composite.
+    vec4 color = texelFetch(uiImage, ivec2(gl_FragCoord.xy), 0);
+    outColor = color.a > 0.0 ? vec4(color.rgb / color.a, color.a) : vec4(0.0);
+}
@@ -0,0 +1,40 @@
+#version 460
+#extension GL_EXT_buffer_reference: require
+#extension GL_EXT_scalar_block_layout: require
+#include "camera.glsl"
+
+layout (buffer_reference, scalar) buffer ParticleBuffer {
+    vec4 particles[];
+};
+
+layout (scalar, push_constant) uniform Registers
+{
+    ParticleBuffer particleBuffer;
+    CameraBuffer cameraBuffer;
+    vec3 wrappedCenter;
+    float extent;
+    vec3 center;
+    float size;
+    vec3 velocity;
+    float streakTime;
+    vec4 color;
+} pushConstants;
+
+layout (location = 0) in vec2 fragCoord;
+layout (location = 1) in float fragOpacity;
+
+layout (location = 0) out vec4 outColor;
+
+// Lit by the sun and the ambient light like a Lambertian surface, softened towards the edges,
+// round for flakes and across the width for streaks.
+void main() {
+    Camera camera = pushConstants.cameraBuffer.cameras[0];
+    float edge = pushConstants.streakTime > 0.0 ? abs(fragCoord.x) : length(fragCoord);
+    float opacity = pushConstants.color.a * fragOpacity * (1.0 - smoothstep(0.5, 1.0, edge));
+    if (opacity <= 0.0) {
+        discard;
+    }
+    vec3 illuminance = camera.sunColor * camera.sunIlluminance + vec3(camera.ambientIlluminance);
+    vec3 luminance = pushConstants.color.rgb * illuminance / 3.14159265359;
+    outColor = vec4(luminance * camera.exposure, opacity);
+}
@@ -0,0 +1,62 @@
+#version 460
+#extension GL_EXT_buffer_reference: require
+#extension GL_EXT_scalar_block_layout: require
+#include "camera.glsl"
+
+layout (buffer_reference, scalar) buffer ParticleBuffer {
+    vec4 particles[];
+};
+
+layout (scalar, push_constant) uniform Registers
+{
+    ParticleBuffer particleBuffer;
+    CameraBuffer cameraBuffer;
+    // Center of the volume around the camera in the wrapping coordinates of the particles.
+    vec3 wrappedCenter;
+    float extent;
+    // The same center relative to the camera.
+    vec3 center;
+    // Width of a streak or side of a flake, in meters.
+    float size;
+    vec3 velocity;
+    // Streaks are stretched along the velocity over this many seconds, flakes are square.
+    float streakTime;
+    // Albedo and opacity.
+    vec4 color;
+} pushConstants;
+
+layout (location = 0) out vec2 fragCoord;
+layout (location = 1) out float fragOpacity;
+
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main() {
+    Camera camera = pushConstants.cameraBuffer.cameras[0];
+    vec4 particle = pushConstants.particleBuffer.particles[gl_VertexIndex / 6];
+    vec2 corner = CORNERS[gl_VertexIndex % 6];
+
+    float extent = pushConstants.extent;
+    vec3 offset = mod(particle.xyz - pushConstants.wrappedCenter + 0.5 * extent, extent)
+        - 0.5 * extent;
+    vec3 viewCenter = (camera.view * vec4(pushConstants.center + offset, 1.0)).xyz;
+
+    // Quads face the camera, streaks turn around their axis towards it.
+    vec3 axis = mat3(camera.view) * pushConstants.velocity * pushConstants.streakTime;
+    vec3 side;
+    if (pushConstants.streakTime > 0.0 && dot(axis, axis) > 1e-8) {
+        side = normalize(cross(axis, viewCenter));
+        axis *= 0.5;
+    } else {
+        axis = vec3(0.0, 0.5 * pushConstants.size, 0.0);
+        side = vec3(1.0, 0.0, 0.0);
+    }
+    vec3 position = viewCenter + axis * corner.y + side * (0.5 * pushConstants.size * corner.x);
+
+    gl_Position = camera.projection * vec4(position, 1.0);
+    fragCoord = corner;
+    // Fades towards the edges of the volume, where particles wrap around.
+    fragOpacity = 1.0 - smoothstep(0.3 * extent, 0.5 * extent, length(offset));
+}
@@ -0,0 +1,231 @@
+use crate::renderer::json::quote;
+use crate::renderer::Renderer;
+use anyhow::Result;
+use nalgebra as na;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Clone, Debug)]
+pub struct BenchmarkAttributes {
+    // Control points of a Catmull-Rom spline the camera travels along over the whole run.
+    pub camera_path: Vec<na::Point3<f32>>,
+    pub target: na::Point3<f32>,
+    pub frame_count: u32,
+    pub report_path: PathBuf,
+    pub report_format: ReportFormat,
+    pub exit_when_done: bool,
+}
+
+struct FrameSample {
+    frame_time_ms: f32,
+    gpu_timings: Vec<(&'static str, f32)>,
+}
+
+pub(crate) struct Benchmark {
+    pub attributes: BenchmarkAttributes,
+    device_name: String,
+    frame: u32,
+    last_frame_end: Option<Instant>,
+    samples: Vec<FrameSample>,
+}
+
+fn catmull_rom(points: &[na::Point3<f32>], t: f32) -> na::Point3<f32> {
+    if points.len() == 1 {
+        return points[0];
+    }
+
+    let segment_count = points.len() - 1;
+    let t = t.clamp(0.0, 1.0) * segment_count as f32;
+    let segment = (t as usize).min(segment_count - 1);
+    let t = t - segment as f32;
+
+    let point = |index: isize| points[index.clamp(0, segment_count as isize) as usize].coords;
+    let segment = segment as isize;
+    let (p0, p1, p2, p3) = (
+        point(segment - 1),
+        point(segment),
+        point(segment + 1),
+        point(segment + 2),
+    );
+
+    let t2 = t * t;
+    let t3 = t2 * t;
+    na::Point3::from(
+        (p1 * 2.0
+            + (p2 - p0) * t
+            + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+            + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+            * 0.5,
+    )
+}
+
+struct Statistics {
+    mean: f32,
+    min: f32,
+    max: f32,
+    p99: f32,
+}
+
+impl Statistics {
+    fn new(mut values: Vec<f32>) -> Self {
+        if values.is_empty() {
+            return Self {
+                mean: 0.0,
+                min: 0.0,
+                max: 0.0,
+                p99: 0.0,
+            };
+        }
+        values.sort_by(f32::total_cmp);
+        Self {
+            mean: values.iter().sum::<f32>() / values.len() as f32,
+            min: values[0],
+            max: values[values.len() - 1],
+            p99: values[((values.len() - 1) as f32 * 0.99) as usize],
+        }
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            r#"{{"mean": {}, "min": {}, "max": {}, "p99": {}}}"#,
+            self.mean, self.min, self.max, self.p99
+        )
+    }
+}
+
+impl Benchmark {
+    pub fn new(attributes: BenchmarkAttributes, device_name: String) -> Result<Self> {
+        if attributes.camera_path.is_empty() {
+            return Err(anyhow::anyhow!("Benchmark camera path is empty"));
+        }
+        Ok(Self {
+            attributes,
+            device_name,
+            frame: 0,
+            last_frame_end: None,
+            samples: Vec::new(),
+        })
+    }
+
+    pub fn begin_frame(&self, renderer: &mut Renderer) {
+        let t = self.frame as f32 / self.attributes.frame_count.saturating_sub(1).max(1) as f32;
+        let eye = catmull_rom(&self.attributes.camera_path, t);
+        renderer.set_camera_look_at(Some((eye.cast(), self.attributes.target.cast())));
+    }
+
+    // Returns true once all frames have been rendered.
+    pub fn end_frame(&mut self, renderer: &Renderer) -> bool {
+        let now = Instant::now();
+        // The first frame has no previous frame to measure against.
+        if let Some(last_frame_end) = self.last_frame_end {
+            self.samples.push(FrameSample {
+                frame_time_ms: (now - last_frame_end).as_secs_f32() * 1000.0,
+                gpu_timings: renderer.gpu_timings().to_vec(),
+            });
+        }
+        self.last_frame_end = Some(now);
+        self.frame += 1;
+        self.frame >= self.attributes.frame_count
+    }
+
+    fn pass_names(&self) -> Vec<&'static str> {
+        self.samples
+            .iter()
+            .find(|sample| !sample.gpu_timings.is_empty())
+            .map(|sample| sample.gpu_timings.iter().map(|(pass, _)| *pass).collect())
+            .unwrap_or_default()
+    }
+
+    fn pass_time(sample: &FrameSample, pass: &str) -> f32 {
+        sample
+            .gpu_timings
+            .iter()
+            .find(|(name, _)| *name == pass)
+            .map_or(0.0, |(_, time)| *time)
+    }
+
+    fn to_csv(&self) -> String {
+        let passes = self.pass_names();
+        let mut csv = String::from("frame,frame_time_ms");
+        for pass in &passes {
+            write!(csv, ",gpu_{pass}_ms").unwrap();
+        }
+        csv.push('\n');
+        for (index, sample) in self.samples.iter().enumerate() {
+            write!(csv, "{},{}", index + 1, sample.frame_time_ms).unwrap();
+            for pass in &passes {
+                write!(csv, ",{}", Self::pass_time(sample, pass)).unwrap();
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+
+    fn to_json(&self) -> String {
+        let passes = self.pass_names();
+
+        let mut summary = format!(
+            r#""frame_time_ms": {}"#,
+            Statistics::new(self.samples.iter().map(|s| s.frame_time_ms).collect()).to_json()
+        );
+        for pass in &passes {
+            let times = self
+                .samples
+                .iter()
+                .map(|sample| Self::pass_time(sample, pass))
+                .collect();
+            write!(
+                summary,
+                ", {}: {}",
+                quote(&format!("gpu_{pass}_ms")),
+                Statistics::new(times).to_json()
+            )
+            .unwrap();
+        }
+
+        let frames = self
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(index, sample)| {
+                let gpu = sample
+                    .gpu_timings
+                    .iter()
+                    .map(|(pass, time)| format!("{}: {time}", quote(pass)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!(
+                    r#"    {{"frame": {}, "frame_time_ms": {}, "gpu_ms": {{{gpu}}}}}"#,
+                    index + 1,
+                    sample.frame_time_ms
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",\n");
+
+        format!(
+            "{{\n  \"device\": {},\n  \"frame_count\": {},\n  \"summary\": {{{summary}}},\n  \"frames\": [\n{frames}\n  ]\n}}\n",
+            quote(&self.device_name),
+            self.samples.len()
+        )
+    }
+
+    pub fn write_report(&self) -> Result<()> {
+        let report = match self.attributes.report_format {
+            ReportFormat::Json => self.to_json(),
+            ReportFormat::Csv => self.to_csv(),
+        };
+        if let Some(parent) = self.attributes.report_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.attributes.report_path, report)?;
+        Ok(())
+    }
+}
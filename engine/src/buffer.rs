@@ -105,6 +105,14 @@ impl Buffer {
         Ok(())
     }
 
+    pub fn read<T: bytemuck::Pod>(&self) -> Result<&[T]> {
+        Ok(bytemuck::cast_slice(
+            self.allocation
+                .mapped_slice()
+                .context("Failed to map buffer memory")?,
+        ))
+    }
+
     pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
         unsafe {
             self.attributes
@@ -0,0 +1,290 @@
+use crate::renderer::tonemap::{AutoExposureAttributes, TonemapOperator};
+use crate::renderer::window_renderer::WindowRenderer;
+use anyhow::{anyhow, Result};
+use ash::vk;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use tracing::{info, warn};
+
+// Clients sending longer lines are disconnected instead of buffering them without end.
+const MAX_LINE_LENGTH: usize = 4096;
+
+const HELP: &str = "commands:
+  exposure <value>|auto
+  ev100 <value>
+  tonemap none|reinhard|aces
+  exposure_compensation <stops>
+  auto_exposure on|off
+  ssaa <scale>
+  ssaa_filter nearest|linear
+  debug grid|frustum|nan_guard on|off
+  freeze_camera on|off
+  cascades <split>...
+  outline_width <pixels>
+  refresh_divisor <n>
+  help";
+
+#[derive(Clone, Debug)]
+pub struct DebugServerAttributes {
+    // Loopback by default, the server has no authentication.
+    pub address: SocketAddr,
+}
+
+impl Default for DebugServerAttributes {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], 7878)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DebugView {
+    Grid,
+    Frustum,
+    NanGuard,
+}
+
+// One line of the text protocol, applied to every window.
+#[derive(Clone, Debug, PartialEq)]
+enum DebugCommand {
+    Exposure(Option<f32>),
+    Ev100(f32),
+    Tonemap(TonemapOperator),
+    ExposureCompensation(f32),
+    AutoExposure(bool),
+    Ssaa(f32),
+    SsaaFilter(vk::Filter),
+    Debug(DebugView, bool),
+    FreezeCamera(bool),
+    Cascades(Vec<f32>),
+    OutlineWidth(f32),
+    RefreshDivisor(u32),
+    Help,
+}
+
+fn parse_switch(argument: Option<&str>) -> Result<bool> {
+    match argument {
+        Some("on") => Ok(true),
+        Some("off") => Ok(false),
+        _ => Err(anyhow!("expected on or off")),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(argument: Option<&str>) -> Result<T> {
+    argument
+        .and_then(|argument| argument.parse().ok())
+        .ok_or_else(|| anyhow!("expected a number"))
+}
+
+impl DebugCommand {
+    fn parse(line: &str) -> Result<Self> {
+        let mut arguments = line.split_whitespace();
+        let command = arguments.next().unwrap_or_default();
+        let mut argument = || arguments.next();
+        Ok(match command {
+            "exposure" => match argument() {
+                Some("auto") => Self::Exposure(None),
+                value => Self::Exposure(Some(parse_number(value)?)),
+            },
+            "ev100" => Self::Ev100(parse_number(argument())?),
+            "tonemap" => Self::Tonemap(match argument() {
+                Some("none") => TonemapOperator::None,
+                Some("reinhard") => TonemapOperator::Reinhard,
+                Some("aces") => TonemapOperator::Aces,
+                _ => return Err(anyhow!("expected none, reinhard or aces")),
+            }),
+            "exposure_compensation" => Self::ExposureCompensation(parse_number(argument())?),
+            "auto_exposure" => Self::AutoExposure(parse_switch(argument())?),
+            "ssaa" => Self::Ssaa(parse_number::<f32>(argument())?.clamp(0.25, 4.0)),
+            "ssaa_filter" => Self::SsaaFilter(match argument() {
+                Some("nearest") => vk::Filter::NEAREST,
+                Some("linear") => vk::Filter::LINEAR,
+                _ => return Err(anyhow!("expected nearest or linear")),
+            }),
+            "debug" => {
+                let view = match argument() {
+                    Some("grid") => DebugView::Grid,
+                    Some("frustum") => DebugView::Frustum,
+                    Some("nan_guard") => DebugView::NanGuard,
+                    _ => return Err(anyhow!("expected grid, frustum or nan_guard")),
+                };
+                Self::Debug(view, parse_switch(argument())?)
+            }
+            "freeze_camera" => Self::FreezeCamera(parse_switch(argument())?),
+            "cascades" => Self::Cascades(
+                arguments
+                    .map(|split| split.parse().map_err(|_| anyhow!("expected numbers")))
+                    .collect::<Result<_>>()?,
+            ),
+            "outline_width" => Self::OutlineWidth(parse_number(argument())?),
+            "refresh_divisor" => Self::RefreshDivisor(parse_number(argument())?),
+            "help" => Self::Help,
+            _ => return Err(anyhow!("unknown command {command:?}, try help")),
+        })
+    }
+
+    fn apply(&self, window_renderer: &mut WindowRenderer) {
+        let mut tonemap = window_renderer.attributes().tonemap;
+        let renderer = &mut window_renderer.renderer;
+        match self {
+            Self::Exposure(exposure) => renderer.set_exposure(*exposure),
+            Self::Ev100(ev100) => renderer.set_exposure_ev100(*ev100),
+            Self::Tonemap(operator) => {
+                tonemap.operator = *operator;
+                window_renderer.set_tonemap(tonemap);
+            }
+            Self::ExposureCompensation(stops) => {
+                tonemap.exposure_compensation = *stops;
+                window_renderer.set_tonemap(tonemap);
+            }
+            Self::AutoExposure(enabled) => {
+                // Keeps tuned attributes when switched back on.
+                if *enabled != tonemap.auto_exposure.is_some() {
+                    tonemap.auto_exposure = enabled.then(AutoExposureAttributes::default);
+                    window_renderer.set_tonemap(tonemap);
+                }
+            }
+            Self::Ssaa(ssaa) => window_renderer.set_ssaa(*ssaa),
+            Self::SsaaFilter(filter) => window_renderer.set_ssaa_filter(*filter),
+            Self::Debug(DebugView::Grid, enabled) => window_renderer.set_grid(*enabled),
+            Self::Debug(DebugView::Frustum, enabled) => {
+                renderer.debug_visualizations.camera_frustum = *enabled
+            }
+            Self::Debug(DebugView::NanGuard, enabled) => {
+                renderer.debug_visualizations.nan_guard = *enabled
+            }
+            Self::FreezeCamera(freeze) => renderer.freeze_debug_camera(*freeze),
+            Self::Cascades(splits) => renderer.debug_visualizations.cascade_splits = splits.clone(),
+            Self::OutlineWidth(width) => renderer.outline.width_pixels = *width,
+            Self::RefreshDivisor(divisor) => window_renderer.set_refresh_divisor(*divisor),
+            Self::Help => {}
+        }
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    address: SocketAddr,
+    // Bytes received after the last complete line.
+    pending: Vec<u8>,
+}
+
+// Line based text protocol over TCP, e.g. `nc localhost 7878`, so render settings of a
+// running build can be tuned without code changes. Polled once per frame without blocking;
+// every line is answered with `ok` or `error: ...`.
+pub(crate) struct DebugServer {
+    listener: TcpListener,
+    clients: Vec<Client>,
+}
+
+impl DebugServer {
+    pub fn new(attributes: &DebugServerAttributes) -> Result<Self> {
+        let listener = TcpListener::bind(attributes.address)?;
+        listener.set_nonblocking(true)?;
+        info!("Debug server listening on {}", listener.local_addr()?);
+        Ok(Self {
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    pub fn poll<'a>(&mut self, renderers: impl Iterator<Item = &'a mut WindowRenderer>) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    if let Err(error) = stream.set_nonblocking(true) {
+                        warn!("Dropping debug client {address}: {error}");
+                        continue;
+                    }
+                    info!("Debug client {address} connected");
+                    self.clients.push(Client {
+                        stream,
+                        address,
+                        pending: Vec::new(),
+                    });
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => {
+                    warn!("Debug server failed to accept a client: {error}");
+                    break;
+                }
+            }
+        }
+
+        let mut commands = Vec::new();
+        self.clients.retain_mut(|client| {
+            let connected = client.receive(&mut commands);
+            if !connected {
+                info!("Debug client {} disconnected", client.address);
+            }
+            connected
+        });
+
+        if !commands.is_empty() {
+            for renderer in renderers {
+                for command in &commands {
+                    command.apply(renderer);
+                }
+            }
+        }
+    }
+}
+
+impl Client {
+    // Parses every complete line and answers it, returns false once the client is gone.
+    fn receive(&mut self, commands: &mut Vec<DebugCommand>) -> bool {
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                // Lines sent right before closing the connection, e.g. piped into nc, were
+                // answered and still apply.
+                Ok(0) => return false,
+                Ok(count) => {
+                    self.pending.extend_from_slice(&buffer[..count]);
+                    if !self.answer_lines(commands) {
+                        return false;
+                    }
+                    if self.pending.len() > MAX_LINE_LENGTH {
+                        warn!(
+                            "Debug client {} sent a line longer than {MAX_LINE_LENGTH} bytes",
+                            self.address
+                        );
+                        return false;
+                    }
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return true,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+    }
+
+    // Returns false once replying fails.
+    fn answer_lines(&mut self, commands: &mut Vec<DebugCommand>) -> bool {
+        while let Some(end) = self.pending.iter().position(|&byte| byte == b'\n') {
+            let line = String::from_utf8_lossy(&self.pending[..end])
+                .trim()
+                .to_owned();
+            self.pending.drain(..=end);
+            if line.is_empty() {
+                continue;
+            }
+            let reply = match DebugCommand::parse(&line) {
+                Ok(DebugCommand::Help) => format!("{HELP}\nok\n"),
+                Ok(command) => {
+                    commands.push(command);
+                    "ok\n".to_owned()
+                }
+                Err(error) => format!("error: {error}\n"),
+            };
+            // Replies are small enough for the socket buffer, a client that doesn't read them
+            // just loses them.
+            match self.stream.write_all(reply.as_bytes()) {
+                Err(error) if error.kind() != ErrorKind::WouldBlock => return false,
+                _ => {}
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,198 @@
+use crate::renderer::geometry::Geometry;
+use crate::renderer::material::Material;
+use crate::renderer::{InstanceHit, Renderer};
+use anyhow::Result;
+use nalgebra as na;
+use std::collections::HashMap;
+use std::path::Path;
+use winit::dpi::PhysicalPosition;
+use winit::window::{Window, WindowId};
+
+// Reference to an object dragged out of a window, carried over to the window it is dropped on.
+#[derive(Clone, Debug)]
+pub struct DragPayload {
+    pub source_window: WindowId,
+    pub instance: usize,
+    // Whatever the drag source attached, e.g. an asset path or an entity id.
+    pub metadata: String,
+}
+
+// Called with the instance under the cursor when a drag starts, returns the metadata to carry
+// or None to not start a drag.
+pub type DragSource = Box<dyn FnMut(&Renderer, &InstanceHit) -> Option<String>>;
+
+// Called on the window the payload is dropped on, with the instance under the cursor if any
+// and the cursor position in the window's physical pixels.
+pub type DropTarget =
+    Box<dyn FnMut(&mut Renderer, &DragPayload, Option<InstanceHit>, PhysicalPosition<f64>)>;
+
+// What a file dropped onto a window from outside the engine was loaded as.
+#[derive(Clone, Debug)]
+pub enum DroppedAsset {
+    // An instance per model of the file.
+    Model {
+        instances: Vec<usize>,
+    },
+    // Shown on a cube with a material of its own.
+    Texture {
+        texture: u32,
+        material: u32,
+        instance: usize,
+    },
+}
+
+// Called on the window a file is dropped on with what it was spawned as, or why it couldn't be.
+pub type FileDropTarget = Box<dyn FnMut(&mut Renderer, &Path, Result<DroppedAsset>)>;
+
+// In meters, dropped assets appear this far ahead of the camera, scaled to fit a cube of
+// SPAWN_SIZE so both tiny and huge models can be looked at right away.
+const SPAWN_DISTANCE: f64 = 3.0;
+const SPAWN_SIZE: f64 = 1.5;
+
+// Loads an OBJ or glTF model or a texture through the renderer and spawns it in front of the camera.
+pub fn spawn_dropped_file(renderer: &mut Renderer, path: &Path) -> Result<DroppedAsset> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let (eye, target) = renderer.frame_look_at();
+    let forward = (target - eye)
+        .try_normalize(f64::EPSILON)
+        .unwrap_or(-na::Vector3::z());
+    let position = eye + forward * SPAWN_DISTANCE;
+
+    let texture = match extension.as_str() {
+        "obj" | "gltf" | "glb" => {
+            let models = if extension == "obj" {
+                renderer.load_obj(path)?
+            } else {
+                renderer.load_gltf(path)?
+            };
+            let (min, max) = models.iter().fold(
+                (
+                    na::Vector3::repeat(f32::INFINITY),
+                    na::Vector3::repeat(f32::NEG_INFINITY),
+                ),
+                |(min, max), &(mesh, _)| {
+                    let (mesh_min, mesh_max) = renderer.mesh_bounds(mesh);
+                    (min.inf(&mesh_min), max.sup(&mesh_max))
+                },
+            );
+            let transform = fit_transform(&position, &min.cast(), &max.cast());
+            let instances = models
+                .into_iter()
+                .map(|(mesh, material)| renderer.add_instance(mesh, material, transform))
+                .collect::<Result<_>>()?;
+            return Ok(DroppedAsset::Model { instances });
+        }
+        "hdr" | "exr" => renderer.load_hdr_texture(path)?,
+        "ktx2" | "dds" => renderer.load_compressed_texture(path)?,
+        "png" | "jpg" | "jpeg" | "bmp" | "tga" | "gif" | "webp" => {
+            let image = ::image::ImageReader::open(path)?.decode()?;
+            renderer.add_texture(&path.to_string_lossy(), &image.into_rgba8())?
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Unsupported file type {extension:?}, expected a model or a texture"
+            ))
+        }
+    };
+
+    let material = renderer.add_material(Material {
+        base_color_texture: Some(texture),
+        ..Default::default()
+    })?;
+    let mesh = renderer.add_mesh(Geometry::cube())?;
+    let half_extent = na::Vector3::repeat(0.5);
+    let transform = fit_transform(&position, &-half_extent, &half_extent);
+    let instance = renderer.add_instance(mesh, material, transform)?;
+    Ok(DroppedAsset::Texture {
+        texture,
+        material,
+        instance,
+    })
+}
+
+// Centers the bounds on the position, uniformly scaled to SPAWN_SIZE.
+fn fit_transform(
+    position: &na::Point3<f64>,
+    min: &na::Vector3<f64>,
+    max: &na::Vector3<f64>,
+) -> na::Affine3<f64> {
+    let size = (max - min).max();
+    let scale = if size > 0.0 { SPAWN_SIZE / size } else { 1.0 };
+    let center = (min + max) * 0.5;
+    na::Affine3::from_matrix_unchecked(
+        na::Matrix4::new_translation(&(position.coords - center * scale))
+            * na::Matrix4::new_scaling(scale),
+    )
+}
+
+#[derive(Default)]
+pub(crate) struct DragAndDrop {
+    pub sources: HashMap<WindowId, DragSource>,
+    pub targets: HashMap<WindowId, DropTarget>,
+    pub file_targets: HashMap<WindowId, FileDropTarget>,
+    pub cursor_positions: HashMap<WindowId, PhysicalPosition<f64>>,
+    pub hovered_window: Option<WindowId>,
+    pub payload: Option<DragPayload>,
+}
+
+impl DragAndDrop {
+    pub fn remove_window(&mut self, window_id: WindowId) {
+        self.sources.remove(&window_id);
+        self.targets.remove(&window_id);
+        self.file_targets.remove(&window_id);
+        self.cursor_positions.remove(&window_id);
+        if self.hovered_window == Some(window_id) {
+            self.hovered_window = None;
+        }
+        if self
+            .payload
+            .as_ref()
+            .is_some_and(|payload| payload.source_window == window_id)
+        {
+            self.payload = None;
+        }
+    }
+
+    // The window under the cursor when the button is released and the cursor position in it.
+    // Platforms keep sending events to the window a drag started in, so the release position is
+    // mapped through the windows' screen positions, falling back to the last entered window.
+    pub fn drop_location(
+        &self,
+        windows: &HashMap<WindowId, std::sync::Arc<Window>>,
+        source_window: WindowId,
+    ) -> Option<(WindowId, PhysicalPosition<f64>)> {
+        let cursor = *self.cursor_positions.get(&source_window)?;
+        let screen_position = windows
+            .get(&source_window)?
+            .inner_position()
+            .ok()
+            .map(|origin| {
+                PhysicalPosition::new(origin.x as f64 + cursor.x, origin.y as f64 + cursor.y)
+            });
+
+        if let Some(screen_position) = screen_position {
+            let location = windows.iter().find_map(|(&window_id, window)| {
+                let origin = window.inner_position().ok()?;
+                let size = window.inner_size();
+                let position = PhysicalPosition::new(
+                    screen_position.x - origin.x as f64,
+                    screen_position.y - origin.y as f64,
+                );
+                (position.x >= 0.0
+                    && position.y >= 0.0
+                    && position.x < size.width as f64
+                    && position.y < size.height as f64)
+                    .then_some((window_id, position))
+            });
+            if location.is_some() {
+                return location;
+            }
+        }
+
+        let window_id = self.hovered_window?;
+        Some((window_id, *self.cursor_positions.get(&window_id)?))
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Resolution of the histogram, percentiles are rounded up to the next bucket.
+pub const FRAME_TIME_BUCKET_WIDTH: Duration = Duration::from_micros(100);
+// Frame times past the last bucket are only counted, percentiles falling there report the
+// slowest frame of the window instead.
+pub const FRAME_TIME_BUCKET_COUNT: usize = 2500;
+
+#[derive(Clone, Debug)]
+pub struct FramePacingAttributes {
+    // Number of most recent frames the statistics cover.
+    pub window: usize,
+    // A frame taking longer than this multiple of the window's median is a spike.
+    pub spike_factor: f32,
+    // Frames faster than this are never spikes, so jitter at high frame rates isn't reported.
+    pub min_spike: Duration,
+    // Frames recorded before spikes are reported, so the median has settled.
+    pub warmup: usize,
+}
+
+impl Default for FramePacingAttributes {
+    fn default() -> Self {
+        Self {
+            window: 1000,
+            spike_factor: 2.0,
+            min_spike: Duration::from_millis(8),
+            warmup: 60,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameSpike {
+    // Counted from the first recorded frame.
+    pub frame: u64,
+    pub frame_time: Duration,
+    pub median: Duration,
+}
+
+pub type SpikeCallback = Box<dyn FnMut(&FrameSpike)>;
+
+// Rolling histogram of CPU frame times, measured between consecutive calls to record_frame, for
+// percentile queries and detecting hitches programmatically, e.g. in soak tests.
+pub struct FramePacing {
+    attributes: FramePacingAttributes,
+    frame_times: VecDeque<Duration>,
+    // Counts of the frame times in the window, the last bucket holds everything slower.
+    histogram: Vec<u32>,
+    last_frame: Option<Instant>,
+    frame: u64,
+    spike_count: u64,
+    on_spike: Option<SpikeCallback>,
+}
+
+impl Default for FramePacing {
+    fn default() -> Self {
+        Self::new(FramePacingAttributes::default())
+    }
+}
+
+fn bucket(frame_time: Duration) -> usize {
+    ((frame_time.as_nanos() / FRAME_TIME_BUCKET_WIDTH.as_nanos()) as usize)
+        .min(FRAME_TIME_BUCKET_COUNT)
+}
+
+impl FramePacing {
+    pub fn new(attributes: FramePacingAttributes) -> Self {
+        Self {
+            frame_times: VecDeque::with_capacity(attributes.window),
+            attributes,
+            histogram: vec![0; FRAME_TIME_BUCKET_COUNT + 1],
+            last_frame: None,
+            frame: 0,
+            spike_count: 0,
+            on_spike: None,
+        }
+    }
+
+    pub fn attributes(&self) -> &FramePacingAttributes {
+        &self.attributes
+    }
+
+    // Called on every frame that takes longer than the spike threshold.
+    pub fn set_spike_callback(&mut self, on_spike: impl FnMut(&FrameSpike) + 'static) {
+        self.on_spike = Some(Box::new(on_spike));
+    }
+
+    pub fn clear_spike_callback(&mut self) {
+        self.on_spike = None;
+    }
+
+    // Measures the time since the previous call, the first call only starts the clock.
+    pub fn record_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            self.record(now - last_frame);
+        }
+    }
+
+    pub fn record(&mut self, frame_time: Duration) {
+        if self.frame_times.len() >= self.attributes.window.max(1) {
+            if let Some(oldest) = self.frame_times.pop_front() {
+                self.histogram[bucket(oldest)] -= 1;
+            }
+        }
+
+        // Compared against the frames before it, so a spike doesn't raise its own threshold.
+        if self.frame_times.len() >= self.attributes.warmup {
+            if let Some(median) = self.median() {
+                let threshold = median
+                    .mul_f32(self.attributes.spike_factor)
+                    .max(self.attributes.min_spike);
+                if frame_time > threshold {
+                    self.spike_count += 1;
+                    if let Some(on_spike) = &mut self.on_spike {
+                        on_spike(&FrameSpike {
+                            frame: self.frame,
+                            frame_time,
+                            median,
+                        });
+                    }
+                }
+            }
+        }
+
+        self.frame_times.push_back(frame_time);
+        self.histogram[bucket(frame_time)] += 1;
+        self.frame += 1;
+    }
+
+    // Drops the window and restarts the clock, e.g. after loading a level.
+    pub fn reset(&mut self) {
+        self.frame_times.clear();
+        self.histogram.fill(0);
+        self.last_frame = None;
+    }
+
+    // Frames in the window.
+    pub fn len(&self) -> usize {
+        self.frame_times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frame_times.is_empty()
+    }
+
+    pub fn spike_count(&self) -> u64 {
+        self.spike_count
+    }
+
+    pub fn histogram(&self) -> &[u32] {
+        &self.histogram
+    }
+
+    // Upper edge of the bucket holding the given percentile in [0, 100] of the window.
+    pub fn percentile(&self, percentile: f32) -> Option<Duration> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * self.frame_times.len() as f32).ceil()
+            as u32)
+            .max(1);
+        let mut count = 0;
+        for (bucket, &bucket_count) in self.histogram.iter().enumerate() {
+            count += bucket_count;
+            if count >= rank {
+                return Some(if bucket == FRAME_TIME_BUCKET_COUNT {
+                    self.max()?
+                } else {
+                    FRAME_TIME_BUCKET_WIDTH * (bucket as u32 + 1)
+                });
+            }
+        }
+        self.max()
+    }
+
+    pub fn median(&self) -> Option<Duration> {
+        self.percentile(50.0)
+    }
+
+    pub fn p95(&self) -> Option<Duration> {
+        self.percentile(95.0)
+    }
+
+    pub fn p99(&self) -> Option<Duration> {
+        self.percentile(99.0)
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        Some(self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32)
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.frame_times.iter().max().copied()
+    }
+}
@@ -1,9 +1,11 @@
+use crate::renderer::nan_guard::NAN_GUARD_FORMAT;
 use crate::rendering_context::RenderingContext;
 use anyhow::Result;
 use ash::vk;
 use ash::vk::{Extent2D, Format, QUEUE_FAMILY_IGNORED};
 use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme, Allocator};
 use gpu_allocator::MemoryLocation;
+use std::ops::Range;
 use std::sync::Arc;
 
 pub struct ImageAttributes {
@@ -16,13 +18,16 @@ pub struct ImageAttributes {
     pub usage: vk::ImageUsageFlags,
     pub subresource_range: vk::ImageSubresourceRange,
     pub samples: vk::SampleCountFlags,
+    // Six layers in +X, -X, +Y, -Y, +Z, -Z order, viewed as a cube map.
+    pub cube: bool,
 }
 
 pub struct Image {
     pub handle: vk::Image,
     pub allocation: Option<Allocation>,
     pub view: vk::ImageView,
-    pub layout: ImageLayoutState,
+    // One per subresource of attributes.subresource_range, mip level major.
+    layouts: Vec<ImageLayoutState>,
     pub attributes: ImageAttributes,
     context: Arc<RenderingContext>,
 }
@@ -30,23 +35,27 @@ pub struct Image {
 fn create_image_view(
     context: &RenderingContext,
     image: vk::Image,
-    format: vk::Format,
-    aspect_flags: vk::ImageAspectFlags,
+    attributes: &ImageAttributes,
 ) -> Result<vk::ImageView> {
+    let (view_type, layer_count) = if attributes.cube {
+        (vk::ImageViewType::CUBE, 6)
+    } else {
+        (vk::ImageViewType::TYPE_2D, 1)
+    };
     let image_view = unsafe {
         context.device.create_image_view(
             &vk::ImageViewCreateInfo::default()
                 .image(image)
-                .view_type(vk::ImageViewType::TYPE_2D)
-                .format(format)
+                .view_type(view_type)
+                .format(attributes.format)
                 .components(vk::ComponentMapping::default())
                 .subresource_range(
                     vk::ImageSubresourceRange::default()
-                        .aspect_mask(aspect_flags)
+                        .aspect_mask(attributes.subresource_range.aspect_mask)
                         .base_mip_level(0)
-                        .level_count(1)
+                        .level_count(attributes.subresource_range.level_count)
                         .base_array_layer(0)
-                        .layer_count(1),
+                        .layer_count(layer_count),
                 ),
             None,
         )
@@ -61,14 +70,20 @@ impl Image {
         name: &str,
         attributes: ImageAttributes,
     ) -> Result<Self> {
+        let flags = if attributes.cube {
+            vk::ImageCreateFlags::CUBE_COMPATIBLE
+        } else {
+            vk::ImageCreateFlags::empty()
+        };
         let image = unsafe {
             context.device.create_image(
                 &vk::ImageCreateInfo::default()
+                    .flags(flags)
                     .image_type(vk::ImageType::TYPE_2D)
                     .format(attributes.format)
                     .extent(attributes.extent)
-                    .mip_levels(1)
-                    .array_layers(1)
+                    .mip_levels(attributes.subresource_range.level_count)
+                    .array_layers(attributes.subresource_range.layer_count)
                     .samples(attributes.samples)
                     .tiling(vk::ImageTiling::OPTIMAL)
                     .usage(attributes.usage)
@@ -104,18 +119,13 @@ impl Image {
                 .bind_image_memory(image, allocation.memory(), allocation.offset())
         }?;
 
-        let view = create_image_view(
-            context.as_ref(),
-            image,
-            attributes.format,
-            attributes.subresource_range.aspect_mask,
-        )?;
+        let view = create_image_view(context.as_ref(), image, &attributes)?;
 
         Ok(Image {
             handle: image,
             allocation: Some(allocation),
             view,
-            layout: ImageLayoutState::ignored(),
+            layouts: vec![ImageLayoutState::ignored(); subresource_count(&attributes)],
             attributes,
             context,
         })
@@ -147,6 +157,7 @@ impl Image {
                     .layer_count(1),
                 allocation_priority: 1.0,
                 samples,
+                cube: false,
             },
         )
     }
@@ -159,6 +170,12 @@ impl Image {
         format: vk::Format,
         allocation_priority: f32,
     ) -> Result<Image> {
+        // Float targets are also storage images so the NaN guard can mark pixels in place.
+        let storage = if format == NAN_GUARD_FORMAT {
+            vk::ImageUsageFlags::STORAGE
+        } else {
+            vk::ImageUsageFlags::empty()
+        };
         Image::new(
             context,
             allocator,
@@ -166,7 +183,11 @@ impl Image {
             ImageAttributes {
                 extent: extent.into(),
                 format,
-                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                // Sampled for analysis passes, see ImageAnalyzer.
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::SAMPLED
+                    | storage,
                 location: MemoryLocation::GpuOnly,
                 linear: false,
                 allocation_scheme: AllocationScheme::GpuAllocatorManaged,
@@ -176,6 +197,109 @@ impl Image {
                     .layer_count(1),
                 allocation_priority,
                 samples: vk::SampleCountFlags::TYPE_1,
+                cube: false,
+            },
+        )
+    }
+
+    // Levels below the first are filled by Commands::generate_mipmaps, see mip_level_count.
+    pub fn new_texture(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        name: &str,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        mip_levels: u32,
+    ) -> Result<Image> {
+        Image::new(
+            context,
+            allocator,
+            name,
+            ImageAttributes {
+                extent: extent.into(),
+                format,
+                // Mip levels are blitted from the level above them.
+                usage: vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_DST
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+                location: MemoryLocation::GpuOnly,
+                linear: false,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                subresource_range: vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .level_count(mip_levels)
+                    .layer_count(1),
+                allocation_priority: 1.0,
+                samples: vk::SampleCountFlags::TYPE_1,
+                cube: false,
+            },
+        )
+    }
+
+    // Sampled cube map with the faces as its six layers, uploaded like textures with every face
+    // of a level one after the other.
+    pub fn new_cubemap(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        name: &str,
+        size: u32,
+        format: vk::Format,
+        mip_levels: u32,
+    ) -> Result<Image> {
+        Image::new(
+            context,
+            allocator,
+            name,
+            ImageAttributes {
+                extent: vk::Extent3D {
+                    width: size,
+                    height: size,
+                    depth: 1,
+                },
+                format,
+                usage: vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_DST
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+                location: MemoryLocation::GpuOnly,
+                linear: false,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                subresource_range: vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .level_count(mip_levels)
+                    .layer_count(6),
+                allocation_priority: 1.0,
+                samples: vk::SampleCountFlags::TYPE_1,
+                cube: true,
+            },
+        )
+    }
+
+    // Intermediate color image that frames are blitted into before being read back.
+    pub fn new_readback_target(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        name: &str,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Image> {
+        Image::new(
+            context,
+            allocator,
+            name,
+            ImageAttributes {
+                extent: extent.into(),
+                format,
+                usage: vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST,
+                location: MemoryLocation::GpuOnly,
+                linear: false,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                subresource_range: vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .level_count(1)
+                    .layer_count(1),
+                allocation_priority: 1.0,
+                samples: vk::SampleCountFlags::TYPE_1,
+                cube: false,
             },
         )
     }
@@ -194,7 +318,10 @@ impl Image {
             ImageAttributes {
                 extent: extent.into(),
                 format,
-                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                // Sampled by debug passes, see NanGuard, and copied out for depth readback.
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
                 location: MemoryLocation::GpuOnly,
                 linear: false,
                 allocation_scheme: AllocationScheme::GpuAllocatorManaged,
@@ -204,6 +331,7 @@ impl Image {
                     .layer_count(1),
                 allocation_priority: 1.0,
                 samples: vk::SampleCountFlags::TYPE_1,
+                cube: false,
             },
         )
     }
@@ -234,6 +362,7 @@ impl Image {
                     .layer_count(1),
                 allocation_priority: 1.0,
                 samples,
+                cube: false,
             },
         )
     }
@@ -243,25 +372,124 @@ impl Image {
         handle: vk::Image,
         attributes: ImageAttributes,
     ) -> Result<Self> {
-        let view = create_image_view(
-            context.as_ref(),
-            handle,
-            attributes.format,
-            attributes.subresource_range.aspect_mask,
-        )?;
+        let view = create_image_view(context.as_ref(), handle, &attributes)?;
 
         Ok(Self {
             handle,
             allocation: None,
             view,
-            layout: ImageLayoutState::ignored(),
+            layouts: vec![ImageLayoutState::ignored(); subresource_count(&attributes)],
             attributes,
             context,
         })
     }
 
     pub fn reset_layout(&mut self) {
-        self.layout = ImageLayoutState::ignored();
+        self.layouts.fill(ImageLayoutState::ignored());
+    }
+
+    // Layout of the first subresource, which is the layout of the whole image unless parts of
+    // it were transitioned on their own.
+    pub fn layout(&self) -> ImageLayoutState {
+        self.layouts[0]
+    }
+
+    pub fn subresource_layout(&self, mip_level: u32, array_layer: u32) -> ImageLayoutState {
+        self.layouts[self.subresource_index(mip_level, array_layer)]
+    }
+
+    pub fn layouts(&self) -> &[ImageLayoutState] {
+        &self.layouts
+    }
+
+    fn subresource_index(&self, mip_level: u32, array_layer: u32) -> usize {
+        let range = self.attributes.subresource_range;
+        ((mip_level - range.base_mip_level) * range.layer_count + array_layer
+            - range.base_array_layer) as usize
+    }
+
+    // Resolves VK_REMAINING_MIP_LEVELS and VK_REMAINING_ARRAY_LAYERS against the image's range.
+    pub fn resolve_subresource_range(
+        &self,
+        range: vk::ImageSubresourceRange,
+    ) -> vk::ImageSubresourceRange {
+        let full_range = self.attributes.subresource_range;
+        let mut range = range;
+        if range.level_count == vk::REMAINING_MIP_LEVELS {
+            range.level_count =
+                full_range.base_mip_level + full_range.level_count - range.base_mip_level;
+        }
+        if range.layer_count == vk::REMAINING_ARRAY_LAYERS {
+            range.layer_count =
+                full_range.base_array_layer + full_range.layer_count - range.base_array_layer;
+        }
+        range
+    }
+
+    pub fn set_subresource_layout(
+        &mut self,
+        range: vk::ImageSubresourceRange,
+        state: ImageLayoutState,
+    ) {
+        let range = self.resolve_subresource_range(range);
+        for mip_level in range.base_mip_level..range.base_mip_level + range.level_count {
+            for array_layer in range.base_array_layer..range.base_array_layer + range.layer_count {
+                let index = self.subresource_index(mip_level, array_layer);
+                self.layouts[index] = state;
+            }
+        }
+    }
+
+    // Barriers moving the range to the new state, skipping subresources that are already in it
+    // when only_if_needed is set. Layers of a mip level sharing a state are covered by one
+    // barrier, which grows over the following levels as long as their layers match.
+    pub fn layout_transitions(
+        &self,
+        range: vk::ImageSubresourceRange,
+        new_state: ImageLayoutState,
+        only_if_needed: bool,
+    ) -> Vec<(ImageLayoutState, vk::ImageSubresourceRange)> {
+        let range = self.resolve_subresource_range(range);
+        let mut transitions = Vec::<(ImageLayoutState, vk::ImageSubresourceRange)>::new();
+        for mip_level in range.base_mip_level..range.base_mip_level + range.level_count {
+            let mut runs = Vec::<(ImageLayoutState, Range<u32>)>::new();
+            for array_layer in range.base_array_layer..range.base_array_layer + range.layer_count {
+                let state = self.subresource_layout(mip_level, array_layer);
+                if only_if_needed && new_state.is_subset_of(state) {
+                    continue;
+                }
+                match runs.last_mut() {
+                    Some((run_state, layers))
+                        if *run_state == state && layers.end == array_layer =>
+                    {
+                        layers.end += 1
+                    }
+                    _ => runs.push((state, array_layer..array_layer + 1)),
+                }
+            }
+
+            for (state, layers) in runs {
+                let previous_level = transitions.iter_mut().find(|(transition_state, range)| {
+                    *transition_state == state
+                        && range.base_mip_level + range.level_count == mip_level
+                        && range.base_array_layer == layers.start
+                        && range.layer_count == layers.len() as u32
+                });
+                match previous_level {
+                    Some((_, range)) => range.level_count += 1,
+                    None => transitions.push((
+                        state,
+                        vk::ImageSubresourceRange::default()
+                            .aspect_mask(range.aspect_mask)
+                            .base_mip_level(mip_level)
+                            .level_count(1)
+                            .base_array_layer(layers.start)
+                            .layer_count(layers.len() as u32),
+                    )),
+                }
+            }
+        }
+        transitions
     }
 
     pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
@@ -275,6 +503,31 @@ impl Image {
         Ok(())
     }
 
+    // Bytes of the first mip level when tightly packed, e.g. in a staging buffer.
+    pub fn size(&self) -> Result<vk::DeviceSize> {
+        self.level_size(0)
+    }
+
+    // Bytes of a level across all layers.
+    pub fn level_size(&self, level: u32) -> Result<vk::DeviceSize> {
+        Ok(level_size(self.attributes.format, self.mip_extent(level))?
+            * self.attributes.subresource_range.layer_count as vk::DeviceSize)
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.attributes.subresource_range.level_count
+    }
+
+    // Extent of a mip level, halved per level down to one texel.
+    pub fn mip_extent(&self, level: u32) -> vk::Extent3D {
+        let extent = self.attributes.extent;
+        vk::Extent3D {
+            width: (extent.width >> level).max(1),
+            height: (extent.height >> level).max(1),
+            depth: (extent.depth >> level).max(1),
+        }
+    }
+
     pub fn subresource_layers(&self) -> vk::ImageSubresourceLayers {
         vk::ImageSubresourceLayers::default()
             .aspect_mask(self.attributes.subresource_range.aspect_mask)
@@ -284,6 +537,89 @@ impl Image {
     }
 }
 
+// Levels of a full mip chain down to one texel.
+pub fn mip_level_count(extent: vk::Extent2D) -> u32 {
+    u32::BITS - extent.width.max(extent.height).max(1).leading_zeros()
+}
+
+fn subresource_count(attributes: &ImageAttributes) -> usize {
+    (attributes.subresource_range.level_count * attributes.subresource_range.layer_count) as usize
+}
+
+// Bytes per 4x4 block of the BCn formats, None for formats that aren't block compressed.
+pub fn block_size(format: vk::Format) -> Option<vk::DeviceSize> {
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK => Some(8),
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => Some(16),
+        _ => None,
+    }
+}
+
+// Bytes of a tightly packed level of the given extent.
+pub fn level_size(format: vk::Format, extent: vk::Extent3D) -> Result<vk::DeviceSize> {
+    Ok(match block_size(format) {
+        Some(block_size) => {
+            extent.width.div_ceil(4) as vk::DeviceSize
+                * extent.height.div_ceil(4) as vk::DeviceSize
+                * extent.depth as vk::DeviceSize
+                * block_size
+        }
+        None => {
+            (extent.width * extent.height * extent.depth) as vk::DeviceSize * texel_size(format)?
+        }
+    })
+}
+
+// Bytes per texel of the uncompressed formats, an error for the ones images aren't created
+// with. Depth and stencil formats count their depth aspect only, as it is copied on its own.
+pub fn texel_size(format: vk::Format) -> Result<vk::DeviceSize> {
+    Ok(match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB | vk::Format::R8_UINT => 1,
+        vk::Format::R8G8_UNORM
+        | vk::Format::R8G8_SRGB
+        | vk::Format::R16_UNORM
+        | vk::Format::R16_SFLOAT
+        | vk::Format::R16_UINT
+        | vk::Format::D16_UNORM
+        | vk::Format::D16_UNORM_S8_UINT => 2,
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::A2R10G10B10_UNORM_PACK32
+        | vk::Format::B10G11R11_UFLOAT_PACK32
+        | vk::Format::E5B9G9R9_UFLOAT_PACK32
+        | vk::Format::R16G16_UNORM
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_UINT
+        | vk::Format::R32_SFLOAT
+        | vk::Format::D32_SFLOAT
+        | vk::Format::D32_SFLOAT_S8_UINT
+        | vk::Format::X8_D24_UNORM_PACK32
+        | vk::Format::D24_UNORM_S8_UINT => 4,
+        vk::Format::R16G16B16A16_UNORM
+        | vk::Format::R16G16B16A16_SFLOAT
+        | vk::Format::R32G32_SFLOAT => 8,
+        vk::Format::R32G32B32A32_SFLOAT => 16,
+        _ => return Err(anyhow::anyhow!("Texel size of {format:?} is unknown")),
+    })
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct ImageLayoutState {
     pub access: vk::AccessFlags2,
@@ -315,7 +651,19 @@ impl ImageLayoutState {
         Self {
             access: vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
             layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-            stage: vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS,
+            // Depth is stored in the late tests, which later reads have to wait for.
+            stage: vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            queue_family: QUEUE_FAMILY_IGNORED,
+        }
+    }
+
+    // Resolve attachments are written in the color attachment output stage, depth ones included.
+    pub fn depth_resolve_attachment() -> Self {
+        Self {
+            access: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            stage: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
             queue_family: QUEUE_FAMILY_IGNORED,
         }
     }
@@ -356,6 +704,24 @@ impl ImageLayoutState {
         }
     }
 
+    pub fn compute_shader_read() -> Self {
+        Self {
+            access: vk::AccessFlags2::SHADER_READ,
+            layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            stage: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            queue_family: QUEUE_FAMILY_IGNORED,
+        }
+    }
+
+    pub fn compute_shader_storage() -> Self {
+        Self {
+            access: vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            layout: vk::ImageLayout::GENERAL,
+            stage: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            queue_family: QUEUE_FAMILY_IGNORED,
+        }
+    }
+
     pub fn is_subset_of(&self, other: Self) -> bool {
         self.layout == other.layout
             && self.access.contains(other.access)
@@ -0,0 +1,217 @@
+use nalgebra as na;
+use std::collections::{HashMap, HashSet};
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{Key, KeyCode, ModifiersState, PhysicalKey};
+use winit::window::WindowId;
+
+// Keyboard and mouse state of one window. Keys are physical, so bindings stay in place across
+// keyboard layouts. Presses, releases, cursor movement and scrolling are counted since the
+// window's last rendered frame.
+#[derive(Clone, Debug, Default)]
+pub struct InputState {
+    keys_down: HashSet<KeyCode>,
+    keys_pressed: HashSet<KeyCode>,
+    keys_released: HashSet<KeyCode>,
+    mouse_buttons_down: HashSet<MouseButton>,
+    mouse_buttons_pressed: HashSet<MouseButton>,
+    mouse_buttons_released: HashSet<MouseButton>,
+    modifiers: ModifiersState,
+    // None while the cursor is outside the window.
+    cursor_position: Option<PhysicalPosition<f64>>,
+    cursor_delta: na::Vector2<f64>,
+    scroll_lines: na::Vector2<f32>,
+    scroll_pixels: na::Vector2<f64>,
+}
+
+impl InputState {
+    pub fn is_key_down(&self, key: KeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    // Key repeats don't count as presses.
+    pub fn was_key_pressed(&self, key: KeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn was_key_released(&self, key: KeyCode) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn keys_down(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        self.keys_down.iter().copied()
+    }
+
+    pub fn is_mouse_button_down(&self, button: MouseButton) -> bool {
+        self.mouse_buttons_down.contains(&button)
+    }
+
+    pub fn was_mouse_button_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_buttons_pressed.contains(&button)
+    }
+
+    pub fn was_mouse_button_released(&self, button: MouseButton) -> bool {
+        self.mouse_buttons_released.contains(&button)
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    // In the window's physical pixels.
+    pub fn cursor_position(&self) -> Option<PhysicalPosition<f64>> {
+        self.cursor_position
+    }
+
+    // Only counts movement inside the window, in physical pixels.
+    pub fn cursor_delta(&self) -> na::Vector2<f64> {
+        self.cursor_delta
+    }
+
+    // Wheels scroll by lines and touchpads by pixels, positive y scrolls up.
+    pub fn scroll_lines(&self) -> na::Vector2<f32> {
+        self.scroll_lines
+    }
+
+    pub fn scroll_pixels(&self) -> na::Vector2<f64> {
+        self.scroll_pixels
+    }
+
+    fn handle_event(&mut self, event: &WindowEvent) -> Option<InputEvent> {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                if let PhysicalKey::Code(key) = event.physical_key {
+                    match event.state {
+                        ElementState::Pressed if !event.repeat => {
+                            self.keys_down.insert(key);
+                            self.keys_pressed.insert(key);
+                        }
+                        ElementState::Pressed => {}
+                        ElementState::Released => {
+                            self.keys_down.remove(&key);
+                            self.keys_released.insert(key);
+                        }
+                    }
+                }
+                Some(InputEvent::Key {
+                    physical_key: event.physical_key,
+                    logical_key: event.logical_key.clone(),
+                    state: event.state,
+                    repeat: event.repeat,
+                })
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                match state {
+                    ElementState::Pressed => {
+                        self.mouse_buttons_down.insert(*button);
+                        self.mouse_buttons_pressed.insert(*button);
+                    }
+                    ElementState::Released => {
+                        self.mouse_buttons_down.remove(button);
+                        self.mouse_buttons_released.insert(*button);
+                    }
+                }
+                Some(InputEvent::MouseButton {
+                    button: *button,
+                    state: *state,
+                })
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some(previous) = self.cursor_position {
+                    self.cursor_delta +=
+                        na::Vector2::new(position.x - previous.x, position.y - previous.y);
+                }
+                self.cursor_position = Some(*position);
+                Some(InputEvent::CursorMoved(*position))
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor_position = None;
+                None
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                match delta {
+                    MouseScrollDelta::LineDelta(x, y) => {
+                        self.scroll_lines += na::Vector2::new(*x, *y);
+                    }
+                    MouseScrollDelta::PixelDelta(position) => {
+                        self.scroll_pixels += na::Vector2::new(position.x, position.y);
+                    }
+                }
+                Some(InputEvent::Scroll(*delta))
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+                None
+            }
+            // Releases while unfocused never arrive, so nothing stays held down.
+            WindowEvent::Focused(false) => {
+                self.keys_released.extend(self.keys_down.drain());
+                self.mouse_buttons_released
+                    .extend(self.mouse_buttons_down.drain());
+                self.modifiers = ModifiersState::empty();
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.mouse_buttons_pressed.clear();
+        self.mouse_buttons_released.clear();
+        self.cursor_delta = na::Vector2::zeros();
+        self.scroll_lines = na::Vector2::zeros();
+        self.scroll_pixels = na::Vector2::zeros();
+    }
+}
+
+// Input events as they arrive, for apps that react to them instead of polling InputState.
+#[derive(Clone, Debug)]
+pub enum InputEvent {
+    Key {
+        physical_key: PhysicalKey,
+        // What the key types in the current layout, e.g. for text shortcuts.
+        logical_key: Key,
+        state: ElementState,
+        repeat: bool,
+    },
+    MouseButton {
+        button: MouseButton,
+        state: ElementState,
+    },
+    CursorMoved(PhysicalPosition<f64>),
+    Scroll(MouseScrollDelta),
+}
+
+// Called after the window's InputState took the event in.
+pub type InputCallback = Box<dyn FnMut(WindowId, &InputEvent)>;
+
+#[derive(Default)]
+pub(crate) struct Input {
+    pub windows: HashMap<WindowId, InputState>,
+    pub callback: Option<InputCallback>,
+}
+
+impl Input {
+    pub fn handle_event(&mut self, window_id: WindowId, event: &WindowEvent) {
+        let input_event = self
+            .windows
+            .entry(window_id)
+            .or_default()
+            .handle_event(event);
+        if let (Some(input_event), Some(callback)) = (input_event, &mut self.callback) {
+            callback(window_id, &input_event);
+        }
+    }
+
+    pub fn end_frame(&mut self, window_id: WindowId) {
+        if let Some(state) = self.windows.get_mut(&window_id) {
+            state.end_frame();
+        }
+    }
+
+    pub fn remove_window(&mut self, window_id: WindowId) {
+        self.windows.remove(&window_id);
+    }
+}
@@ -1,32 +1,163 @@
 #![allow(dead_code)]
+mod benchmark;
 mod buffer;
+mod debug_server;
+mod drag_drop;
+mod frame_pacing;
 mod image;
+mod input;
+mod query_pool;
 mod renderer;
 mod rendering_context;
+mod replay;
+mod shader_reflection;
+mod sparse_image;
+mod time;
 
-use crate::rendering_context::{queue_family_picker, RenderingContext, RenderingContextAttributes};
+use crate::benchmark::Benchmark;
+use crate::debug_server::DebugServer;
+use crate::drag_drop::DragAndDrop;
+use crate::input::Input;
+use crate::rendering_context::{queue_family_picker, RenderingContextAttributes};
+use crate::replay::{Recorder, Replay};
 use anyhow::Result;
-use renderer::window_renderer::WindowRenderer;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use winit::event::{ElementState, WindowEvent};
-use winit::event_loop::ActiveEventLoop;
+use std::time::{Duration, Instant};
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, MouseButton, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, ControlFlow};
 use winit::window::{Window, WindowAttributes, WindowId};
 
-pub use crate::renderer::window_renderer::WindowRendererAttributes;
+pub use crate::benchmark::{BenchmarkAttributes, ReportFormat};
+pub use crate::debug_server::DebugServerAttributes;
+pub use crate::drag_drop::{
+    spawn_dropped_file, DragPayload, DragSource, DropTarget, DroppedAsset, FileDropTarget,
+};
+pub use crate::frame_pacing::{
+    FramePacing, FramePacingAttributes, FrameSpike, SpikeCallback, FRAME_TIME_BUCKET_COUNT,
+    FRAME_TIME_BUCKET_WIDTH,
+};
+pub use crate::input::{InputCallback, InputEvent, InputState};
+pub use crate::renderer::ambient_occlusion::AmbientOcclusionBakeAttributes;
+pub use crate::renderer::atlas::{AtlasAttributes, AtlasRegion, TextureAtlas};
+pub use crate::renderer::commands::Commands;
+pub use crate::renderer::compressed_texture::CompressedTexture;
+pub use crate::renderer::compute_pass::{ComputeStage, ComputeTargets};
+pub use crate::renderer::debug_draw::DebugDraw;
+#[cfg(feature = "rapier")]
+pub use crate::renderer::debug_draw::RapierDebugDraw;
+pub use crate::renderer::debug_lines::DebugLines;
+pub use crate::renderer::depth_readback::{DepthImage, DepthReadback};
+pub use crate::renderer::display_timing::DisplayTimingClock;
+pub use crate::renderer::frame_capture::CaptureFormat;
+pub use crate::renderer::geometry::{
+    Geometry, MeshHandle, ObjMaterial, SkinVertex, Submesh, Vertex,
+};
+pub use crate::renderer::gltf::GltfMaterial;
+pub use crate::renderer::image_analysis::{
+    ImageAnalysisAttributes, ImageAnalyzer, ImageStatistics, HISTOGRAM_BIN_COUNT,
+};
+pub use crate::renderer::lens_flare::LensFlareAttributes;
+pub use crate::renderer::lighting::{
+    candela_to_lumens, ev100_from_average_luminance, ev100_to_exposure, lumens_to_candela, Lighting,
+};
+pub use crate::renderer::mesh_validation::MeshError;
+pub use crate::renderer::painter::Painter;
+pub use crate::renderer::physical_camera::PhysicalCamera;
+pub use crate::renderer::post_process::{
+    PostChain, PostProcess, ShaderPostProcess, MAX_POST_PROCESS_PUSH_CONSTANTS_SIZE,
+};
+pub use crate::renderer::present_feedback::{PresentTiming, PresentedFrame};
+pub use crate::renderer::probe_grid::{
+    IrradianceProbe, ProbeCaptureAttributes, ProbeGrid, ProbeGridAttributes,
+};
+pub use crate::renderer::render_graph::{
+    GraphAccess, GraphBuffer, GraphImage, GraphImages, RenderGraph,
+};
+pub use crate::renderer::scene::{NodeAttachment, NodeHandle, Scene};
+pub use crate::renderer::shader_reload::ShaderReloadAttributes;
+pub use crate::renderer::skinning::JointAttachment;
+pub use crate::renderer::splash::SplashAttributes;
+pub use crate::renderer::time_of_day::TimeOfDay;
+pub use crate::renderer::tonemap::{AutoExposureAttributes, TonemapAttributes, TonemapOperator};
+pub use crate::renderer::ui_pass::UI_FORMAT;
+pub use crate::renderer::weather::{Precipitation, WeatherAttributes};
+pub use crate::renderer::window_renderer::{
+    FrameStatus, MinimizedPolicy, WindowRenderer, WindowRendererAttributes,
+};
+pub use crate::renderer::{
+    DebugVisualizations, InstanceHit, OutlineStyle, PointLight, ProceduralSkyAttributes, Renderer,
+    ShaderPaths, SpotLight, SunShadowAttributes, Unprojection, ALL_LAYERS, DEFAULT_LAYER,
+    MAX_SHADOWED_POINT_LIGHTS, MAX_SHADOWED_SPOT_LIGHTS,
+};
+pub use crate::rendering_context::{ComputePipelineAttributes, Image, RenderingContext};
+pub use crate::replay::{read_recording, RecordedFrame, RecordingAttributes, ReplayAttributes};
+pub use crate::shader_reflection::{ReflectedBinding, ShaderReflection};
+pub use crate::sparse_image::{SparseImage, SparseImageAttributes};
+pub use crate::time::{Clock, FixedStepClock, FixedTimestep, FrameIndexClock, RealTimeClock, Time};
 pub use anyhow;
 pub use ash::vk;
+#[cfg(feature = "egui")]
+pub use egui;
+pub use nalgebra;
 use renderdoc::RenderDoc;
-use tracing::info;
+use tracing::{error, info};
 pub use winit;
 use winit::keyboard::{Key, NamedKey};
 
+#[derive(Clone, Debug, Default)]
+pub struct EngineAttributes {
+    // Creates the device over every GPU linked with the picked one (SLI/CrossFire style rigs),
+    // falling back to a single device when there is no such group.
+    pub device_group: bool,
+    // Presents from another queue family than the one rendering when the GPU has one that can,
+    // handing swapchain images over between the two.
+    pub dedicated_present_queue: bool,
+    // What windows do while they can't be drawn to, e.g. minimized.
+    pub minimized_policy: MinimizedPolicy,
+}
+
+// Game logic driven by the engine, registered with Engine::set_app.
+pub trait EngineApp {
+    // Called with the fixed timestep, as many times per frame as the frame's scaled delta time
+    // is worth, so the simulation runs at the same rate regardless of the frame rate.
+    fn update(&mut self, engine: &mut Engine, dt: Duration);
+
+    // Called once per frame after the updates, before the windows render. Alpha is how far time
+    // got into the next update, from 0 to 1, to interpolate between the last two updates.
+    fn render(&mut self, _engine: &mut Engine, _alpha: f32) {}
+
+    // Called when a window's frame found it unable to be drawn to, e.g. after minimizing it.
+    fn window_minimized(&mut self, _engine: &mut Engine, _window_id: WindowId) {}
+
+    // Called after the first frame presented to a window that was minimized, e.g. to refresh
+    // what was left stale meanwhile.
+    fn window_drawable(&mut self, _engine: &mut Engine, _window_id: WindowId) {}
+}
+
 pub struct Engine {
     windows: HashMap<WindowId, Arc<Window>>,
     renderers: HashMap<WindowId, WindowRenderer>,
     primary_window_id: WindowId,
     rendering_context: Arc<RenderingContext>,
     renderdoc: Option<RenderDoc<renderdoc::V100>>,
+    benchmark: Option<Benchmark>,
+    recorder: Option<Recorder>,
+    replay: Option<Replay>,
+    debug_server: Option<DebugServer>,
+    time: Time,
+    drag_and_drop: DragAndDrop,
+    input: Input,
+    frame_pacing: FramePacing,
+    app: Option<Box<dyn EngineApp>>,
+    fixed_timestep: FixedTimestep,
+    minimized_policy: MinimizedPolicy,
+    last_redraw_request: Option<Instant>,
+    // Control flow to go back to once MinimizedPolicy::ThrottleUpdates stops throttling.
+    unthrottled_control_flow: Option<ControlFlow>,
+    exit_requested: bool,
 }
 
 impl Engine {
@@ -34,6 +165,7 @@ impl Engine {
         event_loop: &ActiveEventLoop,
         primary_window_attributes: WindowAttributes,
         primary_renderer_attributes: WindowRendererAttributes,
+        attributes: EngineAttributes,
     ) -> Result<Self> {
         let renderdoc = RenderDoc::new().ok();
         if renderdoc.is_some() {
@@ -45,20 +177,32 @@ impl Engine {
 
         let rendering_context = Arc::new(RenderingContext::new(RenderingContextAttributes {
             compatibility_window: primary_window.as_ref(),
-            queue_family_picker: queue_family_picker::single_queue_family,
+            queue_family_picker: if attributes.dedicated_present_queue {
+                queue_family_picker::dedicated_present_queue_family
+            } else {
+                queue_family_picker::single_queue_family
+            },
+            device_group: attributes.device_group,
         })?);
+        if rendering_context.device_count() > 1 {
+            info!(
+                "Created a device group of {} GPUs",
+                rendering_context.device_count()
+            );
+        }
 
         let windows = HashMap::from([(primary_window_id, primary_window)]);
 
         let renderers = windows
             .iter()
             .map(|(id, window)| {
-                let renderer = WindowRenderer::new(
+                let mut renderer = WindowRenderer::new(
                     rendering_context.clone(),
                     window.clone(),
                     primary_renderer_attributes.clone(),
                 )
                 .unwrap();
+                renderer.set_minimized_policy(attributes.minimized_policy);
                 (*id, renderer)
             })
             .collect::<HashMap<_, _>>();
@@ -69,6 +213,20 @@ impl Engine {
             primary_window_id,
             rendering_context,
             renderdoc,
+            benchmark: None,
+            recorder: None,
+            replay: None,
+            debug_server: None,
+            time: Time::default(),
+            drag_and_drop: DragAndDrop::default(),
+            input: Input::default(),
+            frame_pacing: FramePacing::default(),
+            app: None,
+            fixed_timestep: FixedTimestep::default(),
+            minimized_policy: attributes.minimized_policy,
+            last_redraw_request: None,
+            unthrottled_control_flow: None,
+            exit_requested: false,
         })
     }
 
@@ -78,13 +236,16 @@ impl Engine {
         window_id: WindowId,
         event: WindowEvent,
     ) {
+        self.input.handle_event(window_id, &event);
+        #[cfg(feature = "egui")]
+        if let Some(renderer) = self.renderers.get_mut(&window_id) {
+            renderer.egui_event(&event);
+        }
         match event {
             WindowEvent::CloseRequested => {
-                if window_id == self.primary_window_id {
+                self.close_window(window_id);
+                if self.exit_requested {
                     event_loop.exit();
-                } else {
-                    self.windows.remove(&window_id);
-                    self.renderers.remove(&window_id);
                 }
             }
             WindowEvent::Resized(_) => {
@@ -98,10 +259,94 @@ impl Engine {
                 }
             }
             WindowEvent::RedrawRequested => {
+                let mut minimized_changed = false;
                 if let Some(renderer) = self.renderers.get_mut(&window_id) {
-                    renderer.render().unwrap();
+                    let benchmark = self
+                        .benchmark
+                        .as_mut()
+                        .filter(|_| window_id == self.primary_window_id);
+
+                    let is_primary = window_id == self.primary_window_id;
+
+                    if let Some(benchmark) = &benchmark {
+                        benchmark.begin_frame(&mut renderer.renderer);
+                    }
+                    if let Some(replay) = self.replay.as_ref().filter(|_| is_primary) {
+                        replay.begin_frame(&mut renderer.renderer);
+                    }
+
+                    let was_minimized = renderer.is_minimized();
+                    renderer.render(&self.time).unwrap();
+                    minimized_changed = renderer.is_minimized() != was_minimized;
+                    self.input.end_frame(window_id);
+
+                    if let Some(recorder) = self.recorder.as_mut().filter(|_| is_primary) {
+                        recorder
+                            .record_frame(&renderer.renderer, &self.time)
+                            .unwrap();
+                    }
+
+                    if let Some(benchmark) = benchmark {
+                        if benchmark.end_frame(&renderer.renderer) {
+                            match benchmark.write_report() {
+                                Ok(()) => info!(
+                                    "Benchmark report written to {:?}",
+                                    benchmark.attributes.report_path
+                                ),
+                                Err(error) => error!(
+                                    "Failed to write the benchmark report to {:?}: {error}",
+                                    benchmark.attributes.report_path
+                                ),
+                            }
+                            renderer.renderer.set_camera_look_at(None);
+                            let exit_when_done = benchmark.attributes.exit_when_done;
+                            self.benchmark = None;
+                            if exit_when_done {
+                                self.exit();
+                            }
+                        }
+                    }
+
+                    if let Some(replay) = self.replay.as_mut().filter(|_| is_primary) {
+                        if replay.end_frame() {
+                            info!("Replay of {:?} finished", replay.attributes.path);
+                            if let Some(renderer) = self.renderers.get_mut(&window_id) {
+                                renderer.renderer.set_camera_look_at(None);
+                            }
+                            let exit_when_done = replay.attributes.exit_when_done;
+                            self.replay = None;
+                            if exit_when_done {
+                                self.exit();
+                            }
+                        }
+                    }
+                }
+                if minimized_changed {
+                    self.notify_minimized_changed(window_id);
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.drag_and_drop
+                    .cursor_positions
+                    .insert(window_id, position);
+            }
+            WindowEvent::CursorEntered { .. } => {
+                self.drag_and_drop.hovered_window = Some(window_id);
+            }
+            WindowEvent::CursorLeft { .. } => {
+                if self.drag_and_drop.hovered_window == Some(window_id) {
+                    self.drag_and_drop.hovered_window = None;
                 }
             }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => match state {
+                ElementState::Pressed => self.begin_drag(window_id),
+                ElementState::Released => self.end_drag(),
+            },
+            WindowEvent::DroppedFile(path) => self.drop_file(window_id, path),
             WindowEvent::KeyboardInput { event, .. } => match event.logical_key {
                 Key::Named(NamedKey::F1) => {
                     if event.state == ElementState::Pressed {
@@ -110,6 +355,15 @@ impl Engine {
                         }
                     }
                 }
+                // The frame graph is only recorded in debug builds.
+                Key::Named(NamedKey::F2)
+                    if cfg!(debug_assertions) && event.state == ElementState::Pressed =>
+                {
+                    if let Some(renderer) = self.renderers.get(&window_id) {
+                        renderer.export_frame_graph("frame_graph.dot").unwrap();
+                        info!("Frame graph written to frame_graph.dot");
+                    }
+                }
                 _ => {}
             },
             _ => {}
@@ -126,17 +380,360 @@ impl Engine {
         let window_id = window.id();
         self.windows.insert(window_id, window.clone());
 
-        let renderer = WindowRenderer::new(
+        let mut renderer = WindowRenderer::new(
             self.rendering_context.clone(),
             window.clone(),
             renderer_attributes,
         )?;
+        renderer.set_minimized_policy(self.minimized_policy);
         self.renderers.insert(window_id, renderer);
 
         Ok(window_id)
     }
 
-    pub fn request_redraw(&self) {
+    // Closing the primary window exits the engine.
+    pub fn close_window(&mut self, window_id: WindowId) {
+        if window_id == self.primary_window_id {
+            self.exit();
+            return;
+        }
+        self.renderers.remove(&window_id);
+        self.windows.remove(&window_id);
+        self.drag_and_drop.remove_window(window_id);
+        self.input.remove_window(window_id);
+    }
+
+    // Renderers are destroyed right away, the event loop stops on the next about_to_wait.
+    pub fn exit(&mut self) {
+        if let Err(error) = self.stop_recording() {
+            error!("Failed to finish the scene recording: {error}");
+        }
+        self.renderers.clear();
+        self.windows.clear();
+        self.drag_and_drop = DragAndDrop::default();
+        self.input.windows.clear();
+        self.exit_requested = true;
+    }
+
+    pub fn is_exiting(&self) -> bool {
+        self.exit_requested
+    }
+
+    pub fn window_ids(&self) -> impl Iterator<Item = WindowId> + '_ {
+        self.windows.keys().copied()
+    }
+
+    pub fn window(&self, window_id: WindowId) -> Option<&Arc<Window>> {
+        self.windows.get(&window_id)
+    }
+
+    pub fn primary_window_id(&self) -> WindowId {
+        self.primary_window_id
+    }
+
+    pub fn primary_window(&self) -> Option<&Arc<Window>> {
+        self.windows.get(&self.primary_window_id)
+    }
+
+    pub fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if self.exit_requested {
+            event_loop.exit();
+            return;
+        }
+        if let Some(next_redraw) = self.throttled_redraw() {
+            if Instant::now() < next_redraw {
+                self.unthrottled_control_flow
+                    .get_or_insert(event_loop.control_flow());
+                event_loop.set_control_flow(ControlFlow::WaitUntil(next_redraw));
+                return;
+            }
+        } else if let Some(control_flow) = self.unthrottled_control_flow.take() {
+            event_loop.set_control_flow(control_flow);
+        }
+        self.request_redraw();
+    }
+
+    pub fn minimized_policy(&self) -> MinimizedPolicy {
+        self.minimized_policy
+    }
+
+    // Applies to every window, including those created later.
+    pub fn set_minimized_policy(&mut self, minimized_policy: MinimizedPolicy) {
+        self.minimized_policy = minimized_policy;
+        for renderer in self.renderers.values_mut() {
+            renderer.set_minimized_policy(minimized_policy);
+        }
+    }
+
+    // When the next frame may start while MinimizedPolicy::ThrottleUpdates holds frames back,
+    // which it does while no window can be drawn to.
+    fn throttled_redraw(&self) -> Option<Instant> {
+        let MinimizedPolicy::ThrottleUpdates(interval) = self.minimized_policy else {
+            return None;
+        };
+        if self.renderers.is_empty() || !self.renderers.values().all(WindowRenderer::is_minimized) {
+            return None;
+        }
+        Some(self.last_redraw_request? + interval)
+    }
+
+    fn notify_minimized_changed(&mut self, window_id: WindowId) {
+        let Some(minimized) = self
+            .renderers
+            .get(&window_id)
+            .map(WindowRenderer::is_minimized)
+        else {
+            return;
+        };
+        // Taken out while it runs like in request_redraw.
+        if let Some(mut app) = self.app.take() {
+            if minimized {
+                app.window_minimized(self, window_id);
+            } else {
+                app.window_drawable(self, window_id);
+            }
+            self.app.get_or_insert(app);
+        }
+    }
+
+    // Lets objects be dragged out of the window by pressing the left mouse button over them.
+    pub fn set_drag_source(
+        &mut self,
+        window_id: WindowId,
+        source: impl FnMut(&Renderer, &InstanceHit) -> Option<String> + 'static,
+    ) {
+        self.drag_and_drop
+            .sources
+            .insert(window_id, Box::new(source));
+    }
+
+    pub fn set_drop_target(
+        &mut self,
+        window_id: WindowId,
+        target: impl FnMut(&mut Renderer, &DragPayload, Option<InstanceHit>, PhysicalPosition<f64>)
+            + 'static,
+    ) {
+        self.drag_and_drop
+            .targets
+            .insert(window_id, Box::new(target));
+    }
+
+    // Spawns files dropped onto the window from the platform's file manager in front of its
+    // camera, see spawn_dropped_file, then tells the target what they were loaded as. Files
+    // dropped together arrive one event, and so one call, each.
+    pub fn set_file_drop_target(
+        &mut self,
+        window_id: WindowId,
+        target: impl FnMut(&mut Renderer, &Path, Result<DroppedAsset>) + 'static,
+    ) {
+        self.drag_and_drop
+            .file_targets
+            .insert(window_id, Box::new(target));
+    }
+
+    pub fn clear_file_drop_target(&mut self, window_id: WindowId) {
+        self.drag_and_drop.file_targets.remove(&window_id);
+    }
+
+    fn drop_file(&mut self, window_id: WindowId, path: PathBuf) {
+        if let (Some(target), Some(renderer)) = (
+            self.drag_and_drop.file_targets.get_mut(&window_id),
+            self.renderers.get_mut(&window_id),
+        ) {
+            let asset = spawn_dropped_file(&mut renderer.renderer, &path);
+            target(&mut renderer.renderer, &path, asset);
+        }
+    }
+
+    // The object being dragged, e.g. to draw a drop preview while hovering another window.
+    pub fn dragged(&self) -> Option<&DragPayload> {
+        self.drag_and_drop.payload.as_ref()
+    }
+
+    fn begin_drag(&mut self, window_id: WindowId) {
+        let (Some(source), Some(renderer), Some(&cursor)) = (
+            self.drag_and_drop.sources.get_mut(&window_id),
+            self.renderers.get(&window_id),
+            self.drag_and_drop.cursor_positions.get(&window_id),
+        ) else {
+            return;
+        };
+        let Some(hit) = renderer.hit_test(cursor) else {
+            return;
+        };
+        self.drag_and_drop.payload = source(&renderer.renderer, &hit).map(|metadata| DragPayload {
+            source_window: window_id,
+            instance: hit.instance,
+            metadata,
+        });
+    }
+
+    fn end_drag(&mut self) {
+        let Some(payload) = self.drag_and_drop.payload.take() else {
+            return;
+        };
+        let Some((window_id, position)) = self
+            .drag_and_drop
+            .drop_location(&self.windows, payload.source_window)
+        else {
+            return;
+        };
+        if let (Some(target), Some(renderer)) = (
+            self.drag_and_drop.targets.get_mut(&window_id),
+            self.renderers.get_mut(&window_id),
+        ) {
+            let hit = renderer.hit_test(position);
+            target(&mut renderer.renderer, &payload, hit, position);
+        }
+    }
+
+    // None until the window received its first event.
+    pub fn input(&self, window_id: WindowId) -> Option<&InputState> {
+        self.input.windows.get(&window_id)
+    }
+
+    // Called with every keyboard and mouse event of every window.
+    pub fn set_input_callback(&mut self, callback: impl FnMut(WindowId, &InputEvent) + 'static) {
+        self.input.callback = Some(Box::new(callback));
+    }
+
+    pub fn clear_input_callback(&mut self) {
+        self.input.callback = None;
+    }
+
+    pub fn window_renderer(&self, window_id: WindowId) -> Option<&WindowRenderer> {
+        self.renderers.get(&window_id)
+    }
+
+    pub fn window_renderer_mut(&mut self, window_id: WindowId) -> Option<&mut WindowRenderer> {
+        self.renderers.get_mut(&window_id)
+    }
+
+    // Flies the primary window's camera along the path and writes a frame timing report at the end.
+    pub fn start_benchmark(&mut self, attributes: BenchmarkAttributes) -> Result<()> {
+        let device_name = self
+            .rendering_context
+            .physical_device
+            .properties
+            .device_name_as_c_str()?
+            .to_string_lossy()
+            .into_owned();
+        self.benchmark = Some(Benchmark::new(attributes, device_name)?);
+        Ok(())
+    }
+
+    pub fn is_benchmarking(&self) -> bool {
+        self.benchmark.is_some()
+    }
+
+    // Writes the primary window's camera and instance transforms of every frame to the file
+    // until stop_recording, replacing a recording in progress.
+    pub fn start_recording(&mut self, attributes: RecordingAttributes) -> Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::new(&attributes)?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<()> {
+        if let Some(recorder) = self.recorder.take() {
+            info!("Recorded {} frames", recorder.frame_count());
+            recorder.finish()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // Drives the primary window's camera and instance transforms from a recording, one
+    // recorded frame per rendered frame regardless of how long each takes.
+    pub fn start_replay(&mut self, attributes: ReplayAttributes) -> Result<()> {
+        self.replay = Some(Replay::new(attributes)?);
+        Ok(())
+    }
+
+    pub fn stop_replay(&mut self) {
+        if self.replay.take().is_some() {
+            if let Some(renderer) = self.renderers.get_mut(&self.primary_window_id) {
+                renderer.renderer.set_camera_look_at(None);
+            }
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    // Accepts render setting commands from local tools, see DebugServer for the protocol.
+    pub fn start_debug_server(&mut self, attributes: DebugServerAttributes) -> Result<()> {
+        self.debug_server = Some(DebugServer::new(&attributes)?);
+        Ok(())
+    }
+
+    pub fn stop_debug_server(&mut self) {
+        self.debug_server = None;
+    }
+
+    pub fn time(&self) -> &Time {
+        &self.time
+    }
+
+    pub fn time_mut(&mut self) -> &mut Time {
+        &mut self.time
+    }
+
+    // CPU frame times of the engine's frames, measured between redraw requests.
+    pub fn frame_pacing(&self) -> &FramePacing {
+        &self.frame_pacing
+    }
+
+    pub fn frame_pacing_mut(&mut self) -> &mut FramePacing {
+        &mut self.frame_pacing
+    }
+
+    pub fn set_app(&mut self, app: impl EngineApp + 'static) {
+        self.app = Some(Box::new(app));
+    }
+
+    pub fn clear_app(&mut self) {
+        self.app = None;
+    }
+
+    pub fn fixed_timestep(&self) -> &FixedTimestep {
+        &self.fixed_timestep
+    }
+
+    pub fn fixed_timestep_mut(&mut self) -> &mut FixedTimestep {
+        &mut self.fixed_timestep
+    }
+
+    // Number of GPUs in the device group, one unless EngineAttributes::device_group found a group.
+    pub fn device_count(&self) -> u32 {
+        self.rendering_context.device_count()
+    }
+
+    // Advances time once for all windows, runs the app's updates, then asks each of them to
+    // render.
+    pub fn request_redraw(&mut self) {
+        self.last_redraw_request = Some(Instant::now());
+        self.time.tick();
+        self.frame_pacing.record_frame();
+        if let Some(debug_server) = &mut self.debug_server {
+            debug_server.poll(self.renderers.values_mut());
+        }
+
+        // Taken out while it runs so it can use the engine, an app set meanwhile replaces it.
+        if let Some(mut app) = self.app.take() {
+            let steps = self.fixed_timestep.advance(self.time.delta());
+            let step = self.fixed_timestep.step;
+            for _ in 0..steps {
+                app.update(self, step);
+            }
+            app.render(self, self.fixed_timestep.alpha());
+            self.app.get_or_insert(app);
+        }
+
         for window in self.windows.values() {
             window.request_redraw();
         }
@@ -0,0 +1,68 @@
+use crate::rendering_context::RenderingContext;
+use anyhow::Result;
+use ash::vk;
+use std::sync::Arc;
+
+pub struct QueryPool {
+    pub handle: vk::QueryPool,
+    pub query_type: vk::QueryType,
+    pub query_count: u32,
+    context: Arc<RenderingContext>,
+}
+
+impl QueryPool {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        query_type: vk::QueryType,
+        query_count: u32,
+    ) -> Result<Self> {
+        let handle = unsafe {
+            context.device.create_query_pool(
+                &vk::QueryPoolCreateInfo::default()
+                    .query_type(query_type)
+                    .query_count(query_count),
+                None,
+            )
+        }?;
+
+        Ok(Self {
+            handle,
+            query_type,
+            query_count,
+            context,
+        })
+    }
+
+    pub fn new_occlusion(context: Arc<RenderingContext>, query_count: u32) -> Result<Self> {
+        Self::new(context, vk::QueryType::OCCLUSION, query_count)
+    }
+
+    pub fn new_timestamp(context: Arc<RenderingContext>, query_count: u32) -> Result<Self> {
+        Self::new(context, vk::QueryType::TIMESTAMP, query_count)
+    }
+
+    // Returns None when some of the queries have not completed yet.
+    pub fn results(&self) -> Result<Option<Vec<u64>>> {
+        let mut results = vec![0u64; self.query_count as usize];
+        match unsafe {
+            self.context.device.get_query_pool_results(
+                self.handle,
+                0,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        } {
+            Ok(()) => Ok(Some(results)),
+            Err(vk::Result::NOT_READY) => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+impl Drop for QueryPool {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device.destroy_query_pool(self.handle, None);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use crate::renderer::geometry::Geometry;
+use nalgebra as na;
+use std::f32::consts::TAU;
+
+// Triangles per BVH leaf.
+const LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct AmbientOcclusionBakeAttributes {
+    // Rays cast per vertex, more trade bake time for less noise.
+    pub ray_count: u32,
+    // Farther hits don't occlude, None uses a quarter of the bounding box diagonal.
+    pub max_distance: Option<f32>,
+}
+
+impl Default for AmbientOcclusionBakeAttributes {
+    fn default() -> Self {
+        Self {
+            ray_count: 64,
+            max_distance: None,
+        }
+    }
+}
+
+impl Geometry {
+    // Offline bake of every vertex's ambient occlusion and bent normal, only the geometry itself
+    // occludes, e.g. a static mesh's crevices. Run it once the normals are final, the results are
+    // uploaded with the vertices and darken the indirect lighting of meshes without a lightmap.
+    pub fn bake_ambient_occlusion(&mut self, attributes: AmbientOcclusionBakeAttributes) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        let (min, max) = self.vertices.iter().fold(
+            (
+                na::Vector3::repeat(f32::INFINITY),
+                na::Vector3::repeat(f32::NEG_INFINITY),
+            ),
+            |(min, max), vertex| (min.inf(&vertex.position), max.sup(&vertex.position)),
+        );
+        let diagonal = (max - min).norm();
+        let max_distance = attributes.max_distance.unwrap_or(diagonal * 0.25);
+        // Keeps rays from hitting the triangles they start on.
+        let bias = diagonal * 1e-4;
+
+        let bvh = Bvh::new(
+            self.indices
+                .chunks_exact(3)
+                .map(|triangle| {
+                    [0, 1, 2].map(|corner| self.vertices[triangle[corner] as usize].position)
+                })
+                .collect(),
+        );
+        let ray_count = attributes.ray_count.max(1);
+        for (index, vertex) in self.vertices.iter_mut().enumerate() {
+            let Some(normal) = vertex.normal.try_normalize(f32::EPSILON) else {
+                continue;
+            };
+            let (tangent, bitangent) = tangent_frame(&normal);
+            let origin = vertex.position + normal * bias;
+            // Rotates the ray pattern per vertex, so neighbors don't share the same banding.
+            let rotation = (index as f32 * 0.618_034).fract() * TAU;
+
+            let mut unoccluded = 0;
+            let mut bent_normal = na::Vector3::zeros();
+            for ray in 0..ray_count {
+                // Cosine weighted Hammersley points, each ray counts the same.
+                let u = (ray as f32 + 0.5) / ray_count as f32;
+                let v = ray.reverse_bits() as f32 / (u32::MAX as f32 + 1.0);
+                let radius = u.sqrt();
+                let angle = v * TAU + rotation;
+                let direction = tangent * (radius * angle.cos())
+                    + bitangent * (radius * angle.sin())
+                    + normal * (1.0 - u).max(0.0).sqrt();
+                if !bvh.is_occluded(&origin, &direction, max_distance) {
+                    unoccluded += 1;
+                    bent_normal += direction;
+                }
+            }
+            vertex.ambient_occlusion = unoccluded as f32 / ray_count as f32;
+            vertex.bent_normal = bent_normal.try_normalize(f32::EPSILON).unwrap_or(normal);
+        }
+    }
+}
+
+fn tangent_frame(normal: &na::Vector3<f32>) -> (na::Vector3<f32>, na::Vector3<f32>) {
+    // Any direction that isn't parallel to the normal spans the tangent plane with it.
+    let helper = if normal.x.abs() < 0.9 {
+        na::Vector3::x()
+    } else {
+        na::Vector3::y()
+    };
+    let tangent = normal.cross(&helper).normalize();
+    (tangent, normal.cross(&tangent))
+}
+
+struct BvhNode {
+    min: na::Vector3<f32>,
+    max: na::Vector3<f32>,
+    // Leaves hold triangles start..start + count, inner nodes have their first child right
+    // after them and the second one at start.
+    start: usize,
+    count: usize,
+}
+
+// Bounding volume hierarchy over the triangles, only answering whether a ray hits anything.
+struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<[na::Vector3<f32>; 3]>,
+}
+
+impl Bvh {
+    fn new(mut triangles: Vec<[na::Vector3<f32>; 3]>) -> Self {
+        let mut nodes = Vec::new();
+        let count = triangles.len();
+        if count > 0 {
+            build(&mut nodes, &mut triangles, 0, count);
+        }
+        Self { nodes, triangles }
+    }
+
+    fn is_occluded(
+        &self,
+        origin: &na::Vector3<f32>,
+        direction: &na::Vector3<f32>,
+        max_distance: f32,
+    ) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let inverse_direction = direction.map(|value| 1.0 / value);
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node_data = &self.nodes[node];
+            if !ray_hits_box(
+                origin,
+                &inverse_direction,
+                &node_data.min,
+                &node_data.max,
+                max_distance,
+            ) {
+                continue;
+            }
+            if node_data.count == 0 {
+                stack.extend([node + 1, node_data.start]);
+                continue;
+            }
+            let triangles = &self.triangles[node_data.start..node_data.start + node_data.count];
+            if triangles.iter().any(|triangle| {
+                ray_triangle_distance(origin, direction, triangle)
+                    .is_some_and(|distance| distance <= max_distance)
+            }) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+// Splits at the median centroid along the longest axis, returns the node's index.
+fn build(
+    nodes: &mut Vec<BvhNode>,
+    triangles: &mut [[na::Vector3<f32>; 3]],
+    start: usize,
+    count: usize,
+) -> usize {
+    let part = &mut triangles[start..start + count];
+    let (min, max) = part.iter().flatten().fold(
+        (
+            na::Vector3::repeat(f32::INFINITY),
+            na::Vector3::repeat(f32::NEG_INFINITY),
+        ),
+        |(min, max), corner| (min.inf(corner), max.sup(corner)),
+    );
+    let node = nodes.len();
+    nodes.push(BvhNode {
+        min,
+        max,
+        start,
+        count,
+    });
+    if count <= LEAF_SIZE {
+        return node;
+    }
+
+    let axis = (max - min).imax();
+    let centroid = |triangle: &[na::Vector3<f32>; 3]| {
+        triangle[0][axis] + triangle[1][axis] + triangle[2][axis]
+    };
+    let half = count / 2;
+    part.select_nth_unstable_by(half, |a, b| centroid(a).total_cmp(&centroid(b)));
+    build(nodes, triangles, start, half);
+    let second = build(nodes, triangles, start + half, count - half);
+    nodes[node].start = second;
+    nodes[node].count = 0;
+    node
+}
+
+// Slab test against the box, limited to the ray's length.
+fn ray_hits_box(
+    origin: &na::Vector3<f32>,
+    inverse_direction: &na::Vector3<f32>,
+    min: &na::Vector3<f32>,
+    max: &na::Vector3<f32>,
+    max_distance: f32,
+) -> bool {
+    let near = (min - origin).component_mul(inverse_direction);
+    let far = (max - origin).component_mul(inverse_direction);
+    let entry = near.inf(&far).max().max(0.0);
+    let exit = near.sup(&far).min().min(max_distance);
+    entry <= exit
+}
+
+// Möller-Trumbore, hits from either side count.
+fn ray_triangle_distance(
+    origin: &na::Vector3<f32>,
+    direction: &na::Vector3<f32>,
+    [a, b, c]: &[na::Vector3<f32>; 3],
+) -> Option<f32> {
+    let ab = b - a;
+    let ac = c - a;
+    let p = direction.cross(&ac);
+    let determinant = ab.dot(&p);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let to_origin = origin - a;
+    let u = to_origin.dot(&p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = to_origin.cross(&ab);
+    let v = direction.dot(&q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = ac.dot(&q) * inverse_determinant;
+    (distance > 0.0).then_some(distance)
+}
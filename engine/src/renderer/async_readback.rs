@@ -0,0 +1,331 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::renderer::commands::Commands;
+use crate::renderer::depth_readback::{decode_depths, depth_texel_size, DepthImage};
+use crate::renderer::Unprojection;
+use crate::rendering_context::{Image, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use nalgebra as na;
+use std::sync::Arc;
+
+// Called on the render thread once the bytes are on the CPU, or with the error that kept them
+// from getting there.
+pub type ReadbackCallback<T> = Box<dyn FnOnce(Result<T>)>;
+
+// Colors are stored as sRGB like the swapchain, see FrameCapture.
+const COLOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+enum ReadbackRequest {
+    // The render target scaled to the output resolution.
+    Color(ReadbackCallback<::image::RgbaImage>),
+    // The whole resolved depth buffer without a region.
+    Depth {
+        region: Option<vk::Rect2D>,
+        callback: ReadbackCallback<DepthImage>,
+    },
+    // World position of the surface under a point in normalized device coordinates.
+    Position {
+        ndc: na::Point2<f64>,
+        callback: ReadbackCallback<Option<na::Point3<f64>>>,
+    },
+}
+
+enum Completion {
+    Color {
+        extent: vk::Extent2D,
+        callback: ReadbackCallback<::image::RgbaImage>,
+    },
+    Depth {
+        extent: vk::Extent2D,
+        format: vk::Format,
+        callback: ReadbackCallback<DepthImage>,
+    },
+    Position {
+        ndc: na::Point2<f64>,
+        format: vk::Format,
+        // Of the frame the depth was read from, the camera may have moved since.
+        unprojection: Option<Unprojection>,
+        callback: ReadbackCallback<Option<na::Point3<f64>>>,
+    },
+}
+
+impl Completion {
+    fn complete(self, bytes: Result<&[u8]>) {
+        match self {
+            Completion::Color { extent, callback } => callback(bytes.and_then(|bytes| {
+                ::image::RgbaImage::from_raw(extent.width, extent.height, bytes.to_vec())
+                    .ok_or_else(|| anyhow::anyhow!("Color readback is smaller than its extent"))
+            })),
+            Completion::Depth {
+                extent,
+                format,
+                callback,
+            } => callback(bytes.map(|bytes| DepthImage {
+                extent,
+                depths: decode_depths(format, bytes),
+            })),
+            Completion::Position {
+                ndc,
+                format,
+                unprojection,
+                callback,
+            } => callback(bytes.map(|bytes| {
+                let depth = decode_depths(format, bytes)[0];
+                unprojection?.unproject(ndc, depth as f64)
+            })),
+        }
+    }
+
+    fn fail(self, error: anyhow::Error) {
+        match self {
+            Completion::Color { callback, .. } => callback(Err(error)),
+            Completion::Depth { callback, .. } => callback(Err(error)),
+            Completion::Position { callback, .. } => callback(Err(error)),
+        }
+    }
+}
+
+struct PendingReadback {
+    buffer: Buffer,
+    // Intermediate target colors are blitted into, for the conversion to COLOR_FORMAT.
+    image: Option<Image>,
+    completion: Completion,
+}
+
+// Non-blocking counterpart of DepthReadback and analyze_last_frame: requests are copied out of
+// the next rendered frame into their own buffers, and delivered once that frame's fence has
+// signaled instead of stalling the frame on a wait for the device. Buffers are allocated per
+// request and freed after delivery, readbacks are meant to be occasional.
+pub struct AsyncReadback {
+    context: Arc<RenderingContext>,
+    requests: Vec<ReadbackRequest>,
+    // Per frame in flight, recorded into that frame's command buffer.
+    pending: Vec<Vec<PendingReadback>>,
+}
+
+impl AsyncReadback {
+    pub fn new(context: Arc<RenderingContext>, in_flight_frames_count: usize) -> Result<Self> {
+        Ok(Self {
+            context,
+            requests: Vec::new(),
+            pending: (0..in_flight_frames_count).map(|_| Vec::new()).collect(),
+        })
+    }
+
+    pub fn request_color(&mut self, callback: ReadbackCallback<::image::RgbaImage>) {
+        self.requests.push(ReadbackRequest::Color(callback));
+    }
+
+    pub fn request_depth(
+        &mut self,
+        region: Option<vk::Rect2D>,
+        callback: ReadbackCallback<DepthImage>,
+    ) {
+        self.requests
+            .push(ReadbackRequest::Depth { region, callback });
+    }
+
+    pub fn request_position(
+        &mut self,
+        ndc: na::Point2<f64>,
+        callback: ReadbackCallback<Option<na::Point3<f64>>>,
+    ) {
+        self.requests
+            .push(ReadbackRequest::Position { ndc, callback });
+    }
+
+    pub fn has_requests(&self) -> bool {
+        !self.requests.is_empty()
+    }
+
+    pub fn is_pending(&self, frame_index: usize) -> bool {
+        !self.pending[frame_index].is_empty()
+    }
+
+    fn create_buffer(&self, allocator: &mut Allocator, size: vk::DeviceSize) -> Result<Buffer> {
+        Buffer::new(
+            allocator,
+            BufferAttributes {
+                name: "async_readback_buffer".into(),
+                context: self.context.clone(),
+                size,
+                usage: vk::BufferUsageFlags::TRANSFER_DST,
+                location: MemoryLocation::GpuToCpu,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                allocation_priority: 0.5,
+            },
+        )
+    }
+
+    // Records the copies of every request into the frame's command buffer, after the frame
+    // has been rendered. A request that can't be recorded is completed with its error.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &mut self,
+        allocator: &mut Allocator,
+        commands: &Commands,
+        frame_index: usize,
+        render_target: &mut Image,
+        depth_buffer: &mut Image,
+        output_extent: vk::Extent2D,
+        unprojection: Option<Unprojection>,
+    ) {
+        for request in std::mem::take(&mut self.requests) {
+            let (completion, recorded) = match request {
+                ReadbackRequest::Color(callback) => {
+                    let recorded =
+                        self.record_color(allocator, commands, render_target, output_extent);
+                    (
+                        Completion::Color {
+                            extent: output_extent,
+                            callback,
+                        },
+                        recorded,
+                    )
+                }
+                ReadbackRequest::Depth { region, callback } => {
+                    let extent = depth_buffer.attributes.extent;
+                    let region = region.unwrap_or(vk::Rect2D::default().extent(vk::Extent2D {
+                        width: extent.width,
+                        height: extent.height,
+                    }));
+                    let recorded = self.record_depth(allocator, commands, depth_buffer, region);
+                    (
+                        Completion::Depth {
+                            extent: region.extent,
+                            format: depth_buffer.attributes.format,
+                            callback,
+                        },
+                        recorded,
+                    )
+                }
+                ReadbackRequest::Position { ndc, callback } => {
+                    let extent = depth_buffer.attributes.extent;
+                    let x = ((ndc.x * 0.5 + 0.5) * extent.width as f64) as u32;
+                    let y = ((ndc.y * 0.5 + 0.5) * extent.height as f64) as u32;
+                    let region = vk::Rect2D {
+                        offset: vk::Offset2D {
+                            x: x.min(extent.width - 1) as i32,
+                            y: y.min(extent.height - 1) as i32,
+                        },
+                        extent: vk::Extent2D {
+                            width: 1,
+                            height: 1,
+                        },
+                    };
+                    let recorded = self.record_depth(allocator, commands, depth_buffer, region);
+                    (
+                        Completion::Position {
+                            ndc,
+                            format: depth_buffer.attributes.format,
+                            unprojection,
+                            callback,
+                        },
+                        recorded,
+                    )
+                }
+            };
+
+            match recorded {
+                Ok((buffer, image)) => {
+                    commands.buffer_memory_barrier(
+                        &buffer,
+                        (
+                            vk::PipelineStageFlags2::TRANSFER,
+                            vk::AccessFlags2::TRANSFER_WRITE,
+                        ),
+                        (vk::PipelineStageFlags2::HOST, vk::AccessFlags2::HOST_READ),
+                    );
+                    self.pending[frame_index].push(PendingReadback {
+                        buffer,
+                        image,
+                        completion,
+                    });
+                }
+                Err(error) => completion.fail(error),
+            }
+        }
+    }
+
+    fn record_color(
+        &self,
+        allocator: &mut Allocator,
+        commands: &Commands,
+        render_target: &mut Image,
+        extent: vk::Extent2D,
+    ) -> Result<(Buffer, Option<Image>)> {
+        let mut image = Image::new_readback_target(
+            self.context.clone(),
+            allocator,
+            "async_readback_image",
+            extent,
+            COLOR_FORMAT,
+        )?;
+        let buffer = match self.create_buffer(
+            allocator,
+            (extent.width * extent.height * 4) as vk::DeviceSize,
+        ) {
+            Ok(buffer) => buffer,
+            Err(error) => {
+                image.destroy(allocator)?;
+                return Err(error);
+            }
+        };
+        commands
+            .blit_full_image(render_target, &mut image, vk::Filter::LINEAR)
+            .copy_image_to_buffer(&mut image, &buffer, 0);
+        Ok((buffer, Some(image)))
+    }
+
+    fn record_depth(
+        &self,
+        allocator: &mut Allocator,
+        commands: &Commands,
+        depth_buffer: &mut Image,
+        region: vk::Rect2D,
+    ) -> Result<(Buffer, Option<Image>)> {
+        let size = region.extent.width as vk::DeviceSize
+            * region.extent.height as vk::DeviceSize
+            * depth_texel_size(depth_buffer.attributes.format)?;
+        let buffer = self.create_buffer(allocator, size)?;
+        commands.copy_image_region_to_buffer(depth_buffer, region, &buffer, 0);
+        Ok((buffer, None))
+    }
+
+    // Must be called once the frame's fence has signaled, delivers its readbacks in the order
+    // they were requested.
+    pub fn collect(&mut self, allocator: &mut Allocator, frame_index: usize) -> Result<()> {
+        for PendingReadback {
+            mut buffer,
+            image,
+            completion,
+        } in std::mem::take(&mut self.pending[frame_index])
+        {
+            // The mapped memory may be larger than the buffer.
+            let size = buffer.attributes.size as usize;
+            completion.complete(buffer.read::<u8>().map(|bytes| &bytes[..size]));
+            if let Some(mut image) = image {
+                image.destroy(allocator)?;
+            }
+            buffer.destroy(allocator)?;
+        }
+        Ok(())
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for frame_index in 0..self.pending.len() {
+            self.collect(allocator, frame_index)?;
+        }
+        for request in self.requests.drain(..) {
+            let error = anyhow::anyhow!("The renderer was dropped before the readback");
+            match request {
+                ReadbackRequest::Color(callback) => callback(Err(error)),
+                ReadbackRequest::Depth { callback, .. } => callback(Err(error)),
+                ReadbackRequest::Position { callback, .. } => callback(Err(error)),
+            }
+        }
+        Ok(())
+    }
+}
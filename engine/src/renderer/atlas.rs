@@ -0,0 +1,156 @@
+use anyhow::Result;
+use image::RgbaImage;
+use nalgebra as na;
+
+#[derive(Clone, Copy, Debug)]
+pub struct AtlasAttributes {
+    // Width and height of every page in pixels.
+    pub page_size: u32,
+    // Border around each image filled with its edge pixels, so filtering doesn't bleed
+    // neighbouring images in.
+    pub padding: u32,
+}
+
+impl Default for AtlasAttributes {
+    fn default() -> Self {
+        Self {
+            page_size: 2048,
+            padding: 2,
+        }
+    }
+}
+
+// Where a packed image ended up, UVs span exactly the image without its padding.
+#[derive(Clone, Copy, Debug)]
+pub struct AtlasRegion {
+    pub page: usize,
+    pub uv_min: na::Vector2<f32>,
+    pub uv_max: na::Vector2<f32>,
+}
+
+struct Shelf {
+    y: u32,
+    height: u32,
+    cursor: u32,
+}
+
+#[derive(Default)]
+struct PageLayout {
+    shelves: Vec<Shelf>,
+    height: u32,
+}
+
+impl PageLayout {
+    // First fit shelf packing; returns the top left corner of the allocated rectangle.
+    fn allocate(&mut self, width: u32, height: u32, page_size: u32) -> Option<(u32, u32)> {
+        for shelf in &mut self.shelves {
+            if height <= shelf.height && shelf.cursor + width <= page_size {
+                let x = shelf.cursor;
+                shelf.cursor += width;
+                return Some((x, shelf.y));
+            }
+        }
+
+        if self.height + height > page_size {
+            return None;
+        }
+        let y = self.height;
+        self.shelves.push(Shelf {
+            y,
+            height,
+            cursor: width,
+        });
+        self.height += height;
+        Some((0, y))
+    }
+}
+
+// Many small images, e.g. sprites, icons or decals, merged into a few pages so they share
+// a handful of bindless texture slots.
+pub struct TextureAtlas {
+    pub attributes: AtlasAttributes,
+    pub pages: Vec<RgbaImage>,
+    // In the order the images were passed in.
+    pub regions: Vec<AtlasRegion>,
+}
+
+impl TextureAtlas {
+    pub fn pack(images: &[&RgbaImage], attributes: AtlasAttributes) -> Result<Self> {
+        let AtlasAttributes { page_size, padding } = attributes;
+
+        // Packing the tallest images first keeps the shelves tight.
+        let mut order = (0..images.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&index| std::cmp::Reverse(images[index].height()));
+
+        let mut layouts: Vec<PageLayout> = Vec::new();
+        let mut pages: Vec<RgbaImage> = Vec::new();
+        let mut regions = vec![None; images.len()];
+
+        for index in order {
+            let image = images[index];
+            if image.width() == 0 || image.height() == 0 {
+                return Err(anyhow::anyhow!("Image {} is empty", index));
+            }
+            let width = image.width() + padding * 2;
+            let height = image.height() + padding * 2;
+            if width > page_size || height > page_size {
+                return Err(anyhow::anyhow!(
+                    "Image {} of {}x{} doesn't fit into an atlas page of {page_size}x{page_size}",
+                    index,
+                    image.width(),
+                    image.height()
+                ));
+            }
+
+            let (page, (x, y)) = match layouts.iter_mut().enumerate().find_map(|(page, layout)| {
+                layout
+                    .allocate(width, height, page_size)
+                    .map(|position| (page, position))
+            }) {
+                Some(allocation) => allocation,
+                None => {
+                    let mut layout = PageLayout::default();
+                    let position = layout.allocate(width, height, page_size).unwrap();
+                    layouts.push(layout);
+                    pages.push(RgbaImage::new(page_size, page_size));
+                    (pages.len() - 1, position)
+                }
+            };
+
+            blit_with_padding(&mut pages[page], image, x, y, padding);
+
+            let size = page_size as f32;
+            let origin = na::Vector2::new((x + padding) as f32, (y + padding) as f32);
+            regions[index] = Some(AtlasRegion {
+                page,
+                uv_min: origin / size,
+                uv_max: (origin + na::Vector2::new(image.width() as f32, image.height() as f32))
+                    / size,
+            });
+        }
+
+        Ok(Self {
+            attributes,
+            pages,
+            regions: regions.into_iter().map(Option::unwrap).collect(),
+        })
+    }
+}
+
+// Copies the image to (x + padding, y + padding), extending its edge pixels into the padding.
+fn blit_with_padding(page: &mut RgbaImage, image: &RgbaImage, x: u32, y: u32, padding: u32) {
+    let padding = padding as i64;
+    for row in -padding..image.height() as i64 + padding {
+        for column in -padding..image.width() as i64 + padding {
+            let source = image.get_pixel(
+                column.clamp(0, image.width() as i64 - 1) as u32,
+                row.clamp(0, image.height() as i64 - 1) as u32,
+            );
+            page.put_pixel(
+                (x as i64 + padding + column) as u32,
+                (y as i64 + padding + row) as u32,
+                *source,
+            );
+        }
+    }
+}
@@ -1,9 +1,12 @@
 use crate::buffer::Buffer;
+use crate::query_pool::QueryPool;
+use crate::renderer::resource_tracker::ResourceTracker;
 use crate::renderer::Frame;
 use crate::rendering_context::{Image, ImageLayoutState, RenderingContext};
 use anyhow::Result;
 use ash::vk;
 use ash::vk::DeviceSize;
+use std::cell::{Cell, RefCell};
 use std::ops::Range;
 use std::sync::Arc;
 use tracing::trace;
@@ -11,15 +14,15 @@ use tracing::trace;
 pub struct Commands {
     context: Arc<RenderingContext>,
     command_buffer: vk::CommandBuffer,
+    submit_device_mask: Cell<u32>,
+    // Only used in debug builds.
+    tracker: RefCell<ResourceTracker>,
 }
 
 impl Commands {
+    // The command buffer must be in the initial state, i.e. freshly allocated or its pool reset.
     pub fn new(context: Arc<RenderingContext>, command_buffer: vk::CommandBuffer) -> Result<Self> {
         unsafe {
-            context
-                .device
-                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
-
             context.device.begin_command_buffer(
                 command_buffer,
                 &vk::CommandBufferBeginInfo::default()
@@ -30,10 +33,61 @@ impl Commands {
         Ok(Self {
             context,
             command_buffer,
+            submit_device_mask: Cell::new(0),
+            tracker: Default::default(),
         })
     }
 
+    fn track(&self, record: impl FnOnce(&mut ResourceTracker)) {
+        if cfg!(debug_assertions) {
+            record(&mut self.tracker.borrow_mut());
+        }
+    }
+
+    // Asserts in debug builds that the image is in a layout shaders can sample it in, call before
+    // drawing or dispatching with descriptors referring to it.
+    pub fn expect_sampled(&self, image: &Image, operation: &str) -> &Self {
+        if cfg!(debug_assertions) {
+            ResourceTracker::sample_image(image, operation);
+        }
+        self
+    }
+
+    pub fn bind_vertex_buffers(&self, first_binding: u32, buffers: &[&Buffer]) -> &Self {
+        self.track(|tracker| {
+            for buffer in buffers {
+                tracker.read_buffer(
+                    buffer,
+                    vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+                    "bind_vertex_buffers",
+                );
+            }
+        });
+        let handles = buffers
+            .iter()
+            .map(|buffer| buffer.handle)
+            .collect::<Vec<_>>();
+        let offsets = vec![0; buffers.len()];
+        unsafe {
+            self.context.device.cmd_bind_vertex_buffers(
+                self.command_buffer,
+                first_binding,
+                &handles,
+                &offsets,
+            );
+        }
+
+        self
+    }
+
     pub fn bind_index_buffer(&self, buffer: &Buffer) -> &Self {
+        self.track(|tracker| {
+            tracker.read_buffer(
+                buffer,
+                vk::PipelineStageFlags2::INDEX_INPUT,
+                "bind_index_buffer",
+            )
+        });
         unsafe {
             self.context.device.cmd_bind_index_buffer(
                 self.command_buffer,
@@ -52,6 +106,10 @@ impl Commands {
         dst_buffer: &Buffer,
         offset: DeviceSize,
     ) -> &Self {
+        self.track(|tracker| {
+            tracker.read_buffer(src_buffer, vk::PipelineStageFlags2::COPY, "copy_buffer");
+            tracker.write_buffer(dst_buffer, vk::PipelineStageFlags2::COPY, "copy_buffer");
+        });
         unsafe {
             self.context.device.cmd_copy_buffer(
                 self.command_buffer,
@@ -66,12 +124,55 @@ impl Commands {
         self
     }
 
+    // Copies size bytes into part of the destination, e.g. updated vertices of a mesh.
+    pub fn copy_buffer_region(
+        &self,
+        src_buffer: &Buffer,
+        dst_buffer: &Buffer,
+        src_offset: DeviceSize,
+        dst_offset: DeviceSize,
+        size: DeviceSize,
+    ) -> &Self {
+        self.track(|tracker| {
+            tracker.read_buffer(
+                src_buffer,
+                vk::PipelineStageFlags2::COPY,
+                "copy_buffer_region",
+            );
+            tracker.write_buffer(
+                dst_buffer,
+                vk::PipelineStageFlags2::COPY,
+                "copy_buffer_region",
+            );
+        });
+        unsafe {
+            self.context.device.cmd_copy_buffer(
+                self.command_buffer,
+                src_buffer.handle,
+                dst_buffer.handle,
+                &[vk::BufferCopy::default()
+                    .size(size)
+                    .src_offset(src_offset)
+                    .dst_offset(dst_offset)],
+            );
+        }
+
+        self
+    }
+
     pub fn copy_buffer_to_image(
         &self,
         src_buffer: &Buffer,
         dst_image: &mut Image,
         src_offset: vk::DeviceSize,
     ) -> &Self {
+        self.track(|tracker| {
+            tracker.read_buffer(
+                src_buffer,
+                vk::PipelineStageFlags2::COPY,
+                "copy_buffer_to_image",
+            )
+        });
         self.ensure_image_layout(dst_image, ImageLayoutState::transfer_destination());
 
         unsafe {
@@ -79,7 +180,7 @@ impl Commands {
                 self.command_buffer,
                 src_buffer.handle,
                 dst_image.handle,
-                dst_image.layout.layout,
+                dst_image.layout().layout,
                 &[vk::BufferImageCopy::default()
                     .buffer_offset(src_offset)
                     .image_subresource(dst_image.subresource_layers())
@@ -90,6 +191,147 @@ impl Commands {
         self
     }
 
+    // Writes a tightly packed rectangle of the first mip level, e.g. a partial texture update.
+    pub fn copy_buffer_to_image_region(
+        &self,
+        src_buffer: &Buffer,
+        dst_image: &mut Image,
+        src_offset: vk::DeviceSize,
+        offset: vk::Offset3D,
+        extent: vk::Extent3D,
+    ) -> &Self {
+        self.track(|tracker| {
+            tracker.read_buffer(
+                src_buffer,
+                vk::PipelineStageFlags2::COPY,
+                "copy_buffer_to_image_region",
+            )
+        });
+        self.ensure_image_layout(dst_image, ImageLayoutState::transfer_destination());
+
+        unsafe {
+            self.context.device.cmd_copy_buffer_to_image(
+                self.command_buffer,
+                src_buffer.handle,
+                dst_image.handle,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::BufferImageCopy::default()
+                    .buffer_offset(src_offset)
+                    .image_subresource(dst_image.subresource_layers())
+                    .image_offset(offset)
+                    .image_extent(extent)],
+            );
+        }
+
+        self
+    }
+
+    // Copies a rectangle of the first mip level, tightly packed, e.g. a single pixel for picking.
+    // Copies one tightly packed mip level of every layer.
+    pub fn copy_buffer_to_image_level(
+        &self,
+        src_buffer: &Buffer,
+        dst_image: &mut Image,
+        src_offset: vk::DeviceSize,
+        level: u32,
+    ) -> &Self {
+        self.track(|tracker| {
+            tracker.read_buffer(
+                src_buffer,
+                vk::PipelineStageFlags2::COPY,
+                "copy_buffer_to_image_level",
+            )
+        });
+        self.ensure_image_layout(dst_image, ImageLayoutState::transfer_destination());
+
+        unsafe {
+            self.context.device.cmd_copy_buffer_to_image(
+                self.command_buffer,
+                src_buffer.handle,
+                dst_image.handle,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::BufferImageCopy::default()
+                    .buffer_offset(src_offset)
+                    .image_subresource(dst_image.subresource_layers().mip_level(level))
+                    .image_extent(dst_image.mip_extent(level))],
+            );
+        }
+
+        self
+    }
+
+    pub fn copy_image_region_to_buffer(
+        &self,
+        src_image: &mut Image,
+        region: vk::Rect2D,
+        dst_buffer: &Buffer,
+        dst_offset: vk::DeviceSize,
+    ) -> &Self {
+        self.track(|tracker| {
+            tracker.write_buffer(
+                dst_buffer,
+                vk::PipelineStageFlags2::COPY,
+                "copy_image_region_to_buffer",
+            )
+        });
+        self.ensure_image_layout(src_image, ImageLayoutState::transfer_source());
+
+        unsafe {
+            self.context.device.cmd_copy_image_to_buffer(
+                self.command_buffer,
+                src_image.handle,
+                src_image.layout().layout,
+                dst_buffer.handle,
+                &[vk::BufferImageCopy::default()
+                    .buffer_offset(dst_offset)
+                    .image_subresource(src_image.subresource_layers())
+                    .image_offset(vk::Offset3D {
+                        x: region.offset.x,
+                        y: region.offset.y,
+                        z: 0,
+                    })
+                    .image_extent(vk::Extent3D {
+                        width: region.extent.width,
+                        height: region.extent.height,
+                        depth: 1,
+                    })],
+            );
+        }
+
+        self
+    }
+
+    pub fn copy_image_to_buffer(
+        &self,
+        src_image: &mut Image,
+        dst_buffer: &Buffer,
+        dst_offset: vk::DeviceSize,
+    ) -> &Self {
+        self.track(|tracker| {
+            tracker.write_buffer(
+                dst_buffer,
+                vk::PipelineStageFlags2::COPY,
+                "copy_image_to_buffer",
+            )
+        });
+        self.ensure_image_layout(src_image, ImageLayoutState::transfer_source());
+
+        unsafe {
+            self.context.device.cmd_copy_image_to_buffer(
+                self.command_buffer,
+                src_image.handle,
+                src_image.layout().layout,
+                dst_buffer.handle,
+                &[vk::BufferImageCopy::default()
+                    .buffer_offset(dst_offset)
+                    .image_subresource(src_image.subresource_layers())
+                    .image_extent(src_image.attributes.extent)],
+            );
+        }
+
+        self
+    }
+
     pub fn bind_descriptor_sets(
         &self,
         pipeline_layout: vk::PipelineLayout,
@@ -127,42 +369,156 @@ impl Commands {
         self
     }
 
-    pub fn transition_image_layout(&self, image: &mut Image, new_state: ImageLayoutState) -> &Self {
+    // For push constants only known as bytes, e.g. of shaders supplied by users.
+    pub fn set_push_constant_bytes(
+        &self,
+        pipeline_layout: vk::PipelineLayout,
+        data: &[u8],
+    ) -> &Self {
+        if data.is_empty() {
+            return self;
+        }
         unsafe {
-            let old_state = image.layout;
+            self.context.device.cmd_push_constants(
+                self.command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                data,
+            );
+        }
+
+        self
+    }
 
-            trace!("Transitioned image layout from {old_state:#?} to {new_state:#?}");
+    fn record_layout_transitions(
+        &self,
+        image: &mut Image,
+        range: vk::ImageSubresourceRange,
+        new_state: ImageLayoutState,
+        only_if_needed: bool,
+    ) -> &Self {
+        let barriers = image
+            .layout_transitions(range, new_state, only_if_needed)
+            .into_iter()
+            .map(|(old_state, range)| {
+                trace!("Transitioned {range:?} from {old_state:#?} to {new_state:#?}");
+
+                vk::ImageMemoryBarrier2KHR::default()
+                    .src_stage_mask(old_state.stage)
+                    .dst_stage_mask(new_state.stage)
+                    .src_access_mask(old_state.access)
+                    .dst_access_mask(new_state.access)
+                    .old_layout(old_state.layout)
+                    .new_layout(new_state.layout)
+                    .src_queue_family_index(old_state.queue_family)
+                    .dst_queue_family_index(new_state.queue_family)
+                    .image(image.handle)
+                    .subresource_range(range)
+            })
+            .collect::<Vec<_>>();
+        if barriers.is_empty() {
+            return self;
+        }
 
+        unsafe {
             self.context.device.cmd_pipeline_barrier2(
                 self.command_buffer,
-                &vk::DependencyInfo::default().image_memory_barriers(&[
-                    vk::ImageMemoryBarrier2KHR::default()
-                        .src_stage_mask(old_state.stage)
-                        .dst_stage_mask(new_state.stage)
-                        .src_access_mask(old_state.access)
-                        .dst_access_mask(new_state.access)
-                        .old_layout(old_state.layout)
-                        .new_layout(new_state.layout)
-                        .src_queue_family_index(old_state.queue_family)
-                        .dst_queue_family_index(new_state.queue_family)
-                        .image(image.handle)
-                        .subresource_range(image.attributes.subresource_range),
-                ]),
+                &vk::DependencyInfo::default().image_memory_barriers(&barriers),
             );
+        }
+        image.set_subresource_layout(range, new_state);
+        self
+    }
 
-            image.layout = new_state;
+    // Queue family ownership transfer of the whole image in its current layout. The source queue
+    // records the release, then the destination queue the acquire with the same families before
+    // touching the image, ordered by a semaphore between the two submissions.
+    pub fn release_image_ownership(
+        &self,
+        image: &Image,
+        src_queue_family: u32,
+        dst_queue_family: u32,
+    ) -> &Self {
+        let barrier = self
+            .ownership_barrier(image, src_queue_family, dst_queue_family)
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE);
+        unsafe {
+            self.context.device.cmd_pipeline_barrier2(
+                self.command_buffer,
+                &vk::DependencyInfo::default().image_memory_barriers(&[barrier]),
+            );
         }
         self
     }
 
-    pub fn ensure_image_layout(&self, image: &mut Image, new_state: ImageLayoutState) -> &Self {
-        let state = image.layout;
-        if !new_state.is_subset_of(state) {
-            self.transition_image_layout(image, new_state);
+    pub fn acquire_image_ownership(
+        &self,
+        image: &Image,
+        src_queue_family: u32,
+        dst_queue_family: u32,
+    ) -> &Self {
+        let barrier = self
+            .ownership_barrier(image, src_queue_family, dst_queue_family)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_READ);
+        unsafe {
+            self.context.device.cmd_pipeline_barrier2(
+                self.command_buffer,
+                &vk::DependencyInfo::default().image_memory_barriers(&[barrier]),
+            );
         }
         self
     }
 
+    fn ownership_barrier(
+        &self,
+        image: &Image,
+        src_queue_family: u32,
+        dst_queue_family: u32,
+    ) -> vk::ImageMemoryBarrier2<'static> {
+        let layout = image.layout().layout;
+        vk::ImageMemoryBarrier2::default()
+            .old_layout(layout)
+            .new_layout(layout)
+            .src_queue_family_index(src_queue_family)
+            .dst_queue_family_index(dst_queue_family)
+            .image(image.handle)
+            .subresource_range(image.attributes.subresource_range)
+    }
+
+    pub fn transition_image_layout(&self, image: &mut Image, new_state: ImageLayoutState) -> &Self {
+        let range = image.attributes.subresource_range;
+        self.record_layout_transitions(image, range, new_state, false)
+    }
+
+    // Only transitions the subresources that are not in the new state yet.
+    pub fn ensure_image_layout(&self, image: &mut Image, new_state: ImageLayoutState) -> &Self {
+        let range = image.attributes.subresource_range;
+        self.record_layout_transitions(image, range, new_state, true)
+    }
+
+    // Mip levels and array layers can be in different layouts, e.g. while generating mip maps
+    // or rendering into some layers of a shadow atlas.
+    pub fn transition_subresource_layout(
+        &self,
+        image: &mut Image,
+        range: vk::ImageSubresourceRange,
+        new_state: ImageLayoutState,
+    ) -> &Self {
+        self.record_layout_transitions(image, range, new_state, false)
+    }
+
+    pub fn ensure_subresource_layout(
+        &self,
+        image: &mut Image,
+        range: vk::ImageSubresourceRange,
+        new_state: ImageLayoutState,
+    ) -> &Self {
+        self.record_layout_transitions(image, range, new_state, true)
+    }
+
     pub fn blit_image(
         &self,
         src_image: &mut Image,
@@ -178,9 +534,9 @@ impl Commands {
             self.context.device.cmd_blit_image(
                 self.command_buffer,
                 src_image.handle,
-                src_image.layout.layout,
+                src_image.layout().layout,
                 dst_image.handle,
-                dst_image.layout.layout,
+                dst_image.layout().layout,
                 &[vk::ImageBlit::default()
                     .src_subresource(src_image.subresource_layers())
                     .src_offsets(src_offsets)
@@ -193,6 +549,84 @@ impl Commands {
         self
     }
 
+    // Fills the mip levels below the first by blitting each level into the next one, leaving the
+    // whole image in TRANSFER_SRC_OPTIMAL. The format must support linearly filtered blits.
+    // Images with a single level are left as they are.
+    pub fn generate_mipmaps(&self, image: &mut Image) -> &Self {
+        if image.mip_levels() <= 1 {
+            return self;
+        }
+
+        let range = image.attributes.subresource_range;
+        let level_range = |level| {
+            vk::ImageSubresourceRange::default()
+                .aspect_mask(range.aspect_mask)
+                .base_mip_level(level)
+                .level_count(1)
+                .base_array_layer(range.base_array_layer)
+                .layer_count(range.layer_count)
+        };
+        let level_layers = |level| {
+            vk::ImageSubresourceLayers::default()
+                .aspect_mask(range.aspect_mask)
+                .mip_level(level)
+                .base_array_layer(range.base_array_layer)
+                .layer_count(range.layer_count)
+        };
+        let corner = |extent: vk::Extent3D| vk::Offset3D {
+            x: extent.width as i32,
+            y: extent.height as i32,
+            z: extent.depth as i32,
+        };
+
+        for level in 1..image.mip_levels() {
+            self.ensure_subresource_layout(
+                image,
+                level_range(level - 1),
+                ImageLayoutState::transfer_source(),
+            )
+            .ensure_subresource_layout(
+                image,
+                level_range(level),
+                ImageLayoutState::transfer_destination(),
+            );
+
+            unsafe {
+                self.context.device.cmd_blit_image(
+                    self.command_buffer,
+                    image.handle,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    image.handle,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[vk::ImageBlit::default()
+                        .src_subresource(level_layers(level - 1))
+                        .src_offsets([vk::Offset3D::default(), corner(image.mip_extent(level - 1))])
+                        .dst_subresource(level_layers(level))
+                        .dst_offsets([vk::Offset3D::default(), corner(image.mip_extent(level))])],
+                    vk::Filter::LINEAR,
+                );
+            }
+        }
+
+        self.ensure_image_layout(image, ImageLayoutState::transfer_source())
+    }
+
+    pub fn clear_color_image(&self, image: &mut Image, color: vk::ClearColorValue) -> &Self {
+        self.ensure_image_layout(image, ImageLayoutState::transfer_destination());
+
+        unsafe {
+            self.context.device.cmd_clear_color_image(
+                self.command_buffer,
+                image.handle,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &color,
+                &[image.attributes.subresource_range],
+            );
+        }
+
+        self
+    }
+
     pub fn blit_image_extent(
         &self,
         src_image: &mut Image,
@@ -239,10 +673,35 @@ impl Commands {
         )
     }
 
-    pub fn begin_rendering(
+    // Copies every layer of the first mip level, the images must have the same format and extent.
+    pub fn copy_image(&self, src_image: &mut Image, dst_image: &mut Image) -> &Self {
+        self.ensure_image_layout(src_image, ImageLayoutState::transfer_source())
+            .ensure_image_layout(dst_image, ImageLayoutState::transfer_destination());
+
+        unsafe {
+            self.context.device.cmd_copy_image(
+                self.command_buffer,
+                src_image.handle,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst_image.handle,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageCopy::default()
+                    .src_subresource(src_image.subresource_layers())
+                    .dst_subresource(dst_image.subresource_layers())
+                    .extent(src_image.attributes.extent)],
+            );
+        }
+
+        self
+    }
+
+    // The multisampled attachments are resolved into the frame's render target and depth buffer
+    // at the end of the pass and never stored, so tilers can keep them in tile memory.
+    pub(crate) fn begin_rendering(
         &self,
         frame: &mut Frame,
         clear_color: vk::ClearColorValue,
+        depth_resolve_mode: vk::ResolveModeFlags,
         render_area: vk::Rect2D,
     ) -> &Self {
         self.ensure_image_layout(
@@ -251,7 +710,7 @@ impl Commands {
         )
         .ensure_image_layout(
             &mut frame.depth_buffer,
-            ImageLayoutState::depth_stencil_attachment(),
+            ImageLayoutState::depth_resolve_attachment(),
         )
         .ensure_image_layout(
             &mut frame.msaa_render_target,
@@ -269,18 +728,18 @@ impl Commands {
                     .layer_count(1)
                     .color_attachments(&[vk::RenderingAttachmentInfo::default()
                         .image_view(frame.msaa_render_target.view)
-                        .image_layout(frame.msaa_render_target.layout.layout)
+                        .image_layout(frame.msaa_render_target.layout().layout)
                         .clear_value(vk::ClearValue { color: clear_color })
                         .load_op(vk::AttachmentLoadOp::CLEAR)
-                        .store_op(vk::AttachmentStoreOp::STORE)
-                        .resolve_image_layout(frame.render_target.layout.layout)
+                        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                        .resolve_image_layout(frame.render_target.layout().layout)
                         .resolve_image_view(frame.render_target.view)
                         .resolve_mode(vk::ResolveModeFlagsKHR::AVERAGE)])
                     .render_area(render_area)
                     .depth_attachment(
                         &vk::RenderingAttachmentInfo::default()
                             .image_view(frame.msaa_depth_buffer.view)
-                            .image_layout(frame.msaa_depth_buffer.layout.layout)
+                            .image_layout(frame.msaa_depth_buffer.layout().layout)
                             .clear_value(vk::ClearValue {
                                 depth_stencil: vk::ClearDepthStencilValue {
                                     depth: 1.0,
@@ -288,10 +747,147 @@ impl Commands {
                                 },
                             })
                             .load_op(vk::AttachmentLoadOp::CLEAR)
-                            .store_op(vk::AttachmentStoreOp::STORE)
-                            .resolve_image_layout(frame.depth_buffer.layout.layout)
+                            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                            .resolve_image_layout(frame.depth_buffer.layout().layout)
                             .resolve_image_view(frame.depth_buffer.view)
-                            .resolve_mode(vk::ResolveModeFlagsKHR::AVERAGE),
+                            .resolve_mode(depth_resolve_mode),
+                    ),
+            );
+        }
+
+        self
+    }
+
+    // Depth only pass into one view of the image, e.g. a single face of a shadow map, cleared to
+    // the far plane when clear is set and drawn on top of the existing depth otherwise. Only the
+    // array layer the view points at is transitioned, the image's other layers are left alone.
+    pub fn begin_depth_rendering(
+        &self,
+        image: &mut Image,
+        view: vk::ImageView,
+        array_layer: u32,
+        render_area: vk::Rect2D,
+        clear: bool,
+    ) -> &Self {
+        self.begin_layered_depth_rendering(image, view, array_layer, 1, render_area, clear)
+    }
+
+    // Renders into layer_count layers from array_layer at once through a view covering them,
+    // the vertex shader picks the layer of each primitive with gl_Layer.
+    pub fn begin_layered_depth_rendering(
+        &self,
+        image: &mut Image,
+        view: vk::ImageView,
+        array_layer: u32,
+        layer_count: u32,
+        render_area: vk::Rect2D,
+        clear: bool,
+    ) -> &Self {
+        let range = vk::ImageSubresourceRange {
+            base_array_layer: array_layer,
+            layer_count,
+            ..image.attributes.subresource_range
+        };
+        self.ensure_subresource_layout(image, range, ImageLayoutState::depth_stencil_attachment());
+
+        unsafe {
+            self.context.device.cmd_begin_rendering(
+                self.command_buffer,
+                &vk::RenderingInfo::default()
+                    .layer_count(layer_count)
+                    .render_area(render_area)
+                    .depth_attachment(
+                        &vk::RenderingAttachmentInfo::default()
+                            .image_view(view)
+                            .image_layout(image.subresource_layout(0, array_layer).layout)
+                            .clear_value(vk::ClearValue {
+                                depth_stencil: vk::ClearDepthStencilValue {
+                                    depth: 1.0,
+                                    stencil: 0,
+                                },
+                            })
+                            .load_op(if clear {
+                                vk::AttachmentLoadOp::CLEAR
+                            } else {
+                                vk::AttachmentLoadOp::LOAD
+                            })
+                            .store_op(vk::AttachmentStoreOp::STORE),
+                    ),
+            );
+        }
+
+        self
+    }
+
+    // Single color attachment pass without depth, cleared when a clear color is given and
+    // drawn on top of the existing contents otherwise.
+    pub fn begin_color_rendering(
+        &self,
+        image: &mut Image,
+        clear_color: Option<vk::ClearColorValue>,
+        render_area: vk::Rect2D,
+    ) -> &Self {
+        self.ensure_image_layout(image, ImageLayoutState::color_attachment());
+
+        unsafe {
+            self.context.device.cmd_begin_rendering(
+                self.command_buffer,
+                &vk::RenderingInfo::default()
+                    .layer_count(1)
+                    .render_area(render_area)
+                    .color_attachments(&[vk::RenderingAttachmentInfo::default()
+                        .image_view(image.view)
+                        .image_layout(image.layout().layout)
+                        .clear_value(vk::ClearValue {
+                            color: clear_color.unwrap_or_default(),
+                        })
+                        .load_op(if clear_color.is_some() {
+                            vk::AttachmentLoadOp::CLEAR
+                        } else {
+                            vk::AttachmentLoadOp::LOAD
+                        })
+                        .store_op(vk::AttachmentStoreOp::STORE)]),
+            );
+        }
+
+        self
+    }
+
+    // Single sampled color and depth pass, both cleared, the depth is only kept for the pass.
+    pub fn begin_color_depth_rendering(
+        &self,
+        color_image: &mut Image,
+        depth_image: &mut Image,
+        clear_color: vk::ClearColorValue,
+        render_area: vk::Rect2D,
+    ) -> &Self {
+        self.ensure_image_layout(color_image, ImageLayoutState::color_attachment())
+            .ensure_image_layout(depth_image, ImageLayoutState::depth_stencil_attachment());
+
+        unsafe {
+            self.context.device.cmd_begin_rendering(
+                self.command_buffer,
+                &vk::RenderingInfo::default()
+                    .layer_count(1)
+                    .render_area(render_area)
+                    .color_attachments(&[vk::RenderingAttachmentInfo::default()
+                        .image_view(color_image.view)
+                        .image_layout(color_image.layout().layout)
+                        .clear_value(vk::ClearValue { color: clear_color })
+                        .load_op(vk::AttachmentLoadOp::CLEAR)
+                        .store_op(vk::AttachmentStoreOp::STORE)])
+                    .depth_attachment(
+                        &vk::RenderingAttachmentInfo::default()
+                            .image_view(depth_image.view)
+                            .image_layout(depth_image.layout().layout)
+                            .clear_value(vk::ClearValue {
+                                depth_stencil: vk::ClearDepthStencilValue {
+                                    depth: 1.0,
+                                    stencil: 0,
+                                },
+                            })
+                            .load_op(vk::AttachmentLoadOp::CLEAR)
+                            .store_op(vk::AttachmentStoreOp::DONT_CARE),
                     ),
             );
         }
@@ -299,6 +895,26 @@ impl Commands {
         self
     }
 
+    // Clears parts of the first color attachment of the current rendering.
+    pub fn clear_color_rects(&self, color: vk::ClearColorValue, rects: &[vk::Rect2D]) -> &Self {
+        let clear_rects = rects
+            .iter()
+            .map(|rect| vk::ClearRect::default().rect(*rect).layer_count(1))
+            .collect::<Vec<_>>();
+        unsafe {
+            self.context.device.cmd_clear_attachments(
+                self.command_buffer,
+                &[vk::ClearAttachment::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .color_attachment(0)
+                    .clear_value(vk::ClearValue { color })],
+                &clear_rects,
+            );
+        }
+
+        self
+    }
+
     pub fn end_rendering(&self) -> &Self {
         unsafe {
             self.context.device.cmd_end_rendering(self.command_buffer);
@@ -327,6 +943,49 @@ impl Commands {
         self
     }
 
+    // From the first viewport on, for pipelines created with a viewport_count of at least as many.
+    pub fn set_viewports(&self, viewports: &[vk::Viewport]) -> &Self {
+        unsafe {
+            self.context
+                .device
+                .cmd_set_viewport(self.command_buffer, 0, viewports);
+        }
+
+        self
+    }
+
+    pub fn set_scissors(&self, scissors: &[vk::Rect2D]) -> &Self {
+        unsafe {
+            self.context
+                .device
+                .cmd_set_scissor(self.command_buffer, 0, scissors);
+        }
+
+        self
+    }
+
+    // Requires a pipeline created with dynamic_line_width.
+    pub fn set_line_width(&self, line_width: f32) -> &Self {
+        unsafe {
+            self.context
+                .device
+                .cmd_set_line_width(self.command_buffer, line_width);
+        }
+
+        self
+    }
+
+    // Requires a pipeline created with dynamic_topology, the new topology must be of the same class.
+    pub fn set_primitive_topology(&self, topology: vk::PrimitiveTopology) -> &Self {
+        unsafe {
+            self.context
+                .device
+                .cmd_set_primitive_topology(self.command_buffer, topology);
+        }
+
+        self
+    }
+
     pub fn bind_pipeline(&self, pipeline: vk::Pipeline) -> &Self {
         unsafe {
             self.context.device.cmd_bind_pipeline(
@@ -339,6 +998,95 @@ impl Commands {
         self
     }
 
+    pub fn bind_compute_pipeline(&self, pipeline: vk::Pipeline) -> &Self {
+        unsafe {
+            self.context.device.cmd_bind_pipeline(
+                self.command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline,
+            );
+        }
+
+        self
+    }
+
+    pub fn bind_compute_descriptor_sets(
+        &self,
+        pipeline_layout: vk::PipelineLayout,
+        descriptor_sets: &[vk::DescriptorSet],
+    ) -> &Self {
+        unsafe {
+            self.context.device.cmd_bind_descriptor_sets(
+                self.command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline_layout,
+                0,
+                descriptor_sets,
+                &[],
+            );
+        }
+
+        self
+    }
+
+    pub fn set_compute_push_constants<T: bytemuck::Pod>(
+        &self,
+        pipeline_layout: vk::PipelineLayout,
+        data: T,
+    ) -> &Self {
+        unsafe {
+            self.context.device.cmd_push_constants(
+                self.command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                bytemuck::bytes_of(&data),
+            );
+        }
+
+        self
+    }
+
+    pub fn dispatch(&self, group_counts: [u32; 3]) -> &Self {
+        unsafe {
+            self.context.device.cmd_dispatch(
+                self.command_buffer,
+                group_counts[0],
+                group_counts[1],
+                group_counts[2],
+            );
+        }
+
+        self
+    }
+
+    // Enough groups of the shader's local size to cover every invocation.
+    pub fn dispatch_invocations(&self, invocations: [u32; 3], local_size: [u32; 3]) -> &Self {
+        self.dispatch([
+            invocations[0].div_ceil(local_size[0]),
+            invocations[1].div_ceil(local_size[1]),
+            invocations[2].div_ceil(local_size[2]),
+        ])
+    }
+
+    // Group counts read on the GPU, e.g. written by a previous culling pass.
+    pub fn dispatch_indirect(&self, buffer: &Buffer, offset: vk::DeviceSize) -> &Self {
+        self.track(|tracker| {
+            tracker.read_buffer(
+                buffer,
+                vk::PipelineStageFlags2::DRAW_INDIRECT,
+                "dispatch_indirect",
+            )
+        });
+        unsafe {
+            self.context
+                .device
+                .cmd_dispatch_indirect(self.command_buffer, buffer.handle, offset);
+        }
+
+        self
+    }
+
     pub fn draw(&self, vertices: Range<u32>, instances: Range<u32>) -> &Self {
         unsafe {
             self.context.device.cmd_draw(
@@ -353,6 +1101,17 @@ impl Commands {
         self
     }
 
+    // Same as draw with the topology set first, e.g. to switch a lines pipeline to line strips.
+    pub fn draw_with_topology(
+        &self,
+        topology: vk::PrimitiveTopology,
+        vertices: Range<u32>,
+        instances: Range<u32>,
+    ) -> &Self {
+        self.set_primitive_topology(topology)
+            .draw(vertices, instances)
+    }
+
     pub fn draw_indexed(&self, indices: Range<u32>, instances: Range<u32>) -> &Self {
         unsafe {
             self.context.device.cmd_draw_indexed(
@@ -368,6 +1127,274 @@ impl Commands {
         self
     }
 
+    pub fn draw_indexed_indirect(
+        &self,
+        buffer: &Buffer,
+        offset: vk::DeviceSize,
+        draw_count: u32,
+    ) -> &Self {
+        self.track(|tracker| {
+            tracker.read_buffer(
+                buffer,
+                vk::PipelineStageFlags2::DRAW_INDIRECT,
+                "draw_indexed_indirect",
+            )
+        });
+        unsafe {
+            self.context.device.cmd_draw_indexed_indirect(
+                self.command_buffer,
+                buffer.handle,
+                offset,
+                draw_count,
+                size_of::<vk::DrawIndexedIndirectCommand>() as u32,
+            );
+        }
+
+        self
+    }
+
+    // Draws as many commands as the u32 in count_buffer says, at most max_draw_count.
+    pub fn draw_indexed_indirect_count(
+        &self,
+        buffer: &Buffer,
+        offset: vk::DeviceSize,
+        count_buffer: &Buffer,
+        count_offset: vk::DeviceSize,
+        max_draw_count: u32,
+    ) -> &Self {
+        self.track(|tracker| {
+            for buffer in [buffer, count_buffer] {
+                tracker.read_buffer(
+                    buffer,
+                    vk::PipelineStageFlags2::DRAW_INDIRECT,
+                    "draw_indexed_indirect_count",
+                );
+            }
+        });
+        unsafe {
+            self.context.device.cmd_draw_indexed_indirect_count(
+                self.command_buffer,
+                buffer.handle,
+                offset,
+                count_buffer.handle,
+                count_offset,
+                max_draw_count,
+                size_of::<vk::DrawIndexedIndirectCommand>() as u32,
+            );
+        }
+
+        self
+    }
+
+    pub fn reset_query_pool(&self, query_pool: &QueryPool, queries: Range<u32>) -> &Self {
+        unsafe {
+            self.context.device.cmd_reset_query_pool(
+                self.command_buffer,
+                query_pool.handle,
+                queries.start,
+                queries.end - queries.start,
+            );
+        }
+
+        self
+    }
+
+    pub fn begin_query(&self, query_pool: &QueryPool, query: u32) -> &Self {
+        unsafe {
+            self.context.device.cmd_begin_query(
+                self.command_buffer,
+                query_pool.handle,
+                query,
+                vk::QueryControlFlags::empty(),
+            );
+        }
+
+        self
+    }
+
+    pub fn end_query(&self, query_pool: &QueryPool, query: u32) -> &Self {
+        unsafe {
+            self.context
+                .device
+                .cmd_end_query(self.command_buffer, query_pool.handle, query);
+        }
+
+        self
+    }
+
+    pub fn write_timestamp(
+        &self,
+        query_pool: &QueryPool,
+        stage: vk::PipelineStageFlags2,
+        query: u32,
+    ) -> &Self {
+        unsafe {
+            self.context.device.cmd_write_timestamp2(
+                self.command_buffer,
+                stage,
+                query_pool.handle,
+                query,
+            );
+        }
+
+        self
+    }
+
+    pub fn copy_query_pool_results(
+        &self,
+        query_pool: &QueryPool,
+        queries: Range<u32>,
+        dst_buffer: &Buffer,
+        dst_offset: vk::DeviceSize,
+    ) -> &Self {
+        self.track(|tracker| {
+            tracker.write_buffer(
+                dst_buffer,
+                vk::PipelineStageFlags2::COPY,
+                "copy_query_pool_results",
+            )
+        });
+        unsafe {
+            self.context.device.cmd_copy_query_pool_results(
+                self.command_buffer,
+                query_pool.handle,
+                queries.start,
+                queries.end - queries.start,
+                dst_buffer.handle,
+                dst_offset,
+                size_of::<u32>() as vk::DeviceSize,
+                vk::QueryResultFlags::empty(),
+            );
+        }
+
+        self
+    }
+
+    pub fn buffer_memory_barrier(
+        &self,
+        buffer: &Buffer,
+        src: (vk::PipelineStageFlags2, vk::AccessFlags2),
+        dst: (vk::PipelineStageFlags2, vk::AccessFlags2),
+    ) -> &Self {
+        self.track(|tracker| tracker.barrier_buffer(buffer));
+        unsafe {
+            self.context.device.cmd_pipeline_barrier2(
+                self.command_buffer,
+                &vk::DependencyInfo::default().buffer_memory_barriers(&[
+                    vk::BufferMemoryBarrier2::default()
+                        .src_stage_mask(src.0)
+                        .src_access_mask(src.1)
+                        .dst_stage_mask(dst.0)
+                        .dst_access_mask(dst.1)
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .buffer(buffer.handle)
+                        .size(vk::WHOLE_SIZE),
+                ]),
+            );
+        }
+
+        self
+    }
+
+    // Records all barriers of a render graph pass at once.
+    pub fn pipeline_barrier(
+        &self,
+        memory_barriers: &[vk::MemoryBarrier2],
+        image_barriers: &[vk::ImageMemoryBarrier2],
+    ) -> &Self {
+        if memory_barriers.is_empty() && image_barriers.is_empty() {
+            return self;
+        }
+        unsafe {
+            self.context.device.cmd_pipeline_barrier2(
+                self.command_buffer,
+                &vk::DependencyInfo::default()
+                    .memory_barriers(memory_barriers)
+                    .image_memory_barriers(image_barriers),
+            );
+        }
+
+        self
+    }
+
+    // Orders every earlier access in the src stages before the dst stages, for resources that are
+    // only reached through device addresses or shared by many passes.
+    pub fn memory_barrier(
+        &self,
+        src: (vk::PipelineStageFlags2, vk::AccessFlags2),
+        dst: (vk::PipelineStageFlags2, vk::AccessFlags2),
+    ) -> &Self {
+        unsafe {
+            self.context.device.cmd_pipeline_barrier2(
+                self.command_buffer,
+                &vk::DependencyInfo::default().memory_barriers(&[vk::MemoryBarrier2::default()
+                    .src_stage_mask(src.0)
+                    .src_access_mask(src.1)
+                    .dst_stage_mask(dst.0)
+                    .dst_access_mask(dst.1)]),
+            );
+        }
+
+        self
+    }
+
+    // Draws recorded until end_conditional_rendering are discarded when the u32 at offset is zero.
+    // Without VK_EXT_conditional_rendering this is a no-op and everything is drawn.
+    pub fn begin_conditional_rendering(
+        &self,
+        buffer: &Buffer,
+        offset: vk::DeviceSize,
+        inverted: bool,
+    ) -> &Self {
+        if let Some(extension) = &self.context.conditional_rendering_extension {
+            let flags = if inverted {
+                vk::ConditionalRenderingFlagsEXT::INVERTED
+            } else {
+                vk::ConditionalRenderingFlagsEXT::empty()
+            };
+
+            unsafe {
+                (extension.fp().cmd_begin_conditional_rendering_ext)(
+                    self.command_buffer,
+                    &vk::ConditionalRenderingBeginInfoEXT::default()
+                        .buffer(buffer.handle)
+                        .offset(offset)
+                        .flags(flags),
+                );
+            }
+        }
+
+        self
+    }
+
+    pub fn end_conditional_rendering(&self) -> &Self {
+        if let Some(extension) = &self.context.conditional_rendering_extension {
+            unsafe {
+                (extension.fp().cmd_end_conditional_rendering_ext)(self.command_buffer);
+            }
+        }
+
+        self
+    }
+
+    // Restricts the following commands to the devices of a device group, see RenderingContext::device_mask.
+    pub fn set_device_mask(&self, device_mask: u32) -> &Self {
+        unsafe {
+            self.context
+                .device
+                .cmd_set_device_mask(self.command_buffer, device_mask);
+        }
+
+        self
+    }
+
+    // Devices of a device group that execute the command buffer, zero meaning all of them.
+    pub fn set_submit_device_mask(&self, device_mask: u32) -> &Self {
+        self.submit_device_mask.set(device_mask);
+        self
+    }
+
     pub fn submit(
         &self,
         queue: vk::Queue,
@@ -380,8 +1407,9 @@ impl Commands {
                 .device
                 .end_command_buffer(self.command_buffer)?;
 
-            let command_buffer_submit_infos =
-                &[vk::CommandBufferSubmitInfoKHR::default().command_buffer(self.command_buffer)];
+            let command_buffer_submit_infos = &[vk::CommandBufferSubmitInfoKHR::default()
+                .command_buffer(self.command_buffer)
+                .device_mask(self.submit_device_mask.get())];
 
             let mut submit_info =
                 vk::SubmitInfo2KHR::default().command_buffer_infos(command_buffer_submit_infos);
@@ -0,0 +1,235 @@
+use crate::image::{block_size, level_size, mip_level_count};
+use anyhow::{anyhow, Result};
+use ash::vk;
+use std::path::Path;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+const KTX2_LEVEL_INDEX_OFFSET: usize = 80;
+
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+const DDS_HEADER_SIZE: usize = 128;
+const DDS_DX10_HEADER_SIZE: usize = 20;
+const DDS_CAPS2_CUBEMAP: u32 = 0x200;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+const DDS_DIMENSION_TEXTURE2D: u32 = 3;
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow!("Unexpected end of file at byte {offset}"))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64> {
+    bytes
+        .get(offset..offset + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow!("Unexpected end of file at byte {offset}"))
+}
+
+fn dds_fourcc_format(fourcc: &[u8]) -> Option<vk::Format> {
+    Some(match fourcc {
+        b"DXT1" => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        b"DXT2" | b"DXT3" => vk::Format::BC2_UNORM_BLOCK,
+        b"DXT4" | b"DXT5" => vk::Format::BC3_UNORM_BLOCK,
+        b"ATI1" | b"BC4U" => vk::Format::BC4_UNORM_BLOCK,
+        b"BC4S" => vk::Format::BC4_SNORM_BLOCK,
+        b"ATI2" | b"BC5U" => vk::Format::BC5_UNORM_BLOCK,
+        b"BC5S" => vk::Format::BC5_SNORM_BLOCK,
+        _ => return None,
+    })
+}
+
+fn dxgi_format(dxgi_format: u32) -> Option<vk::Format> {
+    Some(match dxgi_format {
+        70 | 71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        73 | 74 => vk::Format::BC2_UNORM_BLOCK,
+        75 => vk::Format::BC2_SRGB_BLOCK,
+        76 | 77 => vk::Format::BC3_UNORM_BLOCK,
+        78 => vk::Format::BC3_SRGB_BLOCK,
+        79 | 80 => vk::Format::BC4_UNORM_BLOCK,
+        81 => vk::Format::BC4_SNORM_BLOCK,
+        82 | 83 => vk::Format::BC5_UNORM_BLOCK,
+        84 => vk::Format::BC5_SNORM_BLOCK,
+        94 | 95 => vk::Format::BC6H_UFLOAT_BLOCK,
+        96 => vk::Format::BC6H_SFLOAT_BLOCK,
+        97 | 98 => vk::Format::BC7_UNORM_BLOCK,
+        99 => vk::Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    })
+}
+
+// Block compressed 2D texture with its mip chain, largest level first, uploaded as is instead
+// of being decoded to RGBA8.
+#[derive(Clone, Debug)]
+pub struct CompressedTexture {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedTexture {
+    // KTX2 or DDS, told apart by the file's magic.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        Self::from_bytes(&bytes).map_err(|error| anyhow!("{}: {error}", path.display()))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.starts_with(&KTX2_IDENTIFIER) {
+            Self::from_ktx2(bytes)
+        } else if bytes.starts_with(DDS_MAGIC) {
+            Self::from_dds(bytes)
+        } else {
+            Err(anyhow!("Not a KTX2 or DDS file"))
+        }
+    }
+
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(&KTX2_IDENTIFIER) {
+            return Err(anyhow!("Not a KTX2 file"));
+        }
+        let format = vk::Format::from_raw(read_u32(bytes, 12)? as i32);
+        let extent = vk::Extent2D {
+            width: read_u32(bytes, 20)?,
+            height: read_u32(bytes, 24)?,
+        };
+        let depth = read_u32(bytes, 28)?;
+        let layer_count = read_u32(bytes, 32)?;
+        let face_count = read_u32(bytes, 36)?;
+        // Zero asks the loader to generate the mip chain, which isn't possible for BCn.
+        let level_count = read_u32(bytes, 40)?.max(1);
+        let supercompression = read_u32(bytes, 44)?;
+
+        if supercompression != 0 {
+            return Err(anyhow!(
+                "KTX2 supercompression scheme {supercompression} isn't supported"
+            ));
+        }
+        if depth > 1 || layer_count > 1 || face_count > 1 {
+            return Err(anyhow!(
+                "Only 2D KTX2 textures are supported, not arrays, cubemaps or 3D textures"
+            ));
+        }
+
+        let levels = (0..level_count as usize)
+            .map(|level| {
+                let entry = KTX2_LEVEL_INDEX_OFFSET + level * 24;
+                let offset = read_u64(bytes, entry)? as usize;
+                let length = read_u64(bytes, entry + 8)? as usize;
+                // Offsets and lengths come from the file, so they may overflow.
+                offset
+                    .checked_add(length)
+                    .and_then(|end| bytes.get(offset..end))
+                    .map(<[u8]>::to_vec)
+                    .ok_or_else(|| anyhow!("KTX2 level {level} is out of bounds"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Self::new(format, extent, levels)
+    }
+
+    pub fn from_dds(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(DDS_MAGIC) {
+            return Err(anyhow!("Not a DDS file"));
+        }
+        let extent = vk::Extent2D {
+            width: read_u32(bytes, 16)?,
+            height: read_u32(bytes, 12)?,
+        };
+        let level_count = read_u32(bytes, 28)?.max(1);
+        let fourcc = bytes
+            .get(84..88)
+            .ok_or_else(|| anyhow!("Unexpected end of file at byte 84"))?;
+        if read_u32(bytes, 112)? & DDS_CAPS2_CUBEMAP != 0 {
+            return Err(anyhow!("DDS cubemaps aren't supported"));
+        }
+
+        let (format, data_offset) = if fourcc == b"DX10" {
+            let dxgi = read_u32(bytes, DDS_HEADER_SIZE)?;
+            let dimension = read_u32(bytes, DDS_HEADER_SIZE + 4)?;
+            let misc_flags = read_u32(bytes, DDS_HEADER_SIZE + 8)?;
+            let array_size = read_u32(bytes, DDS_HEADER_SIZE + 12)?;
+            if dimension != DDS_DIMENSION_TEXTURE2D
+                || misc_flags & DDS_RESOURCE_MISC_TEXTURECUBE != 0
+                || array_size > 1
+            {
+                return Err(anyhow!(
+                    "Only 2D DDS textures are supported, not arrays, cubemaps or 3D textures"
+                ));
+            }
+            let format = dxgi_format(dxgi)
+                .ok_or_else(|| anyhow!("DXGI format {dxgi} isn't block compressed"))?;
+            (format, DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE)
+        } else {
+            let format = dds_fourcc_format(fourcc).ok_or_else(|| {
+                anyhow!(
+                    "DDS four character code {:?} isn't block compressed",
+                    String::from_utf8_lossy(fourcc)
+                )
+            })?;
+            (format, DDS_HEADER_SIZE)
+        };
+
+        // Levels are stored back to back, largest first.
+        let mut offset = data_offset;
+        let levels = (0..level_count)
+            .map(|level| {
+                let length = level_size(format, mip_extent(extent, level))? as usize;
+                let pixels = offset
+                    .checked_add(length)
+                    .and_then(|end| bytes.get(offset..end))
+                    .map(<[u8]>::to_vec)
+                    .ok_or_else(|| anyhow!("DDS level {level} is out of bounds"))?;
+                offset += length;
+                Ok(pixels)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Self::new(format, extent, levels)
+    }
+
+    fn new(format: vk::Format, extent: vk::Extent2D, levels: Vec<Vec<u8>>) -> Result<Self> {
+        if block_size(format).is_none() {
+            return Err(anyhow!("{format:?} isn't a BCn format"));
+        }
+        if extent.width == 0 || extent.height == 0 {
+            return Err(anyhow!("Texture has no pixels"));
+        }
+        if levels.len() as u32 > mip_level_count(extent) {
+            return Err(anyhow!(
+                "{} mip levels don't fit a {}x{} texture",
+                levels.len(),
+                extent.width,
+                extent.height
+            ));
+        }
+        for (level, pixels) in levels.iter().enumerate() {
+            let expected = level_size(format, mip_extent(extent, level as u32))?;
+            if pixels.len() as vk::DeviceSize != expected {
+                return Err(anyhow!(
+                    "Mip level {level} has {} bytes instead of {expected}",
+                    pixels.len()
+                ));
+            }
+        }
+
+        Ok(Self {
+            format,
+            extent,
+            levels,
+        })
+    }
+}
+
+fn mip_extent(extent: vk::Extent2D, level: u32) -> vk::Extent3D {
+    vk::Extent3D {
+        width: (extent.width >> level).max(1),
+        height: (extent.height >> level).max(1),
+        depth: 1,
+    }
+}
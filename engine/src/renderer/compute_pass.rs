@@ -0,0 +1,45 @@
+use crate::renderer::commands::Commands;
+use crate::rendering_context::Image;
+use ash::vk;
+
+// Where in the frame a compute pass is recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ComputeStage {
+    // After uploads and before anything is drawn, e.g. culling or particle simulation. Writes
+    // are visible to every draw of the frame, including indirect commands.
+    BeforeScene,
+    // After the scene is resolved into the render target, e.g. post-processing, and before it's
+    // presented.
+    AfterScene,
+}
+
+// Resolved targets of the frame being recorded. Passes bring them into the layout they need
+// with Commands::ensure_image_layout, the renderer transitions them back afterwards.
+pub struct ComputeTargets<'a> {
+    // Index of the frame in flight, for per-frame resources of the pass.
+    pub frame_index: usize,
+    pub render_target: &'a mut Image,
+    pub depth_buffer: &'a mut Image,
+}
+
+pub(crate) type RecordComputePass = Box<dyn FnMut(&Commands, &mut ComputeTargets)>;
+
+pub(crate) struct ComputePass {
+    pub name: String,
+    pub stage: ComputeStage,
+    pub record: RecordComputePass,
+}
+
+// Storage writes of the passes before whatever the rest of the frame does with them.
+pub(crate) fn compute_barrier(commands: &Commands) {
+    commands.memory_barrier(
+        (
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_WRITE,
+        ),
+        (
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+        ),
+    );
+}
@@ -0,0 +1,80 @@
+use crate::renderer::debug_lines::DebugLines;
+
+// Anything that can describe itself as lines, e.g. a physics world's colliders and contacts.
+pub trait DebugDraw {
+    fn debug_draw(&mut self, lines: &mut DebugLines);
+}
+
+#[cfg(feature = "rapier")]
+mod rapier {
+    use super::*;
+    use nalgebra as na;
+    use rapier3d::pipeline::{
+        DebugColor, DebugRenderBackend, DebugRenderMode, DebugRenderObject, DebugRenderPipeline,
+        DebugRenderStyle,
+    };
+
+    // Rapier reports colors in HSLA.
+    fn hsla_to_rgba([hue, saturation, lightness, alpha]: DebugColor) -> na::Vector4<f32> {
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        let hue = hue / 60.0;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (red, green, blue) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = lightness - chroma * 0.5;
+        na::Vector4::new(red + m, green + m, blue + m, alpha)
+    }
+
+    impl DebugRenderBackend for DebugLines {
+        fn draw_line(
+            &mut self,
+            _object: DebugRenderObject,
+            a: na::Point3<f32>,
+            b: na::Point3<f32>,
+            color: DebugColor,
+        ) {
+            self.line(&a, &b, hsla_to_rgba(color));
+        }
+    }
+
+    // Renders collider wireframes, joints and contacts of a rapier world.
+    pub struct RapierDebugDraw<'a> {
+        pub pipeline: &'a mut DebugRenderPipeline,
+        pub bodies: &'a rapier3d::prelude::RigidBodySet,
+        pub colliders: &'a rapier3d::prelude::ColliderSet,
+        pub impulse_joints: &'a rapier3d::prelude::ImpulseJointSet,
+        pub multibody_joints: &'a rapier3d::prelude::MultibodyJointSet,
+        pub narrow_phase: &'a rapier3d::prelude::NarrowPhase,
+    }
+
+    impl RapierDebugDraw<'_> {
+        pub fn default_pipeline() -> DebugRenderPipeline {
+            DebugRenderPipeline::new(
+                DebugRenderStyle::default(),
+                DebugRenderMode::default() | DebugRenderMode::CONTACTS,
+            )
+        }
+    }
+
+    impl DebugDraw for RapierDebugDraw<'_> {
+        fn debug_draw(&mut self, lines: &mut DebugLines) {
+            self.pipeline.render(
+                lines,
+                self.bodies,
+                self.colliders,
+                self.impulse_joints,
+                self.multibody_joints,
+                self.narrow_phase,
+            );
+        }
+    }
+}
+
+#[cfg(feature = "rapier")]
+pub use self::rapier::RapierDebugDraw;
@@ -0,0 +1,458 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::renderer::commands::Commands;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{GraphicsPipelineAttributes, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use nalgebra as na;
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+// Segments of circles and spheres, enough to look round at debugging distances.
+const CIRCLE_SEGMENTS: usize = 32;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugVertex {
+    pub position: na::Vector3<f32>,
+    pub color: na::Vector4<f32>,
+}
+
+// Immediate mode line list, line strips and points, cleared after every rendered frame.
+#[derive(Default)]
+pub struct DebugLines {
+    vertices: Vec<DebugVertex>,
+    // Every strip's vertices one after the other, split by strip_lengths.
+    strips: Vec<DebugVertex>,
+    strip_lengths: Vec<u32>,
+    points: Vec<DebugVertex>,
+}
+
+impl DebugLines {
+    pub fn line(
+        &mut self,
+        start: &na::Point3<f32>,
+        end: &na::Point3<f32>,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        self.vertices.extend([
+            DebugVertex {
+                position: start.coords,
+                color,
+            },
+            DebugVertex {
+                position: end.coords,
+                color,
+            },
+        ]);
+        self
+    }
+
+    // Connects each point to the next one, e.g. a path or a plotted curve. Drawn as a line strip,
+    // one vertex per point instead of two per segment.
+    pub fn line_strip(&mut self, points: &[na::Point3<f32>], color: na::Vector4<f32>) -> &mut Self {
+        if points.len() < 2 {
+            return self;
+        }
+        self.strips.extend(points.iter().map(|point| DebugVertex {
+            position: point.coords,
+            color,
+        }));
+        self.strip_lengths.push(points.len() as u32);
+        self
+    }
+
+    // Drawn as a square of the renderer's point size in pixels, e.g. for point clouds.
+    pub fn point(&mut self, position: &na::Point3<f32>, color: na::Vector4<f32>) -> &mut Self {
+        self.points.push(DebugVertex {
+            position: position.coords,
+            color,
+        });
+        self
+    }
+
+    pub fn points(&mut self, positions: &[na::Point3<f32>], color: na::Vector4<f32>) -> &mut Self {
+        for position in positions {
+            self.point(position, color);
+        }
+        self
+    }
+
+    pub fn cross(
+        &mut self,
+        center: &na::Point3<f32>,
+        size: f32,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        let half_size = size * 0.5;
+        for axis in [na::Vector3::x(), na::Vector3::y(), na::Vector3::z()] {
+            self.line(
+                &(center - axis * half_size),
+                &(center + axis * half_size),
+                color,
+            );
+        }
+        self
+    }
+
+    // Draws the edges of the volume that projects to clip space, with OpenGL style depth in [-1, 1]
+    // as produced by nalgebra's projections.
+    pub fn frustum(
+        &mut self,
+        inverse_view_projection: &na::Matrix4<f32>,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        let corners = [
+            [-1.0, -1.0, -1.0],
+            [1.0, -1.0, -1.0],
+            [1.0, 1.0, -1.0],
+            [-1.0, 1.0, -1.0],
+            [-1.0, -1.0, 1.0],
+            [1.0, -1.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [-1.0, 1.0, 1.0],
+        ]
+        .map(|[x, y, z]| inverse_view_projection.transform_point(&na::Point3::new(x, y, z)));
+        self.box_edges(&corners, color)
+    }
+
+    pub fn aabb(
+        &mut self,
+        min: &na::Point3<f32>,
+        max: &na::Point3<f32>,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        self.oriented_box(&na::Matrix4::identity(), min, max, color)
+    }
+
+    // A box given in the transform's space, e.g. a mesh's bounds placed by its instance.
+    pub fn oriented_box(
+        &mut self,
+        transform: &na::Matrix4<f32>,
+        min: &na::Point3<f32>,
+        max: &na::Point3<f32>,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        let corners = [
+            [min.x, min.y, min.z],
+            [max.x, min.y, min.z],
+            [max.x, max.y, min.z],
+            [min.x, max.y, min.z],
+            [min.x, min.y, max.z],
+            [max.x, min.y, max.z],
+            [max.x, max.y, max.z],
+            [min.x, max.y, max.z],
+        ]
+        .map(|[x, y, z]| transform.transform_point(&na::Point3::new(x, y, z)));
+        self.box_edges(&corners, color)
+    }
+
+    pub fn circle(
+        &mut self,
+        center: &na::Point3<f32>,
+        normal: &na::Vector3<f32>,
+        radius: f32,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        let normal = normal.normalize();
+        // Any direction that isn't parallel to the normal spans the circle's plane with it.
+        let helper = if normal.x.abs() < 0.9 {
+            na::Vector3::x()
+        } else {
+            na::Vector3::y()
+        };
+        let tangent = normal.cross(&helper).normalize() * radius;
+        let bitangent = normal.cross(&tangent);
+        let point = |index: usize| {
+            let angle = index as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+            center + tangent * angle.cos() + bitangent * angle.sin()
+        };
+        for index in 0..CIRCLE_SEGMENTS {
+            self.line(&point(index), &point(index + 1), color);
+        }
+        self
+    }
+
+    // Outlined by its three great circles around the axes.
+    pub fn sphere(
+        &mut self,
+        center: &na::Point3<f32>,
+        radius: f32,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        for axis in [na::Vector3::x(), na::Vector3::y(), na::Vector3::z()] {
+            self.circle(center, &axis, radius, color);
+        }
+        self
+    }
+
+    // The transform's x, y and z axes in red, green and blue, scaled by size.
+    pub fn axis(&mut self, transform: &na::Matrix4<f32>, size: f32) -> &mut Self {
+        let origin = transform.transform_point(&na::Point3::origin());
+        for (axis, color) in [
+            (na::Vector3::x(), na::Vector4::new(1.0, 0.2, 0.2, 1.0)),
+            (na::Vector3::y(), na::Vector4::new(0.2, 1.0, 0.2, 1.0)),
+            (na::Vector3::z(), na::Vector4::new(0.2, 0.4, 1.0, 1.0)),
+        ] {
+            self.line(
+                &origin,
+                &(origin + transform.transform_vector(&axis) * size),
+                color,
+            );
+        }
+        self
+    }
+
+    // Corners of two faces in matching order, each going around its face.
+    fn box_edges(&mut self, corners: &[na::Point3<f32>; 8], color: na::Vector4<f32>) -> &mut Self {
+        for i in 0..4 {
+            self.line(&corners[i], &corners[(i + 1) % 4], color)
+                .line(&corners[i + 4], &corners[(i + 1) % 4 + 4], color)
+                .line(&corners[i], &corners[i + 4], color);
+        }
+        self
+    }
+
+    pub fn vertices(&self) -> &[DebugVertex] {
+        &self.vertices
+    }
+
+    pub fn strip_vertices(&self) -> &[DebugVertex] {
+        &self.strips
+    }
+
+    pub fn strip_lengths(&self) -> &[u32] {
+        &self.strip_lengths
+    }
+
+    pub fn point_vertices(&self) -> &[DebugVertex] {
+        &self.points
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty() && self.strips.is_empty() && self.points.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.strips.clear();
+        self.strip_lengths.clear();
+        self.points.clear();
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugPushConstants {
+    vertex_buffer_address: vk::DeviceAddress,
+    camera_buffer_address: vk::DeviceAddress,
+    point_size: f32,
+    padding: u32,
+}
+
+pub struct DebugLineRendererAttributes {
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    pub depth_format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+    pub buffering: usize,
+}
+
+pub struct DebugLineRenderer {
+    context: Arc<RenderingContext>,
+    pipeline: vk::Pipeline,
+    point_pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    // One buffer per frame in flight, grown on demand once that frame's fence has been waited on.
+    // Lines come first, followed by the line strips and the points.
+    vertex_buffers: Vec<Option<Buffer>>,
+    vertex_counts: Vec<u32>,
+    strip_lengths: Vec<Vec<u32>>,
+    point_counts: Vec<u32>,
+    pub line_width: f32,
+    pub point_size: f32,
+}
+
+impl DebugLineRenderer {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        attributes: DebugLineRendererAttributes,
+    ) -> Result<Self> {
+        let vertex_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "debug_line.vert.spv",
+        )?;
+        let fragment_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "debug_line.frag.spv",
+        )?;
+
+        unsafe {
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&[
+                    vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                        .offset(0)
+                        .size(size_of::<DebugPushConstants>() as u32),
+                ]),
+                None,
+            )?;
+
+            let pipeline_attributes = |topology| GraphicsPipelineAttributes {
+                vertex_shader,
+                fragment_shader,
+                extent: attributes.extent,
+                color_format: attributes.format,
+                depth_format: attributes.depth_format,
+                samples: attributes.samples,
+                topology,
+                line_width: 1.0,
+                dynamic_line_width: topology == vk::PrimitiveTopology::LINE_LIST,
+                // Lines and line strips share a pipeline.
+                dynamic_topology: topology == vk::PrimitiveTopology::LINE_LIST,
+                alpha_blending: true,
+                additive_blending: false,
+                depth_write: true,
+                cull_mode: vk::CullModeFlags::NONE,
+                viewport_count: 1,
+                vertex_layout: Default::default(),
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            };
+            let pipeline = context
+                .create_graphics_pipeline(pipeline_attributes(vk::PrimitiveTopology::LINE_LIST))?;
+            let point_pipeline = context
+                .create_graphics_pipeline(pipeline_attributes(vk::PrimitiveTopology::POINT_LIST))?;
+
+            context.device.destroy_shader_module(vertex_shader, None);
+            context.device.destroy_shader_module(fragment_shader, None);
+
+            Ok(Self {
+                context,
+                pipeline,
+                point_pipeline,
+                pipeline_layout,
+                vertex_buffers: (0..attributes.buffering).map(|_| None).collect(),
+                vertex_counts: vec![0; attributes.buffering],
+                strip_lengths: vec![Vec::new(); attributes.buffering],
+                point_counts: vec![0; attributes.buffering],
+                line_width: 1.0,
+                point_size: 1.0,
+            })
+        }
+    }
+
+    pub fn upload(
+        &mut self,
+        allocator: &mut Allocator,
+        frame_index: usize,
+        lines: &DebugLines,
+    ) -> Result<()> {
+        let vertices = lines.vertices();
+        let strips = lines.strip_vertices();
+        let points = lines.point_vertices();
+        self.vertex_counts[frame_index] = vertices.len() as u32;
+        self.strip_lengths[frame_index].clear();
+        self.strip_lengths[frame_index].extend_from_slice(lines.strip_lengths());
+        self.point_counts[frame_index] = points.len() as u32;
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let size =
+            (size_of_val(vertices) + size_of_val(strips) + size_of_val(points)) as vk::DeviceSize;
+        let vertex_buffer = &mut self.vertex_buffers[frame_index];
+        if vertex_buffer
+            .as_ref()
+            .is_some_and(|vertex_buffer| vertex_buffer.attributes.size < size)
+        {
+            vertex_buffer.take().unwrap().destroy(allocator)?;
+        }
+
+        let vertex_buffer = match vertex_buffer {
+            Some(vertex_buffer) => vertex_buffer,
+            None => vertex_buffer.insert(Buffer::new(
+                allocator,
+                BufferAttributes {
+                    name: "debug_vertex_buffer".into(),
+                    context: self.context.clone(),
+                    size: size.next_power_of_two(),
+                    usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                    location: MemoryLocation::CpuToGpu,
+                    allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                    allocation_priority: 1.0,
+                },
+            )?),
+        };
+        vertex_buffer.write(vertices, 0)?;
+        vertex_buffer.write(strips, size_of_val(vertices) as vk::DeviceSize)?;
+        vertex_buffer.write(
+            points,
+            (size_of_val(vertices) + size_of_val(strips)) as vk::DeviceSize,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn draw(
+        &self,
+        commands: &Commands,
+        frame_index: usize,
+        camera_buffer_address: vk::DeviceAddress,
+    ) {
+        let vertex_count = self.vertex_counts[frame_index];
+        let strip_lengths = &self.strip_lengths[frame_index];
+        let point_count = self.point_counts[frame_index];
+        let Some(vertex_buffer) = &self.vertex_buffers[frame_index] else {
+            return;
+        };
+        let push_constants = DebugPushConstants {
+            vertex_buffer_address: vertex_buffer.address,
+            camera_buffer_address,
+            point_size: self.context.clamp_point_size(self.point_size),
+            padding: 0,
+        };
+
+        if vertex_count > 0 || !strip_lengths.is_empty() {
+            commands
+                .bind_pipeline(self.pipeline)
+                .set_line_width(self.context.clamp_line_width(self.line_width))
+                .set_push_constants(self.pipeline_layout, push_constants)
+                .draw_with_topology(vk::PrimitiveTopology::LINE_LIST, 0..vertex_count, 0..1);
+        }
+        let mut first_vertex = vertex_count;
+        for &length in strip_lengths {
+            commands.draw_with_topology(
+                vk::PrimitiveTopology::LINE_STRIP,
+                first_vertex..first_vertex + length,
+                0..1,
+            );
+            first_vertex += length;
+        }
+        if point_count > 0 {
+            commands
+                .bind_pipeline(self.point_pipeline)
+                .set_push_constants(self.pipeline_layout, push_constants)
+                .draw(first_vertex..first_vertex + point_count, 0..1);
+        }
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for vertex_buffer in self.vertex_buffers.iter_mut().flatten() {
+            vertex_buffer.destroy(allocator)?;
+        }
+        unsafe {
+            self.context.device.destroy_pipeline(self.pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline(self.point_pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::renderer::commands::Commands;
+use crate::rendering_context::{Image, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use std::sync::Arc;
+
+// Bytes per texel of the depth aspect when copied to a buffer.
+pub(crate) fn depth_texel_size(format: vk::Format) -> Result<vk::DeviceSize> {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::D16_UNORM_S8_UINT => Ok(2),
+        vk::Format::D32_SFLOAT
+        | vk::Format::D32_SFLOAT_S8_UINT
+        | vk::Format::X8_D24_UNORM_PACK32
+        | vk::Format::D24_UNORM_S8_UINT => Ok(4),
+        _ => Err(anyhow::anyhow!(
+            "Depth readback of {format:?} is not supported"
+        )),
+    }
+}
+
+pub(crate) fn decode_depths(format: vk::Format, bytes: &[u8]) -> Vec<f32> {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::D16_UNORM_S8_UINT => bytes
+            .chunks_exact(2)
+            .map(|texel| u16::from_ne_bytes([texel[0], texel[1]]) as f32 / u16::MAX as f32)
+            .collect(),
+        // 24 bit depth is copied into the low bits of 32 bit texels.
+        vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D24_UNORM_S8_UINT => bytes
+            .chunks_exact(4)
+            .map(|texel| {
+                let bits = u32::from_ne_bytes([texel[0], texel[1], texel[2], texel[3]]);
+                (bits & 0x00FF_FFFF) as f32 / 0x00FF_FFFF as f32
+            })
+            .collect(),
+        _ => bytes
+            .chunks_exact(4)
+            .map(|texel| f32::from_ne_bytes([texel[0], texel[1], texel[2], texel[3]]))
+            .collect(),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DepthImage {
+    pub extent: vk::Extent2D,
+    // Row major, in [0, 1] with 1 at the far plane.
+    pub depths: Vec<f32>,
+}
+
+// Copies depth buffers, or parts of them, back to the CPU, e.g. for picking from depth or
+// depth screenshots. The buffer grows to the largest region read so far.
+pub struct DepthReadback {
+    context: Arc<RenderingContext>,
+    buffer: Option<Buffer>,
+}
+
+impl DepthReadback {
+    pub fn new(context: Arc<RenderingContext>) -> Self {
+        Self {
+            context,
+            buffer: None,
+        }
+    }
+
+    fn buffer(&mut self, allocator: &mut Allocator, size: vk::DeviceSize) -> Result<&Buffer> {
+        if self
+            .buffer
+            .as_ref()
+            .is_some_and(|buffer| buffer.attributes.size < size)
+        {
+            self.buffer.take().unwrap().destroy(allocator)?;
+        }
+
+        if self.buffer.is_none() {
+            self.buffer = Some(Buffer::new(
+                allocator,
+                BufferAttributes {
+                    name: "depth_readback_buffer".into(),
+                    context: self.context.clone(),
+                    size,
+                    usage: vk::BufferUsageFlags::TRANSFER_DST,
+                    location: MemoryLocation::GpuToCpu,
+                    allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                    allocation_priority: 0.5,
+                },
+            )?);
+        }
+
+        Ok(self.buffer.as_ref().unwrap())
+    }
+
+    // Reads the region right away and waits for it, the image must be single sampled.
+    pub fn read(
+        &mut self,
+        allocator: &mut Allocator,
+        image: &mut Image,
+        region: vk::Rect2D,
+    ) -> Result<DepthImage> {
+        let format = image.attributes.format;
+        let size = region.extent.width as vk::DeviceSize
+            * region.extent.height as vk::DeviceSize
+            * depth_texel_size(format)?;
+        let context = self.context.clone();
+        let buffer = self.buffer(allocator, size)?;
+
+        context.immediate_submit(|commands: &Commands| {
+            commands
+                .copy_image_region_to_buffer(image, region, buffer, 0)
+                .buffer_memory_barrier(
+                    buffer,
+                    (
+                        vk::PipelineStageFlags2::COPY,
+                        vk::AccessFlags2::TRANSFER_WRITE,
+                    ),
+                    (vk::PipelineStageFlags2::HOST, vk::AccessFlags2::HOST_READ),
+                );
+            Ok(())
+        })?;
+
+        Ok(DepthImage {
+            extent: region.extent,
+            depths: decode_depths(format, &buffer.read::<u8>()?[..size as usize]),
+        })
+    }
+
+    pub fn read_all(&mut self, allocator: &mut Allocator, image: &mut Image) -> Result<DepthImage> {
+        let extent = image.attributes.extent;
+        self.read(
+            allocator,
+            image,
+            vk::Rect2D::default().extent(vk::Extent2D {
+                width: extent.width,
+                height: extent.height,
+            }),
+        )
+    }
+
+    pub fn read_pixel(
+        &mut self,
+        allocator: &mut Allocator,
+        image: &mut Image,
+        x: u32,
+        y: u32,
+    ) -> Result<f32> {
+        let region = vk::Rect2D {
+            offset: vk::Offset2D {
+                x: x as i32,
+                y: y as i32,
+            },
+            extent: vk::Extent2D {
+                width: 1,
+                height: 1,
+            },
+        };
+        Ok(self.read(allocator, image, region)?.depths[0])
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        if let Some(mut buffer) = self.buffer.take() {
+            buffer.destroy(allocator)?;
+        }
+        Ok(())
+    }
+}
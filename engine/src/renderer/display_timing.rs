@@ -0,0 +1,110 @@
+use crate::time::Clock;
+use ash::vk;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Predicts when a window's next frame reaches the display from the refresh cycle and the last
+// present the display timing extension reported. Times are in the presentation engine's clock.
+#[derive(Debug, Default)]
+pub(crate) struct PresentSchedule {
+    refresh_duration: Option<Duration>,
+    refresh_divisor: u32,
+    // Present id and actual presentation time of the latest reported present.
+    last_present: Option<(u64, Duration)>,
+    next_present: u64,
+}
+
+impl PresentSchedule {
+    // None until a refresh cycle and a present have been reported.
+    pub fn predicted_present_time(&self) -> Option<Duration> {
+        let refresh_duration = self.refresh_duration?;
+        let (present, present_time) = self.last_present?;
+        let refreshes = (self.next_present - present) * self.refresh_divisor.max(1) as u64;
+        Some(present_time + refresh_duration * refreshes as u32)
+    }
+
+    pub fn refresh_duration(&self) -> Option<Duration> {
+        self.refresh_duration
+    }
+
+    pub fn set_refresh_duration(&mut self, refresh_duration: Option<Duration>) {
+        self.refresh_duration = refresh_duration;
+    }
+
+    pub fn set_refresh_divisor(&mut self, refresh_divisor: u32) {
+        self.refresh_divisor = refresh_divisor;
+    }
+
+    // Asks for the next present at the predicted time, so it's shown when its animation was
+    // sampled for even when it was rendered early.
+    pub fn present_time(&self) -> vk::PresentTimeGOOGLE {
+        vk::PresentTimeGOOGLE::default()
+            .present_id(self.next_present as u32)
+            .desired_present_time(
+                self.predicted_present_time()
+                    .map_or(0, |time| time.as_nanos() as u64),
+            )
+    }
+
+    pub fn presented(&mut self, timings: &[vk::PastPresentationTimingGOOGLE]) {
+        // Present ids are the lower bits of the present count.
+        let present_id_base = self.next_present & !(u32::MAX as u64);
+        for timing in timings {
+            let mut present = present_id_base | timing.present_id as u64;
+            if present > self.next_present {
+                present = present.saturating_sub(1 << 32);
+            }
+            if self.last_present.is_none_or(|(last, _)| present > last) {
+                self.last_present =
+                    Some((present, Duration::from_nanos(timing.actual_present_time)));
+            }
+        }
+        self.next_present += 1;
+    }
+
+    pub fn next_present(&self) -> u64 {
+        self.next_present
+    }
+}
+
+// Samples animations at the time the frame is predicted to reach the display instead of when
+// it's recorded, so uneven CPU frame times don't show up as stutter in smooth motion. Follows
+// real time until the window's first presents were reported, see
+// WindowRenderer::display_timing_clock.
+pub struct DisplayTimingClock {
+    schedule: Arc<Mutex<PresentSchedule>>,
+    start: Instant,
+    // Elapsed time and predicted present time when predictions became available, later
+    // predictions are relative to it.
+    origin: Option<(Duration, Duration)>,
+}
+
+impl DisplayTimingClock {
+    pub(crate) fn new(schedule: Arc<Mutex<PresentSchedule>>) -> Self {
+        Self {
+            schedule,
+            start: Instant::now(),
+            origin: None,
+        }
+    }
+}
+
+impl Clock for DisplayTimingClock {
+    fn elapsed(&mut self, _frame_index: u64) -> Duration {
+        let predicted_present_time = self.schedule.lock().unwrap().predicted_present_time();
+        match (predicted_present_time, self.origin) {
+            (Some(present_time), Some((elapsed, origin_present_time))) => {
+                elapsed + present_time.saturating_sub(origin_present_time)
+            }
+            (Some(present_time), None) => {
+                let elapsed = self.start.elapsed();
+                self.origin = Some((elapsed, present_time));
+                elapsed
+            }
+            (None, _) => {
+                self.origin = None;
+                self.start.elapsed()
+            }
+        }
+    }
+}
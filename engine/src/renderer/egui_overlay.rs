@@ -0,0 +1,690 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::renderer::commands::Commands;
+use crate::renderer::material::SamplerAttributes;
+use crate::renderer::ui_pass::UI_FORMAT;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{
+    GraphicsPipelineAttributes, Image, ImageLayoutState, RenderingContext,
+};
+use anyhow::Result;
+use ash::vk;
+use egui::epaint::{ImageDelta, Primitive};
+use egui::{ClippedPrimitive, ImageData, TextureFilter, TextureId, TextureOptions};
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use nalgebra as na;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Instant;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::Key;
+
+// Textures alive at once, egui needs a few for fonts and whatever images the UI shows.
+const MAX_TEXTURES: u32 = 256;
+
+// Sampled as is, egui blends in gamma space.
+const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct EguiPushConstants {
+    vertex_buffer_address: vk::DeviceAddress,
+    // In points, which egui's vertices are in.
+    screen_size: na::Vector2<f32>,
+}
+
+struct EguiTexture {
+    image: Image,
+    descriptor_set: vk::DescriptorSet,
+}
+
+// Pixels copied into the frame's staging buffer, recorded before the UI pass.
+struct TextureUpload {
+    texture: TextureId,
+    staging_offset: vk::DeviceSize,
+    offset: vk::Offset3D,
+    extent: vk::Extent3D,
+}
+
+// One egui mesh, its indices already point into the frame's shared vertex buffer.
+struct EguiDraw {
+    texture: TextureId,
+    scissor: vk::Rect2D,
+    indices: Range<u32>,
+}
+
+// Per frame in flight, buffers grow on demand once that frame's fence has been waited on.
+#[derive(Default)]
+struct EguiFrame {
+    staging_buffer: Option<Buffer>,
+    vertex_buffer: Option<Buffer>,
+    index_buffer: Option<Buffer>,
+    uploads: Vec<TextureUpload>,
+    draws: Vec<EguiDraw>,
+    screen_size: na::Vector2<f32>,
+}
+
+// Runs an egui UI every frame and draws it inside the UI pass, under the painter's shapes.
+pub(crate) struct EguiOverlay {
+    context: Arc<RenderingContext>,
+    egui_context: egui::Context,
+    raw_input: egui::RawInput,
+    ui: Box<dyn FnMut(&egui::Context)>,
+    start: Instant,
+    // In points, None while the cursor is outside the window.
+    pointer_position: Option<egui::Pos2>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    samplers: HashMap<TextureOptions, vk::Sampler>,
+    textures: HashMap<TextureId, EguiTexture>,
+    // egui frees textures after the frame that last drew them.
+    pending_frees: Vec<TextureId>,
+    // Replaced and freed textures with the frame count from which no frame in flight uses them.
+    retired_textures: Vec<(EguiTexture, u64)>,
+    frames: Vec<EguiFrame>,
+    frame_count: u64,
+}
+
+impl EguiOverlay {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        buffering: usize,
+        ui: Box<dyn FnMut(&egui::Context)>,
+    ) -> Result<Self> {
+        let vertex_shader =
+            load_shader_module(context.as_ref(), SHADERS_DIR.to_owned() + "egui.vert.spv")?;
+        let fragment_shader =
+            load_shader_module(context.as_ref(), SHADERS_DIR.to_owned() + "egui.frag.spv")?;
+
+        unsafe {
+            let descriptor_set_layout = context.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+                ]),
+                None,
+            )?;
+
+            // Textures come and go with the UI, their sets are freed one by one.
+            let descriptor_pool = context.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+                    .max_sets(MAX_TEXTURES)
+                    .pool_sizes(&[vk::DescriptorPoolSize::default()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(MAX_TEXTURES)]),
+                None,
+            )?;
+
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_set_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                        .offset(0)
+                        .size(size_of::<EguiPushConstants>() as u32)]),
+                None,
+            )?;
+
+            let pipeline = context.create_graphics_pipeline(GraphicsPipelineAttributes {
+                vertex_shader,
+                fragment_shader,
+                extent: vk::Extent2D::default(),
+                color_format: UI_FORMAT,
+                depth_format: vk::Format::UNDEFINED,
+                samples: vk::SampleCountFlags::TYPE_1,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                line_width: 1.0,
+                dynamic_line_width: false,
+                dynamic_topology: false,
+                alpha_blending: true,
+                additive_blending: false,
+                depth_write: false,
+                cull_mode: vk::CullModeFlags::NONE,
+                viewport_count: 1,
+                vertex_layout: Default::default(),
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            })?;
+
+            context.device.destroy_shader_module(vertex_shader, None);
+            context.device.destroy_shader_module(fragment_shader, None);
+
+            Ok(Self {
+                context,
+                egui_context: egui::Context::default(),
+                raw_input: egui::RawInput::default(),
+                ui,
+                start: Instant::now(),
+                pointer_position: None,
+                descriptor_set_layout,
+                descriptor_pool,
+                pipeline_layout,
+                pipeline,
+                samplers: HashMap::new(),
+                textures: HashMap::new(),
+                pending_frees: Vec::new(),
+                retired_textures: Vec::new(),
+                frames: (0..buffering).map(|_| EguiFrame::default()).collect(),
+                frame_count: 0,
+            })
+        }
+    }
+
+    pub fn egui_context(&self) -> &egui::Context {
+        &self.egui_context
+    }
+
+    pub fn set_ui(&mut self, ui: Box<dyn FnMut(&egui::Context)>) {
+        self.ui = ui;
+    }
+
+    // Translates window events into egui's input for the next frame.
+    pub fn handle_event(&mut self, event: &WindowEvent, scale_factor: f64) {
+        let scale_factor = scale_factor as f32;
+        let modifiers = self.raw_input.modifiers;
+        let events = &mut self.raw_input.events;
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = egui::pos2(position.x as f32, position.y as f32) / scale_factor;
+                self.pointer_position = Some(position);
+                events.push(egui::Event::PointerMoved(position));
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.pointer_position = None;
+                events.push(egui::Event::PointerGone);
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                if let (Some(pos), Some(button)) = (self.pointer_position, pointer_button(*button))
+                {
+                    events.push(egui::Event::PointerButton {
+                        pos,
+                        button,
+                        pressed: *state == ElementState::Pressed,
+                        modifiers,
+                    });
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (unit, delta) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => {
+                        (egui::MouseWheelUnit::Line, egui::vec2(*x, *y))
+                    }
+                    MouseScrollDelta::PixelDelta(position) => (
+                        egui::MouseWheelUnit::Point,
+                        egui::vec2(position.x as f32, position.y as f32) / scale_factor,
+                    ),
+                };
+                events.push(egui::Event::MouseWheel {
+                    unit,
+                    delta,
+                    modifiers,
+                });
+            }
+            WindowEvent::ModifiersChanged(state) => {
+                let state = state.state();
+                let is_mac = cfg!(target_os = "macos");
+                self.raw_input.modifiers = egui::Modifiers {
+                    alt: state.alt_key(),
+                    ctrl: state.control_key(),
+                    shift: state.shift_key(),
+                    mac_cmd: is_mac && state.super_key(),
+                    command: if is_mac {
+                        state.super_key()
+                    } else {
+                        state.control_key()
+                    },
+                };
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                let pressed = event.state == ElementState::Pressed;
+                if let Some(key) = egui_key(&event.logical_key) {
+                    events.push(egui::Event::Key {
+                        key,
+                        physical_key: None,
+                        pressed,
+                        repeat: event.repeat,
+                        modifiers,
+                    });
+                }
+                // Characters typed while holding command are shortcuts, not text.
+                if let Some(text) = event
+                    .text
+                    .as_ref()
+                    .filter(|_| pressed && !modifiers.command)
+                {
+                    if text.chars().all(|character| !character.is_control()) {
+                        events.push(egui::Event::Text(text.to_string()));
+                    }
+                }
+            }
+            WindowEvent::Focused(focused) => {
+                self.raw_input.focused = *focused;
+                events.push(egui::Event::WindowFocused(*focused));
+            }
+            _ => {}
+        }
+    }
+
+    pub fn wants_pointer_input(&self) -> bool {
+        self.egui_context.wants_pointer_input()
+    }
+
+    pub fn wants_keyboard_input(&self) -> bool {
+        self.egui_context.wants_keyboard_input()
+    }
+
+    // Runs the UI and uploads its meshes and texture changes. Must be called once per submitted
+    // frame, after the frame's fence has been waited on.
+    pub fn prepare(
+        &mut self,
+        allocator: &mut Allocator,
+        frame_index: usize,
+        extent: vk::Extent2D,
+        scale_factor: f64,
+    ) -> Result<()> {
+        self.collect_retired(allocator)?;
+        let buffering = self.frames.len() as u64;
+        for texture in std::mem::take(&mut self.pending_frees) {
+            if let Some(texture) = self.textures.remove(&texture) {
+                self.retired_textures
+                    .push((texture, self.frame_count + buffering));
+            }
+        }
+
+        let screen_size =
+            egui::vec2(extent.width as f32, extent.height as f32) / scale_factor as f32;
+        self.raw_input.screen_rect = Some(egui::Rect::from_min_size(egui::Pos2::ZERO, screen_size));
+        self.raw_input
+            .viewports
+            .entry(egui::ViewportId::ROOT)
+            .or_default()
+            .native_pixels_per_point = Some(scale_factor as f32);
+        self.raw_input.time = Some(self.start.elapsed().as_secs_f64());
+
+        let ui = &mut self.ui;
+        let output = self
+            .egui_context
+            .run(self.raw_input.take(), |context| ui(context));
+
+        let mut staging_data = Vec::<u8>::new();
+        let mut uploads = Vec::new();
+        for (texture, delta) in &output.textures_delta.set {
+            uploads.push(self.set_texture(allocator, *texture, delta, &mut staging_data)?);
+        }
+        self.pending_frees = output.textures_delta.free;
+
+        let primitives = self
+            .egui_context
+            .tessellate(output.shapes, output.pixels_per_point);
+        let (vertices, indices, draws) = batch(&primitives, output.pixels_per_point, extent);
+
+        let frame = &mut self.frames[frame_index];
+        for (buffer, name, usage, data) in [
+            (
+                &mut frame.staging_buffer,
+                "egui_staging_buffer",
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                staging_data.as_slice(),
+            ),
+            (
+                &mut frame.vertex_buffer,
+                "egui_vertex_buffer",
+                vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                bytemuck::cast_slice(&vertices),
+            ),
+            (
+                &mut frame.index_buffer,
+                "egui_index_buffer",
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                bytemuck::cast_slice(&indices),
+            ),
+        ] {
+            write_buffer(&self.context, allocator, buffer, name, usage, data)?;
+        }
+        frame.uploads = uploads;
+        frame.draws = draws;
+        frame.screen_size = na::Vector2::new(screen_size.x, screen_size.y);
+
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    // Copies the frame's texture changes, outside of any rendering.
+    pub fn record_uploads(&mut self, commands: &Commands, frame_index: usize) {
+        let frame = &self.frames[frame_index];
+        let Some(staging_buffer) = &frame.staging_buffer else {
+            return;
+        };
+        for upload in &frame.uploads {
+            let Some(texture) = self.textures.get_mut(&upload.texture) else {
+                continue;
+            };
+            commands
+                .copy_buffer_to_image_region(
+                    staging_buffer,
+                    &mut texture.image,
+                    upload.staging_offset,
+                    upload.offset,
+                    upload.extent,
+                )
+                .ensure_image_layout(&mut texture.image, ImageLayoutState::shader_read());
+        }
+    }
+
+    pub fn has_draws(&self, frame_index: usize) -> bool {
+        !self.frames[frame_index].draws.is_empty()
+    }
+
+    // Restores the scissor to the whole extent for whatever is drawn after.
+    pub fn draw(&self, commands: &Commands, frame_index: usize, extent: vk::Extent2D) {
+        let frame = &self.frames[frame_index];
+        let (Some(vertex_buffer), Some(index_buffer)) = (&frame.vertex_buffer, &frame.index_buffer)
+        else {
+            return;
+        };
+        if frame.draws.is_empty() {
+            return;
+        }
+
+        commands
+            .bind_pipeline(self.pipeline)
+            .bind_index_buffer(index_buffer)
+            .set_push_constants(
+                self.pipeline_layout,
+                EguiPushConstants {
+                    vertex_buffer_address: vertex_buffer.address,
+                    screen_size: frame.screen_size,
+                },
+            );
+        for draw in &frame.draws {
+            // User textures aren't managed here and are skipped.
+            let Some(texture) = self.textures.get(&draw.texture) else {
+                continue;
+            };
+            commands
+                .bind_descriptor_sets(self.pipeline_layout, &[texture.descriptor_set])
+                .set_scissor(draw.scissor)
+                .draw_indexed(draw.indices.clone(), 0..1);
+        }
+        commands.set_scissor(vk::Rect2D::default().extent(extent));
+    }
+
+    // Creates the texture for whole images, or patches the existing one.
+    fn set_texture(
+        &mut self,
+        allocator: &mut Allocator,
+        id: TextureId,
+        delta: &ImageDelta,
+        staging_data: &mut Vec<u8>,
+    ) -> Result<TextureUpload> {
+        let [width, height] = delta.image.size().map(|size| size as u32);
+        let upload = TextureUpload {
+            texture: id,
+            staging_offset: staging_data.len() as vk::DeviceSize,
+            offset: delta
+                .pos
+                .map_or(vk::Offset3D::default(), |[x, y]| vk::Offset3D {
+                    x: x as i32,
+                    y: y as i32,
+                    z: 0,
+                }),
+            extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+        };
+        match &delta.image {
+            ImageData::Color(image) => {
+                staging_data.extend_from_slice(bytemuck::cast_slice(&image.pixels))
+            }
+            ImageData::Font(image) => {
+                staging_data.extend(image.srgba_pixels(None).flat_map(|pixel| pixel.to_array()))
+            }
+        }
+
+        if delta.pos.is_none() {
+            let image = Image::new_texture(
+                self.context.clone(),
+                allocator,
+                "egui_texture",
+                vk::Extent2D { width, height },
+                TEXTURE_FORMAT,
+                1,
+            )?;
+            let sampler = self.sampler(delta.options)?;
+            let descriptor_set = unsafe {
+                let descriptor_set = self.context.device.allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::default()
+                        .descriptor_pool(self.descriptor_pool)
+                        .set_layouts(&[self.descriptor_set_layout]),
+                )?[0];
+                self.context.device.update_descriptor_sets(
+                    &[vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&[vk::DescriptorImageInfo::default()
+                            .sampler(sampler)
+                            .image_view(image.view)
+                            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)])],
+                    &[],
+                );
+                descriptor_set
+            };
+            if let Some(texture) = self.textures.insert(
+                id,
+                EguiTexture {
+                    image,
+                    descriptor_set,
+                },
+            ) {
+                self.retired_textures
+                    .push((texture, self.frame_count + self.frames.len() as u64));
+            }
+        }
+        Ok(upload)
+    }
+
+    // Minification and mipmaps are ignored, textures have a single level.
+    fn sampler(&mut self, options: TextureOptions) -> Result<vk::Sampler> {
+        if let Some(&sampler) = self.samplers.get(&options) {
+            return Ok(sampler);
+        }
+        let attributes = SamplerAttributes {
+            filter: match options.magnification {
+                TextureFilter::Nearest => vk::Filter::NEAREST,
+                TextureFilter::Linear => vk::Filter::LINEAR,
+            },
+            address_mode: match options.wrap_mode {
+                egui::TextureWrapMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                egui::TextureWrapMode::Repeat => vk::SamplerAddressMode::REPEAT,
+                egui::TextureWrapMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+            },
+        };
+        let sampler = unsafe {
+            self.context
+                .device
+                .create_sampler(&attributes.create_info(), None)?
+        };
+        self.samplers.insert(options, sampler);
+        Ok(sampler)
+    }
+
+    fn collect_retired(&mut self, allocator: &mut Allocator) -> Result<()> {
+        let frame_count = self.frame_count;
+        let (retired, kept) = std::mem::take(&mut self.retired_textures)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, retire_frame)| *retire_frame <= frame_count);
+        self.retired_textures = kept;
+        for (texture, _) in retired {
+            self.destroy_texture(allocator, texture)?;
+        }
+        Ok(())
+    }
+
+    fn destroy_texture(&self, allocator: &mut Allocator, mut texture: EguiTexture) -> Result<()> {
+        unsafe {
+            self.context
+                .device
+                .free_descriptor_sets(self.descriptor_pool, &[texture.descriptor_set])?;
+        }
+        texture.image.destroy(allocator)
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        let textures = self
+            .textures
+            .drain()
+            .map(|(_, texture)| texture)
+            .chain(self.retired_textures.drain(..).map(|(texture, _)| texture))
+            .collect::<Vec<_>>();
+        for texture in textures {
+            self.destroy_texture(allocator, texture)?;
+        }
+        for frame in &mut self.frames {
+            for buffer in [
+                &mut frame.staging_buffer,
+                &mut frame.vertex_buffer,
+                &mut frame.index_buffer,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                buffer.destroy(allocator)?;
+            }
+        }
+
+        unsafe {
+            for (_, sampler) in self.samplers.drain() {
+                self.context.device.destroy_sampler(sampler, None);
+            }
+            self.context.device.destroy_pipeline(self.pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        Ok(())
+    }
+}
+
+// Gathers the meshes into one vertex and index buffer, with a scissor from each clip rectangle.
+fn batch(
+    primitives: &[ClippedPrimitive],
+    pixels_per_point: f32,
+    extent: vk::Extent2D,
+) -> (Vec<egui::epaint::Vertex>, Vec<u32>, Vec<EguiDraw>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut draws = Vec::new();
+    for ClippedPrimitive {
+        clip_rect,
+        primitive,
+    } in primitives
+    {
+        // Paint callbacks would need access to the frame's commands, they aren't supported.
+        let Primitive::Mesh(mesh) = primitive else {
+            continue;
+        };
+        let clip_min = (clip_rect.min.to_vec2() * pixels_per_point).round();
+        let clip_max = (clip_rect.max.to_vec2() * pixels_per_point).round();
+        let min_x = clip_min.x.clamp(0.0, extent.width as f32) as u32;
+        let min_y = clip_min.y.clamp(0.0, extent.height as f32) as u32;
+        let max_x = clip_max.x.clamp(min_x as f32, extent.width as f32) as u32;
+        let max_y = clip_max.y.clamp(min_y as f32, extent.height as f32) as u32;
+        if mesh.indices.is_empty() || max_x == min_x || max_y == min_y {
+            continue;
+        }
+
+        let first_vertex = vertices.len() as u32;
+        let first_index = indices.len() as u32;
+        vertices.extend_from_slice(&mesh.vertices);
+        indices.extend(mesh.indices.iter().map(|index| first_vertex + index));
+        draws.push(EguiDraw {
+            texture: mesh.texture_id,
+            scissor: vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: min_x as i32,
+                    y: min_y as i32,
+                },
+                extent: vk::Extent2D {
+                    width: max_x - min_x,
+                    height: max_y - min_y,
+                },
+            },
+            indices: first_index..indices.len() as u32,
+        });
+    }
+    (vertices, indices, draws)
+}
+
+// Grows the buffer when the data doesn't fit, the frame's previous use must have finished.
+fn write_buffer(
+    context: &Arc<RenderingContext>,
+    allocator: &mut Allocator,
+    buffer: &mut Option<Buffer>,
+    name: &str,
+    usage: vk::BufferUsageFlags,
+    data: &[u8],
+) -> Result<()> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    let size = data.len() as vk::DeviceSize;
+    if buffer
+        .as_ref()
+        .is_some_and(|buffer| buffer.attributes.size < size)
+    {
+        buffer.take().unwrap().destroy(allocator)?;
+    }
+
+    let buffer = match buffer {
+        Some(buffer) => buffer,
+        None => buffer.insert(Buffer::new(
+            allocator,
+            BufferAttributes {
+                name: name.into(),
+                context: context.clone(),
+                size: size.next_power_of_two(),
+                usage,
+                location: MemoryLocation::CpuToGpu,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                allocation_priority: 1.0,
+            },
+        )?),
+    };
+    buffer.write(data, 0)
+}
+
+fn pointer_button(button: MouseButton) -> Option<egui::PointerButton> {
+    match button {
+        MouseButton::Left => Some(egui::PointerButton::Primary),
+        MouseButton::Right => Some(egui::PointerButton::Secondary),
+        MouseButton::Middle => Some(egui::PointerButton::Middle),
+        MouseButton::Back => Some(egui::PointerButton::Extra1),
+        MouseButton::Forward => Some(egui::PointerButton::Extra2),
+        MouseButton::Other(_) => None,
+    }
+}
+
+// egui names its keys like winit's named keys, and letter keys like the characters they type.
+fn egui_key(key: &Key) -> Option<egui::Key> {
+    match key {
+        Key::Named(named) => egui::Key::from_name(&format!("{named:?}")),
+        Key::Character(text) => egui::Key::from_name(&text.to_uppercase()),
+        _ => None,
+    }
+}
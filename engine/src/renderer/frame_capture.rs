@@ -0,0 +1,232 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::renderer::commands::Commands;
+use crate::rendering_context::{Image, RenderingContext};
+use anyhow::{anyhow, Result};
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use tracing::error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureFormat {
+    Png,
+    // Tightly packed 8 bit sRGB RGBA rows, the resolution is part of the file name.
+    Raw,
+}
+
+// Presented frames are stored as sRGB like the swapchain.
+const CAPTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+struct CapturedFrame {
+    index: u64,
+    extent: vk::Extent2D,
+    pixels: Vec<u8>,
+}
+
+struct CaptureSlot {
+    image: Image,
+    buffer: Buffer,
+    pending_frame: Option<u64>,
+}
+
+impl CaptureSlot {
+    fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        let image = Image::new_readback_target(
+            context.clone(),
+            allocator,
+            "capture_image",
+            extent,
+            CAPTURE_FORMAT,
+        )?;
+        let buffer = Buffer::new(
+            allocator,
+            BufferAttributes {
+                name: "capture_buffer".into(),
+                context,
+                size: (extent.width * extent.height * 4) as vk::DeviceSize,
+                usage: vk::BufferUsageFlags::TRANSFER_DST,
+                location: MemoryLocation::GpuToCpu,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                allocation_priority: 1.0,
+            },
+        )?;
+        Ok(Self {
+            image,
+            buffer,
+            pending_frame: None,
+        })
+    }
+
+    fn extent(&self) -> vk::Extent2D {
+        vk::Extent2D {
+            width: self.image.attributes.extent.width,
+            height: self.image.attributes.extent.height,
+        }
+    }
+
+    fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        self.image.destroy(allocator)?;
+        self.buffer.destroy(allocator)
+    }
+}
+
+fn save_frame(directory: &Path, format: CaptureFormat, frame: CapturedFrame) -> Result<()> {
+    match format {
+        CaptureFormat::Png => {
+            let path = directory.join(format!("frame_{:06}.png", frame.index));
+            ::image::save_buffer(
+                path,
+                &frame.pixels,
+                frame.extent.width,
+                frame.extent.height,
+                ::image::ExtendedColorType::Rgba8,
+            )?;
+        }
+        CaptureFormat::Raw => {
+            let path = directory.join(format!(
+                "frame_{:06}_{}x{}.rgba",
+                frame.index, frame.extent.width, frame.extent.height
+            ));
+            std::fs::write(path, &frame.pixels)?;
+        }
+    }
+    Ok(())
+}
+
+// Copies every presented frame into a per frame in flight readback buffer; the buffer is read
+// once that frame's fence has been waited on again and encoded on a separate thread.
+pub struct FrameCapture {
+    context: Arc<RenderingContext>,
+    slots: Vec<Option<CaptureSlot>>,
+    next_frame: u64,
+    sender: Option<mpsc::Sender<CapturedFrame>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl FrameCapture {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        directory: impl Into<PathBuf>,
+        format: CaptureFormat,
+        in_flight_frames_count: usize,
+    ) -> Result<Self> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)?;
+
+        let (sender, receiver) = mpsc::channel::<CapturedFrame>();
+        let writer = std::thread::spawn(move || {
+            for frame in receiver {
+                let index = frame.index;
+                if let Err(error) = save_frame(&directory, format, frame) {
+                    error!("Failed to save captured frame {index}: {error}");
+                }
+            }
+        });
+
+        Ok(Self {
+            context,
+            slots: (0..in_flight_frames_count).map(|_| None).collect(),
+            next_frame: 0,
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    // Must be called after the frame's fence has been waited on.
+    pub fn collect(&mut self, frame_index: usize) -> Result<()> {
+        let Some(slot) = &mut self.slots[frame_index] else {
+            return Ok(());
+        };
+        let Some(index) = slot.pending_frame.take() else {
+            return Ok(());
+        };
+
+        let frame = CapturedFrame {
+            index,
+            extent: slot.extent(),
+            pixels: slot.buffer.read::<u8>()?.to_vec(),
+        };
+        if let Some(sender) = &self.sender {
+            sender.send(frame)?;
+        }
+        Ok(())
+    }
+
+    // Must be called after the frame's fence has been waited on, before the frame is recorded.
+    pub fn prepare(
+        &mut self,
+        allocator: &mut Allocator,
+        frame_index: usize,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        let slot = &mut self.slots[frame_index];
+        if slot.as_ref().is_some_and(|slot| slot.extent() != extent) {
+            slot.take().unwrap().destroy(allocator)?;
+        }
+        if slot.is_none() {
+            *slot = Some(CaptureSlot::new(self.context.clone(), allocator, extent)?);
+        }
+        Ok(())
+    }
+
+    pub fn record(
+        &mut self,
+        commands: &Commands,
+        frame_index: usize,
+        source: &mut Image,
+    ) -> Result<()> {
+        let slot = self.slots[frame_index]
+            .as_mut()
+            .ok_or_else(|| anyhow!("The capture of frame {frame_index} wasn't prepared"))?;
+
+        slot.image.reset_layout();
+        commands
+            .blit_full_image(source, &mut slot.image, vk::Filter::NEAREST)
+            .copy_image_to_buffer(&mut slot.image, &slot.buffer, 0)
+            .buffer_memory_barrier(
+                &slot.buffer,
+                (
+                    vk::PipelineStageFlags2::TRANSFER,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                ),
+                (vk::PipelineStageFlags2::HOST, vk::AccessFlags2::HOST_READ),
+            );
+
+        slot.pending_frame = Some(self.next_frame);
+        self.next_frame += 1;
+        Ok(())
+    }
+
+    pub fn captured_frames(&self) -> u64 {
+        self.next_frame
+    }
+
+    // Must be called once the device is idle, blocks until the captured frames are written.
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for frame_index in 0..self.slots.len() {
+            if let Err(error) = self.collect(frame_index) {
+                error!("Failed to collect captured frame: {error}");
+            }
+        }
+
+        for slot in self.slots.iter_mut().flatten() {
+            slot.destroy(allocator)?;
+        }
+
+        drop(self.sender.take());
+        if let Some(writer) = self.writer.take() {
+            writer
+                .join()
+                .map_err(|_| anyhow!("The capture writer thread panicked"))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use ash::vk;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+#[derive(Clone, Debug)]
+pub struct ResourceAccess {
+    pub resource: String,
+    // Layout images are used in, None for buffers.
+    pub layout: Option<vk::ImageLayout>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FramePass {
+    pub name: String,
+    pub reads: Vec<ResourceAccess>,
+    pub writes: Vec<ResourceAccess>,
+}
+
+fn accesses(accesses: &[(&str, Option<vk::ImageLayout>)]) -> Vec<ResourceAccess> {
+    accesses
+        .iter()
+        .map(|&(resource, layout)| ResourceAccess {
+            resource: resource.to_owned(),
+            layout,
+        })
+        .collect()
+}
+
+// Passes of the last recorded frame in submission order with the resources they read and write.
+// Only recorded in debug builds.
+#[derive(Clone, Debug, Default)]
+pub struct FrameGraph {
+    frame: u64,
+    passes: Vec<FramePass>,
+}
+
+impl FrameGraph {
+    pub fn begin_frame(&mut self, frame: u64) {
+        self.frame = frame;
+        self.passes.clear();
+    }
+
+    pub fn add_pass(
+        &mut self,
+        name: &str,
+        reads: &[(&str, Option<vk::ImageLayout>)],
+        writes: &[(&str, Option<vk::ImageLayout>)],
+    ) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        self.passes.push(FramePass {
+            name: name.to_owned(),
+            reads: accesses(reads),
+            writes: accesses(writes),
+        });
+    }
+
+    // Passes recorded by a render graph.
+    pub fn add_passes(&mut self, passes: Vec<FramePass>) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        self.passes.extend(passes);
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn passes(&self) -> &[FramePass] {
+        &self.passes
+    }
+
+    // Graphviz DOT with passes as boxes and resources as ellipses. Every write creates a new
+    // version of the resource so the graph stays acyclic; edges are labeled with image layouts.
+    pub fn to_dot(&self) -> String {
+        let mut versions = HashMap::<&str, u32>::new();
+        let mut resource_nodes = String::new();
+        let mut pass_nodes = String::new();
+        let mut edges = String::new();
+
+        let label = |access: &ResourceAccess| {
+            access
+                .layout
+                .map(|layout| format!(" [label=\"{layout:?}\"]"))
+                .unwrap_or_default()
+        };
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            let _ = writeln!(
+                pass_nodes,
+                "    pass_{index} [label=\"{}\", shape=box, style=filled, fillcolor=lightblue];",
+                pass.name
+            );
+
+            for read in &pass.reads {
+                let version = *versions.entry(&read.resource).or_insert_with(|| {
+                    let _ = writeln!(
+                        resource_nodes,
+                        "    \"{0}_0\" [label=\"{0}\", shape=ellipse];",
+                        read.resource
+                    );
+                    0
+                });
+                let _ = writeln!(
+                    edges,
+                    "    \"{}_{version}\" -> pass_{index}{};",
+                    read.resource,
+                    label(read)
+                );
+            }
+
+            for write in &pass.writes {
+                let version = versions
+                    .entry(&write.resource)
+                    .and_modify(|version| *version += 1)
+                    .or_insert(0);
+                let _ = writeln!(
+                    resource_nodes,
+                    "    \"{0}_{version}\" [label=\"{0} v{version}\", shape=ellipse];",
+                    write.resource
+                );
+                let _ = writeln!(
+                    edges,
+                    "    pass_{index} -> \"{}_{version}\"{};",
+                    write.resource,
+                    label(write)
+                );
+            }
+        }
+
+        format!(
+            "digraph frame_{} {{\n    rankdir=LR;\n{pass_nodes}{resource_nodes}{edges}}}\n",
+            self.frame
+        )
+    }
+}
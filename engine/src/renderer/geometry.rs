@@ -1,81 +1,624 @@
 use crate::buffer::{Buffer, BufferAttributes};
-use crate::rendering_context::RenderingContext;
-use anyhow::Result;
+use crate::renderer::mesh_validation::MeshError;
+use crate::rendering_context::{
+    RenderingContext, VertexAttribute, VertexBufferLayout, VertexLayout,
+};
+use anyhow::{anyhow, Result};
 use ash::vk;
 use gpu_allocator::vulkan::{AllocationScheme, Allocator};
 use gpu_allocator::MemoryLocation;
 use nalgebra as na;
+use std::collections::HashMap;
 use std::fmt;
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tobj::GPU_LOAD_OPTIONS;
+use tracing::warn;
 
 type VertexIndex = u32;
 
+// Faces of loaded meshes without normals stay smooth up to this angle.
+pub(crate) const DEFAULT_SMOOTHING_ANGLE: f32 = std::f32::consts::PI / 3.0;
+
+// Returned by Renderer::add_mesh, the renderer's built-in model is MeshHandle::DEFAULT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshHandle(pub(crate) usize);
+
+impl MeshHandle {
+    pub const DEFAULT: Self = Self(0);
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
     pub position: na::Vector3<f32>,
     pub normal: na::Vector3<f32>,
     pub tex_coord: na::Vector2<f32>,
+    pub lightmap_tex_coord: na::Vector2<f32>,
+    // Average unoccluded direction and the unoccluded fraction of the hemisphere, see
+    // Geometry::bake_ambient_occlusion. The normal and 1 until baked.
+    pub bent_normal: na::Vector3<f32>,
+    pub ambient_occlusion: f32,
+    // Direction of increasing u in the surface plane, with the bitangent's handedness in w so
+    // bitangent = w * cross(normal, tangent.xyz), see Geometry::generate_tangents.
+    pub tangent: na::Vector4<f32>,
+}
+
+impl Vertex {
+    // Matches the inputs of shader_vertex_input.vert.
+    pub fn layout() -> VertexLayout {
+        let attribute = |location, format, offset| VertexAttribute {
+            location,
+            format,
+            offset: offset as u32,
+        };
+        VertexLayout {
+            buffers: vec![VertexBufferLayout {
+                stride: size_of::<Self>() as u32,
+                input_rate: vk::VertexInputRate::VERTEX,
+                attributes: vec![
+                    attribute(
+                        0,
+                        vk::Format::R32G32B32_SFLOAT,
+                        std::mem::offset_of!(Self, position),
+                    ),
+                    attribute(
+                        1,
+                        vk::Format::R32G32B32_SFLOAT,
+                        std::mem::offset_of!(Self, normal),
+                    ),
+                    attribute(
+                        2,
+                        vk::Format::R32G32_SFLOAT,
+                        std::mem::offset_of!(Self, tex_coord),
+                    ),
+                    attribute(
+                        3,
+                        vk::Format::R32G32_SFLOAT,
+                        std::mem::offset_of!(Self, lightmap_tex_coord),
+                    ),
+                    attribute(
+                        4,
+                        vk::Format::R32G32B32_SFLOAT,
+                        std::mem::offset_of!(Self, bent_normal),
+                    ),
+                    attribute(
+                        5,
+                        vk::Format::R32_SFLOAT,
+                        std::mem::offset_of!(Self, ambient_occlusion),
+                    ),
+                    attribute(
+                        6,
+                        vk::Format::R32G32B32A32_SFLOAT,
+                        std::mem::offset_of!(Self, tangent),
+                    ),
+                ],
+            }],
+        }
+    }
+}
+
+// Positions are 16 bit snorm relative to the mesh bounds and normals are octahedral encoded
+// 16 bit snorm pairs, packed like GLSL's packSnorm2x16; texture coordinates stay full precision.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct QuantizedVertex {
+    pub position: [u32; 2],
+    pub normal: u32,
+    pub tex_coord: na::Vector2<f32>,
+    pub lightmap_tex_coord: na::Vector2<f32>,
+    // Octahedral bent normal in two snorm bytes, then the ambient occlusion as an unorm byte.
+    pub occlusion: u32,
+    // Octahedral tangent in two snorm bytes, then the handedness as a snorm byte.
+    pub tangent: u32,
+}
+
+// Maps quantized positions in [-1, 1] back to model space: offset + position * scale.
+#[derive(Debug, Clone, Copy)]
+pub struct PositionDequantization {
+    pub offset: na::Vector3<f32>,
+    pub scale: na::Vector3<f32>,
+}
+
+fn pack_snorm_2x16(x: f32, y: f32) -> u32 {
+    let snorm = |value: f32| (value.clamp(-1.0, 1.0) * 32767.0).round() as i16 as u16 as u32;
+    snorm(x) | (snorm(y) << 16)
+}
+
+fn pack_occlusion(bent_normal: &na::Vector3<f32>, ambient_occlusion: f32) -> u32 {
+    let snorm = |value: f32| (value.clamp(-1.0, 1.0) * 127.0).round() as i8 as u8 as u32;
+    let bent_normal = octahedral_encode(bent_normal);
+    snorm(bent_normal.x)
+        | (snorm(bent_normal.y) << 8)
+        | (((ambient_occlusion.clamp(0.0, 1.0) * 255.0).round() as u32) << 16)
+}
+
+fn pack_tangent(tangent: &na::Vector4<f32>) -> u32 {
+    let snorm = |value: f32| (value.clamp(-1.0, 1.0) * 127.0).round() as i8 as u8 as u32;
+    let direction = octahedral_encode(&tangent.xyz());
+    snorm(direction.x)
+        | (snorm(direction.y) << 8)
+        | (snorm(if tangent.w < 0.0 { -1.0 } else { 1.0 }) << 16)
+}
+
+fn octahedral_encode(normal: &na::Vector3<f32>) -> na::Vector2<f32> {
+    let sign = |value: f32| if value >= 0.0 { 1.0 } else { -1.0 };
+    let normal = normal / (normal.x.abs() + normal.y.abs() + normal.z.abs()).max(f32::EPSILON);
+    if normal.z >= 0.0 {
+        normal.xy()
+    } else {
+        na::Vector2::new(
+            (1.0 - normal.y.abs()) * sign(normal.x),
+            (1.0 - normal.x.abs()) * sign(normal.y),
+        )
+    }
+}
+
+fn quantize_vertex(vertex: &Vertex, dequantization: &PositionDequantization) -> QuantizedVertex {
+    let position = (vertex.position - dequantization.offset).component_div(&dequantization.scale);
+    let normal = octahedral_encode(&vertex.normal);
+    QuantizedVertex {
+        position: [
+            pack_snorm_2x16(position.x, position.y),
+            pack_snorm_2x16(position.z, 0.0),
+        ],
+        normal: pack_snorm_2x16(normal.x, normal.y),
+        tex_coord: vertex.tex_coord,
+        lightmap_tex_coord: vertex.lightmap_tex_coord,
+        occlusion: pack_occlusion(&vertex.bent_normal, vertex.ambient_occlusion),
+        tangent: pack_tangent(&vertex.tangent),
+    }
+}
+
+// Joint indices are relative to the instance's joint matrices.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinVertex {
+    pub joints: [u32; 4],
+    pub weights: na::Vector4<f32>,
 }
 
 pub struct Geometry {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<VertexIndex>,
+    pub skin_vertices: Vec<SkinVertex>,
+    // Empty for geometry that's a single part.
+    pub submeshes: Vec<Submesh>,
+}
+
+// Part of a geometry with its own material, e.g. one model of an OBJ file.
+#[derive(Clone, Debug)]
+pub struct Submesh {
+    pub name: String,
+    pub indices: Range<u32>,
+    // Into the materials loaded along with the geometry, None when the file doesn't give one.
+    pub material: Option<usize>,
+}
+
+// What the material system uses of a .mtl material.
+#[derive(Clone, Debug)]
+pub struct ObjMaterial {
+    pub name: String,
+    pub diffuse: na::Vector3<f32>,
+    pub dissolve: f32,
+    // Resolved against the directory of the OBJ file.
+    pub diffuse_texture: Option<PathBuf>,
+    // From map_Bump, resolved like the diffuse texture.
+    pub normal_texture: Option<PathBuf>,
 }
 
 pub struct GPUGeometry {
     pub geometry: Geometry,
+    // Replaces geometry.vertices on the GPU when vertex quantization is enabled.
+    pub quantized_vertices: Vec<QuantizedVertex>,
+    pub dequantization: Option<PositionDequantization>,
     pub vertex_buffer: Buffer,
     pub index_buffer: Buffer,
+    pub skin_buffer: Option<Buffer>,
+    // Object space bounding box as minimum and maximum corner.
+    pub bounds: (na::Vector3<f32>, na::Vector3<f32>),
+    // Vertices changed by update_vertices that haven't been staged yet.
+    pub(crate) dirty_vertices: Option<Range<usize>>,
 }
 
 impl GPUGeometry {
+    // Bytes uploaded through the staging belt.
+    pub fn size(&self) -> usize {
+        match self.dequantization {
+            Some(_) => {
+                self.geometry.size() - self.geometry.vertices_size()
+                    + self.quantized_vertices.len() * size_of::<QuantizedVertex>()
+            }
+            None => self.geometry.size(),
+        }
+    }
+
+    fn vertex_size(&self) -> usize {
+        match self.dequantization {
+            Some(_) => size_of::<QuantizedVertex>(),
+            None => size_of::<Vertex>(),
+        }
+    }
+
+    // Replaces the vertices in the range, the GPU copy is updated through the staging belt of
+    // the next rendered frame. The bounds only grow, so culling stays conservative; quantized
+    // positions are clamped to the bounds the mesh was created with.
+    pub fn update_vertices(&mut self, range: Range<usize>, vertices: &[Vertex]) -> Result<()> {
+        if range.end > self.geometry.vertices.len() || range.len() != vertices.len() {
+            return Err(anyhow!(
+                "Can't write {} vertices to {range:?} of a mesh with {} vertices",
+                vertices.len(),
+                self.geometry.vertices.len()
+            ));
+        }
+        if range.is_empty() {
+            return Ok(());
+        }
+        if let Some(vertex) = vertices.iter().position(|vertex| !vertex.is_finite()) {
+            return Err(MeshError::NonFiniteVertex {
+                vertex: range.start + vertex,
+            }
+            .into());
+        }
+
+        self.geometry.vertices[range.clone()].copy_from_slice(vertices);
+        match &self.dequantization {
+            Some(dequantization) => {
+                for (quantized, vertex) in self.quantized_vertices[range.clone()]
+                    .iter_mut()
+                    .zip(vertices)
+                {
+                    *quantized = quantize_vertex(vertex, dequantization);
+                }
+            }
+            None => {
+                let (min, max) = &mut self.bounds;
+                for vertex in vertices {
+                    *min = min.inf(&vertex.position);
+                    *max = max.sup(&vertex.position);
+                }
+            }
+        }
+        self.dirty_vertices = Some(match self.dirty_vertices.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+        Ok(())
+    }
+
+    // Bytes of the vertices waiting to be staged.
+    pub(crate) fn dirty_vertices_size(&self) -> usize {
+        self.dirty_vertices
+            .as_ref()
+            .map_or(0, |range| range.len() * self.vertex_size())
+    }
+
     pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
         self.index_buffer.destroy(allocator)?;
         self.vertex_buffer.destroy(allocator)?;
+        if let Some(skin_buffer) = &mut self.skin_buffer {
+            skin_buffer.destroy(allocator)?;
+        }
         Ok(())
     }
 }
 
 impl Geometry {
     pub fn new(vertices: Vec<Vertex>, indices: Vec<VertexIndex>) -> Self {
-        Self { vertices, indices }
+        Self {
+            vertices,
+            indices,
+            skin_vertices: Vec::new(),
+            submeshes: Vec::new(),
+        }
     }
 
+    // Every model of the file becomes a submesh, see load_obj_with_materials for their materials.
     pub fn load_obj(path: impl AsRef<Path> + fmt::Debug) -> Result<Self> {
         let (models, _materials) = tobj::load_obj(path.as_ref(), &GPU_LOAD_OPTIONS)?;
+        Self::from_obj_models(models)
+    }
+
+    // Also parses the .mtl files the OBJ references, a missing one leaves the submeshes without
+    // materials.
+    pub fn load_obj_with_materials(
+        path: impl AsRef<Path> + fmt::Debug,
+    ) -> Result<(Self, Vec<ObjMaterial>)> {
+        let path = path.as_ref();
+        let (models, materials) = tobj::load_obj(path, &GPU_LOAD_OPTIONS)?;
+        let materials = materials.unwrap_or_else(|error| {
+            warn!("Failed to load the materials of {path:?}: {error}");
+            Vec::new()
+        });
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let materials = materials
+            .into_iter()
+            .map(|material| ObjMaterial {
+                diffuse: material
+                    .diffuse
+                    .map_or(na::Vector3::repeat(1.0), na::Vector3::from),
+                dissolve: material.dissolve.unwrap_or(1.0),
+                diffuse_texture: material
+                    .diffuse_texture
+                    .map(|texture| directory.join(texture.replace('\\', "/"))),
+                normal_texture: material
+                    .normal_texture
+                    .map(|texture| directory.join(texture.replace('\\', "/"))),
+                name: material.name,
+            })
+            .collect();
+        Ok((Self::from_obj_models(models)?, materials))
+    }
 
-        let mesh = models.into_iter().next().unwrap().mesh;
+    fn from_obj_models(models: Vec<tobj::Model>) -> Result<Self> {
+        if models.is_empty() {
+            return Err(anyhow!("OBJ file has no models"));
+        }
 
-        Ok(Self {
-            vertices: mesh
-                .positions
-                .chunks(3)
-                .zip(mesh.normals.chunks(3))
-                .zip(mesh.texcoords.chunks(2))
-                .map(|((position, normal), uv)| Vertex {
+        let mut geometry = Self::new(Vec::new(), Vec::new());
+        for model in models {
+            let mesh = model.mesh;
+            let first_vertex = geometry.vertices.len() as VertexIndex;
+            let first_index = geometry.indices.len() as u32;
+
+            // Files without texture coordinates get zeroed ones and files without normals
+            // generated ones, instead of the missing attribute dropping every vertex.
+            let vertex_count = mesh.positions.len() / 3;
+            let vertices = (0..vertex_count).map(|index| {
+                let position = &mesh.positions[index * 3..index * 3 + 3];
+                let normal = mesh
+                    .normals
+                    .get(index * 3..index * 3 + 3)
+                    .map_or(na::Vector3::zeros(), |normal| {
+                        na::Vector3::new(normal[0], normal[1], normal[2])
+                    });
+                let uv = mesh
+                    .texcoords
+                    .get(index * 2..index * 2 + 2)
+                    .map_or(na::Vector2::zeros(), |uv| na::Vector2::new(uv[0], uv[1]));
+                Vertex {
                     position: na::Vector3::new(position[0], position[1], position[2]),
-                    normal: na::Vector3::new(normal[0], normal[1], normal[2]),
-                    tex_coord: na::Vector2::new(uv[0], uv[1]),
+                    normal,
+                    tex_coord: uv,
+                    lightmap_tex_coord: uv,
+                    bent_normal: normal,
+                    ambient_occlusion: 1.0,
+                    tangent: na::Vector4::zeros(),
+                }
+            });
+            let mut model_geometry = Self::new(vertices.collect(), mesh.indices);
+            if mesh.normals.is_empty() {
+                model_geometry.recompute_normals(DEFAULT_SMOOTHING_ANGLE);
+            } else {
+                model_geometry.generate_tangents();
+            }
+            geometry.vertices.extend(model_geometry.vertices);
+            geometry.indices.extend(
+                model_geometry
+                    .indices
+                    .iter()
+                    .map(|index| first_vertex + index),
+            );
+            geometry.submeshes.push(Submesh {
+                name: model.name,
+                indices: first_index..geometry.indices.len() as u32,
+                material: mesh.material_id,
+            });
+        }
+        Ok(geometry)
+    }
+
+    // Replaces the normals with the area weighted average of the faces around each position,
+    // faces meeting at a larger angle than the threshold in radians keep a hard edge. Zero gives
+    // faceted normals and PI smooths everything. Vertices that end up with several normals are
+    // split, positions shared by separate vertices, e.g. along UV seams, are still smoothed.
+    // Bent normals are reset to the new normals, bake ambient occlusion afterwards. Tangents are
+    // regenerated to stay perpendicular to the new normals.
+    pub fn recompute_normals(&mut self, angle_threshold: f32) {
+        // Cross products, their length weighs the faces by area.
+        let face_normals = self
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                let [a, b, c] =
+                    [0, 1, 2].map(|corner| self.vertices[triangle[corner] as usize].position);
+                (b - a).cross(&(c - a))
+            })
+            .collect::<Vec<_>>();
+        let unit_normals = face_normals
+            .iter()
+            .map(|normal| normal.try_normalize(f32::EPSILON).unwrap_or_default())
+            .collect::<Vec<_>>();
+
+        let position_key = |vertex: &Vertex| vertex.position.map(f32::to_bits);
+        let mut position_faces = HashMap::<_, Vec<usize>>::new();
+        for (index, &vertex) in self.indices.iter().enumerate() {
+            position_faces
+                .entry(position_key(&self.vertices[vertex as usize]))
+                .or_default()
+                .push(index / 3);
+        }
+
+        let min_cos = angle_threshold.cos();
+        // The first normal a vertex gets is kept in place, other ones get a copy of the vertex.
+        let mut assigned = vec![None; self.vertices.len()];
+        let mut copies = HashMap::new();
+        for index in 0..self.indices.len() {
+            let vertex = self.indices[index] as usize;
+            let face = index / 3;
+            let normal = position_faces[&position_key(&self.vertices[vertex])]
+                .iter()
+                .filter(|&&other| {
+                    other == face || unit_normals[face].dot(&unit_normals[other]) >= min_cos
                 })
-                .collect(),
-            indices: mesh.indices,
-        })
+                .map(|&other| face_normals[other])
+                .sum::<na::Vector3<f32>>()
+                .try_normalize(f32::EPSILON)
+                .unwrap_or(unit_normals[face]);
+
+            match assigned[vertex] {
+                None => {
+                    assigned[vertex] = Some(normal);
+                    self.vertices[vertex].normal = normal;
+                    self.vertices[vertex].bent_normal = normal;
+                }
+                Some(assigned_normal) if (assigned_normal - normal).norm() <= 1e-4 => {}
+                Some(_) => {
+                    let copy = *copies
+                        .entry((vertex, normal.map(f32::to_bits)))
+                        .or_insert_with(|| {
+                            self.vertices.push(Vertex {
+                                normal,
+                                bent_normal: normal,
+                                ..self.vertices[vertex]
+                            });
+                            if let Some(&skin_vertex) = self.skin_vertices.get(vertex) {
+                                self.skin_vertices.push(skin_vertex);
+                            }
+                            self.vertices.len() as VertexIndex - 1
+                        });
+                    self.indices[index] = copy;
+                }
+            }
+        }
+        self.generate_tangents();
+    }
+
+    // Tangents along the texture's u axis, the area weighted average of the faces around each
+    // vertex orthogonalized against its normal, which is what normal maps baked in MikkTSpace
+    // expect for all but mirrored seams sharing vertices. Vertices without usable texture
+    // coordinates get an arbitrary tangent perpendicular to the normal.
+    pub fn generate_tangents(&mut self) {
+        let mut tangents = vec![na::Vector3::<f32>::zeros(); self.vertices.len()];
+        let mut bitangents = vec![na::Vector3::<f32>::zeros(); self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|corner| &self.vertices[triangle[corner] as usize]);
+            let (edge1, edge2) = (b.position - a.position, c.position - a.position);
+            let (uv1, uv2) = (b.tex_coord - a.tex_coord, c.tex_coord - a.tex_coord);
+            let determinant = uv1.x * uv2.y - uv2.x * uv1.y;
+            if determinant.abs() <= f32::EPSILON {
+                continue;
+            }
+            let area = edge1.cross(&edge2).norm();
+            let tangent = (edge1 * uv2.y - edge2 * uv1.y) / determinant;
+            let bitangent = (edge2 * uv1.x - edge1 * uv2.x) / determinant;
+            let (Some(tangent), Some(bitangent)) = (
+                tangent.try_normalize(f32::EPSILON),
+                bitangent.try_normalize(f32::EPSILON),
+            ) else {
+                continue;
+            };
+            for &vertex in triangle {
+                tangents[vertex as usize] += tangent * area;
+                bitangents[vertex as usize] += bitangent * area;
+            }
+        }
+
+        for ((vertex, tangent), bitangent) in self.vertices.iter_mut().zip(tangents).zip(bitangents)
+        {
+            let normal = vertex.normal;
+            let tangent = (tangent - normal * normal.dot(&tangent))
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_else(|| {
+                    let axis = if normal.x.abs() < 0.9 {
+                        na::Vector3::x()
+                    } else {
+                        na::Vector3::y()
+                    };
+                    (axis - normal * normal.dot(&axis))
+                        .try_normalize(f32::EPSILON)
+                        .unwrap_or(axis)
+                });
+            let handedness = if normal.cross(&tangent).dot(&bitangent) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            vertex.tangent = tangent.push(handedness);
+        }
+    }
+
+    // The submesh as a geometry of its own, with only the vertices its triangles use.
+    pub fn submesh(&self, submesh: usize) -> Self {
+        let indices = self.submeshes[submesh].indices.clone();
+        let mut geometry = Self::new(Vec::new(), Vec::new());
+        let mut remapped = HashMap::new();
+        for &index in &self.indices[indices.start as usize..indices.end as usize] {
+            let new_index = *remapped.entry(index).or_insert_with(|| {
+                geometry.vertices.push(self.vertices[index as usize]);
+                if let Some(skin_vertex) = self.skin_vertices.get(index as usize) {
+                    geometry.skin_vertices.push(*skin_vertex);
+                }
+                geometry.vertices.len() as VertexIndex - 1
+            });
+            geometry.indices.push(new_index);
+        }
+        geometry
+    }
+
+    // Minimum and maximum corner of the vertex positions, zero for empty geometry.
+    pub fn bounds(&self) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        if self.vertices.is_empty() {
+            return (na::Vector3::zeros(), na::Vector3::zeros());
+        }
+        self.vertices.iter().fold(
+            (
+                na::Vector3::repeat(f32::INFINITY),
+                na::Vector3::repeat(f32::NEG_INFINITY),
+            ),
+            |(min, max), vertex| (min.inf(&vertex.position), max.sup(&vertex.position)),
+        )
+    }
+
+    pub fn quantize(&self) -> (Vec<QuantizedVertex>, PositionDequantization) {
+        let (min, max) = self.bounds();
+        let dequantization = if self.vertices.is_empty() {
+            PositionDequantization {
+                offset: na::Vector3::zeros(),
+                scale: na::Vector3::repeat(1.0),
+            }
+        } else {
+            PositionDequantization {
+                offset: (min + max) * 0.5,
+                // Flat meshes would otherwise divide by zero along their flat axis.
+                scale: ((max - min) * 0.5).map(|scale| scale.max(f32::EPSILON)),
+            }
+        };
+
+        let vertices = self
+            .vertices
+            .iter()
+            .map(|vertex| quantize_vertex(vertex, &dequantization))
+            .collect();
+
+        (vertices, dequantization)
     }
 
     pub fn create_gpu_geometry(
         self,
         context: Arc<RenderingContext>,
         allocator: &mut Allocator,
+        quantize: bool,
     ) -> Result<GPUGeometry> {
+        let (quantized_vertices, dequantization) = if quantize {
+            let (vertices, dequantization) = self.quantize();
+            (vertices, Some(dequantization))
+        } else {
+            (Vec::new(), None)
+        };
+        let vertex_size = if quantize {
+            size_of::<QuantizedVertex>()
+        } else {
+            size_of::<Vertex>()
+        };
+
         let vertex_buffer = Buffer::new(
             allocator,
             BufferAttributes {
                 name: "vertex_buffer".into(),
                 context: context.clone(),
-                size: (self.vertices.len() * size_of::<Vertex>()) as vk::DeviceSize,
+                size: (self.vertices.len() * vertex_size) as vk::DeviceSize,
                 usage: vk::BufferUsageFlags::VERTEX_BUFFER
                     | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
                     | vk::BufferUsageFlags::TRANSFER_DST,
@@ -98,15 +641,71 @@ impl Geometry {
             },
         )?;
 
+        let skin_buffer = if self.skin_vertices.is_empty() {
+            None
+        } else {
+            Some(Buffer::new(
+                allocator,
+                BufferAttributes {
+                    name: "skin_buffer".into(),
+                    context: context.clone(),
+                    size: (self.skin_vertices.len() * size_of::<SkinVertex>()) as vk::DeviceSize,
+                    usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                        | vk::BufferUsageFlags::TRANSFER_DST,
+                    location: MemoryLocation::GpuOnly,
+                    allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                    allocation_priority: 1.0,
+                },
+            )?)
+        };
+
+        let bounds = self.bounds();
+
         Ok(GPUGeometry {
+            bounds,
             geometry: self,
+            quantized_vertices,
+            dequantization,
             vertex_buffer,
             index_buffer,
+            skin_buffer,
+            dirty_vertices: None,
         })
     }
 
+    // OBJ files only carry one UV set, so lightmap UVs default to the texture UVs
+    // until a dedicated unwrap is provided here.
+    pub fn set_lightmap_tex_coords(&mut self, tex_coords: &[na::Vector2<f32>]) -> Result<()> {
+        if tex_coords.len() != self.vertices.len() {
+            return Err(anyhow::anyhow!(
+                "Expected {} lightmap texture coordinates, got {}",
+                self.vertices.len(),
+                tex_coords.len()
+            ));
+        }
+        for (vertex, tex_coord) in self.vertices.iter_mut().zip(tex_coords) {
+            vertex.lightmap_tex_coord = *tex_coord;
+        }
+        Ok(())
+    }
+
+    pub fn set_skin_vertices(&mut self, skin_vertices: &[SkinVertex]) -> Result<()> {
+        if skin_vertices.len() != self.vertices.len() {
+            return Err(anyhow::anyhow!(
+                "Expected {} skin vertices, got {}",
+                self.vertices.len(),
+                skin_vertices.len()
+            ));
+        }
+        self.skin_vertices = skin_vertices.to_vec();
+        Ok(())
+    }
+
     pub fn size(&self) -> usize {
-        self.vertices.len() * size_of::<Vertex>() + self.indices.len() * size_of::<VertexIndex>()
+        self.vertices.len() * size_of::<Vertex>()
+            + self.indices.len() * size_of::<VertexIndex>()
+            + self.skin_vertices.len() * size_of::<SkinVertex>()
     }
 
     pub fn vertices_size(&self) -> usize {
@@ -0,0 +1,762 @@
+use crate::renderer::geometry::{Geometry, Submesh, Vertex, DEFAULT_SMOOTHING_ANGLE};
+use crate::renderer::json::Json;
+use crate::renderer::material::SamplerAttributes;
+use crate::renderer::meshopt::{
+    apply_filter, decode_index_buffer, decode_index_sequence, decode_vertex_buffer, MeshoptFilter,
+    MeshoptMode,
+};
+use anyhow::{anyhow, Result};
+use ash::vk;
+use nalgebra as na;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_JSON_CHUNK: u32 = 0x4e4f534a;
+const GLB_BIN_CHUNK: u32 = 0x004e4942;
+
+const COMPONENT_BYTE: usize = 5120;
+const COMPONENT_UNSIGNED_BYTE: usize = 5121;
+const COMPONENT_SHORT: usize = 5122;
+const COMPONENT_UNSIGNED_SHORT: usize = 5123;
+const COMPONENT_UNSIGNED_INT: usize = 5125;
+const COMPONENT_FLOAT: usize = 5126;
+
+const MODE_TRIANGLES: usize = 4;
+
+const FILTER_NEAREST: usize = 9728;
+const WRAP_CLAMP_TO_EDGE: usize = 33071;
+const WRAP_MIRRORED_REPEAT: usize = 33648;
+
+const SUPPORTED_EXTENSIONS: [&str; 3] = [
+    "EXT_meshopt_compression",
+    "KHR_mesh_quantization",
+    "KHR_texture_transform",
+];
+
+// Draco isn't decoded, only files that keep uncompressed fallback data alongside it load.
+const DRACO_EXTENSION: &str = "KHR_draco_mesh_compression";
+
+// What the material system uses of a glTF material.
+#[derive(Clone, Debug)]
+pub struct GltfMaterial {
+    pub name: String,
+    pub base_color_factor: na::Vector4<f32>,
+    // Decoded from the file or the image it points to, None when there's none or it failed to
+    // load.
+    pub base_color_texture: Option<::image::RgbaImage>,
+    pub sampler: SamplerAttributes,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow!("Unexpected end of file at byte {offset}"))
+}
+
+// The JSON chunk and the binary chunk, if there's one.
+fn parse_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>)> {
+    let version = read_u32(bytes, 4)?;
+    if version != 2 {
+        return Err(anyhow!("Unsupported GLB version {version}"));
+    }
+    let mut json = None;
+    let mut binary = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let length = read_u32(bytes, offset)? as usize;
+        let chunk_type = read_u32(bytes, offset + 4)?;
+        let chunk = (offset + 8)
+            .checked_add(length)
+            .and_then(|end| bytes.get(offset + 8..end))
+            .ok_or_else(|| anyhow!("GLB chunk at byte {offset} is out of bounds"))?;
+        match chunk_type {
+            GLB_JSON_CHUNK => json = json.or(Some(chunk)),
+            GLB_BIN_CHUNK => binary = binary.or(Some(chunk)),
+            _ => {}
+        }
+        offset += 8 + length;
+    }
+    Ok((
+        json.ok_or_else(|| anyhow!("GLB file has no JSON chunk"))?,
+        binary,
+    ))
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for character in text.bytes().take_while(|&character| character != b'=') {
+        let value = match character {
+            b'A'..=b'Z' => character - b'A',
+            b'a'..=b'z' => character - b'a' + 26,
+            b'0'..=b'9' => character - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(anyhow!("Invalid base64 character {:?}", character as char)),
+        };
+        bits = bits << 6 | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+// Relative URIs are percent encoded, e.g. spaces in file names become %20.
+fn decode_uri(uri: &str) -> String {
+    let mut bytes = Vec::with_capacity(uri.len());
+    let mut rest = uri.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+        match escaped {
+            Some(escaped) => {
+                bytes.push(escaped);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn component_size(component_type: usize) -> Result<usize> {
+    match component_type {
+        COMPONENT_BYTE | COMPONENT_UNSIGNED_BYTE => Ok(1),
+        COMPONENT_SHORT | COMPONENT_UNSIGNED_SHORT => Ok(2),
+        COMPONENT_UNSIGNED_INT | COMPONENT_FLOAT => Ok(4),
+        _ => Err(anyhow!("Unknown accessor component type {component_type}")),
+    }
+}
+
+fn component_count(accessor_type: &str) -> Result<usize> {
+    match accessor_type {
+        "SCALAR" => Ok(1),
+        "VEC2" => Ok(2),
+        "VEC3" => Ok(3),
+        "VEC4" | "MAT2" => Ok(4),
+        "MAT3" => Ok(9),
+        "MAT4" => Ok(16),
+        _ => Err(anyhow!("Unknown accessor type {accessor_type:?}")),
+    }
+}
+
+// Normalized integers map to [0, 1] when unsigned and [-1, 1] when signed.
+fn read_component(bytes: &[u8], component_type: usize, normalized: bool) -> f32 {
+    let (value, max) = match component_type {
+        COMPONENT_BYTE => (bytes[0] as i8 as f32, i8::MAX as f32),
+        COMPONENT_UNSIGNED_BYTE => (bytes[0] as f32, u8::MAX as f32),
+        COMPONENT_SHORT => (
+            i16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+            i16::MAX as f32,
+        ),
+        COMPONENT_UNSIGNED_SHORT => (
+            u16::from_le_bytes([bytes[0], bytes[1]]) as f32,
+            u16::MAX as f32,
+        ),
+        COMPONENT_UNSIGNED_INT => (
+            u32::from_le_bytes(bytes[..4].try_into().unwrap()) as f32,
+            1.0,
+        ),
+        _ => return f32::from_le_bytes(bytes[..4].try_into().unwrap()),
+    };
+    if normalized {
+        (value / max).max(-1.0)
+    } else {
+        value
+    }
+}
+
+fn json_floats<const N: usize>(value: &Json, default: [f32; N]) -> [f32; N] {
+    let values = value.as_slice();
+    if values.len() != N {
+        return default;
+    }
+    std::array::from_fn(|index| values[index].as_f64().unwrap_or(default[index] as f64) as f32)
+}
+
+fn node_transform(node: &Json) -> na::Matrix4<f32> {
+    // Column major like nalgebra's.
+    let matrix = node.get("matrix").as_slice();
+    if matrix.len() == 16 {
+        return na::Matrix4::from_iterator(
+            matrix
+                .iter()
+                .map(|value| value.as_f64().unwrap_or(0.0) as f32),
+        );
+    }
+    let [x, y, z] = json_floats(node.get("translation"), [0.0; 3]);
+    let [rotation_x, rotation_y, rotation_z, rotation_w] =
+        json_floats(node.get("rotation"), [0.0, 0.0, 0.0, 1.0]);
+    let scale = json_floats(node.get("scale"), [1.0; 3]);
+    na::Matrix4::new_translation(&na::Vector3::new(x, y, z))
+        * na::UnitQuaternion::from_quaternion(na::Quaternion::new(
+            rotation_w, rotation_x, rotation_y, rotation_z,
+        ))
+        .to_homogeneous()
+        * na::Matrix4::new_nonuniform_scaling(&na::Vector3::from(scale))
+}
+
+// From KHR_texture_transform, which quantized files use to rescale their texture coordinates.
+fn texture_transform(texture_info: &Json) -> na::Matrix3<f32> {
+    let transform = texture_info.get("extensions").get("KHR_texture_transform");
+    let [offset_x, offset_y] = json_floats(transform.get("offset"), [0.0; 2]);
+    let [scale_x, scale_y] = json_floats(transform.get("scale"), [1.0; 2]);
+    let rotation = transform.get("rotation").as_f64().unwrap_or(0.0) as f32;
+    let (sin, cos) = rotation.sin_cos();
+    na::Matrix3::new(1.0, 0.0, offset_x, 0.0, 1.0, offset_y, 0.0, 0.0, 1.0)
+        * na::Matrix3::new(cos, sin, 0.0, -sin, cos, 0.0, 0.0, 0.0, 1.0)
+        * na::Matrix3::new_nonuniform_scaling(&na::Vector2::new(scale_x, scale_y))
+}
+
+struct Accessor<'a> {
+    component_type: usize,
+    component_count: usize,
+    normalized: bool,
+    // None when the accessor has no buffer view, which means it's all zeros.
+    elements: Option<Vec<&'a [u8]>>,
+    count: usize,
+}
+
+// The parsed JSON with the contents of every buffer view, meshopt compressed ones decoded.
+struct Document {
+    json: Json,
+    buffer_views: Vec<Vec<u8>>,
+    directory: PathBuf,
+}
+
+impl Document {
+    fn load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let (json, binary) = if bytes.starts_with(GLB_MAGIC) {
+            parse_glb(&bytes)?
+        } else {
+            (bytes.as_slice(), None)
+        };
+        let json = Json::parse(std::str::from_utf8(json)?)?;
+
+        for extension in json.get("extensionsRequired").as_slice() {
+            let extension = extension.as_str().unwrap_or_default();
+            if extension == DRACO_EXTENSION {
+                return Err(anyhow!(
+                    "Draco compressed glTF files aren't supported, \
+                     re-export with meshopt compression instead, e.g. with gltfpack -cc"
+                ));
+            }
+            if !SUPPORTED_EXTENSIONS.contains(&extension) {
+                return Err(anyhow!(
+                    "Required glTF extension {extension} isn't supported"
+                ));
+            }
+        }
+
+        let directory = path.parent().unwrap_or(Path::new("")).to_owned();
+        let buffers = json
+            .get("buffers")
+            .as_slice()
+            .iter()
+            .enumerate()
+            .map(|(index, buffer)| {
+                if let Some(uri) = buffer.get("uri").as_str() {
+                    return Self::load_uri(&directory, uri);
+                }
+                match binary {
+                    Some(binary) if index == 0 => Ok(binary.to_vec()),
+                    // Stands in for the uncompressed data of meshopt compressed views, never read.
+                    _ if !buffer
+                        .get("extensions")
+                        .get("EXT_meshopt_compression")
+                        .is_null() =>
+                    {
+                        Ok(Vec::new())
+                    }
+                    _ => Err(anyhow!("glTF buffer {index} has no data")),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let buffer_views = json
+            .get("bufferViews")
+            .as_slice()
+            .iter()
+            .enumerate()
+            .map(|(index, view)| {
+                Self::load_buffer_view(view, &buffers)
+                    .map_err(|error| anyhow!("glTF buffer view {index}: {error}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            json,
+            buffer_views,
+            directory,
+        })
+    }
+
+    fn load_uri(directory: &Path, uri: &str) -> Result<Vec<u8>> {
+        match uri.strip_prefix("data:") {
+            Some(data) => {
+                let (_, base64) = data
+                    .split_once(";base64,")
+                    .ok_or_else(|| anyhow!("Only base64 data URIs are supported"))?;
+                decode_base64(base64)
+            }
+            None => Ok(std::fs::read(directory.join(decode_uri(uri)))?),
+        }
+    }
+
+    fn load_buffer_view(view: &Json, buffers: &[Vec<u8>]) -> Result<Vec<u8>> {
+        let slice = |description: &Json| -> Result<&[u8]> {
+            let buffer = description
+                .get("buffer")
+                .as_usize()
+                .and_then(|buffer| buffers.get(buffer))
+                .ok_or_else(|| anyhow!("Invalid buffer"))?;
+            let offset = description.get("byteOffset").as_usize().unwrap_or(0);
+            let length = description
+                .get("byteLength")
+                .as_usize()
+                .ok_or_else(|| anyhow!("Missing byte length"))?;
+            offset
+                .checked_add(length)
+                .and_then(|end| buffer.get(offset..end))
+                .ok_or_else(|| anyhow!("Out of its buffer's bounds"))
+        };
+
+        let meshopt = view.get("extensions").get("EXT_meshopt_compression");
+        if meshopt.is_null() {
+            return Ok(slice(view)?.to_vec());
+        }
+        let data = slice(meshopt)?;
+        let count = meshopt
+            .get("count")
+            .as_usize()
+            .ok_or_else(|| anyhow!("Missing meshopt element count"))?;
+        let stride = meshopt
+            .get("byteStride")
+            .as_usize()
+            .ok_or_else(|| anyhow!("Missing meshopt byte stride"))?;
+        let mode = match meshopt.get("mode").as_str() {
+            Some("ATTRIBUTES") => MeshoptMode::Attributes,
+            Some("TRIANGLES") => MeshoptMode::Triangles,
+            Some("INDICES") => MeshoptMode::Indices,
+            mode => return Err(anyhow!("Unknown meshopt mode {mode:?}")),
+        };
+        let filter = match meshopt.get("filter").as_str().unwrap_or("NONE") {
+            "NONE" => MeshoptFilter::None,
+            "OCTAHEDRAL" => MeshoptFilter::Octahedral,
+            "QUATERNION" => MeshoptFilter::Quaternion,
+            "EXPONENTIAL" => MeshoptFilter::Exponential,
+            filter => return Err(anyhow!("Unknown meshopt filter {filter:?}")),
+        };
+
+        let mut decoded = match mode {
+            MeshoptMode::Attributes => decode_vertex_buffer(data, count, stride)?,
+            MeshoptMode::Triangles => decode_index_buffer(data, count, stride)?,
+            MeshoptMode::Indices => decode_index_sequence(data, count, stride)?,
+        };
+        apply_filter(filter, &mut decoded, stride)?;
+        Ok(decoded)
+    }
+
+    fn accessor(&self, index: usize) -> Result<Accessor<'_>> {
+        let accessor = self.json.get("accessors").index(index);
+        if accessor.is_null() {
+            return Err(anyhow!("glTF accessor {index} doesn't exist"));
+        }
+        if !accessor.get("sparse").is_null() {
+            return Err(anyhow!("Sparse glTF accessors aren't supported"));
+        }
+        let component_type = accessor
+            .get("componentType")
+            .as_usize()
+            .ok_or_else(|| anyhow!("glTF accessor {index} has no component type"))?;
+        let component_count = component_count(accessor.get("type").as_str().unwrap_or_default())?;
+        let count = accessor
+            .get("count")
+            .as_usize()
+            .ok_or_else(|| anyhow!("glTF accessor {index} has no count"))?;
+
+        let elements = accessor
+            .get("bufferView")
+            .as_usize()
+            .map(|view_index| -> Result<_> {
+                let view = self
+                    .buffer_views
+                    .get(view_index)
+                    .ok_or_else(|| anyhow!("glTF buffer view {view_index} doesn't exist"))?;
+                let element_size = component_size(component_type)? * component_count;
+                let stride = self
+                    .json
+                    .get("bufferViews")
+                    .index(view_index)
+                    .get("byteStride")
+                    .as_usize()
+                    .unwrap_or(element_size);
+                let offset = accessor.get("byteOffset").as_usize().unwrap_or(0);
+                (0..count)
+                    .map(|element| {
+                        let start = offset + element * stride;
+                        view.get(start..start + element_size).ok_or_else(|| {
+                            anyhow!("glTF accessor {index} is out of its buffer view's bounds")
+                        })
+                    })
+                    .collect()
+            })
+            .transpose()?;
+
+        Ok(Accessor {
+            component_type,
+            component_count,
+            normalized: *accessor.get("normalized") == Json::Bool(true),
+            elements,
+            count,
+        })
+    }
+
+    // The first N components of every element, the rest of N zero filled.
+    fn read_vectors<const N: usize>(&self, index: usize) -> Result<Vec<[f32; N]>> {
+        let accessor = self.accessor(index)?;
+        let component_size = component_size(accessor.component_type)?;
+        let Some(elements) = accessor.elements else {
+            return Ok(vec![[0.0; N]; accessor.count]);
+        };
+        Ok(elements
+            .iter()
+            .map(|element| {
+                std::array::from_fn(|component| {
+                    if component < accessor.component_count {
+                        read_component(
+                            &element[component * component_size..],
+                            accessor.component_type,
+                            accessor.normalized,
+                        )
+                    } else {
+                        0.0
+                    }
+                })
+            })
+            .collect())
+    }
+
+    fn read_indices(&self, index: usize) -> Result<Vec<u32>> {
+        let accessor = self.accessor(index)?;
+        let Some(elements) = accessor.elements else {
+            return Ok(vec![0; accessor.count]);
+        };
+        elements
+            .iter()
+            .map(|element| match accessor.component_type {
+                COMPONENT_UNSIGNED_BYTE => Ok(element[0] as u32),
+                COMPONENT_UNSIGNED_SHORT => Ok(u16::from_le_bytes([element[0], element[1]]) as u32),
+                COMPONENT_UNSIGNED_INT => Ok(u32::from_le_bytes(element[..4].try_into().unwrap())),
+                component_type => Err(anyhow!("Invalid index component type {component_type}")),
+            })
+            .collect()
+    }
+
+    fn load_image(&self, image: &Json) -> Result<::image::RgbaImage> {
+        let bytes = match (
+            image.get("uri").as_str(),
+            image.get("bufferView").as_usize(),
+        ) {
+            (Some(uri), _) => Self::load_uri(&self.directory, uri)?,
+            (None, Some(view)) => self
+                .buffer_views
+                .get(view)
+                .ok_or_else(|| anyhow!("glTF buffer view {view} doesn't exist"))?
+                .clone(),
+            (None, None) => return Err(anyhow!("glTF image has no data")),
+        };
+        Ok(::image::load_from_memory(&bytes)?.into_rgba8())
+    }
+
+    fn sampler(&self, texture: &Json) -> SamplerAttributes {
+        let sampler = self
+            .json
+            .get("samplers")
+            .index(texture.get("sampler").as_usize().unwrap_or(usize::MAX));
+        SamplerAttributes {
+            filter: if sampler.get("magFilter").as_usize() == Some(FILTER_NEAREST) {
+                vk::Filter::NEAREST
+            } else {
+                vk::Filter::LINEAR
+            },
+            address_mode: match sampler.get("wrapS").as_usize() {
+                Some(WRAP_CLAMP_TO_EDGE) => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                Some(WRAP_MIRRORED_REPEAT) => vk::SamplerAddressMode::MIRRORED_REPEAT,
+                _ => vk::SamplerAddressMode::REPEAT,
+            },
+        }
+    }
+
+    // Each material with the transform its texture coordinates need.
+    fn materials(&self) -> Vec<(GltfMaterial, na::Matrix3<f32>)> {
+        self.json
+            .get("materials")
+            .as_slice()
+            .iter()
+            .enumerate()
+            .map(|(index, material)| {
+                let name = material
+                    .get("name")
+                    .as_str()
+                    .map_or_else(|| format!("material_{index}"), str::to_owned);
+                let pbr = material.get("pbrMetallicRoughness");
+                let texture_info = pbr.get("baseColorTexture");
+                let texture = self
+                    .json
+                    .get("textures")
+                    .index(texture_info.get("index").as_usize().unwrap_or(usize::MAX));
+                let base_color_texture = texture
+                    .get("source")
+                    .as_usize()
+                    .map(|source| self.load_image(self.json.get("images").index(source)))
+                    .and_then(|image| {
+                        image
+                            .map_err(|error| {
+                                warn!("Failed to load the base color texture of {name}: {error}")
+                            })
+                            .ok()
+                    });
+                (
+                    GltfMaterial {
+                        base_color_factor: na::Vector4::from(json_floats(
+                            pbr.get("baseColorFactor"),
+                            [1.0; 4],
+                        )),
+                        base_color_texture,
+                        sampler: self.sampler(texture),
+                        name,
+                    },
+                    texture_transform(texture_info),
+                )
+            })
+            .collect()
+    }
+
+    // Nodes of the default scene with their world transform, every root node without scenes.
+    fn mesh_nodes(&self) -> Vec<(usize, na::Matrix4<f32>)> {
+        let nodes = self.json.get("nodes").as_slice();
+        let scenes = self.json.get("scenes");
+        let roots = if scenes.as_slice().is_empty() {
+            let children = nodes
+                .iter()
+                .flat_map(|node| node.get("children").as_slice())
+                .filter_map(Json::as_usize)
+                .collect::<HashSet<_>>();
+            (0..nodes.len())
+                .filter(|node| !children.contains(node))
+                .collect::<Vec<_>>()
+        } else {
+            scenes
+                .index(self.json.get("scene").as_usize().unwrap_or(0))
+                .get("nodes")
+                .as_slice()
+                .iter()
+                .filter_map(Json::as_usize)
+                .collect()
+        };
+
+        let mut mesh_nodes = Vec::new();
+        // Nodes belong to one parent at most, this keeps malformed files from looping.
+        let mut visited = HashSet::new();
+        let mut stack = roots
+            .into_iter()
+            .map(|root| (root, na::Matrix4::identity()))
+            .collect::<Vec<_>>();
+        while let Some((index, parent_transform)) = stack.pop() {
+            let Some(node) = nodes.get(index).filter(|_| visited.insert(index)) else {
+                continue;
+            };
+            let transform = parent_transform * node_transform(node);
+            if let Some(mesh) = node.get("mesh").as_usize() {
+                mesh_nodes.push((mesh, transform));
+            }
+            stack.extend(
+                node.get("children")
+                    .as_slice()
+                    .iter()
+                    .filter_map(Json::as_usize)
+                    .map(|child| (child, transform)),
+            );
+        }
+        mesh_nodes
+    }
+
+    fn primitive_geometry(
+        &self,
+        primitive: &Json,
+        transform: &na::Matrix4<f32>,
+        tex_coord_transform: &na::Matrix3<f32>,
+    ) -> Result<Geometry> {
+        let attributes = primitive.get("attributes");
+        let attribute = |name: &str| attributes.get(name).as_usize();
+        let positions = self.read_vectors::<3>(
+            attribute("POSITION").ok_or_else(|| anyhow!("glTF primitive has no positions"))?,
+        )?;
+        let vertex_count = positions.len();
+        let optional = |name: &str| -> Result<Option<Vec<_>>> {
+            attribute(name)
+                .map(|index| {
+                    let values = self.read_vectors::<4>(index)?;
+                    if values.len() != vertex_count {
+                        return Err(anyhow!("glTF attribute {name} has the wrong length"));
+                    }
+                    Ok(values)
+                })
+                .transpose()
+        };
+        let normals = optional("NORMAL")?;
+        let tangents = optional("TANGENT")?;
+        let tex_coords = optional("TEXCOORD_0")?;
+        let lightmap_tex_coords = optional("TEXCOORD_1")?;
+
+        let normal_transform = transform
+            .fixed_view::<3, 3>(0, 0)
+            .try_inverse()
+            .unwrap_or_else(na::Matrix3::identity)
+            .transpose();
+        let vertices = (0..vertex_count)
+            .map(|index| {
+                let position = transform
+                    .transform_point(&na::Point3::from(positions[index]))
+                    .coords;
+                let normal = normals.as_ref().map_or(na::Vector3::zeros(), |normals| {
+                    (normal_transform * na::Vector4::from(normals[index]).xyz())
+                        .try_normalize(f32::EPSILON)
+                        .unwrap_or_else(na::Vector3::zeros)
+                });
+                let tangent = tangents.as_ref().map_or(na::Vector4::zeros(), |tangents| {
+                    let tangent = na::Vector4::from(tangents[index]);
+                    transform
+                        .transform_vector(&tangent.xyz())
+                        .try_normalize(f32::EPSILON)
+                        .unwrap_or_else(na::Vector3::zeros)
+                        .push(tangent.w)
+                });
+                let tex_coord = |tex_coords: &Option<Vec<[f32; 4]>>| {
+                    tex_coords.as_ref().map(|tex_coords| {
+                        let [u, v, ..] = tex_coords[index];
+                        tex_coord_transform
+                            .transform_point(&na::Point2::new(u, v))
+                            .coords
+                    })
+                };
+                let uv = tex_coord(&tex_coords).unwrap_or_else(na::Vector2::zeros);
+                Vertex {
+                    position,
+                    normal,
+                    tex_coord: uv,
+                    lightmap_tex_coord: tex_coord(&lightmap_tex_coords).unwrap_or(uv),
+                    bent_normal: normal,
+                    ambient_occlusion: 1.0,
+                    tangent,
+                }
+            })
+            .collect();
+
+        let mut indices = match primitive.get("indices").as_usize() {
+            Some(indices) => self.read_indices(indices)?,
+            None => (0..vertex_count as u32).collect(),
+        };
+        if indices.len() % 3 != 0 || indices.iter().any(|&index| index as usize >= vertex_count) {
+            return Err(anyhow!("glTF primitive has invalid indices"));
+        }
+        // Mirroring transforms turn the triangles inside out.
+        if transform.fixed_view::<3, 3>(0, 0).determinant() < 0.0 {
+            for triangle in indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+
+        let mut geometry = Geometry::new(vertices, indices);
+        if normals.is_none() {
+            geometry.recompute_normals(DEFAULT_SMOOTHING_ANGLE);
+        } else if tangents.is_none() {
+            geometry.generate_tangents();
+        }
+        Ok(geometry)
+    }
+}
+
+impl Geometry {
+    // Loads the triangles of the default scene of a .gltf or .glb file, placed by their nodes.
+    // Every primitive becomes a submesh, with its material index into the returned materials.
+    // Meshopt compressed and quantized files, e.g. from gltfpack, are decoded on load.
+    pub fn load_gltf(path: impl AsRef<Path>) -> Result<(Self, Vec<GltfMaterial>)> {
+        let path = path.as_ref();
+        let document = Document::load(path)?;
+        let (materials, tex_coord_transforms): (Vec<_>, Vec<_>) =
+            document.materials().into_iter().unzip();
+
+        let mut geometry = Self::new(Vec::new(), Vec::new());
+        for (mesh_index, transform) in document.mesh_nodes() {
+            let mesh = document.json.get("meshes").index(mesh_index);
+            let name = mesh
+                .get("name")
+                .as_str()
+                .map_or_else(|| format!("mesh_{mesh_index}"), str::to_owned);
+            for primitive in mesh.get("primitives").as_slice() {
+                let mode = primitive.get("mode").as_usize().unwrap_or(MODE_TRIANGLES);
+                if mode != MODE_TRIANGLES {
+                    warn!("Skipping a primitive of {name} in {path:?} with mode {mode}");
+                    continue;
+                }
+                // Without a fallback the positions would silently load as zeros.
+                let draco = !primitive.get("extensions").get(DRACO_EXTENSION).is_null();
+                let position = primitive.get("attributes").get("POSITION").as_usize();
+                if draco
+                    && position.is_none_or(|accessor| {
+                        let accessor = document.json.get("accessors").index(accessor);
+                        accessor.get("bufferView").is_null()
+                    })
+                {
+                    return Err(anyhow!(
+                        "{name} in {path:?} is Draco compressed without a fallback, \
+                         which isn't supported"
+                    ));
+                }
+                let material = primitive.get("material").as_usize();
+                let tex_coord_transform = material
+                    .and_then(|material| tex_coord_transforms.get(material))
+                    .copied()
+                    .unwrap_or_else(na::Matrix3::identity);
+                let primitive_geometry =
+                    document.primitive_geometry(primitive, &transform, &tex_coord_transform)?;
+
+                let first_vertex = geometry.vertices.len() as u32;
+                let first_index = geometry.indices.len() as u32;
+                geometry.vertices.extend(primitive_geometry.vertices);
+                geometry.indices.extend(
+                    primitive_geometry
+                        .indices
+                        .iter()
+                        .map(|index| first_vertex + index),
+                );
+                geometry.submeshes.push(Submesh {
+                    name: name.clone(),
+                    indices: first_index..geometry.indices.len() as u32,
+                    material,
+                });
+            }
+        }
+
+        if geometry.submeshes.is_empty() {
+            return Err(anyhow!("glTF file {path:?} has no triangles"));
+        }
+        Ok((geometry, materials))
+    }
+}
@@ -0,0 +1,89 @@
+use crate::renderer::commands::Commands;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{GraphicsPipelineAttributes, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use std::sync::Arc;
+
+// Infinite ground grid on the y = 0 plane with the X and Z axes highlighted,
+// ray traced per fragment from a full screen quad.
+pub struct GridRenderer {
+    context: Arc<RenderingContext>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl GridRenderer {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Self> {
+        let vertex_shader =
+            load_shader_module(context.as_ref(), SHADERS_DIR.to_owned() + "grid.vert.spv")?;
+        let fragment_shader =
+            load_shader_module(context.as_ref(), SHADERS_DIR.to_owned() + "grid.frag.spv")?;
+
+        unsafe {
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&[
+                    vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                        .offset(0)
+                        .size(size_of::<vk::DeviceAddress>() as u32),
+                ]),
+                None,
+            )?;
+
+            let pipeline = context.create_graphics_pipeline(GraphicsPipelineAttributes {
+                vertex_shader,
+                fragment_shader,
+                extent,
+                color_format: format,
+                depth_format,
+                samples,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                line_width: 1.0,
+                dynamic_line_width: false,
+                dynamic_topology: false,
+                alpha_blending: true,
+                additive_blending: false,
+                depth_write: false,
+                cull_mode: vk::CullModeFlags::NONE,
+                viewport_count: 1,
+                vertex_layout: Default::default(),
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            })?;
+
+            context.device.destroy_shader_module(vertex_shader, None);
+            context.device.destroy_shader_module(fragment_shader, None);
+
+            Ok(Self {
+                context,
+                pipeline,
+                pipeline_layout,
+            })
+        }
+    }
+
+    pub fn draw(&self, commands: &Commands, camera_buffer_address: vk::DeviceAddress) {
+        commands
+            .bind_pipeline(self.pipeline)
+            .set_push_constants(self.pipeline_layout, camera_buffer_address)
+            .draw(0..6, 0..1);
+    }
+}
+
+impl Drop for GridRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device.destroy_pipeline(self.pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use crate::image::{Image, ImageAttributes};
+use crate::renderer::commands::Commands;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{ComputePipelineAttributes, ImageLayoutState, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+use gpu_allocator::MemoryLocation;
+use std::sync::Arc;
+
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const IRRADIANCE_SIZE: u32 = 32;
+const SPECULAR_SIZE: u32 = 128;
+// Roughness goes from 0 at the first level to 1 at the last, the shaders expect it linearly.
+const SPECULAR_MIP_LEVELS: u32 = 6;
+const BRDF_LUT_SIZE: u32 = 256;
+const WORKGROUP_SIZE: u32 = 8;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct PrefilterPushConstants {
+    roughness: f32,
+}
+
+fn image_attributes(size: u32, mip_levels: u32, cube: bool) -> ImageAttributes {
+    ImageAttributes {
+        extent: vk::Extent3D {
+            width: size,
+            height: size,
+            depth: 1,
+        },
+        format: FORMAT,
+        usage: vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::STORAGE
+            | vk::ImageUsageFlags::TRANSFER_DST,
+        location: MemoryLocation::GpuOnly,
+        linear: false,
+        allocation_scheme: gpu_allocator::vulkan::AllocationScheme::GpuAllocatorManaged,
+        subresource_range: vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(mip_levels)
+            .layer_count(if cube { 6 } else { 1 }),
+        allocation_priority: 1.0,
+        samples: vk::SampleCountFlags::TYPE_1,
+        cube,
+    }
+}
+
+// Cube faces of one mip level as an array, compute shaders can't write through cube views.
+pub(crate) unsafe fn create_storage_view(
+    context: &RenderingContext,
+    image: &Image,
+    mip_level: u32,
+) -> Result<vk::ImageView> {
+    Ok(context.device.create_image_view(
+        &vk::ImageViewCreateInfo::default()
+            .image(image.handle)
+            .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+            .format(FORMAT)
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(mip_level)
+                    .level_count(1)
+                    .layer_count(6),
+            ),
+        None,
+    )?)
+}
+
+// Image based ambient lighting from the skybox: a cosine convolved irradiance cube map, a cube
+// map prefiltered with the GGX distribution at increasing roughness down its mip chain and the
+// split sum BRDF lookup table, all computed on the GPU whenever the environment changes.
+pub struct ImageBasedLighting {
+    context: Arc<RenderingContext>,
+    irradiance_map: Image,
+    specular_map: Image,
+    brdf_lut: Image,
+    irradiance_storage_view: vk::ImageView,
+    specular_storage_views: Vec<vk::ImageView>,
+    pub sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    // One per specular mip level and frame in flight, frame major.
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    pipeline_layout: vk::PipelineLayout,
+    irradiance_pipeline: vk::Pipeline,
+    specular_pipeline: vk::Pipeline,
+    brdf_pipeline: vk::Pipeline,
+    // The images are cleared and the lookup table computed with the first frame.
+    is_initialized: bool,
+    has_environment: bool,
+}
+
+impl ImageBasedLighting {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        buffering: usize,
+    ) -> Result<Self> {
+        let irradiance_map = Image::new(
+            context.clone(),
+            allocator,
+            "irradiance_map",
+            image_attributes(IRRADIANCE_SIZE, 1, true),
+        )?;
+        let specular_map = Image::new(
+            context.clone(),
+            allocator,
+            "specular_map",
+            image_attributes(SPECULAR_SIZE, SPECULAR_MIP_LEVELS, true),
+        )?;
+        let brdf_lut = Image::new(
+            context.clone(),
+            allocator,
+            "brdf_lut",
+            image_attributes(BRDF_LUT_SIZE, 1, false),
+        )?;
+
+        let shaders = ["ibl_irradiance", "ibl_specular", "ibl_brdf"]
+            .iter()
+            .map(|name| {
+                load_shader_module(
+                    context.as_ref(),
+                    SHADERS_DIR.to_owned() + name + ".comp.spv",
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        unsafe {
+            let irradiance_storage_view = create_storage_view(&context, &irradiance_map, 0)?;
+            let specular_storage_views = (0..SPECULAR_MIP_LEVELS)
+                .map(|mip_level| create_storage_view(&context, &specular_map, mip_level))
+                .collect::<Result<Vec<_>>>()?;
+
+            let storage_binding = |binding| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(binding)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            };
+            let descriptor_set_layout = context.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE),
+                    storage_binding(1),
+                    storage_binding(2),
+                    storage_binding(3),
+                ]),
+                None,
+            )?;
+
+            let set_count = buffering as u32 * SPECULAR_MIP_LEVELS;
+            let descriptor_pool = context.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(set_count)
+                    .pool_sizes(&[
+                        vk::DescriptorPoolSize::default()
+                            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                            .descriptor_count(set_count),
+                        vk::DescriptorPoolSize::default()
+                            .ty(vk::DescriptorType::STORAGE_IMAGE)
+                            .descriptor_count(3 * set_count),
+                    ]),
+                None,
+            )?;
+
+            let descriptor_sets = context.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&vec![descriptor_set_layout; set_count as usize]),
+            )?;
+
+            // Only the environment changes, the storage images are written once.
+            let storage_info = |view| {
+                [vk::DescriptorImageInfo::default()
+                    .image_view(view)
+                    .image_layout(vk::ImageLayout::GENERAL)]
+            };
+            let irradiance_info = storage_info(irradiance_storage_view);
+            let brdf_info = storage_info(brdf_lut.view);
+            let specular_infos = specular_storage_views
+                .iter()
+                .map(|view| storage_info(*view))
+                .collect::<Vec<_>>();
+            context.device.update_descriptor_sets(
+                &descriptor_sets
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(index, descriptor_set)| {
+                        let storage_write = |binding, image_info| {
+                            vk::WriteDescriptorSet::default()
+                                .dst_set(*descriptor_set)
+                                .dst_binding(binding)
+                                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                                .image_info(image_info)
+                        };
+                        [
+                            storage_write(1, &irradiance_info),
+                            storage_write(2, &specular_infos[index % SPECULAR_MIP_LEVELS as usize]),
+                            storage_write(3, &brdf_info),
+                        ]
+                    })
+                    .collect::<Vec<_>>(),
+                &[],
+            );
+
+            let sampler = context.device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .max_lod(vk::LOD_CLAMP_NONE),
+                None,
+            )?;
+
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_set_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .offset(0)
+                        .size(size_of::<PrefilterPushConstants>() as u32)]),
+                None,
+            )?;
+
+            let [irradiance_pipeline, specular_pipeline, brdf_pipeline] =
+                [shaders[0], shaders[1], shaders[2]].map(|shader| {
+                    context.create_compute_pipeline(ComputePipelineAttributes {
+                        shader,
+                        pipeline_layout,
+                        pipeline_cache: Default::default(),
+                    })
+                });
+
+            for shader in shaders {
+                context.device.destroy_shader_module(shader, None);
+            }
+
+            Ok(Self {
+                context,
+                irradiance_map,
+                specular_map,
+                brdf_lut,
+                irradiance_storage_view,
+                specular_storage_views,
+                sampler,
+                descriptor_set_layout,
+                descriptor_pool,
+                descriptor_sets,
+                pipeline_layout,
+                irradiance_pipeline: irradiance_pipeline?,
+                specular_pipeline: specular_pipeline?,
+                brdf_pipeline: brdf_pipeline?,
+                is_initialized: false,
+                has_environment: false,
+            })
+        }
+    }
+
+    pub fn irradiance_view(&self) -> vk::ImageView {
+        self.irradiance_map.view
+    }
+
+    pub fn specular_view(&self) -> vk::ImageView {
+        self.specular_map.view
+    }
+
+    pub fn brdf_lut_view(&self) -> vk::ImageView {
+        self.brdf_lut.view
+    }
+
+    // Whether the maps hold a prefiltered environment rather than black.
+    pub fn has_environment(&self) -> bool {
+        self.has_environment
+    }
+
+    // The maps keep their contents, shading just stops using them.
+    pub fn clear_environment(&mut self) {
+        self.has_environment = false;
+    }
+
+    // Prefilters environment when given one, which has to be sampled at shader_read, and
+    // leaves every map ready for fragment shaders.
+    pub fn record(
+        &mut self,
+        commands: &Commands,
+        frame_index: usize,
+        environment: Option<&mut Image>,
+    ) {
+        if !self.is_initialized {
+            commands
+                .clear_color_image(&mut self.irradiance_map, vk::ClearColorValue::default())
+                .clear_color_image(&mut self.specular_map, vk::ClearColorValue::default())
+                .ensure_image_layout(
+                    &mut self.brdf_lut,
+                    ImageLayoutState::compute_shader_storage(),
+                )
+                .bind_compute_pipeline(self.brdf_pipeline)
+                .bind_compute_descriptor_sets(
+                    self.pipeline_layout,
+                    &[self.descriptor_sets[frame_index * SPECULAR_MIP_LEVELS as usize]],
+                )
+                .dispatch([
+                    BRDF_LUT_SIZE.div_ceil(WORKGROUP_SIZE),
+                    BRDF_LUT_SIZE.div_ceil(WORKGROUP_SIZE),
+                    1,
+                ]);
+            self.is_initialized = true;
+        }
+
+        if let Some(environment) = environment {
+            self.prefilter(commands, frame_index, environment);
+            self.has_environment = true;
+        }
+
+        commands
+            .ensure_image_layout(&mut self.irradiance_map, ImageLayoutState::shader_read())
+            .ensure_image_layout(&mut self.specular_map, ImageLayoutState::shader_read())
+            .ensure_image_layout(&mut self.brdf_lut, ImageLayoutState::shader_read());
+    }
+
+    fn prefilter(&mut self, commands: &Commands, frame_index: usize, environment: &mut Image) {
+        let descriptor_sets = &self.descriptor_sets[frame_index * SPECULAR_MIP_LEVELS as usize..]
+            [..SPECULAR_MIP_LEVELS as usize];
+        let environment_info = [vk::DescriptorImageInfo::default()
+            .image_view(environment.view)
+            .sampler(self.sampler)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        unsafe {
+            self.context.device.update_descriptor_sets(
+                &descriptor_sets
+                    .iter()
+                    .map(|descriptor_set| {
+                        vk::WriteDescriptorSet::default()
+                            .dst_set(*descriptor_set)
+                            .dst_binding(0)
+                            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                            .image_info(&environment_info)
+                    })
+                    .collect::<Vec<_>>(),
+                &[],
+            );
+        }
+
+        commands
+            .ensure_image_layout(environment, ImageLayoutState::compute_shader_read())
+            .ensure_image_layout(
+                &mut self.irradiance_map,
+                ImageLayoutState::compute_shader_storage(),
+            )
+            .ensure_image_layout(
+                &mut self.specular_map,
+                ImageLayoutState::compute_shader_storage(),
+            )
+            .bind_compute_pipeline(self.irradiance_pipeline)
+            .bind_compute_descriptor_sets(self.pipeline_layout, &[descriptor_sets[0]])
+            .dispatch([
+                IRRADIANCE_SIZE.div_ceil(WORKGROUP_SIZE),
+                IRRADIANCE_SIZE.div_ceil(WORKGROUP_SIZE),
+                6,
+            ])
+            .bind_compute_pipeline(self.specular_pipeline);
+        for (mip_level, descriptor_set) in descriptor_sets.iter().enumerate() {
+            let size = (SPECULAR_SIZE >> mip_level).max(1);
+            commands
+                .bind_compute_descriptor_sets(self.pipeline_layout, &[*descriptor_set])
+                .set_compute_push_constants(
+                    self.pipeline_layout,
+                    PrefilterPushConstants {
+                        roughness: mip_level as f32 / (SPECULAR_MIP_LEVELS - 1) as f32,
+                    },
+                )
+                .dispatch([
+                    size.div_ceil(WORKGROUP_SIZE),
+                    size.div_ceil(WORKGROUP_SIZE),
+                    6,
+                ]);
+        }
+        commands.ensure_image_layout(environment, ImageLayoutState::shader_read());
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        unsafe {
+            self.context
+                .device
+                .destroy_image_view(self.irradiance_storage_view, None);
+            for view in &self.specular_storage_views {
+                self.context.device.destroy_image_view(*view, None);
+            }
+            for pipeline in [
+                self.irradiance_pipeline,
+                self.specular_pipeline,
+                self.brdf_pipeline,
+            ] {
+                self.context.device.destroy_pipeline(pipeline, None);
+            }
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context.device.destroy_sampler(self.sampler, None);
+            self.context
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        self.irradiance_map.destroy(allocator)?;
+        self.specular_map.destroy(allocator)?;
+        self.brdf_lut.destroy(allocator)
+    }
+}
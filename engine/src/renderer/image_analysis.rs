@@ -0,0 +1,263 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::renderer::commands::Commands;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{
+    ComputePipelineAttributes, Image, ImageLayoutState, RenderingContext,
+};
+use anyhow::Result;
+use ash::vk;
+use bytemuck::Zeroable;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use std::sync::Arc;
+
+pub const HISTOGRAM_BIN_COUNT: usize = 256;
+
+const WORKGROUP_SIZE: u32 = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImageAnalysisAttributes {
+    // Log2 luminance range spread over the histogram bins, values outside are clamped.
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+}
+
+impl Default for ImageAnalysisAttributes {
+    fn default() -> Self {
+        Self {
+            min_log_luminance: -10.0,
+            max_log_luminance: 6.0,
+        }
+    }
+}
+
+// Same layout as ResultBuffer in image_analysis.comp.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GPUImageStatistics {
+    histogram: [u32; HISTOGRAM_BIN_COUNT],
+    nan_count: u32,
+    infinity_count: u32,
+    minimum: [u32; 4],
+    maximum: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct AnalysisPushConstants {
+    result_buffer_address: vk::DeviceAddress,
+    min_log_luminance: f32,
+    max_log_luminance: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct ImageStatistics {
+    // Bin 0 counts black pixels, the rest split the log2 luminance range evenly.
+    pub histogram: Vec<u32>,
+    pub nan_count: u32,
+    pub infinity_count: u32,
+    // Per channel over the finite pixels, NaN when there are none.
+    pub min: [f32; 4],
+    pub max: [f32; 4],
+}
+
+// Inverse of orderedBits in image_analysis.comp.
+fn from_ordered_bits(bits: u32) -> f32 {
+    f32::from_bits(if bits & 0x8000_0000 != 0 {
+        bits & 0x7FFF_FFFF
+    } else {
+        !bits
+    })
+}
+
+// Compute passes for diagnosing HDR issues: a luminance histogram, NaN and infinity counts and
+// per channel min/max of any sampled 2D color image, read back on the CPU.
+// One analysis can be in flight at a time.
+pub struct ImageAnalyzer {
+    context: Arc<RenderingContext>,
+    attributes: ImageAnalysisAttributes,
+    result_buffer: Buffer,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl ImageAnalyzer {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        attributes: ImageAnalysisAttributes,
+    ) -> Result<Self> {
+        let result_buffer = Buffer::new(
+            allocator,
+            BufferAttributes {
+                name: "image_analysis_buffer".into(),
+                context: context.clone(),
+                size: size_of::<GPUImageStatistics>() as vk::DeviceSize,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                location: MemoryLocation::GpuToCpu,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                allocation_priority: 1.0,
+            },
+        )?;
+
+        let shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "image_analysis.comp.spv",
+        )?;
+
+        unsafe {
+            let descriptor_set_layout = context.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE),
+                ]),
+                None,
+            )?;
+
+            let descriptor_pool = context.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(1)
+                    .pool_sizes(&[vk::DescriptorPoolSize::default()
+                        .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                        .descriptor_count(1)]),
+                None,
+            )?;
+
+            let descriptor_set = context.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&[descriptor_set_layout]),
+            )?[0];
+
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_set_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .offset(0)
+                        .size(size_of::<AnalysisPushConstants>() as u32)]),
+                None,
+            )?;
+
+            let pipeline = context.create_compute_pipeline(ComputePipelineAttributes {
+                shader,
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            })?;
+
+            context.device.destroy_shader_module(shader, None);
+
+            Ok(Self {
+                context,
+                attributes,
+                result_buffer,
+                descriptor_set_layout,
+                descriptor_pool,
+                descriptor_set,
+                pipeline_layout,
+                pipeline,
+            })
+        }
+    }
+
+    pub fn attributes(&self) -> ImageAnalysisAttributes {
+        self.attributes
+    }
+
+    pub fn set_attributes(&mut self, attributes: ImageAnalysisAttributes) {
+        self.attributes = attributes;
+    }
+
+    // The image needs sampled usage; the results are readable once the commands have completed.
+    pub fn record(&mut self, commands: &Commands, image: &mut Image) -> Result<()> {
+        let mut statistics = GPUImageStatistics::zeroed();
+        statistics.minimum = [u32::MAX; 4];
+        self.result_buffer.write(&[statistics], 0)?;
+
+        unsafe {
+            self.context.device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::default()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&[vk::DescriptorImageInfo::default()
+                        .image_view(image.view)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)])],
+                &[],
+            );
+        }
+
+        let extent = image.attributes.extent;
+        commands
+            .ensure_image_layout(image, ImageLayoutState::compute_shader_read())
+            .expect_sampled(image, "image_analysis")
+            .bind_compute_pipeline(self.pipeline)
+            .bind_compute_descriptor_sets(self.pipeline_layout, &[self.descriptor_set])
+            .set_compute_push_constants(
+                self.pipeline_layout,
+                AnalysisPushConstants {
+                    result_buffer_address: self.result_buffer.address,
+                    min_log_luminance: self.attributes.min_log_luminance,
+                    max_log_luminance: self.attributes.max_log_luminance,
+                },
+            )
+            .dispatch([
+                extent.width.div_ceil(WORKGROUP_SIZE),
+                extent.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            ])
+            .buffer_memory_barrier(
+                &self.result_buffer,
+                (
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_WRITE,
+                ),
+                (vk::PipelineStageFlags2::HOST, vk::AccessFlags2::HOST_READ),
+            );
+        Ok(())
+    }
+
+    pub fn results(&self) -> Result<ImageStatistics> {
+        let statistics: GPUImageStatistics =
+            bytemuck::pod_read_unaligned(self.result_buffer.read::<u8>()?);
+        Ok(ImageStatistics {
+            histogram: statistics.histogram.to_vec(),
+            nan_count: statistics.nan_count,
+            infinity_count: statistics.infinity_count,
+            min: statistics.minimum.map(from_ordered_bits),
+            max: statistics.maximum.map(from_ordered_bits),
+        })
+    }
+
+    // Analyzes the image right away and waits for the results.
+    pub fn analyze(&mut self, image: &mut Image) -> Result<ImageStatistics> {
+        let context = self.context.clone();
+        context.immediate_submit(|commands| self.record(commands, image))?;
+        self.results()
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        self.result_buffer.destroy(allocator)?;
+
+        unsafe {
+            self.context.device.destroy_pipeline(self.pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        Ok(())
+    }
+}
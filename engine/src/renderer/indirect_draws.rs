@@ -0,0 +1,169 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::renderer::commands::Commands;
+use crate::rendering_context::RenderingContext;
+use anyhow::Result;
+use ash::vk;
+use bytemuck::Zeroable;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use std::ops::Range;
+use std::sync::Arc;
+
+// Same layout as vk::DrawIndexedIndirectCommand.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DrawIndexedIndirectCommand {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    pub first_instance: u32,
+}
+
+fn create_buffer(
+    name: &str,
+    context: Arc<RenderingContext>,
+    allocator: &mut Allocator,
+    size: usize,
+) -> Result<Buffer> {
+    Buffer::new(
+        allocator,
+        BufferAttributes {
+            name: name.into(),
+            context,
+            size: size.max(1) as vk::DeviceSize,
+            usage: vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            location: MemoryLocation::CpuToGpu,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            allocation_priority: 1.0,
+        },
+    )
+}
+
+fn create_draw_buffer(
+    context: Arc<RenderingContext>,
+    allocator: &mut Allocator,
+    capacity: usize,
+) -> Result<Buffer> {
+    create_buffer(
+        "indirect_draw_buffer",
+        context,
+        allocator,
+        capacity.max(1) * size_of::<DrawIndexedIndirectCommand>(),
+    )
+}
+
+fn create_count_buffer(
+    context: Arc<RenderingContext>,
+    allocator: &mut Allocator,
+    capacity: usize,
+) -> Result<Buffer> {
+    create_buffer(
+        "indirect_count_buffer",
+        context,
+        allocator,
+        capacity.max(1) * size_of::<u32>(),
+    )
+}
+
+// Draws that are bound together, e.g. those sharing a mesh, with room for up to capacity of
+// them so a culling pass can compact into the same slots.
+#[derive(Debug, Clone, Default)]
+pub struct DrawBucket {
+    pub capacity: u32,
+    pub draws: Vec<DrawIndexedIndirectCommand>,
+}
+
+// Compacted draw lists, one per bucket, with their draw counts in a separate buffer so they
+// can be written by the CPU as well as by a GPU culling pass through the buffer addresses.
+pub struct IndirectDrawBuffer {
+    context: Arc<RenderingContext>,
+    pub draw_buffer: Buffer,
+    pub count_buffer: Buffer,
+    // Where each bucket's slots are in the draw buffer, its count is at the same index in the
+    // count buffer.
+    buckets: Vec<Range<u32>>,
+}
+
+impl IndirectDrawBuffer {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        capacity: usize,
+    ) -> Result<Self> {
+        let draw_buffer = create_draw_buffer(context.clone(), allocator, capacity)?;
+        let count_buffer = create_count_buffer(context.clone(), allocator, 1)?;
+
+        Ok(Self {
+            context,
+            draw_buffer,
+            count_buffer,
+            buckets: Vec::new(),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.draw_buffer.attributes.size as usize / size_of::<DrawIndexedIndirectCommand>()
+    }
+
+    fn bucket_capacity(&self) -> usize {
+        self.count_buffer.attributes.size as usize / size_of::<u32>()
+    }
+
+    // Must be called after the frame's fence has been waited on.
+    pub fn write(&mut self, allocator: &mut Allocator, buckets: &[DrawBucket]) -> Result<()> {
+        let mut draws = Vec::new();
+        self.buckets.clear();
+        for bucket in buckets {
+            let start = draws.len();
+            draws.extend_from_slice(&bucket.draws);
+            draws.resize(
+                start + bucket.draws.len().max(bucket.capacity as usize),
+                DrawIndexedIndirectCommand::zeroed(),
+            );
+            self.buckets.push(start as u32..draws.len() as u32);
+        }
+
+        if draws.len() > self.capacity() {
+            self.draw_buffer.destroy(allocator)?;
+            self.draw_buffer = create_draw_buffer(
+                self.context.clone(),
+                allocator,
+                draws.len().next_power_of_two(),
+            )?;
+        }
+        if buckets.len() > self.bucket_capacity() {
+            self.count_buffer.destroy(allocator)?;
+            self.count_buffer = create_count_buffer(
+                self.context.clone(),
+                allocator,
+                buckets.len().next_power_of_two(),
+            )?;
+        }
+        self.draw_buffer.write(&draws, 0)?;
+        let counts = buckets
+            .iter()
+            .map(|bucket| bucket.draws.len() as u32)
+            .collect::<Vec<_>>();
+        self.count_buffer.write(&counts, 0)
+    }
+
+    // Draws as many of the bucket's commands as its count says.
+    pub fn draw(&self, commands: &Commands, bucket: usize) {
+        let draws = &self.buckets[bucket];
+        commands.draw_indexed_indirect_count(
+            &self.draw_buffer,
+            (draws.start as usize * size_of::<DrawIndexedIndirectCommand>()) as vk::DeviceSize,
+            &self.count_buffer,
+            (bucket * size_of::<u32>()) as vk::DeviceSize,
+            draws.end - draws.start,
+        );
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        self.draw_buffer.destroy(allocator)?;
+        self.count_buffer.destroy(allocator)
+    }
+}
@@ -0,0 +1,351 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// Just enough JSON for glTF files, numbers are kept as f64.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(HashMap<String, Json>),
+}
+
+const NULL: Json = Json::Null;
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            offset: 0,
+        };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.offset != parser.bytes.len() {
+            return Err(parser.error("Trailing characters"));
+        }
+        Ok(value)
+    }
+
+    // Null for missing keys and for values that aren't objects, like JavaScript's optional
+    // chaining, so nested lookups only need checking once.
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(object) => object.get(key).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+
+    pub fn index(&self, index: usize) -> &Json {
+        match self {
+            Json::Array(array) => array.get(index).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Json::Null
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    // Only for whole non-negative numbers, e.g. indices and sizes.
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_f64()
+            .filter(|number| number.fract() == 0.0 && *number >= 0.0)
+            .map(|number| number as usize)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    // Empty for values that aren't arrays.
+    pub fn as_slice(&self) -> &[Json] {
+        match self {
+            Json::Array(array) => array,
+            _ => &[],
+        }
+    }
+}
+
+// The text as a quoted JSON string, escaping what JSON doesn't allow as is.
+pub(crate) fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for character in text.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\u{0}'..='\u{1f}' => {
+                write!(quoted, "\\u{:04x}", character as u32).unwrap();
+            }
+            _ => quoted.push(character),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Deep enough for any glTF file while keeping malicious input from overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow!("{message} in JSON at byte {}", self.offset)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.offset)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.offset += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.offset).copied()
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<()> {
+        if self.peek() != Some(expected) {
+            return Err(self.error(&format!("Expected '{}'", expected as char)));
+        }
+        self.offset += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, literal: &str, value: Json) -> Result<Json> {
+        if !self.bytes[self.offset..].starts_with(literal.as_bytes()) {
+            return Err(self.error("Unexpected character"));
+        }
+        self.offset += literal.len();
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("Too deeply nested"));
+        }
+        match self.peek() {
+            Some(b'{') => self.object(depth),
+            Some(b'[') => self.array(depth),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.error("Expected a value")),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Json> {
+        self.expect(b'{')?;
+        let mut object = HashMap::new();
+        if self.peek() == Some(b'}') {
+            self.offset += 1;
+            return Ok(Json::Object(object));
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error("Expected a key"));
+            }
+            let key = self.string()?;
+            self.expect(b':')?;
+            object.insert(key, self.value(depth + 1)?);
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                Some(b'}') => {
+                    self.offset += 1;
+                    return Ok(Json::Object(object));
+                }
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Json> {
+        self.expect(b'[')?;
+        let mut array = Vec::new();
+        if self.peek() == Some(b']') {
+            self.offset += 1;
+            return Ok(Json::Array(array));
+        }
+        loop {
+            array.push(self.value(depth + 1)?);
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                Some(b']') => {
+                    self.offset += 1;
+                    return Ok(Json::Array(array));
+                }
+                _ => return Err(self.error("Expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.offset;
+        while self
+            .bytes
+            .get(self.offset)
+            .is_some_and(|byte| matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.offset += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.offset])?
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| self.error("Invalid number"))
+    }
+
+    fn hex_escape(&mut self) -> Result<u32> {
+        let digits = self
+            .bytes
+            .get(self.offset..self.offset + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("Invalid unicode escape"))?;
+        self.offset += 4;
+        Ok(digits)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = *self
+                .bytes
+                .get(self.offset)
+                .ok_or_else(|| self.error("Unterminated string"))?;
+            self.offset += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self
+                        .bytes
+                        .get(self.offset)
+                        .ok_or_else(|| self.error("Unterminated string"))?;
+                    self.offset += 1;
+                    let character = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code_point = self.hex_escape()?;
+                            // Characters outside the basic plane are escaped as surrogate pairs.
+                            if (0xd800..0xdc00).contains(&code_point)
+                                && self.bytes[self.offset..].starts_with(b"\\u")
+                            {
+                                self.offset += 2;
+                                let low = self.hex_escape()?;
+                                code_point = 0x10000
+                                    + ((code_point - 0xd800) << 10)
+                                    + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            char::from_u32(code_point).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        _ => return Err(self.error("Invalid escape")),
+                    };
+                    bytes.extend_from_slice(character.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                _ => bytes.push(byte),
+            }
+        }
+        Ok(String::from_utf8(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_escapes_control_characters() {
+        let text = "GPU \"0\"\\\n\t\u{1b}é";
+        let quoted = quote(text);
+        assert_eq!(quoted, "\"GPU \\\"0\\\"\\\\\\n\\t\\u001bé\"");
+        assert_eq!(Json::parse(&quoted).unwrap(), Json::String(text.into()));
+    }
+
+    #[test]
+    fn parse_nested_values() {
+        let json = Json::parse(
+            r#" { "asset": { "version": "2.0" }, "nodes": [ { "mesh": 3 }, null ],
+                "scale": [1, -2.5, 1e3], "visible": true, "hidden": false } "#,
+        )
+        .unwrap();
+        assert_eq!(json.get("asset").get("version").as_str(), Some("2.0"));
+        assert_eq!(json.get("nodes").index(0).get("mesh").as_usize(), Some(3));
+        assert!(json.get("nodes").index(1).is_null());
+        assert!(json.get("nodes").index(2).get("mesh").is_null());
+        assert!(json.get("missing").get("deeper").is_null());
+        let scale = json
+            .get("scale")
+            .as_slice()
+            .iter()
+            .map(|value| value.as_f64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(scale, [1.0, -2.5, 1000.0]);
+        assert_eq!(json.get("scale").index(1).as_usize(), None);
+        assert_eq!(json.get("visible"), &Json::Bool(true));
+        assert_eq!(json.get("hidden"), &Json::Bool(false));
+        assert_eq!(Json::parse("[]").unwrap(), Json::Array(Vec::new()));
+        assert_eq!(Json::parse("{}").unwrap(), Json::Object(HashMap::new()));
+    }
+
+    #[test]
+    fn parse_escapes() {
+        let json = Json::parse(r#""a\/b\u00e9\ud83d\ude00\"""#).unwrap();
+        assert_eq!(json.as_str(), Some("a/bé😀\""));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_json() {
+        for text in [
+            "",
+            "{} {}",
+            "[1, 2",
+            "[1 2]",
+            "{\"key\" 1}",
+            "{key: 1}",
+            "\"unterminated",
+            "\"\\x\"",
+            "tru",
+            "-",
+            "1.2.3",
+        ] {
+            assert!(Json::parse(text).is_err(), "{text:?} should not parse");
+        }
+        let nested = "[".repeat(MAX_DEPTH + 2) + &"]".repeat(MAX_DEPTH + 2);
+        assert!(Json::parse(&nested).is_err());
+        let nested = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+        assert!(Json::parse(&nested).is_ok());
+    }
+}
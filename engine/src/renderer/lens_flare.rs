@@ -0,0 +1,334 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::renderer::commands::Commands;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{
+    ComputePipelineAttributes, GraphicsPipelineAttributes, Image, ImageLayoutState,
+    RenderingContext,
+};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use std::sync::Arc;
+
+// Flare elements drawn after the light shafts, must match lens_flare.vert.
+const FLARE_ELEMENTS: u32 = 7;
+// Position, visibility and how far on screen the sun is, see lens_flare.glsl.
+const OCCLUSION_SIZE: vk::DeviceSize = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LensFlareAttributes {
+    // Of the rays streaming from the sky around the sun past the geometry in front of it.
+    pub shaft_intensity: f32,
+    // Fraction of the way to the sun each pixel gathers the sky over.
+    pub shaft_length: f32,
+    // Of the glow around the sun and the ghosts mirrored across the screen.
+    pub flare_intensity: f32,
+    // Of the disk tested for occlusion, in screen heights. Larger than the real sun so the
+    // flares fade smoothly as it goes behind an edge.
+    pub sun_radius: f32,
+}
+
+impl Default for LensFlareAttributes {
+    fn default() -> Self {
+        Self {
+            shaft_intensity: 0.5,
+            shaft_length: 0.8,
+            flare_intensity: 0.1,
+            sun_radius: 0.02,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct OcclusionPushConstants {
+    camera_buffer_address: vk::DeviceAddress,
+    occlusion_buffer_address: vk::DeviceAddress,
+    radius: f32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawPushConstants {
+    camera_buffer_address: vk::DeviceAddress,
+    occlusion_buffer_address: vk::DeviceAddress,
+    shaft_intensity: f32,
+    shaft_length: f32,
+    flare_intensity: f32,
+    aspect_ratio: f32,
+}
+
+// Sun lens flares and light shafts added onto the resolved scene. A compute pass tests how much
+// of the sun's disk the depth buffer leaves in front of the sky, which scales the flares on the
+// GPU without reading anything back, and the shafts march the depth buffer towards the sun so
+// the geometry in front of it casts rays.
+pub struct LensFlare {
+    context: Arc<RenderingContext>,
+    attributes: Option<LensFlareAttributes>,
+    // Per frame in flight, written by the occlusion pass and read by the draw.
+    occlusion_buffers: Vec<Buffer>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    // Per frame in flight, the resolved depth buffer.
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    occlusion_pipeline_layout: vk::PipelineLayout,
+    occlusion_pipeline: vk::Pipeline,
+    draw_pipeline_layout: vk::PipelineLayout,
+    draw_pipeline: vk::Pipeline,
+}
+
+impl LensFlare {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        buffering: usize,
+    ) -> Result<Self> {
+        let occlusion_buffers = (0..buffering)
+            .map(|_| {
+                Buffer::new(
+                    allocator,
+                    BufferAttributes {
+                        name: "sun_occlusion_buffer".into(),
+                        context: context.clone(),
+                        size: OCCLUSION_SIZE,
+                        usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                        location: MemoryLocation::GpuOnly,
+                        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                        allocation_priority: 0.5,
+                    },
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let occlusion_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "lens_flare_occlusion.comp.spv",
+        )?;
+        let vertex_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "lens_flare.vert.spv",
+        )?;
+        let fragment_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "lens_flare.frag.spv",
+        )?;
+
+        unsafe {
+            let descriptor_set_layout = context.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .descriptor_count(1)
+                        .stage_flags(
+                            vk::ShaderStageFlags::COMPUTE | vk::ShaderStageFlags::FRAGMENT,
+                        ),
+                ]),
+                None,
+            )?;
+            let descriptor_pool = context.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(buffering as u32)
+                    .pool_sizes(&[vk::DescriptorPoolSize::default()
+                        .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                        .descriptor_count(buffering as u32)]),
+                None,
+            )?;
+            let descriptor_sets = context.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&vec![descriptor_set_layout; buffering]),
+            )?;
+
+            let occlusion_pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_set_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .offset(0)
+                        .size(size_of::<OcclusionPushConstants>() as u32)]),
+                None,
+            )?;
+            let occlusion_pipeline =
+                context.create_compute_pipeline(ComputePipelineAttributes {
+                    shader: occlusion_shader,
+                    pipeline_layout: occlusion_pipeline_layout,
+                    pipeline_cache: Default::default(),
+                })?;
+
+            let draw_pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_set_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                        .offset(0)
+                        .size(size_of::<DrawPushConstants>() as u32)]),
+                None,
+            )?;
+            let draw_pipeline = context.create_graphics_pipeline(GraphicsPipelineAttributes {
+                vertex_shader,
+                fragment_shader,
+                extent,
+                color_format: format,
+                depth_format: vk::Format::UNDEFINED,
+                samples: vk::SampleCountFlags::TYPE_1,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                line_width: 1.0,
+                dynamic_line_width: false,
+                dynamic_topology: false,
+                alpha_blending: false,
+                additive_blending: true,
+                depth_write: false,
+                cull_mode: vk::CullModeFlags::NONE,
+                viewport_count: 1,
+                vertex_layout: Default::default(),
+                pipeline_layout: draw_pipeline_layout,
+                pipeline_cache: Default::default(),
+            })?;
+
+            for shader in [occlusion_shader, vertex_shader, fragment_shader] {
+                context.device.destroy_shader_module(shader, None);
+            }
+
+            Ok(Self {
+                context,
+                attributes: None,
+                occlusion_buffers,
+                descriptor_set_layout,
+                descriptor_pool,
+                descriptor_sets,
+                occlusion_pipeline_layout,
+                occlusion_pipeline,
+                draw_pipeline_layout,
+                draw_pipeline,
+            })
+        }
+    }
+
+    pub fn attributes(&self) -> Option<LensFlareAttributes> {
+        self.attributes
+    }
+
+    // None turns the flares and the shafts off.
+    pub fn set_attributes(&mut self, attributes: Option<LensFlareAttributes>) {
+        self.attributes = attributes;
+    }
+
+    // Tests the sun against the frame's resolved depth buffer. Must be called after the frame's
+    // fence has been waited on, the depth buffer may have been recreated since it was last
+    // recorded.
+    pub fn record_occlusion(
+        &self,
+        commands: &Commands,
+        frame_index: usize,
+        camera_buffer_address: vk::DeviceAddress,
+        depth_buffer: &mut Image,
+    ) {
+        let Some(attributes) = self.attributes else {
+            return;
+        };
+        let descriptor_set = self.descriptor_sets[frame_index];
+        unsafe {
+            self.context.device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&[vk::DescriptorImageInfo::default()
+                        .image_view(depth_buffer.view)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)])],
+                &[],
+            );
+        }
+
+        let height = depth_buffer.attributes.extent.height as f32;
+        commands
+            .ensure_image_layout(depth_buffer, ImageLayoutState::compute_shader_read())
+            .expect_sampled(depth_buffer, "lens_flare")
+            .bind_compute_pipeline(self.occlusion_pipeline)
+            .bind_compute_descriptor_sets(self.occlusion_pipeline_layout, &[descriptor_set])
+            .set_compute_push_constants(
+                self.occlusion_pipeline_layout,
+                OcclusionPushConstants {
+                    camera_buffer_address,
+                    occlusion_buffer_address: self.occlusion_buffers[frame_index].address,
+                    radius: attributes.sun_radius * height,
+                    padding: 0,
+                },
+            )
+            .dispatch([1, 1, 1]);
+    }
+
+    // Adds the shafts and the flares onto the render target, after record_occlusion.
+    pub fn draw(
+        &self,
+        commands: &Commands,
+        frame_index: usize,
+        camera_buffer_address: vk::DeviceAddress,
+        render_target: &mut Image,
+        depth_buffer: &mut Image,
+    ) {
+        let Some(attributes) = self.attributes else {
+            return;
+        };
+        let extent = vk::Extent2D {
+            width: render_target.attributes.extent.width,
+            height: render_target.attributes.extent.height,
+        };
+        let render_area = vk::Rect2D::default().extent(extent);
+        let descriptor_set = self.descriptor_sets[frame_index];
+        commands
+            .ensure_image_layout(depth_buffer, ImageLayoutState::shader_read())
+            .begin_color_rendering(render_target, None, render_area)
+            .set_viewport(
+                vk::Viewport::default()
+                    .width(extent.width as f32)
+                    .height(extent.height as f32)
+                    .max_depth(1.0),
+            )
+            .set_scissor(render_area)
+            .bind_pipeline(self.draw_pipeline)
+            .bind_descriptor_sets(self.draw_pipeline_layout, &[descriptor_set])
+            .set_push_constants(
+                self.draw_pipeline_layout,
+                DrawPushConstants {
+                    camera_buffer_address,
+                    occlusion_buffer_address: self.occlusion_buffers[frame_index].address,
+                    shaft_intensity: attributes.shaft_intensity,
+                    shaft_length: attributes.shaft_length.clamp(0.0, 1.0),
+                    flare_intensity: attributes.flare_intensity,
+                    aspect_ratio: extent.width as f32 / extent.height.max(1) as f32,
+                },
+            )
+            .draw(0..6, 0..1 + FLARE_ELEMENTS)
+            .end_rendering();
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for buffer in &mut self.occlusion_buffers {
+            buffer.destroy(allocator)?;
+        }
+        unsafe {
+            for pipeline in [self.occlusion_pipeline, self.draw_pipeline] {
+                self.context.device.destroy_pipeline(pipeline, None);
+            }
+            for pipeline_layout in [self.occlusion_pipeline_layout, self.draw_pipeline_layout] {
+                self.context
+                    .device
+                    .destroy_pipeline_layout(pipeline_layout, None);
+            }
+            self.context
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        Ok(())
+    }
+}
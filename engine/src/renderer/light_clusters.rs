@@ -0,0 +1,144 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::renderer::commands::Commands;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{ComputePipelineAttributes, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use std::sync::Arc;
+
+// Must match lights.glsl.
+const CLUSTER_COUNTS: [u32; 3] = [16, 9, 24];
+const MAX_CLUSTER_LIGHTS: usize = 63;
+const HEADER_SIZE: usize = 16;
+const CLUSTER_SIZE: usize = (1 + MAX_CLUSTER_LIGHTS) * size_of::<u32>();
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClusterPushConstants {
+    camera_buffer_address: vk::DeviceAddress,
+    light_buffer_address: vk::DeviceAddress,
+    near: f32,
+    far: f32,
+}
+
+// Forward+ light culling: the view frustum is split into screen tiles and exponential depth
+// slices, and a compute pass lists the lights reaching each of them before the scene pass, so
+// fragments only go through the lights of their own cluster.
+pub struct LightClusters {
+    context: Arc<RenderingContext>,
+    // Per frame in flight, written by the compute pass and read by the scene's fragments.
+    cluster_buffers: Vec<Buffer>,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl LightClusters {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        buffering: usize,
+    ) -> Result<Self> {
+        let cluster_count = CLUSTER_COUNTS.iter().product::<u32>() as usize;
+        let cluster_buffers = (0..buffering)
+            .map(|_| {
+                Buffer::new(
+                    allocator,
+                    BufferAttributes {
+                        name: "light_cluster_buffer".into(),
+                        context: context.clone(),
+                        size: (HEADER_SIZE + cluster_count * CLUSTER_SIZE) as vk::DeviceSize,
+                        usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                        location: MemoryLocation::GpuOnly,
+                        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                        allocation_priority: 1.0,
+                    },
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "light_clusters.comp.spv",
+        )?;
+        unsafe {
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&[
+                    vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .offset(0)
+                        .size(size_of::<ClusterPushConstants>() as u32),
+                ]),
+                None,
+            )?;
+            let pipeline = context.create_compute_pipeline(ComputePipelineAttributes {
+                shader,
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            });
+            context.device.destroy_shader_module(shader, None);
+
+            Ok(Self {
+                context,
+                cluster_buffers,
+                pipeline_layout,
+                pipeline: pipeline?,
+            })
+        }
+    }
+
+    pub fn address(&self, frame_index: usize) -> vk::DeviceAddress {
+        self.cluster_buffers[frame_index].address
+    }
+
+    // Bins the frame's lights for the first camera of the camera buffer, near and far being
+    // its projection's planes. The light buffer has to reference this frame's cluster buffer.
+    pub fn record(
+        &self,
+        commands: &Commands,
+        frame_index: usize,
+        camera_buffer_address: vk::DeviceAddress,
+        light_buffer_address: vk::DeviceAddress,
+        near: f32,
+        far: f32,
+    ) {
+        commands
+            .bind_compute_pipeline(self.pipeline)
+            .set_compute_push_constants(
+                self.pipeline_layout,
+                ClusterPushConstants {
+                    camera_buffer_address,
+                    light_buffer_address,
+                    near,
+                    far,
+                },
+            )
+            .dispatch_invocations(CLUSTER_COUNTS, [16, 9, 1])
+            .buffer_memory_barrier(
+                &self.cluster_buffers[frame_index],
+                (
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_READ,
+                ),
+            );
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for buffer in &mut self.cluster_buffers {
+            buffer.destroy(allocator)?;
+        }
+        unsafe {
+            self.context.device.destroy_pipeline(self.pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        Ok(())
+    }
+}
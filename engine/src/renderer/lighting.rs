@@ -0,0 +1,70 @@
+use nalgebra as na;
+use std::f32::consts::PI;
+
+// Scene wide lighting in photometric units, so values from real-world references carry over:
+// illuminance in lux for the sun and the ambient light, point lights in candela. Shading
+// treats surfaces as Lambertian, a white surface lit by E lux has a luminance of E / π cd/m²,
+// which the camera's exposure then brings into the displayable range.
+#[derive(Clone, Copy, Debug)]
+pub struct Lighting {
+    // Direction the sunlight travels in.
+    pub sun_direction: na::Vector3<f32>,
+    pub sun_color: na::Vector3<f32>,
+    // On a surface facing the sun, e.g. 100000 lux for direct sunlight at noon.
+    pub sun_illuminance: f32,
+    // Reaching every surface from all around when there's no lightmap, probe grid or skybox.
+    pub ambient_illuminance: f32,
+    // Lightmaps and probe grids are taken as relative to 1 lux and scaled by this.
+    pub baked_illuminance: f32,
+    // Luminance in cd/m² of a skybox texel of 1, e.g. around 8000 for a clear blue sky.
+    pub sky_luminance: f32,
+}
+
+impl Default for Lighting {
+    // Scaled for an exposure of 1, a white surface facing the sun has a luminance of 1 cd/m².
+    fn default() -> Self {
+        Self {
+            sun_direction: na::Vector3::new(-0.5, 1.0, -0.5).normalize(),
+            sun_color: na::Vector3::repeat(1.0),
+            sun_illuminance: PI,
+            ambient_illuminance: 0.1 * PI,
+            baked_illuminance: PI,
+            sky_luminance: 1.0,
+        }
+    }
+}
+
+impl Lighting {
+    // A clear day, meant for the default PhysicalCamera exposed by the sunny 16 rule.
+    pub fn daylight() -> Self {
+        Self {
+            sun_illuminance: 100_000.0,
+            ambient_illuminance: 20_000.0,
+            baked_illuminance: 20_000.0,
+            sky_luminance: 8_000.0,
+            ..Self::default()
+        }
+    }
+}
+
+// Luminous intensity of a point light emitting lumens evenly in every direction, e.g. about
+// 800 lumens for a 60W incandescent bulb.
+pub fn lumens_to_candela(lumens: f32) -> f32 {
+    lumens / (4.0 * PI)
+}
+
+pub fn candela_to_lumens(candela: f32) -> f32 {
+    candela * 4.0 * PI
+}
+
+// Scale from scene luminance in cd/m² to the [0, 1] sensor range, using the saturation based
+// sensitivity from "Moving Frostbite to Physically Based Rendering".
+pub fn ev100_to_exposure(ev100: f32) -> f32 {
+    1.0 / (1.2 * 2.0_f32.powf(ev100))
+}
+
+// Exposure value that maps an average scene luminance in cd/m² to middle gray, using the
+// reflected light meter constant of 12.5.
+pub fn ev100_from_average_luminance(luminance: f32) -> f32 {
+    (luminance * 100.0 / 12.5).log2()
+}
@@ -0,0 +1,200 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::rendering_context::RenderingContext;
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use nalgebra as na;
+use std::sync::Arc;
+
+const INITIAL_MATERIAL_CAPACITY: usize = 16;
+
+// Matches the sampler every texture is registered with, see Renderer::add_texture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerAttributes {
+    pub filter: vk::Filter,
+    pub address_mode: vk::SamplerAddressMode,
+}
+
+impl Default for SamplerAttributes {
+    fn default() -> Self {
+        Self {
+            filter: vk::Filter::NEAREST,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+        }
+    }
+}
+
+impl SamplerAttributes {
+    pub(crate) fn create_info(&self) -> vk::SamplerCreateInfo<'static> {
+        vk::SamplerCreateInfo::default()
+            .mag_filter(self.filter)
+            .min_filter(self.filter)
+            .address_mode_u(self.address_mode)
+            .address_mode_v(self.address_mode)
+            .address_mode_w(self.address_mode)
+            // Nearest filtering keeps nearest mip selection too, for crisp pixel art.
+            .mipmap_mode(if self.filter == vk::Filter::LINEAR {
+                vk::SamplerMipmapMode::LINEAR
+            } else {
+                vk::SamplerMipmapMode::NEAREST
+            })
+            .max_lod(vk::LOD_CLAMP_NONE)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Material {
+    // Bindless index returned by Renderer::add_texture, None for untextured materials.
+    pub base_color_texture: Option<u32>,
+    pub sampler: SamplerAttributes,
+    // Multiplies the base color texture, or is the base color without one.
+    pub base_color_factor: na::Vector4<f32>,
+    pub specular_strength: f32,
+    // Tangent space normal map in linear RGB with +y up, sampled through the same sampler as
+    // the base color. Needs meshes with tangents, see Geometry::generate_tangents.
+    pub normal_texture: Option<u32>,
+    // How much the scene's wetness darkens the material and makes it glossy, 0 for surfaces
+    // that stay dry like sheltered or water repellent ones. See Renderer::set_weather.
+    pub wetness_response: f32,
+    // Baked indirect lighting sampled with the meshes' lightmap UVs, replacing the ambient, probe
+    // and image based lighting. Meshes sharing a lightmap need their own material each.
+    pub lightmap_texture: Option<u32>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color_texture: None,
+            sampler: SamplerAttributes::default(),
+            base_color_factor: na::Vector4::repeat(1.0),
+            specular_strength: 0.5,
+            normal_texture: None,
+            wetness_response: 1.0,
+            lightmap_texture: None,
+        }
+    }
+}
+
+// Same layout as Material in push_constants.glsl.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GPUMaterial {
+    base_color_factor: na::Vector4<f32>,
+    base_color_texture_index: u32,
+    specular_strength: f32,
+    normal_texture_index: u32,
+    wetness_response: f32,
+    lightmap_texture_index: u32,
+}
+
+// Bindless slots a material's textures are sampled through, INVALID_TEXTURE_INDEX for the
+// textures it doesn't have.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MaterialSlots {
+    pub base_color: u32,
+    pub normal: u32,
+    pub lightmap: u32,
+}
+
+fn create_material_buffer(
+    context: Arc<RenderingContext>,
+    allocator: &mut Allocator,
+    capacity: usize,
+) -> Result<Buffer> {
+    Buffer::new(
+        allocator,
+        BufferAttributes {
+            name: "material_buffer".into(),
+            context,
+            size: (capacity * size_of::<GPUMaterial>()) as vk::DeviceSize,
+            usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            location: MemoryLocation::CpuToGpu,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            allocation_priority: 1.0,
+        },
+    )
+}
+
+// Material table indexed by the instances' material index, uploaded per frame in flight.
+pub(crate) struct Materials {
+    context: Arc<RenderingContext>,
+    // Each material with the bindless slots its textures are sampled through, which differ from
+    // the textures' own index when the material uses a non default sampler.
+    materials: Vec<(Material, MaterialSlots)>,
+    buffers: Vec<Buffer>,
+}
+
+impl Materials {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        buffering: usize,
+    ) -> Result<Self> {
+        let buffers = (0..buffering)
+            .map(|_| create_material_buffer(context.clone(), allocator, INITIAL_MATERIAL_CAPACITY))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            context,
+            materials: Vec::new(),
+            buffers,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Material> {
+        self.materials.get(index).map(|(material, _)| material)
+    }
+
+    pub fn push(&mut self, material: Material, slots: MaterialSlots) -> u32 {
+        self.materials.push((material, slots));
+        self.materials.len() as u32 - 1
+    }
+
+    pub fn set(&mut self, index: usize, material: Material, slots: MaterialSlots) {
+        self.materials[index] = (material, slots);
+    }
+
+    pub fn address(&self, frame_index: usize) -> vk::DeviceAddress {
+        self.buffers[frame_index].address
+    }
+
+    // Must be called after the frame's fence has been waited on.
+    pub fn upload(&mut self, allocator: &mut Allocator, frame_index: usize) -> Result<()> {
+        let capacity =
+            self.buffers[frame_index].attributes.size as usize / size_of::<GPUMaterial>();
+        if self.materials.len() > capacity {
+            self.buffers[frame_index].destroy(allocator)?;
+            self.buffers[frame_index] = create_material_buffer(
+                self.context.clone(),
+                allocator,
+                self.materials.len().next_power_of_two(),
+            )?;
+        }
+
+        let gpu_materials = self
+            .materials
+            .iter()
+            .map(|(material, slots)| GPUMaterial {
+                base_color_factor: material.base_color_factor,
+                base_color_texture_index: slots.base_color,
+                specular_strength: material.specular_strength,
+                normal_texture_index: slots.normal,
+                wetness_response: material.wetness_response,
+                lightmap_texture_index: slots.lightmap,
+            })
+            .collect::<Vec<_>>();
+        self.buffers[frame_index].write(&gpu_materials, 0)
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for buffer in &mut self.buffers {
+            buffer.destroy(allocator)?;
+        }
+        Ok(())
+    }
+}
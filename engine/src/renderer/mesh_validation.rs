@@ -0,0 +1,208 @@
+use crate::renderer::geometry::{Geometry, Vertex};
+use std::collections::HashMap;
+use std::fmt;
+
+// Geometry the GPU can't draw correctly, caught before it's uploaded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MeshError {
+    Empty,
+    IndexCountNotTriangles {
+        index_count: usize,
+    },
+    IndexOutOfRange {
+        // Position in the index buffer.
+        position: usize,
+        index: u32,
+        vertex_count: usize,
+    },
+    NonFiniteVertex {
+        vertex: usize,
+    },
+    SkinVertexCountMismatch {
+        skin_vertex_count: usize,
+        vertex_count: usize,
+    },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Meshes need at least one vertex and one index"),
+            Self::IndexCountNotTriangles { index_count } => {
+                write!(f, "{index_count} indices don't make whole triangles")
+            }
+            Self::IndexOutOfRange {
+                position,
+                index,
+                vertex_count,
+            } => write!(
+                f,
+                "Index {index} at {position} is out of range for {vertex_count} vertices"
+            ),
+            Self::NonFiniteVertex { vertex } => {
+                write!(
+                    f,
+                    "Vertex {vertex} has a NaN or infinite position, normal or tangent"
+                )
+            }
+            Self::SkinVertexCountMismatch {
+                skin_vertex_count,
+                vertex_count,
+            } => write!(
+                f,
+                "{skin_vertex_count} skin vertices don't match {vertex_count} vertices"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+impl Vertex {
+    pub(crate) fn is_finite(&self) -> bool {
+        self.position
+            .iter()
+            .chain(self.normal.iter())
+            .chain(self.tangent.iter())
+            .all(|value| value.is_finite())
+    }
+}
+
+impl Geometry {
+    // Reports the first problem that would make draws read past the buffers or produce garbage.
+    // Degenerate triangles and duplicated vertices only waste work, see the repair functions.
+    pub fn validate(&self) -> Result<(), MeshError> {
+        if self.vertices.is_empty() || self.indices.is_empty() {
+            return Err(MeshError::Empty);
+        }
+        if !self.indices.len().is_multiple_of(3) {
+            return Err(MeshError::IndexCountNotTriangles {
+                index_count: self.indices.len(),
+            });
+        }
+        if let Some((position, &index)) = self
+            .indices
+            .iter()
+            .enumerate()
+            .find(|(_, &index)| index as usize >= self.vertices.len())
+        {
+            return Err(MeshError::IndexOutOfRange {
+                position,
+                index,
+                vertex_count: self.vertices.len(),
+            });
+        }
+        if let Some(vertex) = self.vertices.iter().position(|vertex| !vertex.is_finite()) {
+            return Err(MeshError::NonFiniteVertex { vertex });
+        }
+        if !self.skin_vertices.is_empty() && self.skin_vertices.len() != self.vertices.len() {
+            return Err(MeshError::SkinVertexCountMismatch {
+                skin_vertex_count: self.skin_vertices.len(),
+                vertex_count: self.vertices.len(),
+            });
+        }
+        Ok(())
+    }
+
+    // Triangles with a repeated index or collinear corners, which cover no pixels. This and the
+    // repair functions expect geometry that passed validate.
+    pub fn degenerate_triangles(&self) -> Vec<usize> {
+        self.indices
+            .chunks_exact(3)
+            .enumerate()
+            .filter(|(_, triangle)| self.is_degenerate(triangle))
+            .map(|(triangle, _)| triangle)
+            .collect()
+    }
+
+    // Returns how many triangles were removed, submesh ranges shrink to match.
+    pub fn remove_degenerate_triangles(&mut self) -> usize {
+        let degenerate = self
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| self.is_degenerate(triangle))
+            .collect::<Vec<_>>();
+        // Kept indices before each triangle, to move the submesh ranges with.
+        let mut kept_before = Vec::with_capacity(degenerate.len() + 1);
+        let mut kept = 0;
+        for &is_degenerate in &degenerate {
+            kept_before.push(kept);
+            if !is_degenerate {
+                kept += 3;
+            }
+        }
+        kept_before.push(kept);
+
+        for submesh in &mut self.submeshes {
+            submesh.indices = kept_before[submesh.indices.start as usize / 3]
+                ..kept_before[submesh.indices.end as usize / 3];
+        }
+        let triangle_count = degenerate.len();
+        self.indices = self
+            .indices
+            .chunks_exact(3)
+            .zip(degenerate)
+            .filter(|(_, is_degenerate)| !is_degenerate)
+            .flat_map(|(triangle, _)| triangle.iter().copied())
+            .collect();
+        triangle_count - self.indices.len() / 3
+    }
+
+    // Vertices identical to an earlier one in every attribute, including skinning.
+    pub fn duplicate_vertex_count(&self) -> usize {
+        self.vertices.len() - self.unique_vertices().1
+    }
+
+    // Merges identical vertices, returns how many were removed. Call it after the attributes
+    // are final, e.g. after recompute_normals, since welded vertices can't get different
+    // normals anymore.
+    pub fn weld_vertices(&mut self) -> usize {
+        let (remap, unique_count) = self.unique_vertices();
+        let vertex_count = self.vertices.len();
+
+        // Unique vertices are numbered in order, so each one's first occurrence comes next.
+        let mut vertices = Vec::with_capacity(unique_count);
+        let mut skin_vertices = Vec::with_capacity(unique_count.min(self.skin_vertices.len()));
+        for (vertex, &new_index) in remap.iter().enumerate() {
+            if new_index as usize == vertices.len() {
+                vertices.push(self.vertices[vertex]);
+                skin_vertices.extend(self.skin_vertices.get(vertex));
+            }
+        }
+        for index in &mut self.indices {
+            *index = remap[*index as usize];
+        }
+        self.vertices = vertices;
+        self.skin_vertices = skin_vertices;
+        vertex_count - unique_count
+    }
+
+    // Index of every vertex among the unique ones, in order of first appearance, and how many
+    // unique vertices there are.
+    fn unique_vertices(&self) -> (Vec<u32>, usize) {
+        let mut unique = HashMap::new();
+        let remap = (0..self.vertices.len())
+            .map(|vertex| {
+                let key = (
+                    bytemuck::bytes_of(&self.vertices[vertex]).to_vec(),
+                    self.skin_vertices
+                        .get(vertex)
+                        .map(|skin_vertex| bytemuck::bytes_of(skin_vertex).to_vec()),
+                );
+                let unique_count = unique.len() as u32;
+                *unique.entry(key).or_insert(unique_count)
+            })
+            .collect();
+        (remap, unique.len())
+    }
+
+    fn is_degenerate(&self, triangle: &[u32]) -> bool {
+        if triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[0] == triangle[2] {
+            return true;
+        }
+        let [a, b, c] = [0, 1, 2].map(|corner| self.vertices[triangle[corner] as usize].position);
+        // The sine of the angle at a, so the size of the triangle doesn't matter.
+        let (ab, ac) = (b - a, c - a);
+        ab.cross(&ac).norm() <= f32::EPSILON * ab.norm() * ac.norm()
+    }
+}
@@ -0,0 +1,568 @@
+use anyhow::{anyhow, Result};
+
+// Decoders for the meshoptimizer codecs used by EXT_meshopt_compression, see
+// https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Vendor/EXT_meshopt_compression
+
+const VERTEX_HEADER: u8 = 0xa0;
+const TRIANGLES_HEADER: u8 = 0xe0;
+const INDICES_HEADER: u8 = 0xd0;
+
+const VERTEX_BLOCK_SIZE_BYTES: usize = 8192;
+const VERTEX_BLOCK_MAX_SIZE: usize = 256;
+const BYTE_GROUP_SIZE: usize = 16;
+const TAIL_MIN_SIZE: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MeshoptMode {
+    Attributes,
+    Triangles,
+    Indices,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MeshoptFilter {
+    None,
+    Octahedral,
+    Quaternion,
+    Exponential,
+}
+
+// Reads the data in order and fails instead of panicking on truncated input.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8> {
+        let byte = *self
+            .data
+            .get(self.offset)
+            .ok_or_else(|| anyhow!("Meshopt data ends early at byte {}", self.offset))?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&[u8]> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + count)
+            .ok_or_else(|| anyhow!("Meshopt data ends early at byte {}", self.offset))?;
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    // Little endian base 128, 7 bits per byte with the high bit set on all but the last byte.
+    fn vbyte(&mut self) -> Result<u32> {
+        let mut result = 0;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            result |= ((byte & 127) as u32) << shift;
+            if byte < 128 {
+                break;
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn unzigzag8(value: u8) -> u8 {
+    (value >> 1) ^ (value & 1).wrapping_neg()
+}
+
+fn unzigzag32(value: u32) -> u32 {
+    (value >> 1) ^ (value & 1).wrapping_neg()
+}
+
+// Vertices per block, so that a block of one byte per vertex fits the decoder's buffer.
+fn vertex_block_size(stride: usize) -> usize {
+    ((VERTEX_BLOCK_SIZE_BYTES / stride) & !(BYTE_GROUP_SIZE - 1)).min(VERTEX_BLOCK_MAX_SIZE)
+}
+
+// Fills the buffer with groups of 16 deltas, each packed with 0, 2, 4 or 8 bits per delta as
+// given by a 2 bit header per group. Packed deltas with all bits set are read in full instead.
+fn decode_bytes(reader: &mut Reader, buffer: &mut [u8]) -> Result<()> {
+    let group_count = buffer.len() / BYTE_GROUP_SIZE;
+    let header = reader.bytes(group_count.div_ceil(4))?.to_vec();
+
+    for (group_index, group) in buffer.chunks_exact_mut(BYTE_GROUP_SIZE).enumerate() {
+        let bits_log2 = (header[group_index / 4] >> (group_index % 4 * 2)) & 3;
+        if bits_log2 == 0 {
+            group.fill(0);
+            continue;
+        }
+        if bits_log2 == 3 {
+            group.copy_from_slice(reader.bytes(BYTE_GROUP_SIZE)?);
+            continue;
+        }
+
+        let bits = 1 << bits_log2;
+        let packed = reader.bytes(BYTE_GROUP_SIZE * bits / 8)?.to_vec();
+        let sentinel = (1u8 << bits) - 1;
+        for (index, value) in group.iter_mut().enumerate() {
+            let bit_offset = index * bits;
+            let packed_value = (packed[bit_offset / 8] >> (8 - bits - bit_offset % 8)) & sentinel;
+            *value = if packed_value == sentinel {
+                reader.byte()?
+            } else {
+                packed_value
+            };
+        }
+    }
+    Ok(())
+}
+
+// Each byte of the vertex is stored as zigzag deltas from the same byte of the previous vertex,
+// all of a block's vertices one byte at a time.
+pub(crate) fn decode_vertex_buffer(data: &[u8], count: usize, stride: usize) -> Result<Vec<u8>> {
+    if stride == 0 || stride > 256 || !stride.is_multiple_of(4) {
+        return Err(anyhow!(
+            "Meshopt vertex stride {stride} isn't a multiple of 4 up to 256"
+        ));
+    }
+    let header = *data
+        .first()
+        .ok_or_else(|| anyhow!("Meshopt vertex data is empty"))?;
+    if header & 0xf0 != VERTEX_HEADER || header & 0x0f != 0 {
+        return Err(anyhow!("Unsupported meshopt vertex header {header:#x}"));
+    }
+
+    // The first vertex's values the deltas start from, at the end of the data.
+    let tail_size = stride.max(TAIL_MIN_SIZE);
+    if data.len() < 1 + tail_size {
+        return Err(anyhow!("Meshopt vertex data is too short"));
+    }
+    let mut last_vertex = data[data.len() - stride..].to_vec();
+
+    let mut reader = Reader {
+        data: &data[..data.len() - tail_size],
+        offset: 1,
+    };
+    let mut vertices = vec![0; count * stride];
+    let block_size = vertex_block_size(stride);
+    let mut buffer = vec![0; block_size];
+    for block_start in (0..count).step_by(block_size) {
+        let block_count = block_size.min(count - block_start);
+        let buffer = &mut buffer[..block_count.next_multiple_of(BYTE_GROUP_SIZE)];
+        for byte in 0..stride {
+            decode_bytes(&mut reader, buffer)?;
+            let mut previous = last_vertex[byte];
+            for (index, &delta) in buffer[..block_count].iter().enumerate() {
+                previous = previous.wrapping_add(unzigzag8(delta));
+                vertices[(block_start + index) * stride + byte] = previous;
+            }
+            last_vertex[byte] = previous;
+        }
+    }
+
+    if reader.offset != reader.data.len() {
+        return Err(anyhow!("Meshopt vertex data has trailing bytes"));
+    }
+    Ok(vertices)
+}
+
+struct TriangleDecoder {
+    edges: [(u32, u32); 16],
+    edge_offset: usize,
+    vertices: [u32; 16],
+    vertex_offset: usize,
+    // The next vertex that hasn't been referenced yet.
+    next: u32,
+    // Indices that aren't in the FIFOs are stored as deltas from the last one.
+    last: u32,
+}
+
+impl TriangleDecoder {
+    fn push_edge(&mut self, a: u32, b: u32) {
+        self.edges[self.edge_offset] = (a, b);
+        self.edge_offset = (self.edge_offset + 1) & 15;
+    }
+
+    fn push_vertex(&mut self, vertex: u32, condition: bool) {
+        self.vertices[self.vertex_offset] = vertex;
+        self.vertex_offset = (self.vertex_offset + condition as usize) & 15;
+    }
+
+    fn fifo_vertex(&self, distance: usize) -> u32 {
+        self.vertices[self.vertex_offset.wrapping_sub(distance) & 15]
+    }
+
+    fn delta_index(&mut self, reader: &mut Reader) -> Result<u32> {
+        self.last = self.last.wrapping_add(unzigzag32(reader.vbyte()?));
+        Ok(self.last)
+    }
+}
+
+fn write_index(indices: &mut [u8], position: usize, index_size: usize, index: u32) {
+    let bytes = &mut indices[position * index_size..(position + 1) * index_size];
+    if index_size == 2 {
+        bytes.copy_from_slice(&(index as u16).to_le_bytes());
+    } else {
+        bytes.copy_from_slice(&index.to_le_bytes());
+    }
+}
+
+// Triangles are coded by how their edges and vertices relate to recently seen ones, through a
+// FIFO of edges and one of vertices.
+pub(crate) fn decode_index_buffer(data: &[u8], count: usize, index_size: usize) -> Result<Vec<u8>> {
+    if !count.is_multiple_of(3) || !matches!(index_size, 2 | 4) {
+        return Err(anyhow!(
+            "Meshopt triangles need a multiple of 3 indices of 2 or 4 bytes"
+        ));
+    }
+    if data.len() < 1 + count / 3 + 16 {
+        return Err(anyhow!("Meshopt index data is too short"));
+    }
+    let header = data[0];
+    let version = header & 0x0f;
+    if header & 0xf0 != TRIANGLES_HEADER || version > 1 {
+        return Err(anyhow!("Unsupported meshopt index header {header:#x}"));
+    }
+
+    let codes = &data[1..1 + count / 3];
+    let aux_table = &data[data.len() - 16..];
+    let mut reader = Reader {
+        data: &data[..data.len() - 16],
+        offset: 1 + count / 3,
+    };
+    let mut decoder = TriangleDecoder {
+        edges: [(u32::MAX, u32::MAX); 16],
+        edge_offset: 0,
+        vertices: [u32::MAX; 16],
+        vertex_offset: 0,
+        next: 0,
+        last: 0,
+    };
+    // Version 1 spends two more codes on vertices next to the last free index.
+    let max_fifo_code = if version >= 1 { 13 } else { 15 };
+
+    let mut indices = vec![0; count * index_size];
+    for (triangle, &code) in codes.iter().enumerate() {
+        let (a, b, c) = if code < 0xf0 {
+            // Reuses an edge from the FIFO.
+            let (a, b) =
+                decoder.edges[decoder.edge_offset.wrapping_sub(1 + (code >> 4) as usize) & 15];
+            let vertex_code = (code & 15) as usize;
+            let c = if vertex_code < max_fifo_code {
+                let c = if vertex_code == 0 {
+                    decoder.next
+                } else {
+                    decoder.fifo_vertex(1 + vertex_code)
+                };
+                decoder.next += (vertex_code == 0) as u32;
+                decoder.push_vertex(c, vertex_code == 0);
+                c
+            } else {
+                let c = if vertex_code != 15 {
+                    // 13 and 14 are the vertices right before and after the last free index.
+                    decoder.last = if vertex_code == 13 {
+                        decoder.last.wrapping_sub(1)
+                    } else {
+                        decoder.last.wrapping_add(1)
+                    };
+                    decoder.last
+                } else {
+                    decoder.delta_index(&mut reader)?
+                };
+                decoder.push_vertex(c, true);
+                c
+            };
+            decoder.push_edge(c, b);
+            decoder.push_edge(a, c);
+            (a, b, c)
+        } else if code < 0xfe {
+            // No shared edge, the vertex codes come from the table at the end.
+            let aux = aux_table[(code & 15) as usize];
+            let b_code = (aux >> 4) as usize;
+            let c_code = (aux & 15) as usize;
+            let a = decoder.next;
+            decoder.next += 1;
+            let b = if b_code == 0 {
+                decoder.next
+            } else {
+                decoder.fifo_vertex(b_code)
+            };
+            decoder.next += (b_code == 0) as u32;
+            let c = if c_code == 0 {
+                decoder.next
+            } else {
+                decoder.fifo_vertex(c_code)
+            };
+            decoder.next += (c_code == 0) as u32;
+            decoder.push_vertex(a, true);
+            decoder.push_vertex(b, b_code == 0);
+            decoder.push_vertex(c, c_code == 0);
+            decoder.push_edge(b, a);
+            decoder.push_edge(c, b);
+            decoder.push_edge(a, c);
+            (a, b, c)
+        } else {
+            // No shared edge, the vertex codes follow in the data.
+            let aux = reader.byte()?;
+            let a_code = if code == 0xfe { 0 } else { 15 };
+            let b_code = (aux >> 4) as usize;
+            let c_code = (aux & 15) as usize;
+            if aux == 0 {
+                decoder.next = 0;
+            }
+            let mut vertex = |vertex_code: usize| {
+                if vertex_code == 0 {
+                    decoder.next += 1;
+                    decoder.next - 1
+                } else {
+                    decoder.fifo_vertex(vertex_code)
+                }
+            };
+            let (mut a, mut b, mut c) = (
+                if a_code == 0 { vertex(0) } else { 0 },
+                vertex(b_code),
+                vertex(c_code),
+            );
+            if a_code == 15 {
+                a = decoder.delta_index(&mut reader)?;
+            }
+            if b_code == 15 {
+                b = decoder.delta_index(&mut reader)?;
+            }
+            if c_code == 15 {
+                c = decoder.delta_index(&mut reader)?;
+            }
+            decoder.push_vertex(a, true);
+            decoder.push_vertex(b, b_code == 0 || b_code == 15);
+            decoder.push_vertex(c, c_code == 0 || c_code == 15);
+            decoder.push_edge(b, a);
+            decoder.push_edge(c, b);
+            decoder.push_edge(a, c);
+            (a, b, c)
+        };
+        write_index(&mut indices, triangle * 3, index_size, a);
+        write_index(&mut indices, triangle * 3 + 1, index_size, b);
+        write_index(&mut indices, triangle * 3 + 2, index_size, c);
+    }
+
+    if reader.offset != reader.data.len() {
+        return Err(anyhow!("Meshopt index data has trailing bytes"));
+    }
+    Ok(indices)
+}
+
+// Indices of any topology as deltas from one of the two last indices, e.g. for line lists.
+pub(crate) fn decode_index_sequence(
+    data: &[u8],
+    count: usize,
+    index_size: usize,
+) -> Result<Vec<u8>> {
+    if !matches!(index_size, 2 | 4) {
+        return Err(anyhow!("Meshopt indices need to be 2 or 4 bytes"));
+    }
+    if data.len() < 1 + count + 4 {
+        return Err(anyhow!("Meshopt index sequence is too short"));
+    }
+    let header = data[0];
+    if header & 0xf0 != INDICES_HEADER || header & 0x0f > 1 {
+        return Err(anyhow!(
+            "Unsupported meshopt index sequence header {header:#x}"
+        ));
+    }
+
+    let mut reader = Reader {
+        data: &data[..data.len() - 4],
+        offset: 1,
+    };
+    let mut last = [0u32; 2];
+    let mut indices = vec![0; count * index_size];
+    for position in 0..count {
+        let value = reader.vbyte()?;
+        let baseline = (value & 1) as usize;
+        last[baseline] = last[baseline].wrapping_add(unzigzag32(value >> 1));
+        write_index(&mut indices, position, index_size, last[baseline]);
+    }
+
+    if reader.offset != reader.data.len() {
+        return Err(anyhow!("Meshopt index sequence has trailing bytes"));
+    }
+    Ok(indices)
+}
+
+fn round_to_int(value: f32) -> i32 {
+    (value + if value >= 0.0 { 0.5 } else { -0.5 }) as i32
+}
+
+// Unit vectors in x and y of an octahedral map, with the value 1 is stored as in z. The
+// components are signed bytes for a stride of 4 and signed shorts for a stride of 8.
+fn octahedral_filter(data: &mut [u8], stride: usize) {
+    let component_size = stride / 4;
+    let max = ((1 << (component_size * 8 - 1)) - 1) as f32;
+    let read = |element: &[u8], component: usize| -> f32 {
+        if component_size == 1 {
+            element[component] as i8 as f32
+        } else {
+            i16::from_le_bytes([element[component * 2], element[component * 2 + 1]]) as f32
+        }
+    };
+    for element in data.chunks_exact_mut(stride) {
+        let mut x = read(element, 0);
+        let mut y = read(element, 1);
+        let z = read(element, 2) - x.abs() - y.abs();
+        // Folds the lower hemisphere back out.
+        let t = z.min(0.0);
+        x += if x >= 0.0 { t } else { -t };
+        y += if y >= 0.0 { t } else { -t };
+        let scale = max / (x * x + y * y + z * z).sqrt();
+        for (component, value) in [x, y, z].into_iter().enumerate() {
+            let value = round_to_int(value * scale);
+            if component_size == 1 {
+                element[component] = value as i8 as u8;
+            } else {
+                element[component * 2..component * 2 + 2]
+                    .copy_from_slice(&(value as i16).to_le_bytes());
+            }
+        }
+    }
+}
+
+// Unit quaternions as their three smallest components in signed shorts, the fourth short holds
+// the scale in its upper bits and the index of the largest component in its lowest 2 bits.
+fn quaternion_filter(data: &mut [u8]) {
+    for element in data.chunks_exact_mut(8) {
+        let read = |component: usize| {
+            i16::from_le_bytes([element[component * 2], element[component * 2 + 1]]) as i32
+        };
+        let encoded = read(3);
+        let scale = std::f32::consts::FRAC_1_SQRT_2 / (encoded | 3) as f32;
+        let x = read(0) as f32 * scale;
+        let y = read(1) as f32 * scale;
+        let z = read(2) as f32 * scale;
+        let w = (1.0 - x * x - y * y - z * z).max(0.0).sqrt();
+        let largest = (encoded & 3) as usize;
+        for (offset, value) in [(1, x), (2, y), (3, z), (0, w)] {
+            let component = (largest + offset) & 3;
+            element[component * 2..component * 2 + 2]
+                .copy_from_slice(&(round_to_int(value * 32767.0) as i16).to_le_bytes());
+        }
+    }
+}
+
+// Floats as a signed 24 bit mantissa and a signed 8 bit exponent.
+fn exponential_filter(data: &mut [u8]) {
+    for value in data.chunks_exact_mut(4) {
+        let encoded = i32::from_le_bytes(value.try_into().unwrap());
+        let mantissa = (encoded << 8) >> 8;
+        let exponent = encoded >> 24;
+        let decoded = mantissa as f32 * 2.0f32.powi(exponent);
+        value.copy_from_slice(&decoded.to_le_bytes());
+    }
+}
+
+// Undoes the filter applied before encoding, in place on the decoded vertices.
+pub(crate) fn apply_filter(filter: MeshoptFilter, data: &mut [u8], stride: usize) -> Result<()> {
+    match (filter, stride) {
+        (MeshoptFilter::None, _) => {}
+        (MeshoptFilter::Octahedral, 4 | 8) => octahedral_filter(data, stride),
+        (MeshoptFilter::Quaternion, 8) => quaternion_filter(data),
+        (MeshoptFilter::Exponential, _) if stride.is_multiple_of(4) => exponential_filter(data),
+        _ => {
+            return Err(anyhow!(
+                "Meshopt filter {filter:?} doesn't support a stride of {stride}"
+            ))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Encoded with meshoptimizer's meshopt_encodeIndexBuffer, version 0.
+    const TRIANGLES: [u32; 12] = [0, 1, 2, 2, 1, 3, 4, 6, 5, 7, 8, 9];
+    const ENCODED_TRIANGLES: [u8; 27] = [
+        0xe0, 0xf0, 0x10, 0xfe, 0xff, 0xf0, 0x0c, 0xff, 0x02, 0x02, 0x02, 0x00, 0x76, 0x87, 0x56,
+        0x67, 0x78, 0xa9, 0x86, 0x65, 0x89, 0x68, 0x98, 0x01, 0x69, 0x00, 0x00,
+    ];
+
+    // A 2x2 quad grid over 3x3 vertices, the encoder rotated its fifth triangle from 3, 6, 4
+    // to keep the winding while reusing the previous edge.
+    const GRID: [u32; 24] = [
+        0, 3, 1, 1, 3, 4, 1, 4, 2, 2, 4, 5, 4, 3, 6, 4, 6, 7, 4, 7, 5, 5, 7, 8,
+    ];
+    const ENCODED_GRID: [u8; 32] = [
+        0xe0, 0xfe, 0x1f, 0x00, 0x1f, 0x5f, 0x0f, 0x02, 0x1f, 0xf0, 0x06, 0x02, 0x02, 0x02, 0x02,
+        0x02, 0x00, 0x76, 0x87, 0x56, 0x67, 0x78, 0xa9, 0x86, 0x65, 0x89, 0x68, 0x98, 0x01, 0x69,
+        0x00, 0x00,
+    ];
+
+    // Encoded with meshoptimizer's meshopt_encodeVertexBuffer from test_vertices(), version 0.
+    const ENCODED_VERTICES: [u8; 156] = [
+        0xa0, 0x07, 0x00, 0x58, 0x58, 0x58, 0x58, 0x58, 0x58, 0x58, 0x58, 0x58, 0x58, 0x58, 0x58,
+        0x58, 0x58, 0x58, 0xff, 0x00, 0x00, 0x00, 0x58, 0x58, 0x58, 0x58, 0x05, 0x2a, 0xae, 0xaa,
+        0xea, 0x04, 0x04, 0xae, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x07, 0x00, 0x4a, 0x4a, 0x4a,
+        0x4a, 0x4a, 0x4a, 0x4a, 0x4a, 0x4a, 0x4a, 0x4a, 0x4a, 0x4a, 0x4a, 0x4a, 0xff, 0x00, 0x00,
+        0x00, 0x4a, 0x4a, 0x4a, 0x4a, 0x07, 0x00, 0x1a, 0x4e, 0x82, 0xb6, 0xea, 0xe1, 0xad, 0x79,
+        0x45, 0x11, 0x22, 0x56, 0x8a, 0xbe, 0xf2, 0xff, 0x00, 0x00, 0x00, 0xd9, 0xa5, 0x71, 0x3d,
+        0x05, 0x15, 0x55, 0x55, 0x55, 0x55, 0x00, 0x00, 0x00, 0x07, 0x00, 0x75, 0x76, 0x75, 0x76,
+        0x75, 0x76, 0x75, 0x76, 0x75, 0x76, 0x75, 0x76, 0x75, 0x76, 0x75, 0xff, 0x00, 0x00, 0x00,
+        0x76, 0x75, 0x76, 0x75, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x07, 0x00, 0x0b, 0x00, 0xff, 0x03,
+    ];
+
+    // 20 vertices of 8 bytes: a u16 ramp, constant bytes and bytes that jump around.
+    fn test_vertices() -> Vec<u8> {
+        (0..20u32)
+            .flat_map(|index| {
+                let ramp = (index * 300) as u16;
+                let [low, high] = ramp.to_le_bytes();
+                [
+                    low,
+                    high,
+                    7,
+                    0,
+                    (index * 37 + 11) as u8,
+                    (index * index * 13) as u8,
+                    (255 - index) as u8,
+                    if index % 2 == 1 { 200 } else { 3 },
+                ]
+            })
+            .collect()
+    }
+
+    fn indices_u32(bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks_exact(4)
+            .map(|index| u32::from_le_bytes(index.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_triangles() {
+        let decoded = decode_index_buffer(&ENCODED_TRIANGLES, TRIANGLES.len(), 4).unwrap();
+        assert_eq!(indices_u32(&decoded), TRIANGLES);
+
+        let decoded = decode_index_buffer(&ENCODED_GRID, GRID.len(), 4).unwrap();
+        assert_eq!(indices_u32(&decoded), GRID);
+    }
+
+    #[test]
+    fn decodes_16_bit_triangles() {
+        let decoded = decode_index_buffer(&ENCODED_TRIANGLES, TRIANGLES.len(), 2).unwrap();
+        let indices = decoded
+            .chunks_exact(2)
+            .map(|index| u16::from_le_bytes([index[0], index[1]]) as u32)
+            .collect::<Vec<_>>();
+        assert_eq!(indices, TRIANGLES);
+    }
+
+    #[test]
+    fn decodes_vertices() {
+        let decoded = decode_vertex_buffer(&ENCODED_VERTICES, 20, 8).unwrap();
+        assert_eq!(decoded, test_vertices());
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        assert!(decode_index_buffer(&ENCODED_TRIANGLES[..20], TRIANGLES.len(), 4).is_err());
+        assert!(decode_vertex_buffer(&ENCODED_VERTICES[..100], 20, 8).is_err());
+        assert!(decode_vertex_buffer(&ENCODED_VERTICES, 20, 6).is_err());
+    }
+}
@@ -1,28 +1,122 @@
-mod commands;
-mod geometry;
+pub mod ambient_occlusion;
+pub mod async_readback;
+pub mod atlas;
+pub mod commands;
+pub mod compressed_texture;
+pub mod compute_pass;
+pub mod debug_draw;
+pub mod debug_lines;
+pub mod depth_readback;
+pub mod display_timing;
+#[cfg(feature = "egui")]
+mod egui_overlay;
+pub mod frame_capture;
+pub mod frame_graph;
+pub mod geometry;
+pub mod gltf;
+mod grid;
+mod ibl;
+pub mod image_analysis;
+mod indirect_draws;
+pub(crate) mod json;
+pub mod lens_flare;
+mod light_clusters;
+pub mod lighting;
+pub mod material;
+pub mod mesh_validation;
+mod meshopt;
+pub(crate) mod nan_guard;
+pub mod painter;
+pub mod physical_camera;
+mod point_lights;
+pub mod post_process;
+pub mod present_feedback;
+mod primitives;
+pub mod probe_grid;
+mod procedural_sky;
+pub mod render_graph;
+mod resource_tracker;
+pub mod scene;
+pub mod shader_reload;
+pub mod skinning;
+mod skybox;
+pub mod splash;
 mod staging_belt;
+mod sun_shadow;
 mod swapchain;
+pub mod time_of_day;
+pub mod tonemap;
+pub mod ui_pass;
+pub mod weather;
 pub mod window_renderer;
 
+use crate::image::mip_level_count;
+use crate::query_pool::QueryPool;
+use crate::renderer::atlas::TextureAtlas;
 use crate::renderer::commands::Commands;
-use crate::renderer::geometry::GPUGeometry;
+use crate::renderer::compressed_texture::CompressedTexture;
+use crate::renderer::compute_pass::{compute_barrier, ComputePass, ComputeStage, ComputeTargets};
+use crate::renderer::debug_draw::DebugDraw;
+use crate::renderer::debug_lines::{DebugLineRenderer, DebugLineRendererAttributes, DebugLines};
+use crate::renderer::frame_graph::FrameGraph;
+use crate::renderer::geometry::{GPUGeometry, MeshHandle};
+use crate::renderer::grid::GridRenderer;
+use crate::renderer::ibl::ImageBasedLighting;
+use crate::renderer::indirect_draws::{DrawBucket, DrawIndexedIndirectCommand, IndirectDrawBuffer};
+use crate::renderer::lens_flare::{LensFlare, LensFlareAttributes};
+use crate::renderer::light_clusters::LightClusters;
+use crate::renderer::lighting::{ev100_to_exposure, Lighting};
+use crate::renderer::material::{Material, MaterialSlots, Materials, SamplerAttributes};
+use crate::renderer::nan_guard::{NanGuard, NAN_GUARD_FORMAT};
+use crate::renderer::physical_camera::PhysicalCamera;
+use crate::renderer::point_lights::PointLights;
+use crate::renderer::probe_grid::{ProbeGrid, ProbeGridAttributes, CAPTURE_FORMAT};
+use crate::renderer::procedural_sky::ProceduralSky;
+use crate::renderer::render_graph::{GraphAccess, RenderGraph};
+use crate::renderer::scene::{NodeAttachment, Scene};
+use crate::renderer::shader_reload::{ShaderReloadAttributes, ShaderWatcher};
+use crate::renderer::skinning::{JointAttachment, JointBuffer};
+use crate::renderer::skybox::SkyboxRenderer;
 use crate::renderer::staging_belt::StagingBelt;
-use crate::rendering_context::{Image, RenderingContext};
+use crate::renderer::sun_shadow::SunShadow;
+use crate::renderer::time_of_day::TimeOfDay;
+use crate::renderer::weather::{Weather, WeatherAttributes};
+use crate::rendering_context::{
+    GraphicsPipelineAttributes, Image, ImageLayoutState, RenderingContext,
+};
+use crate::shader_reflection::{ReflectedBinding, ShaderReflection};
+use crate::sparse_image::{SparseImage, SparseImageAttributes};
+use crate::time::Time;
 use anyhow::Result;
 use ash::vk;
-use geometry::Geometry;
+use geometry::{Geometry, Vertex};
 use gpu_allocator::vulkan::{AllocationScheme, Allocator};
 use gpu_allocator::MemoryLocation;
 use itertools::multizip;
-use std::path::Path;
+use std::collections::{BTreeSet, HashMap};
+use std::f32::consts::PI;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Instant;
+use tracing::{info, warn};
 
-struct Frame {
+pub use point_lights::{
+    PointLight, SpotLight, MAX_SHADOWED_POINT_LIGHTS, MAX_SHADOWED_SPOT_LIGHTS,
+};
+pub use procedural_sky::ProceduralSkyAttributes;
+pub use sun_shadow::SunShadowAttributes;
+
+pub(crate) struct Frame {
     render_target: Image,
     depth_buffer: Image,
     msaa_render_target: Image,
     msaa_depth_buffer: Image,
+    occlusion_query_pool: Option<QueryPool>,
+    has_occlusion_queries: bool,
+    predicate_buffer: Option<Buffer>,
+    has_predicates: bool,
+    timestamp_query_pool: Option<QueryPool>,
+    has_timestamps: bool,
 }
 
 pub struct Renderer {
@@ -32,13 +126,30 @@ pub struct Renderer {
     context: Arc<RenderingContext>,
     frames: Vec<Frame>,
     staging_belt: StagingBelt,
-    gpu_geometry: GPUGeometry,
-    camera_buffer: Buffer,
+    // One per frame in flight, grown on demand once that frame's fence has been waited on.
+    upload_belts: Vec<Option<StagingBelt>>,
+    pending_uploads: Vec<PendingUpload>,
+    // Indexed by MeshHandle, removed meshes leave an empty slot behind.
+    meshes: Vec<Option<GPUGeometry>>,
+    // Removed meshes with the frame count from which no frame in flight can draw them anymore.
+    retired_meshes: Vec<(GPUGeometry, u64)>,
+    // Scene shaders the pipeline was built from, rebuilt when the watcher sees them change.
+    shaders: ShaderPaths,
+    shader_watcher: Option<ShaderWatcher>,
+    // Replaced pipelines with the frame count from which no frame in flight can bind them.
+    retired_pipelines: Vec<(vk::Pipeline, u64)>,
+    compute_passes: Vec<ComputePass>,
+    // Per frame in flight, so the CPU never writes data a previous frame may still read.
+    scene_buffers: Vec<SceneBuffer>,
     cameras: Vec<Camera>,
-    pub start_time: Instant,
     attributes: RendererAttributes,
-    instance_buffer: Buffer,
     instances: Vec<Instance>,
+    // World transforms of the last rendered frame by instance, while previous transforms are
+    // kept. Instances added since then have none.
+    previous_transforms: Vec<na::Matrix4<f64>>,
+    // By instance, which ones are cached in the static point shadows, see update_shadow_casters.
+    shadow_casters: Vec<ShadowCaster>,
+    scene: Scene,
 
     descriptor_set_layout: vk::DescriptorSetLayout,
     descriptor_pool: vk::DescriptorPool,
@@ -46,8 +157,101 @@ pub struct Renderer {
 
     textures: Vec<Image>,
     pub texture_sampler: vk::Sampler,
+    materials: Materials,
+    // Samplers of materials that don't use the default one, with the bindless slots of the
+    // textures sampled through them, allocated downwards from the end of the array.
+    samplers: HashMap<SamplerAttributes, vk::Sampler>,
+    sampled_textures: HashMap<(u32, SamplerAttributes), u32>,
+
+    occlusion_results: Vec<u64>,
+    gpu_timings: Vec<(&'static str, f32)>,
+    frame_count: u64,
+    // Passes of the last rendered frame, the window renderer appends its own.
+    pub frame_graph: FrameGraph,
+
+    probe_grid: Option<ProbeGrid>,
+    // Scene pipeline drawing the probe captures, null unless the probe grid captures.
+    probe_capture_pipeline: vk::Pipeline,
+
+    joint_buffer: JointBuffer,
+
+    point_lights: PointLights,
+    light_clusters: LightClusters,
+    sun_shadow: SunShadow,
+    // Per frame in flight draw lists of the visible instances, when indirect count draws
+    // are supported.
+    indirect_draw_buffers: Option<Vec<IndirectDrawBuffer>>,
+
+    debug_lines: DebugLines,
+    debug_line_renderer: DebugLineRenderer,
+    grid_renderer: GridRenderer,
+    weather: Weather,
+    // Added onto the resolved scene while enabled.
+    lens_flare: LensFlare,
+    skybox_renderer: SkyboxRenderer,
+    image_based_lighting: ImageBasedLighting,
+    // Drawn and prefiltered in place of the skybox while enabled.
+    procedural_sky: ProceduralSky,
+    // Drives the sun and the procedural sky while set.
+    time_of_day: Option<TimeOfDay>,
+    // Set when a new skybox is uploaded, for the lighting to be prefiltered from it.
+    prefilter_environment: bool,
+    nan_guard: Option<NanGuard>,
+    pub debug_visualizations: DebugVisualizations,
+    // Selected instances are drawn with an outline around their silhouette.
+    selection: BTreeSet<usize>,
+    outline_pipeline: vk::Pipeline,
+    pub outline: OutlineStyle,
+    pub lighting: Lighting,
+    frozen_camera: Option<Camera>,
+    camera_look_at: Option<(na::Point3<f64>, na::Point3<f64>)>,
+    // Eye and target the last frame was rendered from, including the default orbit.
+    frame_look_at: (na::Point3<f64>, na::Point3<f64>),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct OutlineStyle {
+    pub color: na::Vector4<f32>,
+    pub width_pixels: f32,
+}
+
+impl Default for OutlineStyle {
+    fn default() -> Self {
+        Self {
+            color: na::Vector4::new(1.0, 0.6, 0.1, 1.0),
+            width_pixels: 2.0,
+        }
+    }
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct DebugVisualizations {
+    pub grid: bool,
+    pub camera_frustum: bool,
+    // World space bounds of every visible instance, to check culling against.
+    pub bounding_boxes: bool,
+    // View space distances between cascades, each slice of the camera frustum is drawn in its own color.
+    pub cascade_splits: Vec<f32>,
+    // Paints NaN and infinite pixels and depths outside [0, 1] magenta; needs a half float
+    // render target.
+    pub nan_guard: bool,
+}
+
+const CASCADE_COLORS: [[f32; 4]; 4] = [
+    [1.0, 0.2, 0.2, 1.0],
+    [0.2, 1.0, 0.2, 1.0],
+    [0.2, 0.4, 1.0, 1.0],
+    [1.0, 1.0, 0.2, 1.0],
+];
+
+// Timestamps are written at the boundaries between these passes.
+const GPU_PASSES: [&str; 2] = ["scene", "overlays"];
+
+// Occluded instances stop producing samples once their draws are skipped, so every
+// few frames of each frame in flight everything is drawn unconditionally to refresh its
+// predicates.
+const PREDICATE_REFRESH_INTERVAL: u64 = 16;
+
 const SHADERS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/res/shaders/");
 
 fn load_shader_module(
@@ -58,38 +262,230 @@ fn load_shader_module(
     context.create_shader_module(&code)
 }
 
+// Full mip chains for formats that can be blitted with linear filtering, one level otherwise.
+fn texture_mip_levels(context: &RenderingContext, extent: vk::Extent2D, format: vk::Format) -> u32 {
+    if context.supports_format(
+        format,
+        vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        vk::ImageTiling::OPTIMAL,
+    ) {
+        mip_level_count(extent)
+    } else {
+        1
+    }
+}
+
 use crate::buffer::{Buffer, BufferAttributes};
-use crate::image::ImageAttributes;
 use nalgebra as na;
 
+// The scene side is kept in f64 so far from the origin objects don't jitter; the GPU only sees
+// f32 positions relative to the camera.
+#[derive(Clone, Copy)]
 struct Camera {
-    view: na::Isometry3<f32>,
+    view: na::Isometry3<f64>,
     projection: na::Perspective3<f32>,
+    // Only instances sharing a layer with this mask are drawn.
+    cull_mask: u32,
+    // Overrides the projection's field of view and drives exposure when set.
+    physical: Option<PhysicalCamera>,
+    // Fixed exposure taking precedence over the physical camera's.
+    exposure: Option<f32>,
 }
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-struct GPUCamera {
+pub(crate) struct GPUCamera {
     view: na::Matrix4<f32>,
     projection: na::Matrix4<f32>,
     position: na::Vector3<f32>,
+    // World position of the camera, for shaders that need absolute positions.
+    origin: na::Vector3<f32>,
+    exposure: f32,
+    // Keeps the size a multiple of 16, so single cameras can be referenced by address.
+    padding: u32,
+    // Towards the sun, see Lighting.
+    sun_direction: na::Vector3<f32>,
+    sun_illuminance: f32,
+    sun_color: na::Vector3<f32>,
+    ambient_illuminance: f32,
+    baked_illuminance: f32,
+    sky_luminance: f32,
+    // World space size of a sun shadow map texel, for the normal offset when sampling it.
+    sun_shadow_texel_size: f32,
+    // Of the surfaces, scaled per material.
+    wetness: f32,
+    // From positions relative to the camera to the sun shadow map's clip space.
+    sun_shadow_matrix: na::Matrix4<f32>,
 }
 
+// Maps depth buffer samples of a frame back to world space, see Renderer::unproject.
+#[derive(Clone, Copy, Debug)]
+pub struct Unprojection {
+    inverse_view_projection: na::Matrix4<f64>,
+    camera_position: na::Point3<f64>,
+}
+
+impl Unprojection {
+    pub fn unproject(&self, ndc: na::Point2<f64>, depth: f64) -> Option<na::Point3<f64>> {
+        if depth >= 1.0 {
+            return None;
+        }
+        let relative = self
+            .inverse_view_projection
+            .transform_point(&na::Point3::new(ndc.x, ndc.y, depth));
+        Some(self.camera_position + relative.coords)
+    }
+}
+
+// Closest instance under a point of the screen, see Renderer::hit_test.
+#[derive(Clone, Copy, Debug)]
+pub struct InstanceHit {
+    pub instance: usize,
+    // The instance's user data, see Renderer::set_instance_user_data.
+    pub user_data: u64,
+    pub distance: f64,
+    // Where the ray enters the instance's bounding box.
+    pub position: na::Point3<f64>,
+}
+
+// Distance along the ray to where it enters the box, if it hits it at all.
+fn ray_box_intersection(
+    origin: &na::Point3<f64>,
+    direction: &na::Vector3<f64>,
+    min: &na::Vector3<f64>,
+    max: &na::Vector3<f64>,
+) -> Option<f64> {
+    let mut near = 0.0f64;
+    let mut far = f64::INFINITY;
+    for axis in 0..3 {
+        let inverse = 1.0 / direction[axis];
+        let t0 = (min[axis] - origin[axis]) * inverse;
+        let t1 = (max[axis] - origin[axis]) * inverse;
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+    }
+    (near <= far).then_some(near)
+}
+
+pub const DEFAULT_LAYER: u32 = 1;
+pub const ALL_LAYERS: u32 = u32::MAX;
+
 struct Instance {
-    transform: na::Affine3<f32>,
+    transform: na::Affine3<f64>,
+    layers: u32,
+    material_index: u32,
+    mesh: MeshHandle,
+    attachment: Option<JointAttachment>,
+    user_data: u64,
 }
 
+#[derive(Clone, Copy, Default)]
+struct ShadowCaster {
+    // As of the last rendered frame, None for instances added since then.
+    world_transform: Option<na::Matrix4<f64>>,
+    // Moved or skinned at some point, drawn into the shadow maps every frame from then on.
+    dynamic: bool,
+}
+
+// Per object record of the scene buffer, addressed by instance index by every pass.
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-struct GPUInstance {
+struct GPUObject {
     transform: na::Matrix4<f32>,
+    // The transform of the last rendered frame, relative to the current camera position like
+    // transform. Matches transform unless RendererAttributes::previous_transforms is set.
+    previous_transform: na::Matrix4<f32>,
+    // Object space bounds of the mesh.
+    bounds_min: na::Vector3<f32>,
+    material_index: u32,
+    bounds_max: na::Vector3<f32>,
+    flags: u32,
+}
+
+const OBJECT_VISIBLE_FLAG: u32 = 1;
+const OBJECT_SELECTED_FLAG: u32 = 2;
+
+// One buffer holding the cameras followed by the object table, the push constants point
+// into either section.
+struct SceneBuffer {
+    buffer: Buffer,
+    objects_offset: vk::DeviceSize,
+}
+
+impl SceneBuffer {
+    fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        camera_count: usize,
+        object_count: usize,
+    ) -> Result<Self> {
+        // GPUCamera's size keeps the objects section aligned for buffer references.
+        let objects_offset = (camera_count * size_of::<GPUCamera>()) as vk::DeviceSize;
+        let buffer = Buffer::new(
+            allocator,
+            BufferAttributes {
+                name: "scene_buffer".into(),
+                context,
+                size: objects_offset + (object_count * size_of::<GPUObject>()) as vk::DeviceSize,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                location: MemoryLocation::CpuToGpu,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                allocation_priority: 1.0,
+            },
+        )?;
+        Ok(Self {
+            buffer,
+            objects_offset,
+        })
+    }
+
+    fn object_capacity(&self) -> usize {
+        (self.buffer.attributes.size - self.objects_offset) as usize / size_of::<GPUObject>()
+    }
+
+    fn camera_address(&self) -> vk::DeviceAddress {
+        self.buffer.address
+    }
+
+    fn objects_address(&self) -> vk::DeviceAddress {
+        self.buffer.address + self.objects_offset
+    }
+
+    fn write(&mut self, cameras: &[GPUCamera], objects: &[GPUObject]) -> Result<()> {
+        self.buffer.write(cameras, 0)?;
+        self.buffer.write(objects, self.objects_offset)
+    }
+}
+
+// Holds one occlusion predicate per instance.
+fn create_predicate_buffer(
+    context: Arc<RenderingContext>,
+    allocator: &mut Allocator,
+    instance_capacity: usize,
+) -> Result<Buffer> {
+    Buffer::new(
+        allocator,
+        BufferAttributes {
+            name: "predicate_buffer".into(),
+            context,
+            size: (instance_capacity.max(1) * size_of::<u32>()) as vk::DeviceSize,
+            usage: vk::BufferUsageFlags::CONDITIONAL_RENDERING_EXT
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            location: MemoryLocation::GpuOnly,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            allocation_priority: 1.0,
+        },
+    )
 }
 
 impl Instance {
     fn new(
-        position: na::Vector3<f32>,
-        rotation: na::UnitQuaternion<f32>,
-        scale: na::Vector3<f32>,
+        position: na::Vector3<f64>,
+        rotation: na::UnitQuaternion<f64>,
+        scale: na::Vector3<f64>,
     ) -> Self {
         Self {
             transform: na::Affine3::from_matrix_unchecked(
@@ -97,20 +493,19 @@ impl Instance {
                     * na::Matrix4::from(rotation)
                     * na::Matrix4::new_nonuniform_scaling(&scale),
             ),
-        }
-    }
-
-    fn to_gpu_instance(&self) -> GPUInstance {
-        GPUInstance {
-            transform: self.transform.to_homogeneous(),
+            layers: DEFAULT_LAYER,
+            material_index: 0,
+            mesh: MeshHandle::DEFAULT,
+            attachment: None,
+            user_data: 0,
         }
     }
 }
 
 impl Camera {
     fn new(
-        eye: &na::Point3<f32>,
-        target: &na::Point3<f32>,
+        eye: &na::Point3<f64>,
+        target: &na::Point3<f64>,
         aspect_ratio: f32,
         fovy: f32,
         znear: f32,
@@ -119,18 +514,48 @@ impl Camera {
         Self {
             view: na::Isometry3::look_at_rh(eye, target, &na::Vector3::y()),
             projection: na::Perspective3::new(aspect_ratio, fovy, znear, zfar),
+            cull_mask: ALL_LAYERS,
+            physical: None,
+            exposure: None,
         }
     }
 
-    fn view_projection(&self) -> na::Matrix4<f32> {
-        self.projection.to_homogeneous() * self.view.to_homogeneous()
+    fn position(&self) -> na::Point3<f64> {
+        self.view.inverse_transform_point(&na::Point3::origin())
+    }
+
+    // View matrix of the camera relative space, where the camera sits at the origin.
+    fn relative_view(&self) -> na::Matrix4<f32> {
+        self.view.rotation.to_homogeneous().cast()
+    }
+
+    // Maps clip space back to world space through the given projection.
+    fn inverse_view_projection(&self, projection: &na::Perspective3<f32>) -> na::Matrix4<f32> {
+        na::Matrix4::new_translation(&self.position().coords.cast::<f32>())
+            * (projection.to_homogeneous() * self.relative_view())
+                .try_inverse()
+                .unwrap_or_default()
     }
 
-    fn to_gpu_camera(&self) -> GPUCamera {
+    fn gpu_camera(&self, lighting: &Lighting) -> GPUCamera {
         GPUCamera {
-            view: self.view.to_homogeneous(),
+            view: self.relative_view(),
             projection: self.projection.to_homogeneous(),
-            position: self.view.translation.vector,
+            position: na::Vector3::zeros(),
+            origin: self.position().coords.cast(),
+            exposure: self
+                .exposure
+                .unwrap_or_else(|| self.physical.map_or(1.0, |physical| physical.exposure())),
+            padding: 0,
+            sun_direction: -lighting.sun_direction.normalize(),
+            sun_illuminance: lighting.sun_illuminance,
+            sun_color: lighting.sun_color,
+            ambient_illuminance: lighting.ambient_illuminance,
+            baked_illuminance: lighting.baked_illuminance,
+            sky_luminance: lighting.sky_luminance,
+            sun_shadow_texel_size: 0.0,
+            wetness: 0.0,
+            sun_shadow_matrix: na::Matrix4::identity(),
         }
     }
 }
@@ -139,8 +564,75 @@ impl Camera {
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 struct PushConstants {
     vertex_buffer_address: vk::DeviceAddress,
-    instance_buffer_address: vk::DeviceAddress,
+    scene_buffer_address: vk::DeviceAddress,
     camera_buffer_address: vk::DeviceAddress,
+    flags: u32,
+    padding: u32,
+    probe_grid_address: vk::DeviceAddress,
+    skin_buffer_address: vk::DeviceAddress,
+    joint_buffer_address: vk::DeviceAddress,
+    joint_offset_buffer_address: vk::DeviceAddress,
+    position_offset: na::Vector3<f32>,
+    position_scale: na::Vector3<f32>,
+    outline_color: na::Vector4<f32>,
+    // Outline width in clip space units per axis.
+    outline_size: na::Vector2<f32>,
+    point_light_buffer_address: vk::DeviceAddress,
+    material_buffer_address: vk::DeviceAddress,
+}
+
+const PROBE_GRID_FLAG: u32 = 1;
+const SKINNING_FLAG: u32 = 2;
+const QUANTIZED_FLAG: u32 = 4;
+const IMAGE_BASED_LIGHTING_FLAG: u32 = 8;
+const SUN_SHADOW_FLAG: u32 = 16;
+const UNCLUSTERED_LIGHTS_FLAG: u32 = 32;
+
+const INVALID_TEXTURE_INDEX: u32 = u32::MAX;
+const MAX_TEXTURES: u32 = 1000;
+// Texture uploads start at a multiple of the largest texel block, BC2, BC3, BC5, BC6H and BC7.
+const TEXEL_ALIGNMENT: vk::DeviceSize = 16;
+
+// Writes queued between frames, recorded at the start of the next frame's command buffer.
+enum PendingUpload {
+    // Mip levels largest first, the rest of the chain is generated when only one is given.
+    Texture {
+        index: usize,
+        levels: Vec<Vec<u8>>,
+    },
+    Mesh {
+        mesh: MeshHandle,
+        size: vk::DeviceSize,
+    },
+    // The mesh's dirty vertex range, see GPUGeometry::update_vertices.
+    Vertices {
+        mesh: MeshHandle,
+        size: vk::DeviceSize,
+    },
+    // Mip levels of the skybox cube map with the six faces of each level one after the other.
+    Skybox {
+        levels: Vec<Vec<u8>>,
+    },
+}
+
+impl PendingUpload {
+    fn size(&self) -> vk::DeviceSize {
+        match self {
+            PendingUpload::Texture { levels, .. } | PendingUpload::Skybox { levels } => levels
+                .iter()
+                .map(|pixels| (pixels.len() as vk::DeviceSize).next_multiple_of(TEXEL_ALIGNMENT))
+                .sum(),
+            PendingUpload::Mesh { size, .. } | PendingUpload::Vertices { size, .. } => *size,
+        }
+    }
+}
+
+// Compiled SPIR-V used instead of the built-in shader.vert/shader.frag. The shaders have to
+// accept the same push constants and descriptor set as the built-in ones.
+#[derive(Clone, Debug)]
+pub struct ShaderPaths {
+    pub vertex: PathBuf,
+    pub fragment: PathBuf,
 }
 
 pub struct RendererAttributes {
@@ -148,20 +640,183 @@ pub struct RendererAttributes {
     pub format: vk::Format,
     pub depth_format: vk::Format,
     pub buffering: usize,
+    pub occlusion_queries: bool,
+    pub conditional_rendering: bool,
+    pub probe_grid: Option<ProbeGridAttributes>,
+    pub grid: bool,
+    pub shaders: Option<ShaderPaths>,
+    pub vertex_input: bool,
+    pub quantize_vertices: bool,
+    pub depth_resolve_mode: vk::ResolveModeFlags,
+    // Bytes of texture data uploaded per frame, None uploads everything queued right away.
+    pub upload_budget: Option<vk::DeviceSize>,
+    // Rebuilds the scene pipeline when its shaders change on disk.
+    pub shader_reload: Option<ShaderReloadAttributes>,
+    // Keeps every instance's transform of the last rendered frame in the object table, for
+    // motion vectors and other velocity based effects.
+    pub previous_transforms: bool,
+    // Initial scene lighting, including the direction the sun casts its shadow in.
+    pub lighting: Lighting,
+    // Shadow map of the sun, None leaves the sun unshadowed.
+    pub sun_shadow: Option<SunShadowAttributes>,
+}
+
+// Reflected from every shader drawn with the scene layout, plus the bindings the renderer
+// writes whether or not the shaders kept them.
+fn create_scene_pipeline_layout(
+    context: &RenderingContext,
+    shaders: &ShaderPaths,
+) -> Result<(vk::DescriptorSetLayout, vk::PipelineLayout)> {
+    let layout_shaders = [
+        shaders.vertex.clone(),
+        shaders.fragment.clone(),
+        (SHADERS_DIR.to_owned() + "outline.vert.spv").into(),
+        (SHADERS_DIR.to_owned() + "outline.frag.spv").into(),
+        (SHADERS_DIR.to_owned() + "shadow.vert.spv").into(),
+        (SHADERS_DIR.to_owned() + "shadow_cube.vert.spv").into(),
+        (SHADERS_DIR.to_owned() + "shadow.frag.spv").into(),
+    ]
+    .iter()
+    .map(|path: &PathBuf| ShaderReflection::new(&std::fs::read(path)?))
+    .collect::<Result<Vec<_>>>()?;
+    let mut reflection = ShaderReflection::merge(&layout_shaders)?;
+
+    reflection.add_binding(ReflectedBinding {
+        set: 0,
+        binding: 0,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        count: None,
+        stages: vk::ShaderStageFlags::FRAGMENT,
+    })?;
+    // Point light shadow cube maps.
+    reflection.add_binding(ReflectedBinding {
+        set: 0,
+        binding: 1,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        count: Some(MAX_SHADOWED_POINT_LIGHTS as u32),
+        stages: vk::ShaderStageFlags::FRAGMENT,
+    })?;
+    // Irradiance and prefiltered specular cube maps, the BRDF lookup table, the sun's shadow
+    // map and the spot light shadow map array.
+    for binding in 2..7 {
+        reflection.add_binding(ReflectedBinding {
+            set: 0,
+            binding,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            count: Some(1),
+            stages: vk::ShaderStageFlags::FRAGMENT,
+        })?;
+    }
+    if reflection.set_count() > 1 {
+        return Err(anyhow::anyhow!(
+            "Scene shaders can only use descriptor set 0"
+        ));
+    }
+    if reflection.push_constant_size as usize > size_of::<PushConstants>() {
+        return Err(anyhow::anyhow!(
+            "Scene shaders expect {} bytes of push constants, the renderer provides {}",
+            reflection.push_constant_size,
+            size_of::<PushConstants>()
+        ));
+    }
+    // Point lights push the full block, whatever the shaders read.
+    reflection.push_constant_size = size_of::<PushConstants>() as u32;
+
+    let (set_layouts, pipeline_layout) = context.create_reflected_pipeline_layout(
+        &reflection,
+        vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+        MAX_TEXTURES,
+        |binding| match binding.count {
+            None => {
+                vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                    | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+            }
+            Some(_) => vk::DescriptorBindingFlags::UPDATE_AFTER_BIND,
+        },
+    )?;
+    Ok((set_layouts[0], pipeline_layout))
+}
+
+fn create_scene_pipeline(
+    context: &RenderingContext,
+    shaders: &ShaderPaths,
+    attributes: &RendererAttributes,
+    pipeline_layout: vk::PipelineLayout,
+    color_format: vk::Format,
+    samples: vk::SampleCountFlags,
+) -> Result<vk::Pipeline> {
+    let vertex_shader = load_shader_module(context, &shaders.vertex)?;
+    let fragment_shader = match load_shader_module(context, &shaders.fragment) {
+        Ok(fragment_shader) => fragment_shader,
+        Err(error) => {
+            unsafe { context.device.destroy_shader_module(vertex_shader, None) };
+            return Err(error);
+        }
+    };
+
+    let pipeline = context.create_graphics_pipeline(GraphicsPipelineAttributes {
+        vertex_shader,
+        fragment_shader,
+        extent: attributes.extent,
+        color_format,
+        depth_format: attributes.depth_format,
+        samples,
+        topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        line_width: 1.0,
+        dynamic_line_width: false,
+        dynamic_topology: false,
+        alpha_blending: false,
+        additive_blending: false,
+        depth_write: true,
+        cull_mode: vk::CullModeFlags::NONE,
+        viewport_count: 1,
+        vertex_layout: if attributes.vertex_input {
+            Vertex::layout()
+        } else {
+            Default::default()
+        },
+        pipeline_layout,
+        pipeline_cache: Default::default(),
+    });
+
+    unsafe {
+        context.device.destroy_shader_module(vertex_shader, None);
+        context.device.destroy_shader_module(fragment_shader, None);
+    }
+
+    pipeline
 }
 
 impl Renderer {
     pub fn new(
         context: Arc<RenderingContext>,
         commands: &Commands,
+        // Shared with the window's other passes, e.g. its splash screen during loading.
+        mut allocator: Allocator,
         attributes: RendererAttributes,
+        // Called with the loading progress in [0, 1] between the slow steps, e.g. to update a
+        // splash screen.
+        progress: &mut dyn FnMut(f32) -> Result<()>,
     ) -> Result<Self> {
-        let vertex_shader =
-            load_shader_module(context.as_ref(), SHADERS_DIR.to_owned() + "shader.vert.spv")?;
-        let fragment_shader =
-            load_shader_module(context.as_ref(), SHADERS_DIR.to_owned() + "shader.frag.spv")?;
+        if attributes.vertex_input && attributes.quantize_vertices {
+            return Err(anyhow::anyhow!(
+                "Vertex quantization is only supported with vertex pulling"
+            ));
+        }
 
-        let mut allocator = context.create_allocator(Default::default(), Default::default())?;
+        let shaders = attributes.shaders.clone().unwrap_or_else(|| ShaderPaths {
+            vertex: (SHADERS_DIR.to_owned()
+                + if attributes.vertex_input {
+                    "shader_vertex_input.vert.spv"
+                } else {
+                    "shader.vert.spv"
+                })
+            .into(),
+            fragment: (SHADERS_DIR.to_owned() + "shader.frag.spv").into(),
+        });
+        let shader_watcher = attributes.shader_reload.clone().map(|shader_reload| {
+            ShaderWatcher::new(shader_reload, &[&shaders.vertex, &shaders.fragment])
+        });
 
         let render_targets = (0..attributes.buffering)
             .map(|_| {
@@ -213,9 +868,7 @@ impl Renderer {
             })
             .collect::<Result<Vec<_>>>()?;
 
-        use itertools::Itertools;
-
-        let frames = multizip((
+        let mut frames = multizip((
             render_targets,
             depth_buffers,
             msaa_render_targets,
@@ -227,24 +880,34 @@ impl Renderer {
                 depth_buffer,
                 msaa_render_target,
                 msaa_depth_buffer,
+                occlusion_query_pool: None,
+                has_occlusion_queries: false,
+                predicate_buffer: None,
+                has_predicates: false,
+                timestamp_query_pool: None,
+                has_timestamps: false,
             },
         )
-        .collect();
+        .collect::<Vec<_>>();
 
         unsafe {
-            let gpu_geometry = Geometry::load_obj("res/viking_room.obj")?
-                .create_gpu_geometry(context.clone(), &mut allocator)?;
+            let gpu_geometry = Geometry::load_obj("res/viking_room.obj")?.create_gpu_geometry(
+                context.clone(),
+                &mut allocator,
+                attributes.quantize_vertices,
+            )?;
+            progress(0.25)?;
 
             // generate instances in a grid
             let instances = (-2..2)
                 .flat_map(|x| {
                     (-2..2).map(move |y| {
                         Instance::new(
-                            na::Vector3::new(x as f32 * 2.0, 0.0, y as f32 * 2.0),
+                            na::Vector3::new(x as f64 * 2.0, 0.0, y as f64 * 2.0),
                             // rotate 90 degrees around the y-axis
                             na::UnitQuaternion::from_axis_angle(
                                 &na::Unit::new_normalize(na::Vector3::x()),
-                                std::f32::consts::FRAC_PI_2,
+                                std::f64::consts::FRAC_PI_2,
                             ),
                             na::Vector3::new(1.0, 1.0, 1.0),
                         )
@@ -252,70 +915,175 @@ impl Renderer {
                 })
                 .collect::<Vec<_>>();
 
-            let gpu_instances = instances
-                .iter()
-                .map(Instance::to_gpu_instance)
-                .collect::<Vec<_>>();
+            // Moving the grid node moves all of them.
+            let mut scene = Scene::default();
+            let grid = scene.add_node("grid", None, na::Affine3::identity());
+            for (index, instance) in instances.iter().enumerate() {
+                let node =
+                    scene.add_node(&format!("instance_{index}"), Some(grid), instance.transform);
+                scene.attach(node, NodeAttachment::Instance(index));
+            }
 
-            let instance_buffer = Buffer::new(
-                &mut allocator,
-                BufferAttributes {
-                    name: "instance_buffer".into(),
-                    context: context.clone(),
-                    size: (instances.len() * size_of::<Instance>()) as vk::DeviceSize,
-                    usage: vk::BufferUsageFlags::VERTEX_BUFFER
-                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
-                        | vk::BufferUsageFlags::TRANSFER_DST,
-                    location: MemoryLocation::GpuOnly,
-                    allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-                    allocation_priority: 1.0,
-                },
+            if context
+                .physical_device
+                .properties
+                .limits
+                .timestamp_compute_and_graphics
+                == vk::TRUE
+            {
+                for frame in frames.iter_mut() {
+                    frame.timestamp_query_pool = Some(QueryPool::new_timestamp(
+                        context.clone(),
+                        GPU_PASSES.len() as u32 + 1,
+                    )?);
+                }
+            }
+
+            if attributes.occlusion_queries {
+                if attributes.conditional_rendering
+                    && context.conditional_rendering_extension.is_none()
+                {
+                    warn!("Conditional rendering is not supported, drawing without predicates");
+                }
+                for frame in frames.iter_mut() {
+                    frame.occlusion_query_pool = Some(QueryPool::new_occlusion(
+                        context.clone(),
+                        instances.len() as u32,
+                    )?);
+
+                    if attributes.conditional_rendering
+                        && context.conditional_rendering_extension.is_some()
+                    {
+                        frame.predicate_buffer = Some(create_predicate_buffer(
+                            context.clone(),
+                            &mut allocator,
+                            instances.len(),
+                        )?);
+                    }
+                }
+            }
+
+            let (descriptor_set_layout, pipeline_layout) =
+                create_scene_pipeline_layout(&context, &shaders)?;
+
+            let pipeline = create_scene_pipeline(
+                &context,
+                &shaders,
+                &attributes,
+                pipeline_layout,
+                attributes.format,
+                vk::SampleCountFlags::TYPE_4,
             )?;
 
-            let descriptor_set_layout = context.device.create_descriptor_set_layout(
-                &vk::DescriptorSetLayoutCreateInfo::default()
-                    .bindings(&[vk::DescriptorSetLayoutBinding::default()
-                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                        .descriptor_count(1000)
-                        .stage_flags(vk::ShaderStageFlags::ALL)])
-                    .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
-                    .push_next(
-                        &mut vk::DescriptorSetLayoutBindingFlagsCreateInfo::default()
-                            .binding_flags(&[vk::DescriptorBindingFlags::PARTIALLY_BOUND
-                                | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND]),
-                    ),
-                None,
+            // Inverted hull: only the back faces of the silhouette extruded shell end up visible
+            // around the already drawn instance.
+            let outline_vertex_shader = load_shader_module(
+                context.as_ref(),
+                SHADERS_DIR.to_owned() + "outline.vert.spv",
+            )?;
+            let outline_fragment_shader = load_shader_module(
+                context.as_ref(),
+                SHADERS_DIR.to_owned() + "outline.frag.spv",
             )?;
+            let outline_pipeline =
+                context.create_graphics_pipeline(GraphicsPipelineAttributes {
+                    vertex_shader: outline_vertex_shader,
+                    fragment_shader: outline_fragment_shader,
+                    extent: attributes.extent,
+                    color_format: attributes.format,
+                    depth_format: attributes.depth_format,
+                    samples: vk::SampleCountFlags::TYPE_4,
+                    topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                    line_width: 1.0,
+                    dynamic_line_width: false,
+                    dynamic_topology: false,
+                    alpha_blending: true,
+                    additive_blending: false,
+                    depth_write: false,
+                    cull_mode: vk::CullModeFlags::FRONT,
+                    viewport_count: 1,
+                    vertex_layout: Default::default(),
+                    pipeline_layout,
+                    pipeline_cache: Default::default(),
+                })?;
+            context
+                .device
+                .destroy_shader_module(outline_vertex_shader, None);
+            context
+                .device
+                .destroy_shader_module(outline_fragment_shader, None);
+            progress(0.5)?;
+
+            let indirect_draw_buffers = context
+                .supports_draw_indirect_count()
+                .then(|| {
+                    (0..attributes.buffering)
+                        .map(|_| {
+                            IndirectDrawBuffer::new(
+                                context.clone(),
+                                &mut allocator,
+                                instances.len(),
+                            )
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+                .transpose()?;
 
-            let pipeline_layout = context.device.create_pipeline_layout(
-                &vk::PipelineLayoutCreateInfo::default()
-                    .push_constant_ranges(&[vk::PushConstantRange::default()
-                        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
-                        .offset(0)
-                        .size(size_of::<PushConstants>() as u32)])
-                    .set_layouts(&[descriptor_set_layout]),
-                None,
+            let point_lights = PointLights::new(
+                context.clone(),
+                &mut allocator,
+                pipeline_layout,
+                attributes.buffering,
             )?;
+            let light_clusters =
+                LightClusters::new(context.clone(), &mut allocator, attributes.buffering)?;
 
-            let pipeline = context.create_graphics_pipeline(
-                vertex_shader,
-                fragment_shader,
-                attributes.extent,
-                attributes.format,
-                attributes.depth_format,
+            let sun_shadow = SunShadow::new(
+                context.clone(),
+                commands,
+                &mut allocator,
                 pipeline_layout,
-                Default::default(),
+                attributes.sun_shadow,
+                attributes.buffering,
             )?;
+            let lighting = attributes.lighting;
+
+            let image_based_lighting =
+                ImageBasedLighting::new(context.clone(), &mut allocator, attributes.buffering)?;
+            let procedural_sky = ProceduralSky::new(context.clone(), &mut allocator)?;
+
+            // Material 0, which instances start with, samples the built-in texture.
+            let mut materials =
+                Materials::new(context.clone(), &mut allocator, attributes.buffering)?;
+            materials.push(
+                Material {
+                    base_color_texture: Some(0),
+                    ..Default::default()
+                },
+                MaterialSlots {
+                    base_color: 0,
+                    normal: INVALID_TEXTURE_INDEX,
+                    lightmap: INVALID_TEXTURE_INDEX,
+                },
+            );
 
-            context.device.destroy_shader_module(vertex_shader, None);
-            context.device.destroy_shader_module(fragment_shader, None);
+            let debug_line_renderer = DebugLineRenderer::new(
+                context.clone(),
+                DebugLineRendererAttributes {
+                    extent: attributes.extent,
+                    format: attributes.format,
+                    depth_format: attributes.depth_format,
+                    samples: vk::SampleCountFlags::TYPE_4,
+                    buffering: attributes.buffering,
+                },
+            )?;
 
             let descriptor_pool = context.device.create_descriptor_pool(
                 &vk::DescriptorPoolCreateInfo::default()
                     .max_sets(1000)
                     .pool_sizes(&[vk::DescriptorPoolSize::default()
                         .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                        .descriptor_count(1000)])
+                        .descriptor_count(MAX_TEXTURES + MAX_SHADOWED_POINT_LIGHTS as u32 + 5)])
                     .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND),
                 None,
             )?;
@@ -326,48 +1094,80 @@ impl Renderer {
                     .set_layouts(&[descriptor_set_layout]),
             )?;
 
+            let debug_visualizations = DebugVisualizations {
+                grid: attributes.grid,
+                ..Default::default()
+            };
+
+            let grid_renderer = GridRenderer::new(
+                context.clone(),
+                attributes.extent,
+                attributes.format,
+                attributes.depth_format,
+                vk::SampleCountFlags::TYPE_4,
+            )?;
+
+            let weather = Weather::new(
+                context.clone(),
+                &mut allocator,
+                attributes.extent,
+                attributes.format,
+                attributes.depth_format,
+                vk::SampleCountFlags::TYPE_4,
+            )?;
+
+            let lens_flare = LensFlare::new(
+                context.clone(),
+                &mut allocator,
+                attributes.extent,
+                attributes.format,
+                attributes.buffering,
+            )?;
+
+            let skybox_renderer = SkyboxRenderer::new(
+                context.clone(),
+                attributes.extent,
+                attributes.format,
+                attributes.depth_format,
+                vk::SampleCountFlags::TYPE_4,
+                attributes.buffering,
+            )?;
+
+            let nan_guard = (attributes.format == NAN_GUARD_FORMAT)
+                .then(|| NanGuard::new(context.clone(), attributes.buffering))
+                .transpose()?;
+
             let image = ::image::ImageReader::open("res/viking_room.png")?.decode()?;
             let image = image.into_rgba8();
+            progress(0.7)?;
 
-            let mut texture = Image::new(
+            let extent = vk::Extent2D {
+                width: image.width(),
+                height: image.height(),
+            };
+            let mut texture = Image::new_texture(
                 context.clone(),
                 &mut allocator,
                 "viking_room.png",
-                ImageAttributes {
-                    location: MemoryLocation::GpuOnly,
-                    allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-                    allocation_priority: 1.0,
-                    format: vk::Format::R8G8B8A8_UNORM,
-                    extent: vk::Extent3D {
-                        width: image.width(),
-                        height: image.height(),
-                        depth: 1,
-                    },
-                    samples: vk::SampleCountFlags::TYPE_1,
-                    usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
-                    linear: false,
-                    subresource_range: vk::ImageSubresourceRange::default()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .level_count(1)
-                        .layer_count(1),
-                },
+                extent,
+                vk::Format::R8G8B8A8_UNORM,
+                texture_mip_levels(&context, extent, vk::Format::R8G8B8A8_UNORM),
             )?;
 
+            progress(0.85)?;
+
             let mut staging_belt = StagingBelt::new(
                 context.clone(),
                 &mut allocator,
-                gpu_geometry.geometry.size() as vk::DeviceSize
-                    + instance_buffer.attributes.size
-                    + image.len() as vk::DeviceSize * 4,
+                gpu_geometry.size() as vk::DeviceSize + image.len() as vk::DeviceSize * 4,
             )?;
 
             staging_belt
                 .stage_geometry(&gpu_geometry, commands)?
-                .write(&gpu_instances)?
-                .copy_to(&instance_buffer, commands)
                 .write(image.as_raw())?
-                .copy_image_to(&mut texture, commands)
-                .done();
+                .copy_image_to(&mut texture, commands)?;
+
+            staging_belt.done();
 
             let cameras = vec![Camera::new(
                 &na::Point3::new(0.0, 0.0, 2.0),
@@ -378,33 +1178,62 @@ impl Renderer {
                 1000.0,
             )];
 
-            let gpu_cameras = cameras
-                .iter()
-                .map(Camera::to_gpu_camera)
-                .collect::<Vec<_>>();
-
-            let mut camera_buffer = Buffer::new(
+            // Written at the start of every frame.
+            let scene_buffers = (0..attributes.buffering)
+                .map(|_| {
+                    SceneBuffer::new(
+                        context.clone(),
+                        &mut allocator,
+                        cameras.len(),
+                        instances.len(),
+                    )
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            let probe_grid = attributes
+                .probe_grid
+                .map(|probe_grid_attributes| {
+                    ProbeGrid::new(
+                        context.clone(),
+                        &mut allocator,
+                        probe_grid_attributes,
+                        attributes.depth_format,
+                        attributes.buffering,
+                    )
+                })
+                .transpose()?;
+            let probe_capture_pipeline = if probe_grid.as_ref().is_some_and(ProbeGrid::is_capturing)
+            {
+                create_scene_pipeline(
+                    &context,
+                    &shaders,
+                    &attributes,
+                    pipeline_layout,
+                    CAPTURE_FORMAT,
+                    vk::SampleCountFlags::TYPE_1,
+                )?
+            } else {
+                vk::Pipeline::null()
+            };
+
+            let joint_buffer = JointBuffer::new(
+                context.clone(),
                 &mut allocator,
-                BufferAttributes {
-                    name: "camera_buffer".into(),
-                    context: context.clone(),
-                    size: (cameras.len() * size_of::<GPUCamera>()) as vk::DeviceSize,
-                    usage: vk::BufferUsageFlags::UNIFORM_BUFFER
-                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
-                    location: MemoryLocation::CpuToGpu,
-                    allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-                    allocation_priority: 1.0,
-                },
+                instances.len(),
+                attributes.buffering,
             )?;
-            camera_buffer.write(&gpu_cameras, 0)?;
-
-            let start_time = Instant::now();
 
             let mut textures = vec![texture];
 
+            for texture in textures.iter_mut() {
+                commands
+                    .generate_mipmaps(texture)
+                    .ensure_image_layout(texture, ImageLayoutState::shader_read());
+            }
+
             let texture_sampler = context
                 .device
-                .create_sampler(&vk::SamplerCreateInfo::default(), None)?;
+                .create_sampler(&SamplerAttributes::default().create_info(), None)?;
 
             let image_infos = textures
                 .iter()
@@ -416,15 +1245,76 @@ impl Renderer {
                 })
                 .collect::<Vec<_>>();
 
+            let shadow_image_infos = point_lights
+                .cube_views()
+                .iter()
+                .map(|view| {
+                    vk::DescriptorImageInfo::default()
+                        .image_view(*view)
+                        .sampler(point_lights.shadow_sampler)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                })
+                .collect::<Vec<_>>();
+
+            let environment_image_infos = [
+                image_based_lighting.irradiance_view(),
+                image_based_lighting.specular_view(),
+                image_based_lighting.brdf_lut_view(),
+            ]
+            .map(|view| {
+                [vk::DescriptorImageInfo::default()
+                    .image_view(view)
+                    .sampler(image_based_lighting.sampler)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]
+            });
+            let sun_shadow_image_info = [vk::DescriptorImageInfo::default()
+                .image_view(sun_shadow.shadow_map().view)
+                .sampler(sun_shadow.sampler)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+            let spot_shadow_image_info = [vk::DescriptorImageInfo::default()
+                .image_view(point_lights.spot_array_view())
+                .sampler(point_lights.shadow_sampler)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+
             context.device.update_descriptor_sets(
                 &descriptor_sets
                     .iter()
-                    .map(|descriptor_set| {
-                        vk::WriteDescriptorSet::default()
-                            .dst_set(*descriptor_set)
-                            .dst_binding(0)
-                            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                            .image_info(&image_infos)
+                    .flat_map(|descriptor_set| {
+                        [
+                            vk::WriteDescriptorSet::default()
+                                .dst_set(*descriptor_set)
+                                .dst_binding(0)
+                                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                                .image_info(&image_infos),
+                            vk::WriteDescriptorSet::default()
+                                .dst_set(*descriptor_set)
+                                .dst_binding(1)
+                                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                                .image_info(&shadow_image_infos),
+                            vk::WriteDescriptorSet::default()
+                                .dst_set(*descriptor_set)
+                                .dst_binding(5)
+                                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                                .image_info(&sun_shadow_image_info),
+                            vk::WriteDescriptorSet::default()
+                                .dst_set(*descriptor_set)
+                                .dst_binding(6)
+                                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                                .image_info(&spot_shadow_image_info),
+                        ]
+                        .into_iter()
+                        .chain(
+                            environment_image_infos
+                                .iter()
+                                .zip(2..)
+                                .map(|(image_info, binding)| {
+                                    vk::WriteDescriptorSet::default()
+                                        .dst_set(*descriptor_set)
+                                        .dst_binding(binding)
+                                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                                        .image_info(image_info)
+                                }),
+                        )
                     })
                     .collect::<Vec<_>>(),
                 &[],
@@ -436,70 +1326,186 @@ impl Renderer {
                 pipeline_layout,
                 context,
                 staging_belt,
-                gpu_geometry,
-                camera_buffer,
+                upload_belts: (0..attributes.buffering).map(|_| None).collect(),
+                pending_uploads: Vec::new(),
+                meshes: vec![Some(gpu_geometry)],
+                retired_meshes: Vec::new(),
+                shaders,
+                shader_watcher,
+                retired_pipelines: Vec::new(),
+                compute_passes: Vec::new(),
+                scene_buffers,
                 cameras,
-                start_time,
                 frames,
                 attributes,
-                instance_buffer,
                 instances,
+                previous_transforms: Vec::new(),
+                shadow_casters: Vec::new(),
+                scene,
                 descriptor_set_layout,
                 descriptor_pool,
                 descriptor_sets,
                 textures,
                 texture_sampler,
+                materials,
+                samplers: HashMap::new(),
+                sampled_textures: HashMap::new(),
+                occlusion_results: Vec::new(),
+                gpu_timings: Vec::new(),
+                frame_count: 0,
+                frame_graph: FrameGraph::default(),
+                probe_grid,
+                probe_capture_pipeline,
+                joint_buffer,
+                point_lights,
+                light_clusters,
+                sun_shadow,
+                indirect_draw_buffers,
+                debug_lines: DebugLines::default(),
+                debug_line_renderer,
+                grid_renderer,
+                weather,
+                lens_flare,
+                skybox_renderer,
+                image_based_lighting,
+                procedural_sky,
+                time_of_day: None,
+                prefilter_environment: false,
+                nan_guard,
+                debug_visualizations,
+                selection: BTreeSet::new(),
+                outline_pipeline,
+                outline: OutlineStyle::default(),
+                lighting,
+                frozen_camera: None,
+                camera_look_at: None,
+                frame_look_at: (na::Point3::origin(), na::Point3::origin()),
             })
         }
     }
 
+    // Frames still in flight keep their render targets, each frame recreates its own the next
+    // time it is rendered.
     pub fn resize(&mut self, resolution: vk::Extent2D) -> Result<()> {
-        for frame in self.frames.iter_mut() {
-            frame.render_target.destroy(&mut self.allocator)?;
-            frame.depth_buffer.destroy(&mut self.allocator)?;
-            frame.msaa_render_target.destroy(&mut self.allocator)?;
-            frame.msaa_depth_buffer.destroy(&mut self.allocator)?;
-            frame.render_target = Image::new_render_target(
-                self.context.clone(),
-                &mut self.allocator,
-                "render_target",
-                resolution,
-                self.attributes.format,
-                1.0,
-            )?;
-            frame.depth_buffer = Image::new_depth_buffer(
-                self.context.clone(),
-                &mut self.allocator,
-                "depth_buffer",
-                resolution,
-                self.attributes.depth_format,
-            )?;
-            frame.msaa_render_target = Image::new_msaa_render_target(
-                self.context.clone(),
-                &mut self.allocator,
-                "msaa_render_target",
-                resolution,
-                self.attributes.format,
-                vk::SampleCountFlags::TYPE_4,
-            )?;
-            frame.msaa_depth_buffer = Image::new_msaa_depth_buffer(
+        self.attributes.extent = resolution;
+        self.cameras[0]
+            .projection
+            .set_aspect(resolution.width as f32 / resolution.height as f32);
+
+        Ok(())
+    }
+
+    pub(crate) fn render_target_mut(&mut self, render_target_index: usize) -> &mut Image {
+        &mut self.frames[render_target_index].render_target
+    }
+
+    pub(crate) fn depth_buffer_mut(&mut self, render_target_index: usize) -> &mut Image {
+        &mut self.frames[render_target_index].depth_buffer
+    }
+
+    // Render target and depth buffer of a frame, borrowed together with the allocator for
+    // passes that allocate around them.
+    pub(crate) fn frame_images_mut(
+        &mut self,
+        render_target_index: usize,
+    ) -> (&mut Image, &mut Image, &mut Allocator) {
+        let frame = &mut self.frames[render_target_index];
+        (
+            &mut frame.render_target,
+            &mut frame.depth_buffer,
+            &mut self.allocator,
+        )
+    }
+
+    pub(crate) fn allocator_mut(&mut self) -> &mut Allocator {
+        &mut self.allocator
+    }
+
+    // Must be called after the frame's fence has been waited on.
+    fn resize_frame(&mut self, render_target_index: usize) -> Result<()> {
+        let resolution = self.attributes.extent;
+        let frame = &mut self.frames[render_target_index];
+        let extent = frame.render_target.attributes.extent;
+        if extent.width == resolution.width && extent.height == resolution.height {
+            return Ok(());
+        }
+
+        frame.render_target.destroy(&mut self.allocator)?;
+        frame.depth_buffer.destroy(&mut self.allocator)?;
+        frame.msaa_render_target.destroy(&mut self.allocator)?;
+        frame.msaa_depth_buffer.destroy(&mut self.allocator)?;
+        frame.render_target = Image::new_render_target(
+            self.context.clone(),
+            &mut self.allocator,
+            "render_target",
+            resolution,
+            self.attributes.format,
+            1.0,
+        )?;
+        frame.depth_buffer = Image::new_depth_buffer(
+            self.context.clone(),
+            &mut self.allocator,
+            "depth_buffer",
+            resolution,
+            self.attributes.depth_format,
+        )?;
+        frame.msaa_render_target = Image::new_msaa_render_target(
+            self.context.clone(),
+            &mut self.allocator,
+            "msaa_render_target",
+            resolution,
+            self.attributes.format,
+            vk::SampleCountFlags::TYPE_4,
+        )?;
+        frame.msaa_depth_buffer = Image::new_msaa_depth_buffer(
+            self.context.clone(),
+            &mut self.allocator,
+            "msaa_depth_buffer",
+            resolution,
+            self.attributes.depth_format,
+            vk::SampleCountFlags::TYPE_4,
+        )?;
+
+        Ok(())
+    }
+
+    // Fits the frame's per instance buffers to instances added or removed since it was last
+    // rendered, after its fence has been waited on. Buffers only grow, the occlusion query pool
+    // has exactly one query per instance so all of its results become available.
+    fn resize_instance_buffers(&mut self, render_target_index: usize) -> Result<()> {
+        let instance_count = self.instances.len();
+        let scene_buffer = &mut self.scene_buffers[render_target_index];
+        if instance_count > scene_buffer.object_capacity() {
+            scene_buffer.buffer.destroy(&mut self.allocator)?;
+            *scene_buffer = SceneBuffer::new(
                 self.context.clone(),
                 &mut self.allocator,
-                "msaa_depth_buffer",
-                resolution,
-                self.attributes.depth_format,
-                vk::SampleCountFlags::TYPE_4,
+                self.cameras.len(),
+                instance_count.next_power_of_two(),
             )?;
         }
 
-        self.attributes.extent = resolution;
-        self.cameras[0].projection = na::Perspective3::new(
-            resolution.width as f32 / resolution.height as f32,
-            std::f32::consts::FRAC_PI_2,
-            0.1,
-            1000.0,
-        );
-
+        let frame = &mut self.frames[render_target_index];
+        if let Some(query_pool) = &mut frame.occlusion_query_pool {
+            if query_pool.query_count as usize != instance_count.max(1) {
+                *query_pool =
+                    QueryPool::new_occlusion(self.context.clone(), instance_count.max(1) as u32)?;
+                frame.has_occlusion_queries = false;
+            }
+        }
+        if let Some(predicate_buffer) = &mut frame.predicate_buffer {
+            if (instance_count * size_of::<u32>()) as vk::DeviceSize
+                > predicate_buffer.attributes.size
+            {
+                predicate_buffer.destroy(&mut self.allocator)?;
+                *predicate_buffer = create_predicate_buffer(
+                    self.context.clone(),
+                    &mut self.allocator,
+                    instance_count.next_power_of_two(),
+                )?;
+                frame.has_predicates = false;
+            }
+        }
         Ok(())
     }
 
@@ -508,43 +1514,1760 @@ impl Renderer {
         commands: &Commands,
         clear_color: vk::ClearColorValue,
         render_target_index: usize,
+        time: &Time,
     ) -> Result<&mut Image> {
-        let frame = &mut self.frames[render_target_index];
-        let render_target = &mut frame.render_target;
-
-        render_target.reset_layout();
-
+        self.resize_frame(render_target_index)?;
+        self.resize_instance_buffers(render_target_index)?;
+        self.destroy_retired_meshes()?;
+        self.skybox_renderer
+            .destroy_retired(&mut self.allocator, self.frame_count)?;
+        self.reload_shaders();
+        self.frame_graph.begin_frame(self.frame_count);
+        self.joint_buffer
+            .upload(&mut self.allocator, render_target_index)?;
+
+        self.update_time_of_day(time);
+        self.weather.update(time.delta_seconds());
+        self.update_scene();
+        let scene_look_at = self.scene.camera().map(|camera| {
+            let transform = self.scene.world_transform(camera);
+            let eye = transform * na::Point3::origin();
+            (eye, eye + transform * -na::Vector3::z())
+        });
         let camera = &mut self.cameras[0];
-        let t = (Instant::now() - self.start_time).as_secs_f32();
-        camera.view = na::Isometry3::look_at_rh(
-            &na::Point3::new(t.cos(), -1.0, t.sin()),
-            &na::Point3::new(0.0, 0.0, 0.0),
-            &na::Vector3::y(),
-        );
+        let (eye, target) = self.camera_look_at.or(scene_look_at).unwrap_or_else(|| {
+            let t = time.elapsed_seconds() as f64;
+            (
+                na::Point3::new(t.cos(), -1.0, t.sin()),
+                na::Point3::origin(),
+            )
+        });
+        camera.view = na::Isometry3::look_at_rh(&eye, &target, &na::Vector3::y());
+        self.frame_look_at = (eye, target);
 
+        let sun_shadow_matrix =
+            self.sun_shadow
+                .upload(render_target_index, &eye, &self.lighting.sun_direction)?;
         let gpu_cameras = self
             .cameras
             .iter()
-            .map(Camera::to_gpu_camera)
+            .map(|camera| GPUCamera {
+                sun_shadow_texel_size: self.sun_shadow.texel_size(),
+                wetness: self.weather.wetness(),
+                sun_shadow_matrix,
+                ..camera.gpu_camera(&self.lighting)
+            })
             .collect::<Vec<_>>();
-        self.camera_buffer.write(&gpu_cameras, 0)?;
 
-        commands.begin_rendering(
-            frame,
-            clear_color,
-            vk::Rect2D::default().extent(self.attributes.extent),
-        );
-        self.draw(commands, render_target_index);
-        commands.end_rendering();
+        // Objects are uploaded relative to the camera, so they change whenever it moves.
+        let camera_translation = na::Matrix4::new_translation(&-eye.coords);
+        let world_transforms = (0..self.instances.len())
+            .map(|index| self.instance_world_transform(index))
+            .collect::<Vec<_>>();
+        let gpu_objects = (0..self.instances.len())
+            .map(|index| {
+                let (bounds_min, bounds_max) = self.gpu_mesh(self.instances[index].mesh).bounds;
+                let transform = world_transforms[index];
+                let previous_transform = self.previous_transforms.get(index).unwrap_or(&transform);
+                GPUObject {
+                    transform: (camera_translation * transform).cast(),
+                    previous_transform: (camera_translation * previous_transform).cast(),
+                    bounds_min,
+                    material_index: self.instances[index].material_index,
+                    bounds_max,
+                    flags: if self.is_instance_visible(index) {
+                        OBJECT_VISIBLE_FLAG
+                    } else {
+                        0
+                    } | if self.selection.contains(&index) {
+                        OBJECT_SELECTED_FLAG
+                    } else {
+                        0
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        self.scene_buffers[render_target_index].write(&gpu_cameras, &gpu_objects)?;
+        if let Some(probe_grid) = &mut self.probe_grid {
+            probe_grid.prepare_capture(render_target_index, &eye, &gpu_cameras[0])?;
+        }
+        self.update_shadow_casters(&world_transforms);
+        if self.attributes.previous_transforms {
+            self.previous_transforms = world_transforms;
+        }
+        self.point_lights.upload(
+            &mut self.allocator,
+            render_target_index,
+            &eye,
+            self.light_clusters.address(render_target_index),
+        )?;
+        self.materials
+            .upload(&mut self.allocator, render_target_index)?;
+
+        // Written on the CPU for now, a GPU culling pass can fill the same buffers instead.
+        let buckets = self.indirect_draw_buffers.as_ref().map(|_| {
+            self.indirect_draw_buckets()
+                .into_iter()
+                .map(|(_, bucket)| bucket)
+                .collect::<Vec<_>>()
+        });
+        if let Some((indirect_draw_buffers, buckets)) =
+            self.indirect_draw_buffers.as_mut().zip(buckets)
+        {
+            indirect_draw_buffers[render_target_index].write(&mut self.allocator, &buckets)?;
+        }
 
-        Ok(&mut self.frames[render_target_index].render_target)
-    }
+        self.draw_debug_visualizations();
+        self.debug_line_renderer.upload(
+            &mut self.allocator,
+            render_target_index,
+            &self.debug_lines,
+        )?;
+        self.debug_lines.clear();
+
+        self.flush_uploads(commands, render_target_index)?;
+        self.record_image_based_lighting(commands, render_target_index);
+        self.record_light_clusters(commands, render_target_index);
+        self.weather
+            .record(commands, time.delta_seconds(), time.elapsed_seconds());
+        if self.weather.attributes().is_some() {
+            self.frame_graph
+                .add_pass("weather_update", &[], &[("weather_particles", None)]);
+        }
+        self.record_compute_passes(commands, render_target_index, ComputeStage::BeforeScene);
 
-    pub fn draw(&self, commands: &Commands, render_target_index: usize) {
-        let render_target = &self.frames[render_target_index].render_target;
+        let frame = &mut self.frames[render_target_index];
+        let render_target = &mut frame.render_target;
 
-        commands
-            .set_viewport(
+        render_target.reset_layout();
+
+        if let Some(query_pool) = &frame.occlusion_query_pool {
+            if frame.has_occlusion_queries {
+                if let Some(results) = query_pool.results()? {
+                    self.occlusion_results = results;
+                }
+
+                if let Some(predicate_buffer) = &frame.predicate_buffer {
+                    commands
+                        .copy_query_pool_results(
+                            query_pool,
+                            0..query_pool.query_count,
+                            predicate_buffer,
+                            0,
+                        )
+                        .buffer_memory_barrier(
+                            predicate_buffer,
+                            (
+                                vk::PipelineStageFlags2::TRANSFER,
+                                vk::AccessFlags2::TRANSFER_WRITE,
+                            ),
+                            (
+                                vk::PipelineStageFlags2::CONDITIONAL_RENDERING_EXT,
+                                vk::AccessFlags2::CONDITIONAL_RENDERING_READ_EXT,
+                            ),
+                        );
+                    frame.has_predicates = true;
+                }
+            }
+            commands.reset_query_pool(query_pool, 0..query_pool.query_count);
+            frame.has_occlusion_queries = true;
+        }
+
+        if let Some(query_pool) = &frame.timestamp_query_pool {
+            if frame.has_timestamps {
+                if let Some(timestamps) = query_pool.results()? {
+                    let timestamp_period = self
+                        .context
+                        .physical_device
+                        .properties
+                        .limits
+                        .timestamp_period;
+                    self.gpu_timings = GPU_PASSES
+                        .iter()
+                        .zip(timestamps.windows(2))
+                        .map(|(pass, timestamps)| {
+                            let ticks = timestamps[1].saturating_sub(timestamps[0]);
+                            (*pass, ticks as f32 * timestamp_period / 1_000_000.0)
+                        })
+                        .collect();
+                }
+            }
+            commands.reset_query_pool(query_pool, 0..query_pool.query_count);
+            frame.has_timestamps = true;
+        }
+
+        if let Some(probe_grid) = &mut self.probe_grid {
+            probe_grid.upload(commands, render_target_index)?;
+        }
+
+        if let Some(query_pool) = &frame.timestamp_query_pool {
+            commands.write_timestamp(query_pool, vk::PipelineStageFlags2::ALL_COMMANDS, 0);
+        }
+        self.draw_point_shadows(commands, render_target_index);
+        self.frame_graph.add_pass(
+            "point_shadows",
+            &[("scene_buffer", None), ("point_light_buffer", None)],
+            &[
+                (
+                    "point_static_shadow_maps",
+                    Some(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+                ),
+                (
+                    "point_shadow_maps",
+                    Some(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+                ),
+                (
+                    "spot_static_shadow_maps",
+                    Some(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+                ),
+                (
+                    "spot_shadow_maps",
+                    Some(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+                ),
+            ],
+        );
+        if self.sun_shadow.is_enabled() {
+            self.draw_sun_shadow(commands, render_target_index);
+            self.frame_graph.add_pass(
+                "sun_shadow",
+                &[("scene_buffer", None)],
+                &[(
+                    "sun_shadow_map",
+                    Some(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+                )],
+            );
+        }
+        if self.capture_probes(commands, render_target_index) {
+            self.frame_graph.add_pass(
+                "probe_capture",
+                &[
+                    ("scene_buffer", None),
+                    ("point_light_buffer", None),
+                    (
+                        "point_shadow_maps",
+                        Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                    ),
+                    (
+                        "spot_shadow_maps",
+                        Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                    ),
+                    (
+                        "sun_shadow_map",
+                        Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                    ),
+                    ("textures", Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+                ],
+                &[("probe_grid", None)],
+            );
+        }
+        let frame = &mut self.frames[render_target_index];
+        commands.begin_rendering(
+            frame,
+            clear_color,
+            self.context
+                .depth_resolve_mode(self.attributes.depth_resolve_mode),
+            vk::Rect2D::default().extent(self.attributes.extent),
+        );
+        self.draw(commands, render_target_index);
+        self.draw_skybox(commands, render_target_index);
+        self.frame_graph.add_pass(
+            "scene",
+            &[
+                ("scene_buffer", None),
+                ("point_light_buffer", None),
+                ("light_clusters", None),
+                ("probe_grid", None),
+                (
+                    "point_shadow_maps",
+                    Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                ),
+                (
+                    "spot_shadow_maps",
+                    Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                ),
+                (
+                    "sun_shadow_map",
+                    Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                ),
+                (
+                    "environment_lighting",
+                    Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                ),
+                ("textures", Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+            ],
+            &[
+                (
+                    "msaa_render_target",
+                    Some(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+                ),
+                (
+                    "msaa_depth_buffer",
+                    Some(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+                ),
+            ],
+        );
+        self.draw_weather(commands, render_target_index);
+        self.write_timestamp(commands, render_target_index, 1);
+        self.draw_outlines(commands, render_target_index);
+        if self.debug_visualizations.grid {
+            self.grid_renderer.draw(
+                commands,
+                self.scene_buffers[render_target_index].camera_address(),
+            );
+        }
+        self.debug_line_renderer.draw(
+            commands,
+            render_target_index,
+            self.scene_buffers[render_target_index].camera_address(),
+        );
+        commands.end_rendering();
+        self.frame_graph.add_pass(
+            "overlays",
+            &[
+                ("scene_buffer", None),
+                ("debug_line_buffer", None),
+                (
+                    "msaa_depth_buffer",
+                    Some(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+                ),
+            ],
+            &[
+                (
+                    "msaa_render_target",
+                    Some(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+                ),
+                (
+                    "render_target",
+                    Some(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+                ),
+                (
+                    "depth_buffer",
+                    Some(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+                ),
+            ],
+        );
+        self.write_timestamp(commands, render_target_index, 2);
+
+        if self.lens_flare.attributes().is_some() {
+            self.draw_lens_flare(commands, render_target_index)?;
+        }
+
+        if let Some(nan_guard) = self
+            .nan_guard
+            .as_ref()
+            .filter(|_| self.debug_visualizations.nan_guard)
+        {
+            let frame = &mut self.frames[render_target_index];
+            let mut graph = RenderGraph::default();
+            let render_target = graph.import_image("render_target", &mut frame.render_target);
+            let depth_buffer = graph.import_image("depth_buffer", &mut frame.depth_buffer);
+            graph.add_pass(
+                "nan_guard",
+                &[GraphAccess::Image(
+                    depth_buffer,
+                    ImageLayoutState::compute_shader_read(),
+                )],
+                &[GraphAccess::Image(
+                    render_target,
+                    ImageLayoutState::compute_shader_storage(),
+                )],
+                |commands, images| {
+                    let (render_target, depth_buffer) = images.pair(render_target, depth_buffer);
+                    nan_guard.record(commands, render_target_index, render_target, depth_buffer);
+                    Ok(())
+                },
+            );
+            let passes = graph.execute(commands)?;
+            self.frame_graph.add_passes(passes);
+        }
+
+        self.record_compute_passes(commands, render_target_index, ComputeStage::AfterScene);
+
+        self.frame_count += 1;
+
+        Ok(&mut self.frames[render_target_index].render_target)
+    }
+
+    // Meshes are always uploaded since instances may draw them right away. Textures past the
+    // upload budget wait for a later frame, in order, and are cleared until then; at least one
+    // is uploaded per frame so a texture larger than the budget still gets through.
+    fn flush_uploads(&mut self, commands: &Commands, render_target_index: usize) -> Result<()> {
+        if self.pending_uploads.is_empty() {
+            return Ok(());
+        }
+
+        let mut budget = self.attributes.upload_budget.unwrap_or(vk::DeviceSize::MAX);
+        let mut has_texture = false;
+        let mut is_deferring = false;
+        let (uploads, deferred): (Vec<_>, Vec<_>) =
+            self.pending_uploads.drain(..).partition(|upload| {
+                if let PendingUpload::Texture { .. } = upload {
+                    is_deferring |= has_texture && upload.size() > budget;
+                    if is_deferring {
+                        return false;
+                    }
+                    has_texture = true;
+                }
+                budget = budget.saturating_sub(upload.size());
+                true
+            });
+        self.pending_uploads = deferred;
+
+        for upload in &self.pending_uploads {
+            if let PendingUpload::Texture { index, .. } = upload {
+                let texture = &mut self.textures[*index];
+                if texture.layout().layout == vk::ImageLayout::UNDEFINED {
+                    commands
+                        .clear_color_image(texture, vk::ClearColorValue::default())
+                        .ensure_image_layout(texture, ImageLayoutState::shader_read());
+                }
+            }
+        }
+        if uploads.is_empty() {
+            return Ok(());
+        }
+
+        let size = uploads
+            .iter()
+            .map(PendingUpload::size)
+            .sum::<vk::DeviceSize>();
+        let upload_belt = &mut self.upload_belts[render_target_index];
+        if upload_belt
+            .as_ref()
+            .is_some_and(|upload_belt| upload_belt.capacity() < size)
+        {
+            upload_belt.take().unwrap().destroy(&mut self.allocator)?;
+        }
+        let upload_belt = match upload_belt {
+            Some(upload_belt) => upload_belt,
+            None => upload_belt.insert(StagingBelt::new(
+                self.context.clone(),
+                &mut self.allocator,
+                size.next_power_of_two(),
+            )?),
+        };
+
+        for upload in uploads {
+            match upload {
+                PendingUpload::Texture { index, levels } => {
+                    let texture = &mut self.textures[index];
+                    for (level, pixels) in levels.iter().enumerate() {
+                        upload_belt
+                            .align(TEXEL_ALIGNMENT)
+                            .write(pixels)?
+                            .copy_image_level_to(texture, level as u32, commands)?;
+                    }
+                    if levels.len() == 1 {
+                        commands.generate_mipmaps(texture);
+                    }
+                    commands.ensure_image_layout(texture, ImageLayoutState::shader_read());
+                }
+                PendingUpload::Skybox { levels } => {
+                    let cubemap = self.skybox_renderer.cubemap_mut().unwrap();
+                    for (level, pixels) in levels.iter().enumerate() {
+                        upload_belt
+                            .align(TEXEL_ALIGNMENT)
+                            .write(pixels)?
+                            .copy_image_level_to(cubemap, level as u32, commands)?;
+                    }
+                    if levels.len() == 1 {
+                        commands.generate_mipmaps(cubemap);
+                    }
+                    commands.ensure_image_layout(cubemap, ImageLayoutState::shader_read());
+                }
+                PendingUpload::Mesh { mesh, .. } => {
+                    let gpu_mesh = self.meshes[mesh.0].as_ref().unwrap();
+                    upload_belt.stage_geometry(gpu_mesh, commands)?;
+                    for buffer in [
+                        Some(&gpu_mesh.vertex_buffer),
+                        Some(&gpu_mesh.index_buffer),
+                        gpu_mesh.skin_buffer.as_ref(),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    {
+                        commands.buffer_memory_barrier(
+                            buffer,
+                            (
+                                vk::PipelineStageFlags2::COPY,
+                                vk::AccessFlags2::TRANSFER_WRITE,
+                            ),
+                            (
+                                vk::PipelineStageFlags2::INDEX_INPUT
+                                    | vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT
+                                    | vk::PipelineStageFlags2::VERTEX_SHADER,
+                                vk::AccessFlags2::INDEX_READ
+                                    | vk::AccessFlags2::VERTEX_ATTRIBUTE_READ
+                                    | vk::AccessFlags2::SHADER_STORAGE_READ,
+                            ),
+                        );
+                    }
+                }
+                PendingUpload::Vertices { mesh, .. } => {
+                    let gpu_mesh = self.meshes[mesh.0].as_mut().unwrap();
+                    let vertex_reads = (
+                        vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT
+                            | vk::PipelineStageFlags2::VERTEX_SHADER,
+                        vk::AccessFlags2::VERTEX_ATTRIBUTE_READ
+                            | vk::AccessFlags2::SHADER_STORAGE_READ,
+                    );
+                    let copy_write = (
+                        vk::PipelineStageFlags2::COPY,
+                        vk::AccessFlags2::TRANSFER_WRITE,
+                    );
+                    // Earlier frames may still be drawing with the old vertices.
+                    commands.buffer_memory_barrier(
+                        &gpu_mesh.vertex_buffer,
+                        (vertex_reads.0, vk::AccessFlags2::NONE),
+                        copy_write,
+                    );
+                    upload_belt.stage_dirty_vertices(gpu_mesh, commands)?;
+                    commands.buffer_memory_barrier(
+                        &gpu_mesh.vertex_buffer,
+                        copy_write,
+                        vertex_reads,
+                    );
+                }
+            }
+        }
+        upload_belt.done();
+
+        Ok(())
+    }
+
+    // The mesh is uploaded at the start of the next frame, instances can be switched to it right
+    // away with set_instance_mesh.
+    pub fn add_mesh(&mut self, geometry: Geometry) -> Result<MeshHandle> {
+        geometry.validate()?;
+
+        let gpu_mesh = geometry.create_gpu_geometry(
+            self.context.clone(),
+            &mut self.allocator,
+            self.attributes.quantize_vertices,
+        )?;
+        let mesh = MeshHandle(self.meshes.len());
+        self.pending_uploads.push(PendingUpload::Mesh {
+            mesh,
+            size: gpu_mesh.size() as vk::DeviceSize,
+        });
+        self.meshes.push(Some(gpu_mesh));
+        Ok(mesh)
+    }
+
+    // Deforms a mesh in place, e.g. cloth or a water surface. Only the changed range is staged
+    // at the start of the next frame, after the frames in flight are done reading the old
+    // vertices.
+    pub fn update_mesh_vertices(
+        &mut self,
+        mesh: MeshHandle,
+        range: Range<usize>,
+        vertices: &[Vertex],
+    ) -> Result<()> {
+        let gpu_mesh = self
+            .meshes
+            .get_mut(mesh.0)
+            .and_then(Option::as_mut)
+            .ok_or_else(|| anyhow::anyhow!("Mesh {mesh:?} doesn't exist"))?;
+        gpu_mesh.update_vertices(range, vertices)?;
+        self.point_lights.invalidate_static_shadows();
+        let size = gpu_mesh.dirty_vertices_size() as vk::DeviceSize;
+        match self
+            .pending_uploads
+            .iter_mut()
+            .find_map(|upload| match upload {
+                PendingUpload::Vertices {
+                    mesh: pending,
+                    size,
+                } if *pending == mesh => Some(size),
+                _ => None,
+            }) {
+            Some(pending_size) => *pending_size = size,
+            None => self
+                .pending_uploads
+                .push(PendingUpload::Vertices { mesh, size }),
+        }
+        Ok(())
+    }
+
+    // Fails while instances still use the mesh. Its buffers are destroyed once no frame in
+    // flight can draw it anymore.
+    pub fn remove_mesh(&mut self, mesh: MeshHandle) -> Result<()> {
+        if let Some(instance) = self
+            .instances
+            .iter()
+            .position(|instance| instance.mesh == mesh)
+        {
+            return Err(anyhow::anyhow!(
+                "Mesh {mesh:?} is still used by instance {instance}"
+            ));
+        }
+
+        let gpu_mesh = self
+            .meshes
+            .get_mut(mesh.0)
+            .and_then(Option::take)
+            .ok_or_else(|| anyhow::anyhow!("Mesh {mesh:?} doesn't exist"))?;
+        self.pending_uploads.retain(|upload| match upload {
+            PendingUpload::Mesh { mesh: pending, .. }
+            | PendingUpload::Vertices { mesh: pending, .. } => *pending != mesh,
+            PendingUpload::Texture { .. } | PendingUpload::Skybox { .. } => true,
+        });
+        self.retired_meshes.push((
+            gpu_mesh,
+            self.frame_count + self.attributes.buffering as u64,
+        ));
+        Ok(())
+    }
+
+    fn destroy_retired_meshes(&mut self) -> Result<()> {
+        let frame_count = self.frame_count;
+        let (mut retired, pending): (Vec<_>, Vec<_>) = self
+            .retired_meshes
+            .drain(..)
+            .partition(|(_, retire_frame)| *retire_frame <= frame_count);
+        self.retired_meshes = pending;
+        for (gpu_mesh, _) in &mut retired {
+            gpu_mesh.destroy(&mut self.allocator)?;
+        }
+        Ok(())
+    }
+
+    // Swaps in a pipeline built from the changed shaders, a failed build keeps the current one
+    // so a typo doesn't take the window down.
+    fn reload_shaders(&mut self) {
+        let frame_count = self.frame_count;
+        let (retired, pending): (Vec<_>, Vec<_>) = self
+            .retired_pipelines
+            .drain(..)
+            .partition(|(_, retire_frame)| *retire_frame <= frame_count);
+        self.retired_pipelines = pending;
+        for (pipeline, _) in retired {
+            unsafe { self.context.device.destroy_pipeline(pipeline, None) };
+        }
+
+        if !self
+            .shader_watcher
+            .as_mut()
+            .is_some_and(ShaderWatcher::poll)
+        {
+            return;
+        }
+        match create_scene_pipeline(
+            &self.context,
+            &self.shaders,
+            &self.attributes,
+            self.pipeline_layout,
+            self.attributes.format,
+            vk::SampleCountFlags::TYPE_4,
+        ) {
+            Ok(pipeline) => {
+                let previous = self.replace_pipeline(pipeline);
+                self.retired_pipelines.push((
+                    previous,
+                    self.frame_count + self.attributes.buffering as u64,
+                ));
+                info!("Reloaded the scene shaders");
+            }
+            Err(error) => warn!("Shader reload failed, keeping the previous pipeline: {error}"),
+        }
+        if self.probe_capture_pipeline == vk::Pipeline::null() {
+            return;
+        }
+        match create_scene_pipeline(
+            &self.context,
+            &self.shaders,
+            &self.attributes,
+            self.pipeline_layout,
+            CAPTURE_FORMAT,
+            vk::SampleCountFlags::TYPE_1,
+        ) {
+            Ok(pipeline) => {
+                let previous = std::mem::replace(&mut self.probe_capture_pipeline, pipeline);
+                self.retired_pipelines.push((
+                    previous,
+                    self.frame_count + self.attributes.buffering as u64,
+                ));
+            }
+            Err(error) => {
+                warn!("Probe capture shader reload failed, keeping the previous pipeline: {error}")
+            }
+        }
+    }
+
+    // Recorded every frame at the stage, in the order the passes were added. Pipelines and
+    // descriptors are created with context() and owned by the caller.
+    pub fn add_compute_pass(
+        &mut self,
+        name: &str,
+        stage: ComputeStage,
+        record: impl FnMut(&Commands, &mut ComputeTargets) + 'static,
+    ) {
+        self.compute_passes.push(ComputePass {
+            name: name.to_owned(),
+            stage,
+            record: Box::new(record),
+        });
+    }
+
+    // Returns whether a pass with the name existed. Its resources may still be in use by the
+    // frames in flight.
+    pub fn remove_compute_pass(&mut self, name: &str) -> bool {
+        let count = self.compute_passes.len();
+        self.compute_passes.retain(|pass| pass.name != name);
+        self.compute_passes.len() != count
+    }
+
+    fn record_compute_passes(
+        &mut self,
+        commands: &Commands,
+        render_target_index: usize,
+        stage: ComputeStage,
+    ) {
+        let frame = &mut self.frames[render_target_index];
+        let mut targets = ComputeTargets {
+            frame_index: render_target_index,
+            render_target: &mut frame.render_target,
+            depth_buffer: &mut frame.depth_buffer,
+        };
+        let mut recorded = false;
+        for pass in self
+            .compute_passes
+            .iter_mut()
+            .filter(|pass| pass.stage == stage)
+        {
+            (pass.record)(commands, &mut targets);
+            self.frame_graph.add_pass(&pass.name, &[], &[]);
+            recorded = true;
+        }
+        if recorded {
+            compute_barrier(commands);
+        }
+    }
+
+    pub fn context(&self) -> &Arc<RenderingContext> {
+        &self.context
+    }
+
+    pub fn upload_budget(&self) -> Option<vk::DeviceSize> {
+        self.attributes.upload_budget
+    }
+
+    pub fn set_upload_budget(&mut self, upload_budget: Option<vk::DeviceSize>) {
+        self.attributes.upload_budget = upload_budget;
+    }
+
+    // The texture is uploaded at the start of the next frame and can be sampled from then on
+    // through the returned bindless index.
+    pub fn add_texture(&mut self, name: &str, image: &::image::RgbaImage) -> Result<u32> {
+        self.add_texture_pixels(
+            name,
+            vk::Extent2D {
+                width: image.width(),
+                height: image.height(),
+            },
+            vk::Format::R8G8B8A8_UNORM,
+            vec![image.as_raw().clone()],
+        )
+    }
+
+    // Adds every page of the atlas, returns the bindless index of each page.
+    pub fn add_texture_atlas(&mut self, name: &str, atlas: &TextureAtlas) -> Result<Vec<u32>> {
+        atlas
+            .pages
+            .iter()
+            .enumerate()
+            .map(|(page_index, page)| self.add_texture(&format!("{name}_{page_index}"), page))
+            .collect()
+    }
+
+    // Linear float texture, e.g. an environment map or an HDR reference image.
+    // Stored as half floats, which unlike 32 bit floats are always filterable.
+    pub fn add_hdr_texture(&mut self, name: &str, image: &::image::Rgba32FImage) -> Result<u32> {
+        let half_floats = image
+            .as_raw()
+            .iter()
+            .map(|&value| half::f16::from_f32(value).to_bits())
+            .collect::<Vec<_>>();
+        self.add_texture_pixels(
+            name,
+            vk::Extent2D {
+                width: image.width(),
+                height: image.height(),
+            },
+            vk::Format::R16G16B16A16_SFLOAT,
+            vec![bytemuck::cast_slice(&half_floats).to_vec()],
+        )
+    }
+
+    // Loads a Radiance .hdr or OpenEXR file, LDR files are converted to linear floats as well.
+    pub fn load_hdr_texture(&mut self, path: impl AsRef<Path>) -> Result<u32> {
+        let path = path.as_ref();
+        let image = ::image::ImageReader::open(path)?
+            .with_guessed_format()?
+            .decode()?
+            .into_rgba32f();
+        self.add_hdr_texture(&path.to_string_lossy(), &image)
+    }
+
+    // Environment drawn behind the scene, faces in +X, -X, +Y, -Y, +Z, -Z order as seen from
+    // inside the cube. Uploaded at the start of the next frame, replacing the previous skybox.
+    pub fn set_skybox(&mut self, name: &str, faces: &[::image::Rgba32FImage; 6]) -> Result<()> {
+        let size = faces[0].width();
+        if faces
+            .iter()
+            .any(|face| face.width() != size || face.height() != size)
+        {
+            return Err(anyhow::anyhow!(
+                "Skybox faces have to be square and of the same size"
+            ));
+        }
+        let half_floats = faces
+            .iter()
+            .flat_map(|face| face.as_raw())
+            .map(|&value| half::f16::from_f32(value).to_bits())
+            .collect::<Vec<_>>();
+        let extent = vk::Extent2D {
+            width: size,
+            height: size,
+        };
+        let cubemap = Image::new_cubemap(
+            self.context.clone(),
+            &mut self.allocator,
+            name,
+            size,
+            vk::Format::R16G16B16A16_SFLOAT,
+            mip_level_count(extent),
+        )?;
+        self.clear_skybox();
+        self.skybox_renderer.set_cubemap(
+            Some(cubemap),
+            self.frame_count + self.attributes.buffering as u64,
+        );
+        self.prefilter_environment = true;
+        self.pending_uploads.push(PendingUpload::Skybox {
+            levels: vec![bytemuck::cast_slice(&half_floats).to_vec()],
+        });
+        Ok(())
+    }
+
+    // Loads the six faces like load_hdr_texture, in the order set_skybox expects.
+    pub fn load_skybox(&mut self, paths: [impl AsRef<Path>; 6]) -> Result<()> {
+        let faces = paths
+            .iter()
+            .map(|path| -> Result<_> {
+                Ok(::image::ImageReader::open(path)?
+                    .with_guessed_format()?
+                    .decode()?
+                    .into_rgba32f())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let name = paths[0].as_ref().to_string_lossy();
+        self.set_skybox(&name, &faces.try_into().unwrap())
+    }
+
+    // Frames go back to the clear color behind the scene, and ambient lighting to
+    // Lighting::ambient_illuminance.
+    pub fn clear_skybox(&mut self) {
+        self.prefilter_environment = false;
+        if !self.procedural_sky.is_enabled() {
+            self.image_based_lighting.clear_environment();
+        }
+        self.pending_uploads
+            .retain(|upload| !matches!(upload, PendingUpload::Skybox { .. }));
+        self.skybox_renderer
+            .set_cubemap(None, self.frame_count + self.attributes.buffering as u64);
+    }
+
+    // Replaces the skybox with daylight computed from Lighting::sun_direction, following the sun
+    // as it moves. None goes back to the skybox if there is one.
+    pub fn set_procedural_sky(&mut self, attributes: Option<ProceduralSkyAttributes>) {
+        self.procedural_sky.set_attributes(attributes);
+        if attributes.is_none() {
+            if self.skybox_renderer.cubemap().is_some() {
+                self.prefilter_environment = true;
+            } else {
+                self.image_based_lighting.clear_environment();
+            }
+        }
+    }
+
+    pub fn procedural_sky(&self) -> Option<ProceduralSkyAttributes> {
+        self.procedural_sky.attributes()
+    }
+
+    // Animates the sun and the procedural sky with the frame time, the sky is enabled with the
+    // time of day's attributes. None leaves the lighting where the day cycle left it.
+    pub fn set_time_of_day(&mut self, time_of_day: Option<TimeOfDay>) {
+        if let Some(time_of_day) = &time_of_day {
+            self.set_procedural_sky(Some(time_of_day.sky));
+            time_of_day.apply(&mut self.lighting);
+        }
+        self.time_of_day = time_of_day;
+    }
+
+    pub fn time_of_day(&self) -> Option<&TimeOfDay> {
+        self.time_of_day.as_ref()
+    }
+
+    pub fn time_of_day_mut(&mut self) -> Option<&mut TimeOfDay> {
+        self.time_of_day.as_mut()
+    }
+
+    // Jumps the day cycle to an hour from 0 to 24, does nothing without a time of day.
+    pub fn set_hour(&mut self, hour: f32) {
+        if let Some(time_of_day) = &mut self.time_of_day {
+            time_of_day.set_hour(hour);
+        }
+    }
+
+    fn update_time_of_day(&mut self, time: &Time) {
+        let Some(time_of_day) = &mut self.time_of_day else {
+            return;
+        };
+        time_of_day.advance(time.delta());
+        time_of_day.apply(&mut self.lighting);
+        let sky = Some(time_of_day.sky);
+        if self.procedural_sky.attributes() != sky {
+            self.set_procedural_sky(sky);
+        }
+    }
+
+    fn record_image_based_lighting(&mut self, commands: &Commands, render_target_index: usize) {
+        let prefilter_skybox = std::mem::take(&mut self.prefilter_environment);
+        let environment = if self.procedural_sky.is_enabled() {
+            if self
+                .procedural_sky
+                .record(commands, &self.lighting.sun_direction)
+            {
+                self.frame_graph.add_pass(
+                    "procedural_sky",
+                    &[],
+                    &[("skybox", Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL))],
+                );
+                Some(self.procedural_sky.cubemap_mut())
+            } else {
+                None
+            }
+        } else if prefilter_skybox {
+            self.skybox_renderer.cubemap_mut()
+        } else {
+            None
+        };
+        let prefiltered = environment.is_some();
+        self.image_based_lighting
+            .record(commands, render_target_index, environment);
+        if prefiltered {
+            self.frame_graph.add_pass(
+                "image_based_lighting",
+                &[("skybox", Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL))],
+                &[("environment_lighting", Some(vk::ImageLayout::GENERAL))],
+            );
+        }
+    }
+
+    fn record_light_clusters(&mut self, commands: &Commands, render_target_index: usize) {
+        let projection = &self.cameras[0].projection;
+        self.light_clusters.record(
+            commands,
+            render_target_index,
+            self.scene_buffers[render_target_index].camera_address(),
+            self.point_lights.address(render_target_index),
+            projection.znear(),
+            projection.zfar(),
+        );
+        self.frame_graph.add_pass(
+            "light_clusters",
+            &[("scene_buffer", None), ("point_light_buffer", None)],
+            &[("light_clusters", None)],
+        );
+    }
+
+    // Rain or snow falling around the camera, None stops it and lets the surfaces dry.
+    pub fn set_weather(&mut self, attributes: Option<WeatherAttributes>) {
+        self.weather.set_attributes(attributes);
+    }
+
+    pub fn weather(&self) -> Option<WeatherAttributes> {
+        self.weather.attributes()
+    }
+
+    // From 0 for dry to 1 for soaked surfaces, see Material::wetness_response.
+    pub fn wetness(&self) -> f32 {
+        self.weather.wetness()
+    }
+
+    pub fn set_wetness(&mut self, wetness: f32) {
+        self.weather.set_wetness(wetness);
+    }
+
+    // Sun flares and light shafts, None turns them off.
+    pub fn set_lens_flare(&mut self, attributes: Option<LensFlareAttributes>) {
+        self.lens_flare.set_attributes(attributes);
+    }
+
+    pub fn lens_flare(&self) -> Option<LensFlareAttributes> {
+        self.lens_flare.attributes()
+    }
+
+    fn draw_weather(&mut self, commands: &Commands, render_target_index: usize) {
+        if self.weather.attributes().is_none() {
+            return;
+        }
+        let (eye, target) = self.frame_look_at;
+        let forward = (target - eye)
+            .try_normalize(f64::EPSILON)
+            .unwrap_or(-na::Vector3::z());
+        self.weather.draw(
+            commands,
+            self.scene_buffers[render_target_index].camera_address(),
+            &eye,
+            &forward,
+        );
+        self.frame_graph.add_pass(
+            "weather",
+            &[
+                ("scene_buffer", None),
+                ("weather_particles", None),
+                (
+                    "msaa_depth_buffer",
+                    Some(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+                ),
+            ],
+            &[(
+                "msaa_render_target",
+                Some(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            )],
+        );
+    }
+
+    fn draw_lens_flare(&mut self, commands: &Commands, render_target_index: usize) -> Result<()> {
+        let camera_buffer_address = self.scene_buffers[render_target_index].camera_address();
+        let lens_flare = &self.lens_flare;
+        let frame = &mut self.frames[render_target_index];
+        let mut graph = RenderGraph::default();
+        let render_target = graph.import_image("render_target", &mut frame.render_target);
+        let depth_buffer = graph.import_image("depth_buffer", &mut frame.depth_buffer);
+        let sun_occlusion = graph.import_buffer("sun_occlusion");
+        graph.add_pass(
+            "sun_occlusion",
+            &[GraphAccess::Image(
+                depth_buffer,
+                ImageLayoutState::compute_shader_read(),
+            )],
+            &[GraphAccess::Buffer(
+                sun_occlusion,
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            )],
+            move |commands, images| {
+                lens_flare.record_occlusion(
+                    commands,
+                    render_target_index,
+                    camera_buffer_address,
+                    images.get(depth_buffer),
+                );
+                Ok(())
+            },
+        );
+        graph.add_pass(
+            "lens_flare",
+            &[
+                GraphAccess::Image(depth_buffer, ImageLayoutState::shader_read()),
+                GraphAccess::Buffer(
+                    sun_occlusion,
+                    vk::PipelineStageFlags2::VERTEX_SHADER
+                        | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_READ,
+                ),
+            ],
+            &[GraphAccess::Image(
+                render_target,
+                ImageLayoutState::color_attachment(),
+            )],
+            move |commands, images| {
+                let (render_target, depth_buffer) = images.pair(render_target, depth_buffer);
+                lens_flare.draw(
+                    commands,
+                    render_target_index,
+                    camera_buffer_address,
+                    render_target,
+                    depth_buffer,
+                );
+                Ok(())
+            },
+        );
+        let passes = graph.execute(commands)?;
+        self.frame_graph.add_passes(passes);
+        Ok(())
+    }
+
+    fn draw_skybox(&mut self, commands: &Commands, render_target_index: usize) {
+        let procedural_sky = self
+            .procedural_sky
+            .is_enabled()
+            .then(|| self.procedural_sky.cubemap());
+        if self.skybox_renderer.cubemap().is_none() && procedural_sky.is_none() {
+            return;
+        }
+        self.skybox_renderer.draw(
+            commands,
+            render_target_index,
+            self.scene_buffers[render_target_index].camera_address(),
+            self.lighting.sky_luminance,
+            procedural_sky,
+        );
+        self.frame_graph.add_pass(
+            "skybox",
+            &[
+                ("scene_buffer", None),
+                ("skybox", Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+                (
+                    "msaa_depth_buffer",
+                    Some(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+                ),
+            ],
+            &[(
+                "msaa_render_target",
+                Some(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            )],
+        );
+    }
+
+    // BCn texture uploaded with the mip levels it comes with, a single level isn't extended
+    // since block compressed formats can't be blitted.
+    pub fn add_compressed_texture(
+        &mut self,
+        name: &str,
+        texture: CompressedTexture,
+    ) -> Result<u32> {
+        if !self.context.supports_format(
+            texture.format,
+            vk::FormatFeatureFlags::SAMPLED_IMAGE
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR
+                | vk::FormatFeatureFlags::TRANSFER_DST,
+            vk::ImageTiling::OPTIMAL,
+        ) {
+            return Err(anyhow::anyhow!(
+                "{:?} textures aren't supported by the device",
+                texture.format
+            ));
+        }
+        self.add_texture_pixels(name, texture.extent, texture.format, texture.levels)
+    }
+
+    // Loads a KTX2 or DDS file with a BCn payload.
+    pub fn load_compressed_texture(&mut self, path: impl AsRef<Path>) -> Result<u32> {
+        let path = path.as_ref();
+        let texture = CompressedTexture::load(path)?;
+        self.add_compressed_texture(&path.to_string_lossy(), texture)
+    }
+
+    // Sparse images are owned by the caller, their pages are allocated from the renderer's
+    // memory through the methods below. Fails without sparse residency support.
+    pub fn create_sparse_image(
+        &mut self,
+        name: &str,
+        attributes: SparseImageAttributes,
+    ) -> Result<SparseImage> {
+        SparseImage::new(self.context.clone(), &mut self.allocator, name, attributes)
+    }
+
+    // Queued until the next flush_sparse_image.
+    pub fn bind_sparse_page(&mut self, image: &mut SparseImage, x: u32, y: u32) -> Result<()> {
+        image.bind_page(&mut self.allocator, x, y)
+    }
+
+    // Applies the image's queued binds and unbinds, freeing the unbound pages.
+    pub fn flush_sparse_image(&mut self, image: &mut SparseImage) -> Result<()> {
+        image.flush(&mut self.allocator)
+    }
+
+    // Waits for the device to be idle, frames in flight may still sample the image.
+    pub fn destroy_sparse_image(&mut self, mut image: SparseImage) -> Result<()> {
+        unsafe { self.context.device.device_wait_idle()? };
+        image.destroy(&mut self.allocator)
+    }
+
+    fn add_texture_pixels(
+        &mut self,
+        name: &str,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        levels: Vec<Vec<u8>>,
+    ) -> Result<u32> {
+        let index = self.textures.len();
+        if index as u32 >= MAX_TEXTURES - self.sampled_textures.len() as u32 {
+            return Err(anyhow::anyhow!("Texture limit of {} reached", MAX_TEXTURES));
+        }
+
+        let texture = Image::new_texture(
+            self.context.clone(),
+            &mut self.allocator,
+            name,
+            extent,
+            format,
+            match levels.len() {
+                1 => texture_mip_levels(&self.context, extent, format),
+                level_count => level_count as u32,
+            },
+        )?;
+
+        self.write_texture_descriptor(index as u32, texture.view, self.texture_sampler);
+        self.textures.push(texture);
+        self.pending_uploads
+            .push(PendingUpload::Texture { index, levels });
+        Ok(index as u32)
+    }
+
+    fn write_texture_descriptor(&self, slot: u32, view: vk::ImageView, sampler: vk::Sampler) {
+        let image_info = [vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .sampler(sampler)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        unsafe {
+            self.context.device.update_descriptor_sets(
+                &self
+                    .descriptor_sets
+                    .iter()
+                    .map(|descriptor_set| {
+                        vk::WriteDescriptorSet::default()
+                            .dst_set(*descriptor_set)
+                            .dst_binding(0)
+                            .dst_array_element(slot)
+                            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                            .image_info(&image_info)
+                    })
+                    .collect::<Vec<_>>(),
+                &[],
+            );
+        }
+    }
+
+    // Bindless slot sampling the texture through the sampler. Textures are registered with the
+    // default sampler, other samplers get a slot of their own per texture.
+    fn texture_slot(&mut self, texture: Option<u32>, sampler: SamplerAttributes) -> Result<u32> {
+        let Some(texture) = texture else {
+            return Ok(INVALID_TEXTURE_INDEX);
+        };
+        let view = self
+            .textures
+            .get(texture as usize)
+            .ok_or_else(|| anyhow::anyhow!("Texture {texture} doesn't exist"))?
+            .view;
+        if sampler == SamplerAttributes::default() {
+            return Ok(texture);
+        }
+        if let Some(&slot) = self.sampled_textures.get(&(texture, sampler)) {
+            return Ok(slot);
+        }
+
+        let slot = MAX_TEXTURES - 1 - self.sampled_textures.len() as u32;
+        if slot < self.textures.len() as u32 {
+            return Err(anyhow::anyhow!("Texture limit of {} reached", MAX_TEXTURES));
+        }
+        let vk_sampler = match self.samplers.get(&sampler) {
+            Some(&vk_sampler) => vk_sampler,
+            None => {
+                let vk_sampler = unsafe {
+                    self.context
+                        .device
+                        .create_sampler(&sampler.create_info(), None)?
+                };
+                self.samplers.insert(sampler, vk_sampler);
+                vk_sampler
+            }
+        };
+        self.write_texture_descriptor(slot, view, vk_sampler);
+        self.sampled_textures.insert((texture, sampler), slot);
+        Ok(slot)
+    }
+
+    fn material_slots(&mut self, material: &Material) -> Result<MaterialSlots> {
+        Ok(MaterialSlots {
+            base_color: self.texture_slot(material.base_color_texture, material.sampler)?,
+            normal: self.texture_slot(material.normal_texture, material.sampler)?,
+            // Lightmaps are low resolution, so they're always filtered and never repeat.
+            lightmap: self.texture_slot(
+                material.lightmap_texture,
+                SamplerAttributes {
+                    filter: vk::Filter::LINEAR,
+                    address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                },
+            )?,
+        })
+    }
+
+    // Instances use the material through set_instance_material from the next frame on.
+    pub fn add_material(&mut self, material: Material) -> Result<u32> {
+        let slots = self.material_slots(&material)?;
+        Ok(self.materials.push(material, slots))
+    }
+
+    // Adds every model of the OBJ file as a mesh of its own, with a material made from its .mtl
+    // material, diffuse texture and bump map, taken as a tangent space normal map. Returns the
+    // mesh and material index of each model, to be instanced together; models without a
+    // material use material 0.
+    pub fn load_obj(&mut self, path: impl AsRef<Path>) -> Result<Vec<(MeshHandle, u32)>> {
+        let path = path.as_ref();
+        let (geometry, obj_materials) = Geometry::load_obj_with_materials(path)?;
+
+        let mut load_texture = |texture_path: &Option<PathBuf>| -> Result<Option<u32>> {
+            let Some(texture_path) = texture_path else {
+                return Ok(None);
+            };
+            match ::image::ImageReader::open(texture_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|reader| Ok(reader.decode()?))
+            {
+                Ok(image) => Ok(Some(
+                    self.add_texture(&texture_path.to_string_lossy(), &image.into_rgba8())?,
+                )),
+                Err(error) => {
+                    warn!("Failed to load texture {texture_path:?} of {path:?}: {error}");
+                    Ok(None)
+                }
+            }
+        };
+        let mut textures = Vec::with_capacity(obj_materials.len());
+        for obj_material in &obj_materials {
+            textures.push((
+                load_texture(&obj_material.diffuse_texture)?,
+                load_texture(&obj_material.normal_texture)?,
+            ));
+        }
+
+        let mut materials = Vec::with_capacity(obj_materials.len());
+        for (obj_material, (base_color_texture, normal_texture)) in
+            obj_materials.iter().zip(textures)
+        {
+            materials.push(self.add_material(Material {
+                base_color_texture,
+                normal_texture,
+                base_color_factor: obj_material.diffuse.push(obj_material.dissolve),
+                ..Default::default()
+            })?);
+        }
+
+        self.add_submeshes(&geometry, &materials)
+    }
+
+    // Same as load_obj for a .gltf or .glb file, with materials made from the base color factor
+    // and texture. Every triangle primitive of the default scene becomes a mesh, already placed by
+    // its node, so the meshes are instanced with the same transform.
+    pub fn load_gltf(&mut self, path: impl AsRef<Path>) -> Result<Vec<(MeshHandle, u32)>> {
+        let path = path.as_ref();
+        let (geometry, gltf_materials) = Geometry::load_gltf(path)?;
+
+        let mut materials = Vec::with_capacity(gltf_materials.len());
+        for gltf_material in &gltf_materials {
+            let base_color_texture = gltf_material
+                .base_color_texture
+                .as_ref()
+                .map(|image| {
+                    self.add_texture(
+                        &format!("{}#{}", path.to_string_lossy(), gltf_material.name),
+                        image,
+                    )
+                })
+                .transpose()?;
+            materials.push(self.add_material(Material {
+                base_color_texture,
+                sampler: gltf_material.sampler,
+                base_color_factor: gltf_material.base_color_factor,
+                ..Default::default()
+            })?);
+        }
+
+        self.add_submeshes(&geometry, &materials)
+    }
+
+    // Submeshes without a material, or with one that failed to load, use material 0.
+    fn add_submeshes(
+        &mut self,
+        geometry: &Geometry,
+        materials: &[u32],
+    ) -> Result<Vec<(MeshHandle, u32)>> {
+        (0..geometry.submeshes.len())
+            .map(|submesh| {
+                let mesh = self.add_mesh(geometry.submesh(submesh))?;
+                let material = geometry.submeshes[submesh]
+                    .material
+                    .and_then(|material| materials.get(material).copied())
+                    .unwrap_or(0);
+                Ok((mesh, material))
+            })
+            .collect()
+    }
+
+    pub fn material(&self, index: u32) -> Option<&Material> {
+        self.materials.get(index as usize)
+    }
+
+    pub fn set_material(&mut self, index: u32, material: Material) -> Result<()> {
+        if index as usize >= self.materials.len() {
+            return Err(anyhow::anyhow!("Material {index} doesn't exist"));
+        }
+        let slots = self.material_slots(&material)?;
+        self.materials.set(index as usize, material, slots);
+        Ok(())
+    }
+
+    fn write_timestamp(&self, commands: &Commands, render_target_index: usize, query: u32) {
+        if let Some(query_pool) = &self.frames[render_target_index].timestamp_query_pool {
+            commands.write_timestamp(query_pool, vk::PipelineStageFlags2::ALL_COMMANDS, query);
+        }
+    }
+
+    fn push_constants(&self, render_target_index: usize, mesh: MeshHandle) -> PushConstants {
+        let gpu_mesh = self.gpu_mesh(mesh);
+        PushConstants {
+            vertex_buffer_address: gpu_mesh.vertex_buffer.address,
+            scene_buffer_address: self.scene_buffers[render_target_index].objects_address(),
+            camera_buffer_address: self.scene_buffers[render_target_index].camera_address(),
+            flags: if self.probe_grid.is_some() {
+                PROBE_GRID_FLAG
+            } else {
+                0
+            } | if gpu_mesh.skin_buffer.is_some() {
+                SKINNING_FLAG
+            } else {
+                0
+            } | if gpu_mesh.dequantization.is_some() {
+                QUANTIZED_FLAG
+            } else {
+                0
+            } | if self.image_based_lighting.has_environment() {
+                IMAGE_BASED_LIGHTING_FLAG
+            } else {
+                0
+            } | if self.sun_shadow.is_enabled() {
+                SUN_SHADOW_FLAG
+            } else {
+                0
+            },
+            padding: 0,
+            probe_grid_address: self.probe_grid.as_ref().map_or(0, ProbeGrid::address),
+            skin_buffer_address: gpu_mesh
+                .skin_buffer
+                .as_ref()
+                .map_or(0, |skin_buffer| skin_buffer.address),
+            joint_buffer_address: self.joint_buffer.joint_buffer_address(render_target_index),
+            joint_offset_buffer_address: self
+                .joint_buffer
+                .joint_offset_buffer_address(render_target_index),
+            position_offset: gpu_mesh
+                .dequantization
+                .map_or(na::Vector3::zeros(), |dequantization| dequantization.offset),
+            position_scale: gpu_mesh
+                .dequantization
+                .map_or(na::Vector3::repeat(1.0), |dequantization| {
+                    dequantization.scale
+                }),
+            outline_color: na::Vector4::zeros(),
+            outline_size: na::Vector2::zeros(),
+            point_light_buffer_address: self.point_lights.address(render_target_index),
+            material_buffer_address: self.materials.address(render_target_index),
+        }
+    }
+
+    // Instances that change after they were first drawn become dynamic casters, the static
+    // point shadows are redrawn once for each of them and whenever a static caster appears.
+    fn update_shadow_casters(&mut self, world_transforms: &[na::Matrix4<f64>]) {
+        self.shadow_casters
+            .resize(world_transforms.len(), ShadowCaster::default());
+        let mut invalidate = false;
+        for (index, world_transform) in world_transforms.iter().enumerate() {
+            let skinned = !self.joint_buffer.joint_matrices(index).is_empty();
+            let caster = &mut self.shadow_casters[index];
+            match caster.world_transform {
+                None => invalidate |= !caster.dynamic,
+                Some(previous) if !caster.dynamic && (previous != *world_transform || skinned) => {
+                    caster.dynamic = true;
+                    invalidate = true;
+                }
+                _ => {}
+            }
+            caster.world_transform = Some(*world_transform);
+        }
+        if invalidate {
+            self.point_lights.invalidate_static_shadows();
+        }
+    }
+
+    // Redraws the cached shadows of static casters in the next frame, needed after changes the
+    // renderer can't see, e.g. to the vertices of a shared mesh through a handle kept elsewhere.
+    pub fn invalidate_shadow_cache(&mut self) {
+        self.point_lights.invalidate_static_shadows();
+    }
+
+    fn draw_point_shadows(&mut self, commands: &Commands, render_target_index: usize) {
+        // Visible instance runs split further into runs of static and dynamic casters.
+        let mut draws = Vec::new();
+        for (mesh, instances) in self.visible_instance_ranges() {
+            let push_constants = self.push_constants(render_target_index, mesh);
+            let mut first = instances.start;
+            while first < instances.end {
+                let dynamic = self.shadow_casters[first as usize].dynamic;
+                let mut end = first + 1;
+                while end < instances.end && self.shadow_casters[end as usize].dynamic == dynamic {
+                    end += 1;
+                }
+                draws.push((mesh, push_constants, first..end, dynamic));
+                first = end;
+            }
+        }
+        let has_dynamic_casters = draws.iter().any(|(.., dynamic)| *dynamic);
+        let meshes = &self.meshes;
+        let pipeline_layout = self.pipeline_layout;
+        self.point_lights.draw_shadows(
+            commands,
+            render_target_index,
+            has_dynamic_casters,
+            |commands, camera_buffer_address, draw_dynamic, faces| {
+                for (mesh, push_constants, instances, dynamic) in &draws {
+                    if *dynamic != draw_dynamic {
+                        continue;
+                    }
+                    let gpu_mesh = meshes[mesh.0].as_ref().unwrap();
+                    commands
+                        .bind_index_buffer(&gpu_mesh.index_buffer)
+                        .set_push_constants(
+                            pipeline_layout,
+                            PushConstants {
+                                camera_buffer_address,
+                                ..*push_constants
+                            },
+                        )
+                        .draw_indexed(
+                            0..gpu_mesh.geometry.indices.len() as u32,
+                            instances.start * faces..instances.end * faces,
+                        );
+                }
+            },
+        );
+    }
+
+    // Every visible instance casts, the map follows the camera so nothing is cached.
+    fn draw_sun_shadow(&mut self, commands: &Commands, render_target_index: usize) {
+        let draws = self
+            .visible_instance_ranges()
+            .into_iter()
+            .map(|(mesh, instances)| {
+                (
+                    mesh,
+                    self.push_constants(render_target_index, mesh),
+                    instances,
+                )
+            })
+            .collect::<Vec<_>>();
+        let meshes = &self.meshes;
+        let pipeline_layout = self.pipeline_layout;
+        self.sun_shadow.draw(
+            commands,
+            render_target_index,
+            |commands, camera_buffer_address| {
+                for (mesh, push_constants, instances) in &draws {
+                    let gpu_mesh = meshes[mesh.0].as_ref().unwrap();
+                    commands
+                        .bind_index_buffer(&gpu_mesh.index_buffer)
+                        .set_push_constants(
+                            pipeline_layout,
+                            PushConstants {
+                                camera_buffer_address,
+                                ..*push_constants
+                            },
+                        )
+                        .draw_indexed(0..gpu_mesh.geometry.indices.len() as u32, instances.clone());
+                }
+            },
+        );
+    }
+
+    // Renders the probes whose turn it is, lit like the scene including the shadow maps, and
+    // blends them into the grid. Returns whether anything was captured.
+    fn capture_probes(&mut self, commands: &Commands, render_target_index: usize) -> bool {
+        // Probes are stored relative to the baked illuminance, zero turns them off.
+        if !self
+            .probe_grid
+            .as_ref()
+            .is_some_and(ProbeGrid::is_capturing)
+            || self.lighting.baked_illuminance <= 0.0
+        {
+            return false;
+        }
+
+        // Point lights are binned for the main camera only, the faces look elsewhere.
+        let draws = self
+            .visible_instance_ranges()
+            .into_iter()
+            .map(|(mesh, instances)| {
+                let push_constants = self.push_constants(render_target_index, mesh);
+                (
+                    mesh,
+                    PushConstants {
+                        flags: push_constants.flags | UNCLUSTERED_LIGHTS_FLAG,
+                        ..push_constants
+                    },
+                    instances,
+                )
+            })
+            .collect::<Vec<_>>();
+        commands
+            .expect_sampled(self.point_lights.shadow_map(), "probe_capture")
+            .expect_sampled(self.point_lights.spot_shadow_map(), "probe_capture")
+            .expect_sampled(self.sun_shadow.shadow_map(), "probe_capture");
+        for texture in &self.textures {
+            commands.expect_sampled(texture, "probe_capture");
+        }
+        commands.bind_descriptor_sets(self.pipeline_layout, &self.descriptor_sets);
+
+        let meshes = &self.meshes;
+        let pipeline_layout = self.pipeline_layout;
+        let vertex_input = self.attributes.vertex_input;
+        // A uniform background of E / π cd/m² lights surfaces with E lux, like the ambient light
+        // does without probes.
+        let background = na::Vector3::repeat(self.lighting.ambient_illuminance / PI);
+        let radiance_scale = PI / self.lighting.baked_illuminance;
+        self.probe_grid.as_mut().unwrap().capture(
+            commands,
+            render_target_index,
+            self.probe_capture_pipeline,
+            background,
+            radiance_scale,
+            |commands, camera_buffer_address| {
+                for (mesh, push_constants, instances) in &draws {
+                    let gpu_mesh = meshes[mesh.0].as_ref().unwrap();
+                    commands.bind_index_buffer(&gpu_mesh.index_buffer);
+                    if vertex_input {
+                        commands.bind_vertex_buffers(0, &[&gpu_mesh.vertex_buffer]);
+                    }
+                    commands
+                        .set_push_constants(
+                            pipeline_layout,
+                            PushConstants {
+                                camera_buffer_address,
+                                ..*push_constants
+                            },
+                        )
+                        .draw_indexed(0..gpu_mesh.geometry.indices.len() as u32, instances.clone());
+                }
+            },
+        );
+        true
+    }
+
+    // Consecutive visible instances of the same mesh are drawn together.
+    fn visible_instance_ranges(&self) -> Vec<(MeshHandle, Range<u32>)> {
+        let mut ranges = Vec::new();
+        let mut instance_index = 0;
+        while instance_index < self.instances.len() {
+            if !self.is_instance_visible(instance_index) {
+                instance_index += 1;
+                continue;
+            }
+            let first = instance_index;
+            let mesh = self.instances[first].mesh;
+            while instance_index < self.instances.len()
+                && self.is_instance_visible(instance_index)
+                && self.instances[instance_index].mesh == mesh
+            {
+                instance_index += 1;
+            }
+            ranges.push((mesh, first as u32..instance_index as u32));
+        }
+        ranges
+    }
+
+    // A bucket per mesh that has instances, in mesh order, with a draw per visible run of its
+    // instances and room for one per instance.
+    fn indirect_draw_buckets(&self) -> Vec<(MeshHandle, DrawBucket)> {
+        let mut buckets = vec![None::<DrawBucket>; self.meshes.len()];
+        for instance in &self.instances {
+            buckets[instance.mesh.0]
+                .get_or_insert_with(Default::default)
+                .capacity += 1;
+        }
+        for (mesh, instances) in self.visible_instance_ranges() {
+            if let Some(bucket) = &mut buckets[mesh.0] {
+                bucket.draws.push(DrawIndexedIndirectCommand {
+                    index_count: self.mesh_indices(mesh).end,
+                    instance_count: instances.end - instances.start,
+                    first_index: 0,
+                    vertex_offset: 0,
+                    first_instance: instances.start,
+                });
+            }
+        }
+        buckets
+            .into_iter()
+            .enumerate()
+            .filter_map(|(mesh, bucket)| Some((MeshHandle(mesh), bucket?)))
+            .collect()
+    }
+
+    // Object space minimum and maximum corner of the mesh's vertices.
+    pub fn mesh_bounds(&self, mesh: MeshHandle) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        self.gpu_mesh(mesh).bounds
+    }
+
+    fn gpu_mesh(&self, mesh: MeshHandle) -> &GPUGeometry {
+        self.meshes[mesh.0].as_ref().unwrap()
+    }
+
+    fn mesh_indices(&self, mesh: MeshHandle) -> Range<u32> {
+        0..self.gpu_mesh(mesh).geometry.indices.len() as u32
+    }
+
+    // Binds the mesh's buffers along with push constants pointing at its vertices.
+    fn bind_mesh(&self, commands: &Commands, mesh: MeshHandle, push_constants: PushConstants) {
+        let gpu_mesh = self.gpu_mesh(mesh);
+        commands.bind_index_buffer(&gpu_mesh.index_buffer);
+        if self.attributes.vertex_input {
+            commands.bind_vertex_buffers(0, &[&gpu_mesh.vertex_buffer]);
+        }
+        commands.set_push_constants(self.pipeline_layout, push_constants);
+    }
+
+    fn draw_outlines(&self, commands: &Commands, render_target_index: usize) {
+        if self.selection.is_empty() {
+            return;
+        }
+
+        let extent = self.attributes.extent;
+        let outline_size = na::Vector2::new(
+            2.0 * self.outline.width_pixels / extent.width as f32,
+            2.0 * self.outline.width_pixels / extent.height as f32,
+        );
+        commands.bind_pipeline(self.outline_pipeline);
+        let mut bound_mesh = None;
+        for &instance in self
+            .selection
+            .range(..self.instances.len())
+            .filter(|&&instance| self.is_instance_visible(instance))
+        {
+            let mesh = self.instances[instance].mesh;
+            if bound_mesh != Some(mesh) {
+                self.bind_mesh(
+                    commands,
+                    mesh,
+                    PushConstants {
+                        outline_color: self.outline.color,
+                        outline_size,
+                        ..self.push_constants(render_target_index, mesh)
+                    },
+                );
+                bound_mesh = Some(mesh);
+            }
+            let instance = instance as u32;
+            commands.draw_indexed(self.mesh_indices(mesh), instance..instance + 1);
+        }
+    }
+
+    pub fn draw(&self, commands: &Commands, render_target_index: usize) {
+        let render_target = &self.frames[render_target_index].render_target;
+
+        commands
+            .set_viewport(
                 vk::Viewport::default()
                     .width(render_target.attributes.extent.width as f32)
                     .height(render_target.attributes.extent.height as f32)
@@ -557,21 +3280,602 @@ impl Renderer {
                         .height(render_target.attributes.extent.height),
                 ),
             )
+            .expect_sampled(self.point_lights.shadow_map(), "scene")
+            .expect_sampled(self.point_lights.spot_shadow_map(), "scene")
+            .expect_sampled(self.sun_shadow.shadow_map(), "scene");
+        for texture in &self.textures {
+            commands.expect_sampled(texture, "scene");
+        }
+        commands
             .bind_pipeline(self.pipeline)
-            .bind_descriptor_sets(self.pipeline_layout, &self.descriptor_sets)
-            .bind_index_buffer(&self.gpu_geometry.index_buffer)
-            .set_push_constants(
-                self.pipeline_layout,
-                PushConstants {
-                    vertex_buffer_address: self.gpu_geometry.vertex_buffer.address,
-                    instance_buffer_address: self.instance_buffer.address,
-                    camera_buffer_address: self.camera_buffer.address,
-                },
-            )
-            .draw_indexed(
-                0..self.gpu_geometry.geometry.indices.len() as u32,
-                0..self.instances.len() as u32,
+            .bind_descriptor_sets(self.pipeline_layout, &self.descriptor_sets);
+
+        let frame = &self.frames[render_target_index];
+
+        let predicate_buffer = frame
+            .predicate_buffer
+            .as_ref()
+            .filter(|_| frame.has_predicates)
+            // Frames in flight take turns, counting their own frames keeps every one of them
+            // refreshing instead of only the one the interval lines up with.
+            .filter(|_| {
+                !(self.frame_count / self.attributes.buffering as u64)
+                    .is_multiple_of(PREDICATE_REFRESH_INTERVAL)
+            });
+
+        match &frame.occlusion_query_pool {
+            Some(query_pool) => {
+                let mut bound_mesh = None;
+                for instance_index in 0..self.instances.len() as u32 {
+                    // Culled instances still get a query so the pool's results stay available.
+                    commands.begin_query(query_pool, instance_index);
+                    if !self.is_instance_visible(instance_index as usize) {
+                        commands.end_query(query_pool, instance_index);
+                        continue;
+                    }
+                    let mesh = self.instances[instance_index as usize].mesh;
+                    if bound_mesh != Some(mesh) {
+                        self.bind_mesh(
+                            commands,
+                            mesh,
+                            self.push_constants(render_target_index, mesh),
+                        );
+                        bound_mesh = Some(mesh);
+                    }
+                    if let Some(predicate_buffer) = predicate_buffer {
+                        commands.begin_conditional_rendering(
+                            predicate_buffer,
+                            (instance_index as usize * size_of::<u32>()) as vk::DeviceSize,
+                            false,
+                        );
+                    }
+                    commands
+                        .draw_indexed(self.mesh_indices(mesh), instance_index..instance_index + 1);
+                    if predicate_buffer.is_some() {
+                        commands.end_conditional_rendering();
+                    }
+                    commands.end_query(query_pool, instance_index);
+                }
+            }
+            None => match &self.indirect_draw_buffers {
+                Some(indirect_draw_buffers) => {
+                    // The draw lists were written with the same buckets, one count draw per
+                    // mesh.
+                    for (bucket, (mesh, _)) in self.indirect_draw_buckets().into_iter().enumerate()
+                    {
+                        self.bind_mesh(
+                            commands,
+                            mesh,
+                            self.push_constants(render_target_index, mesh),
+                        );
+                        indirect_draw_buffers[render_target_index].draw(commands, bucket);
+                    }
+                }
+                None => {
+                    for (mesh, instances) in self.visible_instance_ranges() {
+                        self.bind_mesh(
+                            commands,
+                            mesh,
+                            self.push_constants(render_target_index, mesh),
+                        );
+                        commands.draw_indexed(self.mesh_indices(mesh), instances);
+                    }
+                }
+            },
+        }
+    }
+
+    // Uploaded at the start of the next frame; an empty slice leaves the instance unskinned.
+    pub fn set_joint_matrices(&mut self, instance: usize, joint_matrices: &[na::Matrix4<f32>]) {
+        self.joint_buffer
+            .set_joint_matrices(instance, joint_matrices);
+    }
+
+    pub fn joint_matrices(&self, instance: usize) -> &[na::Matrix4<f32>] {
+        self.joint_buffer.joint_matrices(instance)
+    }
+
+    pub fn attach_to_joint(&mut self, instance: usize, attachment: JointAttachment) -> Result<()> {
+        let mut parent = Some(attachment.parent);
+        while let Some(ancestor) = parent {
+            if ancestor == instance {
+                return Err(anyhow::anyhow!(
+                    "Attaching instance {} to instance {} would create a cycle",
+                    instance,
+                    attachment.parent
+                ));
+            }
+            parent = self.instances[ancestor]
+                .attachment
+                .map(|attachment| attachment.parent);
+        }
+
+        self.instances[instance].attachment = Some(attachment);
+        Ok(())
+    }
+
+    pub fn detach(&mut self, instance: usize) {
+        self.instances[instance].attachment = None;
+    }
+
+    fn is_instance_visible(&self, instance: usize) -> bool {
+        self.instances[instance].layers & self.cameras[0].cull_mask != 0
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.instances.len()
+    }
+
+    // Drawn from the next frame on, the frame's instance buffers grow to fit it. Returns the new
+    // instance's index, always the last one.
+    pub fn add_instance(
+        &mut self,
+        mesh: MeshHandle,
+        material_index: u32,
+        transform: na::Affine3<f64>,
+    ) -> Result<usize> {
+        if !self.meshes.get(mesh.0).is_some_and(Option::is_some) {
+            return Err(anyhow::anyhow!("Mesh {mesh:?} doesn't exist"));
+        }
+        if material_index as usize >= self.materials.len() {
+            return Err(anyhow::anyhow!("Material {material_index} doesn't exist"));
+        }
+        self.instances.push(Instance {
+            transform,
+            layers: DEFAULT_LAYER,
+            material_index,
+            mesh,
+            attachment: None,
+            user_data: 0,
+        });
+        self.joint_buffer.add_instance();
+        // The predicate buffers may have room for the instance already, holding whatever a
+        // removed instance or nothing at all left there.
+        for frame in &mut self.frames {
+            frame.has_predicates = false;
+        }
+        Ok(self.instances.len() - 1)
+    }
+
+    // Makes every instance's previous transform its current one in the next frame, e.g. after a
+    // camera cut or teleport, so they don't show a frame of motion.
+    pub fn reset_previous_transforms(&mut self) {
+        self.previous_transforms.clear();
+    }
+
+    // Later instances move down by one index, selection, joint attachments and scene nodes are
+    // updated to match. Instances attached to the removed one are detached, keeping their
+    // transforms.
+    pub fn remove_instance(&mut self, instance: usize) -> Result<()> {
+        if instance >= self.instances.len() {
+            return Err(anyhow::anyhow!(
+                "Instance {instance} doesn't exist, there are {}",
+                self.instances.len()
+            ));
+        }
+        self.instances.remove(instance);
+        if instance < self.previous_transforms.len() {
+            self.previous_transforms.remove(instance);
+        }
+        if instance < self.shadow_casters.len() && !self.shadow_casters.remove(instance).dynamic {
+            self.point_lights.invalidate_static_shadows();
+        }
+        self.joint_buffer.remove_instance(instance);
+        self.scene.remove_instance(instance);
+        for other in &mut self.instances {
+            match &mut other.attachment {
+                Some(attachment) if attachment.parent == instance => other.attachment = None,
+                Some(attachment) if attachment.parent > instance => attachment.parent -= 1,
+                _ => {}
+            }
+        }
+        self.selection = self
+            .selection
+            .iter()
+            .filter(|&&selected| selected != instance)
+            .map(|&selected| selected - (selected > instance) as usize)
+            .collect();
+
+        // Queries of frames in flight and the last results are indexed by the old instances.
+        self.occlusion_results.clear();
+        for frame in &mut self.frames {
+            frame.has_occlusion_queries = false;
+            frame.has_predicates = false;
+        }
+        Ok(())
+    }
+
+    pub fn instance_layers(&self, instance: usize) -> u32 {
+        self.instances[instance].layers
+    }
+
+    // Bitmask of layers the instance belongs to, DEFAULT_LAYER unless changed.
+    pub fn set_instance_layers(&mut self, instance: usize, layers: u32) {
+        if self.instances[instance].layers != layers {
+            self.instances[instance].layers = layers;
+            self.point_lights.invalidate_static_shadows();
+        }
+    }
+
+    pub fn camera_cull_mask(&self) -> u32 {
+        self.cameras[0].cull_mask
+    }
+
+    // E.g. an editor window's camera includes a gizmo layer that the game window's camera leaves out.
+    pub fn set_camera_cull_mask(&mut self, cull_mask: u32) {
+        if self.cameras[0].cull_mask != cull_mask {
+            self.cameras[0].cull_mask = cull_mask;
+            self.point_lights.invalidate_static_shadows();
+        }
+    }
+
+    pub fn select(&mut self, instance: usize) {
+        self.selection.insert(instance);
+    }
+
+    pub fn deselect(&mut self, instance: usize) {
+        self.selection.remove(&instance);
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection.clear();
+    }
+
+    pub fn is_selected(&self, instance: usize) -> bool {
+        self.selection.contains(&instance)
+    }
+
+    pub fn selection(&self) -> impl Iterator<Item = usize> + '_ {
+        self.selection.iter().copied()
+    }
+
+    pub fn instance_material(&self, instance: usize) -> u32 {
+        self.instances[instance].material_index
+    }
+
+    pub fn set_instance_material(&mut self, instance: usize, material_index: u32) {
+        debug_assert!(
+            (material_index as usize) < self.materials.len(),
+            "Material {material_index} doesn't exist"
+        );
+        self.instances[instance].material_index = material_index;
+    }
+
+    pub fn instance_mesh(&self, instance: usize) -> MeshHandle {
+        self.instances[instance].mesh
+    }
+
+    pub fn set_instance_mesh(&mut self, instance: usize, mesh: MeshHandle) {
+        debug_assert!(
+            self.meshes.get(mesh.0).is_some_and(Option::is_some),
+            "Mesh {mesh:?} doesn't exist"
+        );
+        if self.instances[instance].mesh != mesh {
+            self.instances[instance].mesh = mesh;
+            self.point_lights.invalidate_static_shadows();
+        }
+    }
+
+    pub fn instance_transform(&self, instance: usize) -> &na::Affine3<f64> {
+        &self.instances[instance].transform
+    }
+
+    // Relative to the joint for attached instances.
+    pub fn set_instance_transform(&mut self, instance: usize, transform: na::Affine3<f64>) {
+        self.instances[instance].transform = transform;
+    }
+
+    pub fn instance_user_data(&self, instance: usize) -> u64 {
+        self.instances[instance].user_data
+    }
+
+    // Opaque to the renderer, e.g. the id of the game entity the instance belongs to. 0 unless
+    // changed.
+    pub fn set_instance_user_data(&mut self, instance: usize, user_data: u64) {
+        self.instances[instance].user_data = user_data;
+    }
+
+    // Every instance with its user data, in instance order.
+    pub fn instances_user_data(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.instances
+            .iter()
+            .enumerate()
+            .map(|(instance, data)| (instance, data.user_data))
+    }
+
+    pub fn find_instances(&self, user_data: u64) -> impl Iterator<Item = usize> + '_ {
+        self.instances_user_data()
+            .filter(move |&(_, data)| data == user_data)
+            .map(|(instance, _)| instance)
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    // Changes apply in the next rendered frame, overriding transforms set on the attached
+    // instances, lights and camera directly.
+    pub fn scene_mut(&mut self) -> &mut Scene {
+        &mut self.scene
+    }
+
+    // Moves what the changed nodes hold before the frame's objects and lights are written.
+    fn update_scene(&mut self) {
+        for (attachment, world_transform) in self.scene.update() {
+            match attachment {
+                NodeAttachment::Instance(instance) => {
+                    if let Some(instance) = self.instances.get_mut(instance) {
+                        instance.transform = world_transform;
+                    }
+                }
+                NodeAttachment::PointLight(light) => {
+                    if let Some(light) = self.point_lights.lights_mut().get_mut(light) {
+                        light.position = world_transform * na::Point3::origin();
+                    }
+                }
+                NodeAttachment::SpotLight(light) => {
+                    if let Some(light) = self.point_lights.spot_lights_mut().get_mut(light) {
+                        light.position = world_transform * na::Point3::origin();
+                        // Shines down the node's negative z axis like the camera looks.
+                        light.direction = (world_transform * -na::Vector3::z()).cast();
+                    }
+                }
+                // Read every frame, see render.
+                NodeAttachment::Camera => {}
+            }
+        }
+    }
+
+    fn instance_world_transform(&self, index: usize) -> na::Matrix4<f64> {
+        let instance = &self.instances[index];
+        match &instance.attachment {
+            Some(attachment) => {
+                let joint_matrix = self
+                    .joint_buffer
+                    .joint_matrices(attachment.parent)
+                    .get(attachment.joint)
+                    .map_or_else(na::Matrix4::identity, |joint_matrix| joint_matrix.cast());
+                self.instance_world_transform(attachment.parent)
+                    * joint_matrix
+                    * attachment.bind_transform.cast()
+                    * instance.transform.to_homogeneous()
+            }
+            None => instance.transform.to_homogeneous(),
+        }
+    }
+
+    // Casts a ray from the main camera, as of the last rendered frame, through the normalized
+    // device coordinates against the bounding boxes of the visible instances.
+    pub fn hit_test(&self, ndc: na::Point2<f64>) -> Option<InstanceHit> {
+        let camera = &self.cameras[0];
+        let inverse_view_projection = (camera.projection.to_homogeneous().cast::<f64>()
+            * camera.relative_view().cast())
+        .try_inverse()?;
+        let near = inverse_view_projection.transform_point(&na::Point3::new(ndc.x, ndc.y, 0.0));
+        let far = inverse_view_projection.transform_point(&na::Point3::new(ndc.x, ndc.y, 1.0));
+        let origin = camera.position();
+        let direction = (far - near).normalize();
+
+        (0..self.instances.len())
+            .filter(|&instance| self.is_instance_visible(instance))
+            .filter_map(|instance| {
+                let (bounds_min, bounds_max) = self.gpu_mesh(self.instances[instance].mesh).bounds;
+                // Affine, so distances along the object space ray match the world space ones.
+                let inverse = self.instance_world_transform(instance).try_inverse()?;
+                let distance = ray_box_intersection(
+                    &inverse.transform_point(&origin),
+                    &inverse.transform_vector(&direction),
+                    &bounds_min.cast(),
+                    &bounds_max.cast(),
+                )?;
+                Some(InstanceHit {
+                    instance,
+                    user_data: self.instances[instance].user_data,
+                    distance,
+                    position: origin + direction * distance,
+                })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    // World position of a depth buffer sample, None at the far plane where nothing was drawn.
+    pub fn unproject(&self, ndc: na::Point2<f64>, depth: f64) -> Option<na::Point3<f64>> {
+        self.unprojection()?.unproject(ndc, depth)
+    }
+
+    // The current camera's unprojection, kept for depth read back after it moves.
+    pub fn unprojection(&self) -> Option<Unprojection> {
+        let camera = &self.cameras[0];
+        Some(Unprojection {
+            inverse_view_projection: (camera.projection.to_homogeneous().cast::<f64>()
+                * camera.relative_view().cast())
+            .try_inverse()?,
+            camera_position: camera.position(),
+        })
+    }
+
+    // Lines and points recorded here are drawn in the next rendered frame only.
+    pub fn debug_lines(&mut self) -> &mut DebugLines {
+        &mut self.debug_lines
+    }
+
+    pub fn debug_draw(&mut self, source: &mut impl DebugDraw) {
+        source.debug_draw(&mut self.debug_lines);
+    }
+
+    pub fn set_debug_line_width(&mut self, line_width: f32) {
+        self.debug_line_renderer.line_width = line_width;
+    }
+
+    pub fn set_debug_point_size(&mut self, point_size: f32) {
+        self.debug_line_renderer.point_size = point_size;
+    }
+
+    // Layout prebuilt scene pipelines have to be created with.
+    pub fn pipeline_layout(&self) -> vk::PipelineLayout {
+        self.pipeline_layout
+    }
+
+    // Takes ownership of a pipeline created with pipeline_layout() for the scene pass and
+    // returns the previous one, which the caller destroys once no frame in flight uses it.
+    pub fn replace_pipeline(&mut self, pipeline: vk::Pipeline) -> vk::Pipeline {
+        std::mem::replace(&mut self.pipeline, pipeline)
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    // Replaces the default orbiting camera and a scene camera node until cleared with None.
+    pub fn set_camera_look_at(&mut self, look_at: Option<(na::Point3<f64>, na::Point3<f64>)>) {
+        self.camera_look_at = look_at;
+    }
+
+    pub fn frame_look_at(&self) -> (na::Point3<f64>, na::Point3<f64>) {
+        self.frame_look_at
+    }
+
+    // Without a physical camera the field of view stays at 90 degrees and exposure at 1.
+    pub fn set_physical_camera(&mut self, physical: Option<PhysicalCamera>) {
+        let camera = &mut self.cameras[0];
+        camera.physical = physical;
+        camera
+            .projection
+            .set_fovy(physical.map_or(std::f32::consts::FRAC_PI_2, |physical| physical.fovy()));
+    }
+
+    pub fn physical_camera(&self) -> Option<&PhysicalCamera> {
+        self.cameras[0].physical.as_ref()
+    }
+
+    // None goes back to the physical camera's exposure, or 1 without one.
+    pub fn set_exposure(&mut self, exposure: Option<f32>) {
+        self.cameras[0].exposure = exposure;
+    }
+
+    pub fn exposure(&self) -> Option<f32> {
+        self.cameras[0].exposure
+    }
+
+    // Fixed exposure from an exposure value at ISO 100, e.g. 15 for a sunny day.
+    pub fn set_exposure_ev100(&mut self, ev100: f32) {
+        self.set_exposure(Some(ev100_to_exposure(ev100)));
+    }
+
+    // Milliseconds spent on the GPU per pass, as of the last completed frame.
+    pub fn gpu_timings(&self) -> &[(&'static str, f32)] {
+        &self.gpu_timings
+    }
+
+    // Keeps drawing the frustum from the current camera while the camera itself moves on,
+    // so clipping planes and cascades can be inspected from outside.
+    pub fn freeze_debug_camera(&mut self, freeze: bool) {
+        self.frozen_camera = freeze.then(|| self.cameras[0]);
+    }
+
+    fn draw_debug_visualizations(&mut self) {
+        if self.debug_visualizations.grid {
+            // The grid shader only covers the ground plane axes.
+            self.debug_lines.line(
+                &na::Point3::origin(),
+                &na::Point3::new(0.0, 1000.0, 0.0),
+                na::Vector4::new(0.2, 1.0, 0.2, 1.0),
+            );
+        }
+
+        if self.debug_visualizations.bounding_boxes {
+            for instance in 0..self.instances.len() {
+                if !self.is_instance_visible(instance) {
+                    continue;
+                }
+                let (bounds_min, bounds_max) = self.gpu_mesh(self.instances[instance].mesh).bounds;
+                self.debug_lines.oriented_box(
+                    &self.instance_world_transform(instance).cast(),
+                    &bounds_min.into(),
+                    &bounds_max.into(),
+                    na::Vector4::new(1.0, 0.6, 0.1, 1.0),
+                );
+            }
+        }
+
+        if !self.debug_visualizations.camera_frustum {
+            return;
+        }
+
+        let camera = self.frozen_camera.unwrap_or(self.cameras[0]);
+
+        self.debug_lines.frustum(
+            &camera.inverse_view_projection(&camera.projection),
+            na::Vector4::new(1.0, 1.0, 1.0, 1.0),
+        );
+
+        let splits = std::iter::once(camera.projection.znear())
+            .chain(self.debug_visualizations.cascade_splits.iter().copied())
+            .chain(std::iter::once(camera.projection.zfar()))
+            .collect::<Vec<_>>();
+        if splits.len() <= 2 {
+            return;
+        }
+
+        for (index, split) in splits.windows(2).enumerate() {
+            let projection = na::Perspective3::new(
+                camera.projection.aspect(),
+                camera.projection.fovy(),
+                split[0],
+                split[1],
+            );
+            self.debug_lines.frustum(
+                &camera.inverse_view_projection(&projection),
+                CASCADE_COLORS[index % CASCADE_COLORS.len()].into(),
             );
+        }
+    }
+
+    // Uploaded at the start of the next frame, returns the light's index.
+    pub fn add_point_light(&mut self, light: PointLight) -> usize {
+        let lights = self.point_lights.lights_mut();
+        lights.push(light);
+        lights.len() - 1
+    }
+
+    pub fn remove_point_light(&mut self, index: usize) -> PointLight {
+        self.point_lights.lights_mut().remove(index)
+    }
+
+    pub fn point_lights(&self) -> &[PointLight] {
+        self.point_lights.lights()
+    }
+
+    pub fn point_light_mut(&mut self, index: usize) -> &mut PointLight {
+        &mut self.point_lights.lights_mut()[index]
+    }
+
+    // Uploaded at the start of the next frame, returns the light's index.
+    pub fn add_spot_light(&mut self, light: SpotLight) -> usize {
+        let lights = self.point_lights.spot_lights_mut();
+        lights.push(light);
+        lights.len() - 1
+    }
+
+    pub fn remove_spot_light(&mut self, index: usize) -> SpotLight {
+        self.point_lights.spot_lights_mut().remove(index)
+    }
+
+    pub fn spot_lights(&self) -> &[SpotLight] {
+        self.point_lights.spot_lights()
+    }
+
+    pub fn spot_light_mut(&mut self, index: usize) -> &mut SpotLight {
+        &mut self.point_lights.spot_lights_mut()[index]
+    }
+
+    pub fn probe_grid_mut(&mut self) -> Option<&mut ProbeGrid> {
+        self.probe_grid.as_mut()
+    }
+
+    // Samples passed per instance, as of the last completed frame that recorded queries.
+    pub fn occlusion_results(&self) -> &[u64] {
+        &self.occlusion_results
     }
 }
 
@@ -595,11 +3899,46 @@ impl Drop for Renderer {
             self.context
                 .device
                 .destroy_sampler(self.texture_sampler, None);
+            for sampler in self.samplers.values() {
+                self.context.device.destroy_sampler(*sampler, None);
+            }
+            self.materials.destroy(&mut self.allocator).unwrap();
 
-            self.instance_buffer.destroy(&mut self.allocator).unwrap();
-            self.camera_buffer.destroy(&mut self.allocator).unwrap();
+            for scene_buffer in &mut self.scene_buffers {
+                scene_buffer.buffer.destroy(&mut self.allocator).unwrap();
+            }
+            if let Some(probe_grid) = &mut self.probe_grid {
+                probe_grid.destroy(&mut self.allocator).unwrap();
+            }
+            self.joint_buffer.destroy(&mut self.allocator).unwrap();
+            self.point_lights.destroy(&mut self.allocator).unwrap();
+            self.light_clusters.destroy(&mut self.allocator).unwrap();
+            self.sun_shadow.destroy(&mut self.allocator).unwrap();
+            for indirect_draw_buffer in self.indirect_draw_buffers.iter_mut().flatten() {
+                indirect_draw_buffer.destroy(&mut self.allocator).unwrap();
+            }
+            self.debug_line_renderer
+                .destroy(&mut self.allocator)
+                .unwrap();
+            self.skybox_renderer.destroy(&mut self.allocator).unwrap();
+            self.weather.destroy(&mut self.allocator).unwrap();
+            self.lens_flare.destroy(&mut self.allocator).unwrap();
+            self.image_based_lighting
+                .destroy(&mut self.allocator)
+                .unwrap();
+            self.procedural_sky.destroy(&mut self.allocator).unwrap();
             self.staging_belt.destroy(&mut self.allocator).unwrap();
-            self.gpu_geometry.destroy(&mut self.allocator).unwrap();
+            for upload_belt in self.upload_belts.iter_mut().flatten() {
+                upload_belt.destroy(&mut self.allocator).unwrap();
+            }
+            for gpu_mesh in self
+                .meshes
+                .iter_mut()
+                .flatten()
+                .chain(self.retired_meshes.iter_mut().map(|(gpu_mesh, _)| gpu_mesh))
+            {
+                gpu_mesh.destroy(&mut self.allocator).unwrap();
+            }
             for mut frame in self.frames.drain(..) {
                 frame.render_target.destroy(&mut self.allocator).unwrap();
                 frame.depth_buffer.destroy(&mut self.allocator).unwrap();
@@ -611,9 +3950,21 @@ impl Drop for Renderer {
                     .msaa_depth_buffer
                     .destroy(&mut self.allocator)
                     .unwrap();
+                if let Some(mut predicate_buffer) = frame.predicate_buffer {
+                    predicate_buffer.destroy(&mut self.allocator).unwrap();
+                }
             }
 
             self.context.device.destroy_pipeline(self.pipeline, None);
+            for (pipeline, _) in self.retired_pipelines.drain(..) {
+                self.context.device.destroy_pipeline(pipeline, None);
+            }
+            self.context
+                .device
+                .destroy_pipeline(self.outline_pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline(self.probe_capture_pipeline, None);
             self.context
                 .device
                 .destroy_pipeline_layout(self.pipeline_layout, None);
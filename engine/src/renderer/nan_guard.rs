@@ -0,0 +1,157 @@
+use crate::renderer::commands::Commands;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{
+    ComputePipelineAttributes, Image, ImageLayoutState, RenderingContext,
+};
+use anyhow::Result;
+use ash::vk;
+use std::sync::Arc;
+
+// Only float render targets can hold NaN and infinity, the shader expects this format.
+pub const NAN_GUARD_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+const WORKGROUP_SIZE: u32 = 16;
+
+// Debug pass that paints pixels with NaN or infinite colors and depths outside [0, 1] magenta,
+// in place in the resolved render target.
+pub struct NanGuard {
+    context: Arc<RenderingContext>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    // Per frame in flight.
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl NanGuard {
+    pub fn new(context: Arc<RenderingContext>, buffering: usize) -> Result<Self> {
+        let shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "nan_guard.comp.spv",
+        )?;
+
+        unsafe {
+            let descriptor_set_layout = context.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE),
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(1)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE),
+                ]),
+                None,
+            )?;
+
+            let descriptor_pool = context.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(buffering as u32)
+                    .pool_sizes(&[
+                        vk::DescriptorPoolSize::default()
+                            .ty(vk::DescriptorType::STORAGE_IMAGE)
+                            .descriptor_count(buffering as u32),
+                        vk::DescriptorPoolSize::default()
+                            .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                            .descriptor_count(buffering as u32),
+                    ]),
+                None,
+            )?;
+
+            let descriptor_sets = context.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&vec![descriptor_set_layout; buffering]),
+            )?;
+
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default().set_layouts(&[descriptor_set_layout]),
+                None,
+            )?;
+
+            let pipeline = context.create_compute_pipeline(ComputePipelineAttributes {
+                shader,
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            })?;
+
+            context.device.destroy_shader_module(shader, None);
+
+            Ok(Self {
+                context,
+                descriptor_set_layout,
+                descriptor_pool,
+                descriptor_sets,
+                pipeline_layout,
+                pipeline,
+            })
+        }
+    }
+
+    // Must be called after the frame's fence has been waited on, the frame's images may have
+    // been recreated since it was last recorded.
+    pub fn record(
+        &self,
+        commands: &Commands,
+        render_target_index: usize,
+        render_target: &mut Image,
+        depth_buffer: &mut Image,
+    ) {
+        let descriptor_set = self.descriptor_sets[render_target_index];
+        unsafe {
+            self.context.device.update_descriptor_sets(
+                &[
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&[vk::DescriptorImageInfo::default()
+                            .image_view(render_target.view)
+                            .image_layout(vk::ImageLayout::GENERAL)]),
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(1)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .image_info(&[vk::DescriptorImageInfo::default()
+                            .image_view(depth_buffer.view)
+                            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]),
+                ],
+                &[],
+            );
+        }
+
+        let extent = render_target.attributes.extent;
+        commands
+            .ensure_image_layout(render_target, ImageLayoutState::compute_shader_storage())
+            .ensure_image_layout(depth_buffer, ImageLayoutState::compute_shader_read())
+            .expect_sampled(depth_buffer, "nan_guard")
+            .bind_compute_pipeline(self.pipeline)
+            .bind_compute_descriptor_sets(self.pipeline_layout, &[descriptor_set])
+            .dispatch([
+                extent.width.div_ceil(WORKGROUP_SIZE),
+                extent.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            ]);
+    }
+}
+
+impl Drop for NanGuard {
+    fn drop(&mut self) {
+        unsafe {
+            self.context.device.destroy_pipeline(self.pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
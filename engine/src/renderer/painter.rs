@@ -0,0 +1,339 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::renderer::commands::Commands;
+use crate::renderer::ui_pass::UI_FORMAT;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{GraphicsPipelineAttributes, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use nalgebra as na;
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PainterVertex {
+    // In physical pixels from the top left corner of the window.
+    pub position: na::Vector2<f32>,
+    pub color: na::Vector4<f32>,
+}
+
+// Segments approximating a circle of the radius in pixels, enough to look round at any size.
+fn circle_segments(radius: f32) -> usize {
+    ((radius.max(0.0).sqrt() * 4.0) as usize).clamp(8, 64)
+}
+
+// Immediate mode screen space shapes, drawn over the UI and cleared after every rendered frame.
+// Everything is batched into a single triangle list.
+#[derive(Default)]
+pub struct Painter {
+    vertices: Vec<PainterVertex>,
+}
+
+impl Painter {
+    pub fn triangle(
+        &mut self,
+        a: na::Point2<f32>,
+        b: na::Point2<f32>,
+        c: na::Point2<f32>,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        self.vertices
+            .extend([a, b, c].map(|position| PainterVertex {
+                position: position.coords,
+                color,
+            }));
+        self
+    }
+
+    fn quad(&mut self, corners: [na::Point2<f32>; 4], color: na::Vector4<f32>) -> &mut Self {
+        self.triangle(corners[0], corners[1], corners[2], color)
+            .triangle(corners[0], corners[2], corners[3], color)
+    }
+
+    pub fn rect(
+        &mut self,
+        min: na::Point2<f32>,
+        max: na::Point2<f32>,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        self.quad(
+            [
+                min,
+                na::Point2::new(max.x, min.y),
+                max,
+                na::Point2::new(min.x, max.y),
+            ],
+            color,
+        )
+    }
+
+    // The outline is drawn inside the rectangle.
+    pub fn rect_outline(
+        &mut self,
+        min: na::Point2<f32>,
+        max: na::Point2<f32>,
+        thickness: f32,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        let thickness = thickness
+            .min((max.x - min.x) * 0.5)
+            .min((max.y - min.y) * 0.5);
+        self.rect(min, na::Point2::new(max.x, min.y + thickness), color)
+            .rect(na::Point2::new(min.x, max.y - thickness), max, color)
+            .rect(
+                na::Point2::new(min.x, min.y + thickness),
+                na::Point2::new(min.x + thickness, max.y - thickness),
+                color,
+            )
+            .rect(
+                na::Point2::new(max.x - thickness, min.y + thickness),
+                na::Point2::new(max.x, max.y - thickness),
+                color,
+            )
+    }
+
+    pub fn line(
+        &mut self,
+        start: na::Point2<f32>,
+        end: na::Point2<f32>,
+        thickness: f32,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        let direction = end - start;
+        let length = direction.norm();
+        if length == 0.0 {
+            return self;
+        }
+        let normal = na::Vector2::new(-direction.y, direction.x) / length * (thickness * 0.5);
+        self.quad(
+            [start + normal, end + normal, end - normal, start - normal],
+            color,
+        )
+    }
+
+    pub fn polyline(
+        &mut self,
+        points: &[na::Point2<f32>],
+        thickness: f32,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        for segment in points.windows(2) {
+            self.line(segment[0], segment[1], thickness, color);
+        }
+        self
+    }
+
+    pub fn circle(
+        &mut self,
+        center: na::Point2<f32>,
+        radius: f32,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        let segments = circle_segments(radius);
+        let point = |index: usize| {
+            let angle = index as f32 / segments as f32 * TAU;
+            center + na::Vector2::new(angle.cos(), angle.sin()) * radius
+        };
+        for index in 0..segments {
+            self.triangle(center, point(index), point(index + 1), color);
+        }
+        self
+    }
+
+    // The outline is centered on the radius.
+    pub fn circle_outline(
+        &mut self,
+        center: na::Point2<f32>,
+        radius: f32,
+        thickness: f32,
+        color: na::Vector4<f32>,
+    ) -> &mut Self {
+        let segments = circle_segments(radius);
+        let inner_radius = (radius - thickness * 0.5).max(0.0);
+        let outer_radius = radius + thickness * 0.5;
+        let point = |index: usize, radius: f32| {
+            let angle = index as f32 / segments as f32 * TAU;
+            center + na::Vector2::new(angle.cos(), angle.sin()) * radius
+        };
+        for index in 0..segments {
+            self.quad(
+                [
+                    point(index, inner_radius),
+                    point(index, outer_radius),
+                    point(index + 1, outer_radius),
+                    point(index + 1, inner_radius),
+                ],
+                color,
+            );
+        }
+        self
+    }
+
+    pub fn vertices(&self) -> &[PainterVertex] {
+        &self.vertices
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct PainterPushConstants {
+    vertex_buffer_address: vk::DeviceAddress,
+    screen_size: na::Vector2<f32>,
+}
+
+// Draws a Painter's shapes inside the UI pass.
+pub(crate) struct PainterRenderer {
+    context: Arc<RenderingContext>,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    // One buffer per frame in flight, grown on demand once that frame's fence has been waited on.
+    vertex_buffers: Vec<Option<Buffer>>,
+    vertex_counts: Vec<u32>,
+}
+
+impl PainterRenderer {
+    pub fn new(context: Arc<RenderingContext>, buffering: usize) -> Result<Self> {
+        let vertex_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "painter.vert.spv",
+        )?;
+        let fragment_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "painter.frag.spv",
+        )?;
+
+        unsafe {
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&[
+                    vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                        .offset(0)
+                        .size(size_of::<PainterPushConstants>() as u32),
+                ]),
+                None,
+            )?;
+
+            let pipeline = context.create_graphics_pipeline(GraphicsPipelineAttributes {
+                vertex_shader,
+                fragment_shader,
+                extent: vk::Extent2D::default(),
+                color_format: UI_FORMAT,
+                depth_format: vk::Format::UNDEFINED,
+                samples: vk::SampleCountFlags::TYPE_1,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                line_width: 1.0,
+                dynamic_line_width: false,
+                dynamic_topology: false,
+                alpha_blending: true,
+                additive_blending: false,
+                depth_write: false,
+                cull_mode: vk::CullModeFlags::NONE,
+                viewport_count: 1,
+                vertex_layout: Default::default(),
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            })?;
+
+            context.device.destroy_shader_module(vertex_shader, None);
+            context.device.destroy_shader_module(fragment_shader, None);
+
+            Ok(Self {
+                context,
+                pipeline,
+                pipeline_layout,
+                vertex_buffers: (0..buffering).map(|_| None).collect(),
+                vertex_counts: vec![0; buffering],
+            })
+        }
+    }
+
+    // Must be called after the frame's fence has been waited on.
+    pub fn upload(
+        &mut self,
+        allocator: &mut Allocator,
+        frame_index: usize,
+        painter: &Painter,
+    ) -> Result<()> {
+        let vertices = painter.vertices();
+        self.vertex_counts[frame_index] = vertices.len() as u32;
+        if vertices.is_empty() {
+            return Ok(());
+        }
+
+        let size = size_of_val(vertices) as vk::DeviceSize;
+        let vertex_buffer = &mut self.vertex_buffers[frame_index];
+        if vertex_buffer
+            .as_ref()
+            .is_some_and(|vertex_buffer| vertex_buffer.attributes.size < size)
+        {
+            vertex_buffer.take().unwrap().destroy(allocator)?;
+        }
+
+        let vertex_buffer = match vertex_buffer {
+            Some(vertex_buffer) => vertex_buffer,
+            None => vertex_buffer.insert(Buffer::new(
+                allocator,
+                BufferAttributes {
+                    name: "painter_vertex_buffer".into(),
+                    context: self.context.clone(),
+                    size: size.next_power_of_two(),
+                    usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                        | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                    location: MemoryLocation::CpuToGpu,
+                    allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                    allocation_priority: 1.0,
+                },
+            )?),
+        };
+        vertex_buffer.write(vertices, 0)
+    }
+
+    pub fn has_shapes(&self, frame_index: usize) -> bool {
+        self.vertex_counts[frame_index] > 0
+    }
+
+    pub fn draw(&self, commands: &Commands, frame_index: usize, extent: vk::Extent2D) {
+        let vertex_count = self.vertex_counts[frame_index];
+        let Some(vertex_buffer) = &self.vertex_buffers[frame_index] else {
+            return;
+        };
+        if vertex_count == 0 {
+            return;
+        }
+
+        commands
+            .bind_pipeline(self.pipeline)
+            .set_push_constants(
+                self.pipeline_layout,
+                PainterPushConstants {
+                    vertex_buffer_address: vertex_buffer.address,
+                    screen_size: na::Vector2::new(extent.width as f32, extent.height as f32),
+                },
+            )
+            .draw(0..vertex_count, 0..1);
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for vertex_buffer in self.vertex_buffers.iter_mut().flatten() {
+            vertex_buffer.destroy(allocator)?;
+        }
+
+        unsafe {
+            self.context.device.destroy_pipeline(self.pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        Ok(())
+    }
+}
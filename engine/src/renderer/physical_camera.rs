@@ -0,0 +1,52 @@
+use crate::renderer::lighting::ev100_to_exposure;
+
+// Real-world camera settings; the vertical field of view follows from the sensor height and
+// focal length, and aperture, shutter speed and ISO give the exposure.
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalCamera {
+    pub focal_length_mm: f32,
+    pub sensor_width_mm: f32,
+    pub sensor_height_mm: f32,
+    // f-number, e.g. 16 for f/16.
+    pub aperture: f32,
+    pub shutter_speed_seconds: f32,
+    pub iso: f32,
+}
+
+impl Default for PhysicalCamera {
+    // A 50mm lens on a full frame sensor exposed for a sunny day (sunny 16 rule).
+    fn default() -> Self {
+        Self {
+            focal_length_mm: 50.0,
+            sensor_width_mm: 36.0,
+            sensor_height_mm: 24.0,
+            aperture: 16.0,
+            shutter_speed_seconds: 1.0 / 125.0,
+            iso: 100.0,
+        }
+    }
+}
+
+impl PhysicalCamera {
+    pub fn fovy(&self) -> f32 {
+        2.0 * (self.sensor_height_mm / (2.0 * self.focal_length_mm)).atan()
+    }
+
+    pub fn fovx(&self) -> f32 {
+        2.0 * (self.sensor_width_mm / (2.0 * self.focal_length_mm)).atan()
+    }
+
+    pub fn set_fovy(&mut self, fovy: f32) {
+        self.focal_length_mm = self.sensor_height_mm / (2.0 * (fovy * 0.5).tan());
+    }
+
+    // Exposure value at ISO 100.
+    pub fn ev100(&self) -> f32 {
+        (self.aperture * self.aperture / self.shutter_speed_seconds * 100.0 / self.iso).log2()
+    }
+
+    // Scale from scene luminance in cd/m² to the [0, 1] sensor range.
+    pub fn exposure(&self) -> f32 {
+        ev100_to_exposure(self.ev100())
+    }
+}
@@ -0,0 +1,952 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::image::{Image, ImageAttributes, ImageLayoutState};
+use crate::renderer::commands::Commands;
+use crate::renderer::{load_shader_module, GPUCamera, SHADERS_DIR};
+use crate::rendering_context::{GraphicsPipelineAttributes, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use nalgebra as na;
+use std::sync::Arc;
+
+pub const MAX_SHADOWED_POINT_LIGHTS: usize = 4;
+pub const MAX_SHADOWED_SPOT_LIGHTS: usize = 8;
+
+const SHADOW_MAP_RESOLUTION: u32 = 512;
+const SHADOW_NEAR_PLANE: f32 = 0.05;
+const INVALID_SHADOW_INDEX: u32 = u32::MAX;
+const INITIAL_LIGHT_CAPACITY: usize = 16;
+// The light count, padding and the address of the light clusters, see lights.glsl.
+const LIGHT_BUFFER_HEADER_SIZE: usize = 16;
+
+pub(crate) const SHADOW_MAP_FORMATS: [vk::Format; 2] =
+    [vk::Format::D32_SFLOAT, vk::Format::D16_UNORM];
+
+// Cube face directions and up vectors in the order of the cube map layers.
+pub(crate) const CUBE_FACES: [([f64; 3], [f64; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+    pub position: na::Point3<f64>,
+    pub color: na::Vector3<f32>,
+    // Luminous intensity in candela, see lighting::lumens_to_candela.
+    pub intensity: f32,
+    // The light fades out towards this distance and has no effect beyond it.
+    pub range: f32,
+    // Only the first MAX_SHADOWED_POINT_LIGHTS shadow casting lights get a shadow map.
+    pub casts_shadows: bool,
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        Self {
+            position: na::Point3::origin(),
+            color: na::Vector3::repeat(1.0),
+            // Matches the default Lighting, scaled for an exposure of 1.
+            intensity: std::f32::consts::PI,
+            range: 10.0,
+            casts_shadows: false,
+        }
+    }
+}
+
+// A point light limited to a cone, with its shadow rendered into a single face.
+#[derive(Clone, Copy, Debug)]
+pub struct SpotLight {
+    pub position: na::Point3<f64>,
+    // Direction the light shines in.
+    pub direction: na::Vector3<f32>,
+    pub color: na::Vector3<f32>,
+    // Luminous intensity in candela inside the cone.
+    pub intensity: f32,
+    pub range: f32,
+    // Angles from the direction in radians, the light fades out between them.
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    // Only the first MAX_SHADOWED_SPOT_LIGHTS shadow casting lights get a shadow map.
+    pub casts_shadows: bool,
+}
+
+impl Default for SpotLight {
+    fn default() -> Self {
+        Self {
+            position: na::Point3::origin(),
+            direction: -na::Vector3::y(),
+            color: na::Vector3::repeat(1.0),
+            intensity: std::f32::consts::PI,
+            range: 10.0,
+            inner_angle: std::f32::consts::FRAC_PI_8,
+            outer_angle: std::f32::consts::FRAC_PI_4,
+            casts_shadows: false,
+        }
+    }
+}
+
+// Point and spot lights share the light buffer, point lights have a cone covering everything.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GPUPointLight {
+    // Relative to the camera like the instances.
+    position: na::Vector3<f32>,
+    range: f32,
+    color: na::Vector3<f32>,
+    shadow_index: u32,
+    direction: na::Vector3<f32>,
+    cos_outer_angle: f32,
+    cos_inner_angle: f32,
+    spot_shadow_index: u32,
+    padding: [u32; 2],
+    // From positions relative to the camera to the spot shadow map's clip space.
+    spot_shadow_matrix: na::Matrix4<f32>,
+}
+
+// Below the cosine of any angle, for point lights.
+const NO_CONE: f32 = -2.0;
+
+// Projection with Vulkan's [0, 1] depth range, the fragment shader reconstructs the same depth
+// from the largest axis of the light to fragment vector.
+pub(crate) fn cube_face_projection(far: f32) -> na::Matrix4<f32> {
+    let near = SHADOW_NEAR_PLANE;
+    #[rustfmt::skip]
+    let projection = na::Matrix4::new(
+        1.0, 0.0, 0.0, 0.0,
+        0.0, 1.0, 0.0, 0.0,
+        0.0, 0.0, far / (near - far), far * near / (near - far),
+        0.0, 0.0, -1.0, 0.0,
+    );
+    projection
+}
+
+// Projection of a spot light's cone with Vulkan's [0, 1] depth range.
+fn spot_projection(outer_angle: f32, far: f32) -> na::Matrix4<f32> {
+    let near = SHADOW_NEAR_PLANE;
+    let focal_length = 1.0 / outer_angle.clamp(0.01, 1.55).tan();
+    #[rustfmt::skip]
+    let projection = na::Matrix4::new(
+        focal_length, 0.0, 0.0, 0.0,
+        0.0, focal_length, 0.0, 0.0,
+        0.0, 0.0, far / (near - far), far * near / (near - far),
+        0.0, 0.0, -1.0, 0.0,
+    );
+    projection
+}
+
+fn begin_face<'a>(
+    commands: &'a Commands,
+    shadow_map: &mut ShadowMapLayers,
+    face: usize,
+    clear: bool,
+) -> &'a Commands {
+    let extent = vk::Extent2D {
+        width: SHADOW_MAP_RESOLUTION,
+        height: SHADOW_MAP_RESOLUTION,
+    };
+    commands
+        .begin_depth_rendering(
+            &mut shadow_map.image,
+            shadow_map.face_views[face],
+            face as u32,
+            vk::Rect2D::default().extent(extent),
+            clear,
+        )
+        .set_viewport(
+            vk::Viewport::default()
+                .width(extent.width as f32)
+                .height(extent.height as f32)
+                .max_depth(1.0),
+        )
+        .set_scissor(vk::Rect2D::default().extent(extent))
+}
+
+// Begins rendering all faces of a slot at once, for the viewport array pipeline.
+fn begin_slot<'a>(
+    commands: &'a Commands,
+    shadow_map: &mut ShadowMapLayers,
+    slot: usize,
+    clear: bool,
+) -> &'a Commands {
+    let extent = vk::Extent2D {
+        width: SHADOW_MAP_RESOLUTION,
+        height: SHADOW_MAP_RESOLUTION,
+    };
+    let faces = shadow_map.faces_per_slot;
+    commands
+        .begin_layered_depth_rendering(
+            &mut shadow_map.image,
+            shadow_map.slot_views[slot],
+            (slot * faces) as u32,
+            faces as u32,
+            vk::Rect2D::default().extent(extent),
+            clear,
+        )
+        .set_viewports(&vec![
+            vk::Viewport::default()
+                .width(extent.width as f32)
+                .height(extent.height as f32)
+                .max_depth(1.0);
+            faces
+        ])
+        .set_scissors(&vec![vk::Rect2D::default().extent(extent); faces])
+}
+
+fn create_light_buffer(
+    context: Arc<RenderingContext>,
+    allocator: &mut Allocator,
+    capacity: usize,
+) -> Result<Buffer> {
+    Buffer::new(
+        allocator,
+        BufferAttributes {
+            name: "point_light_buffer".into(),
+            context,
+            size: (LIGHT_BUFFER_HEADER_SIZE + capacity * size_of::<GPUPointLight>())
+                as vk::DeviceSize,
+            usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            location: MemoryLocation::CpuToGpu,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            allocation_priority: 1.0,
+        },
+    )
+}
+
+unsafe fn create_layer_view(
+    context: &RenderingContext,
+    image: vk::Image,
+    format: vk::Format,
+    view_type: vk::ImageViewType,
+    base_array_layer: u32,
+    layer_count: u32,
+) -> Result<vk::ImageView, vk::Result> {
+    context.device.create_image_view(
+        &vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(view_type)
+            .format(format)
+            .subresource_range(
+                vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                    .level_count(1)
+                    .base_array_layer(base_array_layer)
+                    .layer_count(layer_count),
+            ),
+        None,
+    )
+}
+
+// Depth image with a layer per face of the shadowed lights, each face rendered through a view of
+// its own, or all faces of a light at once through the slot's view.
+struct ShadowMapLayers {
+    image: Image,
+    allocation: Option<Allocation>,
+    faces_per_slot: usize,
+    face_views: Vec<vk::ImageView>,
+    slot_views: Vec<vk::ImageView>,
+}
+
+impl ShadowMapLayers {
+    unsafe fn new(
+        context: &Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        name: &str,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        slot_count: usize,
+        faces_per_slot: usize,
+    ) -> Result<Self> {
+        let layer_count = (slot_count * faces_per_slot) as u32;
+        let cube = faces_per_slot == 6;
+        let extent = vk::Extent3D {
+            width: SHADOW_MAP_RESOLUTION,
+            height: SHADOW_MAP_RESOLUTION,
+            depth: 1,
+        };
+        let usage = usage | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+
+        let handle = context.device.create_image(
+            &vk::ImageCreateInfo::default()
+                .flags(if cube {
+                    vk::ImageCreateFlags::CUBE_COMPATIBLE
+                } else {
+                    vk::ImageCreateFlags::empty()
+                })
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(format)
+                .extent(extent)
+                .mip_levels(1)
+                .array_layers(layer_count)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED),
+            None,
+        )?;
+
+        let allocation = allocator.allocate(&AllocationCreateDesc {
+            name,
+            requirements: context.device.get_image_memory_requirements(handle),
+            location: MemoryLocation::GpuOnly,
+            linear: false,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        })?;
+        context
+            .device
+            .bind_image_memory(handle, allocation.memory(), allocation.offset())?;
+
+        // Faces are transitioned one by one while rendering and all together for sampling.
+        let image = Image::wrap(
+            context.clone(),
+            handle,
+            ImageAttributes {
+                location: MemoryLocation::GpuOnly,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                allocation_priority: 1.0,
+                linear: false,
+                extent,
+                format,
+                usage,
+                subresource_range: vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                    .level_count(1)
+                    .layer_count(layer_count),
+                samples: vk::SampleCountFlags::TYPE_1,
+                cube: false,
+            },
+        )?;
+
+        let face_views = (0..layer_count)
+            .map(|layer| {
+                create_layer_view(
+                    context,
+                    handle,
+                    format,
+                    vk::ImageViewType::TYPE_2D,
+                    layer,
+                    1,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let slot_views = (0..slot_count as u32)
+            .map(|slot| {
+                create_layer_view(
+                    context,
+                    handle,
+                    format,
+                    vk::ImageViewType::TYPE_2D_ARRAY,
+                    slot * faces_per_slot as u32,
+                    faces_per_slot as u32,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            image,
+            allocation: Some(allocation),
+            faces_per_slot,
+            face_views,
+            slot_views,
+        })
+    }
+
+    unsafe fn destroy(
+        &mut self,
+        context: &RenderingContext,
+        allocator: &mut Allocator,
+    ) -> Result<()> {
+        for view in self.face_views.drain(..).chain(self.slot_views.drain(..)) {
+            context.device.destroy_image_view(view, None);
+        }
+        self.image.destroy(allocator)?;
+        context.device.destroy_image(self.image.handle, None);
+        if let Some(allocation) = self.allocation.take() {
+            allocator.free(allocation)?;
+        }
+        Ok(())
+    }
+}
+
+// What a shadow map slot was rendered for, the static shadows stay valid while it's unchanged.
+#[derive(Clone, Copy, PartialEq)]
+struct ShadowKey {
+    light: usize,
+    position: na::Point3<f64>,
+    range: f32,
+    // Zero for point lights.
+    direction: na::Vector3<f32>,
+    outer_angle: f32,
+}
+
+// Shadow maps of one kind of light, with faces_per_slot faces per shadow casting light.
+//
+// Static casters are rendered into a cache that's only redrawn for lights that moved or after
+// invalidate, and copied into the sampled shadow map whenever dynamic casters have to be drawn
+// on top of it.
+struct ShadowSlots {
+    faces_per_slot: usize,
+    // Index of the first face's camera in the shadow camera buffers.
+    first_camera: usize,
+    // Slots rendered in each frame in flight.
+    shadow_keys: Vec<Vec<ShadowKey>>,
+    shadow_map: ShadowMapLayers,
+    static_shadow_map: ShadowMapLayers,
+    // Per slot, what the cached static shadows were rendered for.
+    static_keys: Vec<Option<ShadowKey>>,
+    // Whether the shadow map matches the cache, no dynamic casters were drawn since the copy.
+    shadow_map_is_static: bool,
+}
+
+impl ShadowSlots {
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn new(
+        context: &Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        name: &str,
+        format: vk::Format,
+        slot_count: usize,
+        faces_per_slot: usize,
+        first_camera: usize,
+        buffering: usize,
+    ) -> Result<Self> {
+        let shadow_map = ShadowMapLayers::new(
+            context,
+            allocator,
+            &format!("{name}_shadow_map"),
+            format,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            slot_count,
+            faces_per_slot,
+        )?;
+        let static_shadow_map = ShadowMapLayers::new(
+            context,
+            allocator,
+            &format!("{name}_static_shadow_map"),
+            format,
+            vk::ImageUsageFlags::TRANSFER_SRC,
+            slot_count,
+            faces_per_slot,
+        )?;
+        Ok(Self {
+            faces_per_slot,
+            first_camera,
+            shadow_keys: vec![Vec::new(); buffering],
+            shadow_map,
+            static_shadow_map,
+            static_keys: vec![None; slot_count],
+            shadow_map_is_static: false,
+        })
+    }
+
+    // With a layered pipeline, the faces of each slot are drawn in a single pass.
+    #[allow(clippy::too_many_arguments)]
+    fn draw(
+        &mut self,
+        commands: &Commands,
+        frame_index: usize,
+        camera_buffer_address: vk::DeviceAddress,
+        pipeline: vk::Pipeline,
+        layered_pipeline: Option<vk::Pipeline>,
+        has_dynamic_casters: bool,
+        draw_instances: &impl Fn(&Commands, vk::DeviceAddress, bool, u32),
+    ) {
+        let first_camera = self.first_camera;
+        let face_camera_address = |face: usize| {
+            camera_buffer_address
+                + ((first_camera + face) * size_of::<GPUCamera>()) as vk::DeviceAddress
+        };
+        let faces_per_slot = self.faces_per_slot;
+        let draw_slot = |shadow_map: &mut ShadowMapLayers, slot: usize, clear: bool, dynamic| {
+            let first_face = slot * faces_per_slot;
+            match layered_pipeline {
+                Some(layered_pipeline) if faces_per_slot > 1 => {
+                    begin_slot(commands, shadow_map, slot, clear).bind_pipeline(layered_pipeline);
+                    draw_instances(
+                        commands,
+                        face_camera_address(first_face),
+                        dynamic,
+                        faces_per_slot as u32,
+                    );
+                    commands.end_rendering();
+                }
+                _ => {
+                    for face in first_face..first_face + faces_per_slot {
+                        begin_face(commands, shadow_map, face, clear).bind_pipeline(pipeline);
+                        draw_instances(commands, face_camera_address(face), dynamic, 1);
+                        commands.end_rendering();
+                    }
+                }
+            }
+        };
+        let shadow_keys = &self.shadow_keys[frame_index];
+
+        let mut static_changed = false;
+        for (slot, key) in shadow_keys.iter().enumerate() {
+            if self.static_keys[slot] == Some(*key) {
+                continue;
+            }
+            draw_slot(&mut self.static_shadow_map, slot, true, false);
+            self.static_keys[slot] = Some(*key);
+            static_changed = true;
+        }
+
+        if static_changed || has_dynamic_casters || !self.shadow_map_is_static {
+            commands.copy_image(
+                &mut self.static_shadow_map.image,
+                &mut self.shadow_map.image,
+            );
+            self.shadow_map_is_static = true;
+        }
+        if has_dynamic_casters {
+            for slot in 0..shadow_keys.len() {
+                draw_slot(&mut self.shadow_map, slot, false, true);
+            }
+            self.shadow_map_is_static = false;
+        }
+
+        commands.ensure_image_layout(&mut self.shadow_map.image, ImageLayoutState::shader_read());
+    }
+
+    unsafe fn destroy(
+        &mut self,
+        context: &RenderingContext,
+        allocator: &mut Allocator,
+    ) -> Result<()> {
+        self.shadow_map.destroy(context, allocator)?;
+        self.static_shadow_map.destroy(context, allocator)
+    }
+}
+
+// Point and spot lights, with shadows rendered into the faces of cube maps for point lights and
+// into single layers for spot lights, one per shadow casting light. All cubes share one depth
+// image with six layers each, all spot lights another.
+pub struct PointLights {
+    context: Arc<RenderingContext>,
+    lights: Vec<PointLight>,
+    spot_lights: Vec<SpotLight>,
+    // Per frame in flight, grown on demand once that frame's fence has been waited on.
+    light_buffers: Vec<Buffer>,
+    // Cameras of the point light faces followed by those of the spot lights.
+    shadow_camera_buffers: Vec<Buffer>,
+    point_shadows: ShadowSlots,
+    spot_shadows: ShadowSlots,
+    cube_views: Vec<vk::ImageView>,
+    spot_array_view: vk::ImageView,
+    pub shadow_sampler: vk::Sampler,
+    shadow_pipeline: vk::Pipeline,
+    // Draws the six faces of a cube in one pass, where viewport arrays are supported.
+    cube_shadow_pipeline: Option<vk::Pipeline>,
+}
+
+impl PointLights {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        pipeline_layout: vk::PipelineLayout,
+        buffering: usize,
+    ) -> Result<Self> {
+        let format = context
+            .best_supported(
+                SHADOW_MAP_FORMATS,
+                vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+                    | vk::FormatFeatureFlags::SAMPLED_IMAGE
+                    | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR
+                    | vk::FormatFeatureFlags::TRANSFER_SRC
+                    | vk::FormatFeatureFlags::TRANSFER_DST,
+                vk::ImageTiling::OPTIMAL,
+            )
+            .ok_or_else(|| anyhow::anyhow!("No filterable depth format for shadow maps"))?;
+
+        let point_camera_count = MAX_SHADOWED_POINT_LIGHTS * 6;
+        let camera_count = point_camera_count + MAX_SHADOWED_SPOT_LIGHTS;
+
+        unsafe {
+            let point_shadows = ShadowSlots::new(
+                &context,
+                allocator,
+                "point",
+                format,
+                MAX_SHADOWED_POINT_LIGHTS,
+                6,
+                0,
+                buffering,
+            )?;
+            let spot_shadows = ShadowSlots::new(
+                &context,
+                allocator,
+                "spot",
+                format,
+                MAX_SHADOWED_SPOT_LIGHTS,
+                1,
+                point_camera_count,
+                buffering,
+            )?;
+            let cube_views = (0..MAX_SHADOWED_POINT_LIGHTS as u32)
+                .map(|slot| {
+                    create_layer_view(
+                        &context,
+                        point_shadows.shadow_map.image.handle,
+                        format,
+                        vk::ImageViewType::CUBE,
+                        slot * 6,
+                        6,
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let spot_array_view = create_layer_view(
+                &context,
+                spot_shadows.shadow_map.image.handle,
+                format,
+                vk::ImageViewType::TYPE_2D_ARRAY,
+                0,
+                MAX_SHADOWED_SPOT_LIGHTS as u32,
+            )?;
+
+            let shadow_sampler = context.device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .compare_enable(true)
+                    .compare_op(vk::CompareOp::LESS_OR_EQUAL),
+                None,
+            )?;
+
+            let vertex_shader =
+                load_shader_module(context.as_ref(), SHADERS_DIR.to_owned() + "shadow.vert.spv")?;
+            let fragment_shader =
+                load_shader_module(context.as_ref(), SHADERS_DIR.to_owned() + "shadow.frag.spv")?;
+            let shadow_pipeline_attributes =
+                |vertex_shader, viewport_count| GraphicsPipelineAttributes {
+                    vertex_shader,
+                    fragment_shader,
+                    extent: vk::Extent2D {
+                        width: SHADOW_MAP_RESOLUTION,
+                        height: SHADOW_MAP_RESOLUTION,
+                    },
+                    color_format: vk::Format::UNDEFINED,
+                    depth_format: format,
+                    samples: vk::SampleCountFlags::TYPE_1,
+                    topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                    line_width: 1.0,
+                    dynamic_line_width: false,
+                    dynamic_topology: false,
+                    alpha_blending: false,
+                    additive_blending: false,
+                    depth_write: true,
+                    cull_mode: vk::CullModeFlags::NONE,
+                    viewport_count,
+                    vertex_layout: Default::default(),
+                    pipeline_layout,
+                    pipeline_cache: Default::default(),
+                };
+            let shadow_pipeline =
+                context.create_graphics_pipeline(shadow_pipeline_attributes(vertex_shader, 1))?;
+            let cube_shadow_pipeline = if context.supports_viewport_arrays() {
+                let cube_vertex_shader = load_shader_module(
+                    context.as_ref(),
+                    SHADERS_DIR.to_owned() + "shadow_cube.vert.spv",
+                )?;
+                let pipeline = context
+                    .create_graphics_pipeline(shadow_pipeline_attributes(cube_vertex_shader, 6));
+                context
+                    .device
+                    .destroy_shader_module(cube_vertex_shader, None);
+                Some(pipeline?)
+            } else {
+                None
+            };
+            context.device.destroy_shader_module(vertex_shader, None);
+            context.device.destroy_shader_module(fragment_shader, None);
+
+            let light_buffers = (0..buffering)
+                .map(|_| create_light_buffer(context.clone(), allocator, INITIAL_LIGHT_CAPACITY))
+                .collect::<Result<Vec<_>>>()?;
+            let shadow_camera_buffers = (0..buffering)
+                .map(|_| {
+                    Buffer::new(
+                        allocator,
+                        BufferAttributes {
+                            name: "point_shadow_camera_buffer".into(),
+                            context: context.clone(),
+                            size: (camera_count * size_of::<GPUCamera>()) as vk::DeviceSize,
+                            usage: vk::BufferUsageFlags::UNIFORM_BUFFER
+                                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                            location: MemoryLocation::CpuToGpu,
+                            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                            allocation_priority: 1.0,
+                        },
+                    )
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            Ok(Self {
+                context,
+                lights: Vec::new(),
+                spot_lights: Vec::new(),
+                light_buffers,
+                shadow_camera_buffers,
+                point_shadows,
+                spot_shadows,
+                cube_views,
+                spot_array_view,
+                shadow_sampler,
+                shadow_pipeline,
+                cube_shadow_pipeline,
+            })
+        }
+    }
+
+    pub fn lights(&self) -> &[PointLight] {
+        &self.lights
+    }
+
+    pub fn lights_mut(&mut self) -> &mut Vec<PointLight> {
+        &mut self.lights
+    }
+
+    pub fn spot_lights(&self) -> &[SpotLight] {
+        &self.spot_lights
+    }
+
+    pub fn spot_lights_mut(&mut self) -> &mut Vec<SpotLight> {
+        &mut self.spot_lights
+    }
+
+    pub fn cube_views(&self) -> &[vk::ImageView] {
+        &self.cube_views
+    }
+
+    // Every spot light shadow map as the layers of one array.
+    pub fn spot_array_view(&self) -> vk::ImageView {
+        self.spot_array_view
+    }
+
+    pub fn shadow_map(&self) -> &Image {
+        &self.point_shadows.shadow_map.image
+    }
+
+    pub fn spot_shadow_map(&self) -> &Image {
+        &self.spot_shadows.shadow_map.image
+    }
+
+    // Redraws the cached static shadows of every light in the next frame, e.g. after static
+    // casters were added, removed or changed.
+    pub fn invalidate_static_shadows(&mut self) {
+        self.point_shadows.static_keys.fill(None);
+        self.spot_shadows.static_keys.fill(None);
+    }
+
+    pub fn address(&self, frame_index: usize) -> vk::DeviceAddress {
+        self.light_buffers[frame_index].address
+    }
+
+    // Must be called after the frame's fence has been waited on.
+    pub fn upload(
+        &mut self,
+        allocator: &mut Allocator,
+        frame_index: usize,
+        camera_position: &na::Point3<f64>,
+        // The frame's light clusters, see LightClusters.
+        cluster_buffer_address: vk::DeviceAddress,
+    ) -> Result<()> {
+        let light_count = self.lights.len() + self.spot_lights.len();
+        let capacity = (self.light_buffers[frame_index].attributes.size as usize
+            - LIGHT_BUFFER_HEADER_SIZE)
+            / size_of::<GPUPointLight>();
+        if light_count > capacity {
+            self.light_buffers[frame_index].destroy(allocator)?;
+            self.light_buffers[frame_index] = create_light_buffer(
+                self.context.clone(),
+                allocator,
+                light_count.next_power_of_two(),
+            )?;
+        }
+
+        let mut gpu_lights = Vec::with_capacity(light_count);
+        let mut shadow_cameras = Vec::new();
+        let shadow_keys = &mut self.point_shadows.shadow_keys[frame_index];
+        shadow_keys.clear();
+        for (index, light) in self.lights.iter().enumerate() {
+            let position = light.position - camera_position.coords;
+            let shadow_index =
+                if light.casts_shadows && shadow_keys.len() < MAX_SHADOWED_POINT_LIGHTS {
+                    let shadow_index = shadow_keys.len() as u32;
+                    shadow_keys.push(ShadowKey {
+                        light: index,
+                        position: light.position,
+                        range: light.range,
+                        direction: na::Vector3::zeros(),
+                        outer_angle: 0.0,
+                    });
+                    let projection = cube_face_projection(light.range);
+                    shadow_cameras.extend(CUBE_FACES.iter().map(|(direction, up)| {
+                        let target = position + na::Vector3::from(*direction);
+                        let view = na::Isometry3::look_at_rh(&position, &target, &(*up).into());
+                        GPUCamera {
+                            view: view.to_homogeneous().cast(),
+                            projection,
+                            position: position.coords.cast(),
+                            origin: light.position.coords.cast(),
+                            exposure: 1.0,
+                            // Shadow passes only write depth, lighting isn't needed.
+                            ..bytemuck::Zeroable::zeroed()
+                        }
+                    }));
+                    shadow_index
+                } else {
+                    INVALID_SHADOW_INDEX
+                };
+
+            gpu_lights.push(GPUPointLight {
+                position: position.coords.cast(),
+                range: light.range,
+                color: light.color * light.intensity,
+                shadow_index,
+                direction: na::Vector3::zeros(),
+                cos_outer_angle: NO_CONE,
+                cos_inner_angle: NO_CONE,
+                spot_shadow_index: INVALID_SHADOW_INDEX,
+                padding: [0; 2],
+                spot_shadow_matrix: na::Matrix4::identity(),
+            });
+        }
+        self.shadow_camera_buffers[frame_index].write(&shadow_cameras, 0)?;
+
+        let mut spot_cameras = Vec::new();
+        let shadow_keys = &mut self.spot_shadows.shadow_keys[frame_index];
+        shadow_keys.clear();
+        for (index, light) in self.spot_lights.iter().enumerate() {
+            let position = light.position - camera_position.coords;
+            let direction = light
+                .direction
+                .try_normalize(f32::EPSILON)
+                .unwrap_or(-na::Vector3::y());
+            let mut spot_shadow_index = INVALID_SHADOW_INDEX;
+            let mut spot_shadow_matrix = na::Matrix4::identity();
+            if light.casts_shadows && shadow_keys.len() < MAX_SHADOWED_SPOT_LIGHTS {
+                spot_shadow_index = shadow_keys.len() as u32;
+                shadow_keys.push(ShadowKey {
+                    light: index,
+                    position: light.position,
+                    range: light.range,
+                    direction,
+                    outer_angle: light.outer_angle,
+                });
+                let up = if direction.y.abs() > 0.99 {
+                    na::Vector3::z()
+                } else {
+                    na::Vector3::y()
+                };
+                let target = position + direction.cast::<f64>();
+                let view = na::Isometry3::look_at_rh(&position, &target, &up);
+                let projection = spot_projection(light.outer_angle, light.range);
+                spot_cameras.push(GPUCamera {
+                    view: view.to_homogeneous().cast(),
+                    projection,
+                    position: position.coords.cast(),
+                    origin: light.position.coords.cast(),
+                    exposure: 1.0,
+                    ..bytemuck::Zeroable::zeroed()
+                });
+                spot_shadow_matrix = projection * view.to_homogeneous().cast();
+            }
+
+            gpu_lights.push(GPUPointLight {
+                position: position.coords.cast(),
+                range: light.range,
+                color: light.color * light.intensity,
+                shadow_index: INVALID_SHADOW_INDEX,
+                direction,
+                cos_outer_angle: light.outer_angle.cos(),
+                cos_inner_angle: light.inner_angle.min(light.outer_angle).cos(),
+                spot_shadow_index,
+                padding: [0; 2],
+                spot_shadow_matrix,
+            });
+        }
+        self.shadow_camera_buffers[frame_index].write(
+            &spot_cameras,
+            (self.spot_shadows.first_camera * size_of::<GPUCamera>()) as vk::DeviceSize,
+        )?;
+
+        let light_buffer = &mut self.light_buffers[frame_index];
+        light_buffer.write(&[gpu_lights.len() as u32, 0], 0)?;
+        light_buffer.write(&[cluster_buffer_address], 8)?;
+        light_buffer.write(&gpu_lights, LIGHT_BUFFER_HEADER_SIZE as vk::DeviceSize)?;
+        Ok(())
+    }
+
+    // Brings the shadow map of every shadow casting light up to date, leaving the shadow maps
+    // ready to be sampled by the scene pass. Static casters are only drawn into the cache for
+    // slots whose light changed, dynamic ones into the shadow maps every frame.
+    pub fn draw_shadows(
+        &mut self,
+        commands: &Commands,
+        frame_index: usize,
+        has_dynamic_casters: bool,
+        // Called per pass with the address of its first face's camera, whether the dynamic or
+        // the static casters are drawn, and the number of faces every instance has to be drawn
+        // into; instance ranges are then multiplied by it, see shadow_cube.vert.
+        draw_instances: impl Fn(&Commands, vk::DeviceAddress, bool, u32),
+    ) {
+        let camera_buffer_address = self.shadow_camera_buffers[frame_index].address;
+        self.point_shadows.draw(
+            commands,
+            frame_index,
+            camera_buffer_address,
+            self.shadow_pipeline,
+            self.cube_shadow_pipeline,
+            has_dynamic_casters,
+            &draw_instances,
+        );
+        self.spot_shadows.draw(
+            commands,
+            frame_index,
+            camera_buffer_address,
+            self.shadow_pipeline,
+            None,
+            has_dynamic_casters,
+            &draw_instances,
+        );
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for buffer in self
+            .light_buffers
+            .iter_mut()
+            .chain(self.shadow_camera_buffers.iter_mut())
+        {
+            buffer.destroy(allocator)?;
+        }
+        unsafe {
+            for view in self.cube_views.drain(..) {
+                self.context.device.destroy_image_view(view, None);
+            }
+            self.context
+                .device
+                .destroy_image_view(self.spot_array_view, None);
+            self.point_shadows.destroy(&self.context, allocator)?;
+            self.spot_shadows.destroy(&self.context, allocator)?;
+            self.context
+                .device
+                .destroy_sampler(self.shadow_sampler, None);
+            self.context
+                .device
+                .destroy_pipeline(self.shadow_pipeline, None);
+            if let Some(pipeline) = self.cube_shadow_pipeline {
+                self.context.device.destroy_pipeline(pipeline, None);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,517 @@
+use crate::image::{Image, ImageAttributes};
+use crate::renderer::render_graph::{GraphAccess, GraphImage, RenderGraph};
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{GraphicsPipelineAttributes, ImageLayoutState, RenderingContext};
+use crate::time::Time;
+use anyhow::{ensure, Result};
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Guaranteed by every device, the pipeline layout reserves all of it for the passes.
+pub const MAX_POST_PROCESS_PUSH_CONSTANTS_SIZE: usize = 128;
+
+// A fullscreen pass of the PostChain. Its fragment shader samples the previous image of the
+// chain from `layout (set = 0, binding = 0) uniform sampler2D` at the UVs of `location = 0`,
+// writes the next one to `location = 0` and may declare push constants from offset 0.
+pub trait PostProcess {
+    // Names the pass in the frame graph.
+    fn name(&self) -> &str;
+
+    // SPIR-V code, read once when the pass is added to the chain.
+    fn fragment_shader(&self) -> Result<Vec<u8>>;
+
+    // Pushed before the pass is drawn, e.g. with bytemuck::bytes_of. At most
+    // MAX_POST_PROCESS_PUSH_CONSTANTS_SIZE bytes in multiples of 4.
+    fn push_constants(&mut self, _time: &Time, _extent: vk::Extent2D) -> Vec<u8> {
+        Vec::new()
+    }
+
+    // Disabled passes are skipped, the next one reads what the pass would have.
+    fn is_enabled(&self) -> bool {
+        true
+    }
+}
+
+// A fragment shader compiled to a SPIR-V file with fixed push constants.
+#[derive(Clone, Debug)]
+pub struct ShaderPostProcess {
+    pub name: String,
+    pub path: PathBuf,
+    pub push_constants: Vec<u8>,
+    pub enabled: bool,
+}
+
+impl ShaderPostProcess {
+    pub fn new(name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            path: path.into(),
+            push_constants: Vec::new(),
+            enabled: true,
+        }
+    }
+}
+
+impl PostProcess for ShaderPostProcess {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fragment_shader(&self) -> Result<Vec<u8>> {
+        Ok(std::fs::read(&self.path)?)
+    }
+
+    fn push_constants(&mut self, _time: &Time, _extent: vk::Extent2D) -> Vec<u8> {
+        self.push_constants.clone()
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+struct ChainedPass {
+    process: Box<dyn PostProcess>,
+    pipeline: vk::Pipeline,
+    // Of the frame being recorded, None while the pass is disabled.
+    push_constants: Option<Vec<u8>>,
+}
+
+// Fullscreen passes run in order over the resolved scene before it's tonemapped onto the
+// swapchain. Each pass reads the previous image and writes the other one of two images at the
+// render target's size and format, so the chain never writes the image it samples.
+pub struct PostChain {
+    context: Arc<RenderingContext>,
+    format: vk::Format,
+    // Ping-ponged, created once a frame has enabled passes and recreated with the render target.
+    images: Vec<Image>,
+    // Counts recreations of the images, so each frame in flight repoints its own descriptors.
+    images_generation: u64,
+    descriptor_generations: Vec<Option<u64>>,
+    // Destroyed once every frame in flight that may still use them has been waited on, with the
+    // number of frames left until then.
+    retired_images: Vec<(Image, usize)>,
+    retired_pipelines: Vec<(vk::Pipeline, usize)>,
+    in_flight_frames_count: usize,
+    sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    // Per frame in flight, sampling the render target and each of the images.
+    descriptor_sets: Vec<[vk::DescriptorSet; 3]>,
+    pipeline_layout: vk::PipelineLayout,
+    // Kept for the pipelines of passes added later.
+    vertex_shader: vk::ShaderModule,
+    passes: Vec<ChainedPass>,
+}
+
+impl PostChain {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        format: vk::Format,
+        in_flight_frames_count: usize,
+    ) -> Result<Self> {
+        let vertex_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "fullscreen.vert.spv",
+        )?;
+        let set_count = 3 * in_flight_frames_count as u32;
+
+        unsafe {
+            let sampler = context.device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                None,
+            )?;
+
+            let descriptor_set_layout = context.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+                ]),
+                None,
+            )?;
+            let descriptor_pool = context.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(set_count)
+                    .pool_sizes(&[vk::DescriptorPoolSize::default()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(set_count)]),
+                None,
+            )?;
+            let descriptor_sets = context
+                .device
+                .allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::default()
+                        .descriptor_pool(descriptor_pool)
+                        .set_layouts(&vec![descriptor_set_layout; set_count as usize]),
+                )?
+                .chunks_exact(3)
+                .map(|sets| [sets[0], sets[1], sets[2]])
+                .collect();
+
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_set_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                        .offset(0)
+                        .size(MAX_POST_PROCESS_PUSH_CONSTANTS_SIZE as u32)]),
+                None,
+            )?;
+
+            Ok(Self {
+                context,
+                format,
+                images: Vec::new(),
+                images_generation: 0,
+                descriptor_generations: vec![None; in_flight_frames_count],
+                retired_images: Vec::new(),
+                retired_pipelines: Vec::new(),
+                in_flight_frames_count,
+                sampler,
+                descriptor_set_layout,
+                descriptor_pool,
+                descriptor_sets,
+                pipeline_layout,
+                vertex_shader,
+                passes: Vec::new(),
+            })
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.passes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    // Names of the passes in the order they run.
+    pub fn names(&self) -> Vec<&str> {
+        self.passes.iter().map(|pass| pass.process.name()).collect()
+    }
+
+    pub fn push(&mut self, process: impl PostProcess + 'static) -> Result<()> {
+        self.insert(self.passes.len(), process)
+    }
+
+    // Creates the pass's pipeline, the pass runs from the next frame on.
+    pub fn insert(&mut self, index: usize, process: impl PostProcess + 'static) -> Result<()> {
+        ensure!(
+            index <= self.passes.len(),
+            "Post process index {index} is out of bounds for {} passes",
+            self.passes.len()
+        );
+        let fragment_shader = self
+            .context
+            .create_shader_module(&process.fragment_shader()?)?;
+        let pipeline = self
+            .context
+            .create_graphics_pipeline(GraphicsPipelineAttributes {
+                vertex_shader: self.vertex_shader,
+                fragment_shader,
+                extent: vk::Extent2D::default(),
+                color_format: self.format,
+                depth_format: vk::Format::UNDEFINED,
+                samples: vk::SampleCountFlags::TYPE_1,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                line_width: 1.0,
+                dynamic_line_width: false,
+                dynamic_topology: false,
+                alpha_blending: false,
+                additive_blending: false,
+                depth_write: false,
+                cull_mode: vk::CullModeFlags::NONE,
+                viewport_count: 1,
+                vertex_layout: Default::default(),
+                pipeline_layout: self.pipeline_layout,
+                pipeline_cache: Default::default(),
+            });
+        unsafe {
+            self.context
+                .device
+                .destroy_shader_module(fragment_shader, None);
+        }
+
+        self.passes.insert(
+            index,
+            ChainedPass {
+                process: Box::new(process),
+                pipeline: pipeline?,
+                push_constants: None,
+            },
+        );
+        Ok(())
+    }
+
+    // The pass's pipeline is destroyed once the frames in flight that may still run it are done.
+    pub fn remove(&mut self, index: usize) -> Result<Box<dyn PostProcess>> {
+        ensure!(
+            index < self.passes.len(),
+            "Post process index {index} is out of bounds for {} passes",
+            self.passes.len()
+        );
+        let pass = self.passes.remove(index);
+        self.retired_pipelines
+            .push((pass.pipeline, self.in_flight_frames_count));
+        Ok(pass.process)
+    }
+
+    pub fn clear(&mut self) -> Result<()> {
+        while !self.passes.is_empty() {
+            self.remove(self.passes.len() - 1)?;
+        }
+        Ok(())
+    }
+
+    fn collect_retired(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for (_, frames_left) in &mut self.retired_images {
+            *frames_left = frames_left.saturating_sub(1);
+        }
+        for (_, frames_left) in &mut self.retired_pipelines {
+            *frames_left = frames_left.saturating_sub(1);
+        }
+        let (done, retired): (Vec<_>, Vec<_>) = std::mem::take(&mut self.retired_images)
+            .into_iter()
+            .partition(|(_, frames_left)| *frames_left == 0);
+        self.retired_images = retired;
+        for (mut image, _) in done {
+            image.destroy(allocator)?;
+        }
+        let (done, retired): (Vec<_>, Vec<_>) = std::mem::take(&mut self.retired_pipelines)
+            .into_iter()
+            .partition(|(_, frames_left)| *frames_left == 0);
+        self.retired_pipelines = retired;
+        for (pipeline, _) in done {
+            unsafe { self.context.device.destroy_pipeline(pipeline, None) };
+        }
+        Ok(())
+    }
+
+    // Whether the frame prepared last runs any pass.
+    fn is_active(&self) -> bool {
+        self.passes.iter().any(|pass| pass.push_constants.is_some())
+    }
+
+    // Gathers the push constants of the enabled passes and points the frame's descriptors at the
+    // render target and the images, recreated when its size changed. Must be called once per
+    // frame after its fence has been waited on, which is also when retired resources are freed.
+    pub(crate) fn prepare(
+        &mut self,
+        allocator: &mut Allocator,
+        frame_index: usize,
+        render_target: &Image,
+        time: &Time,
+    ) -> Result<()> {
+        self.collect_retired(allocator)?;
+        let extent = vk::Extent2D {
+            width: render_target.attributes.extent.width,
+            height: render_target.attributes.extent.height,
+        };
+        for pass in &mut self.passes {
+            pass.push_constants = None;
+            if !pass.process.is_enabled() {
+                continue;
+            }
+            let push_constants = pass.process.push_constants(time, extent);
+            ensure!(
+                push_constants.len() <= MAX_POST_PROCESS_PUSH_CONSTANTS_SIZE
+                    && push_constants.len() % 4 == 0,
+                "Post process {} pushes {} bytes of constants, at most {} in multiples of 4 are \
+                 supported",
+                pass.process.name(),
+                push_constants.len(),
+                MAX_POST_PROCESS_PUSH_CONSTANTS_SIZE
+            );
+            pass.push_constants = Some(push_constants);
+        }
+        if !self.is_active() {
+            return Ok(());
+        }
+
+        if self
+            .images
+            .first()
+            .is_some_and(|image| image.attributes.extent != render_target.attributes.extent)
+        {
+            let frames_left = self.in_flight_frames_count;
+            self.retired_images
+                .extend(self.images.drain(..).map(|image| (image, frames_left)));
+        }
+        if self.images.is_empty() {
+            for index in 0..2 {
+                let image = Image::new(
+                    self.context.clone(),
+                    allocator,
+                    &format!("post_image_{index}"),
+                    ImageAttributes {
+                        extent: render_target.attributes.extent,
+                        format: self.format,
+                        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                        location: MemoryLocation::GpuOnly,
+                        linear: false,
+                        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                        subresource_range: vk::ImageSubresourceRange::default()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .level_count(1)
+                            .layer_count(1),
+                        allocation_priority: 1.0,
+                        samples: vk::SampleCountFlags::TYPE_1,
+                        cube: false,
+                    },
+                )?;
+                self.images.push(image);
+            }
+            self.images_generation += 1;
+        }
+
+        // Other frames in flight may still sample the previous images through theirs.
+        let descriptor_sets = self.descriptor_sets[frame_index];
+        if self.descriptor_generations[frame_index] != Some(self.images_generation) {
+            for (index, image) in self.images.iter().enumerate() {
+                self.write_descriptor(descriptor_sets[1 + index], image.view);
+            }
+            self.descriptor_generations[frame_index] = Some(self.images_generation);
+        }
+        self.write_descriptor(descriptor_sets[0], render_target.view);
+        Ok(())
+    }
+
+    fn write_descriptor(&self, descriptor_set: vk::DescriptorSet, view: vk::ImageView) {
+        unsafe {
+            self.context.device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::default()
+                        .sampler(self.sampler)
+                        .image_view(view)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)])],
+                &[],
+            );
+        }
+    }
+
+    // The image the last enabled pass of the prepared frame writes, None without one.
+    pub(crate) fn output(&self) -> Option<&Image> {
+        let count = self
+            .passes
+            .iter()
+            .filter(|pass| pass.push_constants.is_some())
+            .count();
+        (count > 0).then(|| &self.images[(count - 1) % 2])
+    }
+
+    // Adds the enabled passes of the prepared frame after the render target is resolved and
+    // returns the image the last one writes, the render target itself without any.
+    pub(crate) fn add_passes<'a>(
+        &'a mut self,
+        graph: &mut RenderGraph<'a>,
+        frame_index: usize,
+        render_target: GraphImage,
+    ) -> GraphImage {
+        if !self.is_active() {
+            return render_target;
+        }
+        let descriptor_sets = self.descriptor_sets[frame_index];
+        let pipeline_layout = self.pipeline_layout;
+        let (first, second) = self.images.split_at_mut(1);
+        let images = [
+            graph.import_image("post_image_0", &mut first[0]),
+            graph.import_image("post_image_1", &mut second[0]),
+        ];
+
+        let mut source = (render_target, descriptor_sets[0]);
+        let passes = self
+            .passes
+            .iter()
+            .filter_map(|pass| Some((pass, pass.push_constants.as_deref()?)));
+        for (index, (pass, push_constants)) in passes.enumerate() {
+            let (source_image, descriptor_set) = source;
+            let target = images[index % 2];
+            let name = pass.process.name();
+            graph.add_pass(
+                name,
+                &[GraphAccess::Image(
+                    source_image,
+                    ImageLayoutState::shader_read(),
+                )],
+                &[GraphAccess::Image(
+                    target,
+                    ImageLayoutState::color_attachment(),
+                )],
+                move |commands, images| {
+                    let (source_image, target_image) = images.pair(source_image, target);
+                    let extent = vk::Extent2D {
+                        width: target_image.attributes.extent.width,
+                        height: target_image.attributes.extent.height,
+                    };
+                    let render_area = vk::Rect2D::default().extent(extent);
+                    commands
+                        .begin_color_rendering(target_image, None, render_area)
+                        .set_viewport(
+                            vk::Viewport::default()
+                                .width(extent.width as f32)
+                                .height(extent.height as f32)
+                                .max_depth(1.0),
+                        )
+                        .set_scissor(render_area)
+                        .expect_sampled(source_image, name)
+                        .bind_pipeline(pass.pipeline)
+                        .bind_descriptor_sets(pipeline_layout, &[descriptor_set])
+                        .set_push_constant_bytes(pipeline_layout, push_constants)
+                        .draw(0..3, 0..1)
+                        .end_rendering();
+                    Ok(())
+                },
+            );
+            source = (target, descriptor_sets[1 + index % 2]);
+        }
+        source.0
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for image in self
+            .images
+            .iter_mut()
+            .chain(self.retired_images.iter_mut().map(|(image, _)| image))
+        {
+            image.destroy(allocator)?;
+        }
+
+        unsafe {
+            for pass in &self.passes {
+                self.context.device.destroy_pipeline(pass.pipeline, None);
+            }
+            for (pipeline, _) in &self.retired_pipelines {
+                self.context.device.destroy_pipeline(*pipeline, None);
+            }
+            self.context
+                .device
+                .destroy_shader_module(self.vertex_shader, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.context.device.destroy_sampler(self.sampler, None);
+        }
+        Ok(())
+    }
+}
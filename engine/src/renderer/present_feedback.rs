@@ -0,0 +1,105 @@
+use ash::vk;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Presents reported without a timing once this many are still waiting for one, e.g. when the
+// swapchain they were presented to has been recreated.
+const MAX_PENDING_PRESENTS: usize = 16;
+
+// When an image actually reached the display, from VK_GOOGLE_display_timing. Times are in the
+// presentation engine's clock, CLOCK_MONOTONIC on Linux and Android.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PresentTiming {
+    // Zero when the present didn't ask for a specific time.
+    pub desired_present_time: Duration,
+    pub actual_present_time: Duration,
+    // The earliest the image could have been shown, never later than the actual time.
+    pub earliest_present_time: Duration,
+    // How much earlier the present could have been submitted and still made it.
+    pub present_margin: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PresentedFrame {
+    // Counts the window's presents, starting at 0.
+    pub frame: u64,
+    pub image_index: u32,
+    // When the present was handed to the queue.
+    pub presented_at: Instant,
+    // None without the display timing extension or when the timing got lost.
+    pub timing: Option<PresentTiming>,
+}
+
+impl PresentTiming {
+    fn new(timing: &vk::PastPresentationTimingGOOGLE) -> Self {
+        Self {
+            desired_present_time: Duration::from_nanos(timing.desired_present_time),
+            actual_present_time: Duration::from_nanos(timing.actual_present_time),
+            earliest_present_time: Duration::from_nanos(timing.earliest_present_time),
+            present_margin: Duration::from_nanos(timing.present_margin),
+        }
+    }
+}
+
+// Hands every present of a window to a callback. With display timing the callback for a frame
+// waits until its timing is reported, usually a few frames later; frames are always passed in
+// order.
+pub(crate) struct PresentFeedback {
+    pending: VecDeque<PresentedFrame>,
+    callback: Box<dyn FnMut(&PresentedFrame)>,
+}
+
+impl PresentFeedback {
+    pub fn new(callback: Box<dyn FnMut(&PresentedFrame)>) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            callback,
+        }
+    }
+
+    // The frame doubles as the present id given to the display timing extension, timings are
+    // the ones reported since the last present.
+    pub fn presented(
+        &mut self,
+        frame: u64,
+        image_index: u32,
+        timings: &[vk::PastPresentationTimingGOOGLE],
+        has_timing: bool,
+    ) {
+        self.pending.push_back(PresentedFrame {
+            frame,
+            image_index,
+            presented_at: Instant::now(),
+            timing: None,
+        });
+
+        for timing in timings {
+            if let Some(frame) = self
+                .pending
+                .iter_mut()
+                .find(|frame| frame.frame as u32 == timing.present_id)
+            {
+                frame.timing = Some(PresentTiming::new(timing));
+            }
+        }
+
+        // Timings arrive in present order, so frames before a reported one never get theirs.
+        let last_reported = self
+            .pending
+            .iter()
+            .rev()
+            .find(|frame| frame.timing.is_some())
+            .map(|frame| frame.frame);
+        while let Some(frame) = self.pending.front() {
+            let is_due = !has_timing
+                || frame.timing.is_some()
+                || last_reported.is_some_and(|last| frame.frame <= last)
+                || self.pending.len() > MAX_PENDING_PRESENTS;
+            if !is_due {
+                break;
+            }
+            let frame = self.pending.pop_front().unwrap();
+            (self.callback)(&frame);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use crate::renderer::geometry::{Geometry, Vertex};
+use nalgebra as na;
+use std::f32::consts::{PI, TAU};
+
+// Built-in shapes centered on the origin, with outward normals and counter-clockwise front
+// faces. Lightmap coordinates are copies of the texture coordinates, like loaded meshes get,
+// and tangents are generated from the texture coordinates.
+impl Geometry {
+    // Side length 1, every face maps the whole texture.
+    pub fn cube() -> Self {
+        let mut geometry = Self::new(Vec::new(), Vec::new());
+        let faces = [
+            (na::Vector3::x(), -na::Vector3::y()),
+            (-na::Vector3::x(), -na::Vector3::y()),
+            (na::Vector3::z(), -na::Vector3::y()),
+            (-na::Vector3::z(), -na::Vector3::y()),
+            (na::Vector3::y(), na::Vector3::z()),
+            (-na::Vector3::y(), -na::Vector3::z()),
+        ];
+        for (normal, down) in faces {
+            let right = normal.cross(&down);
+            add_grid(&mut geometry, 1, 1, |u, v| {
+                vertex(
+                    normal * 0.5 + right * (u - 0.5) + down * (v - 0.5),
+                    normal,
+                    na::Vector2::new(u, v),
+                )
+            });
+        }
+        geometry.generate_tangents();
+        geometry
+    }
+
+    // Square in the xz plane facing up.
+    pub fn plane(size: f32) -> Self {
+        let mut geometry = Self::new(Vec::new(), Vec::new());
+        add_grid(&mut geometry, 1, 1, |u, v| {
+            vertex(
+                na::Vector3::new(u - 0.5, 0.0, v - 0.5) * size,
+                na::Vector3::y(),
+                na::Vector2::new(u, v),
+            )
+        });
+        geometry.generate_tangents();
+        geometry
+    }
+
+    // Unit diameter, with subdivisions rings from pole to pole and twice as many segments around
+    // the y axis. The texture wraps around once, equirectangular.
+    pub fn sphere(subdivisions: u32) -> Self {
+        let rings = subdivisions.max(2);
+        let mut geometry = Self::new(Vec::new(), Vec::new());
+        add_grid(&mut geometry, rings * 2, rings, |u, v| {
+            let normal = sphere_normal(u, v * PI);
+            vertex(normal * 0.5, normal, na::Vector2::new(u, v))
+        });
+        geometry.generate_tangents();
+        geometry
+    }
+
+    // Along the y axis. The side maps the whole texture, the caps a circle inscribed in it.
+    pub fn cylinder(radius: f32, height: f32, segments: u32) -> Self {
+        let segments = segments.max(3);
+        let mut geometry = Self::new(Vec::new(), Vec::new());
+        add_grid(&mut geometry, segments, 1, |u, v| {
+            let normal = sphere_normal(u, PI / 2.0);
+            vertex(
+                normal * radius + na::Vector3::y() * (0.5 - v) * height,
+                normal,
+                na::Vector2::new(u, v),
+            )
+        });
+        for (normal, flip) in [(na::Vector3::y(), 1.0), (-na::Vector3::y(), -1.0)] {
+            add_grid(&mut geometry, segments, 1, |u, v| {
+                // From the center out to the rim, mirrored for the bottom cap to face down.
+                let rim =
+                    sphere_normal(u, PI / 2.0).component_mul(&na::Vector3::new(1.0, 0.0, flip));
+                vertex(
+                    rim * v * radius + normal * height * 0.5,
+                    normal,
+                    na::Vector2::new(0.5 + rim.x * v * 0.5, 0.5 + rim.z * v * 0.5),
+                )
+            });
+        }
+        geometry.generate_tangents();
+        geometry
+    }
+
+    // Ring around the y axis, the tube has segments / 2 sides.
+    pub fn torus(major_radius: f32, minor_radius: f32, segments: u32) -> Self {
+        let segments = segments.max(3);
+        let mut geometry = Self::new(Vec::new(), Vec::new());
+        add_grid(&mut geometry, segments, (segments / 2).max(3), |u, v| {
+            let radial = sphere_normal(u, PI / 2.0);
+            // Starts at the top of the tube and turns outwards first.
+            let angle = PI / 2.0 - v * TAU;
+            let normal = radial * angle.cos() + na::Vector3::y() * angle.sin();
+            vertex(
+                radial * major_radius + normal * minor_radius,
+                normal,
+                na::Vector2::new(u, v),
+            )
+        });
+        geometry.generate_tangents();
+        geometry
+    }
+
+    // Along the y axis, height includes the hemispheres. The texture's v follows the surface
+    // from the top to the bottom.
+    pub fn capsule(radius: f32, height: f32, segments: u32) -> Self {
+        let segments = segments.max(3);
+        let rings = (segments / 4).max(2);
+        let cylinder_height = (height - radius * 2.0).max(0.0);
+        let length = PI * radius + cylinder_height;
+        let mut geometry = Self::new(Vec::new(), Vec::new());
+
+        let cap_length = PI / 2.0 * radius / length;
+        for (first_angle, offset, first_v) in [
+            (0.0, cylinder_height * 0.5, 0.0),
+            (PI / 2.0, -cylinder_height * 0.5, 1.0 - cap_length),
+        ] {
+            add_grid(&mut geometry, segments, rings, |u, v| {
+                let normal = sphere_normal(u, first_angle + v * PI / 2.0);
+                vertex(
+                    normal * radius + na::Vector3::y() * offset,
+                    normal,
+                    na::Vector2::new(u, first_v + v * cap_length),
+                )
+            });
+        }
+        add_grid(&mut geometry, segments, 1, |u, v| {
+            let normal = sphere_normal(u, PI / 2.0);
+            vertex(
+                normal * radius + na::Vector3::y() * (0.5 - v) * cylinder_height,
+                normal,
+                na::Vector2::new(u, cap_length + v * (1.0 - cap_length * 2.0)),
+            )
+        });
+        geometry.generate_tangents();
+        geometry
+    }
+}
+
+fn vertex(
+    position: na::Vector3<f32>,
+    normal: na::Vector3<f32>,
+    tex_coord: na::Vector2<f32>,
+) -> Vertex {
+    Vertex {
+        position,
+        normal,
+        tex_coord,
+        lightmap_tex_coord: tex_coord,
+        bent_normal: normal,
+        ambient_occlusion: 1.0,
+        tangent: na::Vector4::zeros(),
+    }
+}
+
+// Unit direction at the fraction u of a turn around the y axis and the polar angle from +y,
+// u moves to the right seen from outside.
+fn sphere_normal(u: f32, polar_angle: f32) -> na::Vector3<f32> {
+    let azimuth = u * TAU;
+    na::Vector3::new(
+        polar_angle.sin() * azimuth.cos(),
+        polar_angle.cos(),
+        -polar_angle.sin() * azimuth.sin(),
+    )
+}
+
+// Appends a columns by rows grid of quads, vertex gets u and v from 0 to 1. Seen from the
+// front, u has to go right and v down like texture coordinates, which keeps the triangles
+// counter-clockwise.
+fn add_grid(geometry: &mut Geometry, columns: u32, rows: u32, vertex: impl Fn(f32, f32) -> Vertex) {
+    let first_vertex = geometry.vertices.len() as u32;
+    for row in 0..=rows {
+        for column in 0..=columns {
+            geometry.vertices.push(vertex(
+                column as f32 / columns as f32,
+                row as f32 / rows as f32,
+            ));
+        }
+    }
+    for row in 0..rows {
+        for column in 0..columns {
+            let top_left = first_vertex + row * (columns + 1) + column;
+            let bottom_left = top_left + columns + 1;
+            geometry.indices.extend_from_slice(&[
+                top_left,
+                bottom_left,
+                top_left + 1,
+                top_left + 1,
+                bottom_left,
+                bottom_left + 1,
+            ]);
+        }
+    }
+}
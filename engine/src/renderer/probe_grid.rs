@@ -0,0 +1,667 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::image::{Image, ImageAttributes, ImageLayoutState};
+use crate::renderer::commands::Commands;
+use crate::renderer::point_lights::{cube_face_projection, CUBE_FACES};
+use crate::renderer::{load_shader_module, GPUCamera, SHADERS_DIR};
+use crate::rendering_context::{ComputePipelineAttributes, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use nalgebra as na;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+const SH_L0: f32 = 0.282_095;
+const SH_L1: f32 = 0.488_603;
+
+// Probes are captured into half floats, the scene shaders output luminance.
+pub(crate) const CAPTURE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProbeCaptureAttributes {
+    // Probes rendered per frame, taking turns over the grid.
+    pub probes_per_frame: u32,
+    // Width and height of each captured cube face in texels.
+    pub resolution: u32,
+    // Fraction of a probe's previous value kept by each capture, hiding flicker as the lighting
+    // changes at the cost of lagging behind it.
+    pub hysteresis: f32,
+    // Distance from the probe past which the scene isn't captured, the ambient light fills in.
+    pub range: f32,
+}
+
+impl Default for ProbeCaptureAttributes {
+    fn default() -> Self {
+        Self {
+            probes_per_frame: 4,
+            resolution: 16,
+            hysteresis: 0.9,
+            range: 100.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProbeGridAttributes {
+    pub origin: na::Point3<f32>,
+    pub spacing: na::Vector3<f32>,
+    pub counts: na::Vector3<u32>,
+    // Updates the probes from the scene on the GPU, None keeps the ones set on the CPU.
+    pub capture: Option<ProbeCaptureAttributes>,
+}
+
+// L1 spherical harmonics of the irradiance (cosine convolved radiance) around a probe,
+// ordered as constant, x, y, z terms with one RGB triple each.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct IrradianceProbe {
+    pub coefficients: [na::Vector3<f32>; 4],
+}
+
+impl IrradianceProbe {
+    pub fn uniform(irradiance: na::Vector3<f32>) -> Self {
+        let mut probe: Self = bytemuck::Zeroable::zeroed();
+        probe.coefficients[0] = irradiance / SH_L0;
+        probe
+    }
+
+    // Samples are expected to be uniformly distributed over the sphere,
+    // e.g. rays traced from the probe position or texels of a captured cube map.
+    pub fn from_radiance_samples(
+        samples: impl IntoIterator<Item = (na::Unit<na::Vector3<f32>>, na::Vector3<f32>)>,
+    ) -> Self {
+        let mut radiance: [na::Vector3<f32>; 4] = bytemuck::Zeroable::zeroed();
+        let mut sample_count = 0;
+
+        for (direction, sample) in samples {
+            radiance[0] += sample * SH_L0;
+            radiance[1] += sample * (SH_L1 * direction.x);
+            radiance[2] += sample * (SH_L1 * direction.y);
+            radiance[3] += sample * (SH_L1 * direction.z);
+            sample_count += 1;
+        }
+
+        if sample_count == 0 {
+            return bytemuck::Zeroable::zeroed();
+        }
+
+        let weight = 4.0 * PI / sample_count as f32;
+        Self {
+            coefficients: [
+                radiance[0] * weight * PI,
+                radiance[1] * weight * (2.0 * PI / 3.0),
+                radiance[2] * weight * (2.0 * PI / 3.0),
+                radiance[3] * weight * (2.0 * PI / 3.0),
+            ],
+        }
+    }
+
+    pub fn irradiance(&self, normal: &na::Unit<na::Vector3<f32>>) -> na::Vector3<f32> {
+        let irradiance = self.coefficients[0] * SH_L0
+            + (self.coefficients[1] * normal.x
+                + self.coefficients[2] * normal.y
+                + self.coefficients[3] * normal.z)
+                * SH_L1;
+        irradiance.map(|channel| channel.max(0.0))
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GPUProbeGridHeader {
+    origin: na::Vector3<f32>,
+    spacing: na::Vector3<f32>,
+    counts: na::Vector3<u32>,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ProbeUpdatePushConstants {
+    probe_grid_address: vk::DeviceAddress,
+    first_probe: u32,
+    probe_count: u32,
+    resolution: u32,
+    hysteresis: f32,
+    radiance_scale: f32,
+    padding: u32,
+}
+
+// Renders a few probes' surroundings per frame with the scene pipeline, one row of six cube
+// faces per probe, and projects them to spherical harmonics blended into the probe buffer.
+struct ProbeCapture {
+    context: Arc<RenderingContext>,
+    attributes: ProbeCaptureAttributes,
+    color_image: Image,
+    depth_image: Image,
+    // Per frame in flight, one camera per face of every captured probe.
+    camera_buffers: Vec<Buffer>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    // Probe of the first row of the current capture.
+    first_probe: usize,
+}
+
+impl ProbeCapture {
+    fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        attributes: ProbeCaptureAttributes,
+        // Captured per frame, never more than the grid's probes.
+        row_count: u32,
+        depth_format: vk::Format,
+        buffering: usize,
+    ) -> Result<Self> {
+        let resolution = attributes.resolution.max(1);
+        let extent = vk::Extent3D {
+            width: resolution * 6,
+            height: resolution * row_count,
+            depth: 1,
+        };
+        let create_image = |allocator: &mut Allocator, name, format, usage, aspect_mask| {
+            Image::new(
+                context.clone(),
+                allocator,
+                name,
+                ImageAttributes {
+                    extent,
+                    format,
+                    usage,
+                    location: MemoryLocation::GpuOnly,
+                    linear: false,
+                    allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                    subresource_range: vk::ImageSubresourceRange::default()
+                        .aspect_mask(aspect_mask)
+                        .level_count(1)
+                        .layer_count(1),
+                    allocation_priority: 1.0,
+                    samples: vk::SampleCountFlags::TYPE_1,
+                    cube: false,
+                },
+            )
+        };
+        let color_image = create_image(
+            allocator,
+            "probe_capture_color",
+            CAPTURE_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+        let depth_image = create_image(
+            allocator,
+            "probe_capture_depth",
+            depth_format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+
+        let camera_buffers = (0..buffering)
+            .map(|_| {
+                Buffer::new(
+                    allocator,
+                    BufferAttributes {
+                        name: "probe_capture_camera_buffer".into(),
+                        context: context.clone(),
+                        size: (row_count as usize * 6 * size_of::<GPUCamera>()) as vk::DeviceSize,
+                        usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                        location: MemoryLocation::CpuToGpu,
+                        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                        allocation_priority: 1.0,
+                    },
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "probe_update.comp.spv",
+        )?;
+
+        unsafe {
+            let descriptor_set_layout = context.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE),
+                ]),
+                None,
+            )?;
+
+            let descriptor_pool = context.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(1)
+                    .pool_sizes(&[vk::DescriptorPoolSize::default()
+                        .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                        .descriptor_count(1)]),
+                None,
+            )?;
+
+            let descriptor_set = context.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&[descriptor_set_layout]),
+            )?[0];
+
+            context.device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&[vk::DescriptorImageInfo::default()
+                        .image_view(color_image.view)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)])],
+                &[],
+            );
+
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_set_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .offset(0)
+                        .size(size_of::<ProbeUpdatePushConstants>() as u32)]),
+                None,
+            )?;
+
+            let pipeline = context.create_compute_pipeline(ComputePipelineAttributes {
+                shader,
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            })?;
+
+            context.device.destroy_shader_module(shader, None);
+
+            Ok(Self {
+                context,
+                attributes: ProbeCaptureAttributes {
+                    resolution,
+                    ..attributes
+                },
+                color_image,
+                depth_image,
+                camera_buffers,
+                descriptor_set_layout,
+                descriptor_pool,
+                descriptor_set,
+                pipeline_layout,
+                pipeline,
+                first_probe: 0,
+            })
+        }
+    }
+
+    fn row_count(&self) -> u32 {
+        self.color_image.attributes.extent.height / self.attributes.resolution
+    }
+
+    fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for buffer in &mut self.camera_buffers {
+            buffer.destroy(allocator)?;
+        }
+        self.color_image.destroy(allocator)?;
+        self.depth_image.destroy(allocator)?;
+        unsafe {
+            self.context.device.destroy_pipeline(self.pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        Ok(())
+    }
+}
+
+pub struct ProbeGrid {
+    pub attributes: ProbeGridAttributes,
+    // As last set on the CPU, captures only update the probe buffer.
+    probes: Vec<IrradianceProbe>,
+    // Header and probes read by the scene, shared by the frames in flight since captures
+    // build on the previous values.
+    probe_buffer: Buffer,
+    // Per frame in flight, CPU side changes are copied through them into the probe buffer.
+    staging_buffers: Vec<Buffer>,
+    is_dirty: bool,
+    capture: Option<ProbeCapture>,
+}
+
+impl ProbeGrid {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        attributes: ProbeGridAttributes,
+        // Of the captures, the renderer's.
+        depth_format: vk::Format,
+        buffering: usize,
+    ) -> Result<Self> {
+        let probe_count = attributes.counts.iter().product::<u32>() as usize;
+        let size = (size_of::<GPUProbeGridHeader>() + probe_count * size_of::<IrradianceProbe>())
+            as vk::DeviceSize;
+
+        let probe_buffer = Buffer::new(
+            allocator,
+            BufferAttributes {
+                name: "probe_grid_buffer".into(),
+                context: context.clone(),
+                size,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                    | vk::BufferUsageFlags::TRANSFER_DST,
+                location: MemoryLocation::GpuOnly,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                allocation_priority: 1.0,
+            },
+        )?;
+        let staging_buffers = (0..buffering)
+            .map(|_| {
+                Buffer::new(
+                    allocator,
+                    BufferAttributes {
+                        name: "probe_grid_staging_buffer".into(),
+                        context: context.clone(),
+                        size,
+                        usage: vk::BufferUsageFlags::TRANSFER_SRC,
+                        location: MemoryLocation::CpuToGpu,
+                        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                        allocation_priority: 1.0,
+                    },
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let capture = attributes
+            .capture
+            .filter(|_| probe_count > 0)
+            .map(|capture_attributes| {
+                ProbeCapture::new(
+                    context.clone(),
+                    allocator,
+                    capture_attributes,
+                    capture_attributes
+                        .probes_per_frame
+                        .clamp(1, probe_count as u32),
+                    depth_format,
+                    buffering,
+                )
+            })
+            .transpose()?;
+
+        Ok(Self {
+            attributes,
+            probes: vec![bytemuck::Zeroable::zeroed(); probe_count],
+            probe_buffer,
+            staging_buffers,
+            is_dirty: true,
+            capture,
+        })
+    }
+
+    pub fn probe_count(&self) -> usize {
+        self.probes.len()
+    }
+
+    pub fn probe_index(&self, cell: na::Vector3<u32>) -> usize {
+        let counts = self.attributes.counts;
+        (cell.x + counts.x * (cell.y + counts.y * cell.z)) as usize
+    }
+
+    pub fn probe_position(&self, index: usize) -> na::Point3<f32> {
+        let counts = self.attributes.counts;
+        let index = index as u32;
+        let cell = na::Vector3::new(
+            index % counts.x,
+            (index / counts.x) % counts.y,
+            index / (counts.x * counts.y),
+        );
+        self.attributes.origin + cell.cast::<f32>().component_mul(&self.attributes.spacing)
+    }
+
+    pub fn probe(&self, index: usize) -> &IrradianceProbe {
+        &self.probes[index]
+    }
+
+    pub fn set_probe(&mut self, index: usize, probe: IrradianceProbe) {
+        self.probes[index] = probe;
+        self.mark_dirty();
+    }
+
+    // Blends towards the new value like DDGI's hysteresis to hide noisy updates.
+    pub fn blend_probe(&mut self, index: usize, probe: IrradianceProbe, hysteresis: f32) {
+        let current = &mut self.probes[index];
+        for (current, new) in current.coefficients.iter_mut().zip(probe.coefficients) {
+            *current = current.lerp(&new, 1.0 - hysteresis);
+        }
+        self.mark_dirty();
+    }
+
+    // Overwrites every probe in the probe buffer, captured ones included.
+    fn mark_dirty(&mut self) {
+        self.is_dirty = true;
+    }
+
+    pub fn address(&self) -> vk::DeviceAddress {
+        self.probe_buffer.address
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    // Must be called after the frame's fence has been waited on, before the scene is drawn.
+    pub(crate) fn upload(&mut self, commands: &Commands, frame_index: usize) -> Result<()> {
+        if !self.is_dirty {
+            return Ok(());
+        }
+
+        let staging_buffer = &mut self.staging_buffers[frame_index];
+        staging_buffer.write(
+            &[GPUProbeGridHeader {
+                origin: self.attributes.origin.coords,
+                spacing: self.attributes.spacing,
+                counts: self.attributes.counts,
+            }],
+            0,
+        )?;
+        staging_buffer.write(
+            &self.probes,
+            size_of::<GPUProbeGridHeader>() as vk::DeviceSize,
+        )?;
+        commands
+            .buffer_memory_barrier(
+                &self.probe_buffer,
+                (
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags2::COPY,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                ),
+            )
+            .copy_buffer(staging_buffer, &self.probe_buffer, 0)
+            .buffer_memory_barrier(
+                &self.probe_buffer,
+                (
+                    vk::PipelineStageFlags2::COPY,
+                    vk::AccessFlags2::TRANSFER_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                ),
+            );
+        self.is_dirty = false;
+        Ok(())
+    }
+
+    // Writes the face cameras of the next probes in turn, must be called after the frame's
+    // fence has been waited on. Lighting is taken from the frame's main camera.
+    pub(crate) fn prepare_capture(
+        &mut self,
+        frame_index: usize,
+        eye: &na::Point3<f64>,
+        main_camera: &GPUCamera,
+    ) -> Result<()> {
+        let Some(capture) = &self.capture else {
+            return Ok(());
+        };
+        let projection = cube_face_projection(capture.attributes.range);
+        let cameras = (0..capture.row_count() as usize)
+            .flat_map(|row| {
+                let probe = (capture.first_probe + row) % self.probe_count();
+                let position = self.probe_position(probe).cast::<f64>() - eye.coords;
+                CUBE_FACES.iter().map(move |(direction, up)| {
+                    let target = position + na::Vector3::from(*direction);
+                    let view = na::Isometry3::look_at_rh(&position, &target, &(*up).into());
+                    GPUCamera {
+                        view: view.to_homogeneous().cast(),
+                        projection,
+                        position: position.coords.cast(),
+                        exposure: 1.0,
+                        // Shading positions stay relative to the main camera, and so does the
+                        // origin probes are looked up from.
+                        ..*main_camera
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        self.capture.as_mut().unwrap().camera_buffers[frame_index].write(&cameras, 0)
+    }
+
+    // Renders the probes prepared for the frame and blends them into the probe buffer for the
+    // scene pass to read, does nothing without capture attributes. The scene's descriptor sets
+    // have to be bound already.
+    pub(crate) fn capture(
+        &mut self,
+        commands: &Commands,
+        frame_index: usize,
+        scene_pipeline: vk::Pipeline,
+        // Luminance of everything past the capture range.
+        background: na::Vector3<f32>,
+        // From captured luminance to the units of the probes, see Lighting::baked_illuminance.
+        radiance_scale: f32,
+        // Called once per face with the address of its camera.
+        draw_instances: impl Fn(&Commands, vk::DeviceAddress),
+    ) {
+        let probe_count = self.probe_count();
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        let resolution = capture.attributes.resolution;
+        let row_count = capture.row_count();
+        let extent = vk::Extent2D {
+            width: capture.color_image.attributes.extent.width,
+            height: capture.color_image.attributes.extent.height,
+        };
+
+        commands
+            .begin_color_depth_rendering(
+                &mut capture.color_image,
+                &mut capture.depth_image,
+                vk::ClearColorValue {
+                    float32: [background.x, background.y, background.z, 1.0],
+                },
+                vk::Rect2D::default().extent(extent),
+            )
+            .bind_pipeline(scene_pipeline);
+        for row in 0..row_count {
+            for face in 0..6 {
+                let offset = vk::Offset2D {
+                    x: (face * resolution) as i32,
+                    y: (row * resolution) as i32,
+                };
+                let camera_index = (row * 6 + face) as usize;
+                commands
+                    .set_viewport(
+                        vk::Viewport::default()
+                            .x(offset.x as f32)
+                            .y(offset.y as f32)
+                            .width(resolution as f32)
+                            .height(resolution as f32)
+                            .max_depth(1.0),
+                    )
+                    .set_scissor(vk::Rect2D {
+                        offset,
+                        extent: vk::Extent2D {
+                            width: resolution,
+                            height: resolution,
+                        },
+                    });
+                draw_instances(
+                    commands,
+                    capture.camera_buffers[frame_index].address
+                        + (camera_index * size_of::<GPUCamera>()) as vk::DeviceAddress,
+                );
+            }
+        }
+
+        commands
+            .end_rendering()
+            .ensure_image_layout(
+                &mut capture.color_image,
+                ImageLayoutState::compute_shader_read(),
+            )
+            .expect_sampled(&capture.color_image, "probe_update")
+            // The captures themselves read the probes for a further bounce.
+            .buffer_memory_barrier(
+                &self.probe_buffer,
+                (
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_READ,
+                ),
+                (
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                ),
+            )
+            .bind_compute_pipeline(capture.pipeline)
+            .bind_compute_descriptor_sets(capture.pipeline_layout, &[capture.descriptor_set])
+            .set_compute_push_constants(
+                capture.pipeline_layout,
+                ProbeUpdatePushConstants {
+                    probe_grid_address: self.probe_buffer.address,
+                    first_probe: capture.first_probe as u32,
+                    probe_count: probe_count as u32,
+                    resolution,
+                    hysteresis: capture.attributes.hysteresis.clamp(0.0, 1.0),
+                    radiance_scale,
+                    padding: 0,
+                },
+            )
+            .dispatch([row_count, 1, 1])
+            .buffer_memory_barrier(
+                &self.probe_buffer,
+                (
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_READ,
+                ),
+            );
+        capture.first_probe = (capture.first_probe + row_count as usize) % probe_count;
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        if let Some(capture) = &mut self.capture {
+            capture.destroy(allocator)?;
+        }
+        for buffer in &mut self.staging_buffers {
+            buffer.destroy(allocator)?;
+        }
+        self.probe_buffer.destroy(allocator)
+    }
+}
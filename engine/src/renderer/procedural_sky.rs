@@ -0,0 +1,256 @@
+use crate::image::{mip_level_count, Image, ImageAttributes, ImageLayoutState};
+use crate::renderer::commands::Commands;
+use crate::renderer::ibl::create_storage_view;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{ComputePipelineAttributes, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use nalgebra as na;
+use std::sync::Arc;
+
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+// The sky is smooth apart from the sun's glow, which the prefiltered lighting blurs anyway.
+const SIZE: u32 = 128;
+const WORKGROUP_SIZE: u32 = 8;
+// The sun moves this far in two minutes of a day, smaller steps wouldn't visibly change the sky
+// but would prefilter the lighting again every frame.
+const RECOMPUTE_ANGLE: f32 = 0.5 * std::f32::consts::PI / 180.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProceduralSkyAttributes {
+    // Haziness of the atmosphere, from 2 for a very clear sky to around 10 for a hazy one.
+    pub turbidity: f32,
+    // Fraction of the horizon's light reflected by the ground below it.
+    pub ground_albedo: f32,
+}
+
+impl Default for ProceduralSkyAttributes {
+    fn default() -> Self {
+        Self {
+            turbidity: 2.5,
+            ground_albedo: 0.3,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyPushConstants {
+    sun_direction: na::Vector3<f32>,
+    turbidity: f32,
+    ground_albedo: f32,
+}
+
+// Preetham daylight computed into a cube map that stands in for the skybox, for both the
+// background and the image based lighting. Radiance is relative to the zenith, scaled like a
+// skybox by Lighting::sky_luminance. The map is only recomputed when the sun or the attributes
+// change.
+pub struct ProceduralSky {
+    context: Arc<RenderingContext>,
+    attributes: Option<ProceduralSkyAttributes>,
+    cubemap: Image,
+    storage_view: vk::ImageView,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    // What the cube map was last computed from.
+    computed: Option<(na::Vector3<f32>, ProceduralSkyAttributes)>,
+}
+
+impl ProceduralSky {
+    pub fn new(context: Arc<RenderingContext>, allocator: &mut Allocator) -> Result<Self> {
+        let cubemap = Image::new(
+            context.clone(),
+            allocator,
+            "procedural_sky",
+            ImageAttributes {
+                extent: vk::Extent3D {
+                    width: SIZE,
+                    height: SIZE,
+                    depth: 1,
+                },
+                format: FORMAT,
+                usage: vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+                location: MemoryLocation::GpuOnly,
+                linear: false,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                subresource_range: vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .level_count(mip_level_count(vk::Extent2D {
+                        width: SIZE,
+                        height: SIZE,
+                    }))
+                    .layer_count(6),
+                allocation_priority: 1.0,
+                samples: vk::SampleCountFlags::TYPE_1,
+                cube: true,
+            },
+        )?;
+        let shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "procedural_sky.comp.spv",
+        )?;
+
+        unsafe {
+            let storage_view = create_storage_view(&context, &cubemap, 0)?;
+
+            let descriptor_set_layout = context.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE),
+                ]),
+                None,
+            )?;
+            let descriptor_pool = context.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(1)
+                    .pool_sizes(&[vk::DescriptorPoolSize::default()
+                        .ty(vk::DescriptorType::STORAGE_IMAGE)
+                        .descriptor_count(1)]),
+                None,
+            )?;
+            let descriptor_set = context.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&[descriptor_set_layout]),
+            )?[0];
+            context.device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(&[vk::DescriptorImageInfo::default()
+                        .image_view(storage_view)
+                        .image_layout(vk::ImageLayout::GENERAL)])],
+                &[],
+            );
+
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_set_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .offset(0)
+                        .size(size_of::<SkyPushConstants>() as u32)]),
+                None,
+            )?;
+            let pipeline = context.create_compute_pipeline(ComputePipelineAttributes {
+                shader,
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            });
+            context.device.destroy_shader_module(shader, None);
+
+            Ok(Self {
+                context,
+                attributes: None,
+                cubemap,
+                storage_view,
+                descriptor_set_layout,
+                descriptor_pool,
+                descriptor_set,
+                pipeline_layout,
+                pipeline: pipeline?,
+                computed: None,
+            })
+        }
+    }
+
+    pub fn attributes(&self) -> Option<ProceduralSkyAttributes> {
+        self.attributes
+    }
+
+    // The sky is computed again with the next frame, None turns it off.
+    pub fn set_attributes(&mut self, attributes: Option<ProceduralSkyAttributes>) {
+        self.attributes = attributes;
+        self.computed = None;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.attributes.is_some()
+    }
+
+    pub fn cubemap(&self) -> &Image {
+        &self.cubemap
+    }
+
+    pub fn cubemap_mut(&mut self) -> &mut Image {
+        &mut self.cubemap
+    }
+
+    // Computes the sky for the sun when it has moved and leaves the cube map ready to be
+    // sampled. Returns whether it was recomputed, for the lighting to be prefiltered again.
+    pub fn record(
+        &mut self,
+        commands: &Commands,
+        // Direction the sunlight travels in, see Lighting.
+        sun_direction: &na::Vector3<f32>,
+    ) -> bool {
+        let Some(attributes) = self.attributes else {
+            return false;
+        };
+        if let Some((computed_direction, computed_attributes)) = self.computed {
+            if computed_attributes == attributes
+                && computed_direction.angle(sun_direction) < RECOMPUTE_ANGLE
+            {
+                return false;
+            }
+        }
+        self.computed = Some((*sun_direction, attributes));
+
+        commands
+            .ensure_image_layout(
+                &mut self.cubemap,
+                ImageLayoutState::compute_shader_storage(),
+            )
+            .bind_compute_pipeline(self.pipeline)
+            .bind_compute_descriptor_sets(self.pipeline_layout, &[self.descriptor_set])
+            .set_compute_push_constants(
+                self.pipeline_layout,
+                SkyPushConstants {
+                    sun_direction: -sun_direction
+                        .try_normalize(f32::EPSILON)
+                        .unwrap_or(na::Vector3::y()),
+                    turbidity: attributes.turbidity.max(1.0),
+                    ground_albedo: attributes.ground_albedo,
+                },
+            )
+            .dispatch([
+                SIZE.div_ceil(WORKGROUP_SIZE),
+                SIZE.div_ceil(WORKGROUP_SIZE),
+                6,
+            ])
+            .generate_mipmaps(&mut self.cubemap)
+            .ensure_image_layout(&mut self.cubemap, ImageLayoutState::shader_read());
+        true
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        unsafe {
+            self.context
+                .device
+                .destroy_image_view(self.storage_view, None);
+            self.context.device.destroy_pipeline(self.pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        self.cubemap.destroy(allocator)
+    }
+}
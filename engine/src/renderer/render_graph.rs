@@ -0,0 +1,231 @@
+use crate::renderer::commands::Commands;
+use crate::renderer::frame_graph::{FramePass, ResourceAccess};
+use crate::rendering_context::{Image, ImageLayoutState};
+use anyhow::Result;
+use ash::vk;
+use tracing::trace;
+
+// Handles to the resources imported into a RenderGraph.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GraphImage(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GraphBuffer(usize);
+
+#[derive(Clone, Copy, Debug)]
+pub enum GraphAccess {
+    Image(GraphImage, ImageLayoutState),
+    // Buffers are only ordered with memory barriers, passes reach them through their own handles
+    // or device addresses.
+    Buffer(GraphBuffer, vk::PipelineStageFlags2, vk::AccessFlags2),
+}
+
+// The imported images, handed to every pass while it's recorded.
+#[derive(Default)]
+pub struct GraphImages<'a> {
+    images: Vec<(String, &'a mut Image)>,
+}
+
+impl GraphImages<'_> {
+    pub fn get(&mut self, image: GraphImage) -> &mut Image {
+        self.images[image.0].1
+    }
+
+    // Two different images at once, e.g. the source and destination of a blit.
+    pub fn pair(&mut self, first: GraphImage, second: GraphImage) -> (&mut Image, &mut Image) {
+        assert_ne!(first, second, "Can't borrow the same graph image twice");
+        if first.0 < second.0 {
+            let (head, tail) = self.images.split_at_mut(second.0);
+            (head[first.0].1, tail[0].1)
+        } else {
+            let (head, tail) = self.images.split_at_mut(first.0);
+            (tail[0].1, head[second.0].1)
+        }
+    }
+}
+
+type RecordPass<'a> = Box<dyn FnOnce(&Commands, &mut GraphImages<'a>) -> Result<()> + 'a>;
+
+struct GraphPass<'a> {
+    name: String,
+    reads: Vec<GraphAccess>,
+    writes: Vec<GraphAccess>,
+    record: RecordPass<'a>,
+}
+
+#[derive(Default)]
+struct BufferState {
+    // The last write, made visible to the accesses after it by the next barrier.
+    write: Option<(vk::PipelineStageFlags2, vk::AccessFlags2)>,
+    // Reads since the last write, which already wait for it.
+    read_stages: vk::PipelineStageFlags2,
+    read_access: vk::AccessFlags2,
+}
+
+// Passes declare the images and buffers they read and write, the graph records them in the
+// order they were added with the barriers between them. Images keep their tracked layouts, so
+// transitions done inside a pass and layouts from before the graph both stay valid; buffer
+// writes from before the graph need their own barrier.
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    images: GraphImages<'a>,
+    buffers: Vec<(String, BufferState)>,
+    passes: Vec<GraphPass<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn import_image(&mut self, name: &str, image: &'a mut Image) -> GraphImage {
+        self.images.images.push((name.to_owned(), image));
+        GraphImage(self.images.images.len() - 1)
+    }
+
+    pub fn import_buffer(&mut self, name: &str) -> GraphBuffer {
+        self.buffers.push((name.to_owned(), BufferState::default()));
+        GraphBuffer(self.buffers.len() - 1)
+    }
+
+    // An image both read and written by a pass is brought into the state of the write.
+    pub fn add_pass(
+        &mut self,
+        name: &str,
+        reads: &[GraphAccess],
+        writes: &[GraphAccess],
+        record: impl FnOnce(&Commands, &mut GraphImages<'a>) -> Result<()> + 'a,
+    ) {
+        self.passes.push(GraphPass {
+            name: name.to_owned(),
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            record: Box::new(record),
+        });
+    }
+
+    // Returns the recorded passes for the frame graph.
+    pub fn execute(mut self, commands: &Commands) -> Result<Vec<FramePass>> {
+        let mut frame_passes = Vec::with_capacity(self.passes.len());
+        for pass in std::mem::take(&mut self.passes) {
+            let mut memory_barriers = Vec::new();
+            let mut image_barriers = Vec::new();
+            let accesses = pass
+                .reads
+                .iter()
+                .map(|access| (access, false))
+                .chain(pass.writes.iter().map(|access| (access, true)));
+            for (access, is_write) in accesses {
+                match *access {
+                    GraphAccess::Image(image, state) => {
+                        let is_written = pass.writes.iter().any(|write| {
+                            matches!(write, GraphAccess::Image(written, _) if *written == image)
+                        });
+                        if !is_write && is_written {
+                            continue;
+                        }
+                        transition_image(
+                            self.images.get(image),
+                            state,
+                            is_write,
+                            &mut image_barriers,
+                        );
+                    }
+                    GraphAccess::Buffer(buffer, stage, access) => {
+                        let state = &mut self.buffers[buffer.0].1;
+                        memory_barriers.extend(buffer_barrier(state, stage, access, is_write));
+                    }
+                }
+            }
+            trace!(
+                "Render graph pass {} with {} memory and {} image barriers",
+                pass.name,
+                memory_barriers.len(),
+                image_barriers.len()
+            );
+            commands.pipeline_barrier(&memory_barriers, &image_barriers);
+
+            frame_passes.push(FramePass {
+                name: pass.name,
+                reads: self.resource_accesses(&pass.reads),
+                writes: self.resource_accesses(&pass.writes),
+            });
+            (pass.record)(commands, &mut self.images)?;
+        }
+        Ok(frame_passes)
+    }
+
+    fn resource_accesses(&self, accesses: &[GraphAccess]) -> Vec<ResourceAccess> {
+        accesses
+            .iter()
+            .map(|access| match *access {
+                GraphAccess::Image(image, state) => ResourceAccess {
+                    resource: self.images.images[image.0].0.clone(),
+                    layout: Some(state.layout),
+                },
+                GraphAccess::Buffer(buffer, ..) => ResourceAccess {
+                    resource: self.buffers[buffer.0].0.clone(),
+                    layout: None,
+                },
+            })
+            .collect()
+    }
+}
+
+// Writes always wait for the earlier accesses, even in the same layout; reads only when the
+// image isn't readable in the new state yet.
+fn transition_image(
+    image: &mut Image,
+    state: ImageLayoutState,
+    is_write: bool,
+    barriers: &mut Vec<vk::ImageMemoryBarrier2<'static>>,
+) {
+    let range = image.attributes.subresource_range;
+    for (old_state, range) in image.layout_transitions(range, state, !is_write) {
+        barriers.push(
+            vk::ImageMemoryBarrier2::default()
+                .src_stage_mask(old_state.stage)
+                .dst_stage_mask(state.stage)
+                .src_access_mask(old_state.access)
+                .dst_access_mask(state.access)
+                .old_layout(old_state.layout)
+                .new_layout(state.layout)
+                .src_queue_family_index(old_state.queue_family)
+                .dst_queue_family_index(state.queue_family)
+                .image(image.handle)
+                .subresource_range(range),
+        );
+        image.set_subresource_layout(range, state);
+    }
+}
+
+fn buffer_barrier(
+    state: &mut BufferState,
+    stage: vk::PipelineStageFlags2,
+    access: vk::AccessFlags2,
+    is_write: bool,
+) -> Option<vk::MemoryBarrier2<'static>> {
+    let barrier = |src_stage, src_access| {
+        vk::MemoryBarrier2::default()
+            .src_stage_mask(src_stage)
+            .src_access_mask(src_access)
+            .dst_stage_mask(stage)
+            .dst_access_mask(access)
+    };
+    if is_write {
+        // Waits for the reads so they don't see the new data, and for the last write.
+        let (write_stage, write_access) = state.write.unwrap_or_default();
+        let src_stage = write_stage | state.read_stages;
+        *state = BufferState {
+            write: Some((stage, access)),
+            ..Default::default()
+        };
+        return (!src_stage.is_empty()).then(|| barrier(src_stage, write_access));
+    }
+
+    let is_synchronized = state.read_stages.contains(stage) && state.read_access.contains(access);
+    state.read_stages |= stage;
+    state.read_access |= access;
+    match state.write {
+        Some((write_stage, write_access)) if !is_synchronized => {
+            Some(barrier(write_stage, write_access))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,74 @@
+use crate::buffer::Buffer;
+use crate::rendering_context::Image;
+use ash::vk;
+use std::collections::HashMap;
+
+struct PendingWrite {
+    stage: vk::PipelineStageFlags2,
+    operation: &'static str,
+    name: String,
+}
+
+// Debug only bookkeeping of the buffers written by a command buffer, so obvious hazards panic
+// with the offending operations at record time instead of showing up as validation errors or
+// corrupted frames later. Barriers are assumed to cover whatever reads follow them.
+#[derive(Default)]
+pub struct ResourceTracker {
+    pending_buffer_writes: HashMap<vk::Buffer, PendingWrite>,
+}
+
+impl ResourceTracker {
+    pub fn write_buffer(
+        &mut self,
+        buffer: &Buffer,
+        stage: vk::PipelineStageFlags2,
+        operation: &'static str,
+    ) {
+        self.pending_buffer_writes.insert(
+            buffer.handle,
+            PendingWrite {
+                stage,
+                operation,
+                name: buffer.attributes.name.clone(),
+            },
+        );
+    }
+
+    pub fn read_buffer(
+        &self,
+        buffer: &Buffer,
+        stage: vk::PipelineStageFlags2,
+        operation: &'static str,
+    ) {
+        if let Some(write) = self.pending_buffer_writes.get(&buffer.handle) {
+            panic!(
+                "{operation} reads buffer {:?} at {stage:?} while it is still being written by \
+                 {} at {:?}, a buffer_memory_barrier is missing",
+                write.name, write.operation, write.stage
+            );
+        }
+    }
+
+    pub fn barrier_buffer(&mut self, buffer: &Buffer) {
+        self.pending_buffer_writes.remove(&buffer.handle);
+    }
+
+    pub fn sample_image(image: &Image, operation: &str) {
+        for state in image.layouts() {
+            let layout = state.layout;
+            assert!(
+            matches!(
+                layout,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                    | vk::ImageLayout::GENERAL
+                    | vk::ImageLayout::DEPTH_READ_ONLY_OPTIMAL
+                    | vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+                    | vk::ImageLayout::READ_ONLY_OPTIMAL
+            ),
+            "{operation} samples image {:?} while it is in {layout:?}, it must be transitioned to \
+             a shader readable layout first",
+            image.handle
+            );
+        }
+    }
+}
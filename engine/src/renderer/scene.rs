@@ -0,0 +1,246 @@
+use anyhow::{anyhow, Result};
+use nalgebra as na;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeHandle(usize);
+
+// What a node positions in the renderer, each follows the node's world transform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NodeAttachment {
+    Instance(usize),
+    PointLight(usize),
+    // Shines down the node's negative z axis.
+    SpotLight(usize),
+    // The main camera, looking down the node's negative z axis with y up. Only one node holds
+    // it at a time.
+    Camera,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    name: String,
+    parent: Option<NodeHandle>,
+    children: Vec<NodeHandle>,
+    local_transform: na::Affine3<f64>,
+    world_transform: na::Affine3<f64>,
+    // The local transform changed since the last update, its subtree needs new world transforms.
+    is_dirty: bool,
+    attachments: Vec<NodeAttachment>,
+}
+
+// Transform hierarchy the renderer places its instances, lights and camera with. World
+// transforms are only recomputed for the subtrees of changed nodes, once per frame.
+#[derive(Clone, Debug, Default)]
+pub struct Scene {
+    // Indexed by NodeHandle, removed nodes leave an empty slot behind.
+    nodes: Vec<Option<Node>>,
+    camera: Option<NodeHandle>,
+}
+
+impl Scene {
+    pub fn add_node(
+        &mut self,
+        name: &str,
+        parent: Option<NodeHandle>,
+        local_transform: na::Affine3<f64>,
+    ) -> NodeHandle {
+        let handle = NodeHandle(self.nodes.len());
+        self.nodes.push(Some(Node {
+            name: name.to_owned(),
+            parent,
+            children: Vec::new(),
+            local_transform,
+            world_transform: local_transform,
+            is_dirty: true,
+            attachments: Vec::new(),
+        }));
+        if let Some(parent) = parent {
+            self.node_mut(parent).children.push(handle);
+        }
+        handle
+    }
+
+    // Removes the node with its subtree, attached instances and lights keep their last
+    // transforms.
+    pub fn remove_node(&mut self, node: NodeHandle) {
+        if let Some(parent) = self.node(node).parent {
+            self.node_mut(parent)
+                .children
+                .retain(|&child| child != node);
+        }
+        let mut removed = vec![node];
+        while let Some(node) = removed.pop() {
+            let node_data = self.nodes[node.0].take().unwrap();
+            removed.extend(node_data.children);
+            if self.camera == Some(node) {
+                self.camera = None;
+            }
+        }
+    }
+
+    pub fn contains(&self, node: NodeHandle) -> bool {
+        self.nodes.get(node.0).is_some_and(Option::is_some)
+    }
+
+    // Keeps the local transform, so the node moves along with its new parent.
+    pub fn set_parent(&mut self, node: NodeHandle, parent: Option<NodeHandle>) -> Result<()> {
+        let mut ancestor = parent;
+        while let Some(handle) = ancestor {
+            if handle == node {
+                return Err(anyhow!(
+                    "Node {:?} can't become a descendant of itself",
+                    self.node(node).name
+                ));
+            }
+            ancestor = self.node(handle).parent;
+        }
+
+        if let Some(old_parent) = self.node(node).parent {
+            self.node_mut(old_parent)
+                .children
+                .retain(|&child| child != node);
+        }
+        if let Some(parent) = parent {
+            self.node_mut(parent).children.push(node);
+        }
+        let node = self.node_mut(node);
+        node.parent = parent;
+        node.is_dirty = true;
+        Ok(())
+    }
+
+    pub fn parent(&self, node: NodeHandle) -> Option<NodeHandle> {
+        self.node(node).parent
+    }
+
+    pub fn children(&self, node: NodeHandle) -> &[NodeHandle] {
+        &self.node(node).children
+    }
+
+    pub fn roots(&self) -> impl Iterator<Item = NodeHandle> + '_ {
+        self.nodes.iter().enumerate().filter_map(|(index, node)| {
+            node.as_ref()
+                .filter(|node| node.parent.is_none())
+                .map(|_| NodeHandle(index))
+        })
+    }
+
+    pub fn name(&self, node: NodeHandle) -> &str {
+        &self.node(node).name
+    }
+
+    // First node with the name, in creation order.
+    pub fn find(&self, name: &str) -> Option<NodeHandle> {
+        self.nodes
+            .iter()
+            .position(|node| node.as_ref().is_some_and(|node| node.name == name))
+            .map(NodeHandle)
+    }
+
+    pub fn local_transform(&self, node: NodeHandle) -> &na::Affine3<f64> {
+        &self.node(node).local_transform
+    }
+
+    pub fn set_local_transform(&mut self, node: NodeHandle, local_transform: na::Affine3<f64>) {
+        let node = self.node_mut(node);
+        node.local_transform = local_transform;
+        node.is_dirty = true;
+    }
+
+    // As of the last update, i.e. the last rendered frame.
+    pub fn world_transform(&self, node: NodeHandle) -> &na::Affine3<f64> {
+        &self.node(node).world_transform
+    }
+
+    // Takes the camera away from the node holding it.
+    pub fn attach(&mut self, node: NodeHandle, attachment: NodeAttachment) {
+        if attachment == NodeAttachment::Camera {
+            if let Some(camera) = self.camera.replace(node) {
+                self.node_mut(camera)
+                    .attachments
+                    .retain(|&attached| attached != NodeAttachment::Camera);
+            }
+        }
+        let node = self.node_mut(node);
+        if !node.attachments.contains(&attachment) {
+            node.attachments.push(attachment);
+        }
+        // The attachment is positioned in the next update even if nothing moved.
+        node.is_dirty = true;
+    }
+
+    pub fn detach(&mut self, node: NodeHandle, attachment: NodeAttachment) {
+        if attachment == NodeAttachment::Camera && self.camera == Some(node) {
+            self.camera = None;
+        }
+        self.node_mut(node)
+            .attachments
+            .retain(|&attached| attached != attachment);
+    }
+
+    pub fn attachments(&self, node: NodeHandle) -> &[NodeAttachment] {
+        &self.node(node).attachments
+    }
+
+    pub fn camera(&self) -> Option<NodeHandle> {
+        self.camera
+    }
+
+    // Recomputes the world transforms of changed subtrees and returns the attachments they
+    // moved with their new world transforms.
+    pub(crate) fn update(&mut self) -> Vec<(NodeAttachment, na::Affine3<f64>)> {
+        let mut moved = Vec::new();
+        let mut stack = self
+            .roots()
+            .map(|root| (root, na::Affine3::identity(), false))
+            .collect::<Vec<_>>();
+        while let Some((handle, parent_transform, is_parent_dirty)) = stack.pop() {
+            let node = self.node_mut(handle);
+            let is_dirty = node.is_dirty || is_parent_dirty;
+            if is_dirty {
+                node.world_transform = parent_transform * node.local_transform;
+                node.is_dirty = false;
+                moved.extend(
+                    node.attachments
+                        .iter()
+                        .map(|&attachment| (attachment, node.world_transform)),
+                );
+            }
+            let world_transform = node.world_transform;
+            stack.extend(
+                node.children
+                    .iter()
+                    .map(|&child| (child, world_transform, is_dirty)),
+            );
+        }
+        moved
+    }
+
+    // Keeps instance attachments pointing at the same instances after the renderer removed one,
+    // which moves the later instances down by one.
+    pub(crate) fn remove_instance(&mut self, instance: usize) {
+        for node in self.nodes.iter_mut().flatten() {
+            node.attachments
+                .retain(|&attached| attached != NodeAttachment::Instance(instance));
+            for attachment in &mut node.attachments {
+                if let NodeAttachment::Instance(attached) = attachment {
+                    if *attached > instance {
+                        *attached -= 1;
+                    }
+                }
+            }
+        }
+    }
+
+    fn node(&self, node: NodeHandle) -> &Node {
+        self.nodes[node.0]
+            .as_ref()
+            .unwrap_or_else(|| panic!("Node {node:?} doesn't exist"))
+    }
+
+    fn node_mut(&mut self, node: NodeHandle) -> &mut Node {
+        self.nodes[node.0]
+            .as_mut()
+            .unwrap_or_else(|| panic!("Node {node:?} doesn't exist"))
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+const SOURCES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/devres/shaders");
+
+#[derive(Clone, Debug)]
+pub struct ShaderReloadAttributes {
+    // GLSL sources recompiled with glslc into the watched SPIR-V files when they change. None
+    // only watches the SPIR-V files, e.g. ones rewritten by a cargo build in another terminal.
+    pub sources: Option<PathBuf>,
+    pub poll_interval: Duration,
+}
+
+impl Default for ShaderReloadAttributes {
+    fn default() -> Self {
+        Self {
+            sources: Some(SOURCES_DIR.into()),
+            poll_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn source_times(sources: &Path) -> HashMap<PathBuf, SystemTime> {
+    std::fs::read_dir(sources)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let modified = modified(&path)?;
+            Some((path, modified))
+        })
+        .collect()
+}
+
+// Polls modification times instead of subscribing to file system events, a few stat calls per
+// interval are cheap and work the same on every platform.
+pub(crate) struct ShaderWatcher {
+    attributes: ShaderReloadAttributes,
+    spirv: Vec<(PathBuf, Option<SystemTime>)>,
+    source_times: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+    // Set once glslc couldn't be started, sources aren't compiled from then on.
+    compiler_missing: bool,
+}
+
+impl ShaderWatcher {
+    pub fn new(attributes: ShaderReloadAttributes, spirv: &[&Path]) -> Self {
+        Self {
+            source_times: attributes
+                .sources
+                .as_deref()
+                .map(source_times)
+                .unwrap_or_default(),
+            attributes,
+            spirv: spirv
+                .iter()
+                .map(|path| (path.to_path_buf(), modified(path)))
+                .collect(),
+            last_poll: Instant::now(),
+            compiler_missing: false,
+        }
+    }
+
+    // True when a watched SPIR-V file changed since the last poll, after recompiling the
+    // sources that changed.
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < self.attributes.poll_interval {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        if let Some(sources) = self.attributes.sources.clone() {
+            let source_times = source_times(&sources);
+            if source_times != self.source_times {
+                self.source_times = source_times;
+                // Any change may be to an include, so every watched stage is rebuilt.
+                self.compile(&sources);
+            }
+        }
+
+        let mut changed = false;
+        for (path, last_modified) in &mut self.spirv {
+            let modified = modified(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    fn compile(&mut self, sources: &Path) {
+        if self.compiler_missing {
+            return;
+        }
+        for (spirv, _) in &self.spirv {
+            // shader.frag.spv is compiled from shader.frag.
+            let Some(source) = spirv.file_stem().map(|stem| sources.join(stem)) else {
+                continue;
+            };
+            if !source.exists() {
+                continue;
+            }
+            let output = Command::new("glslc")
+                .arg("--target-env=vulkan1.3")
+                .arg("-I")
+                .arg(sources)
+                .arg(&source)
+                .arg("-o")
+                .arg(spirv)
+                .output();
+            match output {
+                Ok(output) if output.status.success() => info!("Compiled {source:?}"),
+                Ok(output) => warn!(
+                    "Failed to compile {source:?}:\n{}",
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+                Err(error) => {
+                    warn!("Couldn't run glslc, only SPIR-V changes are reloaded: {error}");
+                    self.compiler_missing = true;
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::rendering_context::RenderingContext;
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use nalgebra as na;
+use std::sync::Arc;
+
+pub const INVALID_JOINT_OFFSET: u32 = u32::MAX;
+
+const INITIAL_JOINT_CAPACITY: usize = 256;
+
+// Attaches an instance to a joint of a skinned instance, e.g. a weapon to a hand socket.
+// The attached instance's own transform is then relative to the joint.
+#[derive(Clone, Copy, Debug)]
+pub struct JointAttachment {
+    pub parent: usize,
+    pub joint: usize,
+    // The joint's transform in the parent's model space at bind pose,
+    // i.e. the inverse of its inverse bind matrix.
+    pub bind_transform: na::Matrix4<f32>,
+}
+
+// Joint matrices are supplied per instance by the caller's animation system and packed
+// contiguously; the offset buffer maps each instance to its first joint.
+pub struct JointBuffer {
+    context: Arc<RenderingContext>,
+    instance_joints: Vec<Vec<na::Matrix4<f32>>>,
+    // One slot per frame in flight, each re-uploaded once the joints changed.
+    slots: Vec<JointSlot>,
+}
+
+struct JointSlot {
+    joint_buffer: Buffer,
+    joint_offset_buffer: Buffer,
+    is_dirty: bool,
+}
+
+fn create_joint_buffer(
+    context: Arc<RenderingContext>,
+    allocator: &mut Allocator,
+    capacity: usize,
+) -> Result<Buffer> {
+    Buffer::new(
+        allocator,
+        BufferAttributes {
+            name: "joint_buffer".into(),
+            context,
+            size: (capacity * size_of::<na::Matrix4<f32>>()) as vk::DeviceSize,
+            usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            location: MemoryLocation::CpuToGpu,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            allocation_priority: 1.0,
+        },
+    )
+}
+
+fn create_joint_offset_buffer(
+    context: Arc<RenderingContext>,
+    allocator: &mut Allocator,
+    instance_capacity: usize,
+) -> Result<Buffer> {
+    Buffer::new(
+        allocator,
+        BufferAttributes {
+            name: "joint_offset_buffer".into(),
+            context,
+            size: (instance_capacity.max(1) * size_of::<u32>()) as vk::DeviceSize,
+            usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            location: MemoryLocation::CpuToGpu,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            allocation_priority: 1.0,
+        },
+    )
+}
+
+impl JointSlot {
+    fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        instance_count: usize,
+    ) -> Result<Self> {
+        let joint_buffer = create_joint_buffer(context.clone(), allocator, INITIAL_JOINT_CAPACITY)?;
+        let joint_offset_buffer = create_joint_offset_buffer(context, allocator, instance_count)?;
+
+        Ok(Self {
+            joint_buffer,
+            joint_offset_buffer,
+            is_dirty: true,
+        })
+    }
+
+    fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        self.joint_buffer.destroy(allocator)?;
+        self.joint_offset_buffer.destroy(allocator)
+    }
+}
+
+impl JointBuffer {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        instance_count: usize,
+        buffering: usize,
+    ) -> Result<Self> {
+        let slots = (0..buffering)
+            .map(|_| JointSlot::new(context.clone(), allocator, instance_count))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            context,
+            instance_joints: vec![Vec::new(); instance_count],
+            slots,
+        })
+    }
+
+    pub fn set_joint_matrices(&mut self, instance: usize, joint_matrices: &[na::Matrix4<f32>]) {
+        let joints = &mut self.instance_joints[instance];
+        joints.clear();
+        joints.extend_from_slice(joint_matrices);
+        self.mark_dirty();
+    }
+
+    pub fn joint_matrices(&self, instance: usize) -> &[na::Matrix4<f32>] {
+        &self.instance_joints[instance]
+    }
+
+    // Starts out unskinned.
+    pub fn add_instance(&mut self) {
+        self.instance_joints.push(Vec::new());
+        self.mark_dirty();
+    }
+
+    // Later instances move down by one, like in the renderer.
+    pub fn remove_instance(&mut self, instance: usize) {
+        self.instance_joints.remove(instance);
+        self.mark_dirty();
+    }
+
+    fn mark_dirty(&mut self) {
+        for slot in &mut self.slots {
+            slot.is_dirty = true;
+        }
+    }
+
+    pub fn joint_buffer_address(&self, frame_index: usize) -> vk::DeviceAddress {
+        self.slots[frame_index].joint_buffer.address
+    }
+
+    pub fn joint_offset_buffer_address(&self, frame_index: usize) -> vk::DeviceAddress {
+        self.slots[frame_index].joint_offset_buffer.address
+    }
+
+    // Must be called after the frame's fence has been waited on.
+    pub fn upload(&mut self, allocator: &mut Allocator, frame_index: usize) -> Result<()> {
+        let slot = &mut self.slots[frame_index];
+        if !slot.is_dirty {
+            return Ok(());
+        }
+
+        let joint_count = self.instance_joints.iter().map(Vec::len).sum::<usize>();
+        let capacity = slot.joint_buffer.attributes.size as usize / size_of::<na::Matrix4<f32>>();
+
+        if joint_count > capacity {
+            slot.joint_buffer.destroy(allocator)?;
+            slot.joint_buffer = create_joint_buffer(
+                self.context.clone(),
+                allocator,
+                joint_count.next_power_of_two(),
+            )?;
+        }
+
+        let instance_capacity =
+            slot.joint_offset_buffer.attributes.size as usize / size_of::<u32>();
+        if self.instance_joints.len() > instance_capacity {
+            slot.joint_offset_buffer.destroy(allocator)?;
+            slot.joint_offset_buffer = create_joint_offset_buffer(
+                self.context.clone(),
+                allocator,
+                self.instance_joints.len().next_power_of_two(),
+            )?;
+        }
+
+        let mut joint_offsets = Vec::with_capacity(self.instance_joints.len());
+        let mut joint_offset = 0;
+        for joints in &self.instance_joints {
+            if joints.is_empty() {
+                joint_offsets.push(INVALID_JOINT_OFFSET);
+                continue;
+            }
+            slot.joint_buffer.write(
+                joints,
+                (joint_offset * size_of::<na::Matrix4<f32>>()) as vk::DeviceSize,
+            )?;
+            joint_offsets.push(joint_offset as u32);
+            joint_offset += joints.len();
+        }
+        slot.joint_offset_buffer.write(&joint_offsets, 0)?;
+
+        slot.is_dirty = false;
+        Ok(())
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for slot in &mut self.slots {
+            slot.destroy(allocator)?;
+        }
+        Ok(())
+    }
+}
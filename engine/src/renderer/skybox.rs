@@ -0,0 +1,234 @@
+use crate::image::Image;
+use crate::renderer::commands::Commands;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{GraphicsPipelineAttributes, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::Allocator;
+use std::sync::Arc;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyboxPushConstants {
+    camera_buffer_address: vk::DeviceAddress,
+    // cd/m² of a texel value of 1, see Lighting::sky_luminance.
+    luminance: f32,
+    padding: u32,
+}
+
+// Environment cube map drawn behind the scene, from a full screen triangle at the far plane so
+// only pixels no geometry covered pass the depth test.
+pub struct SkyboxRenderer {
+    context: Arc<RenderingContext>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    // One per frame in flight, rewritten once that frame's fence has been waited on.
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    frame_views: Vec<vk::ImageView>,
+    sampler: vk::Sampler,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    cubemap: Option<Image>,
+    // Replaced cube maps with the frame count from which no frame in flight samples them.
+    retired_cubemaps: Vec<(Image, u64)>,
+}
+
+impl SkyboxRenderer {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+        buffering: usize,
+    ) -> Result<Self> {
+        let vertex_shader =
+            load_shader_module(context.as_ref(), SHADERS_DIR.to_owned() + "skybox.vert.spv")?;
+        let fragment_shader =
+            load_shader_module(context.as_ref(), SHADERS_DIR.to_owned() + "skybox.frag.spv")?;
+
+        unsafe {
+            let descriptor_set_layout = context.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+                ]),
+                None,
+            )?;
+
+            let descriptor_pool = context.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(buffering as u32)
+                    .pool_sizes(&[vk::DescriptorPoolSize::default()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(buffering as u32)]),
+                None,
+            )?;
+
+            let descriptor_sets = context.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&vec![descriptor_set_layout; buffering]),
+            )?;
+
+            let sampler = context.device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .max_lod(vk::LOD_CLAMP_NONE),
+                None,
+            )?;
+
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_set_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                        .offset(0)
+                        .size(size_of::<SkyboxPushConstants>() as u32)]),
+                None,
+            )?;
+
+            let pipeline = context.create_graphics_pipeline(GraphicsPipelineAttributes {
+                vertex_shader,
+                fragment_shader,
+                extent,
+                color_format: format,
+                depth_format,
+                samples,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                line_width: 1.0,
+                dynamic_line_width: false,
+                dynamic_topology: false,
+                alpha_blending: false,
+                additive_blending: false,
+                depth_write: false,
+                cull_mode: vk::CullModeFlags::NONE,
+                viewport_count: 1,
+                vertex_layout: Default::default(),
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            })?;
+
+            context.device.destroy_shader_module(vertex_shader, None);
+            context.device.destroy_shader_module(fragment_shader, None);
+
+            Ok(Self {
+                context,
+                descriptor_set_layout,
+                descriptor_pool,
+                descriptor_sets,
+                frame_views: vec![vk::ImageView::null(); buffering],
+                sampler,
+                pipeline_layout,
+                pipeline,
+                cubemap: None,
+                retired_cubemaps: Vec::new(),
+            })
+        }
+    }
+
+    pub fn cubemap(&self) -> Option<&Image> {
+        self.cubemap.as_ref()
+    }
+
+    pub fn cubemap_mut(&mut self) -> Option<&mut Image> {
+        self.cubemap.as_mut()
+    }
+
+    // The previous cube map is destroyed once frame_count reaches retire_frame.
+    pub fn set_cubemap(&mut self, cubemap: Option<Image>, retire_frame: u64) {
+        if let Some(previous) = std::mem::replace(&mut self.cubemap, cubemap) {
+            self.retired_cubemaps.push((previous, retire_frame));
+        }
+    }
+
+    pub fn destroy_retired(&mut self, allocator: &mut Allocator, frame_count: u64) -> Result<()> {
+        let (retired, pending): (Vec<_>, Vec<_>) = self
+            .retired_cubemaps
+            .drain(..)
+            .partition(|(_, retire_frame)| *retire_frame <= frame_count);
+        self.retired_cubemaps = pending;
+        for (mut cubemap, _) in retired {
+            cubemap.destroy(allocator)?;
+        }
+        Ok(())
+    }
+
+    // Draws environment in place of the cube map when given one, does nothing without either.
+    // Expects the scene's depth to be bound.
+    pub fn draw(
+        &mut self,
+        commands: &Commands,
+        frame_index: usize,
+        camera_buffer_address: vk::DeviceAddress,
+        luminance: f32,
+        environment: Option<&Image>,
+    ) {
+        let Some(cubemap) = environment.or(self.cubemap.as_ref()) else {
+            return;
+        };
+        let descriptor_set = self.descriptor_sets[frame_index];
+        if self.frame_views[frame_index] != cubemap.view {
+            let image_info = [vk::DescriptorImageInfo::default()
+                .image_view(cubemap.view)
+                .sampler(self.sampler)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+            unsafe {
+                self.context.device.update_descriptor_sets(
+                    &[vk::WriteDescriptorSet::default()
+                        .dst_set(descriptor_set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&image_info)],
+                    &[],
+                );
+            }
+            self.frame_views[frame_index] = cubemap.view;
+        }
+
+        commands
+            .expect_sampled(cubemap, "skybox")
+            .bind_pipeline(self.pipeline)
+            .bind_descriptor_sets(self.pipeline_layout, &[descriptor_set])
+            .set_push_constants(
+                self.pipeline_layout,
+                SkyboxPushConstants {
+                    camera_buffer_address,
+                    luminance,
+                    padding: 0,
+                },
+            )
+            .draw(0..3, 0..1);
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for (mut cubemap, _) in self.retired_cubemaps.drain(..) {
+            cubemap.destroy(allocator)?;
+        }
+        if let Some(mut cubemap) = self.cubemap.take() {
+            cubemap.destroy(allocator)?;
+        }
+        unsafe {
+            self.context.device.destroy_pipeline(self.pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context.device.destroy_sampler(self.sampler, None);
+            self.context
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+use crate::renderer::commands::Commands;
+use crate::renderer::staging_belt::StagingBelt;
+use crate::renderer::swapchain::Swapchain;
+use crate::rendering_context::{Image, ImageAttributes, ImageLayoutState, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use nalgebra as na;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct SplashAttributes {
+    // Image centered above the progress bar, drawn without blending.
+    pub logo: Option<PathBuf>,
+    pub background_color: na::Vector4<f32>,
+    pub progress_color: na::Vector4<f32>,
+    pub track_color: na::Vector4<f32>,
+}
+
+impl Default for SplashAttributes {
+    fn default() -> Self {
+        Self {
+            logo: None,
+            background_color: na::Vector4::new(0.02, 0.02, 0.02, 1.0),
+            progress_color: na::Vector4::new(0.9, 0.9, 0.9, 1.0),
+            track_color: na::Vector4::new(0.15, 0.15, 0.15, 1.0),
+        }
+    }
+}
+
+fn clear_color(color: na::Vector4<f32>) -> vk::ClearColorValue {
+    vk::ClearColorValue {
+        float32: color.into(),
+    }
+}
+
+fn rect(x: f32, y: f32, width: f32, height: f32) -> vk::Rect2D {
+    vk::Rect2D {
+        offset: vk::Offset2D {
+            x: x as i32,
+            y: y as i32,
+        },
+        extent: vk::Extent2D {
+            width: (width as u32).max(1),
+            height: (height as u32).max(1),
+        },
+    }
+}
+
+// Presented straight to the swapchain while a window's renderer is created, without any
+// pipeline: the bar is cleared rectangles and the logo is blitted. Every present waits for the
+// GPU, loading blocks the thread anyway.
+pub(crate) struct SplashScreen {
+    context: Arc<RenderingContext>,
+    attributes: SplashAttributes,
+    logo: Option<Image>,
+    command_pool: vk::CommandPool,
+    image_available_semaphore: vk::Semaphore,
+    render_finished_semaphore: vk::Semaphore,
+    fence: vk::Fence,
+}
+
+impl SplashScreen {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        attributes: SplashAttributes,
+    ) -> Result<Self> {
+        let logo = attributes
+            .logo
+            .as_ref()
+            .map(|path| -> Result<Image> {
+                let pixels = ::image::ImageReader::open(path)?.decode()?.into_rgba8();
+                let mut logo = Image::new(
+                    context.clone(),
+                    allocator,
+                    "splash_logo",
+                    ImageAttributes {
+                        extent: vk::Extent3D {
+                            width: pixels.width(),
+                            height: pixels.height(),
+                            depth: 1,
+                        },
+                        format: vk::Format::R8G8B8A8_SRGB,
+                        usage: vk::ImageUsageFlags::TRANSFER_SRC
+                            | vk::ImageUsageFlags::TRANSFER_DST,
+                        location: MemoryLocation::GpuOnly,
+                        linear: false,
+                        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                        subresource_range: vk::ImageSubresourceRange::default()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .level_count(1)
+                            .layer_count(1),
+                        allocation_priority: 1.0,
+                        samples: vk::SampleCountFlags::TYPE_1,
+                        cube: false,
+                    },
+                )?;
+                let mut staging_belt =
+                    StagingBelt::new(context.clone(), allocator, pixels.len() as vk::DeviceSize)?;
+                context.immediate_submit(|commands| {
+                    staging_belt
+                        .write(pixels.as_raw())?
+                        .copy_image_to(&mut logo, commands)?
+                        .done();
+                    Ok(())
+                })?;
+                staging_belt.destroy(allocator)?;
+                Ok(logo)
+            })
+            .transpose()?;
+
+        unsafe {
+            let command_pool = context.device.create_command_pool(
+                &vk::CommandPoolCreateInfo::default()
+                    .queue_family_index(context.queue_families.graphics)
+                    .flags(vk::CommandPoolCreateFlags::TRANSIENT),
+                None,
+            )?;
+            let image_available_semaphore = context
+                .device
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?;
+            let render_finished_semaphore = context
+                .device
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?;
+            let fence = context
+                .device
+                .create_fence(&vk::FenceCreateInfo::default(), None)?;
+
+            Ok(Self {
+                context,
+                attributes,
+                logo,
+                command_pool,
+                image_available_semaphore,
+                render_finished_semaphore,
+                fence,
+            })
+        }
+    }
+
+    // Presents one frame with the progress in [0, 1] and waits for it.
+    pub fn present(&mut self, swapchain: &mut Swapchain, progress: f32) -> Result<()> {
+        if swapchain.is_dirty {
+            swapchain.resize()?;
+        }
+        let extent = swapchain.extent;
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(());
+        }
+
+        // Out of date, the next progress update recreates the swapchain.
+        let Some(image_index) = swapchain.acquire_next_image(self.image_available_semaphore)?
+        else {
+            return Ok(());
+        };
+
+        unsafe {
+            self.context
+                .device
+                .reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty())?;
+            let command_buffer = self.context.device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::default()
+                    .command_pool(self.command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1),
+            )?[0];
+            let commands = Commands::new(self.context.clone(), command_buffer)?;
+            let swapchain_image = &mut swapchain.images[image_index as usize];
+            self.record(&commands, swapchain_image, extent, progress.clamp(0.0, 1.0));
+            commands
+                .transition_image_layout(swapchain_image, ImageLayoutState::present())
+                .submit(
+                    self.context.queues[&self.context.queue_families.graphics],
+                    (
+                        self.image_available_semaphore,
+                        vk::PipelineStageFlags2::ALL_COMMANDS,
+                    ),
+                    (
+                        self.render_finished_semaphore,
+                        vk::PipelineStageFlags2::ALL_COMMANDS,
+                    ),
+                    self.fence,
+                )?;
+            swapchain.present(image_index, self.render_finished_semaphore, None)?;
+
+            self.context
+                .device
+                .wait_for_fences(&[self.fence], true, u64::MAX)?;
+            self.context.device.reset_fences(&[self.fence])?;
+            self.context
+                .device
+                .free_command_buffers(self.command_pool, &[command_buffer]);
+        }
+
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        commands: &Commands,
+        swapchain_image: &mut Image,
+        extent: vk::Extent2D,
+        progress: f32,
+    ) {
+        let (width, height) = (extent.width as f32, extent.height as f32);
+        let bar_width = width * 0.5;
+        let bar_height = (height * 0.01).max(4.0);
+        let bar_x = (width - bar_width) * 0.5;
+        let bar_y = height * 0.75;
+
+        commands
+            .begin_color_rendering(
+                swapchain_image,
+                Some(clear_color(self.attributes.background_color)),
+                vk::Rect2D::default().extent(extent),
+            )
+            .clear_color_rects(
+                clear_color(self.attributes.track_color),
+                &[rect(bar_x, bar_y, bar_width, bar_height)],
+            );
+        if progress > 0.0 {
+            commands.clear_color_rects(
+                clear_color(self.attributes.progress_color),
+                &[rect(bar_x, bar_y, bar_width * progress, bar_height)],
+            );
+        }
+        commands.end_rendering();
+
+        if let Some(logo) = &mut self.logo {
+            // Fitted into the area above the bar, keeping the logo's aspect ratio.
+            let logo_extent = logo.attributes.extent;
+            let scale = (width * 0.4 / logo_extent.width as f32)
+                .min(height * 0.4 / logo_extent.height as f32);
+            let logo_width = logo_extent.width as f32 * scale;
+            let logo_height = logo_extent.height as f32 * scale;
+            let x = ((width - logo_width) * 0.5) as i32;
+            let y = ((bar_y - logo_height) * 0.5) as i32;
+            commands.blit_image(
+                logo,
+                swapchain_image,
+                [
+                    vk::Offset3D::default(),
+                    vk::Offset3D {
+                        x: logo_extent.width as i32,
+                        y: logo_extent.height as i32,
+                        z: 1,
+                    },
+                ],
+                [
+                    vk::Offset3D { x, y, z: 0 },
+                    vk::Offset3D {
+                        x: x + logo_width as i32,
+                        y: y + logo_height as i32,
+                        z: 1,
+                    },
+                ],
+                vk::Filter::LINEAR,
+            );
+        }
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        if let Some(mut logo) = self.logo.take() {
+            logo.destroy(allocator)?;
+        }
+
+        unsafe {
+            self.context
+                .device
+                .destroy_semaphore(self.image_available_semaphore, None);
+            self.context
+                .device
+                .destroy_semaphore(self.render_finished_semaphore, None);
+            self.context.device.destroy_fence(self.fence, None);
+            self.context
+                .device
+                .destroy_command_pool(self.command_pool, None);
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 use crate::buffer::{Buffer, BufferAttributes};
 use crate::image::Image;
 use crate::renderer::commands::Commands;
-use crate::renderer::geometry::GPUGeometry;
+use crate::renderer::geometry::{GPUGeometry, QuantizedVertex, Vertex};
 use crate::rendering_context::RenderingContext;
 use anyhow::Result;
 use ash::vk;
@@ -40,6 +40,10 @@ impl StagingBelt {
         })
     }
 
+    pub fn capacity(&self) -> vk::DeviceSize {
+        self.buffer.attributes.size
+    }
+
     pub fn write<T: bytemuck::Pod>(&mut self, data: &[T]) -> Result<&mut Self> {
         let size = (data.len() * size_of::<T>()) as vk::DeviceSize;
         self.buffer.write(data, self.write_cursor)?;
@@ -47,17 +51,37 @@ impl StagingBelt {
         Ok(self)
     }
 
+    // Pads both cursors, copies into block compressed images need offsets that are a multiple
+    // of the block size.
+    pub fn align(&mut self, alignment: vk::DeviceSize) -> &mut Self {
+        self.write_cursor = self.write_cursor.next_multiple_of(alignment);
+        self.copy_cursor = self.copy_cursor.next_multiple_of(alignment);
+        self
+    }
+
     pub fn copy_to(&mut self, buffer: &Buffer, commands: &Commands) -> &mut Self {
         commands.copy_buffer(&self.buffer, buffer, self.copy_cursor);
         self.copy_cursor += buffer.attributes.size;
         self
     }
 
-    pub fn copy_image_to(&mut self, image: &mut Image, commands: &Commands) -> &mut Self {
+    pub fn copy_image_to(&mut self, image: &mut Image, commands: &Commands) -> Result<&mut Self> {
+        let size = image.size()?;
         commands.copy_buffer_to_image(&self.buffer, image, self.copy_cursor);
-        self.copy_cursor +=
-            (image.attributes.extent.width * image.attributes.extent.height * 4) as vk::DeviceSize;
-        self
+        self.copy_cursor += size;
+        Ok(self)
+    }
+
+    pub fn copy_image_level_to(
+        &mut self,
+        image: &mut Image,
+        level: u32,
+        commands: &Commands,
+    ) -> Result<&mut Self> {
+        let size = image.level_size(level)?;
+        commands.copy_buffer_to_image_level(&self.buffer, image, self.copy_cursor, level);
+        self.copy_cursor += size;
+        Ok(self)
     }
 
     pub fn stage_geometry(
@@ -65,11 +89,52 @@ impl StagingBelt {
         gpu_geometry: &GPUGeometry,
         commands: &Commands,
     ) -> Result<&mut Self> {
-        Ok(self
-            .write(&gpu_geometry.geometry.vertices)?
-            .copy_to(&gpu_geometry.vertex_buffer, commands)
+        if gpu_geometry.dequantization.is_some() {
+            self.write(&gpu_geometry.quantized_vertices)?;
+        } else {
+            self.write(&gpu_geometry.geometry.vertices)?;
+        }
+        self.copy_to(&gpu_geometry.vertex_buffer, commands)
             .write(&gpu_geometry.geometry.indices)?
-            .copy_to(&gpu_geometry.index_buffer, commands))
+            .copy_to(&gpu_geometry.index_buffer, commands);
+        if let Some(skin_buffer) = &gpu_geometry.skin_buffer {
+            self.write(&gpu_geometry.geometry.skin_vertices)?
+                .copy_to(skin_buffer, commands);
+        }
+        Ok(self)
+    }
+
+    // Only the vertices changed since the last upload, see GPUGeometry::update_vertices.
+    pub fn stage_dirty_vertices(
+        &mut self,
+        gpu_geometry: &mut GPUGeometry,
+        commands: &Commands,
+    ) -> Result<&mut Self> {
+        let Some(range) = gpu_geometry.dirty_vertices.take() else {
+            return Ok(self);
+        };
+        let (offset, size) = if gpu_geometry.dequantization.is_some() {
+            self.write(&gpu_geometry.quantized_vertices[range.clone()])?;
+            (
+                range.start * size_of::<QuantizedVertex>(),
+                range.len() * size_of::<QuantizedVertex>(),
+            )
+        } else {
+            self.write(&gpu_geometry.geometry.vertices[range.clone()])?;
+            (
+                range.start * size_of::<Vertex>(),
+                range.len() * size_of::<Vertex>(),
+            )
+        };
+        commands.copy_buffer_region(
+            &self.buffer,
+            &gpu_geometry.vertex_buffer,
+            self.copy_cursor,
+            offset as vk::DeviceSize,
+            size as vk::DeviceSize,
+        );
+        self.copy_cursor += size as vk::DeviceSize;
+        Ok(self)
     }
 
     pub fn done(&mut self) {
@@ -0,0 +1,291 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::image::{Image, ImageAttributes, ImageLayoutState};
+use crate::renderer::commands::Commands;
+use crate::renderer::point_lights::SHADOW_MAP_FORMATS;
+use crate::renderer::{load_shader_module, GPUCamera, SHADERS_DIR};
+use crate::rendering_context::{GraphicsPipelineAttributes, RenderingContext};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use nalgebra as na;
+use std::sync::Arc;
+
+// Casters up to this many times the shadow distance towards the sun still cast into the map.
+const CASTER_DISTANCE_SCALE: f32 = 4.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SunShadowAttributes {
+    // Width and height of the shadow map in texels.
+    pub resolution: u32,
+    // Half the side of the square around the camera that receives shadows, in meters.
+    pub distance: f32,
+}
+
+impl Default for SunShadowAttributes {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            distance: 30.0,
+        }
+    }
+}
+
+// Projection looking down -z with Vulkan's [0, 1] depth range, covering a square of twice the
+// half extent up to the far plane.
+fn orthographic_projection(half_extent: f32, far: f32) -> na::Matrix4<f32> {
+    #[rustfmt::skip]
+    let projection = na::Matrix4::new(
+        1.0 / half_extent, 0.0, 0.0, 0.0,
+        0.0, 1.0 / half_extent, 0.0, 0.0,
+        0.0, 0.0, -1.0 / far, 0.0,
+        0.0, 0.0, 0.0, 1.0,
+    );
+    projection
+}
+
+// Shadow of the sun, a single orthographic depth map following the camera. The map is moved in
+// whole texels so shadow edges don't shimmer as the camera moves. Without attributes the map is
+// a single texel that's never drawn, keeping the scene's descriptor valid.
+pub struct SunShadow {
+    context: Arc<RenderingContext>,
+    attributes: Option<SunShadowAttributes>,
+    shadow_map: Image,
+    // Per frame in flight, the sun's camera for the shadow pass.
+    camera_buffers: Vec<Buffer>,
+    pub sampler: vk::Sampler,
+    pipeline: vk::Pipeline,
+}
+
+impl SunShadow {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        commands: &Commands,
+        allocator: &mut Allocator,
+        pipeline_layout: vk::PipelineLayout,
+        attributes: Option<SunShadowAttributes>,
+        buffering: usize,
+    ) -> Result<Self> {
+        let format = context
+            .best_supported(
+                SHADOW_MAP_FORMATS,
+                vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+                    | vk::FormatFeatureFlags::SAMPLED_IMAGE
+                    | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+                vk::ImageTiling::OPTIMAL,
+            )
+            .ok_or_else(|| anyhow::anyhow!("No filterable depth format for shadow maps"))?;
+        let resolution = attributes.map_or(1, |attributes| attributes.resolution.max(1));
+        let extent = vk::Extent2D {
+            width: resolution,
+            height: resolution,
+        };
+
+        let mut shadow_map = Image::new(
+            context.clone(),
+            allocator,
+            "sun_shadow_map",
+            ImageAttributes {
+                extent: extent.into(),
+                format,
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                location: MemoryLocation::GpuOnly,
+                linear: false,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                subresource_range: vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                    .level_count(1)
+                    .layer_count(1),
+                allocation_priority: 1.0,
+                samples: vk::SampleCountFlags::TYPE_1,
+                cube: false,
+            },
+        )?;
+        commands.ensure_image_layout(&mut shadow_map, ImageLayoutState::shader_read());
+
+        let camera_buffers = (0..buffering)
+            .map(|_| {
+                Buffer::new(
+                    allocator,
+                    BufferAttributes {
+                        name: "sun_shadow_camera_buffer".into(),
+                        context: context.clone(),
+                        size: size_of::<GPUCamera>() as vk::DeviceSize,
+                        usage: vk::BufferUsageFlags::UNIFORM_BUFFER
+                            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                        location: MemoryLocation::CpuToGpu,
+                        allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                        allocation_priority: 1.0,
+                    },
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        unsafe {
+            // Outside the map everything is lit, see sunShadow in shader.frag.
+            let sampler = context.device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .compare_enable(true)
+                    .compare_op(vk::CompareOp::LESS_OR_EQUAL),
+                None,
+            )?;
+
+            let vertex_shader =
+                load_shader_module(context.as_ref(), SHADERS_DIR.to_owned() + "shadow.vert.spv")?;
+            let fragment_shader =
+                load_shader_module(context.as_ref(), SHADERS_DIR.to_owned() + "shadow.frag.spv")?;
+            let pipeline = context.create_graphics_pipeline(GraphicsPipelineAttributes {
+                vertex_shader,
+                fragment_shader,
+                extent,
+                color_format: vk::Format::UNDEFINED,
+                depth_format: format,
+                samples: vk::SampleCountFlags::TYPE_1,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                line_width: 1.0,
+                dynamic_line_width: false,
+                dynamic_topology: false,
+                alpha_blending: false,
+                additive_blending: false,
+                depth_write: true,
+                cull_mode: vk::CullModeFlags::NONE,
+                viewport_count: 1,
+                vertex_layout: Default::default(),
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            })?;
+            context.device.destroy_shader_module(vertex_shader, None);
+            context.device.destroy_shader_module(fragment_shader, None);
+
+            Ok(Self {
+                context,
+                attributes,
+                shadow_map,
+                camera_buffers,
+                sampler,
+                pipeline,
+            })
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.attributes.is_some()
+    }
+
+    pub fn shadow_map(&self) -> &Image {
+        &self.shadow_map
+    }
+
+    // World space size of a shadow map texel, zero when disabled.
+    pub fn texel_size(&self) -> f32 {
+        self.attributes.map_or(0.0, |attributes| {
+            attributes.distance * 2.0 / attributes.resolution as f32
+        })
+    }
+
+    // Must be called after the frame's fence has been waited on. Returns the matrix from camera
+    // relative positions to the shadow map's clip space, identity when disabled.
+    pub fn upload(
+        &mut self,
+        frame_index: usize,
+        camera_position: &na::Point3<f64>,
+        // Direction the sunlight travels in, see Lighting.
+        sun_direction: &na::Vector3<f32>,
+    ) -> Result<na::Matrix4<f32>> {
+        let Some(attributes) = self.attributes else {
+            return Ok(na::Matrix4::identity());
+        };
+        let direction = sun_direction
+            .try_normalize(f32::EPSILON)
+            .unwrap_or(-na::Vector3::y())
+            .cast::<f64>();
+        let up = if direction.y.abs() > 0.99 {
+            na::Vector3::z()
+        } else {
+            na::Vector3::y()
+        };
+
+        // Snaps the camera position to the texel grid in the sun's view, in world space so the
+        // grid doesn't move with the camera.
+        let rotation = na::Rotation3::look_at_rh(&direction, &up);
+        let texel_size = self.texel_size() as f64;
+        let snapped = (rotation * camera_position.coords)
+            .map(|coordinate| (coordinate / texel_size).floor() * texel_size);
+        let center = rotation.inverse() * snapped - camera_position.coords;
+
+        let distance = attributes.distance * CASTER_DISTANCE_SCALE;
+        let eye = na::Point3::from(center - direction * distance as f64);
+        let view = na::Isometry3::look_at_rh(&eye, &center.into(), &up);
+        let projection = orthographic_projection(attributes.distance, distance * 2.0);
+
+        self.camera_buffers[frame_index].write(
+            &[GPUCamera {
+                view: view.to_homogeneous().cast(),
+                projection,
+                position: eye.coords.cast(),
+                origin: (camera_position.coords + eye.coords).cast(),
+                exposure: 1.0,
+                // Shadow passes only write depth, lighting isn't needed.
+                ..bytemuck::Zeroable::zeroed()
+            }],
+            0,
+        )?;
+        Ok(projection * view.to_homogeneous().cast())
+    }
+
+    // Renders the casters into the shadow map and leaves it ready to be sampled by the scene
+    // pass, does nothing when disabled.
+    pub fn draw(
+        &mut self,
+        commands: &Commands,
+        frame_index: usize,
+        // Called with the address of the sun's camera.
+        draw_instances: impl Fn(&Commands, vk::DeviceAddress),
+    ) {
+        if self.attributes.is_none() {
+            return;
+        }
+        let extent = vk::Extent2D {
+            width: self.shadow_map.attributes.extent.width,
+            height: self.shadow_map.attributes.extent.height,
+        };
+        let view = self.shadow_map.view;
+        commands
+            .begin_depth_rendering(
+                &mut self.shadow_map,
+                view,
+                0,
+                vk::Rect2D::default().extent(extent),
+                true,
+            )
+            .set_viewport(
+                vk::Viewport::default()
+                    .width(extent.width as f32)
+                    .height(extent.height as f32)
+                    .max_depth(1.0),
+            )
+            .set_scissor(vk::Rect2D::default().extent(extent))
+            .bind_pipeline(self.pipeline);
+        draw_instances(commands, self.camera_buffers[frame_index].address);
+        commands
+            .end_rendering()
+            .ensure_image_layout(&mut self.shadow_map, ImageLayoutState::shader_read());
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for buffer in &mut self.camera_buffers {
+            buffer.destroy(allocator)?;
+        }
+        self.shadow_map.destroy(allocator)?;
+        unsafe {
+            self.context.device.destroy_sampler(self.sampler, None);
+            self.context.device.destroy_pipeline(self.pipeline, None);
+        }
+        Ok(())
+    }
+}
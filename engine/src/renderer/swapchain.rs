@@ -1,3 +1,4 @@
+use crate::renderer::commands::Commands;
 use crate::rendering_context::{Image, ImageAttributes, RenderingContext, Surface};
 use anyhow::Result;
 use ash::vk;
@@ -5,23 +6,92 @@ use ash::vk::AcquireNextImageInfoKHR;
 use gpu_allocator::vulkan::AllocationScheme;
 use gpu_allocator::MemoryLocation;
 use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
 use winit::window::Window;
 
+// The present queue's half of handing an image over from the graphics queue family, see
+// Commands::release_image_ownership. Re-recorded with every present of its image, which can
+// only be acquired again once the previous present, and so this submission, is done.
+struct OwnershipTransfer {
+    command_buffer: vk::CommandBuffer,
+    // Signaled once the present queue owns the image, waited on by the present.
+    semaphore: vk::Semaphore,
+}
+
+// A replaced swapchain, kept alive until the frames that presented from it have finished.
+struct RetiredSwapchain {
+    handle: vk::SwapchainKHR,
+    images: Vec<Image>,
+    ownership_transfers: Vec<OwnershipTransfer>,
+    frames_left: usize,
+}
+
 pub struct Swapchain {
     pub desired_image_count: u32,
     pub format: vk::Format,
     pub extent: vk::Extent2D,
     pub images: Vec<Image>,
     handle: vk::SwapchainKHR,
+    retired: Vec<RetiredSwapchain>,
+    // Only when the present queue family differs from the graphics one.
+    present_command_pool: Option<vk::CommandPool>,
+    // Per image, empty without a present command pool.
+    ownership_transfers: Vec<OwnershipTransfer>,
+    in_flight_frames_count: usize,
     surface: Surface,
     window: Arc<Window>,
     context: Arc<RenderingContext>,
     pub is_dirty: bool,
 }
 
+// Surface of the window the present queue family can present to.
+fn create_present_surface(context: &RenderingContext, window: &Window) -> Result<Surface> {
+    let surface = unsafe { context.create_surface(window)? };
+    let present = context.queue_families.present;
+    let supports_present = unsafe {
+        context
+            .surface_extension
+            .get_physical_device_surface_support(
+                context.physical_device.handle,
+                present,
+                surface.handle,
+            )
+    };
+    if supports_present != Ok(true) {
+        unsafe {
+            context
+                .surface_extension
+                .destroy_surface(surface.handle, None);
+        }
+        return match supports_present {
+            Err(error) => Err(error.into()),
+            _ => Err(anyhow::anyhow!(
+                "Queue family {present} can't present to the window"
+            )),
+        };
+    }
+    Ok(surface)
+}
+
 impl Swapchain {
-    pub fn new(context: Arc<RenderingContext>, window: Arc<Window>) -> Result<Self> {
-        let surface = unsafe { context.create_surface(window.as_ref())? };
+    pub fn new(
+        context: Arc<RenderingContext>,
+        window: Arc<Window>,
+        in_flight_frames_count: usize,
+    ) -> Result<Self> {
+        let surface = create_present_surface(&context, &window)?;
+        let queue_families = &context.queue_families;
+        let present_command_pool = (queue_families.present != queue_families.graphics)
+            .then(|| unsafe {
+                context.device.create_command_pool(
+                    &vk::CommandPoolCreateInfo::default()
+                        .queue_family_index(queue_families.present)
+                        .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER),
+                    None,
+                )
+            })
+            .transpose()?;
         let format = vk::Format::B8G8R8A8_SRGB;
         let extent = if surface.capabilities.current_extent.width != u32::MAX {
             surface.capabilities.current_extent
@@ -47,6 +117,10 @@ impl Swapchain {
             extent,
             images: Default::default(),
             handle: Default::default(),
+            retired: Vec::new(),
+            present_command_pool,
+            ownership_transfers: Vec::new(),
+            in_flight_frames_count,
             surface,
             window,
             context,
@@ -54,6 +128,24 @@ impl Swapchain {
         })
     }
 
+    // Also copied from for frame captures where the surface allows it.
+    fn image_usage(&self) -> vk::ImageUsageFlags {
+        vk::ImageUsageFlags::TRANSFER_DST
+            | vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | (self.surface.capabilities.supported_usage_flags & vk::ImageUsageFlags::TRANSFER_SRC)
+    }
+
+    // Whether the presented images can be blitted into a capture.
+    pub fn supports_capture(&self) -> bool {
+        self.image_usage()
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+            && self.context.supports_format(
+                self.format,
+                vk::FormatFeatureFlags::BLIT_SRC,
+                vk::ImageTiling::OPTIMAL,
+            )
+    }
+
     pub fn resize(&mut self) -> Result<()> {
         let size = self.window.inner_size();
         self.extent = vk::Extent2D {
@@ -66,6 +158,7 @@ impl Swapchain {
         }
 
         self.is_dirty = false;
+        let usage = self.image_usage();
 
         unsafe {
             let new_swapchain = self.context.swapchain_extension.create_swapchain(
@@ -76,9 +169,7 @@ impl Swapchain {
                     .image_color_space(vk::ColorSpaceKHR::SRGB_NONLINEAR)
                     .image_extent(self.extent)
                     .image_array_layers(1)
-                    .image_usage(
-                        vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::COLOR_ATTACHMENT,
-                    )
+                    .image_usage(usage)
                     .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
                     .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
                     .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
@@ -86,13 +177,21 @@ impl Swapchain {
                     .clipped(true)
                     .old_swapchain(self.handle),
                 None,
-            )?;
-            self.images.drain(..).for_each(|image| {
-                self.context.device.destroy_image_view(image.view, None);
-            });
-            self.context
-                .swapchain_extension
-                .destroy_swapchain(self.handle, None);
+            );
+            let new_swapchain = match new_swapchain {
+                Ok(new_swapchain) => new_swapchain,
+                // Recreated again with the new surface before the next image is acquired.
+                Err(vk::Result::ERROR_SURFACE_LOST_KHR) => return self.recreate_surface(),
+                Err(error) => return Err(error.into()),
+            };
+            if self.handle != vk::SwapchainKHR::null() {
+                self.retired.push(RetiredSwapchain {
+                    handle: self.handle,
+                    images: std::mem::take(&mut self.images),
+                    ownership_transfers: std::mem::take(&mut self.ownership_transfers),
+                    frames_left: self.in_flight_frames_count,
+                });
+            }
 
             self.handle = new_swapchain;
             self.images = self
@@ -107,8 +206,7 @@ impl Swapchain {
                         ImageAttributes {
                             format: self.format,
                             extent: self.extent.into(),
-                            usage: vk::ImageUsageFlags::TRANSFER_DST
-                                | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                            usage,
                             location: MemoryLocation::Unknown,
                             linear: false,
                             allocation_scheme: AllocationScheme::GpuAllocatorManaged,
@@ -118,16 +216,90 @@ impl Swapchain {
                                 .layer_count(1),
                             allocation_priority: 1.0,
                             samples: Default::default(),
+                            cube: false,
                         },
                     )?)
                 })
                 .collect::<Result<Vec<_>>>()?;
+
+            if let Some(command_pool) = self.present_command_pool {
+                let command_buffers = self.context.device.allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo::default()
+                        .command_pool(command_pool)
+                        .level(vk::CommandBufferLevel::PRIMARY)
+                        .command_buffer_count(self.images.len() as u32),
+                )?;
+                self.ownership_transfers = command_buffers
+                    .into_iter()
+                    .map(|command_buffer| {
+                        Ok(OwnershipTransfer {
+                            command_buffer,
+                            semaphore: self
+                                .context
+                                .device
+                                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+            }
         }
         Ok(())
     }
 
-    pub fn acquire_next_image(&mut self, image_available_semaphore: vk::Semaphore) -> Result<u32> {
-        let (image_index, is_suboptimal) = unsafe {
+    // Must be called once per frame after its fence has been waited on; once every frame in
+    // flight has been waited on, nothing can still use the retired swapchains.
+    pub fn collect_retired(&mut self) {
+        for retired in &mut self.retired {
+            retired.frames_left = retired.frames_left.saturating_sub(1);
+        }
+        let (done, retired) = std::mem::take(&mut self.retired)
+            .into_iter()
+            .partition(|retired| retired.frames_left == 0);
+        self.retired = retired;
+        for retired in done {
+            self.destroy_swapchain(retired.handle, retired.images, retired.ownership_transfers);
+        }
+    }
+
+    fn destroy_swapchain(
+        &self,
+        handle: vk::SwapchainKHR,
+        images: Vec<Image>,
+        ownership_transfers: Vec<OwnershipTransfer>,
+    ) {
+        unsafe {
+            images.into_iter().for_each(|image| {
+                self.context.device.destroy_image_view(image.view, None);
+            });
+            if let Some(command_pool) = self.present_command_pool {
+                for transfer in ownership_transfers {
+                    self.context
+                        .device
+                        .free_command_buffers(command_pool, &[transfer.command_buffer]);
+                    self.context
+                        .device
+                        .destroy_semaphore(transfer.semaphore, None);
+                }
+            }
+            self.context
+                .swapchain_extension
+                .destroy_swapchain(handle, None);
+        }
+    }
+
+    // None when the swapchain is out of date or its surface was lost and it has to be recreated
+    // first, the semaphore is then left unsignaled and can be used for the next attempt. A
+    // suboptimal image is still returned, with the swapchain marked for recreation before the
+    // next frame.
+    pub fn acquire_next_image(
+        &mut self,
+        image_available_semaphore: vk::Semaphore,
+    ) -> Result<Option<u32>> {
+        if self.handle == vk::SwapchainKHR::null() {
+            self.is_dirty = true;
+            return Ok(None);
+        }
+        let result = unsafe {
             self.context.swapchain_extension.acquire_next_image2(
                 &AcquireNextImageInfoKHR::default()
                     .swapchain(self.handle)
@@ -135,29 +307,130 @@ impl Swapchain {
                     .semaphore(image_available_semaphore)
                     .fence(vk::Fence::null())
                     .device_mask(1),
-            )?
+            )
+        };
+        let (image_index, is_suboptimal) = match result {
+            Ok(acquired) => acquired,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.is_dirty = true;
+                return Ok(None);
+            }
+            Err(vk::Result::ERROR_SURFACE_LOST_KHR) => {
+                self.recreate_surface()?;
+                return Ok(None);
+            }
+            Err(error) => return Err(error.into()),
         };
         if is_suboptimal {
             self.is_dirty = true;
         }
-        Ok(image_index)
+        // Presented images stay owned by the present queue family, their contents are discarded
+        // instead of transferred back since frames overwrite them anyway.
+        if self.present_command_pool.is_some() {
+            self.images[image_index as usize].reset_layout();
+        }
+        Ok(Some(image_index))
+    }
+
+    // Replaces a lost surface with a new one of the same window, e.g. after the compositor or
+    // display server restarted, and marks the swapchain for recreation. Swapchains of the lost
+    // surface have to be destroyed first, which waits for the device to idle.
+    fn recreate_surface(&mut self) -> Result<()> {
+        warn!("Surface lost, recreating it");
+        unsafe {
+            self.context.device.device_wait_idle()?;
+        }
+        for retired in std::mem::take(&mut self.retired) {
+            self.destroy_swapchain(retired.handle, retired.images, retired.ownership_transfers);
+        }
+        if self.handle != vk::SwapchainKHR::null() {
+            let images = std::mem::take(&mut self.images);
+            let ownership_transfers = std::mem::take(&mut self.ownership_transfers);
+            self.destroy_swapchain(self.handle, images, ownership_transfers);
+            self.handle = vk::SwapchainKHR::null();
+        }
+        unsafe {
+            self.context
+                .surface_extension
+                .destroy_surface(self.surface.handle, None);
+        }
+        // Dropping the swapchain destroys the surface again, so it must never hold the lost one.
+        self.surface.handle = vk::SurfaceKHR::null();
+        self.surface = create_present_surface(&self.context, &self.window)?;
+        self.is_dirty = true;
+        Ok(())
     }
 
+    // Records the graphics queue's half of handing the image over to the present queue family,
+    // after its last use in the frame. Does nothing when both are the same family.
+    pub fn release_image(&self, commands: &Commands, image_index: u32) {
+        if self.present_command_pool.is_none() {
+            return;
+        }
+        let queue_families = &self.context.queue_families;
+        commands.release_image_ownership(
+            &self.images[image_index as usize],
+            queue_families.graphics,
+            queue_families.present,
+        );
+    }
+
+    // The present time is only passed on with the display timing extension, its id shows up in
+    // past_presentation_timing once the image reached the display.
     pub fn present(
         &mut self,
         image_index: u32,
         render_finished_semaphore: vk::Semaphore,
+        present_time: Option<vk::PresentTimeGOOGLE>,
     ) -> Result<()> {
+        let queue_families = &self.context.queue_families;
+        let present_queue = self.context.queues[&queue_families.present];
+        let wait_semaphore = match self.ownership_transfers.get(image_index as usize) {
+            Some(transfer) => {
+                Commands::new(self.context.clone(), transfer.command_buffer)?
+                    .acquire_image_ownership(
+                        &self.images[image_index as usize],
+                        queue_families.graphics,
+                        queue_families.present,
+                    )
+                    .submit(
+                        present_queue,
+                        (
+                            render_finished_semaphore,
+                            vk::PipelineStageFlags2::ALL_COMMANDS,
+                        ),
+                        (transfer.semaphore, vk::PipelineStageFlags2::ALL_COMMANDS),
+                        vk::Fence::null(),
+                    )?;
+                transfer.semaphore
+            }
+            None => render_finished_semaphore,
+        };
+
+        let present_times = present_time
+            .filter(|_| self.context.display_timing_extension.is_some())
+            .map(|present_time| [present_time]);
+        let mut present_times_info = vk::PresentTimesInfoGOOGLE::default();
+        let mut present_info = vk::PresentInfoKHR::default()
+            .wait_semaphores(std::slice::from_ref(&wait_semaphore))
+            .swapchains(std::slice::from_ref(&self.handle))
+            .image_indices(std::slice::from_ref(&image_index));
+        if let Some(present_times) = &present_times {
+            present_times_info = present_times_info.times(present_times);
+            present_info = present_info.push_next(&mut present_times_info);
+        }
         let is_suboptimal = unsafe {
-            match self.context.swapchain_extension.queue_present(
-                self.context.queues[self.context.queue_families.present as usize],
-                &vk::PresentInfoKHR::default()
-                    .wait_semaphores(&[render_finished_semaphore])
-                    .swapchains(&[self.handle])
-                    .image_indices(&[image_index]),
-            ) {
+            match self
+                .context
+                .swapchain_extension
+                .queue_present(present_queue, &present_info)
+            {
                 Ok(is_suboptimal) => is_suboptimal,
                 Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => true,
+                Err(vk::Result::ERROR_SURFACE_LOST_KHR) => {
+                    self.recreate_surface()?;
+                    true
+                }
                 Err(error) => return Err(error.into()),
             }
         };
@@ -166,17 +439,38 @@ impl Swapchain {
         }
         Ok(())
     }
+
+    // Timings of images presented with an id since the last call, empty without the display
+    // timing extension.
+    pub fn past_presentation_timing(&self) -> Vec<vk::PastPresentationTimingGOOGLE> {
+        let Some(display_timing) = &self.context.display_timing_extension else {
+            return Vec::new();
+        };
+        unsafe { display_timing.get_past_presentation_timing(self.handle) }.unwrap_or_default()
+    }
+
+    // Duration of a refresh of the display the swapchain presents to, if the display timing
+    // extension knows it.
+    pub fn refresh_cycle_duration(&self) -> Option<Duration> {
+        let display_timing = self.context.display_timing_extension.as_ref()?;
+        let refresh_cycle =
+            unsafe { display_timing.get_refresh_cycle_duration(self.handle) }.ok()?;
+        Some(Duration::from_nanos(refresh_cycle.refresh_duration))
+    }
 }
 
 impl Drop for Swapchain {
     fn drop(&mut self) {
+        for retired in std::mem::take(&mut self.retired) {
+            self.destroy_swapchain(retired.handle, retired.images, retired.ownership_transfers);
+        }
+        let images = std::mem::take(&mut self.images);
+        let ownership_transfers = std::mem::take(&mut self.ownership_transfers);
+        self.destroy_swapchain(self.handle, images, ownership_transfers);
         unsafe {
-            self.images.drain(..).for_each(|image| {
-                self.context.device.destroy_image_view(image.view, None);
-            });
-            self.context
-                .swapchain_extension
-                .destroy_swapchain(self.handle, None);
+            if let Some(command_pool) = self.present_command_pool {
+                self.context.device.destroy_command_pool(command_pool, None);
+            }
             self.context
                 .surface_extension
                 .destroy_surface(self.surface.handle, None);
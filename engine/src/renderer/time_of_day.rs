@@ -0,0 +1,125 @@
+use crate::renderer::lighting::Lighting;
+use crate::renderer::procedural_sky::ProceduralSkyAttributes;
+use nalgebra as na;
+use std::f32::consts::PI;
+use std::time::Duration;
+
+// Illuminance of sunlight above the atmosphere, in lux.
+const EXTRATERRESTRIAL_ILLUMINANCE: f32 = 128_000.0;
+// Of the clear atmosphere per air mass, about 100000 lux remain with the sun overhead.
+const EXTINCTION: f32 = 0.21;
+// Per color channel, blue is scattered out of the direct light the most.
+const COLOR_EXTINCTION: [f32; 3] = [0.08, 0.14, 0.28];
+// The sky keeps some light until the sun is this far below the horizon, civil twilight.
+const TWILIGHT_ELEVATION: f32 = -6.0 * PI / 180.0;
+// Of a clear moonless night sky, in cd/m².
+const NIGHT_SKY_LUMINANCE: f32 = 0.001;
+
+// Air mass the sunlight crosses at an elevation in radians, after Kasten and Young.
+fn air_mass(elevation: f32) -> f32 {
+    let degrees = elevation.max(0.0).to_degrees();
+    1.0 / (elevation.max(0.0).sin() + 0.50572 * (degrees + 6.07995).powf(-1.6364))
+}
+
+// Zenith luminance of a clear sky in cd/m² from Preetham's model, for a sun above the horizon.
+fn zenith_luminance(sun_zenith_angle: f32, turbidity: f32) -> f32 {
+    let chi = (4.0 / 9.0 - turbidity / 120.0) * (PI - 2.0 * sun_zenith_angle);
+    ((4.0453 * turbidity - 4.9710) * chi.tan() - 0.2155 * turbidity + 2.4192) * 1000.0
+}
+
+// Day cycle driving the sun and the procedural sky: the hour sets where the sun is, and from
+// how high it stands follow the sunlight's illuminance and color and the sky's luminance. The
+// sun moves as on an equinox, rising in the east at 6, highest at noon and setting in the west
+// at 18. Gameplay can set the hour at any time, e.g. when sleeping through the night.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeOfDay {
+    // From 0 to 24.
+    hour: f32,
+    // Real time a whole day takes at a time scale of 1.
+    pub day_length: Duration,
+    // Keeps the hour still while the rest of the frame time goes on.
+    pub paused: bool,
+    // In radians, the noon sun is this far south of the zenith.
+    pub latitude: f32,
+    pub sky: ProceduralSkyAttributes,
+}
+
+impl Default for TimeOfDay {
+    // Mid morning, with an hour of the day per real minute.
+    fn default() -> Self {
+        Self {
+            hour: 9.0,
+            day_length: Duration::from_secs(24 * 60),
+            paused: false,
+            latitude: 45.0_f32.to_radians(),
+            sky: ProceduralSkyAttributes::default(),
+        }
+    }
+}
+
+impl TimeOfDay {
+    pub fn new(hour: f32) -> Self {
+        let mut time_of_day = Self::default();
+        time_of_day.set_hour(hour);
+        time_of_day
+    }
+
+    pub fn hour(&self) -> f32 {
+        self.hour
+    }
+
+    // Wraps around midnight.
+    pub fn set_hour(&mut self, hour: f32) {
+        self.hour = hour.rem_euclid(24.0);
+    }
+
+    // Moves the hour on by the part of the day length that delta is.
+    pub fn advance(&mut self, delta: Duration) {
+        if self.paused || self.day_length.is_zero() {
+            return;
+        }
+        self.set_hour(self.hour + 24.0 * delta.as_secs_f32() / self.day_length.as_secs_f32());
+    }
+
+    // Towards the sun, with -y up like the rest of the scene, east along +x and north along -z.
+    pub fn sun_direction(&self) -> na::Vector3<f32> {
+        let hour_angle = (self.hour - 12.0) / 24.0 * 2.0 * PI;
+        let east = -hour_angle.sin();
+        let north = -self.latitude.sin() * hour_angle.cos();
+        let up = self.latitude.cos() * hour_angle.cos();
+        na::Vector3::new(east, -up, -north)
+    }
+
+    // Of the sun above the horizon in radians, negative at night.
+    pub fn sun_elevation(&self) -> f32 {
+        (-self.sun_direction().y).clamp(-1.0, 1.0).asin()
+    }
+
+    pub fn is_day(&self) -> bool {
+        self.sun_elevation() > 0.0
+    }
+
+    // Sets the sun and the sky's luminance, keeping the rest of the lighting.
+    pub fn apply(&self, lighting: &mut Lighting) {
+        let elevation = self.sun_elevation();
+        lighting.sun_direction = -self.sun_direction();
+
+        if elevation > 0.0 {
+            let air_mass = air_mass(elevation);
+            lighting.sun_illuminance =
+                EXTRATERRESTRIAL_ILLUMINANCE * (-EXTINCTION * air_mass).exp();
+            let color = na::Vector3::from(COLOR_EXTINCTION).map(|k| (-k * air_mass).exp());
+            lighting.sun_color = color / color.max();
+        } else {
+            lighting.sun_illuminance = 0.0;
+        }
+
+        // The sky dims through twilight from its luminance at sunset.
+        let twilight = ((elevation - TWILIGHT_ELEVATION) / -TWILIGHT_ELEVATION).clamp(0.0, 1.0);
+        let sun_zenith_angle = (PI / 2.0 - elevation).min(PI / 2.0);
+        let sky_luminance = zenith_luminance(sun_zenith_angle, self.sky.turbidity);
+        lighting.sky_luminance = (sky_luminance * twilight * twilight).max(NIGHT_SKY_LUMINANCE);
+        // A uniform sky of that luminance over the hemisphere.
+        lighting.ambient_illuminance = PI * lighting.sky_luminance;
+    }
+}
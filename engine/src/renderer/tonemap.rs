@@ -0,0 +1,442 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::renderer::commands::Commands;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{
+    ComputePipelineAttributes, GraphicsPipelineAttributes, Image, RenderingContext,
+};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use std::sync::Arc;
+
+// Must match auto_exposure.glsl.
+const EXPOSURE_HISTOGRAM_BIN_COUNT: usize = 256;
+const HISTOGRAM_WORKGROUP_SIZE: u32 = 16;
+
+// How the exposed HDR scene is brought into the swapchain's range, must match tonemap.frag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TonemapOperator {
+    // Clips everything above 1, for comparing against the untouched scene.
+    None,
+    // Of the luminance, soft and keeps saturated colors.
+    Reinhard,
+    // Filmic curve with more contrast and highlights rolling off to white.
+    #[default]
+    Aces,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoExposureAttributes {
+    // Log2 luminance range the histogram covers, the average is clamped to it.
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    // Luminance the scene's average is exposed to, middle gray.
+    pub key: f32,
+    // How quickly the exposure follows the scene, per second; eyes adapt in a few seconds.
+    pub adaptation_rate: f32,
+}
+
+impl Default for AutoExposureAttributes {
+    fn default() -> Self {
+        Self {
+            min_log_luminance: -10.0,
+            max_log_luminance: 6.0,
+            key: 0.18,
+            adaptation_rate: 1.5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TonemapAttributes {
+    pub operator: TonemapOperator,
+    // In stops on top of the camera's exposure and the automatic one, 1 doubles the brightness.
+    pub exposure_compensation: f32,
+    // Measures the scene's luminance on the GPU every frame and adapts the exposure to it,
+    // None leaves the exposure to the camera.
+    pub auto_exposure: Option<AutoExposureAttributes>,
+}
+
+// Same layout as ExposureBuffer in auto_exposure.glsl.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GPUExposure {
+    exposure: f32,
+    histogram: [u32; EXPOSURE_HISTOGRAM_BIN_COUNT],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct HistogramPushConstants {
+    exposure_buffer_address: vk::DeviceAddress,
+    min_log_luminance: f32,
+    max_log_luminance: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct AveragePushConstants {
+    exposure_buffer_address: vk::DeviceAddress,
+    min_log_luminance: f32,
+    max_log_luminance: f32,
+    key: f32,
+    adaptation: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapPushConstants {
+    exposure_buffer_address: vk::DeviceAddress,
+    exposure: f32,
+    operator: u32,
+    auto_exposure: u32,
+    padding: u32,
+}
+
+// Final step before the UI and present: samples the HDR render target, scaled to the swapchain's
+// size, applies the exposure and a tonemapping curve and writes the swapchain image, which
+// encodes to sRGB itself. The automatic exposure lives in a buffer that stays on the GPU across
+// frames, filled from a luminance histogram of each frame and read by the tonemap pass without
+// reading anything back.
+pub struct Tonemapper {
+    context: Arc<RenderingContext>,
+    exposure_buffer: Buffer,
+    // Picked by the resolution scale filter.
+    nearest_sampler: vk::Sampler,
+    linear_sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    // Per frame in flight, the render target.
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    compute_pipeline_layout: vk::PipelineLayout,
+    histogram_pipeline: vk::Pipeline,
+    average_pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl Tonemapper {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        swapchain_format: vk::Format,
+        in_flight_frames_count: usize,
+    ) -> Result<Self> {
+        let mut exposure_buffer = Buffer::new(
+            allocator,
+            BufferAttributes {
+                name: "exposure_buffer".into(),
+                context: context.clone(),
+                size: size_of::<GPUExposure>() as vk::DeviceSize,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                location: MemoryLocation::CpuToGpu,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                allocation_priority: 0.5,
+            },
+        )?;
+        exposure_buffer.write(
+            &[GPUExposure {
+                exposure: 1.0,
+                histogram: [0; EXPOSURE_HISTOGRAM_BIN_COUNT],
+            }],
+            0,
+        )?;
+
+        let histogram_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "auto_exposure_histogram.comp.spv",
+        )?;
+        let average_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "auto_exposure_average.comp.spv",
+        )?;
+        let vertex_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "fullscreen.vert.spv",
+        )?;
+        let fragment_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "tonemap.frag.spv",
+        )?;
+
+        unsafe {
+            let create_sampler = |filter| {
+                context.device.create_sampler(
+                    &vk::SamplerCreateInfo::default()
+                        .mag_filter(filter)
+                        .min_filter(filter)
+                        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                    None,
+                )
+            };
+            let nearest_sampler = create_sampler(vk::Filter::NEAREST)?;
+            let linear_sampler = create_sampler(vk::Filter::LINEAR)?;
+
+            let descriptor_set_layout = context.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(
+                            vk::ShaderStageFlags::COMPUTE | vk::ShaderStageFlags::FRAGMENT,
+                        ),
+                ]),
+                None,
+            )?;
+            let descriptor_pool = context.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(in_flight_frames_count as u32)
+                    .pool_sizes(&[vk::DescriptorPoolSize::default()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(in_flight_frames_count as u32)]),
+                None,
+            )?;
+            let descriptor_sets = context.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&vec![descriptor_set_layout; in_flight_frames_count]),
+            )?;
+
+            let compute_pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_set_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .offset(0)
+                        .size(size_of::<AveragePushConstants>() as u32)]),
+                None,
+            )?;
+            let histogram_pipeline = context.create_compute_pipeline(ComputePipelineAttributes {
+                shader: histogram_shader,
+                pipeline_layout: compute_pipeline_layout,
+                pipeline_cache: Default::default(),
+            });
+            let average_pipeline = context.create_compute_pipeline(ComputePipelineAttributes {
+                shader: average_shader,
+                pipeline_layout: compute_pipeline_layout,
+                pipeline_cache: Default::default(),
+            });
+
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_set_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                        .offset(0)
+                        .size(size_of::<TonemapPushConstants>() as u32)]),
+                None,
+            )?;
+            let pipeline = context.create_graphics_pipeline(GraphicsPipelineAttributes {
+                vertex_shader,
+                fragment_shader,
+                extent: vk::Extent2D::default(),
+                color_format: swapchain_format,
+                depth_format: vk::Format::UNDEFINED,
+                samples: vk::SampleCountFlags::TYPE_1,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                line_width: 1.0,
+                dynamic_line_width: false,
+                dynamic_topology: false,
+                alpha_blending: false,
+                additive_blending: false,
+                depth_write: false,
+                cull_mode: vk::CullModeFlags::NONE,
+                viewport_count: 1,
+                vertex_layout: Default::default(),
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            });
+
+            context.device.destroy_shader_module(histogram_shader, None);
+            context.device.destroy_shader_module(average_shader, None);
+            context.device.destroy_shader_module(vertex_shader, None);
+            context.device.destroy_shader_module(fragment_shader, None);
+
+            Ok(Self {
+                context,
+                exposure_buffer,
+                nearest_sampler,
+                linear_sampler,
+                descriptor_set_layout,
+                descriptor_pool,
+                descriptor_sets,
+                compute_pipeline_layout,
+                histogram_pipeline: histogram_pipeline?,
+                average_pipeline: average_pipeline?,
+                pipeline_layout,
+                pipeline: pipeline?,
+            })
+        }
+    }
+
+    // Points the frame's descriptor at the render target, must be called after the frame's
+    // fence has been waited on and before recording the frame's passes.
+    pub fn prepare(&self, frame_index: usize, render_target: &Image, filter: vk::Filter) {
+        let sampler = match filter {
+            vk::Filter::NEAREST => self.nearest_sampler,
+            _ => self.linear_sampler,
+        };
+        unsafe {
+            self.context.device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::default()
+                    .dst_set(self.descriptor_sets[frame_index])
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::default()
+                        .sampler(sampler)
+                        .image_view(render_target.view)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)])],
+                &[],
+            );
+        }
+    }
+
+    // Bins the render target's luminance and moves the exposure towards the one bringing its
+    // average to the key. The render target has to be readable by compute shaders.
+    pub fn record_auto_exposure(
+        &self,
+        commands: &Commands,
+        frame_index: usize,
+        render_target: &Image,
+        attributes: &AutoExposureAttributes,
+        // Real time since the last frame.
+        delta_seconds: f32,
+    ) {
+        let extent = render_target.attributes.extent;
+        let exposure_buffer_address = self.exposure_buffer.address;
+        let storage_access =
+            vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE;
+        commands
+            // The previous frame's tonemap and averaging are done with the buffer.
+            .buffer_memory_barrier(
+                &self.exposure_buffer,
+                (
+                    vk::PipelineStageFlags2::COMPUTE_SHADER
+                        | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    storage_access,
+                ),
+                (vk::PipelineStageFlags2::COMPUTE_SHADER, storage_access),
+            )
+            .expect_sampled(render_target, "auto_exposure")
+            .bind_compute_pipeline(self.histogram_pipeline)
+            .bind_compute_descriptor_sets(
+                self.compute_pipeline_layout,
+                &[self.descriptor_sets[frame_index]],
+            )
+            .set_compute_push_constants(
+                self.compute_pipeline_layout,
+                HistogramPushConstants {
+                    exposure_buffer_address,
+                    min_log_luminance: attributes.min_log_luminance,
+                    max_log_luminance: attributes.max_log_luminance,
+                },
+            )
+            .dispatch_invocations(
+                [extent.width, extent.height, 1],
+                [HISTOGRAM_WORKGROUP_SIZE, HISTOGRAM_WORKGROUP_SIZE, 1],
+            )
+            .buffer_memory_barrier(
+                &self.exposure_buffer,
+                (vk::PipelineStageFlags2::COMPUTE_SHADER, storage_access),
+                (vk::PipelineStageFlags2::COMPUTE_SHADER, storage_access),
+            )
+            .bind_compute_pipeline(self.average_pipeline)
+            .set_compute_push_constants(
+                self.compute_pipeline_layout,
+                AveragePushConstants {
+                    exposure_buffer_address,
+                    min_log_luminance: attributes.min_log_luminance,
+                    max_log_luminance: attributes.max_log_luminance,
+                    key: attributes.key,
+                    adaptation: 1.0 - (-delta_seconds * attributes.adaptation_rate.max(0.0)).exp(),
+                },
+            )
+            .dispatch([1, 1, 1])
+            .buffer_memory_barrier(
+                &self.exposure_buffer,
+                (vk::PipelineStageFlags2::COMPUTE_SHADER, storage_access),
+                (
+                    vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_READ,
+                ),
+            );
+    }
+
+    // Draws the tonemapped render target over the whole swapchain image.
+    pub fn record(
+        &self,
+        commands: &Commands,
+        frame_index: usize,
+        render_target: &Image,
+        swapchain_image: &mut Image,
+        attributes: &TonemapAttributes,
+    ) {
+        let extent = vk::Extent2D {
+            width: swapchain_image.attributes.extent.width,
+            height: swapchain_image.attributes.extent.height,
+        };
+        let render_area = vk::Rect2D::default().extent(extent);
+        commands
+            .begin_color_rendering(swapchain_image, None, render_area)
+            .set_viewport(
+                vk::Viewport::default()
+                    .width(extent.width as f32)
+                    .height(extent.height as f32)
+                    .max_depth(1.0),
+            )
+            .set_scissor(render_area)
+            .expect_sampled(render_target, "tonemap")
+            .bind_pipeline(self.pipeline)
+            .bind_descriptor_sets(self.pipeline_layout, &[self.descriptor_sets[frame_index]])
+            .set_push_constants(
+                self.pipeline_layout,
+                TonemapPushConstants {
+                    exposure_buffer_address: self.exposure_buffer.address,
+                    exposure: attributes.exposure_compensation.exp2(),
+                    operator: attributes.operator as u32,
+                    auto_exposure: attributes.auto_exposure.is_some() as u32,
+                    padding: 0,
+                },
+            )
+            .draw(0..3, 0..1)
+            .end_rendering();
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        self.exposure_buffer.destroy(allocator)?;
+
+        unsafe {
+            self.context.device.destroy_pipeline(self.pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context
+                .device
+                .destroy_pipeline(self.histogram_pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline(self.average_pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.compute_pipeline_layout, None);
+            self.context
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.context
+                .device
+                .destroy_sampler(self.nearest_sampler, None);
+            self.context
+                .device
+                .destroy_sampler(self.linear_sampler, None);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+use crate::image::{Image, ImageAttributes};
+use crate::renderer::commands::Commands;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{GraphicsPipelineAttributes, ImageLayoutState, RenderingContext};
+use anyhow::{anyhow, Result};
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use std::sync::Arc;
+
+// Color format UI pipelines have to be created with.
+pub const UI_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+// Draws the UI into its own render target at the swapchain's resolution and composites it over
+// the swapchain image after the scaled scene has been blitted, so UI and text stay sharp at any
+// resolution scale.
+pub struct UiPass {
+    context: Arc<RenderingContext>,
+    // Per frame in flight, recreated when the swapchain extent changes.
+    targets: Vec<Option<Image>>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl UiPass {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        swapchain_format: vk::Format,
+        in_flight_frames_count: usize,
+    ) -> Result<Self> {
+        let vertex_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "ui_composite.vert.spv",
+        )?;
+        let fragment_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "ui_composite.frag.spv",
+        )?;
+
+        unsafe {
+            let descriptor_set_layout = context.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+                ]),
+                None,
+            )?;
+
+            let descriptor_pool = context.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(in_flight_frames_count as u32)
+                    .pool_sizes(&[vk::DescriptorPoolSize::default()
+                        .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                        .descriptor_count(in_flight_frames_count as u32)]),
+                None,
+            )?;
+
+            let descriptor_sets = context.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&vec![descriptor_set_layout; in_flight_frames_count]),
+            )?;
+
+            let pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default().set_layouts(&[descriptor_set_layout]),
+                None,
+            )?;
+
+            let pipeline = context.create_graphics_pipeline(GraphicsPipelineAttributes {
+                vertex_shader,
+                fragment_shader,
+                extent: vk::Extent2D::default(),
+                color_format: swapchain_format,
+                depth_format: vk::Format::UNDEFINED,
+                samples: vk::SampleCountFlags::TYPE_1,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                line_width: 1.0,
+                dynamic_line_width: false,
+                dynamic_topology: false,
+                alpha_blending: true,
+                additive_blending: false,
+                depth_write: false,
+                cull_mode: vk::CullModeFlags::NONE,
+                viewport_count: 1,
+                vertex_layout: Default::default(),
+                pipeline_layout,
+                pipeline_cache: Default::default(),
+            })?;
+
+            context.device.destroy_shader_module(vertex_shader, None);
+            context.device.destroy_shader_module(fragment_shader, None);
+
+            Ok(Self {
+                context,
+                targets: (0..in_flight_frames_count).map(|_| None).collect(),
+                descriptor_set_layout,
+                descriptor_pool,
+                descriptor_sets,
+                pipeline_layout,
+                pipeline,
+            })
+        }
+    }
+
+    // Must be called after the frame's fence has been waited on.
+    pub fn prepare(
+        &mut self,
+        allocator: &mut Allocator,
+        frame_index: usize,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        let target = &mut self.targets[frame_index];
+        if target.as_ref().is_some_and(|target| {
+            target.attributes.extent.width != extent.width
+                || target.attributes.extent.height != extent.height
+        }) {
+            target.take().unwrap().destroy(allocator)?;
+        }
+
+        if target.is_none() {
+            let image = Image::new(
+                self.context.clone(),
+                allocator,
+                "ui_target",
+                ImageAttributes {
+                    extent: extent.into(),
+                    format: UI_FORMAT,
+                    usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                    location: MemoryLocation::GpuOnly,
+                    linear: false,
+                    allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                    subresource_range: vk::ImageSubresourceRange::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .level_count(1)
+                        .layer_count(1),
+                    allocation_priority: 1.0,
+                    samples: vk::SampleCountFlags::TYPE_1,
+                    cube: false,
+                },
+            )?;
+            unsafe {
+                self.context.device.update_descriptor_sets(
+                    &[vk::WriteDescriptorSet::default()
+                        .dst_set(self.descriptor_sets[frame_index])
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .image_info(&[vk::DescriptorImageInfo::default()
+                            .image_view(image.view)
+                            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)])],
+                    &[],
+                );
+            }
+            *target = Some(image);
+        }
+
+        Ok(())
+    }
+
+    // Records the UI into the frame's target, then blends it over the swapchain image.
+    pub fn record(
+        &mut self,
+        commands: &Commands,
+        frame_index: usize,
+        swapchain_image: &mut Image,
+        draw: impl FnOnce(&Commands, vk::Extent2D),
+    ) -> Result<()> {
+        let extent = vk::Extent2D {
+            width: swapchain_image.attributes.extent.width,
+            height: swapchain_image.attributes.extent.height,
+        };
+        let render_area = vk::Rect2D::default().extent(extent);
+        let viewport = vk::Viewport::default()
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .max_depth(1.0);
+
+        let target = self.targets[frame_index]
+            .as_mut()
+            .ok_or_else(|| anyhow!("The UI target of frame {frame_index} wasn't prepared"))?;
+        target.reset_layout();
+        commands
+            .begin_color_rendering(target, Some(vk::ClearColorValue::default()), render_area)
+            .set_viewport(viewport)
+            .set_scissor(render_area);
+        draw(commands, extent);
+        commands
+            .end_rendering()
+            .transition_image_layout(target, ImageLayoutState::shader_read());
+
+        commands
+            .begin_color_rendering(swapchain_image, None, render_area)
+            .set_viewport(viewport)
+            .set_scissor(render_area)
+            .expect_sampled(target, "ui_composite")
+            .bind_pipeline(self.pipeline)
+            .bind_descriptor_sets(self.pipeline_layout, &[self.descriptor_sets[frame_index]])
+            .draw(0..3, 0..1)
+            .end_rendering();
+        Ok(())
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        for target in self.targets.iter_mut().flatten() {
+            target.destroy(allocator)?;
+        }
+        unsafe {
+            self.context.device.destroy_pipeline(self.pipeline, None);
+            self.context
+                .device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context
+                .device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            self.context
+                .device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+        Ok(())
+    }
+}
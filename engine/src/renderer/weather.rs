@@ -0,0 +1,372 @@
+use crate::buffer::{Buffer, BufferAttributes};
+use crate::renderer::commands::Commands;
+use crate::renderer::{load_shader_module, SHADERS_DIR};
+use crate::rendering_context::{
+    ComputePipelineAttributes, GraphicsPipelineAttributes, RenderingContext,
+};
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use nalgebra as na;
+use std::sync::Arc;
+
+// Particles at an intensity of 1.
+const MAX_PARTICLES: u32 = 65536;
+// Side of the volume the particles fall through, in meters.
+const VOLUME_EXTENT: f32 = 40.0;
+const WORKGROUP_SIZE: u32 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precipitation {
+    Rain,
+    Snow,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WeatherAttributes {
+    pub precipitation: Precipitation,
+    // From 0 for nothing falling to 1 for a downpour or a blizzard.
+    pub intensity: f32,
+    // Drift of the particles in meters per second.
+    pub wind: na::Vector3<f32>,
+    // Wetness gained per second of rain at an intensity of 1, and lost per second otherwise.
+    pub wetting_rate: f32,
+    pub drying_rate: f32,
+}
+
+impl Default for WeatherAttributes {
+    fn default() -> Self {
+        Self {
+            precipitation: Precipitation::Rain,
+            intensity: 0.5,
+            wind: na::Vector3::zeros(),
+            wetting_rate: 0.05,
+            drying_rate: 0.01,
+        }
+    }
+}
+
+// How each kind of precipitation falls and looks.
+struct ParticleStyle {
+    // Meters per second.
+    fall_speed: f32,
+    sway: f32,
+    size: f32,
+    // Streaks are stretched along the velocity over this many seconds, 0 for flakes.
+    streak_time: f32,
+    color: na::Vector4<f32>,
+}
+
+impl Precipitation {
+    fn style(self) -> ParticleStyle {
+        match self {
+            Precipitation::Rain => ParticleStyle {
+                fall_speed: 9.0,
+                sway: 0.0,
+                size: 0.01,
+                streak_time: 0.03,
+                color: na::Vector4::new(0.6, 0.65, 0.7, 0.25),
+            },
+            Precipitation::Snow => ParticleStyle {
+                fall_speed: 1.0,
+                sway: 0.4,
+                size: 0.03,
+                streak_time: 0.0,
+                color: na::Vector4::new(0.9, 0.9, 0.9, 0.9),
+            },
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct UpdatePushConstants {
+    particle_buffer_address: vk::DeviceAddress,
+    wind: na::Vector3<f32>,
+    delta_time: f32,
+    time: f32,
+    fall_speed: f32,
+    sway: f32,
+    extent: f32,
+    count: u32,
+    reset: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawPushConstants {
+    particle_buffer_address: vk::DeviceAddress,
+    camera_buffer_address: vk::DeviceAddress,
+    wrapped_center: na::Vector3<f32>,
+    extent: f32,
+    center: na::Vector3<f32>,
+    size: f32,
+    velocity: na::Vector3<f32>,
+    streak_time: f32,
+    color: na::Vector4<f32>,
+}
+
+// Rain and snow as GPU particles: a compute pass moves them through a wrapping volume that's
+// drawn around the camera, ahead of it so most of them are in view, and surfaces get wet while
+// it rains. Wetness darkens materials and makes them glossy, see Material.wetness_response.
+pub struct Weather {
+    context: Arc<RenderingContext>,
+    attributes: Option<WeatherAttributes>,
+    wetness: f32,
+    // Of the last attributes, surfaces keep drying after the weather is cleared.
+    drying_rate: f32,
+    // The particles are scattered through the volume before they're first moved.
+    is_initialized: bool,
+    particle_buffer: Buffer,
+    update_pipeline_layout: vk::PipelineLayout,
+    update_pipeline: vk::Pipeline,
+    draw_pipeline_layout: vk::PipelineLayout,
+    draw_pipeline: vk::Pipeline,
+}
+
+impl Weather {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> Result<Self> {
+        let particle_buffer = Buffer::new(
+            allocator,
+            BufferAttributes {
+                name: "weather_particle_buffer".into(),
+                context: context.clone(),
+                size: (MAX_PARTICLES as usize * size_of::<na::Vector4<f32>>()) as vk::DeviceSize,
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+                location: MemoryLocation::GpuOnly,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                allocation_priority: 0.5,
+            },
+        )?;
+
+        let update_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "weather.comp.spv",
+        )?;
+        let vertex_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "weather.vert.spv",
+        )?;
+        let fragment_shader = load_shader_module(
+            context.as_ref(),
+            SHADERS_DIR.to_owned() + "weather.frag.spv",
+        )?;
+
+        unsafe {
+            let update_pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&[
+                    vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .offset(0)
+                        .size(size_of::<UpdatePushConstants>() as u32),
+                ]),
+                None,
+            )?;
+            let update_pipeline = context.create_compute_pipeline(ComputePipelineAttributes {
+                shader: update_shader,
+                pipeline_layout: update_pipeline_layout,
+                pipeline_cache: Default::default(),
+            })?;
+
+            let draw_pipeline_layout = context.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&[
+                    vk::PushConstantRange::default()
+                        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                        .offset(0)
+                        .size(size_of::<DrawPushConstants>() as u32),
+                ]),
+                None,
+            )?;
+            let draw_pipeline = context.create_graphics_pipeline(GraphicsPipelineAttributes {
+                vertex_shader,
+                fragment_shader,
+                extent,
+                color_format: format,
+                depth_format,
+                samples,
+                topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+                line_width: 1.0,
+                dynamic_line_width: false,
+                dynamic_topology: false,
+                alpha_blending: true,
+                additive_blending: false,
+                depth_write: false,
+                cull_mode: vk::CullModeFlags::NONE,
+                viewport_count: 1,
+                vertex_layout: Default::default(),
+                pipeline_layout: draw_pipeline_layout,
+                pipeline_cache: Default::default(),
+            })?;
+
+            for shader in [update_shader, vertex_shader, fragment_shader] {
+                context.device.destroy_shader_module(shader, None);
+            }
+
+            Ok(Self {
+                context,
+                attributes: None,
+                wetness: 0.0,
+                drying_rate: WeatherAttributes::default().drying_rate,
+                is_initialized: false,
+                particle_buffer,
+                update_pipeline_layout,
+                update_pipeline,
+                draw_pipeline_layout,
+                draw_pipeline,
+            })
+        }
+    }
+
+    pub fn attributes(&self) -> Option<WeatherAttributes> {
+        self.attributes
+    }
+
+    // None stops the precipitation, surfaces then dry at the last drying rate.
+    pub fn set_attributes(&mut self, attributes: Option<WeatherAttributes>) {
+        if let Some(attributes) = attributes {
+            self.drying_rate = attributes.drying_rate;
+        }
+        self.attributes = attributes;
+    }
+
+    pub fn wetness(&self) -> f32 {
+        self.wetness
+    }
+
+    // From 0 to 1, e.g. to start a scene after the rain.
+    pub fn set_wetness(&mut self, wetness: f32) {
+        self.wetness = wetness.clamp(0.0, 1.0);
+    }
+
+    fn particle_count(&self) -> u32 {
+        self.attributes.map_or(0, |attributes| {
+            (attributes.intensity.clamp(0.0, 1.0) * MAX_PARTICLES as f32) as u32
+        })
+    }
+
+    // Wets or dries the surfaces by the frame's delta time.
+    pub fn update(&mut self, delta_time: f32) {
+        let rain = self
+            .attributes
+            .filter(|attributes| attributes.precipitation == Precipitation::Rain)
+            .map_or(0.0, |attributes| attributes.intensity.clamp(0.0, 1.0));
+        self.wetness = if rain > 0.0 {
+            let wetting_rate = self.attributes.unwrap().wetting_rate;
+            self.wetness + wetting_rate * rain * delta_time
+        } else {
+            self.wetness - self.drying_rate * delta_time
+        }
+        .clamp(0.0, 1.0);
+    }
+
+    // Moves the particles on by the frame's delta time, leaving them ready to be drawn.
+    pub fn record(&mut self, commands: &Commands, delta_time: f32, time: f32) {
+        let Some(attributes) = self.attributes else {
+            return;
+        };
+        let count = self.particle_count();
+        if count == 0 {
+            return;
+        }
+        let style = attributes.precipitation.style();
+        let reset = !self.is_initialized;
+        self.is_initialized = true;
+        commands
+            .bind_compute_pipeline(self.update_pipeline)
+            .set_compute_push_constants(
+                self.update_pipeline_layout,
+                UpdatePushConstants {
+                    particle_buffer_address: self.particle_buffer.address,
+                    wind: attributes.wind,
+                    delta_time,
+                    time,
+                    fall_speed: style.fall_speed,
+                    sway: style.sway,
+                    extent: VOLUME_EXTENT,
+                    // Every particle is scattered, so raising the intensity later reveals
+                    // particles that are already spread out.
+                    count: if reset { MAX_PARTICLES } else { count },
+                    reset: reset as u32,
+                },
+            )
+            .dispatch([
+                if reset { MAX_PARTICLES } else { count }.div_ceil(WORKGROUP_SIZE),
+                1,
+                1,
+            ])
+            .buffer_memory_barrier(
+                &self.particle_buffer,
+                (
+                    vk::PipelineStageFlags2::COMPUTE_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags2::VERTEX_SHADER,
+                    vk::AccessFlags2::SHADER_STORAGE_READ,
+                ),
+            );
+    }
+
+    // Expects the scene's color and depth to be bound, particles are hidden by the geometry in
+    // front of them.
+    pub fn draw(
+        &self,
+        commands: &Commands,
+        camera_buffer_address: vk::DeviceAddress,
+        camera_position: &na::Point3<f64>,
+        camera_forward: &na::Vector3<f64>,
+    ) {
+        let Some(attributes) = self.attributes else {
+            return;
+        };
+        let count = self.particle_count();
+        if count == 0 {
+            return;
+        }
+        let style = attributes.precipitation.style();
+        let extent = VOLUME_EXTENT as f64;
+        let center = camera_forward * extent * 0.3;
+        let wrapped_center = (camera_position.coords + center).map(|x| x.rem_euclid(extent));
+        commands
+            .bind_pipeline(self.draw_pipeline)
+            .set_push_constants(
+                self.draw_pipeline_layout,
+                DrawPushConstants {
+                    particle_buffer_address: self.particle_buffer.address,
+                    camera_buffer_address,
+                    wrapped_center: wrapped_center.cast(),
+                    extent: VOLUME_EXTENT,
+                    center: center.cast(),
+                    size: style.size,
+                    velocity: attributes.wind + na::Vector3::y() * style.fall_speed,
+                    streak_time: style.streak_time,
+                    color: style.color,
+                },
+            )
+            .draw(0..count * 6, 0..1);
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        self.particle_buffer.destroy(allocator)?;
+        unsafe {
+            for pipeline in [self.update_pipeline, self.draw_pipeline] {
+                self.context.device.destroy_pipeline(pipeline, None);
+            }
+            for pipeline_layout in [self.update_pipeline_layout, self.draw_pipeline_layout] {
+                self.context
+                    .device
+                    .destroy_pipeline_layout(pipeline_layout, None);
+            }
+        }
+        Ok(())
+    }
+}
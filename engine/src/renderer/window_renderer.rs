@@ -1,21 +1,46 @@
+use crate::renderer::async_readback::AsyncReadback;
+use crate::renderer::depth_readback::{DepthImage, DepthReadback};
+use crate::renderer::display_timing::{DisplayTimingClock, PresentSchedule};
+#[cfg(feature = "egui")]
+use crate::renderer::egui_overlay::EguiOverlay;
+use crate::renderer::frame_capture::{CaptureFormat, FrameCapture};
+use crate::renderer::image_analysis::{ImageAnalysisAttributes, ImageAnalyzer, ImageStatistics};
+use crate::renderer::lighting::Lighting;
+use crate::renderer::painter::{Painter, PainterRenderer};
+use crate::renderer::post_process::PostChain;
+use crate::renderer::present_feedback::{PresentFeedback, PresentedFrame};
+use crate::renderer::probe_grid::ProbeGridAttributes;
+use crate::renderer::render_graph::{GraphAccess, RenderGraph};
+use crate::renderer::shader_reload::ShaderReloadAttributes;
+use crate::renderer::splash::{SplashAttributes, SplashScreen};
 use crate::renderer::swapchain::Swapchain;
-use crate::renderer::{Renderer, RendererAttributes};
+use crate::renderer::tonemap::{TonemapAttributes, Tonemapper};
+use crate::renderer::ui_pass::UiPass;
+use crate::renderer::{
+    InstanceHit, Renderer, RendererAttributes, ShaderPaths, SunShadowAttributes,
+};
 use crate::rendering_context::{ImageLayoutState, RenderingContext};
+use crate::time::Time;
 use ash::vk;
 use ash::vk::CommandBuffer;
-use std::sync::Arc;
+use nalgebra as na;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use winit::dpi::PhysicalPosition;
 use winit::window::Window;
 
-use crate::image;
-use crate::image::ImageAttributes;
 use crate::renderer::commands::Commands;
-use anyhow::Result;
-use gpu_allocator::vulkan::AllocationScheme;
-use gpu_allocator::MemoryLocation;
-use tracing::trace;
+use anyhow::{anyhow, Result};
+use tracing::{trace, warn};
 
+// Each frame owns a transient command pool that is reset as a whole once its fence signals,
+// which is cheaper than resetting command buffers one by one.
 struct Frame {
+    command_pool: vk::CommandPool,
     command_buffer: CommandBuffer,
+    transient_command_buffers: Vec<CommandBuffer>,
+    used_transient_command_buffers: usize,
     image_available_semaphore: vk::Semaphore,
     render_finished_semaphore: vk::Semaphore,
     in_flight_fence: vk::Fence,
@@ -29,25 +54,136 @@ pub struct WindowRendererAttributes {
     pub ssaa: f32,
     pub ssaa_filter: vk::Filter,
     pub in_flight_frames_count: usize,
+    pub occlusion_queries: bool,
+    pub conditional_rendering: bool,
+    pub probe_grid: Option<ProbeGridAttributes>,
+    pub grid: bool,
+    // Lets windows shade the same scene differently, e.g. stylized next to realistic.
+    pub shaders: Option<ShaderPaths>,
+    // Feeds vertices through classic vertex input bindings instead of vertex pulling, for
+    // shaders written against vertex attributes and easier inspection in graphics debuggers.
+    pub vertex_input: bool,
+    // Stores positions and normals in 16 bit, dequantized in the vertex shader; requires pulling.
+    pub quantize_vertices: bool,
+    // How the multisampled depth is resolved for picking and depth readback, falls back to
+    // SAMPLE_ZERO when the device doesn't support it.
+    pub depth_resolve_mode: vk::ResolveModeFlags,
+    // Presents every nth refresh of the window's monitor, e.g. 2 renders background or secondary
+    // windows at half rate. 1 presents as often as the present mode allows.
+    pub refresh_divisor: u32,
+    // Shown with the loading progress while the renderer is created, instead of a frozen window.
+    pub splash: Option<SplashAttributes>,
+    // Bytes of texture data uploaded per frame, so streaming in many textures doesn't stall
+    // single frames. None uploads everything queued right away.
+    pub upload_budget: Option<vk::DeviceSize>,
+    // Rebuilds the scene pipeline when its shaders change on disk, meant for development.
+    pub shader_reload: Option<ShaderReloadAttributes>,
+    // Keeps the last frame's instance transforms in the object table, see RendererAttributes.
+    pub previous_transforms: bool,
+    // Initial lighting of the window's renderer, the sun direction also orients its shadow.
+    pub lighting: Lighting,
+    // Resolution and reach of the sun's shadow map, None leaves the sun unshadowed.
+    pub sun_shadow: Option<SunShadowAttributes>,
+    // How the HDR render target is exposed and mapped to the swapchain.
+    pub tonemap: TonemapAttributes,
+}
+
+// What windows do while they can't be drawn to, e.g. minimized or resized to nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MinimizedPolicy {
+    // Nothing is rendered, the engine keeps running its updates as usual.
+    #[default]
+    SkipRendering,
+    // Nothing is rendered, and while every window is minimized the engine waits this long
+    // between its frames instead of spinning through them.
+    ThrottleUpdates(Duration),
+    // The scene keeps being rendered at its last size without presenting, so readbacks are
+    // still delivered.
+    RenderOffscreen,
 }
 
+// What render did with the frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameStatus {
+    Presented,
+    // Too soon after the last present for the refresh divisor.
+    NotDue,
+    // The window can't be drawn to, nothing was rendered.
+    Minimized,
+    // The window can't be drawn to and MinimizedPolicy::RenderOffscreen rendered the scene.
+    RenderedOffscreen,
+    // The swapchain kept going out of date, e.g. while resizing; retried next frame.
+    OutOfDate,
+}
+
+type RecordUi = Box<dyn FnMut(&Commands, vk::Extent2D)>;
+
 pub struct WindowRenderer {
     frame_index: usize,
     frames: Vec<Frame>,
-    command_pool: vk::CommandPool,
+    // Whether the current frame's fence has been waited on and its command pool reset.
+    frame_ready: bool,
     swapchain: Swapchain,
     context: Arc<RenderingContext>,
 
     attributes: WindowRendererAttributes,
+    minimized_policy: MinimizedPolicy,
+    // Whether the last rendered frame found the window unable to be drawn to.
+    is_minimized: bool,
+
+    capture: Option<FrameCapture>,
+    readback: AsyncReadback,
+    depth_readback: DepthReadback,
+    // Created by the first analyze_last_frame.
+    image_analyzer: Option<ImageAnalyzer>,
+
+    last_present: Option<Instant>,
+    present_feedback: Option<PresentFeedback>,
+    present_schedule: Arc<Mutex<PresentSchedule>>,
+    // Set once a display timing clock was handed out, presents then ask for the time their
+    // animation was sampled for.
+    scheduled_presents: bool,
+
+    post_chain: PostChain,
+    tonemapper: Tonemapper,
+    ui_pass: UiPass,
+    // Records the UI at the swapchain's resolution, independent of the resolution scale.
+    ui: Option<RecordUi>,
+    painter: Painter,
+    painter_renderer: PainterRenderer,
+    #[cfg(feature = "egui")]
+    egui: Option<EguiOverlay>,
 
     pub renderer: Renderer,
     pub window: Arc<Window>,
 }
 
+// Times the swapchain is recreated within a frame before acquiring is left to the next one.
+const MAX_ACQUIRE_ATTEMPTS: usize = 3;
+
+// Render target fallbacks from the highest to the lowest precision.
+const COLOR_FORMATS: [vk::Format; 3] = [
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::A2B10G10R10_UNORM_PACK32,
+    vk::Format::R8G8B8A8_UNORM,
+];
+
+// Supersampling factors past this would ask for render targets larger than any device allows.
+const MAX_SSAA: f32 = 4.0;
+
+// Zero, negative and non-finite factors fall back to rendering at the window's resolution.
+fn valid_ssaa(ssaa: f32) -> f32 {
+    if ssaa.is_finite() && ssaa > 0.0 {
+        ssaa.min(MAX_SSAA)
+    } else {
+        1.0
+    }
+}
+
 fn scale_extent(extent: vk::Extent2D, scale: f32) -> vk::Extent2D {
     vk::Extent2D {
-        width: (extent.width as f32 * scale) as u32,
-        height: (extent.height as f32 * scale) as u32,
+        width: ((extent.width as f32 * scale) as u32).max(1),
+        height: ((extent.height as f32 * scale) as u32).max(1),
     }
 }
 
@@ -55,29 +191,61 @@ impl WindowRenderer {
     pub fn new(
         context: Arc<RenderingContext>,
         window: Arc<Window>,
-        attributes: WindowRendererAttributes,
+        mut attributes: WindowRendererAttributes,
     ) -> Result<Self> {
-        let mut swapchain = Swapchain::new(context.clone(), window.clone())?;
-        swapchain.resize()?;
+        attributes.ssaa = valid_ssaa(attributes.ssaa);
+        // Render targets are blended into, filtered by the tonemap pass and blitted for readbacks.
+        let color_format = context
+            .best_supported(
+                [attributes.format].into_iter().chain(COLOR_FORMATS),
+                vk::FormatFeatureFlags::COLOR_ATTACHMENT
+                    | vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND
+                    | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR
+                    | vk::FormatFeatureFlags::BLIT_SRC,
+                vk::ImageTiling::OPTIMAL,
+            )
+            .ok_or_else(|| anyhow::anyhow!("No supported render target format found"))?;
+        if color_format != attributes.format {
+            warn!(
+                "Render target format {:?} is not supported, using {:?}",
+                attributes.format, color_format
+            );
+            attributes.format = color_format;
+        }
 
-        unsafe {
-            let command_pool = context.device.create_command_pool(
-                &vk::CommandPoolCreateInfo::default()
-                    .queue_family_index(context.queue_families.graphics)
-                    .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER),
-                None,
-            )?;
+        // Pipelines and depth buffers are created with the picked format, see attributes().
+        let depth_format = context.pick_depth_format(attributes.depth_format)?;
+        if depth_format != attributes.depth_format {
+            warn!(
+                "Depth format {:?} is not supported, using {:?}",
+                attributes.depth_format, depth_format
+            );
+            attributes.depth_format = depth_format;
+        }
 
-            let command_buffers = context.device.allocate_command_buffers(
-                &vk::CommandBufferAllocateInfo::default()
-                    .command_pool(command_pool)
-                    .level(vk::CommandBufferLevel::PRIMARY)
-                    .command_buffer_count(attributes.in_flight_frames_count as u32),
-            )?;
+        let mut swapchain = Swapchain::new(
+            context.clone(),
+            window.clone(),
+            attributes.in_flight_frames_count,
+        )?;
+        swapchain.resize()?;
 
-            let mut frames = Vec::with_capacity(command_buffers.len());
+        unsafe {
+            let mut frames = Vec::with_capacity(attributes.in_flight_frames_count);
 
-            for &command_buffer in command_buffers.iter() {
+            for _ in 0..attributes.in_flight_frames_count {
+                let command_pool = context.device.create_command_pool(
+                    &vk::CommandPoolCreateInfo::default()
+                        .queue_family_index(context.queue_families.graphics)
+                        .flags(vk::CommandPoolCreateFlags::TRANSIENT),
+                    None,
+                )?;
+                let command_buffer = context.device.allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo::default()
+                        .command_pool(command_pool)
+                        .level(vk::CommandBufferLevel::PRIMARY)
+                        .command_buffer_count(1),
+                )?[0];
                 let image_available_semaphore = context
                     .device
                     .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?;
@@ -90,52 +258,120 @@ impl WindowRenderer {
                 )?;
 
                 frames.push(Frame {
+                    command_pool,
                     command_buffer,
+                    transient_command_buffers: Vec::new(),
+                    used_transient_command_buffers: 0,
                     image_available_semaphore,
                     render_finished_semaphore,
                     in_flight_fence,
                 });
             }
 
-            let command_buffer = frames[0].command_buffer;
+            let swapchain_extent = swapchain.extent;
+            let swapchain_format = swapchain.format;
+            // Owned by the renderer, every pass of the window allocates from it.
+            let mut allocator = context.create_allocator(Default::default(), Default::default())?;
+            let mut splash = attributes
+                .splash
+                .clone()
+                .map(|splash| SplashScreen::new(context.clone(), &mut allocator, splash))
+                .transpose()?;
+            let mut progress = |progress| match &mut splash {
+                Some(splash) => splash.present(&mut swapchain, progress),
+                None => Ok(()),
+            };
+            progress(0.0)?;
 
-            let commands = Commands::new(context.clone(), command_buffer)?;
+            let mut renderer = context.immediate_submit(|commands| {
+                Renderer::new(
+                    context.clone(),
+                    commands,
+                    allocator,
+                    RendererAttributes {
+                        extent: scale_extent(swapchain_extent, attributes.ssaa),
+                        format: attributes.format,
+                        depth_format: attributes.depth_format,
+                        buffering: attributes.in_flight_frames_count,
+                        occlusion_queries: attributes.occlusion_queries,
+                        conditional_rendering: attributes.conditional_rendering,
+                        probe_grid: attributes.probe_grid,
+                        grid: attributes.grid,
+                        shaders: attributes.shaders.clone(),
+                        vertex_input: attributes.vertex_input,
+                        quantize_vertices: attributes.quantize_vertices,
+                        depth_resolve_mode: attributes.depth_resolve_mode,
+                        upload_budget: attributes.upload_budget,
+                        shader_reload: attributes.shader_reload.clone(),
+                        previous_transforms: attributes.previous_transforms,
+                        lighting: attributes.lighting,
+                        sun_shadow: attributes.sun_shadow,
+                    },
+                    &mut progress,
+                )
+            })?;
 
-            let mut renderer = Renderer::new(
+            let post_chain = PostChain::new(
                 context.clone(),
-                &commands,
-                RendererAttributes {
-                    extent: scale_extent(swapchain.extent, attributes.ssaa),
-                    format: attributes.format,
-                    depth_format: attributes.depth_format,
-                    buffering: attributes.in_flight_frames_count,
-                },
+                attributes.format,
+                attributes.in_flight_frames_count,
             )?;
-
-            let fence = context
-                .device
-                .create_fence(&vk::FenceCreateInfo::default(), None)?;
-
-            commands.submit(
-                context.queues[context.queue_families.graphics as usize],
-                Default::default(),
-                Default::default(),
-                fence,
+            let tonemapper = Tonemapper::new(
+                context.clone(),
+                renderer.allocator_mut(),
+                swapchain_format,
+                attributes.in_flight_frames_count,
             )?;
+            let ui_pass = UiPass::new(
+                context.clone(),
+                swapchain_format,
+                attributes.in_flight_frames_count,
+            )?;
+            let painter_renderer =
+                PainterRenderer::new(context.clone(), attributes.in_flight_frames_count)?;
+            let readback = AsyncReadback::new(context.clone(), attributes.in_flight_frames_count)?;
+            let depth_readback = DepthReadback::new(context.clone());
+            progress(1.0)?;
+            if let Some(mut splash) = splash {
+                context.device.device_wait_idle()?;
+                splash.destroy(renderer.allocator_mut())?;
+            }
+            // The window may have been resized while the splash screen was shown.
+            if swapchain.extent != swapchain_extent {
+                swapchain.is_dirty = true;
+            }
 
-            context.device.wait_for_fences(&[fence], true, u64::MAX)?;
-
-            context.device.destroy_fence(fence, None);
+            let mut present_schedule = PresentSchedule::default();
+            present_schedule.set_refresh_divisor(attributes.refresh_divisor);
+            let present_schedule = Arc::new(Mutex::new(present_schedule));
 
             Ok(Self {
                 frame_index: 0,
                 frames,
-                command_pool,
+                frame_ready: false,
                 swapchain,
                 context,
                 renderer,
                 window,
                 attributes,
+                minimized_policy: MinimizedPolicy::default(),
+                is_minimized: false,
+                capture: None,
+                readback,
+                depth_readback,
+                image_analyzer: None,
+                last_present: None,
+                present_feedback: None,
+                present_schedule,
+                scheduled_presents: false,
+                post_chain,
+                tonemapper,
+                ui_pass,
+                ui: None,
+                painter: Painter::default(),
+                painter_renderer,
+                #[cfg(feature = "egui")]
+                egui: None,
             })
         }
     }
@@ -144,41 +380,561 @@ impl WindowRenderer {
         self.swapchain.is_dirty = true;
     }
 
-    pub fn render(&mut self) -> Result<()> {
-        let frame = &self.frames[self.frame_index];
+    pub fn attributes(&self) -> &WindowRendererAttributes {
+        &self.attributes
+    }
+
+    // The setters below take effect on the next rendered frame. Formats, frame count and query
+    // support are baked into the renderer's resources and still require a new WindowRenderer.
+    pub fn set_clear_color(&mut self, clear_color: vk::ClearColorValue) {
+        self.attributes.clear_color = clear_color;
+    }
+
+    pub fn set_ssaa(&mut self, ssaa: f32) {
+        let ssaa = valid_ssaa(ssaa);
+        if self.attributes.ssaa != ssaa {
+            self.attributes.ssaa = ssaa;
+            self.swapchain.is_dirty = true;
+        }
+    }
+
+    pub fn set_ssaa_filter(&mut self, ssaa_filter: vk::Filter) {
+        self.attributes.ssaa_filter = ssaa_filter;
+    }
+
+    pub fn set_tonemap(&mut self, tonemap: TonemapAttributes) {
+        self.attributes.tonemap = tonemap;
+    }
+
+    // Fullscreen passes run over the scene before it's tonemapped, in the order they were added.
+    pub fn post_chain(&mut self) -> &mut PostChain {
+        &mut self.post_chain
+    }
+
+    pub fn set_grid(&mut self, grid: bool) {
+        self.attributes.grid = grid;
+        self.renderer.debug_visualizations.grid = grid;
+    }
+
+    pub fn set_upload_budget(&mut self, upload_budget: Option<vk::DeviceSize>) {
+        self.attributes.upload_budget = upload_budget;
+        self.renderer.set_upload_budget(upload_budget);
+    }
+
+    pub fn set_refresh_divisor(&mut self, refresh_divisor: u32) {
+        self.attributes.refresh_divisor = refresh_divisor.max(1);
+        self.present_schedule
+            .lock()
+            .unwrap()
+            .set_refresh_divisor(self.attributes.refresh_divisor);
+    }
+
+    // Set by the engine from EngineAttributes::minimized_policy.
+    pub fn set_minimized_policy(&mut self, minimized_policy: MinimizedPolicy) {
+        self.minimized_policy = minimized_policy;
+    }
+
+    pub fn minimized_policy(&self) -> MinimizedPolicy {
+        self.minimized_policy
+    }
+
+    // Whether the last frame found the window unable to be drawn to, e.g. minimized.
+    pub fn is_minimized(&self) -> bool {
+        self.is_minimized
+    }
+
+    // The closure is called every frame inside a color pass over a transparent target with the
+    // viewport and scissor set to the window's extent.
+    pub fn set_ui(&mut self, ui: impl FnMut(&Commands, vk::Extent2D) + 'static) {
+        self.ui = Some(Box::new(ui));
+    }
+
+    pub fn clear_ui(&mut self) {
+        self.ui = None;
+    }
+
+    // Runs the egui UI every frame and draws it after the set_ui closure, under the painter's
+    // shapes. Textures and fonts stay loaded when the closure is replaced.
+    #[cfg(feature = "egui")]
+    pub fn set_egui(&mut self, ui: impl FnMut(&egui::Context) + 'static) -> Result<()> {
+        match &mut self.egui {
+            Some(egui) => egui.set_ui(Box::new(ui)),
+            None => {
+                self.egui = Some(EguiOverlay::new(
+                    self.context.clone(),
+                    self.attributes.in_flight_frames_count,
+                    Box::new(ui),
+                )?)
+            }
+        }
+        Ok(())
+    }
+
+    // Waits for the device to idle to release the textures.
+    #[cfg(feature = "egui")]
+    pub fn clear_egui(&mut self) -> Result<()> {
+        if let Some(mut egui) = self.egui.take() {
+            unsafe {
+                self.context.device.device_wait_idle()?;
+            }
+            egui.destroy(self.renderer.allocator_mut())?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "egui")]
+    pub fn egui_context(&self) -> Option<&egui::Context> {
+        self.egui.as_ref().map(EguiOverlay::egui_context)
+    }
+
+    // Forwarded by Engine::window_event, call it when driving the renderer without an Engine.
+    #[cfg(feature = "egui")]
+    pub fn egui_event(&mut self, event: &winit::event::WindowEvent) {
+        if let Some(egui) = &mut self.egui {
+            egui.handle_event(event, self.window.scale_factor());
+        }
+    }
+
+    // Whether egui used the pointer or keyboard in the last frame, e.g. a window was hovered or
+    // a text field focused, so the app should ignore that input.
+    #[cfg(feature = "egui")]
+    pub fn egui_wants_pointer_input(&self) -> bool {
+        self.egui
+            .as_ref()
+            .is_some_and(EguiOverlay::wants_pointer_input)
+    }
+
+    #[cfg(feature = "egui")]
+    pub fn egui_wants_keyboard_input(&self) -> bool {
+        self.egui
+            .as_ref()
+            .is_some_and(EguiOverlay::wants_keyboard_input)
+    }
+
+    // Called once per present, in order, e.g. to measure latency or schedule animations. When
+    // the device supports VK_GOOGLE_display_timing the call waits for the frame's actual
+    // presentation time, which arrives a few frames later.
+    pub fn set_present_callback(&mut self, callback: impl FnMut(&PresentedFrame) + 'static) {
+        self.present_feedback = Some(PresentFeedback::new(Box::new(callback)));
+    }
+
+    pub fn clear_present_callback(&mut self) {
+        self.present_feedback = None;
+    }
+
+    pub fn has_present_timing(&self) -> bool {
+        self.context.display_timing_extension.is_some()
+    }
+
+    // A clock for Time that samples animations at this window's predicted present times, None
+    // without VK_GOOGLE_display_timing. The window's presents are then held back until the time
+    // they were predicted for, which keeps them on the refresh grid.
+    pub fn display_timing_clock(&mut self) -> Option<DisplayTimingClock> {
+        if !self.has_present_timing() {
+            return None;
+        }
+        self.scheduled_presents = true;
+        Some(DisplayTimingClock::new(self.present_schedule.clone()))
+    }
+
+    // Shapes drawn here appear over the UI in the next rendered frame only.
+    pub fn painter(&mut self) -> &mut Painter {
+        &mut self.painter
+    }
+
+    // Hit test at a position in the window's physical pixels, e.g. the cursor position.
+    pub fn hit_test(&self, position: PhysicalPosition<f64>) -> Option<InstanceHit> {
+        let size = self.window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            return None;
+        }
+        self.renderer.hit_test(na::Point2::new(
+            position.x / size.width as f64 * 2.0 - 1.0,
+            position.y / size.height as f64 * 2.0 - 1.0,
+        ))
+    }
+
+    // Refresh rate in hertz of the monitor the window is currently on, if the platform reports it.
+    pub fn refresh_rate(&self) -> Option<f64> {
+        let millihertz = self.window.current_monitor()?.refresh_rate_millihertz()?;
+        Some(millihertz as f64 / 1000.0)
+    }
+
+    // Whether enough refreshes have passed since the last present for the refresh divisor.
+    fn is_frame_due(&self) -> bool {
+        let (Some(last_present), Some(refresh_rate)) = (self.last_present, self.refresh_rate())
+        else {
+            return true;
+        };
+        if self.attributes.refresh_divisor <= 1 {
+            return true;
+        }
+        // Half a refresh of slack so jitter doesn't push presents to the refresh after.
+        let interval = (self.attributes.refresh_divisor as f64 - 0.5) / refresh_rate;
+        last_present.elapsed() >= Duration::from_secs_f64(interval)
+    }
+
+    // Analyzes the scene render target of the last rendered frame, before the UI and the
+    // blit to the swapchain; None until a frame has been rendered.
+    pub fn analyze_last_frame(
+        &mut self,
+        attributes: ImageAnalysisAttributes,
+    ) -> Result<Option<ImageStatistics>> {
+        let Some(last_frame_index) = self.last_frame_index() else {
+            return Ok(None);
+        };
+        let analyzer = match &mut self.image_analyzer {
+            Some(analyzer) => analyzer,
+            None => self.image_analyzer.insert(ImageAnalyzer::new(
+                self.context.clone(),
+                self.renderer.allocator_mut(),
+                attributes,
+            )?),
+        };
+        analyzer.set_attributes(attributes);
+        let render_target = self.renderer.render_target_mut(last_frame_index);
+        self.context
+            .immediate_submit(|commands| analyzer.record(commands, render_target))?;
+        Ok(Some(analyzer.results()?))
+    }
+
+    fn last_frame_index(&self) -> Option<usize> {
+        self.last_present?;
+        let frames_count = self.attributes.in_flight_frames_count;
+        Some((self.frame_index + frames_count - 1) % frames_count)
+    }
+
+    // Resolved depth of the last rendered frame at render resolution, None until a frame has
+    // been rendered.
+    pub fn read_last_frame_depth(&mut self) -> Result<Option<DepthImage>> {
+        let Some(last_frame_index) = self.last_frame_index() else {
+            return Ok(None);
+        };
+        let (_, depth_buffer, allocator) = self.renderer.frame_images_mut(last_frame_index);
+        Ok(Some(self.depth_readback.read_all(allocator, depth_buffer)?))
+    }
+
+    // World position of the surface under the cursor, read from the last frame's depth buffer
+    // instead of bounding boxes like hit_test; None where nothing was drawn.
+    pub fn pick_position(
+        &mut self,
+        position: PhysicalPosition<f64>,
+    ) -> Result<Option<na::Point3<f64>>> {
+        let size = self.window.inner_size();
+        let Some(last_frame_index) = self.last_frame_index() else {
+            return Ok(None);
+        };
+        if position.x < 0.0
+            || position.y < 0.0
+            || position.x >= size.width as f64
+            || position.y >= size.height as f64
+        {
+            return Ok(None);
+        }
+
+        let ndc = na::Point2::new(
+            position.x / size.width as f64 * 2.0 - 1.0,
+            position.y / size.height as f64 * 2.0 - 1.0,
+        );
+        let (_, depth_buffer, allocator) = self.renderer.frame_images_mut(last_frame_index);
+        let extent = depth_buffer.attributes.extent;
+        let x = ((ndc.x * 0.5 + 0.5) * extent.width as f64) as u32;
+        let y = ((ndc.y * 0.5 + 0.5) * extent.height as f64) as u32;
+        let depth = self.depth_readback.read_pixel(
+            allocator,
+            depth_buffer,
+            x.min(extent.width - 1),
+            y.min(extent.height - 1),
+        )?;
+        Ok(self.renderer.unproject(ndc, depth as f64))
+    }
+
+    // The next rendered frame at the window's resolution in 8 bit sRGB, handed to the callback
+    // a few frames later once the GPU is done with it, unlike the blocking readbacks above.
+    pub fn screenshot_async(
+        &mut self,
+        callback: impl FnOnce(Result<::image::RgbaImage>) + 'static,
+    ) {
+        self.readback.request_color(Box::new(callback));
+    }
+
+    // Resolved depth of the next rendered frame, like read_last_frame_depth without the stall.
+    pub fn read_depth_async(&mut self, callback: impl FnOnce(Result<DepthImage>) + 'static) {
+        self.readback.request_depth(None, Box::new(callback));
+    }
+
+    // Like pick_position, from the next rendered frame's depth and camera.
+    pub fn pick_position_async(
+        &mut self,
+        position: PhysicalPosition<f64>,
+        callback: impl FnOnce(Result<Option<na::Point3<f64>>>) + 'static,
+    ) {
+        let size = self.window.inner_size();
+        if position.x < 0.0
+            || position.y < 0.0
+            || position.x >= size.width as f64
+            || position.y >= size.height as f64
+        {
+            callback(Ok(None));
+            return;
+        }
+        let ndc = na::Point2::new(
+            position.x / size.width as f64 * 2.0 - 1.0,
+            position.y / size.height as f64 * 2.0 - 1.0,
+        );
+        self.readback.request_position(ndc, Box::new(callback));
+    }
+
+    // Pass and resource dependencies of the last recorded frame in Graphviz DOT, empty in
+    // release builds.
+    pub fn frame_graph_dot(&self) -> String {
+        self.renderer.frame_graph.to_dot()
+    }
+
+    pub fn export_frame_graph(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.frame_graph_dot())?;
+        Ok(())
+    }
+
+    // Saves every presented frame to the directory until stopped, numbered from zero.
+    pub fn start_capture(
+        &mut self,
+        directory: impl AsRef<Path>,
+        format: CaptureFormat,
+    ) -> Result<()> {
+        if !self.swapchain.supports_capture() {
+            return Err(anyhow!("The swapchain images can't be copied for captures"));
+        }
+        self.stop_capture()?;
+        self.capture = Some(FrameCapture::new(
+            self.context.clone(),
+            directory.as_ref(),
+            format,
+            self.attributes.in_flight_frames_count,
+        )?);
+        Ok(())
+    }
+
+    // Blocks until the frames still in flight are written.
+    pub fn stop_capture(&mut self) -> Result<()> {
+        if let Some(mut capture) = self.capture.take() {
+            unsafe {
+                self.context.device.device_wait_idle()?;
+            }
+            capture.destroy(self.renderer.allocator_mut())?;
+        }
+        Ok(())
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    // Waits until the current frame's previous submission has finished and recycles its
+    // command buffers.
+    fn wait_for_frame(&mut self) -> Result<()> {
+        if self.frame_ready {
+            return Ok(());
+        }
+        let frame = &mut self.frames[self.frame_index];
 
         unsafe {
             self.context
                 .device
                 .wait_for_fences(&[frame.in_flight_fence], true, u64::MAX)?;
+            self.context
+                .device
+                .reset_command_pool(frame.command_pool, vk::CommandPoolResetFlags::empty())?;
+        }
+        frame.used_transient_command_buffers = 0;
+        self.swapchain.collect_retired();
 
-            if self.swapchain.is_dirty {
-                self.context.device.device_wait_idle()?;
-                self.swapchain.resize()?;
-                let swapchain_extent = self.swapchain.extent;
-                if swapchain_extent.width == 0 || swapchain_extent.height == 0 {
-                    return Ok(());
+        if let Some(capture) = &mut self.capture {
+            capture.collect(self.frame_index)?;
+        }
+        self.poll_readbacks()?;
+
+        self.frame_ready = true;
+        Ok(())
+    }
+
+    // Delivers the readbacks of every frame that has finished on the GPU, without waiting for
+    // the others. Called with every rendered frame, and can be called in between.
+    pub fn poll_readbacks(&mut self) -> Result<()> {
+        for (frame_index, frame) in self.frames.iter().enumerate() {
+            if self.readback.is_pending(frame_index)
+                && unsafe {
+                    self.context
+                        .device
+                        .get_fence_status(frame.in_flight_fence)?
                 }
-                self.renderer
-                    .resize(scale_extent(swapchain_extent, self.attributes.ssaa))?;
+            {
+                self.readback
+                    .collect(self.renderer.allocator_mut(), frame_index)?;
             }
+        }
+        Ok(())
+    }
 
-            let swapchain_extent = self.swapchain.extent;
+    // Begins a one time command buffer from the current frame's pool, e.g. for uploads.
+    // It must be submitted to the graphics queue before the frame is rendered, which recycles it
+    // once the frame's fence signals.
+    pub fn transient_commands(&mut self) -> Result<Commands> {
+        self.wait_for_frame()?;
+        let frame = &mut self.frames[self.frame_index];
+
+        if frame.used_transient_command_buffers == frame.transient_command_buffers.len() {
+            let command_buffer = unsafe {
+                self.context.device.allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo::default()
+                        .command_pool(frame.command_pool)
+                        .level(vk::CommandBufferLevel::PRIMARY)
+                        .command_buffer_count(1),
+                )?[0]
+            };
+            frame.transient_command_buffers.push(command_buffer);
+        }
+        let command_buffer = frame.transient_command_buffers[frame.used_transient_command_buffers];
+        frame.used_transient_command_buffers += 1;
 
+        Commands::new(self.context.clone(), command_buffer)
+    }
+
+    // Recreates the swapchain and resizes the renderer to it. Returns whether the window can be
+    // drawn to, it can't while minimized. The old swapchain and render targets are released
+    // once the frames using them are done instead of waiting for the device to idle.
+    fn recreate_swapchain(&mut self) -> Result<bool> {
+        self.swapchain.resize()?;
+        // The window may have moved to a display with another refresh rate.
+        self.present_schedule
+            .lock()
+            .unwrap()
+            .set_refresh_duration(None);
+        let swapchain_extent = self.swapchain.extent;
+        if swapchain_extent.width == 0 || swapchain_extent.height == 0 {
+            return Ok(false);
+        }
+        self.renderer
+            .resize(scale_extent(swapchain_extent, self.attributes.ssaa))?;
+        Ok(true)
+    }
+
+    // A swapchain that went out of date, e.g. during a fast resize, is recreated right away and
+    // the image acquired again, instead of skipping the frame. None while the window can't be
+    // drawn to.
+    fn acquire_image(&mut self) -> Result<Option<u32>> {
+        let image_available_semaphore = self.frames[self.frame_index].image_available_semaphore;
+        for _ in 0..MAX_ACQUIRE_ATTEMPTS {
+            if self.swapchain.is_dirty && !self.recreate_swapchain()? {
+                return Ok(None);
+            }
+            let swapchain_extent = self.swapchain.extent;
             if swapchain_extent.width == 0 || swapchain_extent.height == 0 {
-                return Ok(());
+                return Ok(None);
             }
-
-            let image_index = match self
+            if let Some(image_index) = self
                 .swapchain
-                .acquire_next_image(frame.image_available_semaphore)
+                .acquire_next_image(image_available_semaphore)?
             {
-                Ok(image_index) => image_index,
-                Err(_) => {
-                    self.swapchain.is_dirty = true;
-                    return Ok(());
+                return Ok(Some(image_index));
+            }
+        }
+        // Still out of date, e.g. while the window keeps resizing; retried next frame.
+        trace!("Swapchain still out of date after {MAX_ACQUIRE_ATTEMPTS} recreations");
+        Ok(None)
+    }
+
+    // Records readbacks requested since the last frame from the frame's render target, scaled
+    // to the output extent.
+    fn record_readbacks(
+        &mut self,
+        commands: &Commands,
+        frame_index: usize,
+        output_extent: vk::Extent2D,
+    ) {
+        if !self.readback.has_requests() {
+            return;
+        }
+        let unprojection = self.renderer.unprojection();
+        let (render_target, depth_buffer, allocator) = self.renderer.frame_images_mut(frame_index);
+        self.readback.record(
+            allocator,
+            commands,
+            frame_index,
+            render_target,
+            depth_buffer,
+            output_extent,
+            unprojection,
+        );
+        self.renderer.frame_graph.add_pass(
+            "readback",
+            &[
+                ("render_target", Some(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)),
+                ("depth_buffer", Some(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)),
+            ],
+            &[("readback_buffers", None)],
+        );
+    }
+
+    // Renders the scene at the render target's size without acquiring or presenting, for
+    // MinimizedPolicy::RenderOffscreen. Readbacks are delivered at that size too.
+    fn render_offscreen(&mut self, time: &Time) -> Result<()> {
+        let frame_index = self.frame_index;
+        let frame = &self.frames[frame_index];
+        let graphics_queue = self.context.queues[&self.context.queue_families.graphics];
+        unsafe {
+            self.context.device.reset_fences(&[frame.in_flight_fence])?;
+        }
+        let commands = Commands::new(self.context.clone(), frame.command_buffer)?;
+        let extent = self
+            .renderer
+            .render(&commands, self.attributes.clear_color, frame_index, time)?
+            .attributes
+            .extent;
+        // Shapes only last for the next presented frame, which this stands in for.
+        self.painter.clear();
+        self.record_readbacks(
+            &commands,
+            frame_index,
+            vk::Extent2D {
+                width: extent.width,
+                height: extent.height,
+            },
+        );
+        commands.submit(
+            graphics_queue,
+            (vk::Semaphore::null(), vk::PipelineStageFlags2::NONE),
+            (vk::Semaphore::null(), vk::PipelineStageFlags2::NONE),
+            self.frames[frame_index].in_flight_fence,
+        )?;
+
+        self.frame_index = (self.frame_index + 1) % self.attributes.in_flight_frames_count;
+        self.frame_ready = false;
+        Ok(())
+    }
+
+    pub fn render(&mut self, time: &Time) -> Result<FrameStatus> {
+        if !self.is_frame_due() {
+            return Ok(FrameStatus::NotDue);
+        }
+        self.wait_for_frame()?;
+
+        unsafe {
+            let Some(image_index) = self.acquire_image()? else {
+                let extent = self.swapchain.extent;
+                if extent.width != 0 && extent.height != 0 {
+                    return Ok(FrameStatus::OutOfDate);
+                }
+                self.is_minimized = true;
+                if self.minimized_policy == MinimizedPolicy::RenderOffscreen {
+                    self.render_offscreen(time)?;
+                    return Ok(FrameStatus::RenderedOffscreen);
                 }
+                return Ok(FrameStatus::Minimized);
             };
+            self.is_minimized = false;
+            let swapchain_extent = self.swapchain.extent;
+            let frame = &self.frames[self.frame_index];
 
             trace!(
                 "Rendering frame {} to image {}",
@@ -186,7 +942,7 @@ impl WindowRenderer {
                 image_index
             );
 
-            let graphics_queue = self.context.queues[self.context.queue_families.graphics as usize];
+            let graphics_queue = self.context.queues[&self.context.queue_families.graphics];
 
             self.context.device.reset_fences(&[frame.in_flight_fence])?;
 
@@ -194,30 +950,194 @@ impl WindowRenderer {
 
             let swapchain_image = &mut self.swapchain.images[image_index as usize];
             let commands = Commands::new(self.context.clone(), command_buffer)?;
-            let render_target =
-                self.renderer
-                    .render(&commands, self.attributes.clear_color, self.frame_index)?;
-            commands
-                .blit_full_image(render_target, swapchain_image, self.attributes.ssaa_filter)
-                .transition_image_layout(swapchain_image, ImageLayoutState::present())
-                .submit(
-                    graphics_queue,
-                    (
-                        frame.image_available_semaphore,
-                        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-                    ),
-                    (
-                        frame.render_finished_semaphore,
-                        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-                    ),
-                    frame.in_flight_fence,
+            let frame_index = self.frame_index;
+            self.renderer
+                .render(&commands, self.attributes.clear_color, frame_index, time)?;
+            let (render_target, _, allocator) = self.renderer.frame_images_mut(frame_index);
+            self.painter_renderer
+                .upload(allocator, frame_index, &self.painter)?;
+            self.painter.clear();
+            #[allow(unused_mut)]
+            let mut has_ui = self.ui.is_some() || self.painter_renderer.has_shapes(frame_index);
+            #[cfg(feature = "egui")]
+            if let Some(egui) = &mut self.egui {
+                egui.prepare(
+                    allocator,
+                    frame_index,
+                    swapchain_extent,
+                    self.window.scale_factor(),
                 )?;
+                egui.record_uploads(&commands, frame_index);
+                has_ui |= egui.has_draws(frame_index);
+            }
+            if has_ui {
+                self.ui_pass
+                    .prepare(allocator, frame_index, swapchain_extent)?;
+            }
+            if let Some(capture) = &mut self.capture {
+                capture.prepare(allocator, frame_index, swapchain_extent)?;
+            }
 
-            self.swapchain
-                .present(image_index, frame.render_finished_semaphore)?;
+            self.post_chain
+                .prepare(allocator, frame_index, render_target, time)?;
+            self.tonemapper.prepare(
+                frame_index,
+                self.post_chain.output().unwrap_or(render_target),
+                self.attributes.ssaa_filter,
+            );
+
+            let mut graph = RenderGraph::default();
+            let render_target = graph.import_image("render_target", render_target);
+            let post_output = self
+                .post_chain
+                .add_passes(&mut graph, frame_index, render_target);
+            let swapchain_image = graph.import_image("swapchain_image", swapchain_image);
+            let tonemap = self.attributes.tonemap;
+            let tonemapper = &self.tonemapper;
+            if let Some(auto_exposure) = tonemap.auto_exposure {
+                let delta_seconds = time.unscaled_delta().as_secs_f32();
+                graph.add_pass(
+                    "auto_exposure",
+                    &[GraphAccess::Image(
+                        post_output,
+                        ImageLayoutState::compute_shader_read(),
+                    )],
+                    &[],
+                    move |commands, images| {
+                        tonemapper.record_auto_exposure(
+                            commands,
+                            frame_index,
+                            images.get(post_output),
+                            &auto_exposure,
+                            delta_seconds,
+                        );
+                        Ok(())
+                    },
+                );
+            }
+            graph.add_pass(
+                "tonemap",
+                &[GraphAccess::Image(
+                    post_output,
+                    ImageLayoutState::shader_read(),
+                )],
+                &[GraphAccess::Image(
+                    swapchain_image,
+                    ImageLayoutState::color_attachment(),
+                )],
+                move |commands, images| {
+                    let (source, swapchain_image) = images.pair(post_output, swapchain_image);
+                    tonemapper.record(commands, frame_index, source, swapchain_image, &tonemap);
+                    Ok(())
+                },
+            );
+            if has_ui {
+                let ui = &mut self.ui;
+                let painter_renderer = &self.painter_renderer;
+                #[cfg(feature = "egui")]
+                let egui = &self.egui;
+                let ui_pass = &mut self.ui_pass;
+                graph.add_pass(
+                    "ui",
+                    &[],
+                    &[GraphAccess::Image(
+                        swapchain_image,
+                        ImageLayoutState::color_attachment(),
+                    )],
+                    move |commands, images| {
+                        ui_pass.record(
+                            commands,
+                            frame_index,
+                            images.get(swapchain_image),
+                            |commands, extent| {
+                                if let Some(ui) = ui {
+                                    ui(commands, extent);
+                                }
+                                #[cfg(feature = "egui")]
+                                if let Some(egui) = egui {
+                                    egui.draw(commands, frame_index, extent);
+                                }
+                                painter_renderer.draw(commands, frame_index, extent);
+                            },
+                        )
+                    },
+                );
+            }
+            if let Some(capture) = &mut self.capture {
+                let capture_buffer = graph.import_buffer("capture_buffer");
+                graph.add_pass(
+                    "capture",
+                    // After the UI, so frames are saved as they're presented.
+                    &[GraphAccess::Image(
+                        swapchain_image,
+                        ImageLayoutState::transfer_source(),
+                    )],
+                    &[GraphAccess::Buffer(
+                        capture_buffer,
+                        vk::PipelineStageFlags2::TRANSFER,
+                        vk::AccessFlags2::TRANSFER_WRITE,
+                    )],
+                    move |commands, images| {
+                        capture.record(commands, frame_index, images.get(swapchain_image))
+                    },
+                );
+            }
+            graph.add_pass(
+                "present",
+                &[GraphAccess::Image(
+                    swapchain_image,
+                    ImageLayoutState::present(),
+                )],
+                &[],
+                |_, _| Ok(()),
+            );
+            let passes = graph.execute(&commands)?;
+            self.renderer.frame_graph.add_passes(passes);
+            self.record_readbacks(&commands, frame_index, swapchain_extent);
+            self.swapchain.release_image(&commands, image_index);
+            let frame = &self.frames[frame_index];
+
+            commands.submit(
+                graphics_queue,
+                (
+                    frame.image_available_semaphore,
+                    vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                ),
+                // All commands, so the signal also covers the release of the swapchain image
+                // to a separate present queue family.
+                (
+                    frame.render_finished_semaphore,
+                    vk::PipelineStageFlags2::ALL_COMMANDS,
+                ),
+                frame.in_flight_fence,
+            )?;
+
+            let has_present_timing = self.has_present_timing();
+            let mut present_schedule = self.present_schedule.lock().unwrap();
+            if present_schedule.refresh_duration().is_none() {
+                present_schedule.set_refresh_duration(self.swapchain.refresh_cycle_duration());
+            }
+            let mut present_time = present_schedule.present_time();
+            if !self.scheduled_presents {
+                present_time.desired_present_time = 0;
+            }
+            self.swapchain.present(
+                image_index,
+                frame.render_finished_semaphore,
+                has_present_timing.then_some(present_time),
+            )?;
+            self.last_present = Some(Instant::now());
+            let timings = self.swapchain.past_presentation_timing();
+            let present = present_schedule.next_present();
+            present_schedule.presented(&timings);
+            drop(present_schedule);
+            if let Some(present_feedback) = &mut self.present_feedback {
+                present_feedback.presented(present, image_index, &timings, has_present_timing);
+            }
 
             self.frame_index = (self.frame_index + 1) % self.attributes.in_flight_frames_count;
-            Ok(())
+            self.frame_ready = false;
+            Ok(FrameStatus::Presented)
         }
     }
 }
@@ -227,6 +1147,24 @@ impl Drop for WindowRenderer {
         unsafe {
             self.context.device.device_wait_idle().unwrap();
 
+            let allocator = self.renderer.allocator_mut();
+            if let Some(capture) = &mut self.capture {
+                capture.destroy(allocator).unwrap();
+            }
+            self.readback.destroy(allocator).unwrap();
+            self.depth_readback.destroy(allocator).unwrap();
+            if let Some(image_analyzer) = &mut self.image_analyzer {
+                image_analyzer.destroy(allocator).unwrap();
+            }
+            self.post_chain.destroy(allocator).unwrap();
+            self.tonemapper.destroy(allocator).unwrap();
+            self.ui_pass.destroy(allocator).unwrap();
+            self.painter_renderer.destroy(allocator).unwrap();
+            #[cfg(feature = "egui")]
+            if let Some(egui) = &mut self.egui {
+                egui.destroy(allocator).unwrap();
+            }
+
             self.frames.drain(..).for_each(|frame| {
                 self.context
                     .device
@@ -237,13 +1175,11 @@ impl Drop for WindowRenderer {
                 self.context
                     .device
                     .destroy_fence(frame.in_flight_fence, None);
+                // Frees the frame's command buffers along with it.
                 self.context
                     .device
-                    .free_command_buffers(self.command_pool, &[frame.command_buffer]);
+                    .destroy_command_pool(frame.command_pool, None);
             });
-            self.context
-                .device
-                .destroy_command_pool(self.command_pool, None);
         }
     }
 }
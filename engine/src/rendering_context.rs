@@ -1,19 +1,30 @@
 pub use crate::image::{Image, ImageAttributes, ImageLayoutState};
+use crate::renderer::commands::Commands;
+use crate::shader_reflection::{ReflectedBinding, ShaderReflection};
 use anyhow::Result;
 use ash::vk;
 use ash::vk::{DeviceQueueInfo2, SurfaceCapabilitiesKHR};
 use gpu_allocator::vulkan::{Allocator, AllocatorCreateDesc};
 use gpu_allocator::{AllocationSizes, AllocatorDebugSettings};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io;
+use std::sync::Arc;
 use winit::raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 use winit::window::Window;
 
 pub struct RenderingContext {
-    pub queues: Vec<vk::Queue>,
+    // First queue of every family in queue_family_indices, by family index.
+    pub queues: HashMap<u32, vk::Queue>,
     pub pageable_device_local_memory_extension:
         Option<ash::ext::pageable_device_local_memory::Device>,
+    pub conditional_rendering_extension: Option<ash::ext::conditional_rendering::Device>,
+    // Reports when presented images actually reached the display, mostly on Android and Mesa.
+    pub display_timing_extension: Option<ash::google::display_timing::Device>,
+    // Only present when sparse residency of 2D images is supported.
+    pub sparse_binding_queue: Option<vk::Queue>,
     pub swapchain_extension: ash::khr::swapchain::Device,
+    // Physical devices backing the logical device, more than one when a device group was created.
+    pub device_group: Vec<vk::PhysicalDevice>,
     pub device: ash::Device,
     pub queue_family_indices: HashSet<u32>,
     pub queue_families: QueueFamilies,
@@ -27,17 +38,21 @@ pub struct RenderingContext {
 pub struct QueueFamily {
     pub index: u32,
     pub properties: vk::QueueFamilyProperties,
+    // Whether it can present to the window the context was created for.
+    pub supports_present: bool,
 }
 
 #[derive(Debug)]
 pub struct PhysicalDevice {
     pub handle: vk::PhysicalDevice,
     pub properties: vk::PhysicalDeviceProperties,
+    pub depth_stencil_resolve_properties: vk::PhysicalDeviceDepthStencilResolveProperties<'static>,
     pub features: vk::PhysicalDeviceFeatures,
     pub vulkan12_features: vk::PhysicalDeviceVulkan12Features<'static>,
     pub vulkan13_features: vk::PhysicalDeviceVulkan13Features<'static>,
     pub pageable_device_local_memory_features:
         vk::PhysicalDevicePageableDeviceLocalMemoryFeaturesEXT<'static>,
+    pub conditional_rendering_features: vk::PhysicalDeviceConditionalRenderingFeaturesEXT<'static>,
     pub memory_properties: vk::PhysicalDeviceMemoryProperties,
     pub queue_families: Vec<QueueFamily>,
 }
@@ -47,17 +62,19 @@ type QueueFamilyPicker = fn(Vec<PhysicalDevice>) -> Result<(PhysicalDevice, Queu
 pub struct RenderingContextAttributes<'window> {
     pub compatibility_window: &'window Window,
     pub queue_family_picker: QueueFamilyPicker,
+    pub device_group: bool,
 }
 
 pub struct QueueFamilies {
     pub graphics: u32,
+    // Swapchain images are handed over from the graphics family when they differ.
     pub present: u32,
     pub transfer: u32,
     pub compute: u32,
 }
 
 pub mod queue_family_picker {
-    use crate::rendering_context::{PhysicalDevice, QueueFamilies};
+    use crate::rendering_context::{PhysicalDevice, QueueFamilies, QueueFamily};
     use anyhow::Context as AnyhowContext;
     use anyhow::Result;
     use ash::vk;
@@ -69,16 +86,7 @@ pub mod queue_family_picker {
         let queue_family = physical_device
             .queue_families
             .iter()
-            .find(|queue_family| {
-                queue_family
-                    .properties
-                    .queue_flags
-                    .contains(vk::QueueFlags::GRAPHICS)
-                    && queue_family
-                        .properties
-                        .queue_flags
-                        .contains(vk::QueueFlags::COMPUTE)
-            })
+            .find(|queue_family| is_graphics_family(queue_family) && queue_family.supports_present)
             .map(|queue_family| queue_family.index)
             .context("No suitable queue family found")?;
         Ok((
@@ -91,6 +99,48 @@ pub mod queue_family_picker {
             },
         ))
     }
+
+    // Presents from another family than the graphics one where the device has one that can,
+    // e.g. to exercise the ownership transfer of swapchain images on hardware that allows it.
+    pub fn dedicated_present_queue_family(
+        physical_devices: Vec<PhysicalDevice>,
+    ) -> Result<(PhysicalDevice, QueueFamilies)> {
+        let physical_device = physical_devices.into_iter().next().unwrap();
+        let graphics = physical_device
+            .queue_families
+            .iter()
+            .find(|queue_family| is_graphics_family(queue_family))
+            .map(|queue_family| queue_family.index)
+            .context("No suitable queue family found")?;
+        let present_families = physical_device
+            .queue_families
+            .iter()
+            .filter(|queue_family| queue_family.supports_present)
+            .map(|queue_family| queue_family.index)
+            .collect::<Vec<_>>();
+        let present = present_families
+            .iter()
+            .copied()
+            .find(|&index| index != graphics)
+            .or(present_families.first().copied())
+            .context("No queue family can present")?;
+        Ok((
+            physical_device,
+            QueueFamilies {
+                graphics,
+                present,
+                transfer: graphics,
+                compute: graphics,
+            },
+        ))
+    }
+
+    fn is_graphics_family(queue_family: &QueueFamily) -> bool {
+        queue_family
+            .properties
+            .queue_flags
+            .contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+    }
 }
 
 macro_rules! check_feature {
@@ -156,15 +206,23 @@ impl RenderingContext {
                 .enumerate_physical_devices()?
                 .into_iter()
                 .map(|handle| {
-                    let properties = instance.get_physical_device_properties(handle);
+                    let mut depth_stencil_resolve_properties =
+                        vk::PhysicalDeviceDepthStencilResolveProperties::default();
+                    let mut properties = vk::PhysicalDeviceProperties2::default()
+                        .push_next(&mut depth_stencil_resolve_properties);
+                    instance.get_physical_device_properties2(handle, &mut properties);
+                    let properties = properties.properties;
                     let mut vulkan12_features = vk::PhysicalDeviceVulkan12Features::default();
                     let mut vulkan13_features = vk::PhysicalDeviceVulkan13Features::default();
                     let mut pageable_device_local_memory_features =
                         vk::PhysicalDevicePageableDeviceLocalMemoryFeaturesEXT::default();
+                    let mut conditional_rendering_features =
+                        vk::PhysicalDeviceConditionalRenderingFeaturesEXT::default();
                     let mut features = vk::PhysicalDeviceFeatures2::default()
                         .push_next(&mut vulkan12_features)
                         .push_next(&mut vulkan13_features)
-                        .push_next(&mut pageable_device_local_memory_features);
+                        .push_next(&mut pageable_device_local_memory_features)
+                        .push_next(&mut conditional_rendering_features);
                     instance.get_physical_device_features2(handle, &mut features);
                     let features = features.features;
                     let memory_properties = instance.get_physical_device_memory_properties(handle);
@@ -177,16 +235,25 @@ impl RenderingContext {
                         .map(|(index, properties)| QueueFamily {
                             index: index as u32,
                             properties,
+                            supports_present: surface_extension
+                                .get_physical_device_surface_support(
+                                    handle,
+                                    index as u32,
+                                    compatibility_surface,
+                                )
+                                .unwrap_or(false),
                         })
                         .collect::<Vec<_>>();
 
                     PhysicalDevice {
                         handle,
                         properties,
+                        depth_stencil_resolve_properties,
                         features,
                         vulkan12_features,
                         vulkan13_features,
                         pageable_device_local_memory_features,
+                        conditional_rendering_features,
                         memory_properties,
                         queue_families,
                     }
@@ -194,9 +261,10 @@ impl RenderingContext {
                 .collect::<Vec<_>>();
 
             physical_devices.retain(|device| {
-                surface_extension
-                    .get_physical_device_surface_support(device.handle, 0, compatibility_surface)
-                    .unwrap_or(false)
+                device
+                    .queue_families
+                    .iter()
+                    .any(|queue_family| queue_family.supports_present)
             });
 
             surface_extension.destroy_surface(compatibility_surface, None);
@@ -242,20 +310,93 @@ impl RenderingContext {
                 .pageable_device_local_memory
                 == vk::TRUE;
 
+            let is_conditional_rendering_supported = physical_device
+                .conditional_rendering_features
+                .conditional_rendering
+                == vk::TRUE;
+
+            let is_sparse_residency_supported = physical_device.features.sparse_binding == vk::TRUE
+                && physical_device.features.sparse_residency_image2_d == vk::TRUE;
+            // Sparse binds are submitted to one of the already created queues.
+            let sparse_binding_queue_family = queue_family_indices
+                .iter()
+                .copied()
+                .find(|&index| {
+                    physical_device.queue_families[index as usize]
+                        .properties
+                        .queue_flags
+                        .contains(vk::QueueFlags::SPARSE_BINDING)
+                })
+                .filter(|_| is_sparse_residency_supported);
+
+            let mut device_group = vec![physical_device.handle];
+            if attributes.device_group {
+                let mut groups = vec![
+                    vk::PhysicalDeviceGroupProperties::default();
+                    instance.enumerate_physical_device_groups_len()?
+                ];
+                instance.enumerate_physical_device_groups(&mut groups)?;
+                if let Some(group) = groups.iter().find(|group| {
+                    group.physical_device_count > 1
+                        && group.physical_devices[..group.physical_device_count as usize]
+                            .contains(&physical_device.handle)
+                }) {
+                    device_group =
+                        group.physical_devices[..group.physical_device_count as usize].to_vec();
+                }
+            }
+
+            let available_device_extensions = instance
+                .enumerate_device_extension_properties(physical_device.handle)?
+                .into_iter()
+                .map(|extension| {
+                    std::ffi::CStr::from_ptr(extension.extension_name.as_ptr()).to_owned()
+                })
+                .collect::<HashSet<_>>();
+            let is_display_timing_supported =
+                available_device_extensions.contains(ash::google::display_timing::NAME);
+
             let mut device_extensions = vec![ash::khr::swapchain::NAME.as_ptr()];
 
             let mut pageable_device_local_memory_extension = None;
+            let mut conditional_rendering_extension = None;
+            let mut display_timing_extension = None;
 
             if is_pageable_device_local_memory_supported {
                 device_extensions.push(ash::ext::memory_priority::NAME.as_ptr());
                 device_extensions.push(ash::ext::pageable_device_local_memory::NAME.as_ptr());
             }
 
+            if is_conditional_rendering_supported {
+                device_extensions.push(ash::ext::conditional_rendering::NAME.as_ptr());
+            }
+
+            if is_display_timing_supported {
+                device_extensions.push(ash::google::display_timing::NAME.as_ptr());
+            }
+
             let device = instance.create_device(
                 physical_device.handle,
                 &vk::DeviceCreateInfo::default()
                     .queue_create_infos(&queue_create_infos)
                     .enabled_extension_names(&device_extensions)
+                    .enabled_features(
+                        &vk::PhysicalDeviceFeatures::default()
+                            .wide_lines(physical_device.features.wide_lines == vk::TRUE)
+                            .large_points(physical_device.features.large_points == vk::TRUE)
+                            .texture_compression_bc(
+                                physical_device.features.texture_compression_bc == vk::TRUE,
+                            )
+                            .multi_draw_indirect(
+                                physical_device.features.multi_draw_indirect == vk::TRUE,
+                            )
+                            .draw_indirect_first_instance(
+                                physical_device.features.draw_indirect_first_instance == vk::TRUE,
+                            )
+                            .multi_viewport(physical_device.features.multi_viewport == vk::TRUE)
+                            .sparse_binding(sparse_binding_queue_family.is_some())
+                            .sparse_residency_image2_d(sparse_binding_queue_family.is_some()),
+                    )
                     .push_next(
                         &mut vk::PhysicalDeviceVulkan12Features::default()
                             .buffer_device_address(true)
@@ -265,7 +406,19 @@ impl RenderingContext {
                             .scalar_block_layout(true)
                             .shader_sampled_image_array_non_uniform_indexing(true)
                             .descriptor_binding_sampled_image_update_after_bind(true)
-                            .descriptor_binding_partially_bound(true),
+                            .descriptor_binding_partially_bound(true)
+                            .draw_indirect_count(
+                                physical_device.vulkan12_features.draw_indirect_count == vk::TRUE,
+                            )
+                            .shader_output_viewport_index(
+                                physical_device
+                                    .vulkan12_features
+                                    .shader_output_viewport_index
+                                    == vk::TRUE,
+                            )
+                            .shader_output_layer(
+                                physical_device.vulkan12_features.shader_output_layer == vk::TRUE,
+                            ),
                     )
                     .push_next(
                         &mut vk::PhysicalDeviceVulkan13Features::default()
@@ -277,6 +430,14 @@ impl RenderingContext {
                             .pageable_device_local_memory(
                                 is_pageable_device_local_memory_supported,
                             ),
+                    )
+                    .push_next(
+                        &mut vk::PhysicalDeviceConditionalRenderingFeaturesEXT::default()
+                            .conditional_rendering(is_conditional_rendering_supported),
+                    )
+                    .push_next(
+                        &mut vk::DeviceGroupDeviceCreateInfo::default()
+                            .physical_devices(&device_group),
                     ),
                 None,
             )?;
@@ -287,15 +448,33 @@ impl RenderingContext {
                 );
             }
 
+            if is_conditional_rendering_supported {
+                conditional_rendering_extension = Some(
+                    ash::ext::conditional_rendering::Device::new(&instance, &device),
+                );
+            }
+
+            if is_display_timing_supported {
+                display_timing_extension =
+                    Some(ash::google::display_timing::Device::new(&instance, &device));
+            }
+
             let swapchain_extension = ash::khr::swapchain::Device::new(&instance, &device);
 
+            let sparse_binding_queue =
+                sparse_binding_queue_family.map(|index| device.get_device_queue(index, 0));
+
             let queues = queue_family_indices
                 .iter()
                 .map(|index| {
-                    device
-                        .get_device_queue2(&DeviceQueueInfo2::default().queue_family_index(*index))
+                    (
+                        *index,
+                        device.get_device_queue2(
+                            &DeviceQueueInfo2::default().queue_family_index(*index),
+                        ),
+                    )
                 })
-                .collect::<Vec<_>>();
+                .collect::<HashMap<_, _>>();
 
             Ok(Self {
                 queues,
@@ -307,11 +486,72 @@ impl RenderingContext {
                 instance,
                 entry,
                 swapchain_extension,
+                device_group,
                 pageable_device_local_memory_extension,
+                conditional_rendering_extension,
+                display_timing_extension,
+                sparse_binding_queue,
             })
         }
     }
 
+    // Multi draw indirect with a GPU written draw count and per draw first instance.
+    pub fn supports_draw_indirect_count(&self) -> bool {
+        let features = &self.physical_device.features;
+        features.multi_draw_indirect == vk::TRUE
+            && features.draw_indirect_first_instance == vk::TRUE
+            && self.physical_device.vulkan12_features.draw_indirect_count == vk::TRUE
+    }
+
+    // Pipelines with several viewports picked by the vertex shader, which also picks the layer
+    // of a layered attachment, so the six faces of a cube are drawn in a single pass.
+    pub fn supports_viewport_arrays(&self) -> bool {
+        let physical_device = &self.physical_device;
+        physical_device.features.multi_viewport == vk::TRUE
+            && physical_device
+                .vulkan12_features
+                .shader_output_viewport_index
+                == vk::TRUE
+            && physical_device.vulkan12_features.shader_output_layer == vk::TRUE
+            && physical_device.properties.limits.max_viewports >= 6
+    }
+
+    // The preferred depth resolve mode if supported, otherwise SAMPLE_ZERO which every device
+    // supports. MIN keeps the nearest surface of each pixel, which suits picking.
+    pub fn depth_resolve_mode(&self, preferred: vk::ResolveModeFlags) -> vk::ResolveModeFlags {
+        let supported = self
+            .physical_device
+            .depth_stencil_resolve_properties
+            .supported_depth_resolve_modes;
+        if !preferred.is_empty() && supported.contains(preferred) {
+            preferred
+        } else {
+            vk::ResolveModeFlags::SAMPLE_ZERO
+        }
+    }
+
+    pub fn supports_sparse_residency(&self) -> bool {
+        self.sparse_binding_queue.is_some()
+    }
+
+    pub fn device_count(&self) -> u32 {
+        self.device_group.len() as u32
+    }
+
+    // Panics for devices that aren't in the group, their bit would be invalid in any mask.
+    pub fn device_mask(&self, device_index: u32) -> u32 {
+        assert!(
+            device_index < self.device_count(),
+            "Device {device_index} is out of range, the group has {} devices",
+            self.device_count()
+        );
+        1 << device_index
+    }
+
+    pub fn all_devices_mask(&self) -> u32 {
+        (1 << self.device_count()) - 1
+    }
+
     // safety: The window should outlive the surface.
     pub unsafe fn create_surface(&self, window: &Window) -> Result<Surface> {
         let raw_display_handle = window.display_handle()?.as_raw();
@@ -353,84 +593,278 @@ impl RenderingContext {
         Ok(shader_module)
     }
 
+    // Descriptor set layouts and the pipeline layout declared by the reflected shaders. Runtime
+    // arrays get runtime_array_count descriptors and binding_flags picks the flags of each
+    // binding; the caller destroys the returned layouts.
+    pub fn create_reflected_pipeline_layout(
+        &self,
+        reflection: &ShaderReflection,
+        set_layout_flags: vk::DescriptorSetLayoutCreateFlags,
+        runtime_array_count: u32,
+        binding_flags: impl Fn(&ReflectedBinding) -> vk::DescriptorBindingFlags,
+    ) -> Result<(Vec<vk::DescriptorSetLayout>, vk::PipelineLayout)> {
+        let mut set_layouts = Vec::new();
+        let destroy_set_layouts = |set_layouts: &[vk::DescriptorSetLayout]| unsafe {
+            for set_layout in set_layouts {
+                self.device.destroy_descriptor_set_layout(*set_layout, None);
+            }
+        };
+
+        for set in 0..reflection.set_count() {
+            let (reflected, bindings): (Vec<_>, Vec<_>) = reflection
+                .set_layout_bindings(set, runtime_array_count)
+                .into_iter()
+                .unzip();
+            let flags = reflected.iter().map(&binding_flags).collect::<Vec<_>>();
+            let set_layout = unsafe {
+                self.device.create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::default()
+                        .bindings(&bindings)
+                        .flags(set_layout_flags)
+                        .push_next(
+                            &mut vk::DescriptorSetLayoutBindingFlagsCreateInfo::default()
+                                .binding_flags(&flags),
+                        ),
+                    None,
+                )
+            };
+            match set_layout {
+                Ok(set_layout) => set_layouts.push(set_layout),
+                Err(error) => {
+                    destroy_set_layouts(&set_layouts);
+                    return Err(error.into());
+                }
+            }
+        }
+
+        let push_constant_ranges = reflection
+            .push_constant_range()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let pipeline_layout = unsafe {
+            self.device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .push_constant_ranges(&push_constant_ranges)
+                    .set_layouts(&set_layouts),
+                None,
+            )
+        };
+        match pipeline_layout {
+            Ok(pipeline_layout) => Ok((set_layouts, pipeline_layout)),
+            Err(error) => {
+                destroy_set_layouts(&set_layouts);
+                Err(error.into())
+            }
+        }
+    }
+
+    // Returns the preferred format when it can be used as a depth attachment, otherwise the most
+    // precise supported one with the same stencil requirement.
+    pub fn pick_depth_format(&self, preferred: vk::Format) -> Result<vk::Format> {
+        let candidates: &[vk::Format] = if has_stencil_component(preferred) {
+            &DEPTH_STENCIL_FORMATS
+        } else {
+            &DEPTH_FORMATS
+        };
+        self.best_supported(
+            std::iter::once(preferred).chain(candidates.iter().copied()),
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::ImageTiling::OPTIMAL,
+        )
+        .ok_or_else(|| anyhow::anyhow!("No supported depth format found"))
+    }
+
+    pub fn supports_format(
+        &self,
+        format: vk::Format,
+        features: vk::FormatFeatureFlags,
+        tiling: vk::ImageTiling,
+    ) -> bool {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device.handle, format)
+        };
+        match tiling {
+            vk::ImageTiling::LINEAR => properties.linear_tiling_features.contains(features),
+            _ => properties.optimal_tiling_features.contains(features),
+        }
+    }
+
+    // First of the formats, in order of preference, supporting all the features.
+    pub fn best_supported(
+        &self,
+        formats: impl IntoIterator<Item = vk::Format>,
+        features: vk::FormatFeatureFlags,
+        tiling: vk::ImageTiling,
+    ) -> Option<vk::Format> {
+        formats
+            .into_iter()
+            .find(|&format| self.supports_format(format, features, tiling))
+    }
+
+    // Without wide_lines support only a width of 1.0 is valid.
+    pub fn clamp_line_width(&self, line_width: f32) -> f32 {
+        if self.physical_device.features.wide_lines == vk::TRUE {
+            let [min, max] = self.physical_device.properties.limits.line_width_range;
+            line_width.clamp(min, max)
+        } else {
+            1.0
+        }
+    }
+
+    // Without large_points support only a size of 1.0 is guaranteed.
+    pub fn clamp_point_size(&self, point_size: f32) -> f32 {
+        if self.physical_device.features.large_points == vk::TRUE {
+            let [min, max] = self.physical_device.properties.limits.point_size_range;
+            point_size.clamp(min, max)
+        } else {
+            1.0
+        }
+    }
+
+    pub fn create_compute_pipeline(
+        &self,
+        attributes: ComputePipelineAttributes,
+    ) -> Result<vk::Pipeline> {
+        let entry_point = std::ffi::CString::new("main")?;
+        let pipeline = unsafe {
+            self.device
+                .create_compute_pipelines(
+                    attributes.pipeline_cache,
+                    &[vk::ComputePipelineCreateInfo::default()
+                        .stage(
+                            vk::PipelineShaderStageCreateInfo::default()
+                                .stage(vk::ShaderStageFlags::COMPUTE)
+                                .module(attributes.shader)
+                                .name(&entry_point),
+                        )
+                        .layout(attributes.pipeline_layout)],
+                    None,
+                )
+                .map_err(|(_, error)| error)?[0]
+        };
+        Ok(pipeline)
+    }
+
     pub fn create_graphics_pipeline(
         &self,
-        vertex_shader: vk::ShaderModule,
-        fragment_shader: vk::ShaderModule,
-        image_extent: vk::Extent2D,
-        image_format: vk::Format,
-        depth_format: vk::Format,
-        pipeline_layout: vk::PipelineLayout,
-        pipeline_cache: vk::PipelineCache,
+        attributes: GraphicsPipelineAttributes,
     ) -> Result<vk::Pipeline> {
         let entry_point = std::ffi::CString::new("main")?;
 
+        let is_strip_topology = matches!(
+            attributes.topology,
+            vk::PrimitiveTopology::LINE_STRIP | vk::PrimitiveTopology::TRIANGLE_STRIP
+        );
+
+        let line_width = self.clamp_line_width(attributes.line_width);
+
+        let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        // Every viewport and scissor starts out covering the extent, they're dynamic anyway.
+        let viewports = vec![
+            vk::Viewport::default()
+                .width(attributes.extent.width as f32)
+                .height(attributes.extent.height as f32)
+                .max_depth(1.0);
+            attributes.viewport_count.max(1) as usize
+        ];
+        let scissors = vec![vk::Rect2D::default().extent(attributes.extent); viewports.len()];
+        if attributes.dynamic_line_width {
+            dynamic_states.push(vk::DynamicState::LINE_WIDTH);
+        }
+        if attributes.dynamic_topology {
+            dynamic_states.push(vk::DynamicState::PRIMITIVE_TOPOLOGY);
+        }
+
+        // Depth only pipelines, e.g. for shadow maps, pass an undefined color format.
+        let color_attachment_formats = if attributes.color_format == vk::Format::UNDEFINED {
+            &[][..]
+        } else {
+            std::slice::from_ref(&attributes.color_format)
+        };
+        let color_blend_attachments = color_attachment_formats
+            .iter()
+            .map(|_| {
+                vk::PipelineColorBlendAttachmentState::default()
+                    .color_write_mask(vk::ColorComponentFlags::RGBA)
+                    .blend_enable(attributes.alpha_blending || attributes.additive_blending)
+                    .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                    .dst_color_blend_factor(if attributes.additive_blending {
+                        vk::BlendFactor::ONE
+                    } else {
+                        vk::BlendFactor::ONE_MINUS_SRC_ALPHA
+                    })
+                    .color_blend_op(vk::BlendOp::ADD)
+                    .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                    .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                    .alpha_blend_op(vk::BlendOp::ADD)
+            })
+            .collect::<Vec<_>>();
+
+        let vertex_bindings = attributes.vertex_layout.bindings();
+        let vertex_attributes = attributes.vertex_layout.attributes();
+
         unsafe {
             Ok(self
                 .device
                 .create_graphics_pipelines(
-                    pipeline_cache,
+                    attributes.pipeline_cache,
                     &[vk::GraphicsPipelineCreateInfo::default()
                         .stages(&[
                             vk::PipelineShaderStageCreateInfo::default()
                                 .stage(vk::ShaderStageFlags::VERTEX)
-                                .module(vertex_shader)
+                                .module(attributes.vertex_shader)
                                 .name(&entry_point),
                             vk::PipelineShaderStageCreateInfo::default()
                                 .stage(vk::ShaderStageFlags::FRAGMENT)
-                                .module(fragment_shader)
+                                .module(attributes.fragment_shader)
                                 .name(&entry_point),
                         ])
-                        .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+                        .vertex_input_state(
+                            &vk::PipelineVertexInputStateCreateInfo::default()
+                                .vertex_binding_descriptions(&vertex_bindings)
+                                .vertex_attribute_descriptions(&vertex_attributes),
+                        )
                         .input_assembly_state(
                             &vk::PipelineInputAssemblyStateCreateInfo::default()
-                                .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                                .topology(attributes.topology)
+                                .primitive_restart_enable(is_strip_topology),
                         )
                         .viewport_state(
                             &vk::PipelineViewportStateCreateInfo::default()
-                                .viewports(&[vk::Viewport::default()
-                                    .width(image_extent.width as f32)
-                                    .height(image_extent.height as f32)
-                                    .max_depth(1.0)])
-                                .scissors(&[vk::Rect2D::default().extent(image_extent)]),
+                                .viewports(&viewports)
+                                .scissors(&scissors),
                         )
                         .rasterization_state(
                             &vk::PipelineRasterizationStateCreateInfo::default()
                                 .polygon_mode(vk::PolygonMode::FILL)
-                                .cull_mode(vk::CullModeFlags::NONE)
+                                .cull_mode(attributes.cull_mode)
                                 .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-                                .line_width(1.0),
-                        )
-                        .multisample_state(
-                            &vk::PipelineMultisampleStateCreateInfo::default()
-                                .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                                .line_width(line_width),
                         )
                         .color_blend_state(
                             &vk::PipelineColorBlendStateCreateInfo::default()
-                                .attachments(&[vk::PipelineColorBlendAttachmentState::default()
-                                    .color_write_mask(vk::ColorComponentFlags::RGBA)]),
+                                .attachments(&color_blend_attachments),
                         )
                         .dynamic_state(
-                            &vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&[
-                                vk::DynamicState::VIEWPORT,
-                                vk::DynamicState::SCISSOR,
-                            ]),
+                            &vk::PipelineDynamicStateCreateInfo::default()
+                                .dynamic_states(&dynamic_states),
                         )
-                        .layout(pipeline_layout)
+                        .layout(attributes.pipeline_layout)
                         .depth_stencil_state(
                             &vk::PipelineDepthStencilStateCreateInfo::default()
                                 .depth_test_enable(true)
-                                .depth_write_enable(true)
+                                .depth_write_enable(attributes.depth_write)
                                 .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL),
                         )
                         .multisample_state(
                             &vk::PipelineMultisampleStateCreateInfo::default()
-                                .rasterization_samples(vk::SampleCountFlags::TYPE_4),
+                                .rasterization_samples(attributes.samples),
                         )
                         .push_next(
                             &mut vk::PipelineRenderingCreateInfo::default()
-                                .color_attachment_formats(&[image_format])
-                                .depth_attachment_format(depth_format),
+                                .color_attachment_formats(color_attachment_formats)
+                                .depth_attachment_format(attributes.depth_format),
                         )],
                     None,
                 )
@@ -441,6 +875,55 @@ impl RenderingContext {
         }
     }
 
+    // Records commands into a one time command buffer, submits it to the graphics queue and
+    // waits for it to complete, e.g. for uploads at initialization.
+    pub fn immediate_submit<T>(
+        self: &Arc<Self>,
+        record: impl FnOnce(&Commands) -> Result<T>,
+    ) -> Result<T> {
+        unsafe {
+            let command_pool = self.device.create_command_pool(
+                &vk::CommandPoolCreateInfo::default()
+                    .queue_family_index(self.queue_families.graphics)
+                    .flags(vk::CommandPoolCreateFlags::TRANSIENT),
+                None,
+            )?;
+            let fence = match self
+                .device
+                .create_fence(&vk::FenceCreateInfo::default(), None)
+            {
+                Ok(fence) => fence,
+                Err(error) => {
+                    self.device.destroy_command_pool(command_pool, None);
+                    return Err(error.into());
+                }
+            };
+
+            let result = (|| {
+                let command_buffer = self.device.allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo::default()
+                        .command_pool(command_pool)
+                        .level(vk::CommandBufferLevel::PRIMARY)
+                        .command_buffer_count(1),
+                )?[0];
+                let commands = Commands::new(self.clone(), command_buffer)?;
+                let value = record(&commands)?;
+                commands.submit(
+                    self.queues[&self.queue_families.graphics],
+                    Default::default(),
+                    Default::default(),
+                    fence,
+                )?;
+                self.device.wait_for_fences(&[fence], true, u64::MAX)?;
+                Ok(value)
+            })();
+
+            self.device.destroy_fence(fence, None);
+            self.device.destroy_command_pool(command_pool, None);
+            result
+        }
+    }
+
     pub fn create_allocator(
         &self,
         debug_settings: AllocatorDebugSettings,
@@ -457,6 +940,106 @@ impl RenderingContext {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub format: vk::Format,
+    pub offset: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct VertexBufferLayout {
+    pub stride: u32,
+    pub input_rate: vk::VertexInputRate,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+// Classic vertex input bindings, one per buffer in binding order. Empty for pipelines that pull
+// their vertices through buffer device addresses.
+#[derive(Clone, Debug, Default)]
+pub struct VertexLayout {
+    pub buffers: Vec<VertexBufferLayout>,
+}
+
+impl VertexLayout {
+    pub fn bindings(&self) -> Vec<vk::VertexInputBindingDescription> {
+        self.buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| {
+                vk::VertexInputBindingDescription::default()
+                    .binding(binding as u32)
+                    .stride(buffer.stride)
+                    .input_rate(buffer.input_rate)
+            })
+            .collect()
+    }
+
+    pub fn attributes(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        self.buffers
+            .iter()
+            .enumerate()
+            .flat_map(|(binding, buffer)| {
+                buffer.attributes.iter().map(move |attribute| {
+                    vk::VertexInputAttributeDescription::default()
+                        .binding(binding as u32)
+                        .location(attribute.location)
+                        .format(attribute.format)
+                        .offset(attribute.offset)
+                })
+            })
+            .collect()
+    }
+}
+
+// Depth formats from the most to the least precise.
+const DEPTH_FORMATS: [vk::Format; 2] = [vk::Format::D32_SFLOAT, vk::Format::D16_UNORM];
+const DEPTH_STENCIL_FORMATS: [vk::Format; 3] = [
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D16_UNORM_S8_UINT,
+];
+
+pub fn has_stencil_component(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::S8_UINT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+pub struct GraphicsPipelineAttributes {
+    pub vertex_shader: vk::ShaderModule,
+    pub fragment_shader: vk::ShaderModule,
+    pub extent: vk::Extent2D,
+    pub color_format: vk::Format,
+    pub depth_format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+    pub topology: vk::PrimitiveTopology,
+    pub line_width: f32,
+    pub dynamic_line_width: bool,
+    pub dynamic_topology: bool,
+    pub alpha_blending: bool,
+    // Adds the color onto the target instead, for glows and flares, alpha still scales it.
+    pub additive_blending: bool,
+    pub depth_write: bool,
+    pub cull_mode: vk::CullModeFlags,
+    // More than one requires supports_viewport_arrays, the vertex shader then writes
+    // gl_ViewportIndex.
+    pub viewport_count: u32,
+    pub vertex_layout: VertexLayout,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline_cache: vk::PipelineCache,
+}
+
+pub struct ComputePipelineAttributes {
+    pub shader: vk::ShaderModule,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline_cache: vk::PipelineCache,
+}
+
 pub struct Surface {
     pub handle: vk::SurfaceKHR,
     pub capabilities: SurfaceCapabilitiesKHR,
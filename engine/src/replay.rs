@@ -0,0 +1,216 @@
+use crate::renderer::Renderer;
+use crate::time::Time;
+use anyhow::{anyhow, Result};
+use nalgebra as na;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+const RECORDING_MAGIC: &[u8; 8] = b"SCNREC\0\0";
+const RECORDING_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 12;
+
+#[derive(Clone, Debug)]
+pub struct RecordingAttributes {
+    pub path: PathBuf,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReplayAttributes {
+    pub path: PathBuf,
+    // Starts over from the first frame instead of ending the replay.
+    pub looping: bool,
+    pub exit_when_done: bool,
+}
+
+// State of one rendered frame of the primary window. Transforms are the instances' own, so
+// attached instances stay relative to their joint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedFrame {
+    pub elapsed_seconds: f64,
+    pub eye: na::Point3<f64>,
+    pub target: na::Point3<f64>,
+    pub transforms: Vec<na::Affine3<f64>>,
+}
+
+impl RecordedFrame {
+    fn capture(renderer: &Renderer, time: &Time) -> Self {
+        let (eye, target) = renderer.frame_look_at();
+        Self {
+            elapsed_seconds: time.elapsed().as_secs_f64(),
+            eye,
+            target,
+            transforms: (0..renderer.instance_count())
+                .map(|instance| *renderer.instance_transform(instance))
+                .collect(),
+        }
+    }
+
+    // Little endian f64s: elapsed, eye, target, the instance count as u64, then the top three
+    // rows of every transform, column major.
+    fn write(&self, writer: &mut impl Write) -> Result<()> {
+        let mut values = vec![self.elapsed_seconds];
+        values.extend(self.eye.coords.iter());
+        values.extend(self.target.coords.iter());
+        for value in values {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&(self.transforms.len() as u64).to_le_bytes())?;
+        for transform in &self.transforms {
+            for value in transform.matrix().fixed_view::<3, 4>(0, 0).iter() {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self> {
+        let elapsed_seconds = reader.f64()?;
+        let eye = na::Point3::new(reader.f64()?, reader.f64()?, reader.f64()?);
+        let target = na::Point3::new(reader.f64()?, reader.f64()?, reader.f64()?);
+        let transform_count = reader.u64()? as usize;
+        let transforms = (0..transform_count)
+            .map(|_| {
+                let mut matrix = na::Matrix4::identity();
+                for value in matrix.fixed_view_mut::<3, 4>(0, 0).iter_mut() {
+                    *value = reader.f64()?;
+                }
+                Ok(na::Affine3::from_matrix_unchecked(matrix))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            elapsed_seconds,
+            eye,
+            target,
+            transforms,
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let bytes = self
+            .bytes
+            .get(self.cursor..self.cursor + N)
+            .ok_or_else(|| anyhow!("Recording ends in the middle of a frame"))?;
+        self.cursor += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+}
+
+pub fn read_recording(path: impl AsRef<std::path::Path>) -> Result<Vec<RecordedFrame>> {
+    let bytes = std::fs::read(path)?;
+    if !bytes.starts_with(RECORDING_MAGIC) {
+        return Err(anyhow!("Not a scene recording"));
+    }
+    if bytes.len() < HEADER_SIZE {
+        return Err(anyhow!("Scene recording ends in its header"));
+    }
+    let version = u32::from_le_bytes(bytes[8..HEADER_SIZE].try_into().unwrap());
+    if version != RECORDING_VERSION {
+        return Err(anyhow!("Unsupported scene recording version {version}"));
+    }
+
+    let mut reader = Reader {
+        bytes: &bytes,
+        cursor: HEADER_SIZE,
+    };
+    let mut frames = Vec::new();
+    while reader.cursor < bytes.len() {
+        frames.push(RecordedFrame::read(&mut reader)?);
+    }
+    Ok(frames)
+}
+
+// Streams every frame to the file as it's rendered, so a recording survives a crash up to the
+// last flushed frame.
+pub(crate) struct Recorder {
+    writer: BufWriter<File>,
+    frame_count: u64,
+}
+
+impl Recorder {
+    pub fn new(attributes: &RecordingAttributes) -> Result<Self> {
+        if let Some(parent) = attributes.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(&attributes.path)?);
+        writer.write_all(RECORDING_MAGIC)?;
+        writer.write_all(&RECORDING_VERSION.to_le_bytes())?;
+        Ok(Self {
+            writer,
+            frame_count: 0,
+        })
+    }
+
+    pub fn record_frame(&mut self, renderer: &Renderer, time: &Time) -> Result<()> {
+        RecordedFrame::capture(renderer, time).write(&mut self.writer)?;
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub(crate) struct Replay {
+    pub attributes: ReplayAttributes,
+    frames: Vec<RecordedFrame>,
+    frame: usize,
+}
+
+impl Replay {
+    pub fn new(attributes: ReplayAttributes) -> Result<Self> {
+        let frames = read_recording(&attributes.path)?;
+        if frames.is_empty() {
+            return Err(anyhow!("Scene recording {:?} is empty", attributes.path));
+        }
+        Ok(Self {
+            attributes,
+            frames,
+            frame: 0,
+        })
+    }
+
+    // Instances added after the recording was made keep their transforms.
+    pub fn begin_frame(&self, renderer: &mut Renderer) {
+        let frame = &self.frames[self.frame];
+        renderer.set_camera_look_at(Some((frame.eye, frame.target)));
+        let instance_count = renderer.instance_count();
+        for (instance, transform) in frame.transforms.iter().enumerate().take(instance_count) {
+            renderer.set_instance_transform(instance, *transform);
+        }
+    }
+
+    // Returns true once all frames have been replayed.
+    pub fn end_frame(&mut self) -> bool {
+        self.frame += 1;
+        if self.frame < self.frames.len() {
+            return false;
+        }
+        if self.attributes.looping {
+            self.frame = 0;
+            return false;
+        }
+        true
+    }
+}
@@ -0,0 +1,436 @@
+use anyhow::{anyhow, Result};
+use ash::vk;
+use std::collections::HashMap;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+const STORAGE_CLASS_PHYSICAL_STORAGE_BUFFER: u32 = 5349;
+
+const IMAGE_DIM_BUFFER: u32 = 5;
+const IMAGE_DIM_SUBPASS_DATA: u32 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    // None for runtime arrays, which are sized when the layout is created.
+    pub count: Option<u32>,
+    pub stages: vk::ShaderStageFlags,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Type {
+    Scalar(u32),
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    AccelerationStructure,
+    Array(u32, u32),
+    RuntimeArray(u32),
+    Struct,
+    Pointer(u32, u32),
+}
+
+// Descriptor bindings and push constants the shader stages declare, read straight from the
+// SPIR-V so pipeline layouts follow the shaders instead of being written out by hand.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderReflection {
+    pub stages: vk::ShaderStageFlags,
+    pub bindings: Vec<ReflectedBinding>,
+    // Bytes of the push constant block, 0 without one.
+    pub push_constant_size: u32,
+}
+
+struct Module {
+    types: HashMap<u32, Type>,
+    struct_members: HashMap<u32, Vec<u32>>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<(u32, u32), u32>,
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+}
+
+impl Module {
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).copied()
+    }
+
+    fn has_decoration(&self, id: u32, decoration: u32) -> bool {
+        self.decorations.contains_key(&(id, decoration))
+    }
+
+    fn type_of(&self, id: u32) -> Result<Type> {
+        self.types
+            .get(&id)
+            .copied()
+            .ok_or_else(|| anyhow!("SPIR-V type {id} isn't declared"))
+    }
+
+    // Scalar block layout sizes, with explicit strides where the shader decorates them.
+    fn size_of(&self, id: u32) -> Result<u32> {
+        Ok(match self.type_of(id)? {
+            Type::Scalar(width) => width / 8,
+            Type::Vector(component, count) => self.size_of(component)? * count,
+            Type::Matrix(column, count) => self.size_of(column)? * count,
+            Type::Array(element, length) => {
+                let stride = match self.decoration(id, DECORATION_ARRAY_STRIDE) {
+                    Some(stride) => stride,
+                    None => self.size_of(element)?,
+                };
+                stride * length
+            }
+            Type::Struct => {
+                let members = &self.struct_members[&id];
+                let mut size = 0;
+                for (member, &member_type) in members.iter().enumerate() {
+                    let member = member as u32;
+                    let offset = self
+                        .member_decorations
+                        .get(&(id, member, DECORATION_OFFSET))
+                        .copied()
+                        .unwrap_or(size);
+                    let member_size = match (
+                        self.type_of(member_type)?,
+                        self.member_decorations
+                            .get(&(id, member, DECORATION_MATRIX_STRIDE)),
+                    ) {
+                        (Type::Matrix(_, columns), Some(&stride)) => columns * stride,
+                        _ => self.size_of(member_type)?,
+                    };
+                    size = size.max(offset + member_size);
+                }
+                size
+            }
+            // Buffer device addresses.
+            Type::Pointer(STORAGE_CLASS_PHYSICAL_STORAGE_BUFFER, _) => 8,
+            other => return Err(anyhow!("{other:?} has no size in a buffer")),
+        })
+    }
+
+    fn descriptor_type(&self, id: u32, storage_class: u32) -> Result<vk::DescriptorType> {
+        Ok(match self.type_of(id)? {
+            Type::SampledImage => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            Type::Sampler => vk::DescriptorType::SAMPLER,
+            Type::AccelerationStructure => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            Type::Image {
+                dim: IMAGE_DIM_BUFFER,
+                sampled,
+            } => {
+                if sampled == 2 {
+                    vk::DescriptorType::STORAGE_TEXEL_BUFFER
+                } else {
+                    vk::DescriptorType::UNIFORM_TEXEL_BUFFER
+                }
+            }
+            Type::Image {
+                dim: IMAGE_DIM_SUBPASS_DATA,
+                ..
+            } => vk::DescriptorType::INPUT_ATTACHMENT,
+            Type::Image { sampled: 2, .. } => vk::DescriptorType::STORAGE_IMAGE,
+            Type::Image { .. } => vk::DescriptorType::SAMPLED_IMAGE,
+            Type::Struct => match storage_class {
+                STORAGE_CLASS_STORAGE_BUFFER => vk::DescriptorType::STORAGE_BUFFER,
+                _ if self.has_decoration(id, DECORATION_BUFFER_BLOCK) => {
+                    vk::DescriptorType::STORAGE_BUFFER
+                }
+                _ => vk::DescriptorType::UNIFORM_BUFFER,
+            },
+            other => return Err(anyhow!("{other:?} can't be bound to a descriptor")),
+        })
+    }
+}
+
+fn stage(execution_model: u32) -> vk::ShaderStageFlags {
+    match execution_model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => vk::ShaderStageFlags::GEOMETRY,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        _ => vk::ShaderStageFlags::empty(),
+    }
+}
+
+impl ShaderReflection {
+    pub fn new(code: &[u8]) -> Result<Self> {
+        if !code.len().is_multiple_of(4) || code.len() < 20 {
+            return Err(anyhow!("SPIR-V code is truncated"));
+        }
+        let words = code
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        if words[0] != SPIRV_MAGIC {
+            return Err(anyhow!("Not SPIR-V code"));
+        }
+
+        let mut module = Module {
+            types: HashMap::new(),
+            struct_members: HashMap::new(),
+            constants: HashMap::new(),
+            decorations: HashMap::new(),
+            member_decorations: HashMap::new(),
+        };
+        let mut stages = vk::ShaderStageFlags::empty();
+        // Result id, pointer type and storage class.
+        let mut variables = Vec::new();
+
+        let mut cursor = 5;
+        while cursor < words.len() {
+            let word_count = (words[cursor] >> 16) as usize;
+            let opcode = words[cursor] & 0xFFFF;
+            let operands = words
+                .get(cursor + 1..cursor + word_count)
+                .filter(|_| word_count > 0)
+                .ok_or_else(|| anyhow!("SPIR-V instruction at word {cursor} is truncated"))?;
+            let operand = |index: usize| {
+                operands
+                    .get(index)
+                    .copied()
+                    .ok_or_else(|| anyhow!("SPIR-V instruction at word {cursor} is truncated"))
+            };
+
+            match opcode {
+                OP_ENTRY_POINT => stages |= stage(operand(0)?),
+                OP_DECORATE => {
+                    let value = operands.get(2).copied().unwrap_or_default();
+                    module.decorations.insert((operand(0)?, operand(1)?), value);
+                }
+                OP_MEMBER_DECORATE => {
+                    let value = operands.get(3).copied().unwrap_or_default();
+                    module
+                        .member_decorations
+                        .insert((operand(0)?, operand(1)?, operand(2)?), value);
+                }
+                OP_TYPE_BOOL => {
+                    module.types.insert(operand(0)?, Type::Scalar(32));
+                }
+                OP_TYPE_INT | OP_TYPE_FLOAT => {
+                    module.types.insert(operand(0)?, Type::Scalar(operand(1)?));
+                }
+                OP_TYPE_VECTOR => {
+                    module
+                        .types
+                        .insert(operand(0)?, Type::Vector(operand(1)?, operand(2)?));
+                }
+                OP_TYPE_MATRIX => {
+                    module
+                        .types
+                        .insert(operand(0)?, Type::Matrix(operand(1)?, operand(2)?));
+                }
+                OP_TYPE_IMAGE => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Image {
+                            dim: operand(2)?,
+                            sampled: operand(6)?,
+                        },
+                    );
+                }
+                OP_TYPE_SAMPLER => {
+                    module.types.insert(operand(0)?, Type::Sampler);
+                }
+                OP_TYPE_SAMPLED_IMAGE => {
+                    module.types.insert(operand(0)?, Type::SampledImage);
+                }
+                OP_TYPE_ACCELERATION_STRUCTURE => {
+                    module
+                        .types
+                        .insert(operand(0)?, Type::AccelerationStructure);
+                }
+                OP_TYPE_ARRAY => {
+                    // Lengths are constants declared before the array type.
+                    let length = module
+                        .constants
+                        .get(&operand(2)?)
+                        .copied()
+                        .ok_or_else(|| anyhow!("Array length isn't a constant"))?;
+                    module
+                        .types
+                        .insert(operand(0)?, Type::Array(operand(1)?, length));
+                }
+                OP_TYPE_RUNTIME_ARRAY => {
+                    module
+                        .types
+                        .insert(operand(0)?, Type::RuntimeArray(operand(1)?));
+                }
+                OP_TYPE_STRUCT => {
+                    module.types.insert(operand(0)?, Type::Struct);
+                    module
+                        .struct_members
+                        .insert(operand(0)?, operands[1..].to_vec());
+                }
+                OP_TYPE_POINTER => {
+                    module
+                        .types
+                        .insert(operand(0)?, Type::Pointer(operand(1)?, operand(2)?));
+                }
+                OP_CONSTANT => {
+                    module.constants.insert(operand(1)?, operand(2)?);
+                }
+                OP_VARIABLE => variables.push((operand(1)?, operand(0)?, operand(2)?)),
+                _ => {}
+            }
+
+            cursor += word_count;
+        }
+
+        let mut reflection = Self {
+            stages,
+            ..Default::default()
+        };
+        for (id, pointer, storage_class) in variables {
+            let Type::Pointer(_, pointee) = module.type_of(pointer)? else {
+                continue;
+            };
+            match storage_class {
+                STORAGE_CLASS_PUSH_CONSTANT => {
+                    reflection.push_constant_size = module.size_of(pointee)?;
+                }
+                STORAGE_CLASS_UNIFORM_CONSTANT
+                | STORAGE_CLASS_UNIFORM
+                | STORAGE_CLASS_STORAGE_BUFFER => {
+                    let (Some(set), Some(binding)) = (
+                        module.decoration(id, DECORATION_DESCRIPTOR_SET),
+                        module.decoration(id, DECORATION_BINDING),
+                    ) else {
+                        continue;
+                    };
+                    let (element, count) = match module.type_of(pointee)? {
+                        Type::Array(element, length) => (element, Some(length)),
+                        Type::RuntimeArray(element) => (element, None),
+                        _ => (pointee, Some(1)),
+                    };
+                    reflection.bindings.push(ReflectedBinding {
+                        set,
+                        binding,
+                        descriptor_type: module.descriptor_type(element, storage_class)?,
+                        count,
+                        stages,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(reflection)
+    }
+
+    // Union of the stages of a pipeline, or of every pipeline sharing a layout.
+    pub fn merge(reflections: &[ShaderReflection]) -> Result<Self> {
+        let mut merged = Self::default();
+        for reflection in reflections {
+            merged.stages |= reflection.stages;
+            merged.push_constant_size =
+                merged.push_constant_size.max(reflection.push_constant_size);
+            for binding in &reflection.bindings {
+                merged.add_binding(*binding)?;
+            }
+        }
+        Ok(merged)
+    }
+
+    // Adds a binding the shaders may not declare, e.g. one that's written regardless of
+    // whether the optimizer kept it. Declared bindings have to match it.
+    pub fn add_binding(&mut self, binding: ReflectedBinding) -> Result<()> {
+        match self
+            .bindings
+            .iter_mut()
+            .find(|existing| existing.set == binding.set && existing.binding == binding.binding)
+        {
+            Some(existing) => {
+                if existing.descriptor_type != binding.descriptor_type {
+                    return Err(anyhow!(
+                        "Set {} binding {} is declared as both {:?} and {:?}",
+                        binding.set,
+                        binding.binding,
+                        existing.descriptor_type,
+                        binding.descriptor_type
+                    ));
+                }
+                existing.count = match (existing.count, binding.count) {
+                    (Some(existing), Some(count)) => Some(existing.max(count)),
+                    _ => None,
+                };
+                existing.stages |= binding.stages;
+            }
+            None => self.bindings.push(binding),
+        }
+        Ok(())
+    }
+
+    pub fn push_constant_range(&self) -> Option<vk::PushConstantRange> {
+        (self.push_constant_size > 0).then(|| {
+            vk::PushConstantRange::default()
+                .stage_flags(self.stages)
+                .size(self.push_constant_size)
+        })
+    }
+
+    // One past the highest set, sets in between without bindings get empty layouts.
+    pub fn set_count(&self) -> u32 {
+        self.bindings
+            .iter()
+            .map(|binding| binding.set + 1)
+            .max()
+            .unwrap_or_default()
+    }
+
+    // Layout bindings of one set, ordered by binding, runtime arrays get runtime_array_count
+    // descriptors.
+    pub fn set_layout_bindings(
+        &self,
+        set: u32,
+        runtime_array_count: u32,
+    ) -> Vec<(ReflectedBinding, vk::DescriptorSetLayoutBinding<'static>)> {
+        let mut bindings = self
+            .bindings
+            .iter()
+            .filter(|binding| binding.set == set)
+            .map(|binding| {
+                (
+                    *binding,
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(binding.binding)
+                        .descriptor_type(binding.descriptor_type)
+                        .descriptor_count(binding.count.unwrap_or(runtime_array_count))
+                        .stage_flags(binding.stages),
+                )
+            })
+            .collect::<Vec<_>>();
+        bindings.sort_by_key(|(binding, _)| binding.binding);
+        bindings
+    }
+}
@@ -0,0 +1,320 @@
+use crate::image::{Image, ImageAttributes};
+use crate::rendering_context::RenderingContext;
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::vulkan::{Allocation, AllocationCreateDesc, AllocationScheme, Allocator};
+use gpu_allocator::MemoryLocation;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SparseImageAttributes {
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags,
+}
+
+// Partially resident 2D image for very large textures, e.g. terrain megatextures.
+// Pages are bound and unbound individually; changes are queued and applied by `flush`
+// on the sparse binding queue.
+pub struct SparseImage {
+    pub image: Image,
+    context: Arc<RenderingContext>,
+    name: String,
+    page_extent: vk::Extent3D,
+    page_counts: vk::Extent2D,
+    memory_requirements: vk::MemoryRequirements,
+    pages: Vec<Option<Allocation>>,
+    mip_tail: Option<Allocation>,
+    pending_binds: Vec<vk::SparseImageMemoryBind>,
+    pending_mip_tail_bind: Option<vk::SparseMemoryBind>,
+    // Pages unbound by the pending binds, freed once those have been applied.
+    pending_frees: Vec<Allocation>,
+}
+
+impl SparseImage {
+    pub fn new(
+        context: Arc<RenderingContext>,
+        allocator: &mut Allocator,
+        name: &str,
+        attributes: SparseImageAttributes,
+    ) -> Result<Self> {
+        if !context.supports_sparse_residency() {
+            return Err(anyhow::anyhow!(
+                "Sparse residency of 2D images is not supported"
+            ));
+        }
+
+        let extent = vk::Extent3D {
+            width: attributes.extent.width,
+            height: attributes.extent.height,
+            depth: 1,
+        };
+
+        let handle = unsafe {
+            context.device.create_image(
+                &vk::ImageCreateInfo::default()
+                    .flags(
+                        vk::ImageCreateFlags::SPARSE_BINDING
+                            | vk::ImageCreateFlags::SPARSE_RESIDENCY,
+                    )
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(attributes.format)
+                    .extent(extent)
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(attributes.usage)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED),
+                None,
+            )?
+        };
+
+        let (memory_requirements, sparse_requirements) = unsafe {
+            (
+                context.device.get_image_memory_requirements(handle),
+                context.device.get_image_sparse_memory_requirements(handle),
+            )
+        };
+        let Some(sparse_requirements) = sparse_requirements.into_iter().find(|requirements| {
+            requirements
+                .format_properties
+                .aspect_mask
+                .contains(vk::ImageAspectFlags::COLOR)
+        }) else {
+            unsafe { context.device.destroy_image(handle, None) };
+            return Err(anyhow::anyhow!(
+                "Format {:?} has no sparse color aspect",
+                attributes.format
+            ));
+        };
+
+        let page_extent = sparse_requirements.format_properties.image_granularity;
+        let page_counts = vk::Extent2D {
+            width: extent.width.div_ceil(page_extent.width),
+            height: extent.height.div_ceil(page_extent.height),
+        };
+
+        let image = Image::wrap(
+            context.clone(),
+            handle,
+            ImageAttributes {
+                location: MemoryLocation::GpuOnly,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+                allocation_priority: 1.0,
+                linear: false,
+                extent,
+                format: attributes.format,
+                usage: attributes.usage,
+                subresource_range: vk::ImageSubresourceRange::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(0)
+                    .level_count(1)
+                    .base_array_layer(0)
+                    .layer_count(1),
+                samples: vk::SampleCountFlags::TYPE_1,
+                cube: false,
+            },
+        )?;
+
+        let mut sparse_image = Self {
+            image,
+            context,
+            name: name.to_owned(),
+            page_extent,
+            page_counts,
+            memory_requirements,
+            pages: (0..page_counts.width * page_counts.height)
+                .map(|_| None)
+                .collect(),
+            mip_tail: None,
+            pending_binds: Vec::new(),
+            pending_mip_tail_bind: None,
+            pending_frees: Vec::new(),
+        };
+
+        // Levels in the mip tail can't be bound per page, so they stay resident.
+        if sparse_requirements.image_mip_tail_first_lod == 0
+            && sparse_requirements.image_mip_tail_size > 0
+        {
+            let allocation = sparse_image.allocate(
+                allocator,
+                &format!("{name}_mip_tail"),
+                sparse_requirements.image_mip_tail_size,
+            )?;
+            sparse_image.pending_mip_tail_bind = Some(
+                vk::SparseMemoryBind::default()
+                    .resource_offset(sparse_requirements.image_mip_tail_offset)
+                    .size(sparse_requirements.image_mip_tail_size)
+                    .memory(unsafe { allocation.memory() })
+                    .memory_offset(allocation.offset()),
+            );
+            sparse_image.mip_tail = Some(allocation);
+        }
+
+        Ok(sparse_image)
+    }
+
+    fn allocate(
+        &self,
+        allocator: &mut Allocator,
+        name: &str,
+        size: vk::DeviceSize,
+    ) -> Result<Allocation> {
+        Ok(allocator.allocate(&AllocationCreateDesc {
+            name,
+            requirements: vk::MemoryRequirements {
+                size,
+                alignment: self.memory_requirements.alignment,
+                memory_type_bits: self.memory_requirements.memory_type_bits,
+            },
+            location: MemoryLocation::GpuOnly,
+            linear: false,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        })?)
+    }
+
+    pub fn page_extent(&self) -> vk::Extent3D {
+        self.page_extent
+    }
+
+    pub fn page_counts(&self) -> vk::Extent2D {
+        self.page_counts
+    }
+
+    fn page_index(&self, x: u32, y: u32) -> usize {
+        assert!(x < self.page_counts.width && y < self.page_counts.height);
+        (y * self.page_counts.width + x) as usize
+    }
+
+    pub fn is_resident(&self, x: u32, y: u32) -> bool {
+        self.pages[self.page_index(x, y)].is_some()
+    }
+
+    pub fn resident_page_count(&self) -> usize {
+        self.pages.iter().flatten().count()
+    }
+
+    fn page_bind(&self, x: u32, y: u32) -> vk::SparseImageMemoryBind {
+        let extent = self.image.attributes.extent;
+        let offset = vk::Offset3D {
+            x: (x * self.page_extent.width) as i32,
+            y: (y * self.page_extent.height) as i32,
+            z: 0,
+        };
+        // Pages on the right and bottom edges may be cut off by the image extent.
+        vk::SparseImageMemoryBind::default()
+            .subresource(
+                vk::ImageSubresource::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .array_layer(0),
+            )
+            .offset(offset)
+            .extent(vk::Extent3D {
+                width: self.page_extent.width.min(extent.width - offset.x as u32),
+                height: self.page_extent.height.min(extent.height - offset.y as u32),
+                depth: 1,
+            })
+    }
+
+    // The page's contents are undefined until written, e.g. by a buffer to image copy.
+    pub fn bind_page(&mut self, allocator: &mut Allocator, x: u32, y: u32) -> Result<()> {
+        let index = self.page_index(x, y);
+        if self.pages[index].is_some() {
+            return Ok(());
+        }
+
+        let allocation = self.allocate(
+            allocator,
+            &format!("{}_page", self.name),
+            self.memory_requirements.alignment,
+        )?;
+        let bind = self
+            .page_bind(x, y)
+            .memory(unsafe { allocation.memory() })
+            .memory_offset(allocation.offset());
+        self.pending_binds.push(bind);
+        self.pages[index] = Some(allocation);
+        Ok(())
+    }
+
+    // Must not be applied while the page is still read by frames in flight.
+    pub fn unbind_page(&mut self, x: u32, y: u32) {
+        let index = self.page_index(x, y);
+        let Some(allocation) = self.pages[index].take() else {
+            return;
+        };
+        let bind = self.page_bind(x, y);
+        self.pending_binds.push(bind);
+        self.pending_frees.push(allocation);
+    }
+
+    // Applies the queued page binds on the sparse binding queue and waits for them to complete.
+    pub fn flush(&mut self, allocator: &mut Allocator) -> Result<()> {
+        if self.pending_binds.is_empty() && self.pending_mip_tail_bind.is_none() {
+            return Ok(());
+        }
+        let queue = self.context.sparse_binding_queue.unwrap();
+
+        let image_binds = [vk::SparseImageMemoryBindInfo::default()
+            .image(self.image.handle)
+            .binds(&self.pending_binds)];
+        let mip_tail_binds = self.pending_mip_tail_bind.as_slice();
+        let opaque_binds = [vk::SparseImageOpaqueMemoryBindInfo::default()
+            .image(self.image.handle)
+            .binds(mip_tail_binds)];
+
+        let mut bind_info = vk::BindSparseInfo::default();
+        if !self.pending_binds.is_empty() {
+            bind_info = bind_info.image_binds(&image_binds);
+        }
+        if !mip_tail_binds.is_empty() {
+            bind_info = bind_info.image_opaque_binds(&opaque_binds);
+        }
+
+        unsafe {
+            let fence = self
+                .context
+                .device
+                .create_fence(&vk::FenceCreateInfo::default(), None)?;
+            let result = self
+                .context
+                .device
+                .queue_bind_sparse(queue, &[bind_info], fence)
+                .and_then(|_| {
+                    self.context
+                        .device
+                        .wait_for_fences(&[fence], true, u64::MAX)
+                });
+            self.context.device.destroy_fence(fence, None);
+            result?;
+        }
+
+        self.pending_binds.clear();
+        self.pending_mip_tail_bind = None;
+        for allocation in self.pending_frees.drain(..) {
+            allocator.free(allocation)?;
+        }
+        Ok(())
+    }
+
+    pub fn destroy(&mut self, allocator: &mut Allocator) -> Result<()> {
+        self.image.destroy(allocator)?;
+        unsafe {
+            self.context.device.destroy_image(self.image.handle, None);
+        }
+        let pages = self.pages.iter_mut().filter_map(Option::take);
+        for allocation in pages
+            .chain(self.mip_tail.take())
+            .chain(self.pending_frees.drain(..))
+            .collect::<Vec<_>>()
+        {
+            allocator.free(allocation)?;
+        }
+        self.pending_binds.clear();
+        self.pending_mip_tail_bind = None;
+        Ok(())
+    }
+}
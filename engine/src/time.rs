@@ -0,0 +1,224 @@
+use anyhow::{ensure, Result};
+use std::time::{Duration, Instant};
+
+// Source of the time the renderer animates with, sampled once per rendered frame.
+pub trait Clock {
+    fn elapsed(&mut self, frame_index: u64) -> Duration;
+}
+
+pub struct RealTimeClock {
+    start: Instant,
+}
+
+impl Default for RealTimeClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for RealTimeClock {
+    fn elapsed(&mut self, _frame_index: u64) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+// Advances by the same step every frame, so identical inputs produce identical frames.
+pub struct FixedStepClock {
+    pub step: Duration,
+    elapsed: Duration,
+}
+
+impl FixedStepClock {
+    pub fn new(step: Duration) -> Self {
+        Self {
+            step,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+impl Clock for FixedStepClock {
+    fn elapsed(&mut self, _frame_index: u64) -> Duration {
+        let elapsed = self.elapsed;
+        self.elapsed += self.step;
+        elapsed
+    }
+}
+
+// Derives time from the frame index alone, which also keeps replays that skip frames in sync.
+pub struct FrameIndexClock {
+    frames_per_second: f64,
+}
+
+impl FrameIndexClock {
+    pub fn new(frames_per_second: f64) -> Result<Self> {
+        ensure!(
+            frames_per_second.is_finite() && frames_per_second > 0.0,
+            "Frames per second must be positive and finite, got {frames_per_second}"
+        );
+        Ok(Self { frames_per_second })
+    }
+
+    pub fn frames_per_second(&self) -> f64 {
+        self.frames_per_second
+    }
+}
+
+impl Clock for FrameIndexClock {
+    fn elapsed(&mut self, frame_index: u64) -> Duration {
+        // Saturates for rates so low the time doesn't fit a Duration.
+        Duration::try_from_secs_f64(frame_index as f64 / self.frames_per_second)
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+// Far more than any slow motion or fast forward needs, while keeping scaled delta times well
+// within a Duration.
+const MAX_TIME_SCALE: f32 = 1000.0;
+
+// Negative and NaN scales stop time, larger ones are capped.
+fn valid_scale(scale: f32) -> f32 {
+    if scale.is_nan() {
+        return 0.0;
+    }
+    scale.clamp(0.0, MAX_TIME_SCALE)
+}
+
+// Frame time shared by everything the engine updates and renders, with slow motion and pause
+// applied once here instead of in every system.
+pub struct Time {
+    clock: Box<dyn Clock>,
+    frame_index: u64,
+    clock_elapsed: Option<Duration>,
+    unscaled_delta: Duration,
+    delta: Duration,
+    elapsed: Duration,
+    pub scale: f32,
+    pub paused: bool,
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self::new(RealTimeClock::default())
+    }
+}
+
+impl Time {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        Self {
+            clock: Box::new(clock),
+            frame_index: 0,
+            clock_elapsed: None,
+            unscaled_delta: Duration::ZERO,
+            delta: Duration::ZERO,
+            elapsed: Duration::ZERO,
+            scale: 1.0,
+            paused: false,
+        }
+    }
+
+    // The scaled elapsed time carries on from where the previous clock left it.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+        self.clock_elapsed = None;
+    }
+
+    pub fn tick(&mut self) {
+        let clock_elapsed = self.clock.elapsed(self.frame_index);
+        self.unscaled_delta = self.clock_elapsed.map_or(Duration::ZERO, |previous| {
+            clock_elapsed.saturating_sub(previous)
+        });
+        self.clock_elapsed = Some(clock_elapsed);
+
+        self.delta = if self.paused {
+            Duration::ZERO
+        } else {
+            // Saturates in case the clock jumped by close to Duration::MAX.
+            Duration::try_from_secs_f64(
+                self.unscaled_delta.as_secs_f64() * valid_scale(self.scale) as f64,
+            )
+            .unwrap_or(Duration::MAX)
+        };
+        self.elapsed = self.elapsed.saturating_add(self.delta);
+        self.frame_index += 1;
+    }
+
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    // Ignores scale and pause, for things like UI that keep running in a pause menu.
+    pub fn unscaled_delta(&self) -> Duration {
+        self.unscaled_delta
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed.as_secs_f32()
+    }
+
+    pub fn frame_index(&self) -> u64 {
+        self.frame_index
+    }
+}
+
+// Turns variable frame times into a whole number of equally long updates, the remainder carries
+// over to the next frame.
+pub struct FixedTimestep {
+    pub step: Duration,
+    // Updates past this many in one frame are dropped, so a slow frame can't cause ever more
+    // updates in the next ones. 0 is treated as 1.
+    pub max_steps_per_frame: u32,
+    accumulator: Duration,
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(Duration::from_secs_f64(1.0 / 60.0))
+    }
+}
+
+impl FixedTimestep {
+    pub fn new(step: Duration) -> Self {
+        Self {
+            step,
+            max_steps_per_frame: 8,
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    // Returns how many updates the frame's delta time is worth.
+    pub fn advance(&mut self, delta: Duration) -> u32 {
+        if self.step.is_zero() {
+            return 0;
+        }
+        self.accumulator += delta;
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+            if steps >= self.max_steps_per_frame.max(1) {
+                self.accumulator = Duration::ZERO;
+                break;
+            }
+        }
+        steps
+    }
+
+    // How far time got into the next update, from 0 to 1, to interpolate rendered state with.
+    pub fn alpha(&self) -> f32 {
+        if self.step.is_zero() {
+            return 0.0;
+        }
+        (self.accumulator.as_secs_f64() / self.step.as_secs_f64()) as f32
+    }
+}
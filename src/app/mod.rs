@@ -1,11 +1,24 @@
-use engine::winit::window::WindowAttributes;
 use ::engine::Engine;
-use engine::{vk, winit, WindowRendererAttributes};
+use engine::winit::window::WindowAttributes;
+use engine::{
+    nalgebra, vk, winit, BenchmarkAttributes, DebugServerAttributes, DroppedAsset,
+    EngineAttributes, Lighting, MinimizedPolicy, RecordingAttributes, ReplayAttributes,
+    ReportFormat, ShaderReloadAttributes, SplashAttributes, SunShadowAttributes, TonemapAttributes,
+    WindowRendererAttributes,
+};
+use std::time::Duration;
 use winit::application::ApplicationHandler;
 use winit::event::WindowEvent;
 use winit::event_loop::ActiveEventLoop;
 use winit::window::WindowId;
 
+// Value following a command line flag, e.g. the path in `--record scene.rec`.
+fn flag_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != flag);
+    args.next()?;
+    args.next()
+}
+
 #[derive(Default)]
 pub struct App {
     engine: Option<Engine>,
@@ -23,6 +36,22 @@ impl ApplicationHandler for App {
             ssaa: 1.0,
             ssaa_filter: vk::Filter::NEAREST,
             in_flight_frames_count: 2,
+            occlusion_queries: false,
+            conditional_rendering: false,
+            probe_grid: None,
+            grid: false,
+            shaders: None,
+            vertex_input: false,
+            quantize_vertices: false,
+            depth_resolve_mode: vk::ResolveModeFlags::MIN,
+            refresh_divisor: 1,
+            splash: Some(SplashAttributes::default()),
+            upload_budget: Some(64 * 1024 * 1024),
+            shader_reload: cfg!(debug_assertions).then(ShaderReloadAttributes::default),
+            previous_transforms: false,
+            lighting: Lighting::default(),
+            sun_shadow: Some(SunShadowAttributes::default()),
+            tonemap: TonemapAttributes::default(),
         };
 
         let secondary_window_attributes =
@@ -36,6 +65,22 @@ impl ApplicationHandler for App {
             ssaa: 1.0,
             ssaa_filter: vk::Filter::NEAREST,
             in_flight_frames_count: 2,
+            occlusion_queries: false,
+            conditional_rendering: false,
+            probe_grid: None,
+            grid: false,
+            shaders: None,
+            vertex_input: false,
+            quantize_vertices: false,
+            depth_resolve_mode: vk::ResolveModeFlags::MIN,
+            refresh_divisor: 2,
+            splash: None,
+            upload_budget: Some(16 * 1024 * 1024),
+            shader_reload: cfg!(debug_assertions).then(ShaderReloadAttributes::default),
+            previous_transforms: false,
+            lighting: Lighting::default(),
+            sun_shadow: None,
+            tonemap: TonemapAttributes::default(),
         };
 
         let secondary_window_count = 1;
@@ -45,10 +90,76 @@ impl ApplicationHandler for App {
                 event_loop,
                 primary_window_attributes,
                 primary_window_renderer_attributes,
+                EngineAttributes {
+                    dedicated_present_queue: std::env::args()
+                        .any(|arg| arg == "--dedicated-present-queue"),
+                    // Nothing is visible while every window is minimized.
+                    minimized_policy: MinimizedPolicy::ThrottleUpdates(Duration::from_millis(100)),
+                    ..Default::default()
+                },
             )
             .unwrap(),
         );
         if let Some(engine) = self.engine.as_mut() {
+            // Animations are sampled at the predicted present time where the driver reports it.
+            let primary_window_id = engine.primary_window_id();
+            if let Some(clock) = engine
+                .window_renderer_mut(primary_window_id)
+                .and_then(|renderer| renderer.display_timing_clock())
+            {
+                engine.time_mut().set_clock(clock);
+            }
+            // Models and textures dragged in from the file manager show up ahead of the camera.
+            engine.set_file_drop_target(primary_window_id, |renderer, path, asset| match asset {
+                Ok(DroppedAsset::Model { instances }) => {
+                    tracing::info!("Spawned {path:?} as {} instances", instances.len());
+                    for instance in instances {
+                        renderer.select(instance);
+                    }
+                }
+                Ok(DroppedAsset::Texture { instance, .. }) => {
+                    tracing::info!("Spawned {path:?} on a cube");
+                    renderer.select(instance);
+                }
+                Err(error) => tracing::warn!("Failed to load dropped {path:?}: {error}"),
+            });
+            if std::env::args().any(|arg| arg == "--benchmark") {
+                engine
+                    .start_benchmark(BenchmarkAttributes {
+                        camera_path: (0..=8)
+                            .map(|i| {
+                                let angle = i as f32 / 8.0 * std::f32::consts::TAU;
+                                nalgebra::Point3::new(angle.cos() * 4.0, -2.0, angle.sin() * 4.0)
+                            })
+                            .collect(),
+                        target: nalgebra::Point3::origin(),
+                        frame_count: 1000,
+                        report_path: "benchmark.json".into(),
+                        report_format: ReportFormat::Json,
+                        exit_when_done: true,
+                    })
+                    .unwrap();
+            }
+            if std::env::args().any(|arg| arg == "--debug-server") {
+                engine
+                    .start_debug_server(DebugServerAttributes::default())
+                    .unwrap();
+            }
+            if let Some(path) = flag_value("--record") {
+                engine
+                    .start_recording(RecordingAttributes { path: path.into() })
+                    .unwrap();
+            }
+            if let Some(path) = flag_value("--replay") {
+                engine
+                    .start_replay(ReplayAttributes {
+                        path: path.into(),
+                        looping: false,
+                        exit_when_done: true,
+                    })
+                    .unwrap();
+            }
+
             for _ in 0..secondary_window_count {
                 _ = engine
                     .create_window(
@@ -72,9 +183,9 @@ impl ApplicationHandler for App {
         }
     }
 
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
         if let Some(engine) = self.engine.as_mut() {
-            engine.request_redraw();
+            engine.about_to_wait(event_loop);
         }
     }
 